@@ -0,0 +1,36 @@
+export type ActivityCell = {
+  /** Day of the week in UTC, 0 (Monday) to 6 (Sunday) */
+  weekday: number;
+  /** Hour of the day in UTC, 0 to 23 */
+  hour: number;
+  messages: number;
+};
+
+export type ActivityHeatmap = {
+  /** One cell per hour of the week (168), Monday 00:00 UTC first */
+  cells: ActivityCell[];
+  /** Days with activity, oldest first */
+  daily: { day: string; messages: number }[];
+  /** The (at most 100) most active channels, most active first */
+  channels: { channel_id: string; messages: number }[];
+  /** The hour of the week with the fewest messages, the earliest if tied */
+  quietest: ActivityCell;
+  total: number;
+};
+
+export type MActivitySyscall = 
+  | { 
+      /** Get the message activity of a guild (or one of its channels) over the last `days` days (Owner only) */
+      op: "Heatmap"; 
+      guild_id: string;
+      /** 1 to 90 */
+      days: number;
+      channel_id?: string | null 
+    };
+
+export type MActivitySyscallRet = 
+  | { 
+      /** Activity heatmap response */
+      op: "Heatmap"; 
+      heatmap: ActivityHeatmap 
+    };
@@ -0,0 +1,16 @@
+import { type KhronosValue } from '../khronosvalue'
+
+export type MAltDetectSyscall = 
+  | { 
+      /** Explain how members are assessed in a guild along with the latest assessment of a member, if still kept (Owner only) */
+      op: "Explain"; 
+      guild_id: string;
+      user_id: string 
+    };
+
+export type MAltDetectSyscallRet = 
+  | { 
+      /** Explain response, the signals and their weights, the guild's sensitivity and the member's assessment */
+      op: "Explanation"; 
+      explanation: KhronosValue 
+    };
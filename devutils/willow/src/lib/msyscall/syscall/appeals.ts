@@ -0,0 +1,51 @@
+import { type KhronosValue } from '../khronosvalue'
+
+export type MAppealSyscall = 
+  | { 
+      /** Submit an appeal of the user against their ban or timeout in a guild */
+      op: "Submit"; 
+      guild_id: string;
+      reason: string;
+      /** Links supporting the appeal */
+      evidence?: string[] 
+    }
+  | { 
+      /** List the most recent appeals of a guild (Owner only) */
+      op: "List"; 
+      guild_id: string;
+      /** Only list appeals which are open or under review */
+      unresolved_only?: boolean 
+    };
+
+export type Appeal = {
+  id: string;
+  guild_id: string;
+  user_id: string;
+  punishment: "ban" | "timeout";
+  reason: string;
+  /** Attachment URLs or links supporting the appeal */
+  evidence: string[];
+  /** Where the appeal was submitted from */
+  source: "dm" | "web";
+  state: "open" | "under_review" | "accepted" | "denied";
+  /** The channel and message the appeal is reviewed in */
+  channel_id: string | null;
+  message_id: string | null;
+  /** The moderator who last acted on the appeal */
+  moderator_id: string | null;
+  created_at: string;
+  updated_at: string;
+  resolved_at: string | null;
+};
+
+export type MAppealSyscallRet = 
+  | { 
+      /** Submit appeal response, the submitted appeal */
+      op: "Submitted"; 
+      appeal: KhronosValue 
+    }
+  | { 
+      /** List appeals response */
+      op: "Appeals"; 
+      appeals: Appeal[] 
+    };
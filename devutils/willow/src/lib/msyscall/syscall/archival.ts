@@ -0,0 +1,39 @@
+import { type Id } from '../types/common'
+
+export type MArchivalSyscall = 
+  | { 
+      /** Get the archive of a tenant, if it was ever archived (Secure only) */
+      op: "GetArchive"; 
+      /** The tenant */
+      id: Id 
+    }
+  | { 
+      /** Archive the data of a tenant now, regardless of its activity (Secure only) */
+      op: "Archive"; 
+      /** The tenant */
+      id: Id 
+    }
+  | { 
+      /** Restore the data of an archived tenant now (Secure only) */
+      op: "Restore"; 
+      /** The tenant */
+      id: Id 
+    };
+
+export type TenantArchive = {
+  /** Path of the archive in cold storage */
+  path: string;
+  size_bytes: number;
+  /** Number of key-value records (templates, stings and template data) in the archive */
+  kv_rows: number;
+  archived_at: string;
+  /** When the archive was last restored, null while the tenant is archived */
+  restored_at: string | null;
+};
+
+export type MArchivalSyscallRet = 
+  | { 
+      /** Archive response, null if there was nothing archived or restored */
+      op: "Archive"; 
+      archive: TenantArchive | null 
+    };
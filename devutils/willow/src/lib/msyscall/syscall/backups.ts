@@ -0,0 +1,41 @@
+import { type KhronosValue } from '../khronosvalue'
+
+export type MBackupSyscall = 
+  | { 
+      /** List the backups of a guild (Owner only) */
+      op: "List"; 
+      guild_id: string 
+    }
+  | { 
+      /** Back up the settings, roles, channels and overwrites of a guild now (Owner only) */
+      op: "Create"; 
+      guild_id: string 
+    }
+  | { 
+      /** Diff the current structure of a guild against a backup, restoring it only if confirm is set (Owner only) */
+      op: "Restore"; 
+      guild_id: string;
+      backup_id: string;
+      /** Channels never deleted by the restore */
+      protected_channels?: string[];
+      /** Roles never deleted by the restore */
+      protected_roles?: string[];
+      confirm?: boolean 
+    };
+
+export type MBackupSyscallRet = 
+  | { 
+      /** List response, the backups (backup_id, created_at and created_by) of the guild */
+      op: "Backups"; 
+      backups: KhronosValue 
+    }
+  | { 
+      /** Create response, the ID of the new backup */
+      op: "Created"; 
+      backup: KhronosValue 
+    }
+  | { 
+      /** Restore response, the diff against the backup and whether it was restored */
+      op: "Restore"; 
+      restore: KhronosValue 
+    };
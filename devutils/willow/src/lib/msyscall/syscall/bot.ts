@@ -1,7 +1,26 @@
 import type { CKhronosValue, RawKhronosValue } from '../khronosvalue'
-import type { BotStatus } from '../types/bot'
+import type { BotStatus, StatusPage, TemplateUsageRow } from '../types/bot'
 import type { Id } from '../types/common'
-import type { StateOp, StateExecResult, TenantState } from '../types/state'
+import type { StateOp, StateExecResult, TenantLimits, TenantState } from '../types/state'
+
+/** IDs to use in place of the defaults of an event fixture */
+export type FixtureIds = {
+  guild_id?: string | null;
+  channel_id?: string | null;
+  /** The author of messages, the joining member, the user running commands and the target of audit log entries */
+  user_id?: string | null;
+  /** The executor of audit log entries */
+  moderator_id?: string | null;
+}
+
+/** The top-level fields of the payload of a gateway event as of an event version */
+export type EventSchema = {
+  event: string;
+  /** Name of the payload type */
+  name: string;
+  version: number;
+  fields: string[];
+}
 
 export type MBotSyscall = 
   | { 
@@ -16,6 +35,35 @@ export type MBotSyscall =
       /** Returns the bots status */
       op: "GetBotStatus" 
     }
+  | { 
+      /** Returns the data of the status page, plus the recent template errors of the guild (owner only) if set */
+      op: "GetStatusPage";
+      guild_id?: string;
+    }
+  | { 
+      /** Returns the JSON schema of @antiraid/rules rule sets */
+      op: "GetRulesSchema" 
+    }
+  | { 
+      /** Returns the schemas of gateway event payloads for every event version, for documentation tooling */
+      op: "GetEventSchemas" 
+    }
+  | { 
+      /** Validates a @antiraid/rules rule set */
+      op: "ValidateRules"; 
+      /** The rule set */
+      rules: unknown 
+    }
+  | { 
+      /** Builds a synthetic gateway event payload for testing templates, the user defaults to the caller */
+      op: "GetEventFixture"; 
+      /** Name of the gateway event, such as MESSAGE_CREATE */
+      event: string; 
+      /** JSON merge patch applied to the payload */
+      overrides?: Record<string, unknown> | null; 
+      /** IDs to use in place of the defaults */
+      ids?: FixtureIds | null 
+    }
   | { 
       /** Dispatch an event to a worker process */
       op: "DispatchEvent"; 
@@ -44,6 +92,14 @@ export type MBotSyscall =
       /** Requested topics */
       topics: string[];
     } 
+  | {
+      /** Returns the daily per-template usage of a tenant. Only the guild owner may view usage outside of secure contexts */
+      op: "GetTemplateUsage";
+      /** Tenant ID to fetch usage for */
+      id: Id;
+      /** Number of days of usage to return (1-90) */
+      days: number;
+    }
   | {
       /** Verify a presigned URL and return the decoded payload */
       op: "GetBlobData";
@@ -53,6 +109,18 @@ export type MBotSyscall =
       signature: string;
   } 
 
+  | { 
+      /** Dispatch a synthetic gateway event (as built by GetEventFixture) to a tenant (Secure only) */
+      op: "AdminDispatchEventFixture"; 
+      /** Tenant ID to dispatch the event to, the guild of the payload defaults to it */
+      id: Id; 
+      /** Name of the gateway event */
+      event: string; 
+      /** JSON merge patch applied to the payload */
+      overrides?: Record<string, unknown> | null; 
+      /** IDs to use in place of the defaults */
+      ids?: FixtureIds | null 
+    }
   | { 
       /** Dispatch an event to a worker process with some safety checks removed (Secure only) */
       op: "AdminRelaxedDispatchEvent"; 
@@ -87,6 +155,14 @@ export type MBotSyscall =
       /** The new moderation flags bitfield */
       modflags: number 
     }
+  | { 
+      /** Admin API to set the VM limit overrides of a tenant, applied live to running VMs (Secure only) */
+      op: "AdminSetTenantLimits"; 
+      /** The ID of the tenant */
+      id: Id; 
+      /** The new limits, unset limits use the defaults */
+      limits: TenantLimits 
+    }
   | { 
       /** Admin API to run a set of state ops on a tenant (Secure only) */
       op: "AdminState"; 
@@ -100,6 +176,14 @@ export type MBotSyscall =
       op: "AdminFetchTenantState"; 
       /** The ID of the tenant */
       id: Id 
+    }
+  | { 
+      /** Admin API to replay a recorded execution of a tenant in a throwaway VM (Secure only) */
+      op: "AdminReplayExecution"; 
+      /** The ID of the tenant */
+      id: Id; 
+      /** The key of the recording in the #replay key-value scope */
+      key: string 
     };
 
 export type MBotSyscallRet = 
@@ -123,6 +207,11 @@ export type MBotSyscallRet =
       /** Current status information of the bot and its shards */
       status: BotStatus 
     }
+  | { 
+      /** Status page response */
+      op: "StatusPage"; 
+      status: StatusPage 
+    }
   | { 
       /** Response containing a Khronos value */
       op: "KhronosValue"; 
@@ -159,6 +248,29 @@ export type MBotSyscallRet =
       op: "FeedTicket";
       payload: string;
       sig: string;
+    } | {
+      /** Daily per-template usage (oldest first) */
+      op: "TemplateUsage";
+      usage: TemplateUsageRow[];
+    } | {
+      /** JSON schema of rule sets */
+      op: "RulesSchema";
+      schema: Record<string, unknown>;
+    } | {
+      /** Schemas of gateway event payloads */
+      op: "EventSchemas";
+      /** Latest event version */
+      version: number;
+      schemas: EventSchema[];
+    } | {
+      /** Rule set validation result, error is null if the rule set is valid */
+      op: "RulesValidation";
+      error: string | null;
+    } | {
+      /** Synthetic gateway event payload */
+      op: "EventFixture";
+      event: string;
+      data: Record<string, unknown>;
     } | { 
       /** Generic success acknowledgement */
       op: "Ack" 
@@ -0,0 +1,85 @@
+import { type Id } from '../types/common'
+
+export type MDataSyscall = 
+  | { 
+      /** Schedule deletion of all data of a tenant once the grace period is over (Owner only) */
+      op: "RequestDeletion"; 
+      /** The tenant */
+      id: Id 
+    }
+  | { 
+      /** Cancel the pending deletion of a tenant (Owner only) */
+      op: "CancelDeletion"; 
+      /** The tenant */
+      id: Id 
+    }
+  | { 
+      /** Start an export of all data of a tenant (Owner only) */
+      op: "RequestExport"; 
+      /** The tenant */
+      id: Id 
+    }
+  | { 
+      /** Get the most recent deletions and exports of a tenant (Owner only) */
+      op: "ListJobs"; 
+      /** The tenant */
+      id: Id 
+    }
+  | { 
+      /** Get the audit log of actions taken on the data of a tenant (Owner only) */
+      op: "GetAuditLog"; 
+      /** The tenant */
+      id: Id 
+    };
+
+export type DataJob = {
+  job_id: string;
+  id: Id;
+  kind: "deletion" | "export";
+  state: "pending" | "running" | "completed" | "cancelled";
+  /** Why the job was started (bot_removed or requested) */
+  reason: string;
+  /** The user who requested the job, null for jobs started by the bot itself */
+  requested_by: string | null;
+  /** When the job will be run */
+  execute_after: string;
+  /** Steps which have finished */
+  steps_done: string[];
+  steps_total: number;
+  /** Error of the last failed attempt, failed jobs are retried */
+  error: string | null;
+  created_at: string;
+  completed_at: string | null;
+  /** Download URL of a completed export, until it expires */
+  download_url: string | null;
+};
+
+export type DataAuditEntry = {
+  job_id: string | null;
+  action: string;
+  /** The user who took the action, null for actions taken by the bot itself */
+  actor: string | null;
+  details: string | null;
+  created_at: string;
+};
+
+export type MDataSyscallRet = 
+  | { 
+      /** Scheduled (or already outstanding) job response */
+      op: "Job"; 
+      job: DataJob 
+    }
+  | { 
+      /** List jobs response */
+      op: "Jobs"; 
+      jobs: DataJob[] 
+    }
+  | { 
+      /** Audit log response */
+      op: "AuditLog"; 
+      entries: DataAuditEntry[] 
+    }
+  | { 
+      /** Acknowledgement response */
+      op: "Ack"; 
+    };
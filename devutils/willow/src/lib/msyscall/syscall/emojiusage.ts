@@ -0,0 +1,43 @@
+export type EmojiItem = {
+  kind: "emoji" | "sticker";
+  id: string;
+  name: string;
+};
+
+export type EmojiUsageStats = {
+  kind: "emoji" | "sticker";
+  item_id: string;
+  /** The name the emoji or sticker was last used with */
+  name: string;
+  message_uses: number;
+  reaction_uses: number;
+  /** The last day the emoji or sticker was used on, null if never */
+  last_used: string | null;
+};
+
+export type MEmojiUsageSyscall = 
+  | { 
+      /** Get the most used emojis and stickers of a guild over the last `days` days (Owner only) */
+      op: "Top"; 
+      guild_id: string;
+      /** 1 to 90 */
+      days: number;
+      /** Only report emojis or stickers */
+      kind?: "emoji" | "sticker" | null;
+      /** At most 500 */
+      limit: number 
+    }
+  | { 
+      /** Get the emojis and stickers of a guild not used in the last `days` days, least recently used first (Owner only) */
+      op: "Unused"; 
+      guild_id: string;
+      /** 1 to 90 */
+      days: number 
+    };
+
+export type MEmojiUsageSyscallRet = 
+  | { 
+      /** Emoji usage report response */
+      op: "Usage"; 
+      usage: EmojiUsageStats[] 
+    };
@@ -0,0 +1,31 @@
+import { type FeatureFlag } from '../types/featureflags'
+
+export type MFeatureFlagSyscall = 
+  | { 
+      /** List all feature flags (Secure only) */
+      op: "ListFeatureFlags"; 
+    }
+  | { 
+      /** Create or update a feature flag (Secure only) */
+      op: "SetFeatureFlag"; 
+      /** The feature flag to set */
+      flag: FeatureFlag 
+    }
+  | { 
+      /** Delete a feature flag (Secure only) */
+      op: "DeleteFeatureFlag"; 
+      /** The name of the feature flag */
+      name: string 
+    };
+
+export type MFeatureFlagSyscallRet = 
+  | { 
+      /** List of feature flags response */
+      op: "FeatureFlagList"; 
+      /** All feature flags */
+      flags: FeatureFlag[] 
+    }
+  | { 
+      /** Generic success acknowledgement */
+      op: "Ack" 
+    };
@@ -0,0 +1,58 @@
+export type MInboundWebhookSyscall = 
+  | { 
+      /** Create an inbound webhook, returning its URL and secret (Owner only) */
+      op: "Create"; 
+      guild_id: string;
+      /** Name of the webhook, passed to templates to tell webhooks apart */
+      name: string 
+    }
+  | { 
+      /** List the inbound webhooks of a guild (Owner only) */
+      op: "List"; 
+      guild_id: string 
+    }
+  | { 
+      /** Delete an inbound webhook (Owner only) */
+      op: "Delete"; 
+      guild_id: string;
+      webhook_id: string 
+    }
+  | { 
+      /** Replace the secret of an inbound webhook (Owner only) */
+      op: "RotateSecret"; 
+      guild_id: string;
+      webhook_id: string 
+    };
+
+export type InboundWebhook = {
+  id: string;
+  guild_id: string;
+  name: string;
+  /** The user who created the webhook */
+  created_by: string | null;
+  created_at: string;
+  last_used_at: string | null;
+};
+
+export type MInboundWebhookSyscallRet = 
+  | { 
+      /** Created webhook response, the secret is only returned here */
+      op: "Created"; 
+      webhook: InboundWebhook;
+      url: string;
+      secret: string 
+    }
+  | { 
+      /** List webhooks response */
+      op: "Webhooks"; 
+      webhooks: InboundWebhook[] 
+    }
+  | { 
+      /** Rotated secret response */
+      op: "Secret"; 
+      secret: string 
+    }
+  | { 
+      /** Acknowledgement response */
+      op: "Ack"; 
+    };
@@ -3,6 +3,22 @@ import { type MAuthSyscall, type MAuthSyscallRet } from './auth'
 import { type MBotSyscall, type MBotSyscallRet } from './bot'
 import { type MDiscordSyscall, type MDiscordSyscallRet } from './discord'
 import { type MGkvSyscall, type MGkvSyscallRet } from './gkv'
+import { type MFeatureFlagSyscall, type MFeatureFlagSyscallRet } from './featureflags'
+import { type MRoutingSyscall, type MRoutingSyscallRet } from './routing'
+import { type MDataSyscall, type MDataSyscallRet } from './data'
+import { type MModImportSyscall, type MModImportSyscallRet } from './modimport'
+import { type MInboundWebhookSyscall, type MInboundWebhookSyscallRet } from './inboundwebhooks'
+import { type MWorkflowSyscall, type MWorkflowSyscallRet } from './workflows'
+import { type MAppealSyscall, type MAppealSyscallRet } from './appeals'
+import { type MAltDetectSyscall, type MAltDetectSyscallRet } from './altdetect'
+import { type MInviteSyscall, type MInviteSyscallRet } from './invites'
+import { type MPermissionSnapshotSyscall, type MPermissionSnapshotSyscallRet } from './permsnapshots'
+import { type MBackupSyscall, type MBackupSyscallRet } from './backups'
+import { type MScheduleSyscall, type MScheduleSyscallRet } from './schedules'
+import { type MEmojiUsageSyscall, type MEmojiUsageSyscallRet } from './emojiusage'
+import { type MActivitySyscall, type MActivitySyscallRet } from './activity'
+import { type MTemplateEnvSyscall, type MTemplateEnvSyscallRet } from './templateenv'
+import { type MArchivalSyscall, type MArchivalSyscallRet } from './archival'
 
 /**
  * All possible top-level msyscall operation types
@@ -36,6 +52,102 @@ export type MSyscallArgs =
       op: "Gkv"; 
       /** The global key-value request payload */
       req: MGkvSyscall 
+    }
+  | { 
+      /** Feature flag specific system calls */
+      op: "FeatureFlags"; 
+      /** The feature flag request payload */
+      req: MFeatureFlagSyscall 
+    }
+  | { 
+      /** Tenant routing specific system calls */
+      op: "Routing"; 
+      /** The routing request payload */
+      req: MRoutingSyscall 
+    }
+  | { 
+      /** Tenant data deletion and export specific system calls */
+      op: "Data"; 
+      /** The data request payload */
+      req: MDataSyscall 
+    }
+  | { 
+      /** Sting import (from the exports of other bots) specific system calls */
+      op: "ModImport"; 
+      /** The import request payload */
+      req: MModImportSyscall 
+    }
+  | { 
+      /** Inbound webhook (external services triggering templates) specific system calls */
+      op: "InboundWebhooks"; 
+      /** The inbound webhook request payload */
+      req: MInboundWebhookSyscall 
+    }
+  | { 
+      /** Workflow instance (multi-step template flows) specific system calls */
+      op: "Workflows"; 
+      /** The workflow request payload */
+      req: MWorkflowSyscall 
+    }
+  | { 
+      /** Appeal (ban and timeout appeals) specific system calls */
+      op: "Appeals"; 
+      /** The appeal request payload */
+      req: MAppealSyscall 
+    }
+  | { 
+      /** Alt detection (join-pattern correlation) specific system calls */
+      op: "AltDetect"; 
+      /** The alt detection request payload */
+      req: MAltDetectSyscall 
+    }
+  | { 
+      /** Invite tracking (join attribution) specific system calls */
+      op: "Invites"; 
+      /** The invite tracking request payload */
+      req: MInviteSyscall 
+    }
+  | { 
+      /** Permission snapshot (drift detection) specific system calls */
+      op: "PermissionSnapshots"; 
+      /** The permission snapshot request payload */
+      req: MPermissionSnapshotSyscall 
+    }
+  | { 
+      /** Guild backup (structure backup and restore) specific system calls */
+      op: "Backups"; 
+      /** The backup request payload */
+      req: MBackupSyscall 
+    }
+  | { 
+      /** Scheduled message (recurring announcement) specific system calls */
+      op: "Schedules"; 
+      /** The scheduled message request payload */
+      req: MScheduleSyscall 
+    }
+  | { 
+      /** Emoji and sticker usage report specific system calls */
+      op: "EmojiUsage"; 
+      /** The emoji usage request payload */
+      req: MEmojiUsageSyscall 
+    }
+  | { 
+      /** Message activity report specific system calls */
+      op: "Activity"; 
+      /** The activity request payload */
+      req: MActivitySyscall 
+    }
+  | { 
+      /** Template environment variable specific system calls */
+      op: "TemplateEnv"; 
+      /** The template environment request payload */
+      req: MTemplateEnvSyscall 
+    }
+  | { 
+      /** Cold storage archival specific system calls (Secure only) */
+      op: "Archival"; 
+      /** The archival request payload */
+      req: MArchivalSyscall 
     };
 
 /**
@@ -65,6 +177,102 @@ export type MSyscallRet =
       op: "Gkv"; 
       /** The global key-value response data */
       data: MGkvSyscallRet 
+    }
+  | { 
+      /** Feature flag specific system call response */
+      op: "FeatureFlags"; 
+      /** The feature flag response data */
+      data: MFeatureFlagSyscallRet 
+    }
+  | { 
+      /** Tenant routing specific system call response */
+      op: "Routing"; 
+      /** The routing response data */
+      data: MRoutingSyscallRet 
+    }
+  | { 
+      /** Tenant data deletion and export specific system call response */
+      op: "Data"; 
+      /** The data response data */
+      data: MDataSyscallRet 
+    }
+  | { 
+      /** Sting import specific system call response */
+      op: "ModImport"; 
+      /** The import response data */
+      data: MModImportSyscallRet 
+    }
+  | { 
+      /** Inbound webhook specific system call response */
+      op: "InboundWebhooks"; 
+      /** The inbound webhook response data */
+      data: MInboundWebhookSyscallRet 
+    }
+  | { 
+      /** Workflow instance specific system call response */
+      op: "Workflows"; 
+      /** The workflow response data */
+      data: MWorkflowSyscallRet 
+    }
+  | { 
+      /** Appeal specific system call response */
+      op: "Appeals"; 
+      /** The appeal response data */
+      data: MAppealSyscallRet 
+    }
+  | { 
+      /** Alt detection specific system call response */
+      op: "AltDetect"; 
+      /** The alt detection response data */
+      data: MAltDetectSyscallRet 
+    }
+  | { 
+      /** Invite tracking specific system call response */
+      op: "Invites"; 
+      /** The invite tracking response data */
+      data: MInviteSyscallRet 
+    }
+  | { 
+      /** Permission snapshot specific system call response */
+      op: "PermissionSnapshots"; 
+      /** The permission snapshot response data */
+      data: MPermissionSnapshotSyscallRet 
+    }
+  | { 
+      /** Guild backup specific system call response */
+      op: "Backups"; 
+      /** The backup response data */
+      data: MBackupSyscallRet 
+    }
+  | { 
+      /** Scheduled message specific system call response */
+      op: "Schedules"; 
+      /** The scheduled message response data */
+      data: MScheduleSyscallRet 
+    }
+  | { 
+      /** Emoji and sticker usage report specific system call response */
+      op: "EmojiUsage"; 
+      /** The emoji usage response data */
+      data: MEmojiUsageSyscallRet 
+    }
+  | { 
+      /** Message activity report specific system call response */
+      op: "Activity"; 
+      /** The activity response data */
+      data: MActivitySyscallRet 
+    }
+  | { 
+      /** Template environment variable specific system call response */
+      op: "TemplateEnv"; 
+      /** The template environment response data */
+      data: MTemplateEnvSyscallRet 
+    }
+  | { 
+      /** Cold storage archival specific system call response */
+      op: "Archival"; 
+      /** The archival response data */
+      data: MArchivalSyscallRet 
     };
 
 /**
@@ -0,0 +1,47 @@
+export type MInviteSyscall = 
+  | { 
+      /** List the most recent joins of a guild along with the invite they were attributed to (Owner only) */
+      op: "List"; 
+      guild_id: string;
+      /** Only list the join of this member */
+      user_id?: string | null;
+      /** Only list joins through this invite (or vanity) code */
+      code?: string | null 
+    }
+  | { 
+      /** Get the number of joins of a guild per invite (Owner only) */
+      op: "Stats"; 
+      guild_id: string 
+    };
+
+export type InviteAttribution = {
+  guild_id: string;
+  user_id: string;
+  /** Unknown if several invites were used at once or the bot lacks Manage Server */
+  source: "invite" | "vanity" | "unknown";
+  /** The invite (or vanity) code */
+  code: string | null;
+  /** The user who created the invite */
+  inviter_id: string | null;
+  joined_at: string;
+};
+
+export type InviteStats = {
+  source: "invite" | "vanity" | "unknown";
+  code: string | null;
+  inviter_id: string | null;
+  joins: number;
+  last_join_at: string;
+};
+
+export type MInviteSyscallRet = 
+  | { 
+      /** List joins response */
+      op: "Joins"; 
+      joins: InviteAttribution[] 
+    }
+  | { 
+      /** Invite stats response */
+      op: "Stats"; 
+      invites: InviteStats[] 
+    };
@@ -0,0 +1,90 @@
+import { type Id } from '../types/common'
+
+/** Format of a moderation export of another bot */
+export type ImportFormat = "dyno" | "carlbot" | "wick";
+
+export type ImportOptions = {
+  /** How many days after the original case an imported sting expires (default 90) */
+  expiry_days?: number;
+};
+
+export type MModImportSyscall = 
+  | { 
+      /** Parse an export and return what would be imported, without importing anything (Owner only) */
+      op: "Preview"; 
+      /** The tenant */
+      id: Id;
+      format: ImportFormat;
+      /** The contents of the export (JSON for Dyno and Wick, CSV for Carl-bot) */
+      content: string;
+      options?: ImportOptions;
+    }
+  | { 
+      /** Start importing the stings of an export in the background (Owner only) */
+      op: "Start"; 
+      /** The tenant */
+      id: Id;
+      format: ImportFormat;
+      /** The contents of the export (JSON for Dyno and Wick, CSV for Carl-bot) */
+      content: string;
+      options?: ImportOptions;
+    }
+  | { 
+      /** Get the progress of the most recent import of a tenant (Owner only) */
+      op: "Status"; 
+      /** The tenant */
+      id: Id 
+    };
+
+export type ImportAction = "warn" | "mute" | "kick" | "ban";
+
+export type ImportedSting = {
+  /** Provenance tag of the sting (import:<format>:<case id>), also its sting id */
+  source: string;
+  action: ImportAction;
+  userid: string;
+  modid: string | null;
+  stings: number;
+  reason: string;
+  created_at: string;
+  expires_at: string;
+};
+
+export type ImportPreview = {
+  format: ImportFormat;
+  /** Total number of cases in the export */
+  total: number;
+  /** Number of stings that would be imported */
+  importable: number;
+  /** Cases whose sting would have already expired */
+  expired: number;
+  by_action: Partial<Record<ImportAction, number>>;
+  /** Number of cases that cannot be imported, the first of which are listed in skipped */
+  skipped_count: number;
+  skipped: { record: number; reason: string }[];
+  /** The first stings that would be imported */
+  sample: ImportedSting[];
+};
+
+export type ImportJob = {
+  job_id: string;
+  format: ImportFormat;
+  state: "running" | "completed" | "failed";
+  total: number;
+  processed: number;
+  error: string | null;
+  started_at: string;
+  finished_at: string | null;
+};
+
+export type MModImportSyscallRet = 
+  | { 
+      /** Preview response */
+      op: "Preview"; 
+      preview: ImportPreview 
+    }
+  | { 
+      /** Started (or most recent) import, null if the tenant has not imported anything since the last restart */
+      op: "Job"; 
+      job: ImportJob | null 
+    };
@@ -0,0 +1,15 @@
+import { type KhronosValue } from '../khronosvalue'
+
+export type MPermissionSnapshotSyscall = 
+  | { 
+      /** Snapshot the channel overwrites and role permissions of a guild now, returning the changes since the last snapshot (Owner only) */
+      op: "Take"; 
+      guild_id: string 
+    };
+
+export type MPermissionSnapshotSyscallRet = 
+  | { 
+      /** Take response, the drift (since, taken_at, manual and changes) relative to the last snapshot */
+      op: "Drift"; 
+      drift: KhronosValue 
+    };
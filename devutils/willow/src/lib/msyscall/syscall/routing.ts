@@ -0,0 +1,48 @@
+import { type Id } from '../types/common'
+
+export type MRoutingSyscall = 
+  | { 
+      /** Get the workers on the hash ring and any pinned tenants (Secure only) */
+      op: "GetRouting"; 
+    }
+  | { 
+      /** Get the worker a tenant is routed to (Secure only) */
+      op: "GetTenantWorker"; 
+      /** The tenant */
+      id: Id 
+    }
+  | { 
+      /** Move the hash ring to a new set of workers, handing off tenants that change worker (Secure only) */
+      op: "Rebalance"; 
+      /** The IDs of the workers to place on the ring */
+      workers: number[] 
+    };
+
+export type RebalanceResult = {
+  /** Number of tenants moved to a new worker */
+  moved: number;
+  /** Tenants that could not be moved and remain on their old worker */
+  failed: { id: Id; error: string }[];
+};
+
+export type MRoutingSyscallRet = 
+  | { 
+      /** Current routing response */
+      op: "Routing"; 
+      /** Number of worker processes in the pool */
+      pool_size: number;
+      /** Workers on the hash ring */
+      workers: number[];
+      /** Tenants pinned to a worker while being handed off, as [tenant, worker] pairs */
+      pinned: [Id, number][] 
+    }
+  | { 
+      /** Worker of a tenant response */
+      op: "TenantWorker"; 
+      worker_id: number 
+    }
+  | { 
+      /** Rebalance response */
+      op: "Rebalanced"; 
+      result: RebalanceResult 
+    };
@@ -0,0 +1,84 @@
+export type ScheduledMessageContent = {
+  content?: string;
+  /** Discord embed objects, at most 10 */
+  embeds?: Record<string, unknown>[];
+  /** Discord allowed mentions object, nothing is mentioned if unset */
+  allowed_mentions?: Record<string, unknown> | null;
+};
+
+export type ScheduleSpec = {
+  channel_id: string;
+  message: ScheduledMessageContent;
+  /** 5 field cron expression (minute, hour, day of month, month and day of week) */
+  cron: string;
+  /** IANA timezone the cron expression is in, UTC if unset */
+  timezone?: string;
+};
+
+export type MScheduleSyscall = 
+  | { 
+      /** List the scheduled messages of a guild (Owner only) */
+      op: "List"; 
+      guild_id: string 
+    }
+  | { 
+      /** Create or replace a scheduled message of a guild, keeping it paused if it was (Owner only) */
+      op: "Set"; 
+      guild_id: string;
+      name: string;
+      schedule: ScheduleSpec 
+    }
+  | { 
+      /** Delete a scheduled message of a guild (Owner only) */
+      op: "Delete"; 
+      guild_id: string;
+      name: string 
+    }
+  | { 
+      /** Pause a scheduled message of a guild (Owner only) */
+      op: "Pause"; 
+      guild_id: string;
+      name: string 
+    }
+  | { 
+      /** Resume a paused scheduled message of a guild from its next run (Owner only) */
+      op: "Resume"; 
+      guild_id: string;
+      name: string 
+    };
+
+export type ScheduledMessage = {
+  id: number;
+  guild_id: string;
+  /** Name of the schedule, unique within the guild */
+  name: string;
+  channel_id: string;
+  message: ScheduledMessageContent;
+  cron: string;
+  timezone: string;
+  paused: boolean;
+  /** When the message is next posted, null while paused */
+  next_run_at: string | null;
+  /** Failed attempts at posting the current run */
+  attempts: number;
+  last_error: string | null;
+  last_run_at: string | null;
+  created_at: string;
+  updated_at: string;
+};
+
+export type MScheduleSyscallRet = 
+  | { 
+      /** List scheduled messages response */
+      op: "Schedules"; 
+      schedules: ScheduledMessage[] 
+    }
+  | { 
+      /** Set scheduled message response */
+      op: "Schedule"; 
+      schedule: ScheduledMessage 
+    }
+  | { 
+      /** Acknowledgement response */
+      op: "Ack"; 
+    };
@@ -0,0 +1,31 @@
+import { type KhronosValue } from '../khronosvalue'
+
+export type MTemplateEnvSyscall = 
+  | { 
+      /** Get the environment variables a template declares and the values set for them (Owner only) */
+      op: "Get"; 
+      guild_id: string;
+      template: string 
+    }
+  | { 
+      /** Set the values of the environment variables of a template, replacing all previous values (Owner only) */
+      op: "Set"; 
+      guild_id: string;
+      template: string;
+      /** Values keyed by variable name, numbers for number variables and strings (IDs for channels, roles and users) otherwise */
+      values: Record<string, string | number> 
+    }
+  | { 
+      /** Get the environment variables a shop template declares, to ask for their values when installing it (Owner only) */
+      op: "Shop"; 
+      guild_id: string;
+      key: string;
+      version: number 
+    };
+
+export type MTemplateEnvSyscallRet = 
+  | { 
+      /** The variables (spec) and, except for shop templates, the values set for them (values) and the required variables still unset (missing) */
+      op: "Env"; 
+      env: KhronosValue 
+    };
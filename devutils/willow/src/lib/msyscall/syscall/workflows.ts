@@ -0,0 +1,70 @@
+import { type Id } from '../types/common'
+import { type KhronosValue } from '../khronosvalue'
+
+export type MWorkflowSyscall = 
+  | { 
+      /** List the most recently started workflow instances of a tenant (Owner only) */
+      op: "List"; 
+      /** The tenant */
+      id: Id;
+      /** Only list running instances */
+      running_only?: boolean 
+    }
+  | { 
+      /** Get a workflow instance (Owner only) */
+      op: "Get"; 
+      /** The tenant */
+      id: Id;
+      workflow_id: number 
+    }
+  | { 
+      /** Cancel a running workflow instance (Owner only) */
+      op: "Cancel"; 
+      /** The tenant */
+      id: Id;
+      workflow_id: number 
+    };
+
+export type WorkflowInstance = {
+  id: number;
+  owner_id: string;
+  owner_type: string;
+  /** Name of the workflow */
+  workflow: string;
+  /** Key of the instance, unique among the running instances of the workflow */
+  key: string;
+  /** The current step */
+  step: string;
+  data: KhronosValue;
+  state: "running" | "completed" | "cancelled" | "failed";
+  seq: number;
+  /** Why the current step runs */
+  trigger: "start" | "step" | "timer" | "signal";
+  /** When the current step is due, null if it only waits for a signal */
+  wake_at: string | null;
+  /** The signal the current step waits for */
+  wait_signal: string | null;
+  signal_data: KhronosValue | null;
+  /** Attempts at running the current step */
+  attempts: number;
+  last_error: string | null;
+  created_at: string;
+  updated_at: string;
+  completed_at: string | null;
+};
+
+export type MWorkflowSyscallRet = 
+  | { 
+      /** List workflow instances response */
+      op: "Workflows"; 
+      workflows: WorkflowInstance[] 
+    }
+  | { 
+      /** Get workflow instance response */
+      op: "Workflow"; 
+      workflow: WorkflowInstance 
+    }
+  | { 
+      /** Acknowledgement response */
+      op: "Ack"; 
+    };
@@ -15,3 +15,62 @@ export interface BotStatus {
   /** The current uptime of the bot process in seconds */
   uptime: number;
 }
+
+/** Latency of recent dispatches, from an event being queued on a worker to its templates finishing */
+export interface DispatchLatency {
+  /** Number of dispatches the latency is over */
+  samples: number;
+  p50_ms: number;
+  p95_ms: number;
+  max_ms: number;
+}
+
+/** Health of the Stratum connection */
+export interface GatewayStatus {
+  mode: "stratum" | "direct";
+  /** Whether the direct gateway fallback is configured */
+  fallback_enabled: boolean;
+  consecutive_failures: number;
+  last_error: string | null;
+  /** When the mode last changed */
+  mode_since: string;
+}
+
+/** An error of the templates of a guild */
+export interface RecentError {
+  error: string;
+  created_at: string;
+}
+
+export interface StatusPage {
+  /** Whether the bot is degraded, templates may be slow to respond or miss events while it is */
+  degraded: boolean;
+  /** Why the bot is degraded, empty if it isn't */
+  degraded_reasons: string[];
+  /** A map of shard group ID to shard connection information */
+  shard_conns: Record<number, ShardConn>;
+  gateway: GatewayStatus;
+  /** Latency of the recent dispatches of all workers */
+  dispatch: DispatchLatency;
+  /** The most recent errors of the templates of the guild (newest first), empty if no guild was requested */
+  recent_errors: RecentError[];
+}
+
+export interface TemplateUsageRow {
+  /** The name of the template */
+  template: string;
+  /** The day (UTC, YYYY-MM-DD) the usage was recorded on */
+  day: string;
+  /** Number of events dispatched to the template */
+  executions: number;
+  /** Number of executions which errored */
+  errors: number;
+  /** Total wall time spent executing the template in milliseconds */
+  wall_time_ms: number;
+  /** Highest memory usage of the tenants VM observed after an execution of the template */
+  memory_peak_bytes: number;
+  /** Number of Discord API calls made */
+  discord_api_calls: number;
+  /** Number of key-value/state operations performed */
+  kv_ops: number;
+}
@@ -0,0 +1,13 @@
+export interface FeatureFlag {
+  /** Name of the flag. `event:{EVENT_NAME}` and `syscall:{Category}` flags gate events/syscalls */
+  name: string;
+  description: string;
+  /** Global kill-switch */
+  enabled: boolean;
+  /** Percentage (0-100) of tenants the flag is enabled for */
+  rollout_percentage: number;
+  /** Tenant IDs the flag is always enabled for */
+  include_tenants: string[];
+  /** Tenant IDs the flag is never enabled for */
+  exclude_tenants: string[];
+}
@@ -1,4 +1,8 @@
 import { type KhronosValue } from '../khronosvalue'
+import { type WorkflowInstance } from '../syscall/workflows'
+import { type ScheduleSpec, type ScheduledMessage } from '../syscall/schedules'
+import { type EmojiItem, type EmojiUsageStats } from '../syscall/emojiusage'
+import { type ActivityHeatmap } from '../syscall/activity'
 
 export type StateOp = 
   | { op: "KvFind"; query: string; scope: string }
@@ -12,7 +16,43 @@ export type StateOp =
   | { op: "GlobalKvDelete"; key: string; version: number; scope: string }
   | { op: "GlobalKvGetData"; key: string; version: number; scope: string }
   | { op: "SubscribeEvent"; event: string; system: string }
-  | { op: "UnsubscribeEvent"; event: string; system: string };
+  | { op: "UnsubscribeEvent"; event: string; system: string }
+  | { op: "SetModmailChannel"; channel_id: string | null }
+  | { op: "SetAppealsChannel"; channel_id: string | null }
+  | { op: "SetWatchlistChannel"; channel_id: string | null }
+  | { op: "WatchlistAdd"; user_id: string; severity: WatchlistSeverity; note: string; added_by?: string | null }
+  | { op: "WatchlistRemove"; user_id: string }
+  | { op: "WatchlistGet"; user_id: string }
+  | { op: "WatchlistList" }
+  | { op: "SetAltDetection"; sensitivity: "low" | "medium" | "high" | null }
+  | { op: "SetInviteTracking"; enabled: boolean }
+  | { op: "SetNamePolicy"; policy: NamePolicy | null }
+  | { op: "SetStickyRoles"; config: StickyRolesConfig | null }
+  | { op: "SetPermissionSnapshots"; enabled: boolean }
+  | { op: "SetNukeProtection"; config: NukeProtectionConfig | null }
+  | { op: "SetWebhookSpam"; config: WebhookSpamConfig | null }
+  | { op: "SetAutoPublish"; config: AutoPublishConfig | null }
+  | { op: "SetThreadPolicies"; config: ThreadPoliciesConfig | null }
+  | { op: "SetEmojiUsage"; config: EmojiUsageConfig | null }
+  | { op: "SetActivityTracking"; config: ActivityConfig | null }
+  | { op: "SetAuditCorrelation"; config: AuditCorrelationConfig | null }
+  | { op: "SetVoiceIdle"; config: VoiceIdleConfig | null }
+  | { op: "SetResultCache"; config: ResultCacheConfig | null }
+  | { op: "SetDisabledPlugins"; plugins: string[] }
+  | { op: "Once"; key: string; ttl: number }
+  | { op: "WorkflowStart"; workflow: string; key: string; step: string; data: KhronosValue }
+  | { op: "WorkflowGet"; workflow: string; key: string }
+  | { op: "WorkflowAdvance"; id: number; seq: number; step: string; data: KhronosValue; delay?: number | null; signal?: string | null }
+  | { op: "WorkflowComplete"; id: number; seq: number; data: KhronosValue }
+  | { op: "WorkflowSignal"; workflow: string; key: string; signal: string; data: KhronosValue }
+  | { op: "WorkflowCancel"; workflow: string; key: string }
+  | { op: "ScheduleList" }
+  | { op: "ScheduleSet"; name: string; schedule: ScheduleSpec }
+  | { op: "ScheduleDelete"; name: string }
+  | { op: "SchedulePause"; name: string; paused: boolean }
+  | { op: "EmojiUsageTop"; days: number; kind?: "emoji" | "sticker" | null; limit: number }
+  | { op: "EmojiUsageUnused"; days: number; items: EmojiItem[] }
+  | { op: "ActivityHeatmap"; days: number; channel_id?: string | null };
 
 export interface KvLookup {
   key: string;
@@ -43,11 +83,163 @@ export type StateExecResult =
   | { op: "KvWithBlob"; l: KvLookup; blob?: number[] }
   | { op: "GlobalKv"; l: GlobalKv }
   | { op: "GlobalKvData"; data: KhronosValue }
-  | { op: "GlobalKvDataOpaque"; data: KhronosValue };
+  | { op: "GlobalKvDataOpaque"; data: KhronosValue }
+  | { op: "Once"; first: boolean }
+  | { op: "Workflow"; w: WorkflowInstance }
+  | { op: "WorkflowStarted"; w: WorkflowInstance; created: boolean }
+  | { op: "WorkflowUpdated"; updated: boolean }
+  | { op: "Schedule"; s: ScheduledMessage }
+  | { op: "ScheduleUpdated"; updated: boolean }
+  | { op: "EmojiUsage"; u: EmojiUsageStats }
+  | { op: "ActivityHeatmap"; h: ActivityHeatmap }
+  | { op: "Watchlist"; e: WatchlistEntry };
+
+export interface TenantLimits {
+  /** Maximum memory usage of the tenants VM in bytes (unset for the default) */
+  memory_limit?: number | null;
+  /** Maximum execution time in milliseconds before a scheduler yield must happen (unset for the default) */
+  execution_time_limit_ms?: number | null;
+  /** Maximum time in milliseconds to wait for a dispatched event to return (unset for the default) */
+  return_wait_ms?: number | null;
+}
+
+export type WatchlistSeverity = "low" | "medium" | "high";
+
+export interface WatchlistEntry {
+  user_id: string;
+  severity: WatchlistSeverity;
+  note: string;
+  added_by?: string | null;
+  created_at: string;
+  updated_at: string;
+}
+
+export type NameCheck =
+  | { type: "blocklist"; patterns: string[]; normalize?: boolean }
+  | { type: "prefix"; prefix: string }
+  | { type: "readable" };
+
+export type NameRule = NameCheck & {
+  name: string;
+  actions: ("rename" | "notify" | "sting")[];
+  rename_to?: string | null;
+  stings?: number;
+  /** Seconds */
+  sting_expiry?: number;
+}
+
+export interface NamePolicy {
+  rules: NameRule[];
+  exempt_roles?: string[];
+  notify_channel_id?: string | null;
+}
+
+export interface StickyRolesConfig {
+  retention_days: number;
+  excluded_roles?: string[];
+  exclude_dangerous?: boolean;
+  restore_nick?: boolean;
+}
+
+export interface NukeThresholds {
+  channel_deletes?: number | null;
+  role_deletes?: number | null;
+  bans?: number | null;
+  kicks?: number | null;
+  webhook_creates?: number | null;
+}
+
+export interface NukeProtectionConfig {
+  window_secs: number;
+  thresholds: NukeThresholds;
+  strip_roles?: boolean;
+  lockdown?: boolean;
+  exempt_users?: string[];
+}
+
+export interface WebhookSpamConfig {
+  window_secs: number;
+  max_messages: number;
+  max_duplicates?: number | null;
+  block_mass_mentions?: boolean;
+  revoke?: boolean;
+  log_channel_id?: string | null;
+  exempt_webhooks?: string[];
+}
+
+export interface AutoPublishConfig {
+  channels: string[];
+  include_bots?: boolean;
+}
+
+export interface ThreadPolicy {
+  channel_id: string;
+  archive_after_days?: number | null;
+  lock_on_archive?: boolean;
+  tag_rules?: { pattern: string; tag_id: string }[];
+  pin_rules?: { pattern: string; pin: boolean }[];
+  max_open_threads?: number | null;
+}
+
+export interface ThreadPoliciesConfig {
+  policies: ThreadPolicy[];
+}
+
+export interface EmojiUsageConfig {
+  sample_one_in?: number;
+  reactions?: boolean;
+}
+
+export interface ActivityConfig {
+  retention_days?: number;
+  include_bots?: boolean;
+}
+
+export interface AuditCorrelationConfig {
+  events: string[];
+}
+
+export interface VoiceIdleThreshold {
+  kind: "idle" | "camping";
+  minutes: number;
+}
+
+export interface VoiceIdleConfig {
+  thresholds: VoiceIdleThreshold[];
+  deafened_only?: boolean;
+  ignored_channels?: string[];
+}
+
+export interface ResultCacheConfig {
+  // Template name to the number of seconds its results are cached for (1-300)
+  templates: Record<string, number>;
+}
 
 export interface TenantState {
   events: Record<string, string[]>;
   modflags: number;
+  limits?: TenantLimits;
+  modmail_channel_id?: string | null;
+  appeals_channel_id?: string | null;
+  watchlist_channel_id?: string | null;
+  alt_sensitivity?: "low" | "medium" | "high" | null;
+  invite_tracking?: boolean;
+  name_policy?: NamePolicy | null;
+  sticky_roles?: StickyRolesConfig | null;
+  permission_snapshots?: boolean;
+  nuke_protection?: NukeProtectionConfig | null;
+  webhook_spam?: WebhookSpamConfig | null;
+  auto_publish?: AutoPublishConfig | null;
+  thread_policies?: ThreadPoliciesConfig | null;
+  emoji_usage?: EmojiUsageConfig | null;
+  activity?: ActivityConfig | null;
+  audit_correlation?: AuditCorrelationConfig | null;
+  voice_idle?: VoiceIdleConfig | null;
+  result_cache?: ResultCacheConfig | null;
+  // Plugins (such as "crypto") the guild has disabled, on top of those disabled by the deployment
+  disabled_plugins?: string[];
+  /** When the data of the tenant was moved to cold storage, null if not archived */
+  archived_at?: string | null;
 }
 
 export interface StateExecResponse {
@@ -0,0 +1,52 @@
+//! Gateway event payloads, from the json Stratum sends up to the typed accessor templates receive
+#![no_main]
+
+use arbitrary::Arbitrary;
+use dapi::UserId;
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use libfuzzer_sys::fuzz_target;
+use tw::worker::actor::EventActor;
+use tw::worker::eventtypes;
+use tw_fuzz::ArbitraryJson;
+
+#[derive(Debug, Arbitrary)]
+enum Payload {
+    /// Raw bytes, as sent by Stratum
+    Raw(Vec<u8>),
+    /// Well formed json
+    Json(ArbitraryJson),
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    /// Index into the dispatchable events
+    event: usize,
+    payload: Payload,
+    bot_id: u64,
+}
+
+thread_local! {
+    static LUA: Lua = Lua::new();
+}
+
+fuzz_target!(|input: Input| {
+    let event = dapi::EVENT_LIST[input.event % dapi::EVENT_LIST.len()];
+    let payload: serde_json::Value = match input.payload {
+        Payload::Raw(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(payload) => payload,
+            Err(_) => return,
+        },
+        Payload::Json(ArbitraryJson(payload)) => payload,
+    };
+
+    let bot_id = UserId::new(input.bot_id.max(1));
+    let _ = EventActor::from_payload(event, &payload, bot_id);
+
+    LUA.with(|lua| {
+        let Ok(data) = lua.to_value_with(&payload, LUA_SERIALIZE_OPTIONS) else {
+            return;
+        };
+        let _ = eventtypes::create_typed(lua, event, &data);
+    });
+});
@@ -0,0 +1,45 @@
+//! KhronosValue json and (compressed) msgpack round trips, and conversion into Luau
+#![no_main]
+
+use arbitrary::Arbitrary;
+use khronos_runtime::rt::mlua::prelude::*;
+use khronos_runtime::utils::khronos_value::{CKhronosValue, KhronosValue};
+use libfuzzer_sys::fuzz_target;
+use tw_fuzz::ArbitraryJson;
+
+#[derive(Debug, Arbitrary)]
+enum Input {
+    /// Raw json, as sent by the website
+    Json(Vec<u8>),
+    /// Well formed json
+    Structured(ArbitraryJson),
+    /// Raw msgpack of a compressed value, as sent over mesophyll
+    Compressed(Vec<u8>),
+}
+
+thread_local! {
+    static LUA: Lua = Lua::new();
+}
+
+fuzz_target!(|input: Input| {
+    let value = match input {
+        Input::Json(bytes) => serde_json::from_slice::<KhronosValue>(&bytes).ok(),
+        Input::Structured(ArbitraryJson(json)) => serde_json::from_value::<KhronosValue>(json).ok(),
+        Input::Compressed(bytes) => rmp_serde::from_slice::<CKhronosValue>(&bytes).ok().map(|c| c.0),
+    };
+    let Some(value) = value else {
+        return;
+    };
+
+    // Json round trips are stable once normalized by the first decode
+    let json = serde_json::to_value(&value).expect("decoded value failed to encode as json");
+    let decoded: KhronosValue = serde_json::from_value(json.clone()).expect("encoded value failed to decode from json");
+    assert_eq!(serde_json::to_value(&decoded).expect("value failed to encode as json"), json, "json round trip changed the value");
+
+    let compressed = rmp_serde::to_vec(&CKhronosValue(value.clone())).expect("value failed to encode as msgpack");
+    rmp_serde::from_slice::<CKhronosValue>(&compressed).expect("encoded value failed to decode from msgpack");
+
+    LUA.with(|lua| {
+        let _ = value.into_lua(lua);
+    });
+});
@@ -0,0 +1,15 @@
+//! Events as sent between the master and workers over mesophyll (msgpack)
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tw::worker::workerdispatch::SimpleEvent;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(event) = rmp_serde::from_slice::<SimpleEvent>(data) else {
+        return;
+    };
+
+    // Anything which decodes must encode and decode again
+    let encoded = rmp_serde::to_vec(&event).expect("decoded event failed to encode");
+    rmp_serde::from_slice::<SimpleEvent>(&encoded).expect("encoded event failed to decode");
+});
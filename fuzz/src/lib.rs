@@ -0,0 +1,61 @@
+//! Structured inputs shared by the fuzz targets
+//!
+//! Run a target with `cargo +nightly fuzz run <target>` from the repository root
+
+use arbitrary::{Arbitrary, Unstructured};
+use serde_json::{Map, Number, Value};
+
+/// Maximum nesting depth of generated json, deeper values become null
+const MAX_DEPTH: usize = 32;
+
+/// Arbitrary json, generated structurally so inputs reach past the json parser
+#[derive(Debug)]
+pub struct ArbitraryJson(pub Value);
+
+impl<'a> Arbitrary<'a> for ArbitraryJson {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self(json(u, 0)?))
+    }
+}
+
+fn json(u: &mut Unstructured, depth: usize) -> arbitrary::Result<Value> {
+    if depth >= MAX_DEPTH {
+        return Ok(Value::Null);
+    }
+
+    Ok(match u.int_in_range(0..=7)? {
+        0 => Value::Null,
+        1 => Value::Bool(u.arbitrary()?),
+        2 => Value::Number(u.arbitrary::<i64>()?.into()),
+        3 => Number::from_f64(u.arbitrary()?).map_or(Value::Null, Value::Number),
+        4 => Value::String(u.arbitrary()?),
+        // Snowflakes, which payloads are full of
+        5 => Value::String(u.arbitrary::<u64>()?.to_string()),
+        6 => {
+            let len = u.arbitrary_len::<u8>()?.min(16);
+            Value::Array((0..len).map(|_| json(u, depth + 1)).collect::<arbitrary::Result<_>>()?)
+        }
+        _ => {
+            let len = u.arbitrary_len::<u8>()?.min(16);
+            let mut fields = Map::new();
+            for _ in 0..len {
+                fields.insert(field_name(u)?, json(u, depth + 1)?);
+            }
+            Value::Object(fields)
+        }
+    })
+}
+
+/// Field names of gateway payloads, so objects are likely to be read by the code under test
+const FIELD_NAMES: &[&str] = &[
+    "id", "user", "user_id", "author", "member", "guild_id", "channel_id", "bot", "webhook_id", "application_id",
+    "creator", "creator_id", "owner_id", "inviter", "roles", "content", "data", "type", "nick", "op",
+];
+
+fn field_name(u: &mut Unstructured) -> arbitrary::Result<String> {
+    if u.ratio(3, 4)? {
+        Ok((*u.choose(FIELD_NAMES)?).to_string())
+    } else {
+        u.arbitrary()
+    }
+}
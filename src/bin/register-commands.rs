@@ -1,4 +1,6 @@
 use dapi::dhttp::HttpCall;
+use sqlx::postgres::PgPoolOptions;
+use tw::config::CONFIG;
 use tw::master::register;
 use tw::setup_discord;
 use log::info;
@@ -69,4 +71,34 @@ async fn main_impl() {
     })
     .await
     .expect("Failed to register commands");
+
+    if CONFIG.branding.is_some() {
+        info!("Syncing branding");
+
+        let reqwest = reqwest::Client::builder()
+            .connect_timeout(std::time::Duration::from_secs(30))
+            .timeout(std::time::Duration::from_secs(90))
+            .build()
+            .expect("Could not initialize reqwest client");
+
+        let pg_pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&CONFIG.postgres_url)
+            .await
+            .expect("Could not initialize connection");
+
+        let result = register::sync_branding(&reqwest, &pg_pool, false)
+            .await
+            .expect("Failed to sync branding");
+
+        let changed = |updated: bool| if updated { "updated" } else { "unchanged" };
+        info!(
+            "Synced branding: description {}, banner {}, emojis {} created, {} updated, {} deleted",
+            changed(result.description_updated),
+            changed(result.banner_updated),
+            result.emojis_created.len(),
+            result.emojis_updated.len(),
+            result.emojis_deleted.len(),
+        );
+    }
 }
@@ -2,8 +2,8 @@ use tw::config::CONFIG;
 use tw::master::syscall::MSyscallHandler;
 use tw::master::workerpool::WorkerPool;
 use tw::setup_discord;
+use tw::geese::dbpools::{DbPools, PoolKind};
 use log::{debug, info};
-use sqlx::postgres::PgPoolOptions;
 use std::io::Write;
 use std::sync::Arc;
 use tokio::signal::unix::{signal, SignalKind};
@@ -11,9 +11,18 @@ use tokio::signal::unix::{signal, SignalKind};
 /// Command line arguments
 #[derive(Debug, Clone)]
 struct CmdArgs {
-    /// Max connections that should be made to the database
+    /// Max connections that should be made to the database (by the default pool)
     pub max_db_connections: u32,
 
+    /// Max connections of the KV, settings, stings and analytics pools, 0 to share the default pool
+    pub kv_db_connections: u32,
+    pub settings_db_connections: u32,
+    pub stings_db_connections: u32,
+    pub analytics_db_connections: u32,
+
+    /// Max connections of the pool running the state ops of templates, never shared with the default pool
+    pub templates_db_connections: u32,
+
     /// Enables debug logging for luau in workers
     pub worker_debug: bool,
 
@@ -23,6 +32,8 @@ struct CmdArgs {
 
 impl CmdArgs {
     const MAX_DB_CONNECTIONS: u32 = 7;
+    const SUBSYSTEM_DB_CONNECTIONS: u32 = 0;
+    const TEMPLATES_DB_CONNECTIONS: u32 = 5;
     const TOKIO_THREADS: usize = 10;
     const WORKER_DEBUG: bool = false;
     pub fn parse() -> Self {
@@ -30,6 +41,18 @@ impl CmdArgs {
             .ok()
             .and_then(|s| s.parse().ok())
             .unwrap_or(Self::MAX_DB_CONNECTIONS);
+        let subsystem_db_connections = |var: &str| std::env::var(var)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(Self::SUBSYSTEM_DB_CONNECTIONS);
+        let kv_db_connections = subsystem_db_connections("KV_DB_CONNECTIONS");
+        let settings_db_connections = subsystem_db_connections("SETTINGS_DB_CONNECTIONS");
+        let stings_db_connections = subsystem_db_connections("STINGS_DB_CONNECTIONS");
+        let analytics_db_connections = subsystem_db_connections("ANALYTICS_DB_CONNECTIONS");
+        let templates_db_connections = std::env::var("TEMPLATES_DB_CONNECTIONS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(Self::TEMPLATES_DB_CONNECTIONS);
         let tokio_threads = std::env::var("TOKIO_THREADS")
             .ok()
             .and_then(|s| s.parse().ok())
@@ -38,7 +61,16 @@ impl CmdArgs {
             .ok()
             .and_then(|s| Some(s.to_lowercase() == "true" || s == "1"))
             .unwrap_or(Self::WORKER_DEBUG);
-        Self { max_db_connections, tokio_threads, worker_debug }
+        Self {
+            max_db_connections,
+            kv_db_connections,
+            settings_db_connections,
+            stings_db_connections,
+            analytics_db_connections,
+            templates_db_connections,
+            tokio_threads,
+            worker_debug,
+        }
     }
 }
 
@@ -75,6 +107,9 @@ async fn main_impl(args: CmdArgs) {
 
     env_builder.init();
 
+    let tracer_provider = tw::geese::telemetry::init("template-worker-master")
+        .expect("Failed to setup tracing");
+
     debug!("Connecting to database");
 
     let reqwest = reqwest::Client::builder()
@@ -83,8 +118,19 @@ async fn main_impl(args: CmdArgs) {
         .build()
         .expect("Could not initialize reqwest client");
 
+    // Serve the fake Discord API before anything makes Discord calls to it
+    #[cfg(feature = "fake-discord")]
+    if let Some(cfg) = CONFIG.fake_discord.as_ref() {
+        tw::master::fakediscord::FakeDiscord::default().spawn(&cfg.bind).await.expect("Failed to serve fake Discord API");
+    }
+
     let stratum = setup_discord().await;
 
+    // Track Stratum health for health endpoints, workers run their own checks to switch to the direct gateway
+    if let Some(cfg) = CONFIG.gateway_fallback.as_ref() {
+        tw::geese::gateway::spawn_health_monitor(stratum.clone(), cfg);
+    }
+
     // Ask stratum for its worker count
     let worker_count: usize = stratum.get_config()
     .await
@@ -93,15 +139,24 @@ async fn main_impl(args: CmdArgs) {
     .try_into()
     .expect("worker_count exceeds usize limits");
 
-    let pg_pool = PgPoolOptions::new()
-        .max_connections(args.max_db_connections)
-        .connect(&CONFIG.postgres_url)
-        .await
-        .expect("Could not initialize connection");
+    let pools = DbPools::connect(&CONFIG.postgres_url, |kind| match kind {
+        PoolKind::Default => args.max_db_connections,
+        PoolKind::Kv => args.kv_db_connections,
+        PoolKind::Settings => args.settings_db_connections,
+        PoolKind::Stings => args.stings_db_connections,
+        PoolKind::Analytics => args.analytics_db_connections,
+        PoolKind::Templates => args.templates_db_connections,
+    })
+    .await
+    .expect("Could not initialize connection");
+
+    // Read-only queries go to the read replica, if configured
+    let db = tw::geese::dbrouter::DbRouter::new(pools)
+        .expect("Could not initialize read replica");
 
     let mesophyll_server = tw::mesophyll::server::MesophyllServer::new(
         worker_count,
-        pg_pool.clone()
+        db.clone()
     )
     .await
     .expect("Failed to create Mesophyll server");
@@ -109,13 +164,50 @@ async fn main_impl(args: CmdArgs) {
     let worker_pool = Arc::new(
         WorkerPool::new(worker_count, args.worker_debug, mesophyll_server.clone())
     );
+
+    // Run pending deletions and exports of tenant data
+    tw::master::datalifecycle::DataLifecycle::new(worker_pool.clone(), stratum.clone()).spawn();
+
+    // Send the Discord actions templates enqueued into the outbox
+    tw::master::outbox::OutboxSender::new(worker_pool.mesophyll().outbox_db().clone(), stratum.clone()).spawn();
+
+    // Run the due steps of template workflows
+    tw::master::workflows::WorkflowScheduler::new(tw::geese::workflows::WorkflowDb::new(db.primary().clone()), worker_pool.clone()).spawn();
+
+    // Post the watchlist digests of guilds
+    tw::master::watchlist::WatchlistDigester::new(tw::geese::watchlist::WatchlistDb::new(db.primary().clone()), worker_pool.clone()).spawn();
+
+    // Snapshot the permissions of guilds, firing drift events
+    tw::master::permsnapshots::PermissionSnapshotter::new(tw::geese::permsnapshots::PermissionSnapshotDb::new(db.primary().clone()), worker_pool.clone()).spawn();
+
+    // Post the due scheduled messages of guilds
+    tw::master::schedules::ScheduledMessenger::new(tw::geese::schedules::ScheduleDb::new(db.primary().clone()), worker_pool.clone()).spawn();
+
+    // Archive the inactive threads of guilds with thread policies
+    tw::master::threadpolicies::ThreadPolicySweeper::new(tw::geese::threadpolicies::ThreadPolicyDb::new(db.primary().clone()), worker_pool.clone()).spawn();
+
+    // Prune the channel activity of guilds past its retention
+    tw::master::activity::ActivityPruner::new(worker_pool.mesophyll().activity_db().clone()).spawn();
+
+    // Push changes made to the database outside this master (or while it missed them) to the workers
+    tw::master::changelistener::ChangeListener::new(db.primary().clone(), worker_pool.clone()).spawn();
+
+    // Scale the workers on the ring with their load
+    if let Some(cfg) = CONFIG.autoscale.as_ref() {
+        tw::master::autoscaler::Autoscaler::new(worker_pool.clone(), cfg).spawn();
+    }
+
+    // Move the data of inactive guilds into cold storage
+    if let Some(cfg) = CONFIG.archival.as_ref() {
+        tw::master::archival::Archiver::new(worker_pool.clone(), cfg).spawn();
+    }
     
     // Start msyscall server
     let msyscall_handler = MSyscallHandler::new(
         worker_pool.clone(),
         stratum,
         reqwest,
-        pg_pool,
+        db,
     );
 
     tokio::task::spawn(async move {
@@ -145,4 +237,9 @@ async fn main_impl(args: CmdArgs) {
             worker_pool.shutdown_all().await.expect("Failed to kill worker pool");
         }
     }
+
+    // Flush any pending spans
+    if let Some(tracer_provider) = tracer_provider && let Err(e) = tracer_provider.shutdown() {
+        log::error!("Failed to shutdown tracer provider: {e}");
+    }
 }
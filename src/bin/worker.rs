@@ -1,5 +1,4 @@
 use log::debug;
-use tokio::sync::watch;
 use tw::mesophyll::client::MesophyllClient;
 use tw::mesophyll::connman::SockFile;
 use tw::setup_discord;
@@ -94,6 +93,9 @@ async fn main_impl(args: CmdArgs) {
 
     env_builder.init();
 
+    let tracer_provider = tw::geese::telemetry::init("template-worker-worker")
+        .expect("Failed to setup tracing");
+
     debug!("Connecting to database");
 
     let reqwest = reqwest::Client::builder()
@@ -108,8 +110,9 @@ async fn main_impl(args: CmdArgs) {
         .await
         .expect("Failed to create Mesophyll client");
 
+    let meso_client = Arc::new(meso_client);
     let worker_state = WorkerState::new(
-        Arc::new(meso_client.clone()),
+        meso_client.clone(),
         stratum.clone(),
         reqwest,
         args.worker_debug
@@ -124,8 +127,19 @@ async fn main_impl(args: CmdArgs) {
     log::info!("set_wt");
     meso_client.set_wt(worker_thread.clone()).expect("Failed to set wt");
 
-    // Start listening to stratum stream
-    let (_shutdown_tx, shutdown_rx) = watch::channel(false);
-    stratum.listen_discord_events(worker_thread, shutdown_rx).await;
-    unreachable!("stratum unexpectedly closed");
+    // Start listening to stratum stream (or the direct gateway fallback) until the master drains the worker
+    let shutdown_rx = meso_client.shutdown_signal();
+    tw::geese::gateway::listen_discord_events(stratum, worker_thread, meso_client.clone(), shutdown_rx.clone()).await;
+    if !*shutdown_rx.borrow() {
+        unreachable!("stratum unexpectedly closed");
+    }
+
+    // Flush pending spans once the drain is done, nothing traced runs after it
+    meso_client.wait_drained().await;
+    if let Some(tracer_provider) = tracer_provider && let Err(e) = tracer_provider.shutdown() {
+        log::error!("Failed to shutdown tracer provider: {e}");
+    }
+
+    // Keep serving mesophyll until the master kills the worker
+    std::future::pending::<()>().await;
 }
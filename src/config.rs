@@ -1,5 +1,6 @@
 use dapi::{ChannelId, UserId};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::sync::LazyLock;
 use crate::Error;
@@ -36,18 +37,411 @@ pub struct Config {
 
     // misc
     pub worker_path: PathBuf,
+    /// Length of a template execution time slice in milliseconds, templates running for longer are preempted
+    /// to let others run. Time slicing is disabled if unset
+    #[serde(default)]
+    pub template_time_slice_ms: Option<u64>,
+
+    /// Direct gateway fallback used while Stratum is unreachable, disabled if unset
+    #[serde(default)]
+    pub gateway_fallback: Option<GatewayFallbackConfig>,
+
+    /// Onboarding of guilds on their first GUILD_CREATE, disabled if unset
+    #[serde(default)]
+    pub onboarding: Option<OnboardingConfig>,
+
+    /// Deletion and export of tenant data
+    #[serde(default)]
+    pub data_lifecycle: DataLifecycleConfig,
+
+    /// Plugins (`@antiraid/*` modules) templates may use, all are if unset
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+
+    /// Archival of the data of inactive guilds into cold storage, disabled if unset
+    #[serde(default)]
+    pub archival: Option<ArchivalConfig>,
+
+    /// Bot profile and application emojis synced to Discord on register, not synced if unset
+    #[serde(default)]
+    pub branding: Option<BrandingConfig>,
+
+    /// Premium tiers granted by Discord entitlements, premium is disabled if unset
+    #[serde(default)]
+    pub premium: Option<PremiumConfig>,
+
+    /// Autoscaling of the workers on the hash ring based on their load, the ring is static if unset
+    #[serde(default)]
+    pub autoscale: Option<AutoscaleConfig>,
+
+    /// Vote webhooks of bot lists (top.gg etc.), votes are not accepted if unset
+    #[serde(default)]
+    pub votes: Option<VotesConfig>,
+
+    /// Publishing of dispatched events and their results to Kafka or NATS for analytics, disabled if unset
+    #[serde(default)]
+    pub event_sink: Option<EventSinkConfig>,
+
+    /// Redis shared by the hosts of a multi-host deployment for caches, caches are in-memory if unset
+    #[serde(default)]
+    pub redis: Option<RedisConfig>,
+
+    /// Read replica for read-only queries (template KV reads, analytics), everything goes to the primary if unset
+    #[serde(default)]
+    pub read_replica: Option<ReadReplicaConfig>,
+
+    /// WASM modules templates can run through the `@antiraid/wasm` plugin, the plugin is disabled if unset
+    #[cfg(feature = "wasm")]
+    #[serde(default)]
+    pub wasm: Option<WasmConfig>,
+
+    /// In-process fake of the Discord HTTP API all Discord calls are made to instead, for end-to-end tests
+    #[cfg(feature = "fake-discord")]
+    #[serde(default)]
+    pub fake_discord: Option<FakeDiscordConfig>,
+
+    // observability
+    /// OTLP (http) endpoint to export traces to, tracing is disabled if unset
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
 
     #[serde(skip)]
     /// Setup by load() for statistics
     pub start_time: chrono::DateTime<chrono::Utc>,
 }
 
+#[cfg(feature = "fake-discord")]
+#[derive(Serialize, Deserialize)]
+pub struct FakeDiscordConfig {
+    /// Address the master serves the fake on, the proxy is replaced with it
+    pub bind: String,
+}
+
+#[cfg(feature = "wasm")]
+#[derive(Serialize, Deserialize)]
+pub struct WasmConfig {
+    /// Fuel (roughly, WASM instructions) a single call into a module may use
+    #[serde(default = "WasmConfig::default_fuel_per_call")]
+    pub fuel_per_call: u64,
+    /// Memory a module may grow its linear memory to, in bytes
+    #[serde(default = "WasmConfig::default_max_memory_bytes")]
+    pub max_memory_bytes: usize,
+}
+
+#[cfg(feature = "wasm")]
+impl WasmConfig {
+    fn default_fuel_per_call() -> u64 { 100_000_000 }
+    fn default_max_memory_bytes() -> usize { 16 * 1024 * 1024 }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GatewayFallbackConfig {
+    /// Total number of shards to connect with, must match the shard count Stratum uses
+    pub shard_count: u32,
+    /// Gateway intents to identify with
+    pub intents: u64,
+    /// Number of consecutive failed Stratum health checks before switching to the direct gateway
+    #[serde(default = "GatewayFallbackConfig::default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Number of consecutive successful Stratum health checks before switching back to Stratum
+    #[serde(default = "GatewayFallbackConfig::default_recovery_threshold")]
+    pub recovery_threshold: u32,
+    /// Interval between Stratum health checks in seconds
+    #[serde(default = "GatewayFallbackConfig::default_check_interval_secs")]
+    pub check_interval_secs: u64,
+}
+
+impl GatewayFallbackConfig {
+    fn default_failure_threshold() -> u32 { 3 }
+    fn default_recovery_threshold() -> u32 { 5 }
+    fn default_check_interval_secs() -> u64 { 10 }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AutoscaleConfig {
+    /// Minimum number of workers kept on the ring
+    #[serde(default = "AutoscaleConfig::default_min_workers")]
+    pub min_workers: usize,
+    /// Maximum number of workers on the ring, capped to the number of worker processes
+    #[serde(default)]
+    pub max_workers: Option<usize>,
+    /// Interval between load samples in seconds
+    #[serde(default = "AutoscaleConfig::default_interval_secs")]
+    pub interval_secs: u64,
+    /// A worker is added once the average time events wait for a VM thread exceeds this
+    #[serde(default = "AutoscaleConfig::default_scale_up_wait_ms")]
+    pub scale_up_wait_ms: u64,
+    /// A worker is removed once the highest time events wait for a VM thread is below this...
+    #[serde(default = "AutoscaleConfig::default_scale_down_wait_ms")]
+    pub scale_down_wait_ms: u64,
+    /// ...and the average number of in-flight dispatches per worker is below this
+    #[serde(default = "AutoscaleConfig::default_scale_down_inflight")]
+    pub scale_down_inflight: f64,
+    /// Minimum time between two scaling decisions in seconds, as every decision moves tenants between workers
+    #[serde(default = "AutoscaleConfig::default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+impl AutoscaleConfig {
+    fn default_min_workers() -> usize { 1 }
+    fn default_interval_secs() -> u64 { 30 }
+    fn default_scale_up_wait_ms() -> u64 { 250 }
+    fn default_scale_down_wait_ms() -> u64 { 20 }
+    fn default_scale_down_inflight() -> f64 { 1.0 }
+    fn default_cooldown_secs() -> u64 { 300 }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OnboardingConfig {
+    /// Whether to install the starter templates shipped with the builtins
+    #[serde(default)]
+    pub starter_templates: bool,
+    /// Whether to post a setup message in the guild's system channel
+    #[serde(default = "OnboardingConfig::default_setup_message")]
+    pub setup_message: bool,
+}
+
+impl OnboardingConfig {
+    fn default_setup_message() -> bool { true }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchivalConfig {
+    /// Directory archives are written to, usually a mounted object storage bucket
+    pub dir: PathBuf,
+    /// Days a guild must have had no template executions or key-value writes for to be archived
+    #[serde(default = "ArchivalConfig::default_inactive_days")]
+    pub inactive_days: u64,
+    /// Interval between sweeps for inactive guilds in seconds
+    #[serde(default = "ArchivalConfig::default_interval_secs")]
+    pub interval_secs: u64,
+    /// Maximum number of guilds archived per sweep
+    #[serde(default = "ArchivalConfig::default_batch_size")]
+    pub batch_size: i64,
+}
+
+impl ArchivalConfig {
+    fn default_inactive_days() -> u64 { 180 }
+    fn default_interval_secs() -> u64 { 3600 }
+    fn default_batch_size() -> i64 { 50 }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DataLifecycleConfig {
+    /// Hours to wait before deleting the data of a tenant, during which the deletion can be cancelled
+    #[serde(default = "DataLifecycleConfig::default_deletion_grace_period_hours")]
+    pub deletion_grace_period_hours: u64,
+    /// Hours a finished export can be downloaded for
+    #[serde(default = "DataLifecycleConfig::default_export_expiry_hours")]
+    pub export_expiry_hours: u64,
+}
+
+impl DataLifecycleConfig {
+    fn default_deletion_grace_period_hours() -> u64 { 72 }
+    fn default_export_expiry_hours() -> u64 { 24 }
+}
+
+impl Default for DataLifecycleConfig {
+    fn default() -> Self {
+        Self {
+            deletion_grace_period_hours: Self::default_deletion_grace_period_hours(),
+            export_expiry_hours: Self::default_export_expiry_hours(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PluginsConfig {
+    /// Names of the plugins templates may use (such as `crypto`), all plugins not denied are if unset
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    /// Names of the plugins templates may not use, even if allowed
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl PluginsConfig {
+    /// Returns whether templates may use a plugin
+    pub fn allows(&self, name: &str) -> bool {
+        self.allow.as_ref().is_none_or(|allow| allow.iter().any(|p| p == name))
+            && !self.deny.iter().any(|p| p == name)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BrandingConfig {
+    /// Description of the application, shown on the bot's profile
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Path to the banner image of the bot's profile
+    #[serde(default)]
+    pub banner: Option<PathBuf>,
+    /// Application emojis keyed by name, pointing to their image
+    ///
+    /// Synced emojis removed from here are deleted, emojis added through the Discord UI are left alone
+    #[serde(default)]
+    pub emojis: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PremiumConfig {
+    /// Premium tiers keyed by name
+    pub tiers: BTreeMap<String, PremiumTier>,
+    /// Features (`syscall:{Category}` e.g. `syscall:ImgGen`) only available to tenants with a tier granting them
+    #[serde(default)]
+    pub gated: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PremiumTier {
+    /// IDs of the SKUs whose entitlements grant the tier
+    pub skus: Vec<String>,
+    /// Gated features the tier grants
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Memory limit of the tenants VM in bytes, unless overridden for the tenant
+    #[serde(default)]
+    pub memory_limit: Option<u64>,
+    /// Execution time limit in milliseconds, unless overridden for the tenant
+    #[serde(default)]
+    pub execution_time_limit_ms: Option<u64>,
+    /// Time to wait for a dispatched event to return in milliseconds, unless overridden for the tenant
+    #[serde(default)]
+    pub return_wait_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VotesConfig {
+    /// Bot lists allowed to send votes, keyed by the name used in the webhook URL (`/webhooks/votes/{list}`)
+    pub lists: BTreeMap<String, VoteListConfig>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VoteListConfig {
+    /// Secret shared with the bot list
+    pub secret: String,
+    /// How the bot list authenticates its webhooks with the secret
+    #[serde(default)]
+    pub auth: VoteAuth,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteAuth {
+    /// The `Authorization` header is set to the secret (top.gg, discordbotlist.com and most other lists)
+    #[default]
+    Authorization,
+    /// The `X-Signature-256` header is the hex HMAC-SHA256 of the body keyed by the secret
+    HmacSha256,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EventSinkConfig {
+    /// Where events are published to
+    pub target: EventSinkTarget,
+    /// Names of the events to publish, a trailing `*` matches any suffix (e.g. `GUILD_BAN_*`). All events are
+    /// published if empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Whether to include the results (return value or error) of executions
+    #[serde(default)]
+    pub results: bool,
+    /// Dotted paths (e.g. `data.author.email`) replaced with `"[redacted]"` before publishing, `*` matches any
+    /// key or array element
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// Number of records buffered per worker, records are dropped (and counted) once the buffer is full
+    #[serde(default = "EventSinkConfig::default_buffer_size")]
+    pub buffer_size: usize,
+    /// Maximum number of records published at once
+    #[serde(default = "EventSinkConfig::default_batch_size")]
+    pub batch_size: usize,
+}
+
+impl EventSinkConfig {
+    fn default_buffer_size() -> usize { 10_000 }
+    fn default_batch_size() -> usize { 500 }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventSinkTarget {
+    /// Kafka, through a Confluent-compatible REST proxy
+    Kafka {
+        /// Base URL of the REST proxy
+        rest_proxy: String,
+        topic: String,
+    },
+    /// NATS core (publishes are fire-and-forget)
+    Nats {
+        /// Address of the NATS server (`host:port`)
+        addr: String,
+        /// Subject records are published to, followed by `.{event name}`
+        subject: String,
+        /// Authentication token, if required by the server
+        #[serde(default)]
+        token: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RedisConfig {
+    /// Address of the Redis server (`host:port`), TLS is not supported
+    pub addr: String,
+    /// ACL username, if any
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Database to select
+    #[serde(default)]
+    pub db: u32,
+    /// Prefix of all keys, to share a Redis between deployments
+    #[serde(default = "RedisConfig::default_key_prefix")]
+    pub key_prefix: String,
+    /// Number of connections per process
+    #[serde(default = "RedisConfig::default_pool_size")]
+    pub pool_size: usize,
+}
+
+impl RedisConfig {
+    fn default_key_prefix() -> String { "tw:".to_string() }
+    fn default_pool_size() -> usize { 4 }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReadReplicaConfig {
+    pub postgres_url: String,
+    #[serde(default = "ReadReplicaConfig::default_max_connections")]
+    pub max_connections: u32,
+    /// Reads go to the primary while the replica lags further behind than this
+    #[serde(default = "ReadReplicaConfig::default_max_lag_ms")]
+    pub max_lag_ms: u64,
+    /// Interval between checks of the replication lag
+    #[serde(default = "ReadReplicaConfig::default_lag_check_interval_ms")]
+    pub lag_check_interval_ms: u64,
+    /// Reads of a tenant go to the primary for this long after it wrote, so templates see their own writes
+    #[serde(default = "ReadReplicaConfig::default_read_your_writes_ms")]
+    pub read_your_writes_ms: u64,
+}
+
+impl ReadReplicaConfig {
+    fn default_max_connections() -> u32 { 10 }
+    fn default_max_lag_ms() -> u64 { 1000 }
+    fn default_lag_check_interval_ms() -> u64 { 1000 }
+    fn default_read_your_writes_ms() -> u64 { 5000 }
+}
+
 impl Config {
     pub fn load() -> Result<Self, Error> {
         // Open config.yaml from parent directory
         let file = std::fs::read_to_string("tw.toml")?;
         let mut cfg: Config = toml::from_str(&file)?;
         cfg.start_time = chrono::Utc::now();
+        #[cfg(feature = "fake-discord")]
+        if let Some(fake) = cfg.fake_discord.as_ref() {
+            cfg.proxy = format!("http://{}", fake.bind);
+        }
         Ok(cfg)
 
     }
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, Utc};
+use dapi::{ChannelId, GuildId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::geese::dbpools::PoolKind;
+use crate::geese::dbrouter::DbRouter;
+
+/// Maximum number of days activity is kept for (and so reported over)
+pub const MAX_ACTIVITY_RETENTION_DAYS: u32 = 90;
+
+/// Maximum number of channels in the per channel breakdown of a heatmap
+const MAX_HEATMAP_CHANNELS: usize = 100;
+
+/// Maximum number of pending counters per worker, messages in further channels are dropped until the next flush
+const MAX_PENDING_ACTIVITY: usize = 50_000;
+
+/// How the message activity of a guild is tracked
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivityConfig {
+    /// How many days hourly activity is kept for
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u32,
+    /// Whether messages of bots and webhooks are counted too
+    #[serde(default)]
+    pub include_bots: bool,
+}
+
+fn default_retention_days() -> u32 {
+    30
+}
+
+impl ActivityConfig {
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.retention_days == 0 || self.retention_days > MAX_ACTIVITY_RETENTION_DAYS {
+            return Err(format!("Activity can be kept for between 1 and {MAX_ACTIVITY_RETENTION_DAYS} days").into());
+        }
+        Ok(())
+    }
+}
+
+/// Number of messages sent in a channel of a guild over an hour, sent from workers to the master
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityRecord {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub hour: DateTime<Utc>,
+    pub messages: u64,
+}
+
+/// Worker-side accumulator of message activity, flushed to the master periodically
+#[derive(Default)]
+pub struct ActivityTracker {
+    pending: Mutex<HashMap<(GuildId, ChannelId, DateTime<Utc>), u64>>,
+}
+
+impl ActivityTracker {
+    /// How often pending activity is flushed to the master
+    pub const FLUSH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+    /// Records a message sent in a channel of a guild
+    pub fn record(&self, guild_id: GuildId, channel_id: ChannelId) {
+        let hour = Utc::now().duration_trunc(chrono::Duration::hours(1)).unwrap_or_else(|_| Utc::now());
+        let mut pending = self.pending.lock();
+        let key = (guild_id, channel_id, hour);
+        if pending.len() >= MAX_PENDING_ACTIVITY && !pending.contains_key(&key) {
+            return;
+        }
+        *pending.entry(key).or_default() += 1;
+    }
+
+    /// Takes all pending activity records
+    pub fn take(&self) -> Vec<ActivityRecord> {
+        let pending = std::mem::take(&mut *self.pending.lock());
+        pending.into_iter()
+            .map(|((guild_id, channel_id, hour), messages)| ActivityRecord { guild_id, channel_id, hour, messages })
+            .collect()
+    }
+}
+
+/// Messages sent in an hour of the week over the report period
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ActivityCell {
+    /// Day of the week in UTC, 0 (Monday) to 6 (Sunday)
+    pub weekday: u8,
+    /// Hour of the day in UTC, 0 to 23
+    pub hour: u8,
+    pub messages: i64,
+}
+
+/// Messages sent on a day of the report period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityDay {
+    pub day: DateTime<Utc>,
+    pub messages: i64,
+}
+
+/// Messages sent in a channel over the report period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityChannel {
+    pub channel_id: String,
+    pub messages: i64,
+}
+
+/// Message activity of a guild (or one of its channels) over the report period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityHeatmap {
+    /// One cell per hour of the week (168), Monday 00:00 UTC first
+    pub cells: Vec<ActivityCell>,
+    /// Days with activity, oldest first
+    pub daily: Vec<ActivityDay>,
+    /// The most active channels, most active first
+    pub channels: Vec<ActivityChannel>,
+    /// The hour of the week with the fewest messages, the earliest if tied
+    pub quietest: ActivityCell,
+    pub total: i64,
+}
+
+impl IntoLua for ActivityHeatmap {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Returns the message activity of a guild (or one of its channels) over the last `days` days
+///
+/// The hours of the week, days and channels are aggregated in one pass (as grouping sets), so the query can run
+/// on a single executor
+pub async fn heatmap<'c, E>(executor: E, guild_id: GuildId, days: u32, channel_id: Option<ChannelId>) -> Result<ActivityHeatmap, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    let since = Utc::now() - chrono::Duration::days(days.clamp(1, MAX_ACTIVITY_RETENTION_DAYS).into());
+    let rows: Vec<(Option<String>, Option<DateTime<Utc>>, Option<i32>, Option<i32>, i64)> = sqlx::query_as(
+        "SELECT channel_id, day, weekday, hour_of_day, SUM(messages)::BIGINT FROM (
+            SELECT channel_id, date_trunc('day', hour, 'UTC') AS day, (EXTRACT(ISODOW FROM hour AT TIME ZONE 'UTC') - 1)::INT AS weekday,
+                EXTRACT(HOUR FROM hour AT TIME ZONE 'UTC')::INT AS hour_of_day, messages
+            FROM channel_activity_hourly WHERE guild_id = $1 AND hour >= $2 AND ($3::TEXT IS NULL OR channel_id = $3)
+        ) a
+        GROUP BY GROUPING SETS ((weekday, hour_of_day), (day), (channel_id))"
+    )
+    .bind(guild_id.to_string())
+    .bind(since)
+    .bind(channel_id.map(|c| c.to_string()))
+    .fetch_all(executor)
+    .await?;
+
+    let mut cells = (0..7u8)
+        .flat_map(|weekday| (0..24u8).map(move |hour| ActivityCell { weekday, hour, messages: 0 }))
+        .collect::<Vec<_>>();
+    let mut daily = Vec::new();
+    let mut channels = Vec::new();
+    for row in rows {
+        match row {
+            (_, _, Some(weekday), Some(hour), messages) => {
+                if let Some(cell) = cells.get_mut(weekday as usize * 24 + hour as usize) {
+                    cell.messages = messages;
+                }
+            }
+            (_, Some(day), _, _, messages) => daily.push(ActivityDay { day, messages }),
+            (Some(channel_id), _, _, _, messages) => channels.push(ActivityChannel { channel_id, messages }),
+            _ => {}
+        }
+    }
+    daily.sort_by_key(|d| d.day);
+    channels.sort_by_key(|c| std::cmp::Reverse(c.messages));
+    channels.truncate(MAX_HEATMAP_CHANNELS);
+
+    let quietest = cells.iter().copied().min_by_key(|c| c.messages).unwrap_or(ActivityCell { weekday: 0, hour: 0, messages: 0 });
+    let total = cells.iter().map(|c| c.messages).sum();
+    Ok(ActivityHeatmap { cells, daily, channels, quietest, total })
+}
+
+/// Storage of the hourly message activity rollups of guilds
+///
+/// Messages are counted by the workers (see `worker::activity`) and recorded by the master, which also prunes
+/// activity past the retention of each guild (see `master::activity`). Templates query them through the
+/// `ActivityHeatmap` state op, the API through the `Activity` syscall
+#[derive(Clone)]
+pub struct ActivityDb {
+    pool: sqlx::PgPool,
+    db: DbRouter,
+}
+
+impl ActivityDb {
+    pub fn new(db: DbRouter) -> Self {
+        Self { pool: db.pool(PoolKind::Analytics).pool().clone(), db }
+    }
+
+    /// Adds the given activity records to the hourly rollups
+    pub async fn record(&self, records: Vec<ActivityRecord>) -> Result<(), crate::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for record in records {
+            sqlx::query(
+                "INSERT INTO channel_activity_hourly (guild_id, channel_id, hour, messages)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (guild_id, channel_id, hour) DO UPDATE SET
+                    messages = channel_activity_hourly.messages + EXCLUDED.messages"
+            )
+            .bind(record.guild_id.to_string())
+            .bind(record.channel_id.to_string())
+            .bind(record.hour)
+            .bind(record.messages as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn heatmap(&self, guild_id: GuildId, days: u32, channel_id: Option<ChannelId>) -> Result<ActivityHeatmap, crate::Error> {
+        if let Some(replica) = self.db.reads() {
+            match heatmap(replica, guild_id, days, channel_id).await {
+                Ok(heatmap) => return Ok(heatmap),
+                Err(e) => self.db.replica_failed(&e),
+            }
+        }
+        heatmap(&self.pool, guild_id, days, channel_id).await
+    }
+
+    /// Deletes the activity of guilds older than their retention, or all of it if they no longer track activity
+    pub async fn prune(&self) -> Result<u64, crate::Error> {
+        let res = sqlx::query(
+            "DELETE FROM channel_activity_hourly a WHERE a.hour < NOW() - make_interval(days => LEAST(COALESCE((
+                SELECT (ts.settings->'activity'->>'retention_days')::INT FROM tenant_state ts
+                WHERE ts.owner_id = a.guild_id AND ts.owner_type = 'guild' AND ts.settings ? 'activity'
+            ), 0), $1))"
+        )
+        .bind(MAX_ACTIVITY_RETENTION_DAYS as i32)
+        .execute(&self.pool)
+        .await?;
+        Ok(res.rows_affected())
+    }
+}
@@ -0,0 +1,268 @@
+use chrono::{DateTime, Utc};
+use dapi::{ChannelId, GuildId, UserId};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Maximum length of the reason of an appeal
+pub const MAX_APPEAL_REASON_LENGTH: usize = 2000;
+
+/// Maximum number of evidence items (attachment URLs or links) of an appeal
+pub const MAX_APPEAL_EVIDENCE: usize = 10;
+
+/// Maximum length of an evidence item
+pub const MAX_APPEAL_EVIDENCE_LENGTH: usize = 512;
+
+/// The state of an appeal, appeals move from `Open` (optionally through `UnderReview`) to `Accepted` or `Denied`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AppealState {
+    Open,
+    /// Claimed by a moderator
+    UnderReview,
+    /// The punishment has been lifted
+    Accepted,
+    Denied,
+}
+
+impl AppealState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::UnderReview => "under_review",
+            Self::Accepted => "accepted",
+            Self::Denied => "denied",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, crate::Error> {
+        match s {
+            "open" => Ok(Self::Open),
+            "under_review" => Ok(Self::UnderReview),
+            "accepted" => Ok(Self::Accepted),
+            "denied" => Ok(Self::Denied),
+            _ => Err(format!("Unknown appeal state: {s}").into()),
+        }
+    }
+
+    pub fn is_resolved(self) -> bool {
+        matches!(self, Self::Accepted | Self::Denied)
+    }
+
+    /// The states an appeal may move to this state from
+    fn from_states(self) -> &'static [&'static str] {
+        match self {
+            Self::Open => &[],
+            Self::UnderReview => &["open"],
+            Self::Accepted | Self::Denied => &["open", "under_review"],
+        }
+    }
+}
+
+/// The punishment an appeal is against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Punishment {
+    Ban,
+    Timeout,
+}
+
+impl Punishment {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ban => "ban",
+            Self::Timeout => "timeout",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, crate::Error> {
+        match s {
+            "ban" => Ok(Self::Ban),
+            "timeout" => Ok(Self::Timeout),
+            _ => Err(format!("Unknown punishment: {s}").into()),
+        }
+    }
+}
+
+/// An appeal of a user against their punishment in a guild
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Appeal {
+    pub id: Uuid,
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    pub punishment: Punishment,
+    pub reason: String,
+    /// Attachment URLs or links supporting the appeal
+    pub evidence: Vec<String>,
+    /// Where the appeal was submitted from, `dm` or `web`
+    pub source: String,
+    pub state: AppealState,
+    /// The channel and message the appeal is reviewed in, set once posted
+    pub channel_id: Option<ChannelId>,
+    pub message_id: Option<String>,
+    /// The moderator who last acted on the appeal
+    pub moderator_id: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AppealRow {
+    id: Uuid,
+    guild_id: String,
+    user_id: String,
+    punishment: String,
+    reason: String,
+    evidence: Vec<String>,
+    source: String,
+    state: String,
+    channel_id: Option<String>,
+    message_id: Option<String>,
+    moderator_id: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    resolved_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<AppealRow> for Appeal {
+    type Error = crate::Error;
+
+    fn try_from(row: AppealRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            guild_id: row.guild_id.parse()?,
+            user_id: row.user_id.parse()?,
+            punishment: Punishment::parse(&row.punishment)?,
+            reason: row.reason,
+            evidence: row.evidence,
+            source: row.source,
+            state: AppealState::parse(&row.state)?,
+            channel_id: row.channel_id.map(|c| c.parse()).transpose()?,
+            message_id: row.message_id,
+            moderator_id: row.moderator_id.map(|u| u.parse()).transpose()?,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            resolved_at: row.resolved_at,
+        })
+    }
+}
+
+/// An appeal request from a worker to the master
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AppealReq {
+    /// Records a new appeal, unless the user already has an unresolved appeal in the guild
+    Submit { guild_id: GuildId, user_id: UserId, punishment: Punishment, reason: String, evidence: Vec<String>, source: String },
+    /// Records where an appeal is reviewed
+    SetMessage { appeal_id: Uuid, channel_id: ChannelId, message_id: String },
+    /// Moves an appeal of a guild to a new state on behalf of a moderator
+    Transition { guild_id: GuildId, appeal_id: Uuid, to: AppealState, moderator_id: UserId },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AppealResp {
+    Submitted { appeal: Appeal },
+    /// The user already has an unresolved appeal in the guild
+    AlreadyOpen,
+    Updated { appeal: Appeal },
+    Ack,
+}
+
+const APPEAL_COLUMNS: &str = "id, guild_id, user_id, punishment, reason, evidence, source, state, channel_id, message_id, moderator_id, created_at, updated_at, resolved_at";
+
+/// Validates the reason and evidence of an appeal
+pub fn validate(reason: &str, evidence: &[String]) -> Result<(), crate::Error> {
+    if reason.trim().is_empty() || reason.chars().count() > MAX_APPEAL_REASON_LENGTH {
+        return Err(format!("Appeal reasons must be between 1 and {MAX_APPEAL_REASON_LENGTH} characters").into());
+    }
+    if evidence.len() > MAX_APPEAL_EVIDENCE {
+        return Err(format!("Appeals can have at most {MAX_APPEAL_EVIDENCE} pieces of evidence").into());
+    }
+    if evidence.iter().any(|e| e.is_empty() || e.len() > MAX_APPEAL_EVIDENCE_LENGTH) {
+        return Err(format!("Evidence must be between 1 and {MAX_APPEAL_EVIDENCE_LENGTH} characters").into());
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+/// Storage of appeals
+///
+/// Appeals are submitted on the worker of the guild (which checks the user is punished) and acted on by
+/// moderators in the guild's appeals channel, the API can submit and list them
+pub struct AppealDb {
+    pool: sqlx::PgPool,
+}
+
+impl AppealDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Handles an appeal request from a worker
+    pub async fn handle(&self, req: AppealReq) -> Result<AppealResp, crate::Error> {
+        match req {
+            AppealReq::Submit { guild_id, user_id, punishment, reason, evidence, source } => {
+                validate(&reason, &evidence)?;
+                let row: Option<AppealRow> = sqlx::query_as(&format!(
+                    "INSERT INTO appeals (id, guild_id, user_id, punishment, reason, evidence, source) VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    ON CONFLICT (guild_id, user_id) WHERE state IN ('open', 'under_review') DO NOTHING
+                    RETURNING {APPEAL_COLUMNS}"
+                ))
+                .bind(Uuid::now_v7())
+                .bind(guild_id.to_string())
+                .bind(user_id.to_string())
+                .bind(punishment.as_str())
+                .bind(reason.trim())
+                .bind(evidence)
+                .bind(source)
+                .fetch_optional(&self.pool)
+                .await?;
+                match row {
+                    Some(row) => Ok(AppealResp::Submitted { appeal: row.try_into()? }),
+                    None => Ok(AppealResp::AlreadyOpen),
+                }
+            }
+            AppealReq::SetMessage { appeal_id, channel_id, message_id } => {
+                sqlx::query("UPDATE appeals SET channel_id = $2, message_id = $3 WHERE id = $1")
+                    .bind(appeal_id)
+                    .bind(channel_id.to_string())
+                    .bind(message_id)
+                    .execute(&self.pool)
+                    .await?;
+                Ok(AppealResp::Ack)
+            }
+            AppealReq::Transition { guild_id, appeal_id, to, moderator_id } => {
+                let row: Option<AppealRow> = sqlx::query_as(&format!(
+                    "UPDATE appeals SET state = $3, moderator_id = $4, updated_at = NOW(), resolved_at = CASE WHEN $5 THEN NOW() END
+                    WHERE id = $1 AND guild_id = $2 AND state = ANY($6)
+                    RETURNING {APPEAL_COLUMNS}"
+                ))
+                .bind(appeal_id)
+                .bind(guild_id.to_string())
+                .bind(to.as_str())
+                .bind(moderator_id.to_string())
+                .bind(to.is_resolved())
+                .bind(to.from_states())
+                .fetch_optional(&self.pool)
+                .await?;
+                let Some(row) = row else {
+                    return Err("This appeal has already been acted on".into());
+                };
+                Ok(AppealResp::Updated { appeal: row.try_into()? })
+            }
+        }
+    }
+
+    /// Lists the most recent appeals of a guild, only unresolved ones if `unresolved_only` is set
+    pub async fn list(&self, guild_id: GuildId, unresolved_only: bool, limit: i64) -> Result<Vec<Appeal>, crate::Error> {
+        let rows: Vec<AppealRow> = sqlx::query_as(&format!(
+            "SELECT {APPEAL_COLUMNS} FROM appeals WHERE guild_id = $1 AND (NOT $2 OR state IN ('open', 'under_review'))
+            ORDER BY created_at DESC LIMIT $3"
+        ))
+        .bind(guild_id.to_string())
+        .bind(unresolved_only)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(Appeal::try_from).collect()
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::geese::datalifecycle::build_archive;
+use crate::geese::tenantstate::{TenantState, TenantStateDb};
+use crate::worker::workervmmanager::Id;
+
+/// Key-value scopes starting with this are internal to the bot (onboarding progress, exports, replays etc.), are
+/// small and are never archived
+const INTERNAL_SCOPE_PREFIX: &str = "#";
+
+/// The archive of the data of a tenant
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TenantArchive {
+    /// Path of the archive in cold storage
+    pub path: String,
+    pub size_bytes: i64,
+    /// Number of key-value records (templates, stings and template data) in the archive
+    pub kv_rows: i64,
+    pub archived_at: DateTime<Utc>,
+    /// When the archive was last restored, unset while the tenant is archived
+    pub restored_at: Option<DateTime<Utc>>,
+}
+
+/// A key-value record in an archive, blobs are stored alongside as `blobs/{id}`
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+struct ArchivedKv {
+    id: String,
+    key: String,
+    scope: String,
+    value: serde_json::Value,
+    created_at: DateTime<Utc>,
+    last_updated_at: DateTime<Utc>,
+    #[serde(skip)]
+    blob: Option<Vec<u8>>,
+}
+
+/// Result of archiving or restoring a tenant
+pub struct ArchiveOutcome {
+    /// The archive made or restored, unset if there was nothing to archive or restore
+    pub archive: Option<TenantArchive>,
+    /// The new tenant state of the tenant, to be pushed to its worker
+    pub tenant_state: Option<TenantState>,
+}
+
+/// Path of the archive of a tenant in the archive directory
+fn archive_path(dir: &Path, id: Id) -> PathBuf {
+    dir.join(id.tenant_type()).join(format!("{}.tar.gz", id.tenant_id()))
+}
+
+/// Reads the key-value records back out of an archive
+fn read_archive(data: &[u8]) -> Result<Vec<ArchivedKv>, crate::Error> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(data));
+    let mut rows: Option<Vec<ArchivedKv>> = None;
+    let mut blobs = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf)?;
+        if path == "kv.json" {
+            rows = Some(serde_json::from_slice(&buf)?);
+        } else if let Some(kv_id) = path.strip_prefix("blobs/") {
+            blobs.insert(kv_id.to_string(), buf);
+        }
+    }
+
+    let mut rows = rows.ok_or("Archive has no kv.json")?;
+    for row in rows.iter_mut() {
+        row.blob = blobs.remove(&row.id);
+    }
+    Ok(rows)
+}
+
+/// Moves the templates, stings and key-value data of inactive guilds into compressed archives in cold storage
+/// (a directory, usually a mounted object storage bucket) and back
+///
+/// Archiving and restoring are each a single transaction: records written while a tenant is being archived are
+/// left in place, and records written after archival take precedence over archived ones on restore. Archived
+/// tenants are marked in their tenant state, so workers know to rehydrate them on their next GUILD_CREATE
+#[derive(Clone)]
+pub struct ArchiveDb {
+    pool: sqlx::PgPool,
+    tenant_state_db: TenantStateDb,
+}
+
+impl ArchiveDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { tenant_state_db: TenantStateDb::new(pool.clone()), pool }
+    }
+
+    /// Returns the archive of a tenant, if it was ever archived
+    pub async fn get(&self, id: Id) -> Result<Option<TenantArchive>, crate::Error> {
+        let archive = sqlx::query_as("SELECT path, size_bytes, kv_rows, archived_at, restored_at FROM tenant_archives WHERE owner_id = $1 AND owner_type = $2")
+            .bind(id.tenant_id())
+            .bind(id.tenant_type())
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(archive)
+    }
+
+    /// Returns guilds without template executions or key-value writes since `cutoff` which have data to archive,
+    /// skipping those restored since `cutoff` or with a deletion or export outstanding
+    pub async fn inactive_tenants(&self, cutoff: DateTime<Utc>, limit: i64) -> Result<Vec<Id>, crate::Error> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT ts.owner_id, ts.owner_type FROM tenant_state ts
+            WHERE ts.owner_type = 'guild' AND ts.archived_at IS NULL
+            AND EXISTS (SELECT 1 FROM tenant_kv kv WHERE kv.owner_id = ts.owner_id AND kv.owner_type = ts.owner_type AND kv.scope NOT LIKE $3 || '%')
+            AND NOT EXISTS (SELECT 1 FROM tenant_kv kv WHERE kv.owner_id = ts.owner_id AND kv.owner_type = ts.owner_type AND kv.last_updated_at >= $1)
+            AND NOT EXISTS (SELECT 1 FROM template_usage_daily u WHERE u.owner_id = ts.owner_id AND u.owner_type = ts.owner_type AND u.day >= $1::date)
+            AND NOT EXISTS (SELECT 1 FROM tenant_archives a WHERE a.owner_id = ts.owner_id AND a.owner_type = ts.owner_type AND a.restored_at >= $1)
+            AND NOT EXISTS (SELECT 1 FROM tenant_data_jobs j WHERE j.owner_id = ts.owner_id AND j.owner_type = ts.owner_type AND j.state IN ('pending', 'running'))
+            LIMIT $2"
+        )
+        .bind(cutoff)
+        .bind(limit)
+        .bind(INTERNAL_SCOPE_PREFIX)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().filter_map(|(owner_id, owner_type)| Id::from_parts(&owner_type, &owner_id)).collect())
+    }
+
+    /// Archives the key-value data of a tenant into `dir`, removing it from the database
+    ///
+    /// Nothing is archived if the tenant has no key-value data outside of internal scopes
+    pub async fn archive(&self, id: Id, dir: &Path) -> Result<ArchiveOutcome, crate::Error> {
+        let mut tx = self.pool.begin().await?;
+        let rows: Vec<ArchivedKv> = sqlx::query_as(
+            "SELECT id, key, scope, value, created_at, last_updated_at, blob FROM tenant_kv
+            WHERE owner_id = $1 AND owner_type = $2 AND scope NOT LIKE $3 || '%' FOR UPDATE"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .bind(INTERNAL_SCOPE_PREFIX)
+        .fetch_all(&mut *tx)
+        .await?;
+        if rows.is_empty() {
+            return Ok(ArchiveOutcome { archive: None, tenant_state: None });
+        }
+
+        let manifest = serde_json::json!({
+            "tenant_id": id.tenant_id(),
+            "tenant_type": id.tenant_type(),
+            "archived_at": Utc::now(),
+        });
+        let mut files = vec![
+            ("manifest.json".to_string(), serde_json::to_vec_pretty(&manifest)?),
+            ("kv.json".to_string(), serde_json::to_vec(&rows)?),
+        ];
+        let ids = rows.iter().map(|r| r.id.clone()).collect::<Vec<_>>();
+        files.extend(rows.into_iter().filter_map(|r| r.blob.map(|blob| (format!("blobs/{}", r.id), blob))));
+        let data = build_archive(files)?;
+
+        // Written before the records are deleted, a failed archival leaves at most an orphaned file which is
+        // overwritten by the next attempt
+        let path = archive_path(dir, id);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, &data).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        sqlx::query("DELETE FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND id = ANY($3)")
+            .bind(id.tenant_id())
+            .bind(id.tenant_type())
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await?;
+
+        let archive: TenantArchive = sqlx::query_as(
+            "INSERT INTO tenant_archives (owner_id, owner_type, path, size_bytes, kv_rows) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (owner_id, owner_type) DO UPDATE SET path = EXCLUDED.path, size_bytes = EXCLUDED.size_bytes, kv_rows = EXCLUDED.kv_rows, archived_at = NOW(), restored_at = NULL
+            RETURNING path, size_bytes, kv_rows, archived_at, restored_at"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .bind(path.to_string_lossy().to_string())
+        .bind(data.len() as i64)
+        .bind(ids.len() as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE tenant_state SET archived_at = $3 WHERE owner_id = $1 AND owner_type = $2")
+            .bind(id.tenant_id())
+            .bind(id.tenant_type())
+            .bind(archive.archived_at)
+            .execute(&mut *tx)
+            .await?;
+
+        let tenant_state = self.tenant_state_db.get_tenant_state_for(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(ArchiveOutcome { archive: Some(archive), tenant_state })
+    }
+
+    /// Restores the archived data of a tenant into the database, removing the archive from cold storage
+    ///
+    /// Nothing is restored if the tenant is not archived, in which case a stale archived mark is cleared
+    pub async fn restore(&self, id: Id) -> Result<ArchiveOutcome, crate::Error> {
+        let mut tx = self.pool.begin().await?;
+        let archive: Option<TenantArchive> = sqlx::query_as(
+            "SELECT path, size_bytes, kv_rows, archived_at, restored_at FROM tenant_archives WHERE owner_id = $1 AND owner_type = $2 AND restored_at IS NULL FOR UPDATE"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let archive = match archive {
+            Some(archive) => {
+                let data = tokio::fs::read(&archive.path).await
+                    .map_err(|e| format!("Failed to read archive {}: {e}", archive.path))?;
+                for row in read_archive(&data)? {
+                    // Records written since the tenant was archived take precedence
+                    sqlx::query(
+                        "INSERT INTO tenant_kv (id, owner_id, owner_type, key, scope, value, blob, created_at, last_updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                        ON CONFLICT (owner_id, owner_type, key, scope) DO NOTHING"
+                    )
+                    .bind(row.id)
+                    .bind(id.tenant_id())
+                    .bind(id.tenant_type())
+                    .bind(row.key)
+                    .bind(row.scope)
+                    .bind(row.value)
+                    .bind(row.blob)
+                    .bind(row.created_at)
+                    .bind(row.last_updated_at)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+
+                let archive: TenantArchive = sqlx::query_as(
+                    "UPDATE tenant_archives SET restored_at = NOW() WHERE owner_id = $1 AND owner_type = $2
+                    RETURNING path, size_bytes, kv_rows, archived_at, restored_at"
+                )
+                .bind(id.tenant_id())
+                .bind(id.tenant_type())
+                .fetch_one(&mut *tx)
+                .await?;
+                Some(archive)
+            }
+            None => None,
+        };
+
+        let cleared = sqlx::query("UPDATE tenant_state SET archived_at = NULL WHERE owner_id = $1 AND owner_type = $2 AND archived_at IS NOT NULL")
+            .bind(id.tenant_id())
+            .bind(id.tenant_type())
+            .execute(&mut *tx)
+            .await?;
+
+        let tenant_state = self.tenant_state_db.get_tenant_state_for(&mut tx, id).await?;
+        tx.commit().await?;
+
+        match archive {
+            Some(ref archive) => {
+                if let Err(e) = tokio::fs::remove_file(&archive.path).await {
+                    log::warn!("Failed to remove restored archive {}: {e}", archive.path);
+                }
+            }
+            None if cleared.rows_affected() > 0 => log::warn!("Cleared archived mark of {id:?} which had no archive to restore"),
+            None => {}
+        }
+        Ok(ArchiveOutcome { archive, tenant_state })
+    }
+
+    /// Removes the archive of a tenant from cold storage, for deletions of its data
+    pub async fn delete_archive(&self, id: Id) -> Result<(), crate::Error> {
+        let Some(archive) = self.get(id).await? else {
+            return Ok(());
+        };
+        match tokio::fs::remove_file(&archive.path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to remove archive {}: {e}", archive.path).into()),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+/// Gateway events the executor of which can be correlated from the audit log, with the audit log action types
+/// recorded for them
+///
+/// Members leaving on their own, channels deleted with their category etc. have no audit log entry and so are never
+/// correlated
+pub const CORRELATED_EVENTS: [(&str, &[u64]); 10] = [
+    // MEMBER_KICK, MEMBER_BAN_ADD
+    ("GUILD_MEMBER_REMOVE", &[20, 22]),
+    // MEMBER_UPDATE, MEMBER_ROLE_UPDATE
+    ("GUILD_MEMBER_UPDATE", &[24, 25]),
+    ("GUILD_BAN_ADD", &[22]),
+    ("GUILD_BAN_REMOVE", &[23]),
+    ("CHANNEL_CREATE", &[10]),
+    ("CHANNEL_UPDATE", &[11]),
+    ("CHANNEL_DELETE", &[12]),
+    ("GUILD_ROLE_CREATE", &[30]),
+    ("GUILD_ROLE_UPDATE", &[31]),
+    ("GUILD_ROLE_DELETE", &[32]),
+];
+
+/// Returns the audit log action types of an event which can be correlated
+pub fn action_types(event: &str) -> Option<&'static [u64]> {
+    CORRELATED_EVENTS.iter().find(|(name, _)| *name == event).map(|(_, types)| *types)
+}
+
+/// Which gateway events of a guild have their executor and reason looked up in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditCorrelationConfig {
+    /// Events to correlate, see `CORRELATED_EVENTS`
+    pub events: Vec<String>,
+}
+
+impl AuditCorrelationConfig {
+    /// Returns whether an event is correlated
+    pub fn correlates(&self, event: &str) -> bool {
+        self.events.iter().any(|e| e == event)
+    }
+
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.events.is_empty() {
+            return Err("At least one event must be correlated".into());
+        }
+        if let Some(event) = self.events.iter().find(|e| action_types(e).is_none()) {
+            let supported = CORRELATED_EVENTS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+            return Err(format!("Event {event} can't be correlated with the audit log, supported events are {supported}").into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::HashSet;
+
+use dapi::ChannelId;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of announcement channels published from
+pub const MAX_AUTO_PUBLISH_CHANNELS: usize = 25;
+
+/// Which messages of announcement channels are published (crossposted) to the channels following them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AutoPublishConfig {
+    /// The announcement channels whose messages are published
+    pub channels: Vec<ChannelId>,
+    /// Whether messages sent by bots and webhooks are published
+    #[serde(default = "default_true")]
+    pub include_bots: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl AutoPublishConfig {
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.channels.is_empty() {
+            return Err("At least one announcement channel must be published from".into());
+        }
+        if self.channels.len() > MAX_AUTO_PUBLISH_CHANNELS {
+            return Err(format!("At most {MAX_AUTO_PUBLISH_CHANNELS} announcement channels can be published from").into());
+        }
+        if self.channels.iter().collect::<HashSet<_>>().len() != self.channels.len() {
+            return Err("Announcement channels must be unique".into());
+        }
+        Ok(())
+    }
+}
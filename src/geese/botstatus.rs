@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+/// Number of recent dispatches a worker keeps the latency of
+pub const RECENT_DISPATCHES: usize = 256;
+
+/// Shard latency (in milliseconds) past which the bot is considered degraded
+pub const DEGRADED_SHARD_LATENCY_MS: f64 = 2000.0;
+
+/// 95th percentile dispatch latency (in milliseconds) past which the bot is considered degraded
+pub const DEGRADED_DISPATCH_P95_MS: u64 = 5000;
+
+/// Latency of recent dispatches, from an event being queued on a worker to its templates finishing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DispatchLatency {
+    /// Number of dispatches the latency is over
+    pub samples: usize,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+impl DispatchLatency {
+    /// Computes the latency from the durations (in milliseconds) of dispatches
+    pub fn from_samples(mut samples: Vec<u64>) -> Self {
+        samples.sort_unstable();
+        let percentile = |p: f64| {
+            let idx = ((samples.len().saturating_sub(1)) as f64 * p).round() as usize;
+            samples.get(idx).copied().unwrap_or_default()
+        };
+
+        Self {
+            samples: samples.len(),
+            p50_ms: percentile(0.5),
+            p95_ms: percentile(0.95),
+            max_ms: samples.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// Returns why the bot is degraded (empty if it isn't), for the status page and templates to warn users with
+///
+/// `shard_latencies` are the latencies of the shards in milliseconds, `gateway_direct` whether the direct gateway
+/// fallback is active (only known to the master)
+pub fn degraded_reasons(shard_latencies: impl IntoIterator<Item = f64>, dispatch: &DispatchLatency, gateway_direct: bool) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if gateway_direct {
+        reasons.push("Stratum is unreachable, events are received directly from the Discord gateway".to_string());
+    }
+
+    let slow_shards = shard_latencies.into_iter().filter(|l| *l > DEGRADED_SHARD_LATENCY_MS).count();
+    if slow_shards > 0 {
+        reasons.push(format!("{slow_shards} shard(s) have a latency over {DEGRADED_SHARD_LATENCY_MS}ms"));
+    }
+
+    if dispatch.p95_ms > DEGRADED_DISPATCH_P95_MS {
+        reasons.push(format!("Events are taking up to {}ms to be handled", dispatch.p95_ms));
+    }
+
+    reasons
+}
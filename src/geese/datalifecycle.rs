@@ -0,0 +1,536 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::CONFIG;
+use crate::geese::state::SECRETS_SCOPE;
+use crate::worker::replay::{MAX_RECORDING_AGE, REPLAY_SCOPE};
+use crate::worker::workervmmanager::Id;
+
+/// Key-value scope finished exports are stored under, downloadable through presigned URLs
+pub const EXPORT_SCOPE: &str = "#export";
+
+/// Reason of deletions scheduled because the bot was removed from a guild
+pub const REASON_BOT_REMOVED: &str = "bot_removed";
+
+/// Reason of deletions and exports requested through the API
+pub const REASON_REQUESTED: &str = "requested";
+
+/// Steps of a deletion in the order they are run, along with the statement deleting the data of the step
+///
+/// Blobs are stored alongside their key-value record and are deleted with it, as are modmail messages with
+/// their session
+pub const DELETION_STEPS: [(&str, &str); 22] = [
+    ("templates", "DELETE FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND scope = 'builtins.templates'"),
+    ("stings", "DELETE FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND scope = 'builtins.stings'"),
+    ("archives", "DELETE FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND scope LIKE 'builtins.backups%'"),
+    ("kv", "DELETE FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2"),
+    ("global_kv", "DELETE FROM global_kv WHERE owner_id = $1 AND owner_type = $2"),
+    ("usage", "DELETE FROM template_usage_daily WHERE owner_id = $1 AND owner_type = $2"),
+    ("modmail", "DELETE FROM modmail_sessions WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("appeals", "DELETE FROM appeals WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("watchlist", "DELETE FROM watchlist WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("invite_joins", "DELETE FROM invite_joins WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("sticky_roles", "DELETE FROM sticky_roles WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("permission_snapshots", "DELETE FROM permission_snapshots WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("discord_outbox", "DELETE FROM discord_outbox WHERE owner_id = $1 AND owner_type = $2"),
+    ("workflows", "DELETE FROM workflow_instances WHERE owner_id = $1 AND owner_type = $2"),
+    ("scheduled_messages", "DELETE FROM scheduled_messages WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("emoji_usage", "DELETE FROM emoji_usage_daily WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("activity", "DELETE FROM channel_activity_hourly WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("votes", "DELETE FROM bot_votes WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("inbound_webhooks", "DELETE FROM inbound_webhooks WHERE guild_id = $1 AND $2 = 'guild'"),
+    ("tenant_state_events", "DELETE FROM tenant_state_events WHERE owner_id = $1 AND owner_type = $2"),
+    ("tenant_archives", "DELETE FROM tenant_archives WHERE owner_id = $1 AND owner_type = $2"),
+    ("tenant_state", "DELETE FROM tenant_state WHERE owner_id = $1 AND owner_type = $2"),
+];
+
+/// Steps of an export in the order they are run
+pub const EXPORT_STEPS: [&str; 15] = ["tenant_state", "kv", "blobs", "global_kv", "usage", "modmail", "appeals", "watchlist", "invite_joins", "sticky_roles", "permission_snapshots", "scheduled_messages", "emoji_usage", "activity", "archive"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataJobKind {
+    /// Deletes all stored data of a tenant
+    Deletion,
+    /// Bundles all stored data of a tenant into a downloadable archive
+    Export,
+}
+
+impl DataJobKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Deletion => "deletion",
+            Self::Export => "export",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, crate::Error> {
+        match s {
+            "deletion" => Ok(Self::Deletion),
+            "export" => Ok(Self::Export),
+            _ => Err(format!("Unknown data job kind: {s}").into()),
+        }
+    }
+
+    /// Returns the number of steps of a job of this kind
+    pub fn steps_total(self) -> usize {
+        match self {
+            Self::Deletion => DELETION_STEPS.len(),
+            Self::Export => EXPORT_STEPS.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataJobState {
+    /// Waiting to be run (for deletions, until the grace period is over)
+    Pending,
+    /// Being run, jobs left running by a restart are resumed
+    Running,
+    Completed,
+    Cancelled,
+}
+
+impl DataJobState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Completed => "completed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, crate::Error> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "running" => Ok(Self::Running),
+            "completed" => Ok(Self::Completed),
+            "cancelled" => Ok(Self::Cancelled),
+            _ => Err(format!("Unknown data job state: {s}").into()),
+        }
+    }
+}
+
+/// A deletion or export of the data of a tenant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataJob {
+    pub job_id: Uuid,
+    pub id: Id,
+    pub kind: DataJobKind,
+    pub state: DataJobState,
+    pub reason: String,
+    /// The user who requested the job, unset for jobs started by the bot itself
+    pub requested_by: Option<String>,
+    /// When the job will be run
+    pub execute_after: DateTime<Utc>,
+    /// Steps which have finished
+    pub steps_done: Vec<String>,
+    pub steps_total: usize,
+    /// Error of the last failed attempt, failed jobs are retried
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Download URL of a completed export, until it expires
+    pub download_url: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct DataJobRow {
+    id: Uuid,
+    owner_id: String,
+    owner_type: String,
+    kind: String,
+    state: String,
+    reason: String,
+    requested_by: Option<String>,
+    execute_after: DateTime<Utc>,
+    steps_done: Vec<String>,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    completed_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<DataJobRow> for DataJob {
+    type Error = crate::Error;
+
+    fn try_from(row: DataJobRow) -> Result<Self, Self::Error> {
+        let kind = DataJobKind::parse(&row.kind)?;
+        Ok(Self {
+            job_id: row.id,
+            id: Id::from_parts(&row.owner_type, &row.owner_id).ok_or("Invalid tenant of data job")?,
+            kind,
+            state: DataJobState::parse(&row.state)?,
+            reason: row.reason,
+            requested_by: row.requested_by,
+            execute_after: row.execute_after,
+            steps_done: row.steps_done,
+            steps_total: kind.steps_total(),
+            error: row.error,
+            created_at: row.created_at,
+            completed_at: row.completed_at,
+            download_url: None,
+        })
+    }
+}
+
+/// An audit record of an action taken on the data of a tenant
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DataAuditEntry {
+    pub job_id: Option<Uuid>,
+    pub action: String,
+    /// The user who took the action, unset for actions taken by the bot itself
+    pub actor: Option<String>,
+    pub details: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+const JOB_COLUMNS: &str = "id, owner_id, owner_type, kind, state, reason, requested_by, execute_after, steps_done, error, created_at, completed_at";
+
+/// Key an export is stored under in the export scope
+fn export_key(job_id: Uuid) -> String {
+    format!("{job_id}.tar.gz")
+}
+
+/// Bundles files into a gzipped tarball
+pub(crate) fn build_archive(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>, crate::Error> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    let mtime = Utc::now().timestamp().max(0) as u64;
+    for (path, data) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder.append_data(&mut header, path, data.as_slice())?;
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
+
+#[derive(Clone)]
+/// Storage of tenant data jobs and the deletion/export of tenant data
+pub struct DataLifecycleDb {
+    pool: sqlx::PgPool,
+}
+
+impl DataLifecycleDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Schedules deletion of all data of a tenant once the grace period is over
+    ///
+    /// Returns the already outstanding deletion of the tenant if there is one
+    pub async fn schedule_deletion(&self, id: Id, reason: &str, requested_by: Option<String>) -> Result<DataJob, crate::Error> {
+        let execute_after = Utc::now() + chrono::Duration::hours(CONFIG.data_lifecycle.deletion_grace_period_hours as i64);
+        self.create_job(id, DataJobKind::Deletion, reason, requested_by, execute_after).await
+    }
+
+    /// Starts an export of all data of a tenant
+    ///
+    /// Returns the already outstanding export of the tenant if there is one
+    pub async fn request_export(&self, id: Id, requested_by: Option<String>) -> Result<DataJob, crate::Error> {
+        self.create_job(id, DataJobKind::Export, REASON_REQUESTED, requested_by, Utc::now()).await
+    }
+
+    async fn create_job(&self, id: Id, kind: DataJobKind, reason: &str, requested_by: Option<String>, execute_after: DateTime<Utc>) -> Result<DataJob, crate::Error> {
+        let job_id = Uuid::now_v7();
+        let inserted: Option<DataJobRow> = sqlx::query_as(&format!(
+            "INSERT INTO tenant_data_jobs (id, owner_id, owner_type, kind, reason, requested_by, execute_after) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (owner_id, owner_type, kind) WHERE state IN ('pending', 'running') DO NOTHING
+            RETURNING {JOB_COLUMNS}"
+        ))
+        .bind(job_id)
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .bind(kind.as_str())
+        .bind(reason)
+        .bind(&requested_by)
+        .bind(execute_after)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match inserted {
+            Some(row) => {
+                self.record_audit(Some(job_id), id, &format!("{}_scheduled", kind.as_str()), requested_by, Some(format!("reason: {reason}"))).await?;
+                row.try_into()
+            }
+            None => {
+                let row: DataJobRow = sqlx::query_as(&format!(
+                    "SELECT {JOB_COLUMNS} FROM tenant_data_jobs WHERE owner_id = $1 AND owner_type = $2 AND kind = $3 AND state IN ('pending', 'running')"
+                ))
+                .bind(id.tenant_id())
+                .bind(id.tenant_type())
+                .bind(kind.as_str())
+                .fetch_one(&self.pool)
+                .await?;
+                row.try_into()
+            }
+        }
+    }
+
+    /// Cancels the pending deletion of a tenant, returning false if there is none
+    ///
+    /// Deletions which have started cannot be cancelled
+    pub async fn cancel_deletion(&self, id: Id, actor: Option<String>, details: Option<String>) -> Result<bool, crate::Error> {
+        let cancelled: Option<(Uuid,)> = sqlx::query_as(
+            "UPDATE tenant_data_jobs SET state = 'cancelled', completed_at = NOW() WHERE owner_id = $1 AND owner_type = $2 AND kind = 'deletion' AND state = 'pending' RETURNING id"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some((job_id,)) = cancelled else {
+            return Ok(false);
+        };
+        self.record_audit(Some(job_id), id, "deletion_cancelled", actor, details).await?;
+        Ok(true)
+    }
+
+    /// Returns the most recent jobs of a tenant, newest first
+    pub async fn list_jobs(&self, id: Id) -> Result<Vec<DataJob>, crate::Error> {
+        let rows: Vec<DataJobRow> = sqlx::query_as(&format!(
+            "SELECT {JOB_COLUMNS} FROM tenant_data_jobs WHERE owner_id = $1 AND owner_type = $2 ORDER BY created_at DESC LIMIT 50"
+        ))
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .fetch_all(&self.pool)
+        .await?;
+
+        let expiry = chrono::Duration::hours(CONFIG.data_lifecycle.export_expiry_hours as i64);
+        let mut jobs = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut job: DataJob = row.try_into()?;
+            if job.kind == DataJobKind::Export && job.state == DataJobState::Completed && let Some(completed_at) = job.completed_at {
+                let remaining = (completed_at + expiry - Utc::now()).num_seconds();
+                if remaining > 0 {
+                    job.download_url = Some(crate::geese::urlsign::create_url(id, &export_key(job.job_id), EXPORT_SCOPE, remaining as u64)?);
+                }
+            }
+            jobs.push(job);
+        }
+        Ok(jobs)
+    }
+
+    /// Returns the most recent audit records of a tenant, newest first
+    pub async fn audit_log(&self, id: Id) -> Result<Vec<DataAuditEntry>, crate::Error> {
+        let entries = sqlx::query_as(
+            "SELECT job_id, action, actor, details, created_at FROM tenant_data_audit WHERE owner_id = $1 AND owner_type = $2 ORDER BY created_at DESC LIMIT 100"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(entries)
+    }
+
+    /// Records an action taken on the data of a tenant
+    pub async fn record_audit(&self, job_id: Option<Uuid>, id: Id, action: &str, actor: Option<String>, details: Option<String>) -> Result<(), crate::Error> {
+        sqlx::query("INSERT INTO tenant_data_audit (job_id, owner_id, owner_type, action, actor, details) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(job_id)
+            .bind(id.tenant_id())
+            .bind(id.tenant_type())
+            .bind(action)
+            .bind(actor)
+            .bind(details)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the jobs which are due to be run (including ones left running by a restart), oldest first
+    pub async fn due_jobs(&self) -> Result<Vec<DataJob>, crate::Error> {
+        let rows: Vec<DataJobRow> = sqlx::query_as(&format!(
+            "SELECT {JOB_COLUMNS} FROM tenant_data_jobs WHERE state IN ('pending', 'running') AND execute_after <= NOW() ORDER BY execute_after LIMIT 16"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(DataJob::try_from).collect()
+    }
+
+    /// Sets the state of a job, finished jobs have their completion time set
+    pub async fn set_state(&self, job_id: Uuid, state: DataJobState) -> Result<(), crate::Error> {
+        sqlx::query(
+            "UPDATE tenant_data_jobs SET state = $2, error = NULL, completed_at = CASE WHEN $2 IN ('completed', 'cancelled') THEN NOW() END WHERE id = $1"
+        )
+        .bind(job_id)
+        .bind(state.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Records the error of a failed job and retries it after `delay`, resuming from its last finished step
+    pub async fn retry_later(&self, job_id: Uuid, error: &str, delay: Duration) -> Result<(), crate::Error> {
+        sqlx::query("UPDATE tenant_data_jobs SET state = 'pending', error = $2, execute_after = $3 WHERE id = $1")
+            .bind(job_id)
+            .bind(error)
+            .bind(Utc::now() + chrono::Duration::from_std(delay)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn mark_step<'c, E>(executor: E, job_id: Uuid, step: &str) -> Result<(), crate::Error>
+    where
+        E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+    {
+        sqlx::query("UPDATE tenant_data_jobs SET steps_done = array_append(steps_done, $2) WHERE id = $1 AND NOT ($2 = ANY(steps_done))")
+            .bind(job_id)
+            .bind(step)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Runs a step of a deletion, returning the number of rows deleted
+    ///
+    /// The step is marked as done in the same transaction, so an interrupted deletion resumes from the next step
+    pub async fn run_deletion_step(&self, job: &DataJob, step: &str, stmt: &str) -> Result<u64, crate::Error> {
+        let mut tx = self.pool.begin().await?;
+        let res = sqlx::query(stmt)
+            .bind(job.id.tenant_id())
+            .bind(job.id.tenant_type())
+            .execute(&mut *tx)
+            .await?;
+        Self::mark_step(&mut *tx, job.job_id, step).await?;
+        tx.commit().await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Fetches the rows of a table belonging to a tenant as a JSON array
+    async fn tenant_rows(&self, query: &str, id: Id) -> Result<Vec<u8>, crate::Error> {
+        let (rows,): (serde_json::Value,) = sqlx::query_as(&format!("SELECT COALESCE(json_agg(t), '[]'::json) FROM ({query}) t"))
+            .bind(id.tenant_id())
+            .bind(id.tenant_type())
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(serde_json::to_vec_pretty(&rows)?)
+    }
+
+    /// Runs an export, bundling all data of the tenant into an archive stored in the export scope
+    ///
+    /// Exports are built in memory, an interrupted export is hence rebuilt from the start
+    pub async fn run_export(&self, job: &DataJob) -> Result<(), crate::Error> {
+        let id = job.id;
+        sqlx::query("UPDATE tenant_data_jobs SET steps_done = '{}' WHERE id = $1")
+            .bind(job.job_id)
+            .execute(&self.pool)
+            .await?;
+
+        let manifest = serde_json::json!({
+            "tenant_id": id.tenant_id(),
+            "tenant_type": id.tenant_type(),
+            "job_id": job.job_id,
+            "exported_at": Utc::now(),
+        });
+        let mut files = vec![("manifest.json".to_string(), serde_json::to_vec_pretty(&manifest)?)];
+
+        files.push(("tenant_state.json".to_string(), self.tenant_rows("SELECT * FROM tenant_state WHERE owner_id = $1 AND owner_type = $2", id).await?));
+        files.push(("tenant_state_events.json".to_string(), self.tenant_rows("SELECT * FROM tenant_state_events WHERE owner_id = $1 AND owner_type = $2", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "tenant_state").await?;
+
+        // Blobs are exported as separate files named by the id of their record, secrets are never exported
+        files.push(("kv.json".to_string(), self.tenant_rows(
+            &format!("SELECT id, key, scope, value, created_at, last_updated_at, blob IS NOT NULL AS has_blob FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND scope NOT IN ('{EXPORT_SCOPE}', '{SECRETS_SCOPE}')"),
+            id
+        ).await?));
+        Self::mark_step(&self.pool, job.job_id, "kv").await?;
+
+        let blobs: Vec<(String, Vec<u8>)> = sqlx::query_as(
+            "SELECT id, blob FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND scope != $3 AND blob IS NOT NULL"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .bind(EXPORT_SCOPE)
+        .fetch_all(&self.pool)
+        .await?;
+        files.extend(blobs.into_iter().map(|(kv_id, blob)| (format!("blobs/{kv_id}"), blob)));
+        Self::mark_step(&self.pool, job.job_id, "blobs").await?;
+
+        files.push(("global_kv.json".to_string(), self.tenant_rows("SELECT * FROM global_kv WHERE owner_id = $1 AND owner_type = $2", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "global_kv").await?;
+
+        files.push(("usage.json".to_string(), self.tenant_rows("SELECT * FROM template_usage_daily WHERE owner_id = $1 AND owner_type = $2", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "usage").await?;
+
+        files.push(("modmail_sessions.json".to_string(), self.tenant_rows("SELECT * FROM modmail_sessions WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        files.push(("modmail_messages.json".to_string(), self.tenant_rows(
+            "SELECT m.* FROM modmail_messages m JOIN modmail_sessions s ON s.id = m.session_id WHERE s.guild_id = $1 AND $2 = 'guild'",
+            id
+        ).await?));
+        Self::mark_step(&self.pool, job.job_id, "modmail").await?;
+
+        files.push(("appeals.json".to_string(), self.tenant_rows("SELECT * FROM appeals WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "appeals").await?;
+
+        files.push(("watchlist.json".to_string(), self.tenant_rows("SELECT * FROM watchlist WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "watchlist").await?;
+
+        files.push(("invite_joins.json".to_string(), self.tenant_rows("SELECT * FROM invite_joins WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "invite_joins").await?;
+
+        files.push(("sticky_roles.json".to_string(), self.tenant_rows("SELECT * FROM sticky_roles WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "sticky_roles").await?;
+
+        files.push(("permission_snapshots.json".to_string(), self.tenant_rows("SELECT * FROM permission_snapshots WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "permission_snapshots").await?;
+
+        files.push(("scheduled_messages.json".to_string(), self.tenant_rows("SELECT * FROM scheduled_messages WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "scheduled_messages").await?;
+
+        files.push(("emoji_usage.json".to_string(), self.tenant_rows("SELECT * FROM emoji_usage_daily WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "emoji_usage").await?;
+
+        files.push(("activity.json".to_string(), self.tenant_rows("SELECT * FROM channel_activity_hourly WHERE guild_id = $1 AND $2 = 'guild'", id).await?));
+        Self::mark_step(&self.pool, job.job_id, "activity").await?;
+
+        let archive = build_archive(files)?;
+        let size = archive.len();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO tenant_kv (id, owner_id, owner_type, key, value, scope, blob) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (owner_id, owner_type, key, scope) DO UPDATE SET value = EXCLUDED.value, blob = EXCLUDED.blob, last_updated_at = NOW()",
+        )
+        .bind(Alphanumeric.sample_string(&mut rand::rng(), 64))
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .bind(export_key(job.job_id))
+        .bind(serde_json::to_value(KhronosValue::Text(format!("{size} bytes").into()))?)
+        .bind(EXPORT_SCOPE)
+        .bind(archive)
+        .execute(&mut *tx)
+        .await?;
+        Self::mark_step(&mut *tx, job.job_id, "archive").await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Deletes exports which can no longer be downloaded, returning the number deleted
+    pub async fn delete_expired_exports(&self) -> Result<u64, crate::Error> {
+        let res = sqlx::query("DELETE FROM tenant_kv WHERE scope = $1 AND created_at < $2")
+            .bind(EXPORT_SCOPE)
+            .bind(Utc::now() - chrono::Duration::hours(CONFIG.data_lifecycle.export_expiry_hours as i64))
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Deletes execution recordings past their maximum age, returning the number deleted
+    pub async fn delete_expired_recordings(&self) -> Result<u64, crate::Error> {
+        let res = sqlx::query("DELETE FROM tenant_kv WHERE scope = $1 AND created_at < $2")
+            .bind(REPLAY_SCOPE)
+            .bind(Utc::now() - chrono::Duration::from_std(MAX_RECORDING_AGE)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
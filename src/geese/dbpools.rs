@@ -0,0 +1,207 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Postgres, Transaction};
+
+use crate::worker::limits::MAX_TEMPLATE_STATEMENT_TIME;
+
+/// How often connections of the templates partition check whether the client is still connected while running a
+/// statement, so closing a connection cancels its statement
+const TEMPLATE_CONNECTION_CHECK_INTERVAL: &str = "1s";
+
+/// Subsystems with their own partition of database connections, so one can't starve the others
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolKind {
+    /// Everything not in another partition (auth, outbox, data lifecycle etc.)
+    Default,
+    /// Template KV and global KV
+    Kv,
+    /// Tenant states and feature flags, needed to dispatch events
+    Settings,
+    /// Stings (the `builtins.stings` KV scope)
+    Stings,
+    /// Usage rollups and reports
+    Analytics,
+    /// State ops of templates, whose statements are limited to `MAX_TEMPLATE_STATEMENT_TIME`. Never shares the
+    /// default pool
+    Templates,
+}
+
+impl PoolKind {
+    const ALL: [PoolKind; 6] = [Self::Default, Self::Kv, Self::Settings, Self::Stings, Self::Analytics, Self::Templates];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Kv => "kv",
+            Self::Settings => "settings",
+            Self::Stings => "stings",
+            Self::Analytics => "analytics",
+            Self::Templates => "templates",
+        }
+    }
+}
+
+/// Status of a pool, for the health endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStatus {
+    pub name: &'static str,
+    pub max_connections: u32,
+    /// Open connections
+    pub size: u32,
+    /// Open connections not in use
+    pub idle: usize,
+    /// Whether every connection is open and in use, so further acquires wait
+    pub saturated: bool,
+    /// Instrumented acquires since startup
+    pub acquires: u64,
+    /// Total time spent waiting for connections since startup
+    pub wait_total_ms: u64,
+    /// Longest time spent waiting for a connection since startup
+    pub wait_max_ms: u64,
+    /// Acquires which timed out since startup
+    pub timeouts: u64,
+}
+
+#[derive(Default)]
+struct PoolStats {
+    acquires: AtomicU64,
+    wait_total_us: AtomicU64,
+    wait_max_us: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+/// A pool of a subsystem, timing how long connections are waited for
+#[derive(Clone)]
+pub struct LabeledPool {
+    kind: PoolKind,
+    pool: sqlx::PgPool,
+    max_connections: u32,
+    stats: Arc<PoolStats>,
+}
+
+impl LabeledPool {
+    /// Returns the underlying pool, queries run directly on it are not timed
+    pub fn pool(&self) -> &sqlx::PgPool {
+        &self.pool
+    }
+
+    /// Acquires a connection, recording the wait
+    pub async fn acquire(&self) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+        let start = Instant::now();
+        let res = self.pool.acquire().await;
+        self.record(start, &res);
+        res
+    }
+
+    /// Begins a transaction, recording the wait for its connection
+    pub async fn begin(&self) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+        let start = Instant::now();
+        let res = self.pool.begin().await;
+        self.record(start, &res);
+        res
+    }
+
+    fn record<T>(&self, start: Instant, res: &Result<T, sqlx::Error>) {
+        let waited = start.elapsed().as_micros() as u64;
+        self.stats.acquires.fetch_add(1, Ordering::Relaxed);
+        self.stats.wait_total_us.fetch_add(waited, Ordering::Relaxed);
+        self.stats.wait_max_us.fetch_max(waited, Ordering::Relaxed);
+        if matches!(res, Err(sqlx::Error::PoolTimedOut)) {
+            self.stats.timeouts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        let size = self.pool.size();
+        let idle = self.pool.num_idle();
+        PoolStatus {
+            name: self.kind.name(),
+            max_connections: self.max_connections,
+            size,
+            idle,
+            saturated: size >= self.max_connections && idle == 0,
+            acquires: self.stats.acquires.load(Ordering::Relaxed),
+            wait_total_ms: self.stats.wait_total_us.load(Ordering::Relaxed) / 1000,
+            wait_max_ms: self.stats.wait_max_us.load(Ordering::Relaxed) / 1000,
+            timeouts: self.stats.timeouts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The database pools of the master, partitioned by subsystem
+///
+/// Subsystems without their own partition share the default pool
+#[derive(Clone)]
+pub struct DbPools {
+    /// Indexed by `PoolKind`
+    pools: Arc<[LabeledPool; 6]>,
+}
+
+impl DbPools {
+    /// Connects the pools, `sizes` being the maximum number of connections of each partition (the default pool
+    /// included). Partitions with a size of 0 share the default pool, except for the templates partition which
+    /// always gets at least one connection of its own
+    pub async fn connect(url: &str, sizes: impl Fn(PoolKind) -> u32) -> Result<Self, crate::Error> {
+        let connect = async |kind: PoolKind, max_connections: u32| -> Result<LabeledPool, crate::Error> {
+            let options = match kind {
+                PoolKind::Templates => template_pool_options(),
+                _ => PgPoolOptions::new(),
+            };
+            let pool = options
+                .max_connections(max_connections)
+                .connect(url)
+                .await?;
+            Ok(LabeledPool { kind, pool, max_connections, stats: Arc::default() })
+        };
+
+        let default = connect(PoolKind::Default, sizes(PoolKind::Default)).await?;
+        let mut pools = Vec::with_capacity(PoolKind::ALL.len());
+        for kind in PoolKind::ALL {
+            let pool = match (kind, sizes(kind)) {
+                (PoolKind::Templates, n) => connect(kind, n.max(1)).await?,
+                (PoolKind::Default, _) | (_, 0) => default.clone(),
+                (_, n) => connect(kind, n).await?,
+            };
+            pools.push(pool);
+        }
+
+        let pools: [LabeledPool; 6] = pools.try_into().unwrap_or_else(|_| unreachable!("one pool per kind"));
+        Ok(Self { pools: Arc::new(pools) })
+    }
+
+    /// Returns the pool of a subsystem
+    pub fn get(&self, kind: PoolKind) -> &LabeledPool {
+        &self.pools[kind as usize]
+    }
+
+    /// Returns the status of every distinct pool
+    pub fn status(&self) -> Vec<PoolStatus> {
+        self.pools.iter()
+            .enumerate()
+            .filter(|(i, p)| p.kind as usize == *i)
+            .map(|(_, p)| p.status())
+            .collect()
+    }
+}
+
+/// Options of pools running the state ops of templates
+///
+/// Every connection limits its statements to `MAX_TEMPLATE_STATEMENT_TIME` for its whole session, and has the server
+/// check for the client disconnecting while running a statement. Closing a connection (see `StatementGuard`) thus
+/// cancels the statement it was running, without needing the backend pid of the connection
+pub fn template_pool_options() -> PgPoolOptions {
+    PgPoolOptions::new()
+        .after_connect(|conn, _meta| Box::pin(async move {
+            sqlx::query("SELECT set_config('statement_timeout', $1, false), set_config('client_connection_check_interval', $2, false)")
+                .bind(format!("{}ms", MAX_TEMPLATE_STATEMENT_TIME.as_millis()))
+                .bind(TEMPLATE_CONNECTION_CHECK_INTERVAL)
+                .execute(conn)
+                .await?;
+            Ok(())
+        }))
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPoolOptions;
+
+use crate::CONFIG;
+use crate::config::ReadReplicaConfig;
+use crate::geese::dbpools::{DbPools, LabeledPool, PoolKind, template_pool_options};
+use crate::worker::workervmmanager::Id;
+
+/// Lag of a replica whose lag couldn't be checked, always too much
+const UNKNOWN_LAG: u64 = u64::MAX;
+
+/// Counters of how reads were routed, since the process started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicaStats {
+    /// Last measured replication lag, unset if the last check failed
+    pub lag_ms: Option<u64>,
+    /// Reads served by the replica
+    pub replica_reads: u64,
+    /// Reads sent to the primary as the replica lagged too far behind
+    pub primary_reads_lagging: u64,
+    /// Reads sent to the primary as the tenant wrote recently
+    pub primary_reads_recent_write: u64,
+    /// Reads which failed on the replica and were retried on the primary
+    pub replica_errors: u64,
+}
+
+struct Replica {
+    config: &'static ReadReplicaConfig,
+    pool: sqlx::PgPool,
+    /// Pool for the reads of templates, see `template_pool_options`
+    templates: sqlx::PgPool,
+    lag_ms: AtomicU64,
+    /// Tenants which wrote within `read_your_writes_ms`
+    recent_writes: moka::sync::Cache<Id, ()>,
+    replica_reads: AtomicU64,
+    primary_reads_lagging: AtomicU64,
+    primary_reads_recent_write: AtomicU64,
+    replica_errors: AtomicU64,
+}
+
+/// Routes read-only queries to the read replica (if configured) and everything else to the primary
+///
+/// Reads only go to the replica while its replication lag is within `max_lag_ms`. Reads of the data of a
+/// tenant additionally go to the primary for `read_your_writes_ms` after the tenant wrote, so templates
+/// always see their own writes
+#[derive(Clone)]
+pub struct DbRouter {
+    pools: DbPools,
+    replica: Option<Arc<Replica>>,
+}
+
+impl DbRouter {
+    /// Creates the router, connecting to the replica (lazily) and spawning the lag monitor if configured
+    pub fn new(pools: DbPools) -> Result<Self, crate::Error> {
+        let Some(config) = CONFIG.read_replica.as_ref() else {
+            return Ok(Self { pools, replica: None });
+        };
+
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect_lazy(&config.postgres_url)?;
+        let templates = template_pool_options()
+            .max_connections(config.max_connections)
+            .connect_lazy(&config.postgres_url)?;
+
+        let replica = Arc::new(Replica {
+            config,
+            pool,
+            templates,
+            lag_ms: AtomicU64::new(UNKNOWN_LAG),
+            recent_writes: moka::sync::Cache::builder()
+                .time_to_live(Duration::from_millis(config.read_your_writes_ms))
+                .build(),
+            replica_reads: AtomicU64::new(0),
+            primary_reads_lagging: AtomicU64::new(0),
+            primary_reads_recent_write: AtomicU64::new(0),
+            replica_errors: AtomicU64::new(0),
+        });
+
+        tokio::spawn(Self::monitor_lag(replica.clone()));
+        Ok(Self { pools, replica: Some(replica) })
+    }
+
+    /// Returns the default pool of the primary, for writes and reads which must be up to date
+    pub fn primary(&self) -> &sqlx::PgPool {
+        self.pools.get(PoolKind::Default).pool()
+    }
+
+    /// Returns the pool of the primary partitioned to a subsystem
+    pub fn pool(&self, kind: PoolKind) -> &LabeledPool {
+        self.pools.get(kind)
+    }
+
+    /// Returns the pools of the primary
+    pub fn pools(&self) -> &DbPools {
+        &self.pools
+    }
+
+    /// Returns the replica if it is not lagging too far behind, for reads which may be slightly stale
+    /// (analytics etc.)
+    pub fn reads(&self) -> Option<&sqlx::PgPool> {
+        let replica = self.replica.as_ref()?;
+        if replica.lag_ms.load(Ordering::Relaxed) > replica.config.max_lag_ms {
+            replica.primary_reads_lagging.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        replica.replica_reads.fetch_add(1, Ordering::Relaxed);
+        Some(&replica.pool)
+    }
+
+    /// Like `reads`, but also returns None if the tenant wrote recently. Reads of templates get the replica pool
+    /// whose statements are time limited, like the templates partition of the primary
+    pub fn reads_for(&self, id: Id, template: bool) -> Option<&sqlx::PgPool> {
+        let replica = self.replica.as_ref()?;
+        if replica.recent_writes.contains_key(&id) {
+            replica.primary_reads_recent_write.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let pool = self.reads()?;
+        Some(if template { &replica.templates } else { pool })
+    }
+
+    /// Records a write of a tenant, should be called before writing
+    pub fn wrote(&self, id: Id) {
+        if let Some(replica) = self.replica.as_ref() {
+            replica.recent_writes.insert(id, ());
+        }
+    }
+
+    /// Records a read which failed on the replica (and is retried on the primary)
+    pub fn replica_failed(&self, e: &crate::Error) {
+        if let Some(replica) = self.replica.as_ref() {
+            log::warn!("Read failed on the replica, retrying on the primary: {e}");
+            replica.replica_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the routing counters, if a replica is configured
+    pub fn stats(&self) -> Option<ReplicaStats> {
+        let replica = self.replica.as_ref()?;
+        let lag_ms = replica.lag_ms.load(Ordering::Relaxed);
+        Some(ReplicaStats {
+            lag_ms: (lag_ms != UNKNOWN_LAG).then_some(lag_ms),
+            replica_reads: replica.replica_reads.load(Ordering::Relaxed),
+            primary_reads_lagging: replica.primary_reads_lagging.load(Ordering::Relaxed),
+            primary_reads_recent_write: replica.primary_reads_recent_write.load(Ordering::Relaxed),
+            replica_errors: replica.replica_errors.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Periodically measures the replication lag of the replica
+    async fn monitor_lag(replica: Arc<Replica>) {
+        let mut interval = tokio::time::interval(Duration::from_millis(replica.config.lag_check_interval_ms));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut lagging = true;
+        loop {
+            interval.tick().await;
+
+            // No lag if everything received was replayed, otherwise the age of the last replayed transaction
+            let lag: Result<Option<f64>, sqlx::Error> = sqlx::query_scalar(
+                "SELECT CASE
+                    WHEN NOT pg_is_in_recovery() OR pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+                    ELSE EXTRACT(EPOCH FROM NOW() - pg_last_xact_replay_timestamp()) * 1000
+                END::FLOAT8"
+            )
+            .fetch_one(&replica.pool)
+            .await;
+
+            let lag_ms = match lag {
+                Ok(lag) => lag.map_or(UNKNOWN_LAG, |l| l.max(0.0) as u64),
+                Err(e) => {
+                    log::warn!("Failed to check replication lag of the replica: {e}");
+                    UNKNOWN_LAG
+                }
+            };
+            replica.lag_ms.store(lag_ms, Ordering::Relaxed);
+
+            let now_lagging = lag_ms > replica.config.max_lag_ms;
+            if now_lagging != lagging {
+                if now_lagging {
+                    log::warn!("Replica is lagging ({lag_ms}ms), sending reads to the primary");
+                } else {
+                    log::info!("Replica caught up ({lag_ms}ms), sending reads to it");
+                }
+                lagging = now_lagging;
+            }
+        }
+    }
+}
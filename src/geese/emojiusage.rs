@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, Utc};
+use dapi::GuildId;
+use khronos_runtime::core::datetime::DateTime as LuaDateTime;
+use khronos_runtime::rt::mlua::prelude::*;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::geese::dbpools::PoolKind;
+use crate::geese::dbrouter::DbRouter;
+
+/// Maximum sampling rate, i.e. at most 1 in this many messages and reactions are scanned
+pub const MAX_EMOJI_USAGE_SAMPLE: u32 = 100;
+
+/// Maximum number of days usage is reported over
+pub const MAX_EMOJI_USAGE_DAYS: u32 = 90;
+
+/// Maximum number of emojis and stickers in a report, enough for every slot of a guild
+pub const MAX_EMOJI_USAGE_LIMIT: i64 = 500;
+
+/// Maximum number of pending counters per worker, uses of further emojis are dropped until the next flush
+const MAX_PENDING_EMOJI_USAGE: usize = 50_000;
+
+/// How custom emoji and sticker usage of a guild is tracked
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmojiUsageConfig {
+    /// Only 1 in this many messages and reactions are scanned, each use found counting this many times. Bounds the
+    /// cost of tracking on huge guilds
+    #[serde(default = "default_sample")]
+    pub sample_one_in: u32,
+    /// Whether reactions are counted as uses too
+    #[serde(default = "default_true")]
+    pub reactions: bool,
+}
+
+fn default_sample() -> u32 {
+    1
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl EmojiUsageConfig {
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.sample_one_in == 0 || self.sample_one_in > MAX_EMOJI_USAGE_SAMPLE {
+            return Err(format!("Emoji usage can be sampled from 1 in 1 to 1 in {MAX_EMOJI_USAGE_SAMPLE} messages").into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmojiKind {
+    Emoji,
+    Sticker,
+}
+
+impl EmojiKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Emoji => "emoji",
+            Self::Sticker => "sticker",
+        }
+    }
+}
+
+/// Where an emoji or sticker was used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmojiUseSource {
+    Message,
+    Reaction,
+}
+
+/// Use counters of an emoji or sticker
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmojiUsage {
+    pub messages: u64,
+    pub reactions: u64,
+}
+
+/// Usage of an emoji or sticker in a guild over a day, sent from workers to the master
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmojiUsageRecord {
+    pub guild_id: GuildId,
+    pub kind: EmojiKind,
+    pub item_id: String,
+    /// The name the emoji or sticker was last used with
+    pub name: String,
+    pub day: DateTime<Utc>,
+    pub usage: EmojiUsage,
+}
+
+/// Worker-side accumulator of emoji and sticker usage, flushed to the master periodically
+#[derive(Default)]
+pub struct EmojiUsageTracker {
+    pending: Mutex<HashMap<(GuildId, EmojiKind, String, DateTime<Utc>), (String, EmojiUsage)>>,
+}
+
+impl EmojiUsageTracker {
+    /// How often pending usage is flushed to the master
+    pub const FLUSH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+    /// Records `count` uses of an emoji or sticker in a guild
+    pub fn record(&self, guild_id: GuildId, kind: EmojiKind, item_id: &str, name: &str, source: EmojiUseSource, count: u64) {
+        let day = Utc::now().duration_trunc(chrono::Duration::days(1)).unwrap_or_else(|_| Utc::now());
+        let mut pending = self.pending.lock();
+        let key = (guild_id, kind, item_id.to_string(), day);
+        if pending.len() >= MAX_PENDING_EMOJI_USAGE && !pending.contains_key(&key) {
+            return;
+        }
+        let (last_name, usage) = pending.entry(key).or_default();
+        if last_name.as_str() != name {
+            *last_name = name.to_string();
+        }
+        match source {
+            EmojiUseSource::Message => usage.messages += count,
+            EmojiUseSource::Reaction => usage.reactions += count,
+        }
+    }
+
+    /// Takes all pending usage records
+    pub fn take(&self) -> Vec<EmojiUsageRecord> {
+        let pending = std::mem::take(&mut *self.pending.lock());
+        pending.into_iter()
+            .map(|((guild_id, kind, item_id, day), (name, usage))| EmojiUsageRecord { guild_id, kind, item_id, name, day, usage })
+            .collect()
+    }
+}
+
+/// A custom emoji or sticker of a guild
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmojiItem {
+    pub kind: EmojiKind,
+    pub id: String,
+    pub name: String,
+}
+
+/// Returns the custom emojis and stickers of a guild given its guild object
+pub fn guild_items(guild: &Value) -> Vec<EmojiItem> {
+    let items = |field: &str, kind: EmojiKind| {
+        guild.get(field).and_then(|v| v.as_array()).into_iter().flatten().filter_map(move |item| Some(EmojiItem {
+            kind,
+            id: item.get("id")?.as_str()?.to_string(),
+            name: item.get("name").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+        }))
+    };
+    items("emojis", EmojiKind::Emoji).chain(items("stickers", EmojiKind::Sticker)).collect()
+}
+
+/// Usage of an emoji or sticker of a guild over the report period
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct EmojiUsageStats {
+    /// `emoji` or `sticker`
+    pub kind: String,
+    pub item_id: String,
+    pub name: String,
+    pub message_uses: i64,
+    pub reaction_uses: i64,
+    /// The last day the emoji or sticker was used on (even before the report period), unset if never
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl IntoLua for EmojiUsageStats {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        table.set("kind", self.kind)?;
+        table.set("item_id", self.item_id)?;
+        table.set("name", self.name)?;
+        table.set("message_uses", self.message_uses)?;
+        table.set("reaction_uses", self.reaction_uses)?;
+        table.set("last_used", self.last_used.map(LuaDateTime::from_utc))?;
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Returns the most used emojis and stickers of a guild over the last `days` days, optionally of one kind only
+pub async fn top<'c, E>(executor: E, guild_id: GuildId, days: u32, kind: Option<EmojiKind>, limit: i64) -> Result<Vec<EmojiUsageStats>, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    let since = Utc::now() - chrono::Duration::days(days.clamp(1, MAX_EMOJI_USAGE_DAYS).into());
+    let stats = sqlx::query_as(
+        "SELECT kind, item_id, (array_agg(name ORDER BY day DESC))[1] AS name, SUM(message_uses)::BIGINT AS message_uses,
+        SUM(reaction_uses)::BIGINT AS reaction_uses, MAX(day) AS last_used
+        FROM emoji_usage_daily WHERE guild_id = $1 AND day >= $2 AND ($3::TEXT IS NULL OR kind = $3)
+        GROUP BY kind, item_id ORDER BY SUM(message_uses + reaction_uses) DESC LIMIT $4"
+    )
+    .bind(guild_id.to_string())
+    .bind(since)
+    .bind(kind.map(|k| k.as_str()))
+    .bind(limit.clamp(1, MAX_EMOJI_USAGE_LIMIT))
+    .fetch_all(executor)
+    .await?;
+    Ok(stats)
+}
+
+/// Returns which of the given emojis and stickers of a guild were not used in the last `days` days, least recently
+/// used (or never used) first
+pub async fn unused<'c, E>(executor: E, guild_id: GuildId, days: u32, items: &[EmojiItem]) -> Result<Vec<EmojiUsageStats>, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    if items.len() > MAX_EMOJI_USAGE_LIMIT as usize {
+        return Err(format!("At most {MAX_EMOJI_USAGE_LIMIT} emojis and stickers can be checked at once").into());
+    }
+    let since = Utc::now() - chrono::Duration::days(days.clamp(1, MAX_EMOJI_USAGE_DAYS).into());
+    let last_used: Vec<(String, String, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT kind, item_id, MAX(day) FROM emoji_usage_daily WHERE guild_id = $1 AND item_id = ANY($2) GROUP BY kind, item_id"
+    )
+    .bind(guild_id.to_string())
+    .bind(items.iter().map(|i| i.id.clone()).collect::<Vec<_>>())
+    .fetch_all(executor)
+    .await?;
+
+    let last_used = last_used.into_iter()
+        .map(|(kind, item_id, day)| ((kind, item_id), day))
+        .collect::<HashMap<_, _>>();
+    let mut unused = items.iter()
+        .map(|item| EmojiUsageStats {
+            kind: item.kind.as_str().to_string(),
+            item_id: item.id.clone(),
+            name: item.name.clone(),
+            message_uses: 0,
+            reaction_uses: 0,
+            last_used: last_used.get(&(item.kind.as_str().to_string(), item.id.clone())).copied(),
+        })
+        .filter(|s| s.last_used.is_none_or(|l| l < since))
+        .collect::<Vec<_>>();
+    unused.sort_by_key(|s| s.last_used);
+    Ok(unused)
+}
+
+/// Storage of the daily emoji and sticker usage rollups of guilds
+///
+/// Uses are counted by the workers (see `worker::emojiusage`) and recorded by the master. Templates query them
+/// through state ops (see `StateOp::EmojiUsageTop` and `StateOp::EmojiUsageUnused`), the API through the
+/// `EmojiUsage` syscall
+#[derive(Clone)]
+pub struct EmojiUsageDb {
+    pool: sqlx::PgPool,
+    db: DbRouter,
+}
+
+impl EmojiUsageDb {
+    pub fn new(db: DbRouter) -> Self {
+        Self { pool: db.pool(PoolKind::Analytics).pool().clone(), db }
+    }
+
+    /// Adds the given usage records to the daily rollups
+    pub async fn record(&self, records: Vec<EmojiUsageRecord>) -> Result<(), crate::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for record in records {
+            sqlx::query(
+                "INSERT INTO emoji_usage_daily (guild_id, kind, item_id, day, name, message_uses, reaction_uses)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (guild_id, kind, item_id, day) DO UPDATE SET
+                    name = EXCLUDED.name,
+                    message_uses = emoji_usage_daily.message_uses + EXCLUDED.message_uses,
+                    reaction_uses = emoji_usage_daily.reaction_uses + EXCLUDED.reaction_uses"
+            )
+            .bind(record.guild_id.to_string())
+            .bind(record.kind.as_str())
+            .bind(record.item_id)
+            .bind(record.day)
+            .bind(record.name)
+            .bind(record.usage.messages as i64)
+            .bind(record.usage.reactions as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn top(&self, guild_id: GuildId, days: u32, kind: Option<EmojiKind>, limit: i64) -> Result<Vec<EmojiUsageStats>, crate::Error> {
+        if let Some(replica) = self.db.reads() {
+            match top(replica, guild_id, days, kind, limit).await {
+                Ok(stats) => return Ok(stats),
+                Err(e) => self.db.replica_failed(&e),
+            }
+        }
+        top(&self.pool, guild_id, days, kind, limit).await
+    }
+
+    pub async fn unused(&self, guild_id: GuildId, days: u32, items: &[EmojiItem]) -> Result<Vec<EmojiUsageStats>, crate::Error> {
+        if let Some(replica) = self.db.reads() {
+            match unused(replica, guild_id, days, items).await {
+                Ok(stats) => return Ok(stats),
+                Err(e) => self.db.replica_failed(&e),
+            }
+        }
+        unused(&self.pool, guild_id, days, items).await
+    }
+}
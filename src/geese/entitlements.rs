@@ -0,0 +1,193 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use dapi::{GuildId, UserId};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::PremiumTier;
+use crate::geese::tenantstate::TenantLimits;
+use crate::worker::workervmmanager::Id;
+use crate::CONFIG;
+
+/// Maximum number of entitlements Discord returns per page
+const ENTITLEMENTS_PAGE_SIZE: usize = 100;
+
+/// A Discord entitlement (a SKU purchased by or granted to a guild or user)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entitlement {
+    pub id: String,
+    pub sku_id: String,
+    #[serde(default)]
+    pub user_id: Option<UserId>,
+    #[serde(default)]
+    pub guild_id: Option<GuildId>,
+    /// The entitlement type (8 for application subscriptions, 4 for test entitlements etc.)
+    pub r#type: u8,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default)]
+    pub consumed: Option<bool>,
+    #[serde(default)]
+    pub starts_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Test entitlements don't end
+    #[serde(default)]
+    pub ends_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Entitlement {
+    /// Returns the tenant the entitlement belongs to, guild entitlements take precedence
+    pub fn tenant(&self) -> Option<Id> {
+        match (self.guild_id, self.user_id) {
+            (Some(guild_id), _) => Some(Id::Guild(guild_id)),
+            (None, Some(user_id)) => Some(Id::User(user_id)),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns whether the entitlement currently grants its SKU
+    pub fn is_active(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        !self.deleted
+            && self.consumed != Some(true)
+            && self.starts_at.is_none_or(|s| s <= now)
+            && self.ends_at.is_none_or(|e| e > now)
+    }
+}
+
+/// Worker-side cache of entitlements keyed by tenant
+///
+/// Loaded in full periodically and kept up to date in between by the ENTITLEMENT_* gateway events
+#[derive(Default)]
+pub struct EntitlementCache {
+    entitlements: RwLock<HashMap<Id, Vec<Entitlement>>>,
+}
+
+impl EntitlementCache {
+    /// How often all entitlements are reloaded from Discord
+    pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+    /// Reloads all entitlements of the application from Discord
+    pub async fn load(&self, reqwest: &reqwest::Client) -> Result<(), crate::Error> {
+        let mut all: HashMap<Id, Vec<Entitlement>> = HashMap::new();
+        let mut after: Option<String> = None;
+        loop {
+            let mut url = format!(
+                "{}/api/v10/applications/{}/entitlements?exclude_ended=true&exclude_deleted=true&limit={ENTITLEMENTS_PAGE_SIZE}",
+                CONFIG.proxy, CONFIG.client_id
+            );
+            if let Some(after) = &after {
+                url.push_str(&format!("&after={after}"));
+            }
+
+            let page: Vec<Entitlement> = reqwest.get(url)
+                .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            let done = page.len() < ENTITLEMENTS_PAGE_SIZE;
+            // Entitlements are returned oldest first when paginating with `after`
+            after = page.last().map(|e| e.id.clone());
+            for entitlement in page {
+                if let Some(id) = entitlement.tenant() {
+                    all.entry(id).or_default().push(entitlement);
+                }
+            }
+
+            if done {
+                break;
+            }
+        }
+
+        *self.entitlements.write() = all;
+        Ok(())
+    }
+
+    /// Applies an ENTITLEMENT_CREATE/UPDATE/DELETE event, returning the tenant whose entitlements changed
+    pub fn handle_event(&self, name: &str, payload: &Value) -> Option<Id> {
+        let mut entitlement: Entitlement = match serde_json::from_value(payload.clone()) {
+            Ok(e) => e,
+            Err(e) => {
+                log::warn!("Failed to parse {name} payload: {e}");
+                return None;
+            }
+        };
+        if name == "ENTITLEMENT_DELETE" {
+            entitlement.deleted = true;
+        }
+
+        let id = entitlement.tenant()?;
+        let mut entitlements = self.entitlements.write();
+        let tenant = entitlements.entry(id).or_default();
+        tenant.retain(|e| e.id != entitlement.id);
+        if !entitlement.deleted {
+            tenant.push(entitlement);
+        }
+        Some(id)
+    }
+
+    /// Returns the active entitlements of a tenant
+    pub fn active_for(&self, id: Id) -> Vec<Entitlement> {
+        let now = chrono::Utc::now();
+        self.entitlements.read()
+            .get(&id)
+            .map(|e| e.iter().filter(|e| e.is_active(now)).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the premium tiers the active entitlements of a tenant grant
+    pub fn tiers_for(&self, id: Id) -> BTreeSet<String> {
+        let Some(premium) = CONFIG.premium.as_ref() else {
+            return BTreeSet::new();
+        };
+
+        let skus = self.active_for(id).into_iter().map(|e| e.sku_id).collect::<BTreeSet<_>>();
+        premium.tiers.iter()
+            .filter(|(_, tier)| tier.skus.iter().any(|s| skus.contains(s)))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Returns whether the tenant may use `feature` (e.g. `syscall:ImgGen`)
+    ///
+    /// Features not gated by the premium config are always allowed
+    pub fn allows(&self, feature: &str, id: Id) -> bool {
+        let Some(premium) = CONFIG.premium.as_ref() else {
+            return true;
+        };
+        if !premium.gated.iter().any(|f| f == feature) {
+            return true;
+        }
+
+        self.tiers_for(id).iter()
+            .filter_map(|t| premium.tiers.get(t))
+            .any(|tier| tier.features.iter().any(|f| f == feature))
+    }
+
+    /// Returns the limits of a tenant with the limits of its premium tiers filled in
+    ///
+    /// Limits explicitly set on the tenant (through `AdminSetTenantLimits`) always win, otherwise the
+    /// highest limit of its tiers is used
+    pub fn limits_for(&self, id: Id, mut limits: TenantLimits) -> TenantLimits {
+        let Some(premium) = CONFIG.premium.as_ref() else {
+            return limits;
+        };
+
+        let tiers = self.tiers_for(id);
+        let tiers = tiers.iter().filter_map(|t| premium.tiers.get(t)).collect::<Vec<_>>();
+        let highest = |f: fn(&PremiumTier) -> Option<u64>| tiers.iter().filter_map(|t| f(t)).max();
+        if limits.memory_limit.is_none() {
+            limits.memory_limit = highest(|t| t.memory_limit);
+        }
+        if limits.execution_time_limit_ms.is_none() {
+            limits.execution_time_limit_ms = highest(|t| t.execution_time_limit_ms);
+        }
+        if limits.return_wait_ms.is_none() {
+            limits.return_wait_ms = highest(|t| t.return_wait_ms);
+        }
+        limits
+    }
+}
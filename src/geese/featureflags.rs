@@ -0,0 +1,188 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::worker::workervmmanager::Id;
+
+/// A single feature flag
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct FeatureFlag {
+    /// The name of the flag
+    ///
+    /// Flags named `event:{EVENT_NAME}` gate the dispatch of that event and flags
+    /// named `syscall:{Category}` gate the syscall category (e.g. `syscall:Webhook`)
+    pub name: String,
+    /// Human-readable description of the flag
+    pub description: String,
+    /// Global kill-switch, a disabled flag is disabled for every tenant
+    pub enabled: bool,
+    /// Percentage (0-100) of tenants the flag is enabled for
+    pub rollout_percentage: i32,
+    /// Tenants the flag is always enabled for (if `enabled`)
+    pub include_tenants: Vec<String>,
+    /// Tenants the flag is never enabled for
+    pub exclude_tenants: Vec<String>,
+}
+
+impl FeatureFlag {
+    /// Returns whether the flag is enabled for the given tenant
+    pub fn is_enabled_for(&self, id: Id) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let tenant_id = id.tenant_id();
+        if self.exclude_tenants.contains(&tenant_id) {
+            return false;
+        }
+
+        if self.include_tenants.contains(&tenant_id) {
+            return true;
+        }
+
+        (Self::bucket(&self.name, &tenant_id) as i32) < self.rollout_percentage
+    }
+
+    /// Returns the stable rollout bucket (0-99) of a tenant for a flag
+    ///
+    /// The flag name is mixed in so the same tenants aren't always the first to get every rollout
+    fn bucket(name: &str, tenant_id: &str) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(name.as_bytes());
+        hasher.update(b":");
+        hasher.update(tenant_id.as_bytes());
+        let digest = hasher.finalize();
+
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        u64::from_be_bytes(bytes) % 100
+    }
+}
+
+/// A set of feature flags keyed by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FeatureFlags {
+    flags: HashMap<String, FeatureFlag>,
+}
+
+impl FeatureFlags {
+    pub fn new(flags: Vec<FeatureFlag>) -> Self {
+        Self { flags: flags.into_iter().map(|f| (f.name.clone(), f)).collect() }
+    }
+
+    /// Returns whether the flag `name` is enabled for the tenant, unknown flags are disabled
+    pub fn is_enabled(&self, name: &str, id: Id) -> bool {
+        self.flags.get(name).is_some_and(|f| f.is_enabled_for(id))
+    }
+
+    /// Returns whether the flag `name` allows the tenant through, unknown flags allow everything
+    ///
+    /// Used for gating existing functionality (events/syscalls) which should stay available
+    /// unless a flag explicitly exists for it
+    pub fn allows(&self, name: &str, id: Id) -> bool {
+        match self.flags.get(name) {
+            Some(f) => f.is_enabled_for(id),
+            None => true,
+        }
+    }
+
+    /// Returns the names of all flags enabled for the tenant
+    pub fn enabled_for(&self, id: Id) -> HashSet<String> {
+        self.flags.values()
+            .filter(|f| f.is_enabled_for(id))
+            .map(|f| f.name.clone())
+            .collect()
+    }
+
+    pub fn into_vec(self) -> Vec<FeatureFlag> {
+        self.flags.into_values().collect()
+    }
+}
+
+/// Worker-side cache of the current feature flags, replaced wholesale by the master on any change
+#[derive(Default)]
+pub struct FeatureFlagCache {
+    flags: RwLock<Arc<FeatureFlags>>,
+}
+
+impl FeatureFlagCache {
+    pub fn new(flags: FeatureFlags) -> Self {
+        Self { flags: RwLock::new(Arc::new(flags)) }
+    }
+
+    /// Returns a snapshot of the current feature flags
+    pub fn get(&self) -> Arc<FeatureFlags> {
+        self.flags.read().clone()
+    }
+
+    /// Replaces the current feature flags
+    pub fn set(&self, flags: FeatureFlags) {
+        *self.flags.write() = Arc::new(flags);
+    }
+
+    /// Shorthand for `get().is_enabled(name, id)`
+    pub fn is_enabled(&self, name: &str, id: Id) -> bool {
+        self.flags.read().is_enabled(name, id)
+    }
+
+    /// Shorthand for `get().allows(name, id)`
+    pub fn allows(&self, name: &str, id: Id) -> bool {
+        self.flags.read().allows(name, id)
+    }
+}
+
+#[derive(Clone)]
+pub struct FeatureFlagDb {
+    pool: sqlx::PgPool,
+}
+
+impl FeatureFlagDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Returns all feature flags
+    pub async fn list(&self) -> Result<FeatureFlags, crate::Error> {
+        let flags: Vec<FeatureFlag> = sqlx::query_as("SELECT name, description, enabled, rollout_percentage, include_tenants, exclude_tenants FROM feature_flags")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(FeatureFlags::new(flags))
+    }
+
+    /// Creates or updates a feature flag
+    pub async fn set(&self, flag: &FeatureFlag) -> Result<(), crate::Error> {
+        if !(0..=100).contains(&flag.rollout_percentage) {
+            return Err("rollout_percentage must be between 0 and 100".into());
+        }
+
+        sqlx::query(
+            "INSERT INTO feature_flags (name, description, enabled, rollout_percentage, include_tenants, exclude_tenants) VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (name) DO UPDATE SET description = EXCLUDED.description, enabled = EXCLUDED.enabled, rollout_percentage = EXCLUDED.rollout_percentage,
+            include_tenants = EXCLUDED.include_tenants, exclude_tenants = EXCLUDED.exclude_tenants, last_updated_at = NOW()"
+        )
+        .bind(&flag.name)
+        .bind(&flag.description)
+        .bind(flag.enabled)
+        .bind(flag.rollout_percentage)
+        .bind(&flag.include_tenants)
+        .bind(&flag.exclude_tenants)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes a feature flag, returning whether it existed
+    pub async fn delete(&self, name: &str) -> Result<bool, crate::Error> {
+        let res = sqlx::query("DELETE FROM feature_flags WHERE name = $1")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+}
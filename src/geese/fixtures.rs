@@ -0,0 +1,223 @@
+use dapi::{ChannelId, GuildId, UserId};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+/// The Discord epoch (first second of 2015) in milliseconds
+const DISCORD_EPOCH: u64 = 1420070400000;
+
+/// Gateway events fixtures can be built for
+pub const FIXTURE_EVENTS: &[&str] = &[
+    "MESSAGE_CREATE",
+    "MESSAGE_UPDATE",
+    "MESSAGE_DELETE",
+    "GUILD_MEMBER_ADD",
+    "GUILD_MEMBER_UPDATE",
+    "GUILD_MEMBER_REMOVE",
+    "INTERACTION_CREATE",
+    "GUILD_AUDIT_LOG_ENTRY_CREATE",
+];
+
+/// Maximum nesting depth of fixture overrides
+const MAX_OVERRIDE_DEPTH: usize = 16;
+
+/// The guild, channel and users fixtures refer to, so the objects nested in a payload agree with each other
+#[derive(Debug, Clone, Copy)]
+pub struct FixtureContext {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    /// The author of messages, the joining member, the user running commands and the target of audit log entries
+    pub user_id: UserId,
+    /// The executor of audit log entries
+    pub moderator_id: UserId,
+    /// The application interactions are sent to
+    pub application_id: UserId,
+}
+
+impl Default for FixtureContext {
+    fn default() -> Self {
+        Self {
+            guild_id: GuildId::new(1100000000000000001),
+            channel_id: ChannelId::new(1100000000000000002),
+            user_id: UserId::new(1100000000000000003),
+            moderator_id: UserId::new(1100000000000000004),
+            application_id: UserId::new(1100000000000000005),
+        }
+    }
+}
+
+impl FixtureContext {
+    /// Replaces the IDs set in `ids`
+    pub fn with_ids(mut self, ids: FixtureIds) -> Self {
+        self.guild_id = ids.guild_id.unwrap_or(self.guild_id);
+        self.channel_id = ids.channel_id.unwrap_or(self.channel_id);
+        self.user_id = ids.user_id.unwrap_or(self.user_id);
+        self.moderator_id = ids.moderator_id.unwrap_or(self.moderator_id);
+        self
+    }
+}
+
+/// IDs to use in place of those of a `FixtureContext`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FixtureIds {
+    #[serde(default)]
+    pub guild_id: Option<GuildId>,
+    #[serde(default)]
+    pub channel_id: Option<ChannelId>,
+    #[serde(default)]
+    pub user_id: Option<UserId>,
+    #[serde(default)]
+    pub moderator_id: Option<UserId>,
+}
+
+/// Builds a synthetic payload of a gateway event with sensible defaults, for testing templates
+///
+/// `overrides` is applied to the payload as a JSON merge patch: objects are merged field by field, anything else
+/// replaces the default, and null removes the field
+pub fn build(event: &str, ctx: &FixtureContext, overrides: Option<Value>) -> Result<Value, crate::Error> {
+    let now = chrono::Utc::now();
+    let timestamp = now.to_rfc3339();
+    let id = snowflake(now.timestamp_millis().max(0) as u64).to_string();
+
+    let mut payload = match event {
+        "MESSAGE_CREATE" | "MESSAGE_UPDATE" => json!({
+            "id": id,
+            "channel_id": ctx.channel_id.to_string(),
+            "guild_id": ctx.guild_id.to_string(),
+            "author": user(ctx.user_id),
+            "member": member(None, &timestamp),
+            "content": "Hello world",
+            "timestamp": timestamp,
+            "edited_timestamp": if event == "MESSAGE_UPDATE" { Value::String(timestamp.clone()) } else { Value::Null },
+            "tts": false,
+            "mention_everyone": false,
+            "mentions": [],
+            "mention_roles": [],
+            "attachments": [],
+            "embeds": [],
+            "pinned": false,
+            "type": 0,
+            "flags": 0,
+            "components": [],
+        }),
+        "MESSAGE_DELETE" => json!({
+            "id": id,
+            "channel_id": ctx.channel_id.to_string(),
+            "guild_id": ctx.guild_id.to_string(),
+        }),
+        "GUILD_MEMBER_ADD" | "GUILD_MEMBER_UPDATE" => {
+            let mut member = member(Some(ctx.user_id), &timestamp);
+            member["guild_id"] = ctx.guild_id.to_string().into();
+            member
+        }
+        "GUILD_MEMBER_REMOVE" => json!({
+            "guild_id": ctx.guild_id.to_string(),
+            "user": user(ctx.user_id),
+        }),
+        "INTERACTION_CREATE" => {
+            let mut member = member(Some(ctx.user_id), &timestamp);
+            member["permissions"] = "0".into();
+            json!({
+                "id": id,
+                "application_id": ctx.application_id.to_string(),
+                "type": 2,
+                "data": {
+                    "id": id,
+                    "name": "test",
+                    "type": 1,
+                    "options": [],
+                },
+                "guild_id": ctx.guild_id.to_string(),
+                "channel": { "id": ctx.channel_id.to_string(), "type": 0, "guild_id": ctx.guild_id.to_string() },
+                "channel_id": ctx.channel_id.to_string(),
+                "member": member,
+                "token": "fixture-interaction-token",
+                "version": 1,
+                "app_permissions": "0",
+                "locale": "en-US",
+                "guild_locale": "en-US",
+                "entitlements": [],
+                "authorizing_integration_owners": { "0": ctx.guild_id.to_string() },
+                "context": 0,
+                "attachment_size_limit": 26214400,
+            })
+        }
+        "GUILD_AUDIT_LOG_ENTRY_CREATE" => json!({
+            "id": id,
+            "guild_id": ctx.guild_id.to_string(),
+            "target_id": ctx.user_id.to_string(),
+            "user_id": ctx.moderator_id.to_string(),
+            // MEMBER_KICK
+            "action_type": 20,
+            "changes": [],
+            "reason": "Fixture reason",
+        }),
+        _ => return Err(format!("No fixture exists for event {event}, supported events are {}", FIXTURE_EVENTS.join(", ")).into()),
+    };
+
+    if let Some(overrides) = overrides {
+        merge_patch(&mut payload, overrides, 0)?;
+    }
+    Ok(payload)
+}
+
+/// Returns the smallest snowflake created at the given unix timestamp (in milliseconds)
+fn snowflake(millis: u64) -> u64 {
+    millis.saturating_sub(DISCORD_EPOCH) << 22
+}
+
+fn user(user_id: UserId) -> Value {
+    json!({
+        "id": user_id.to_string(),
+        "username": "fixture_user",
+        "global_name": "Fixture User",
+        "discriminator": "0",
+        "avatar": null,
+        "bot": false,
+        "public_flags": 0,
+    })
+}
+
+/// A guild member, with its user if set (the members of messages don't include it)
+fn member(user_id: Option<UserId>, joined_at: &str) -> Value {
+    let mut member = json!({
+        "nick": null,
+        "avatar": null,
+        "roles": [],
+        "joined_at": joined_at,
+        "premium_since": null,
+        "deaf": false,
+        "mute": false,
+        "flags": 0,
+        "pending": false,
+        "communication_disabled_until": null,
+    });
+    if let Some(user_id) = user_id {
+        member["user"] = user(user_id);
+    }
+    member
+}
+
+/// Applies a JSON merge patch (RFC 7396) to a value
+fn merge_patch(target: &mut Value, patch: Value, depth: usize) -> Result<(), crate::Error> {
+    if depth > MAX_OVERRIDE_DEPTH {
+        return Err(format!("Fixture overrides can be nested at most {MAX_OVERRIDE_DEPTH} levels deep").into());
+    }
+
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return Ok(());
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(fields) = target {
+        for (field, value) in patch {
+            if value.is_null() {
+                fields.remove(&field);
+            } else {
+                merge_patch(fields.entry(field).or_insert(Value::Null), value, depth + 1)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use stratum_common::pb;
+use tokio::sync::watch;
+use tokio_stream::StreamExt;
+use twilight_gateway::{Intents, Message, Shard, ShardId};
+
+use crate::{CONFIG, config::GatewayFallbackConfig, geese::stratum::Stratum, mesophyll::client::MesophyllClient, worker::workerthread::WorkerThread};
+
+/// How long a Stratum health check may take before it counts as failed
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where gateway events are currently received from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GatewayMode {
+    /// Through the Stratum proxy (the default)
+    Stratum,
+    /// Directly from the Discord gateway, while Stratum is unreachable
+    ///
+    /// Stratum's cache is bypassed in this mode, with all fetches going to the Discord API
+    Direct,
+}
+
+/// Health of the Stratum connection, as shown in health endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayStatus {
+    pub mode: GatewayMode,
+    /// Whether the direct gateway fallback is configured
+    pub fallback_enabled: bool,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// When the mode last changed
+    pub mode_since: chrono::DateTime<chrono::Utc>,
+}
+
+/// Tracks the health of the Stratum connection and whether to fall back to the direct gateway
+pub struct GatewayHealth {
+    direct: AtomicBool,
+    consecutive_failures: AtomicU32,
+    consecutive_successes: AtomicU32,
+    last_error: Mutex<Option<String>>,
+    mode_since: Mutex<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Default for GatewayHealth {
+    fn default() -> Self {
+        Self {
+            direct: AtomicBool::new(false),
+            consecutive_failures: AtomicU32::new(0),
+            consecutive_successes: AtomicU32::new(0),
+            last_error: Mutex::new(None),
+            mode_since: Mutex::new(chrono::Utc::now()),
+        }
+    }
+}
+
+impl GatewayHealth {
+    pub fn mode(&self) -> GatewayMode {
+        if self.direct.load(Ordering::Relaxed) {
+            GatewayMode::Direct
+        } else {
+            GatewayMode::Stratum
+        }
+    }
+
+    /// Returns whether the direct gateway fallback is active (and so Stratum should not be relied on)
+    pub fn is_direct(&self) -> bool {
+        self.mode() == GatewayMode::Direct
+    }
+
+    pub fn status(&self) -> GatewayStatus {
+        GatewayStatus {
+            mode: self.mode(),
+            fallback_enabled: CONFIG.gateway_fallback.is_some(),
+            consecutive_failures: self.consecutive_failures.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().clone(),
+            mode_since: *self.mode_since.lock(),
+        }
+    }
+
+    /// Records the result of a health check, returning the new mode if the thresholds of `cfg` were crossed
+    fn record(&self, result: Result<(), String>, cfg: &GatewayFallbackConfig) -> Option<GatewayMode> {
+        let new_mode = match result {
+            Ok(()) => {
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                let successes = self.consecutive_successes.fetch_add(1, Ordering::Relaxed) + 1;
+                (self.is_direct() && successes >= cfg.recovery_threshold).then_some(GatewayMode::Stratum)
+            }
+            Err(e) => {
+                *self.last_error.lock() = Some(e);
+                self.consecutive_successes.store(0, Ordering::Relaxed);
+                let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                (!self.is_direct() && failures >= cfg.failure_threshold).then_some(GatewayMode::Direct)
+            }
+        }?;
+
+        self.direct.store(new_mode == GatewayMode::Direct, Ordering::Relaxed);
+        *self.mode_since.lock() = chrono::Utc::now();
+        Some(new_mode)
+    }
+}
+
+/// Periodically health checks Stratum, switching the mode of its `GatewayHealth` when the thresholds of `cfg`
+/// are crossed. The returned receiver is notified of every mode change
+pub fn spawn_health_monitor(stratum: Stratum, cfg: &'static GatewayFallbackConfig) -> watch::Receiver<GatewayMode> {
+    let (tx, rx) = watch::channel(stratum.gateway_health().mode());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(cfg.check_interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let result = match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, stratum.get_config()).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err("health check timed out".to_string()),
+            };
+
+            if let Some(mode) = stratum.gateway_health().record(result, cfg) {
+                match mode {
+                    GatewayMode::Direct => log::error!("Stratum failed {} consecutive health checks, falling back to the direct gateway", cfg.failure_threshold),
+                    GatewayMode::Stratum => log::info!("Stratum recovered, switching back from the direct gateway"),
+                }
+                tx.send_replace(mode);
+            }
+        }
+    });
+    rx
+}
+
+/// Listens for discord events and pushes them to the worker thread, from Stratum or (if configured and Stratum
+/// is unreachable) directly from the Discord gateway
+pub async fn listen_discord_events(stratum: Stratum, wt: WorkerThread, mesophyll: Arc<MesophyllClient>, mut shutdown: watch::Receiver<bool>) {
+    let Some(cfg) = CONFIG.gateway_fallback.as_ref() else {
+        return stratum.listen_discord_events(wt, mesophyll, shutdown).await;
+    };
+
+    let mut mode_rx = spawn_health_monitor(stratum.clone(), cfg);
+    loop {
+        let mode = *mode_rx.borrow_and_update();
+        let (stratum, wt, mesophyll, shutdown_ref) = (stratum.clone(), wt.clone(), mesophyll.clone(), shutdown.clone());
+        let task = match mode {
+            GatewayMode::Stratum => tokio::spawn(async move {
+                stratum.listen_discord_events(wt, mesophyll, shutdown_ref).await
+            }),
+            GatewayMode::Direct => tokio::spawn(async move {
+                if let Err(e) = listen_direct(stratum, wt, mesophyll, cfg).await {
+                    log::error!("Direct gateway failed: {e}");
+                }
+            }),
+        };
+
+        tokio::select! {
+            res = mode_rx.changed() => {
+                task.abort();
+                if res.is_err() {
+                    return;
+                }
+            }
+            _ = shutdown.wait_for(|s| *s) => {
+                task.abort();
+                return;
+            }
+        }
+    }
+}
+
+/// Connects this worker's share of the shards directly to the Discord gateway
+///
+/// Shards are split between workers by `shard_id % num_workers`, events of tenants owned by another
+/// worker are forwarded as with Stratum
+async fn listen_direct(stratum: Stratum, wt: WorkerThread, mesophyll: Arc<MesophyllClient>, cfg: &'static GatewayFallbackConfig) -> Result<(), crate::Error> {
+    let num_workers = mesophyll.fetch_base_worker_info().await?.num_workers;
+    let intents = Intents::from_bits_truncate(cfg.intents);
+    let bot_id = stratum.current_user().id;
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for shard_id in (0..cfg.shard_count).filter(|s| s % num_workers == wt.id() as u32) {
+        let mut shard = Shard::new(ShardId::new(shard_id, cfg.shard_count), CONFIG.nirn_token.clone(), intents);
+        let (wt, mesophyll) = (wt.clone(), mesophyll.clone());
+        tasks.spawn(async move {
+            log::info!("[Worker {wid}] Connecting shard {shard_id} directly to the gateway", wid=wt.id());
+            while let Some(msg) = shard.next().await {
+                let text = match msg {
+                    Ok(Message::Text(text)) => text,
+                    Ok(Message::Close(_)) => continue,
+                    Err(e) => {
+                        log::error!("[Worker {wid}] Shard {shard_id} error: {e}", wid=wt.id());
+                        continue;
+                    }
+                };
+
+                let Some(evt) = parse_dispatch(&text) else {
+                    continue;
+                };
+                if let Err(e) = Stratum::discord_event_dispatch(&wt, &mesophyll, bot_id, evt) {
+                    log::error!("Error dispatching event: {:?}", e);
+                }
+            }
+        });
+    }
+
+    tasks.join_all().await;
+    Ok(())
+}
+
+/// Parses a raw gateway dispatch into the event format Stratum sends, returning None for other opcodes
+fn parse_dispatch(text: &str) -> Option<pb::DiscordEvent> {
+    let mut payload: Value = serde_json::from_str(text).ok()?;
+    if payload.get("op")?.as_u64()? != 0 {
+        return None;
+    }
+    let event_name = payload.get("t")?.as_str()?.to_string();
+    let data = payload.get_mut("d")?.take();
+
+    let snowflake = |v: Option<&Value>| v.and_then(|v| v.as_str()).and_then(|s| s.parse::<u64>().ok()).unwrap_or(0);
+    let guild_id = match event_name.as_str() {
+        "GUILD_CREATE" | "GUILD_UPDATE" | "GUILD_DELETE" => snowflake(data.get("id")),
+        _ => snowflake(data.get("guild_id")),
+    };
+    let target_user = match event_name.as_str() {
+        // Entitlements of user-installed apps have no guild_id
+        "ENTITLEMENT_CREATE" | "ENTITLEMENT_UPDATE" | "ENTITLEMENT_DELETE" => snowflake(data.get("user_id")),
+        _ => snowflake(data.get("user").and_then(|u| u.get("id"))),
+    };
+    let msg_author = match event_name.as_str() {
+        "MESSAGE_CREATE" | "MESSAGE_UPDATE" => snowflake(data.get("author").and_then(|a| a.get("id"))),
+        _ => 0,
+    };
+
+    Some(pb::DiscordEvent {
+        event_name,
+        guild_id,
+        target_user,
+        msg_author,
+        payload: data.to_string(),
+        ..Default::default()
+    })
+}
@@ -0,0 +1,196 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, UserId};
+use hmac::{Hmac, KeyInit, Mac};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::CONFIG;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Event dispatched to the guild of an inbound webhook when it is called with a valid signature
+///
+/// Must not start with `Web`, which users may dispatch through the public `DispatchEvent` syscalls
+pub const SIGNED_WEBHOOK_EVENT: &str = "SignedWebhook";
+
+/// Maximum size of the body of an inbound webhook
+pub const MAX_INBOUND_WEBHOOK_BODY_SIZE: usize = 64 * 1024; // 64kb
+
+/// Maximum number of inbound webhooks per guild
+pub const MAX_INBOUND_WEBHOOKS: i64 = 10;
+
+/// Maximum length of the name of an inbound webhook
+pub const MAX_INBOUND_WEBHOOK_NAME_LENGTH: usize = 64;
+
+/// How far the timestamp of a call may be from the current time, older calls are rejected as replays
+const TIMESTAMP_TOLERANCE_SECS: u64 = 5 * 60;
+
+/// An inbound webhook, letting external services trigger the templates of a guild
+///
+/// Calls are signed the same way as the execution webhooks sent by templates: the `X-AntiRaid-Signature`
+/// header is `sha256=` followed by the hex `HMAC-SHA256(secret, "{timestamp}.{body}")` where timestamp is
+/// the unix timestamp in the `X-AntiRaid-Timestamp` header
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct InboundWebhook {
+    pub id: String,
+    pub guild_id: String,
+    pub name: String,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl InboundWebhook {
+    /// Returns the URL external services should call
+    pub fn url(&self) -> String {
+        format!("{}/webhooks/inbound/{}", CONFIG.api, self.id)
+    }
+}
+
+/// Data of `SignedWebhook` events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedWebhook {
+    pub webhook_id: String,
+    /// The name of the webhook, for templates to tell webhooks apart
+    pub name: String,
+    /// The parsed JSON body of the call
+    pub body: serde_json::Value,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Verifies a call of an inbound webhook given its timestamp and signature headers
+pub fn verify(secret: &str, timestamp: &str, signature: &str, body: &[u8]) -> Result<(), &'static str> {
+    let timestamp: u64 = timestamp.parse().map_err(|_| "Invalid timestamp")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| "System clock is before the unix epoch")?.as_secs();
+    if now.abs_diff(timestamp) > TIMESTAMP_TOLERANCE_SECS {
+        return Err("Timestamp is too far from the current time");
+    }
+
+    let signature = hex::decode(signature.trim_start_matches("sha256=")).map_err(|_| "Invalid signature")?;
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.verify_slice(&signature).map_err(|_| "Invalid signature")
+}
+
+/// Stores the inbound webhooks of guilds
+#[derive(Clone)]
+pub struct InboundWebhookDb {
+    pool: sqlx::PgPool,
+}
+
+impl InboundWebhookDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Creates an inbound webhook, returning it along with its secret
+    ///
+    /// The secret is only ever returned here (and when rotated)
+    pub async fn create(&self, guild_id: GuildId, name: &str, created_by: Option<UserId>) -> Result<(InboundWebhook, String), crate::Error> {
+        if name.is_empty() || name.len() > MAX_INBOUND_WEBHOOK_NAME_LENGTH {
+            return Err(format!("Webhook name must be between 1 and {MAX_INBOUND_WEBHOOK_NAME_LENGTH} characters").into());
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        // Serializes creations per guild so the limit below holds
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('inbound_webhooks:' || $1))")
+            .bind(guild_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM inbound_webhooks WHERE guild_id = $1")
+            .bind(guild_id.to_string())
+            .fetch_one(&mut *tx)
+            .await?;
+        if count >= MAX_INBOUND_WEBHOOKS {
+            return Err(format!("A guild can have at most {MAX_INBOUND_WEBHOOKS} inbound webhooks").into());
+        }
+
+        let secret = Alphanumeric.sample_string(&mut rand::rng(), 64);
+        let webhook: Option<InboundWebhook> = sqlx::query_as(
+            "INSERT INTO inbound_webhooks (id, guild_id, name, secret, created_by) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (guild_id, name) DO NOTHING
+            RETURNING id, guild_id, name, created_by, created_at, last_used_at"
+        )
+        .bind(Alphanumeric.sample_string(&mut rand::rng(), 48))
+        .bind(guild_id.to_string())
+        .bind(name)
+        .bind(&secret)
+        .bind(created_by.map(|u| u.to_string()))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(webhook) = webhook else {
+            return Err("A webhook with this name already exists".into());
+        };
+
+        tx.commit().await?;
+        Ok((webhook, secret))
+    }
+
+    /// Lists the inbound webhooks of a guild
+    pub async fn list(&self, guild_id: GuildId) -> Result<Vec<InboundWebhook>, crate::Error> {
+        let webhooks = sqlx::query_as(
+            "SELECT id, guild_id, name, created_by, created_at, last_used_at FROM inbound_webhooks WHERE guild_id = $1 ORDER BY created_at"
+        )
+        .bind(guild_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(webhooks)
+    }
+
+    /// Deletes an inbound webhook of a guild, returning whether it existed
+    pub async fn delete(&self, guild_id: GuildId, id: &str) -> Result<bool, crate::Error> {
+        let res = sqlx::query("DELETE FROM inbound_webhooks WHERE guild_id = $1 AND id = $2")
+            .bind(guild_id.to_string())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Replaces the secret of an inbound webhook of a guild, returning the new secret if the webhook exists
+    pub async fn rotate_secret(&self, guild_id: GuildId, id: &str) -> Result<Option<String>, crate::Error> {
+        let secret = Alphanumeric.sample_string(&mut rand::rng(), 64);
+        let res = sqlx::query("UPDATE inbound_webhooks SET secret = $3 WHERE guild_id = $1 AND id = $2")
+            .bind(guild_id.to_string())
+            .bind(id)
+            .bind(&secret)
+            .execute(&self.pool)
+            .await?;
+        Ok((res.rows_affected() > 0).then_some(secret))
+    }
+
+    /// Returns an inbound webhook along with its secret
+    pub async fn get(&self, id: &str) -> Result<Option<(InboundWebhook, String)>, crate::Error> {
+        #[derive(sqlx::FromRow)]
+        struct Row {
+            #[sqlx(flatten)]
+            webhook: InboundWebhook,
+            secret: String,
+        }
+
+        let row: Option<Row> = sqlx::query_as(
+            "SELECT id, guild_id, name, secret, created_by, created_at, last_used_at FROM inbound_webhooks WHERE id = $1"
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| (r.webhook, r.secret)))
+    }
+
+    /// Records a verified call of an inbound webhook
+    pub async fn mark_used(&self, id: &str) -> Result<(), crate::Error> {
+        sqlx::query("UPDATE inbound_webhooks SET last_used_at = NOW() WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
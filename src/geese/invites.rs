@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, UserId};
+use serde::{Deserialize, Serialize};
+
+/// How a member joined a guild
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinSource {
+    Invite,
+    /// The vanity URL of the guild
+    Vanity,
+    /// The invite could not be determined, e.g. as several were used at once or the bot lacks Manage Server
+    Unknown,
+}
+
+impl JoinSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Invite => "invite",
+            Self::Vanity => "vanity",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, crate::Error> {
+        match s {
+            "invite" => Ok(Self::Invite),
+            "vanity" => Ok(Self::Vanity),
+            "unknown" => Ok(Self::Unknown),
+            _ => Err(format!("Unknown join source: {s}").into()),
+        }
+    }
+}
+
+/// The invite a member joined a guild through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteAttribution {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    pub source: JoinSource,
+    /// The invite (or vanity) code, unset if unknown
+    pub code: Option<String>,
+    /// The user who created the invite, if known
+    pub inviter_id: Option<UserId>,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct InviteJoinRow {
+    guild_id: String,
+    user_id: String,
+    source: String,
+    code: Option<String>,
+    inviter_id: Option<String>,
+    joined_at: DateTime<Utc>,
+}
+
+impl TryFrom<InviteJoinRow> for InviteAttribution {
+    type Error = crate::Error;
+
+    fn try_from(row: InviteJoinRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            guild_id: row.guild_id.parse()?,
+            user_id: row.user_id.parse()?,
+            source: JoinSource::parse(&row.source)?,
+            code: row.code,
+            inviter_id: row.inviter_id.map(|u| u.parse()).transpose()?,
+            joined_at: row.joined_at,
+        })
+    }
+}
+
+/// Number of joins attributed to an invite
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct InviteStats {
+    pub source: String,
+    pub code: Option<String>,
+    pub inviter_id: Option<String>,
+    pub joins: i64,
+    pub last_join_at: DateTime<Utc>,
+}
+
+/// An invite tracking request from a worker to the master
+#[derive(Debug, Serialize, Deserialize)]
+pub enum InviteReq {
+    /// Records the invite a member joined through, replacing any earlier attribution of the member
+    RecordJoin { attribution: InviteAttribution },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum InviteResp {
+    Ack,
+}
+
+const INVITE_JOIN_COLUMNS: &str = "guild_id, user_id, source, code, inviter_id, joined_at";
+
+/// Storage of the invites members joined guilds through
+///
+/// Joins are attributed by the worker of the guild (see `worker::invites`) and recorded by the master, the API
+/// can query them
+#[derive(Clone)]
+pub struct InviteDb {
+    pool: sqlx::PgPool,
+}
+
+impl InviteDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Handles an invite tracking request from a worker
+    pub async fn handle(&self, req: InviteReq) -> Result<InviteResp, crate::Error> {
+        match req {
+            InviteReq::RecordJoin { attribution } => {
+                sqlx::query(
+                    "INSERT INTO invite_joins (guild_id, user_id, source, code, inviter_id, joined_at) VALUES ($1, $2, $3, $4, $5, $6)
+                    ON CONFLICT (guild_id, user_id) DO UPDATE SET source = EXCLUDED.source, code = EXCLUDED.code,
+                    inviter_id = EXCLUDED.inviter_id, joined_at = EXCLUDED.joined_at"
+                )
+                .bind(attribution.guild_id.to_string())
+                .bind(attribution.user_id.to_string())
+                .bind(attribution.source.as_str())
+                .bind(attribution.code)
+                .bind(attribution.inviter_id.map(|u| u.to_string()))
+                .bind(attribution.joined_at)
+                .execute(&self.pool)
+                .await?;
+                Ok(InviteResp::Ack)
+            }
+        }
+    }
+
+    /// Lists the most recent joins of a guild, optionally only those of a member or through an invite
+    pub async fn list(&self, guild_id: GuildId, user_id: Option<UserId>, code: Option<&str>, limit: i64) -> Result<Vec<InviteAttribution>, crate::Error> {
+        let rows: Vec<InviteJoinRow> = sqlx::query_as(&format!(
+            "SELECT {INVITE_JOIN_COLUMNS} FROM invite_joins WHERE guild_id = $1 AND ($2::TEXT IS NULL OR user_id = $2) AND ($3::TEXT IS NULL OR code = $3)
+            ORDER BY joined_at DESC LIMIT $4"
+        ))
+        .bind(guild_id.to_string())
+        .bind(user_id.map(|u| u.to_string()))
+        .bind(code)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(InviteAttribution::try_from).collect()
+    }
+
+    /// Returns the number of joins of a guild per invite, most joins first
+    pub async fn stats(&self, guild_id: GuildId, limit: i64) -> Result<Vec<InviteStats>, crate::Error> {
+        let stats = sqlx::query_as(
+            "SELECT source, code, (array_agg(inviter_id ORDER BY joined_at DESC))[1] AS inviter_id, COUNT(*) AS joins, MAX(joined_at) AS last_join_at
+            FROM invite_joins WHERE guild_id = $1
+            GROUP BY source, code ORDER BY joins DESC LIMIT $2"
+        )
+        .bind(guild_id.to_string())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(stats)
+    }
+}
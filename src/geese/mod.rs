@@ -4,4 +4,43 @@ pub mod state;
 pub mod urlsign;
 pub mod feedticket;
 pub mod ratelimit;
-pub mod feed;
\ No newline at end of file
+pub mod feed;
+pub mod telemetry;
+pub mod featureflags;
+pub mod usage;
+pub mod gateway;
+pub mod datalifecycle;
+pub mod modmail;
+pub mod entitlements;
+pub mod pluginusage;
+pub mod templatecache;
+pub mod outbox;
+pub mod modimport;
+pub mod votes;
+pub mod inboundwebhooks;
+pub mod sharedcache;
+pub mod dbrouter;
+pub mod dbpools;
+pub mod workflows;
+pub mod appeals;
+pub mod watchlist;
+pub mod invites;
+pub mod namepolicy;
+pub mod stickyroles;
+pub mod permsnapshots;
+pub mod nukeprotection;
+pub mod webhookspam;
+pub mod schedules;
+pub mod autopublish;
+pub mod threadpolicies;
+pub mod emojiusage;
+pub mod activity;
+pub mod auditcorrelation;
+pub mod voiceidle;
+pub mod resultcache;
+pub mod archival;
+pub mod settingssync;
+pub mod rules;
+pub mod modargs;
+pub mod fixtures;
+pub mod botstatus;
@@ -0,0 +1,176 @@
+use dapi::UserId;
+use serde_json::Value;
+
+/// Maximum length of the arguments of a moderation command
+pub const MAX_MOD_ARGS_LENGTH: usize = 4000;
+
+/// Words marking a punishment as permanent in place of a duration
+const PERMANENT: [&str; 3] = ["perm", "permanent", "forever"];
+
+/// The member a moderation command targets, as written by the moderator
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// A mention (`<@id>` or `<@!id>`) or raw user ID
+    Id(UserId),
+    /// A username, display name or nickname, optionally followed by the legacy `#discriminator`
+    Name {
+        name: String,
+        discriminator: Option<String>,
+    },
+}
+
+/// The arguments of a moderation command such as `@user 2h spamming links`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModArgs {
+    pub target: Target,
+    /// Sum of all durations given (`1h 30m`, `1d12h` and `2 hours` are all accepted), None if none was given
+    pub duration_secs: Option<u64>,
+    /// Whether the moderator wrote `perm`, `permanent` or `forever` in place of a duration
+    pub permanent: bool,
+    pub reason: Option<String>,
+}
+
+/// Parses the arguments of a moderation command: a target, then an optional duration, then an optional reason
+///
+/// Names containing spaces must be quoted (`"some name" 1d`)
+pub fn parse(input: &str) -> Result<ModArgs, crate::Error> {
+    if input.len() > MAX_MOD_ARGS_LENGTH {
+        return Err(format!("Moderation arguments must be at most {MAX_MOD_ARGS_LENGTH} bytes").into());
+    }
+
+    let input = input.trim_start();
+    let (target, mut rest) = if let Some(quoted) = input.strip_prefix('"') {
+        let end = quoted.find('"').ok_or("Unterminated quote in target")?;
+        (&quoted[..end], &quoted[end + 1..])
+    } else {
+        next_token(input).ok_or("No target given")?
+    };
+    let target = parse_target(target)?;
+
+    let mut duration_secs: Option<u64> = None;
+    let mut permanent = false;
+    while let Some((token, after)) = next_token(rest) {
+        if duration_secs.is_none() && PERMANENT.iter().any(|p| token.eq_ignore_ascii_case(p)) {
+            permanent = true;
+            rest = after;
+            break;
+        }
+
+        let (secs, after) = if let Some(secs) = compound_duration(token)? {
+            (secs, after)
+        } else if let Ok(n) = token.parse::<u64>()
+            && let Some((unit, after)) = next_token(after)
+            && let Some(unit) = unit_secs(unit)
+        {
+            (n.checked_mul(unit).ok_or("Duration is too long")?, after)
+        } else {
+            break;
+        };
+
+        duration_secs = Some(duration_secs.unwrap_or(0).checked_add(secs).ok_or("Duration is too long")?);
+        rest = after;
+    }
+
+    if duration_secs == Some(0) {
+        return Err("Duration must be at least 1 second".into());
+    }
+
+    let reason = rest.trim();
+    Ok(ModArgs {
+        target,
+        duration_secs,
+        permanent,
+        reason: (!reason.is_empty()).then(|| reason.to_string()),
+    })
+}
+
+/// Returns whether a guild member (as returned by Discord) goes by `name`, and has `discriminator` if set
+pub fn member_matches(member: &Value, name: &str, discriminator: Option<&str>) -> bool {
+    let user = &member["user"];
+    if let Some(discriminator) = discriminator {
+        return user["discriminator"].as_str() == Some(discriminator)
+            && user["username"].as_str().is_some_and(|u| u.eq_ignore_ascii_case(name));
+    }
+
+    [&user["username"], &user["global_name"], &member["nick"]]
+        .into_iter()
+        .any(|n| n.as_str().is_some_and(|n| n.eq_ignore_ascii_case(name)))
+}
+
+/// Splits off the next whitespace separated token, returning it and the rest of the input
+fn next_token(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim_start();
+    if input.is_empty() {
+        return None;
+    }
+    let end = input.find(char::is_whitespace).unwrap_or(input.len());
+    Some((&input[..end], &input[end..]))
+}
+
+fn parse_target(token: &str) -> Result<Target, crate::Error> {
+    if let Some(id) = token.strip_prefix("<@").and_then(|t| t.strip_suffix('>')) {
+        let id = id.strip_prefix('!').unwrap_or(id);
+        return Ok(Target::Id(parse_user_id(id).ok_or_else(|| format!("Invalid mention {token}"))?));
+    }
+
+    if let Some(id) = parse_user_id(token) {
+        return Ok(Target::Id(id));
+    }
+
+    let token = token.strip_prefix('@').unwrap_or(token);
+    if token.is_empty() {
+        return Err("No target given".into());
+    }
+    if let Some((name, discriminator)) = token.rsplit_once('#')
+        && !name.is_empty()
+        && discriminator.len() == 4
+        && discriminator.bytes().all(|b| b.is_ascii_digit())
+    {
+        return Ok(Target::Name { name: name.to_string(), discriminator: Some(discriminator.to_string()) });
+    }
+    Ok(Target::Name { name: token.to_string(), discriminator: None })
+}
+
+/// Parses a snowflake, which is 17 to 20 digits long
+fn parse_user_id(s: &str) -> Option<UserId> {
+    if !(17..=20).contains(&s.len()) || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse::<u64>().ok().filter(|id| *id != 0).map(UserId::new)
+}
+
+/// Parses a duration written without spaces such as `2h` or `1d12h`, None if the token is not one
+fn compound_duration(token: &str) -> Result<Option<u64>, crate::Error> {
+    let mut total: u64 = 0;
+    let mut rest = token;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return Ok(None);
+        }
+        let letters = rest[digits..].find(|c: char| !c.is_ascii_alphabetic()).map_or(rest.len(), |i| digits + i);
+        let Some(unit) = unit_secs(&rest[digits..letters]) else {
+            return Ok(None);
+        };
+        let Ok(n) = rest[..digits].parse::<u64>() else {
+            return Err("Duration is too long".into());
+        };
+        total = n.checked_mul(unit).and_then(|secs| total.checked_add(secs)).ok_or("Duration is too long")?;
+        rest = &rest[letters..];
+    }
+    Ok((!token.is_empty()).then_some(total))
+}
+
+/// Returns the number of seconds in a duration unit
+fn unit_secs(unit: &str) -> Option<u64> {
+    Some(match unit.to_ascii_lowercase().as_str() {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 24 * 60 * 60,
+        "w" | "wk" | "wks" | "week" | "weeks" => 7 * 24 * 60 * 60,
+        "mo" | "month" | "months" => 30 * 24 * 60 * 60,
+        "y" | "yr" | "yrs" | "year" | "years" => 365 * 24 * 60 * 60,
+        _ => return None,
+    })
+}
@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, TimeZone, Utc};
+use dapi::UserId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Maximum size of an export that can be imported
+pub const MAX_IMPORT_BYTES: usize = 8 * 1024 * 1024;
+
+/// Maximum number of cases in an export that can be imported
+pub const MAX_IMPORT_RECORDS: usize = 50_000;
+
+/// Maximum number of skipped cases (and sample stings) returned in a preview
+const MAX_PREVIEW_ENTRIES: usize = 100;
+
+/// Maximum length of the reason of an imported sting, longer reasons are truncated
+const MAX_REASON_LENGTH: usize = 512;
+
+/// Format of a moderation export of another bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// Dyno case export, a JSON array of cases
+    Dyno,
+    /// Carl-bot modlog export, a CSV file with a header row
+    Carlbot,
+    /// Wick-style case export, a JSON object with a `cases` array (or the array itself)
+    Wick,
+}
+
+impl ImportFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Dyno => "dyno",
+            Self::Carlbot => "carlbot",
+            Self::Wick => "wick",
+        }
+    }
+
+    /// Field names (in order of preference) of the case id, action, user, moderator, reason and timestamp of
+    /// a case. Nested fields are separated by dots
+    fn fields(self) -> [&'static [&'static str]; 6] {
+        match self {
+            Self::Dyno => [
+                &["caseNum", "case", "id"],
+                &["type", "action"],
+                &["user.id", "userId", "user"],
+                &["mod.id", "moderator.id", "modId", "mod"],
+                &["reason"],
+                &["createdAt", "date", "timestamp"],
+            ],
+            Self::Carlbot => [
+                &["case_id", "case", "id"],
+                &["action", "type"],
+                &["user_id", "target_id", "user"],
+                &["moderator_id", "mod_id", "moderator"],
+                &["reason"],
+                &["timestamp", "created_at", "date"],
+            ],
+            Self::Wick => [
+                &["id", "caseId", "case"],
+                &["action", "type"],
+                &["target.id", "target", "user.id", "user"],
+                &["moderator.id", "moderator", "executor.id", "executor"],
+                &["reason"],
+                &["timestamp", "createdAt", "date"],
+            ],
+        }
+    }
+}
+
+/// Moderation action of an imported case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Warn,
+    Mute,
+    Kick,
+    Ban,
+}
+
+impl ImportAction {
+    /// Parses the action of a case, returning `None` for actions that are not punishments (unbans, notes etc.)
+    fn parse(action: &str) -> Option<Self> {
+        match action.trim().to_lowercase().replace([' ', '_', '-'], "").as_str() {
+            "warn" | "warning" | "strike" => Some(Self::Warn),
+            "mute" | "tempmute" | "timeout" | "voicemute" => Some(Self::Mute),
+            "kick" | "softban" => Some(Self::Kick),
+            "ban" | "tempban" | "hackban" | "forceban" | "massban" => Some(Self::Ban),
+            _ => None,
+        }
+    }
+
+    /// Number of stings the action is imported as
+    fn stings(self) -> u32 {
+        match self {
+            Self::Warn => 1,
+            Self::Mute | Self::Kick => 2,
+            Self::Ban => 3,
+        }
+    }
+}
+
+/// Options for mapping cases to stings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportOptions {
+    /// How long after the original case an imported sting expires
+    #[serde(default = "ImportOptions::default_expiry_days")]
+    pub expiry_days: u32,
+}
+
+impl ImportOptions {
+    fn default_expiry_days() -> u32 {
+        90
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self { expiry_days: Self::default_expiry_days() }
+    }
+}
+
+/// A case of another bot mapped to a sting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedSting {
+    /// Provenance tag of the sting (`import:<format>:<case id>`), also used as its sting id so importing the
+    /// same export twice does not duplicate stings
+    pub source: String,
+    pub action: ImportAction,
+    pub userid: UserId,
+    pub modid: Option<UserId>,
+    pub stings: u32,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A case that could not be imported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportSkipped {
+    /// Index of the case in the export (for CSV exports, the line number)
+    pub record: usize,
+    pub reason: String,
+}
+
+/// A parsed export
+#[derive(Debug, Clone)]
+pub struct ImportParse {
+    pub format: ImportFormat,
+    /// Total number of cases in the export
+    pub total: usize,
+    pub stings: Vec<ImportedSting>,
+    pub skipped: Vec<ImportSkipped>,
+    /// Cases whose sting would have already expired
+    pub expired: usize,
+}
+
+/// What importing an export would do, without importing anything
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportPreview {
+    pub format: ImportFormat,
+    pub total: usize,
+    /// Number of stings that would be imported
+    pub importable: usize,
+    pub expired: usize,
+    pub by_action: BTreeMap<ImportAction, usize>,
+    /// Number of cases that cannot be imported, the first of which are listed in `skipped`
+    pub skipped_count: usize,
+    pub skipped: Vec<ImportSkipped>,
+    /// The first stings that would be imported
+    pub sample: Vec<ImportedSting>,
+}
+
+impl ImportParse {
+    pub fn preview(&self) -> ImportPreview {
+        let mut by_action = BTreeMap::new();
+        for sting in self.stings.iter() {
+            *by_action.entry(sting.action).or_default() += 1;
+        }
+
+        ImportPreview {
+            format: self.format,
+            total: self.total,
+            importable: self.stings.len(),
+            expired: self.expired,
+            by_action,
+            skipped_count: self.skipped.len(),
+            skipped: self.skipped.iter().take(MAX_PREVIEW_ENTRIES).cloned().collect(),
+            sample: self.stings.iter().take(MAX_PREVIEW_ENTRIES).cloned().collect(),
+        }
+    }
+}
+
+/// Parses a moderation export of another bot, mapping its punishments to stings
+///
+/// Cases that are not punishments, are missing a user or whose sting would have already expired are skipped.
+/// Errors only if the export as a whole is malformed
+pub fn parse(format: ImportFormat, content: &str, opts: &ImportOptions) -> Result<ImportParse, crate::Error> {
+    if content.len() > MAX_IMPORT_BYTES {
+        return Err(format!("Export is too large, the maximum size is {MAX_IMPORT_BYTES} bytes").into());
+    }
+
+    let records = match format {
+        ImportFormat::Dyno | ImportFormat::Wick => json_records(content)?,
+        ImportFormat::Carlbot => csv_records(content)?,
+    };
+    if records.len() > MAX_IMPORT_RECORDS {
+        return Err(format!("Export has too many cases, the maximum is {MAX_IMPORT_RECORDS}").into());
+    }
+
+    let now = Utc::now();
+    let expiry = chrono::Duration::days(opts.expiry_days.into());
+    let mut res = ImportParse { format, total: records.len(), stings: Vec::new(), skipped: Vec::new(), expired: 0 };
+    for (record, value) in records {
+        match map_record(format, &value, expiry) {
+            Ok(Some(sting)) if sting.expires_at <= now => res.expired += 1,
+            Ok(Some(sting)) => res.stings.push(sting),
+            Ok(None) => {}
+            Err(reason) => res.skipped.push(ImportSkipped { record, reason }),
+        }
+    }
+
+    // Case ids must be unique for the provenance tags to be
+    let mut seen = std::collections::HashSet::new();
+    res.stings.retain(|s| seen.insert(s.source.clone()));
+
+    Ok(res)
+}
+
+/// Maps a case to a sting, returning `None` if the case is not a punishment
+fn map_record(format: ImportFormat, value: &Value, expiry: chrono::Duration) -> Result<Option<ImportedSting>, String> {
+    let [case_f, action_f, user_f, mod_f, reason_f, time_f] = format.fields();
+
+    let action = field_str(value, action_f).ok_or("Case has no action")?;
+    let Some(action) = ImportAction::parse(&action) else {
+        return Ok(None);
+    };
+    let case = field_str(value, case_f).filter(|c| !c.is_empty()).ok_or("Case has no case id")?;
+    let userid = field_str(value, user_f)
+        .and_then(|u| u.parse::<UserId>().ok())
+        .ok_or("Case has no valid user id")?;
+    let modid = field_str(value, mod_f).and_then(|m| m.parse::<UserId>().ok());
+    let created_at = match field(value, time_f) {
+        Some(t) => parse_time(t).ok_or("Case has an invalid timestamp")?,
+        None => return Err("Case has no timestamp".to_string()),
+    };
+
+    let mut reason = field_str(value, reason_f).unwrap_or_default();
+    if let Some((i, _)) = reason.char_indices().nth(MAX_REASON_LENGTH) {
+        reason.truncate(i);
+    }
+
+    Ok(Some(ImportedSting {
+        source: format!("import:{}:{case}", format.name()),
+        action,
+        userid,
+        modid,
+        stings: action.stings(),
+        reason,
+        created_at,
+        expires_at: created_at + expiry,
+    }))
+}
+
+/// Returns the first of `names` present (and not null) in a case
+fn field<'a>(value: &'a Value, names: &[&str]) -> Option<&'a Value> {
+    names.iter().find_map(|name| {
+        let v = name.split('.').try_fold(value, |v, part| v.get(part))?;
+        (!v.is_null()).then_some(v)
+    })
+}
+
+/// Same as `field` but stringifies numbers, skipping fields that are objects or arrays
+fn field_str(value: &Value, names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| match field(value, &[name])? {
+        Value::String(s) => Some(s.trim().to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+/// Parses a RFC 3339 timestamp or a unix timestamp in seconds or milliseconds
+fn parse_time(value: &Value) -> Option<DateTime<Utc>> {
+    let unix = |n: i64| {
+        // Timestamps past 2286 in seconds are assumed to be milliseconds
+        if n.abs() >= 10_000_000_000 { Utc.timestamp_millis_opt(n).single() } else { Utc.timestamp_opt(n, 0).single() }
+    };
+
+    match value {
+        Value::Number(n) => unix(n.as_i64().or_else(|| n.as_f64().map(|f| f as i64))?),
+        Value::String(s) => {
+            let s = s.trim();
+            if let Ok(n) = s.parse::<i64>() {
+                return unix(n);
+            }
+            DateTime::parse_from_rfc3339(s)
+                .map(|t| t.with_timezone(&Utc))
+                .or_else(|_| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").map(|t| t.and_utc()))
+                .ok()
+        }
+        _ => None,
+    }
+}
+
+/// Returns the cases of a JSON export along with their index
+fn json_records(content: &str) -> Result<Vec<(usize, Value)>, crate::Error> {
+    let value: Value = serde_json::from_str(content).map_err(|e| format!("Export is not valid JSON: {e}"))?;
+    let cases = match value {
+        Value::Array(cases) => cases,
+        Value::Object(mut obj) => match obj.remove("cases").or_else(|| obj.remove("data")) {
+            Some(Value::Array(cases)) => cases,
+            _ => return Err("Export has no array of cases".into()),
+        },
+        _ => return Err("Export has no array of cases".into()),
+    };
+    Ok(cases.into_iter().enumerate().collect())
+}
+
+/// Returns the rows of a CSV export (keyed by the header row) along with their line number
+fn csv_records(content: &str) -> Result<Vec<(usize, Value)>, crate::Error> {
+    let mut rows = csv_rows(content.trim_start_matches('\u{feff}'))?.into_iter();
+    let Some((_, header)) = rows.next() else {
+        return Err("Export is empty".into());
+    };
+    let header = header.into_iter().map(|h| h.trim().to_lowercase()).collect::<Vec<_>>();
+
+    Ok(rows
+        .filter(|(_, row)| row.iter().any(|c| !c.is_empty()))
+        .map(|(line, row)| {
+            let obj = header.iter().cloned().zip(row.into_iter().map(Value::String)).collect();
+            (line, Value::Object(obj))
+        })
+        .collect())
+}
+
+/// Splits CSV (RFC 4180, quoted fields may contain commas, newlines and doubled quotes) into rows along
+/// with the line each row starts on
+fn csv_rows(content: &str) -> Result<Vec<(usize, Vec<String>)>, crate::Error> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let (mut line, mut row_line) = (1, 1);
+    let mut quoted = false;
+
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    cell.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut cell)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut cell));
+                rows.push((row_line, std::mem::take(&mut row)));
+                line += 1;
+                row_line = line;
+            }
+            '\n' => {
+                cell.push(c);
+                line += 1;
+            }
+            _ => cell.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(format!("Export has an unterminated quoted field starting on line {row_line}").into());
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push((row_line, row));
+    }
+    Ok(rows)
+}
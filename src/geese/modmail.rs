@@ -0,0 +1,281 @@
+use chrono::{DateTime, Utc};
+use dapi::{ChannelId, GuildId, UserId};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Key-value scope modmail transcripts are stored under (as blobs), downloadable through presigned URLs
+pub const MODMAIL_SCOPE: &str = "#modmail";
+
+/// Who sent a relayed modmail message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayDirection {
+    /// Sent by the user in DMs and relayed to the thread
+    User,
+    /// Sent by staff in the thread and relayed to the user
+    Staff,
+}
+
+impl RelayDirection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Staff => "staff",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, crate::Error> {
+        match s {
+            "user" => Ok(Self::User),
+            "staff" => Ok(Self::Staff),
+            _ => Err(format!("Unknown relay direction: {s}").into()),
+        }
+    }
+}
+
+/// A modmail conversation between a user and the staff of a guild
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModmailSession {
+    pub id: Uuid,
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    /// The thread the conversation is relayed to
+    pub thread_id: ChannelId,
+    pub open: bool,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub closed_by: Option<UserId>,
+    /// Key of the transcript in the modmail scope, set once the session is closed
+    pub transcript_key: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct ModmailSessionRow {
+    id: Uuid,
+    guild_id: String,
+    user_id: String,
+    thread_id: String,
+    state: String,
+    opened_at: DateTime<Utc>,
+    closed_at: Option<DateTime<Utc>>,
+    closed_by: Option<String>,
+    transcript_key: Option<String>,
+}
+
+impl TryFrom<ModmailSessionRow> for ModmailSession {
+    type Error = crate::Error;
+
+    fn try_from(row: ModmailSessionRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            guild_id: row.guild_id.parse()?,
+            user_id: row.user_id.parse()?,
+            thread_id: row.thread_id.parse()?,
+            open: row.state == "open",
+            opened_at: row.opened_at,
+            closed_at: row.closed_at,
+            closed_by: row.closed_by.map(|u| u.parse()).transpose()?,
+            transcript_key: row.transcript_key,
+        })
+    }
+}
+
+/// A message relayed in a modmail session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModmailMessage {
+    pub direction: RelayDirection,
+    pub author_id: UserId,
+    pub content: String,
+    /// File names of the relayed attachments
+    pub attachments: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct ModmailMessageRow {
+    direction: String,
+    author_id: String,
+    content: String,
+    attachments: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<ModmailMessageRow> for ModmailMessage {
+    type Error = crate::Error;
+
+    fn try_from(row: ModmailMessageRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            direction: RelayDirection::parse(&row.direction)?,
+            author_id: row.author_id.parse()?,
+            content: row.content,
+            attachments: row.attachments,
+            created_at: row.created_at,
+        })
+    }
+}
+
+/// A modmail request from a worker to the master
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ModmailReq {
+    /// Finds where the DMs of a user should be relayed to
+    ///
+    /// `guild_hint` is the guild the user asked for, if any
+    Route { user_id: UserId, guild_hint: Option<GuildId> },
+    /// Records a new session whose thread has been created
+    Open { guild_id: GuildId, user_id: UserId, thread_id: ChannelId },
+    /// Returns the open session of a thread, if any
+    SessionForThread { guild_id: GuildId, thread_id: ChannelId },
+    /// Records a relayed message
+    Record { session_id: Uuid, direction: RelayDirection, author_id: UserId, content: String, attachments: Vec<String> },
+    /// Closes a session, returning its messages for the transcript
+    Close { session_id: Uuid, closed_by: UserId },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ModmailResp {
+    /// The user has an open session
+    Routed { session: ModmailSession },
+    /// The user has no open session, but one can be opened in the given guild
+    NeedsSession { guild_id: GuildId, channel_id: ChannelId },
+    /// The DMs of the user cannot be relayed anywhere
+    Unrouted,
+    Session { session: Option<ModmailSession> },
+    Closed { session: ModmailSession, messages: Vec<ModmailMessage> },
+    Ack,
+}
+
+const SESSION_COLUMNS: &str = "id, guild_id, user_id, thread_id, state, opened_at, closed_at, closed_by, transcript_key";
+
+/// Returns the key a transcript of a session is stored under in the modmail scope
+pub fn transcript_key(session_id: Uuid) -> String {
+    format!("transcripts/{session_id}.txt")
+}
+
+#[derive(Clone)]
+/// Storage of modmail sessions and their messages
+pub struct ModmailDb {
+    pool: sqlx::PgPool,
+}
+
+impl ModmailDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Handles a modmail request from a worker
+    pub async fn handle(&self, req: ModmailReq) -> Result<ModmailResp, crate::Error> {
+        match req {
+            ModmailReq::Route { user_id, guild_hint } => self.route(user_id, guild_hint).await,
+            ModmailReq::Open { guild_id, user_id, thread_id } => {
+                Ok(ModmailResp::Routed { session: self.open(guild_id, user_id, thread_id).await? })
+            }
+            ModmailReq::SessionForThread { guild_id, thread_id } => {
+                let row: Option<ModmailSessionRow> = sqlx::query_as(&format!(
+                    "SELECT {SESSION_COLUMNS} FROM modmail_sessions WHERE guild_id = $1 AND thread_id = $2 AND state = 'open'"
+                ))
+                .bind(guild_id.to_string())
+                .bind(thread_id.to_string())
+                .fetch_optional(&self.pool)
+                .await?;
+                Ok(ModmailResp::Session { session: row.map(ModmailSession::try_from).transpose()? })
+            }
+            ModmailReq::Record { session_id, direction, author_id, content, attachments } => {
+                sqlx::query("INSERT INTO modmail_messages (session_id, direction, author_id, content, attachments) VALUES ($1, $2, $3, $4, $5)")
+                    .bind(session_id)
+                    .bind(direction.as_str())
+                    .bind(author_id.to_string())
+                    .bind(content)
+                    .bind(attachments)
+                    .execute(&self.pool)
+                    .await?;
+                Ok(ModmailResp::Ack)
+            }
+            ModmailReq::Close { session_id, closed_by } => self.close(session_id, closed_by).await,
+        }
+    }
+
+    async fn route(&self, user_id: UserId, guild_hint: Option<GuildId>) -> Result<ModmailResp, crate::Error> {
+        let open: Option<ModmailSessionRow> = sqlx::query_as(&format!(
+            "SELECT {SESSION_COLUMNS} FROM modmail_sessions WHERE user_id = $1 AND state = 'open'"
+        ))
+        .bind(user_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(row) = open {
+            return Ok(ModmailResp::Routed { session: row.try_into()? });
+        }
+
+        // Fall back to the guild of the users last session if they did not ask for one
+        let guild_id = match guild_hint {
+            Some(guild_id) => Some(guild_id.to_string()),
+            None => sqlx::query_scalar("SELECT guild_id FROM modmail_sessions WHERE user_id = $1 ORDER BY opened_at DESC LIMIT 1")
+                .bind(user_id.to_string())
+                .fetch_optional(&self.pool)
+                .await?,
+        };
+        let Some(guild_id) = guild_id else {
+            return Ok(ModmailResp::Unrouted);
+        };
+
+        let channel_id: Option<Option<String>> = sqlx::query_scalar("SELECT settings->>'modmail_channel_id' FROM tenant_state WHERE owner_id = $1 AND owner_type = 'guild'")
+            .bind(&guild_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        match channel_id.flatten() {
+            Some(channel_id) => Ok(ModmailResp::NeedsSession { guild_id: guild_id.parse()?, channel_id: channel_id.parse()? }),
+            None => Ok(ModmailResp::Unrouted),
+        }
+    }
+
+    /// Records a new session, returning the already open session of the user if there is one
+    async fn open(&self, guild_id: GuildId, user_id: UserId, thread_id: ChannelId) -> Result<ModmailSession, crate::Error> {
+        let inserted: Option<ModmailSessionRow> = sqlx::query_as(&format!(
+            "INSERT INTO modmail_sessions (id, guild_id, user_id, thread_id) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id) WHERE state = 'open' DO NOTHING
+            RETURNING {SESSION_COLUMNS}"
+        ))
+        .bind(Uuid::now_v7())
+        .bind(guild_id.to_string())
+        .bind(user_id.to_string())
+        .bind(thread_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let row = match inserted {
+            Some(row) => row,
+            None => sqlx::query_as(&format!("SELECT {SESSION_COLUMNS} FROM modmail_sessions WHERE user_id = $1 AND state = 'open'"))
+                .bind(user_id.to_string())
+                .fetch_one(&self.pool)
+                .await?,
+        };
+        row.try_into()
+    }
+
+    async fn close(&self, session_id: Uuid, closed_by: UserId) -> Result<ModmailResp, crate::Error> {
+        let row: Option<ModmailSessionRow> = sqlx::query_as(&format!(
+            "UPDATE modmail_sessions SET state = 'closed', closed_at = NOW(), closed_by = $2, transcript_key = $3
+            WHERE id = $1 AND state = 'open'
+            RETURNING {SESSION_COLUMNS}"
+        ))
+        .bind(session_id)
+        .bind(closed_by.to_string())
+        .bind(transcript_key(session_id))
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Err("Modmail session is not open".into());
+        };
+
+        let messages: Vec<ModmailMessageRow> = sqlx::query_as(
+            "SELECT direction, author_id, content, attachments, created_at FROM modmail_messages WHERE session_id = $1 ORDER BY created_at, id"
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ModmailResp::Closed {
+            session: row.try_into()?,
+            messages: messages.into_iter().map(ModmailMessage::try_from).collect::<Result<_, _>>()?,
+        })
+    }
+}
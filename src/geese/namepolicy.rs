@@ -0,0 +1,163 @@
+use dapi::ChannelId;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of rules of a name policy
+pub const MAX_NAME_RULES: usize = 25;
+
+/// Maximum number of patterns of a blocklist rule
+pub const MAX_NAME_PATTERNS: usize = 50;
+
+/// Maximum length of a pattern, prefix or replacement name
+pub const MAX_NAME_PATTERN_LENGTH: usize = 200;
+
+/// Maximum size of a compiled pattern, patterns are evaluated on every join and member update
+pub const MAX_NAME_PATTERN_SIZE: usize = 64 * 1024;
+
+/// Maximum number of stings a rule may give
+pub const MAX_NAME_RULE_STINGS: u32 = 10;
+
+/// Maximum length of a Discord nickname
+pub const MAX_NICKNAME_LENGTH: usize = 32;
+
+/// The nickname, username and display name rules of a guild, enforced on join and member update
+///
+/// Names are those members are shown by (their nickname, else their display name, else their username)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NamePolicy {
+    pub rules: Vec<NameRule>,
+    /// Members with any of these roles are exempt
+    #[serde(default)]
+    pub exempt_roles: Vec<String>,
+    /// Channel violations are posted to by rules with the `notify` action
+    #[serde(default)]
+    pub notify_channel_id: Option<ChannelId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NameRule {
+    /// Name of the rule, shown in notifications and `NamePolicyViolation` events
+    pub name: String,
+    #[serde(flatten)]
+    pub check: NameCheck,
+    /// What is done about a name violating the rule
+    pub actions: Vec<NameAction>,
+    /// The nickname members are renamed to by blocklist rules, their cleaned up name if unset
+    #[serde(default)]
+    pub rename_to: Option<String>,
+    /// Stings given by the `sting` action
+    #[serde(default = "default_stings")]
+    pub stings: u32,
+    /// How long stings given by the `sting` action last, in seconds
+    #[serde(default = "default_sting_expiry")]
+    pub sting_expiry: u64,
+}
+
+fn default_stings() -> u32 {
+    1
+}
+
+fn default_sting_expiry() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+/// What a name must (not) look like
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NameCheck {
+    /// Names matching any of the (case insensitive) regex patterns violate the rule
+    ///
+    /// With `normalize`, patterns are matched against the normalized name (homoglyphs mapped to plain
+    /// letters, invisible characters stripped and case folded) so lookalike names are caught too
+    Blocklist {
+        patterns: Vec<String>,
+        #[serde(default)]
+        normalize: bool,
+    },
+    /// Names must start with the prefix, members are renamed by prepending it
+    Prefix {
+        prefix: String,
+    },
+    /// Names must be readable, i.e. free of invisible characters, combining marks (zalgo) and fancy unicode
+    /// letters. Members are renamed to their cleaned up name
+    Readable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameAction {
+    Rename,
+    Notify,
+    Sting,
+}
+
+impl NameAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Rename => "rename",
+            Self::Notify => "notify",
+            Self::Sting => "sting",
+        }
+    }
+}
+
+/// Compiles a pattern of a blocklist rule
+pub fn compile_pattern(pattern: &str) -> Result<regex::Regex, crate::Error> {
+    Ok(regex::RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(MAX_NAME_PATTERN_SIZE)
+        .build()?)
+}
+
+impl NamePolicy {
+    /// Validates the policy, including that all of its patterns compile
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.rules.len() > MAX_NAME_RULES {
+            return Err(format!("Name policies can have at most {MAX_NAME_RULES} rules").into());
+        }
+        if self.exempt_roles.iter().any(|r| r.parse::<dapi::RoleId>().is_err()) {
+            return Err("Invalid exempt role ID".into());
+        }
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            if self.rules[..i].iter().any(|r| r.name == rule.name) {
+                return Err(format!("Duplicate rule name {}", rule.name).into());
+            }
+            if rule.name.is_empty() || rule.name.len() > MAX_NAME_PATTERN_LENGTH {
+                return Err(format!("Rule names must be between 1 and {MAX_NAME_PATTERN_LENGTH} characters").into());
+            }
+            if rule.actions.is_empty() {
+                return Err(format!("Rule {} has no actions", rule.name).into());
+            }
+            if rule.actions.contains(&NameAction::Notify) && self.notify_channel_id.is_none() {
+                return Err(format!("Rule {} notifies but no notify channel is set", rule.name).into());
+            }
+            if rule.stings == 0 || rule.stings > MAX_NAME_RULE_STINGS {
+                return Err(format!("Rules can give between 1 and {MAX_NAME_RULE_STINGS} stings").into());
+            }
+            if let Some(ref rename_to) = rule.rename_to && (rename_to.is_empty() || rename_to.chars().count() > MAX_NICKNAME_LENGTH) {
+                return Err(format!("Rules can rename to names of between 1 and {MAX_NICKNAME_LENGTH} characters").into());
+            }
+
+            match rule.check {
+                NameCheck::Blocklist { ref patterns, .. } => {
+                    if patterns.is_empty() || patterns.len() > MAX_NAME_PATTERNS {
+                        return Err(format!("Blocklist rules must have between 1 and {MAX_NAME_PATTERNS} patterns").into());
+                    }
+                    for pattern in patterns {
+                        if pattern.len() > MAX_NAME_PATTERN_LENGTH {
+                            return Err(format!("Patterns can be at most {MAX_NAME_PATTERN_LENGTH} characters").into());
+                        }
+                        compile_pattern(pattern).map_err(|e| format!("Invalid pattern {pattern}: {e}"))?;
+                    }
+                }
+                NameCheck::Prefix { ref prefix } => {
+                    if prefix.is_empty() || prefix.chars().count() >= MAX_NICKNAME_LENGTH {
+                        return Err(format!("Prefixes must be between 1 and {} characters", MAX_NICKNAME_LENGTH - 1).into());
+                    }
+                }
+                NameCheck::Readable => {}
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use dapi::UserId;
+use serde::{Deserialize, Serialize};
+
+/// Maximum window destructive actions are counted in
+pub const MAX_NUKE_WINDOW_SECS: u32 = 600;
+
+/// Maximum number of users exempt from nuke protection
+pub const MAX_NUKE_EXEMPT_USERS: usize = 50;
+
+/// A destructive action counted by nuke protection, derived from audit log entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DestructiveAction {
+    ChannelDelete,
+    RoleDelete,
+    Ban,
+    Kick,
+    WebhookCreate,
+}
+
+impl DestructiveAction {
+    /// Returns the destructive action of an audit log action type, if any
+    pub fn from_audit_log_action(action_type: u64) -> Option<Self> {
+        match action_type {
+            12 => Some(Self::ChannelDelete),
+            20 => Some(Self::Kick),
+            22 => Some(Self::Ban),
+            32 => Some(Self::RoleDelete),
+            50 => Some(Self::WebhookCreate),
+            _ => None,
+        }
+    }
+}
+
+/// How many of each destructive action a single user may take within the window, unset actions are not limited
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NukeThresholds {
+    #[serde(default)]
+    pub channel_deletes: Option<u32>,
+    #[serde(default)]
+    pub role_deletes: Option<u32>,
+    #[serde(default)]
+    pub bans: Option<u32>,
+    #[serde(default)]
+    pub kicks: Option<u32>,
+    #[serde(default)]
+    pub webhook_creates: Option<u32>,
+}
+
+impl NukeThresholds {
+    pub fn get(&self, action: DestructiveAction) -> Option<u32> {
+        match action {
+            DestructiveAction::ChannelDelete => self.channel_deletes,
+            DestructiveAction::RoleDelete => self.role_deletes,
+            DestructiveAction::Ban => self.bans,
+            DestructiveAction::Kick => self.kicks,
+            DestructiveAction::WebhookCreate => self.webhook_creates,
+        }
+    }
+}
+
+/// How destructive actions are detected and responded to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NukeProtectionConfig {
+    /// How many seconds destructive actions are counted for
+    pub window_secs: u32,
+    pub thresholds: NukeThresholds,
+    /// Whether the roles with dangerous permissions of users tripping a threshold are removed
+    #[serde(default = "default_true")]
+    pub strip_roles: bool,
+    /// Whether the guild is locked down (through the builtins) when a threshold is tripped
+    #[serde(default)]
+    pub lockdown: bool,
+    /// Users whose actions are never counted
+    #[serde(default)]
+    pub exempt_users: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl NukeProtectionConfig {
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.window_secs == 0 || self.window_secs > MAX_NUKE_WINDOW_SECS {
+            return Err(format!("The nuke protection window must be between 1 and {MAX_NUKE_WINDOW_SECS} seconds").into());
+        }
+        let thresholds = [
+            self.thresholds.channel_deletes, self.thresholds.role_deletes, self.thresholds.bans,
+            self.thresholds.kicks, self.thresholds.webhook_creates,
+        ];
+        if thresholds.iter().all(|t| t.is_none()) {
+            return Err("At least one nuke protection threshold must be set".into());
+        }
+        if thresholds.iter().any(|t| *t == Some(0)) {
+            return Err("Nuke protection thresholds must be at least 1".into());
+        }
+        if self.exempt_users.len() > MAX_NUKE_EXEMPT_USERS {
+            return Err(format!("At most {MAX_NUKE_EXEMPT_USERS} users can be exempt from nuke protection").into());
+        }
+        if self.exempt_users.iter().any(|u| u.parse::<UserId>().is_err()) {
+            return Err("Invalid exempt user ID".into());
+        }
+        Ok(())
+    }
+
+    pub fn window(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.window_secs as i64)
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::worker::workervmmanager::Id;
+
+/// Maximum length of an idempotency key
+pub const MAX_OUTBOX_KEY_LENGTH: usize = 128;
+
+/// Discord calls which may be enqueued into the outbox
+///
+/// Actions are delivered at least once (a sender dying after Discord accepted an action but before marking it
+/// sent retries it), so only calls which have the same effect when made twice are allowed
+pub const OUTBOX_OPS: &[&str] = &[
+    "CreateGuildBan",
+    "RemoveGuildBan",
+    "RemoveGuildMember",
+    "AddGuildMemberRole",
+    "RemoveGuildMemberRole",
+    "ModifyGuildMember",
+];
+
+/// Result of enqueueing a Discord action into the outbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxReceipt {
+    pub id: i64,
+    /// Whether an action with the same idempotency key was already enqueued (in which case nothing was enqueued)
+    pub duplicate: bool,
+}
+
+/// Worker request to enqueue a Discord action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEnqueue {
+    pub key: String,
+    /// The Discord plugin request, as sent to the `Discord` syscall
+    pub action: serde_json::Value,
+}
+
+/// A Discord action waiting to be sent
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub tenant: Id,
+    pub key: String,
+    pub action: serde_json::Value,
+    /// Number of send attempts, including the current one
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct OutboxEntryRow {
+    id: i64,
+    owner_id: String,
+    owner_type: String,
+    idempotency_key: String,
+    action: serde_json::Value,
+    attempts: i32,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<OutboxEntryRow> for OutboxEntry {
+    type Error = crate::Error;
+
+    fn try_from(row: OutboxEntryRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            tenant: Id::from_parts(&row.owner_type, &row.owner_id).ok_or("Invalid tenant of outbox entry")?,
+            key: row.idempotency_key,
+            action: row.action,
+            attempts: row.attempts,
+            created_at: row.created_at,
+        })
+    }
+}
+
+/// Durable queue of Discord actions (the outbox), sent by the master's outbox sender
+///
+/// Actions are unique per tenant and idempotency key, so an action enqueued again (e.g. by an execution
+/// retried after a crash) is not enqueued twice. Sending is at least once, see `OUTBOX_OPS`
+#[derive(Clone)]
+pub struct OutboxDb {
+    pool: sqlx::PgPool,
+}
+
+impl OutboxDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Enqueues an action, returning the existing entry if the key was already used by the tenant
+    pub async fn enqueue(&self, id: Id, req: &OutboxEnqueue) -> Result<OutboxReceipt, crate::Error> {
+        if req.key.is_empty() || req.key.len() > MAX_OUTBOX_KEY_LENGTH {
+            return Err(format!("Outbox key must be between 1 and {MAX_OUTBOX_KEY_LENGTH} characters").into());
+        }
+
+        let inserted: Option<(i64,)> = sqlx::query_as(
+            "INSERT INTO discord_outbox (owner_id, owner_type, idempotency_key, action) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (owner_id, owner_type, idempotency_key) DO NOTHING
+            RETURNING id"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .bind(&req.key)
+        .bind(&req.action)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some((outbox_id,)) = inserted {
+            return Ok(OutboxReceipt { id: outbox_id, duplicate: false });
+        }
+
+        let (outbox_id,): (i64,) = sqlx::query_as(
+            "SELECT id FROM discord_outbox WHERE owner_id = $1 AND owner_type = $2 AND idempotency_key = $3"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .bind(&req.key)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(OutboxReceipt { id: outbox_id, duplicate: true })
+    }
+
+    /// Claims up to `limit` due actions for sending
+    ///
+    /// Claimed actions are leased for `lease`, if the sender dies before marking them sent (or failed) they
+    /// become due again once the lease expires
+    pub async fn claim_due(&self, limit: i64, lease: Duration) -> Result<Vec<OutboxEntry>, crate::Error> {
+        let rows: Vec<OutboxEntryRow> = sqlx::query_as(
+            "UPDATE discord_outbox SET attempts = attempts + 1, next_attempt_at = $2
+            WHERE id IN (
+                SELECT id FROM discord_outbox WHERE state = 'pending' AND next_attempt_at <= NOW()
+                ORDER BY next_attempt_at LIMIT $1 FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, owner_id, owner_type, idempotency_key, action, attempts, created_at"
+        )
+        .bind(limit)
+        .bind(Utc::now() + chrono::Duration::from_std(lease)?)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter().map(OutboxEntry::try_from).collect()
+    }
+
+    /// Marks an action as sent, storing the response of Discord
+    pub async fn mark_sent(&self, outbox_id: i64, result: &serde_json::Value) -> Result<(), crate::Error> {
+        sqlx::query("UPDATE discord_outbox SET state = 'sent', result = $2, last_error = NULL, completed_at = NOW() WHERE id = $1")
+            .bind(outbox_id)
+            .bind(result)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Records a failed attempt, retrying the action after `delay` or giving up on it if `delay` is `None`
+    pub async fn mark_failed(&self, outbox_id: i64, error: &str, delay: Option<Duration>) -> Result<(), crate::Error> {
+        match delay {
+            Some(delay) => {
+                sqlx::query("UPDATE discord_outbox SET last_error = $2, next_attempt_at = $3 WHERE id = $1")
+                    .bind(outbox_id)
+                    .bind(error)
+                    .bind(Utc::now() + chrono::Duration::from_std(delay)?)
+                    .execute(&self.pool)
+                    .await?;
+            }
+            None => {
+                sqlx::query("UPDATE discord_outbox SET state = 'failed', last_error = $2, completed_at = NOW() WHERE id = $1")
+                    .bind(outbox_id)
+                    .bind(error)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes finished actions older than `retention`, returning the number deleted
+    ///
+    /// Idempotency keys of deleted actions can be reused
+    pub async fn delete_finished(&self, retention: Duration) -> Result<u64, crate::Error> {
+        let res = sqlx::query("DELETE FROM discord_outbox WHERE state IN ('sent', 'failed') AND completed_at < $1")
+            .bind(Utc::now() - chrono::Duration::from_std(retention)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
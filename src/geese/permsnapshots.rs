@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dapi::GuildId;
+use serde::{Deserialize, Serialize};
+
+/// A permission overwrite of a channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overwrite {
+    /// 0 for a role, 1 for a member
+    #[serde(rename = "type")]
+    pub kind: u8,
+    pub allow: String,
+    pub deny: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelPermissions {
+    pub name: String,
+    /// Overwrites keyed by the role or member they apply to
+    pub overwrites: BTreeMap<String, Overwrite>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RolePermissions {
+    pub name: String,
+    pub permissions: String,
+}
+
+/// The channel overwrites and role permissions of a guild at a point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionSnapshot {
+    pub guild_id: GuildId,
+    /// Channels keyed by ID
+    pub channels: BTreeMap<String, ChannelPermissions>,
+    /// Roles keyed by ID
+    pub roles: BTreeMap<String, RolePermissions>,
+    pub taken_at: DateTime<Utc>,
+}
+
+/// A permission change between two snapshots, carrying the previous state so it can be reverted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PermissionChange {
+    OverwriteAdded {
+        channel_id: String,
+        target_id: String,
+        overwrite: Overwrite,
+    },
+    OverwriteRemoved {
+        channel_id: String,
+        target_id: String,
+        /// The removed overwrite
+        before: Overwrite,
+    },
+    OverwriteChanged {
+        channel_id: String,
+        target_id: String,
+        before: Overwrite,
+        after: Overwrite,
+    },
+    RolePermissionsChanged {
+        role_id: String,
+        before: String,
+        after: String,
+    },
+}
+
+impl PermissionSnapshot {
+    /// Returns the changes to the permissions of the channels and roles which exist in both snapshots
+    ///
+    /// Created channels and roles are not changes, their permissions are in the next snapshot to diff against
+    pub fn diff(&self, after: &PermissionSnapshot) -> Vec<PermissionChange> {
+        let mut changes = Vec::new();
+        for (channel_id, before) in self.channels.iter() {
+            let Some(after) = after.channels.get(channel_id) else {
+                continue;
+            };
+            for (target_id, old) in before.overwrites.iter() {
+                match after.overwrites.get(target_id) {
+                    None => changes.push(PermissionChange::OverwriteRemoved { channel_id: channel_id.clone(), target_id: target_id.clone(), before: old.clone() }),
+                    Some(new) if new != old => changes.push(PermissionChange::OverwriteChanged {
+                        channel_id: channel_id.clone(),
+                        target_id: target_id.clone(),
+                        before: old.clone(),
+                        after: new.clone(),
+                    }),
+                    Some(_) => {}
+                }
+            }
+            for (target_id, new) in after.overwrites.iter() {
+                if !before.overwrites.contains_key(target_id) {
+                    changes.push(PermissionChange::OverwriteAdded { channel_id: channel_id.clone(), target_id: target_id.clone(), overwrite: new.clone() });
+                }
+            }
+        }
+        for (role_id, before) in self.roles.iter() {
+            if let Some(after) = after.roles.get(role_id) && after.permissions != before.permissions {
+                changes.push(PermissionChange::RolePermissionsChanged { role_id: role_id.clone(), before: before.permissions.clone(), after: after.permissions.clone() });
+            }
+        }
+        changes
+    }
+}
+
+/// A permission snapshot request from a worker to the master
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PermissionSnapshotReq {
+    /// Replaces the last snapshot of a guild, returning it
+    Swap { snapshot: PermissionSnapshot },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PermissionSnapshotResp {
+    Previous { snapshot: Option<PermissionSnapshot> },
+}
+
+/// Data of `$PermissionSnapshot`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionSnapshotTrigger {
+    /// Whether the snapshot was requested through the API, rather than taken periodically
+    pub manual: bool,
+}
+
+/// Storage of the last permission snapshot of guilds
+///
+/// Snapshots are taken and diffed by the worker of the guild (see `worker::permsnapshots`), the master stores them
+/// and schedules periodic snapshots
+#[derive(Clone)]
+pub struct PermissionSnapshotDb {
+    pool: sqlx::PgPool,
+}
+
+impl PermissionSnapshotDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Handles a permission snapshot request from a worker
+    pub async fn handle(&self, req: PermissionSnapshotReq) -> Result<PermissionSnapshotResp, crate::Error> {
+        match req {
+            PermissionSnapshotReq::Swap { snapshot } => {
+                let previous: Option<(Option<serde_json::Value>,)> = sqlx::query_as(
+                    "WITH prev AS (SELECT snapshot FROM permission_snapshots WHERE guild_id = $1)
+                    INSERT INTO permission_snapshots (guild_id, snapshot, taken_at) VALUES ($1, $2, $3)
+                    ON CONFLICT (guild_id) DO UPDATE SET snapshot = EXCLUDED.snapshot, taken_at = EXCLUDED.taken_at
+                    RETURNING (SELECT snapshot FROM prev)"
+                )
+                .bind(snapshot.guild_id.to_string())
+                .bind(serde_json::to_value(&snapshot)?)
+                .bind(snapshot.taken_at)
+                .fetch_optional(&self.pool)
+                .await?;
+
+                let snapshot = previous.and_then(|(s,)| s).map(serde_json::from_value).transpose()?;
+                Ok(PermissionSnapshotResp::Previous { snapshot })
+            }
+        }
+    }
+
+    /// Claims up to `limit` guilds with permission snapshots whose last snapshot is older than `interval`
+    pub async fn claim_due(&self, limit: i64, interval: Duration) -> Result<Vec<GuildId>, crate::Error> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            "UPDATE tenant_state ts SET permission_snapshot_at = NOW()
+            FROM (
+                SELECT owner_id, owner_type FROM tenant_state
+                WHERE owner_type = 'guild' AND settings ? 'permission_snapshots' AND (permission_snapshot_at IS NULL OR permission_snapshot_at <= $2)
+                ORDER BY permission_snapshot_at NULLS FIRST LIMIT $1 FOR UPDATE SKIP LOCKED
+            ) due
+            WHERE ts.owner_id = due.owner_id AND ts.owner_type = due.owner_type
+            RETURNING ts.owner_id"
+        )
+        .bind(limit)
+        .bind(Utc::now() - chrono::Duration::from_std(interval)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(guild_id,)| Ok(guild_id.parse()?))
+            .collect()
+    }
+}
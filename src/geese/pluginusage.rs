@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::geese::dbpools::PoolKind;
+use crate::geese::dbrouter::DbRouter;
+use crate::worker::workervmmanager::Id;
+
+/// Number of buckets tenants are hashed into
+///
+/// Only the bucket of a tenant is stored, which is enough to tell if a method is used by a handful
+/// of tenants or by many without keeping track of which tenants use what
+pub const TENANT_BUCKETS: u64 = 256;
+
+/// Call counters of a plugin method
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PluginUsage {
+    pub calls: u64,
+    pub errors: u64,
+}
+
+/// Usage of a plugin method by a tenant bucket over an hour, sent from workers to the master
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginUsageRecord {
+    pub plugin: String,
+    pub method: String,
+    pub bucket: u8,
+    pub hour: DateTime<Utc>,
+    pub usage: PluginUsage,
+}
+
+/// Worker-side accumulator of plugin usage, flushed to the master hourly
+#[derive(Default)]
+pub struct PluginUsageTracker {
+    pending: Mutex<HashMap<(&'static str, &'static str, u8, DateTime<Utc>), PluginUsage>>,
+}
+
+impl PluginUsageTracker {
+    /// How often pending usage is flushed to the master
+    pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+    /// Records a call to a plugin method
+    pub fn record(&self, id: Id, plugin: &'static str, method: &'static str, ok: bool) {
+        let hour = Utc::now().duration_trunc(chrono::Duration::hours(1)).unwrap_or_else(|_| Utc::now());
+        let mut pending = self.pending.lock();
+        let usage = pending.entry((plugin, method, Self::bucket(id), hour)).or_default();
+        usage.calls += 1;
+        if !ok {
+            usage.errors += 1;
+        }
+    }
+
+    /// Takes all pending usage records
+    pub fn take(&self) -> Vec<PluginUsageRecord> {
+        let pending = std::mem::take(&mut *self.pending.lock());
+        pending.into_iter()
+            .map(|((plugin, method, bucket, hour), usage)| PluginUsageRecord { plugin: plugin.to_string(), method: method.to_string(), bucket, hour, usage })
+            .collect()
+    }
+
+    /// Returns the bucket of a tenant
+    fn bucket(id: Id) -> u8 {
+        let digest = Sha256::digest(id.tenant_id().as_bytes());
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        (u64::from_be_bytes(bytes) % TENANT_BUCKETS) as u8
+    }
+}
+
+/// Usage of a plugin method over the report period
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PluginUsageReportRow {
+    pub plugin: String,
+    pub method: String,
+    pub calls: i64,
+    pub errors: i64,
+    /// Number of distinct tenant buckets (out of `TENANT_BUCKETS`) the method was called from
+    pub buckets: i64,
+    pub last_used: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct PluginUsageDb {
+    pool: sqlx::PgPool,
+    db: DbRouter,
+}
+
+impl PluginUsageDb {
+    pub fn new(db: DbRouter) -> Self {
+        Self { pool: db.pool(PoolKind::Analytics).pool().clone(), db }
+    }
+
+    /// Adds the given usage records to the hourly rollups
+    pub async fn record(&self, records: Vec<PluginUsageRecord>) -> Result<(), crate::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for record in records {
+            sqlx::query(
+                "INSERT INTO plugin_usage_hourly (plugin, method, bucket, hour, calls, errors)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (plugin, method, bucket, hour) DO UPDATE SET
+                    calls = plugin_usage_hourly.calls + EXCLUDED.calls,
+                    errors = plugin_usage_hourly.errors + EXCLUDED.errors"
+            )
+            .bind(record.plugin)
+            .bind(record.method)
+            .bind(record.bucket as i16)
+            .bind(record.hour)
+            .bind(record.usage.calls as i64)
+            .bind(record.usage.errors as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns the usage of every plugin method (optionally of a single plugin) over the last `days` days, most used first
+    pub async fn report(&self, days: u32, plugin: Option<&str>) -> Result<Vec<PluginUsageReportRow>, crate::Error> {
+        let since = Utc::now() - chrono::Duration::days(days.into());
+        if let Some(replica) = self.db.reads() {
+            match Self::fetch_report(replica, since, plugin).await {
+                Ok(rows) => return Ok(rows),
+                Err(e) => self.db.replica_failed(&e.into()),
+            }
+        }
+        Ok(Self::fetch_report(&self.pool, since, plugin).await?)
+    }
+
+    async fn fetch_report(pool: &sqlx::PgPool, since: DateTime<Utc>, plugin: Option<&str>) -> Result<Vec<PluginUsageReportRow>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT plugin, method, SUM(calls)::BIGINT AS calls, SUM(errors)::BIGINT AS errors, COUNT(DISTINCT bucket) AS buckets, MAX(hour) AS last_used
+            FROM plugin_usage_hourly WHERE hour > $1 AND ($2::TEXT IS NULL OR plugin = $2)
+            GROUP BY plugin, method ORDER BY calls DESC"
+        )
+        .bind(since)
+        .bind(plugin)
+        .fetch_all(pool)
+        .await
+    }
+}
@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of templates of a guild whose results are cached
+pub const MAX_CACHED_TEMPLATES: usize = 25;
+
+/// Maximum number of seconds a result is cached for
+pub const MAX_RESULT_CACHE_TTL: u32 = 300;
+
+/// Templates of a guild whose results are cached, for query-style templates (e.g. settings pages rendering
+/// leaderboards) which are re-executed identically many times
+///
+/// Only events targeting a single template (those with a `__tloop_template_id`) are cached, keyed by the event name,
+/// author and data. A template is never cached once it makes a syscall with side effects (see
+/// `SyscallArgs::has_side_effects`), so templates writing to the key-value store or calling Discord are served as
+/// normal. Results may still be up to `ttl` seconds stale with regards to the data the template reads
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResultCacheConfig {
+    /// Name of the template to the number of seconds its results are cached for
+    pub templates: HashMap<String, u32>,
+}
+
+impl ResultCacheConfig {
+    /// Returns the number of seconds the results of a template are cached for, if cached
+    pub fn ttl(&self, template: &str) -> Option<u32> {
+        self.templates.get(template).copied()
+    }
+
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.templates.is_empty() || self.templates.len() > MAX_CACHED_TEMPLATES {
+            return Err(format!("Between 1 and {MAX_CACHED_TEMPLATES} templates must have their results cached").into());
+        }
+        if self.templates.keys().any(|name| name.is_empty()) {
+            return Err("Template names must not be empty".into());
+        }
+        if self.templates.values().any(|ttl| *ttl == 0 || *ttl > MAX_RESULT_CACHE_TTL) {
+            return Err(format!("Results can be cached for between 1 and {MAX_RESULT_CACHE_TTL} seconds").into());
+        }
+        Ok(())
+    }
+}
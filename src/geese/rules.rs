@@ -0,0 +1,640 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+/// Maximum number of rules in a rule set
+pub const MAX_RULES: usize = 100;
+/// Maximum number of conditions (including `all`, `any` and `none`) of a rule
+pub const MAX_CONDITIONS: usize = 64;
+/// Maximum nesting depth of the conditions of a rule
+pub const MAX_CONDITION_DEPTH: usize = 8;
+/// Maximum number of actions of a rule
+pub const MAX_ACTIONS: usize = 10;
+/// Maximum length of rule names, fields and hook names
+pub const MAX_NAME_LENGTH: usize = 100;
+/// Maximum compiled size of a `matches` pattern
+const MAX_PATTERN_SIZE: usize = 64 * 1024;
+/// Maximum duration of a timeout (Discord's own limit)
+const MAX_TIMEOUT_SECS: u64 = 28 * 24 * 60 * 60;
+
+static INVITE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(discord\.gg|discord(app)?\.com/invite|dsc\.gg)/[a-z0-9-]+").expect("valid invite regex")
+});
+
+static LINK_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\bhttps?://[^\s<]+").expect("valid link regex")
+});
+
+/// A declarative moderation rule: when its condition holds for an input, its actions are returned for the template
+/// to carry out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default = "Rule::default_enabled")]
+    pub enabled: bool,
+    pub when: Condition,
+    pub actions: Vec<Action>,
+    /// Whether later rules are skipped once this rule matches
+    #[serde(default)]
+    pub stop: bool,
+}
+
+impl Rule {
+    fn default_enabled() -> bool { true }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// Holds if all conditions hold (and if there are none)
+    All(Vec<Condition>),
+    /// Holds if any condition holds
+    Any(Vec<Condition>),
+    /// Holds if no condition holds
+    None(Vec<Condition>),
+    /// Tests a field of the input
+    Match(Match),
+    /// Calls a hook function provided by the template
+    Hook(HookCall),
+}
+
+/// Tests the field at a dotted path of the input (such as `author.roles` or `embeds.1.title`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Match {
+    pub field: String,
+    pub op: Op,
+    #[serde(default)]
+    pub value: Value,
+    /// Whether strings are compared ignoring case
+    #[serde(default)]
+    pub case_insensitive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Exists,
+    Equals,
+    NotEquals,
+    Contains,
+    ContainsAny,
+    StartsWith,
+    EndsWith,
+    Matches,
+    In,
+    HasAny,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    HasInvite,
+    HasLink,
+}
+
+impl Op {
+    const ALL: [Op; 16] = [
+        Op::Exists, Op::Equals, Op::NotEquals, Op::Contains, Op::ContainsAny, Op::StartsWith, Op::EndsWith, Op::Matches,
+        Op::In, Op::HasAny, Op::Gt, Op::Gte, Op::Lt, Op::Lte, Op::HasInvite, Op::HasLink,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Op::Exists => "exists",
+            Op::Equals => "equals",
+            Op::NotEquals => "not_equals",
+            Op::Contains => "contains",
+            Op::ContainsAny => "contains_any",
+            Op::StartsWith => "starts_with",
+            Op::EndsWith => "ends_with",
+            Op::Matches => "matches",
+            Op::In => "in",
+            Op::HasAny => "has_any",
+            Op::Gt => "gt",
+            Op::Gte => "gte",
+            Op::Lt => "lt",
+            Op::Lte => "lte",
+            Op::HasInvite => "has_invite",
+            Op::HasLink => "has_link",
+        }
+    }
+
+    /// Description of the op and the kind of value it takes (`none`, `scalar`, `string`, `strings`, `scalars` or
+    /// `number`), for the schema
+    fn describe(self) -> (&'static str, &'static str) {
+        match self {
+            Op::Exists => ("The field is set", "none"),
+            Op::Equals => ("The field equals the value", "scalar"),
+            Op::NotEquals => ("The field does not equal the value (or is unset)", "scalar"),
+            Op::Contains => ("The string contains the value, or a list has an element equal to it", "string"),
+            Op::ContainsAny => ("The string contains any of the values, or a list has an element equal to one", "strings"),
+            Op::StartsWith => ("The string starts with the value", "string"),
+            Op::EndsWith => ("The string ends with the value", "string"),
+            Op::Matches => ("The string (or an element of a list) matches the regular expression", "string"),
+            Op::In => ("The field equals one of the values", "scalars"),
+            Op::HasAny => ("The list has an element equal to one of the values (such as role IDs)", "scalars"),
+            Op::Gt => ("The number (or length of a string or list) is greater than the value", "number"),
+            Op::Gte => ("The number (or length of a string or list) is at least the value", "number"),
+            Op::Lt => ("The number (or length of a string or list) is less than the value", "number"),
+            Op::Lte => ("The number (or length of a string or list) is at most the value", "number"),
+            Op::HasInvite => ("The string contains a Discord invite", "none"),
+            Op::HasLink => ("The string contains a link", "none"),
+        }
+    }
+}
+
+/// Calls the condition hook `name` of the template with the input and `args`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HookCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+/// What to do when a rule matches, carried out by the template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    DeleteMessage {},
+    Sting {
+        #[serde(default = "Action::default_stings")]
+        stings: u32,
+        reason: Option<String>,
+        /// Seconds until the stings expire, never if unset
+        expiry_secs: Option<u64>,
+    },
+    Timeout {
+        duration_secs: u64,
+        reason: Option<String>,
+    },
+    Kick {
+        reason: Option<String>,
+    },
+    Ban {
+        reason: Option<String>,
+        /// Seconds of messages of the member to delete
+        delete_message_secs: Option<u32>,
+    },
+    Reply {
+        content: String,
+    },
+    Log {
+        message: String,
+    },
+    /// Calls the action hook `name` of the template
+    Hook {
+        name: String,
+        #[serde(default)]
+        args: Value,
+    },
+}
+
+impl Action {
+    fn default_stings() -> u32 { 1 }
+
+    fn validate(&self) -> Result<(), crate::Error> {
+        match self {
+            Action::Sting { stings, .. } if *stings == 0 => Err("sting actions must give at least 1 sting".into()),
+            Action::Timeout { duration_secs, .. } if *duration_secs == 0 || *duration_secs > MAX_TIMEOUT_SECS => {
+                Err(format!("timeouts must last between 1 and {MAX_TIMEOUT_SECS} seconds").into())
+            }
+            Action::Ban { delete_message_secs: Some(secs), .. } if *secs > 7 * 24 * 60 * 60 => {
+                Err("bans can delete at most 7 days of messages".into())
+            }
+            Action::Reply { content } | Action::Log { message: content } if content.is_empty() || content.chars().count() > 2000 => {
+                Err("reply and log messages must be between 1 and 2000 characters".into())
+            }
+            Action::Hook { name, .. } => validate_name("hook", name),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn validate_name(what: &str, name: &str) -> Result<(), crate::Error> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(format!("{what} names must be between 1 and {MAX_NAME_LENGTH} characters").into());
+    }
+    Ok(())
+}
+
+/// A value of the input a field is tested against
+#[derive(Debug)]
+pub enum FieldValue<'a> {
+    Missing,
+    Bool(bool),
+    Number(f64),
+    Text(Cow<'a, str>),
+    List(Vec<FieldValue<'a>>),
+    /// A value which is none of the above (such as a table with non-sequential keys)
+    Other,
+}
+
+/// A scalar value compared against
+#[derive(Debug, PartialEq)]
+enum Scalar {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl Scalar {
+    fn from_json(value: &Value, case_insensitive: bool) -> Result<Self, crate::Error> {
+        match value {
+            Value::Bool(b) => Ok(Self::Bool(*b)),
+            Value::Number(n) => Ok(Self::Number(n.as_f64().ok_or("value is not a valid number")?)),
+            Value::String(s) => Ok(Self::Text(fold(s, case_insensitive).into_owned())),
+            v => Err(format!("expected a boolean, number or string, got {v}").into()),
+        }
+    }
+
+    fn matches(&self, value: &FieldValue, case_insensitive: bool) -> bool {
+        match (self, value) {
+            (Self::Bool(a), FieldValue::Bool(b)) => a == b,
+            (Self::Number(a), FieldValue::Number(b)) => a == b,
+            (Self::Text(a), FieldValue::Text(b)) => *a == fold(b, case_insensitive),
+            _ => false,
+        }
+    }
+}
+
+fn fold(s: &str, case_insensitive: bool) -> Cow<'_, str> {
+    if case_insensitive {
+        Cow::Owned(s.to_lowercase())
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+fn text(value: &Value) -> Result<&str, crate::Error> {
+    value.as_str().ok_or_else(|| format!("expected a string, got {value}").into())
+}
+
+fn list(value: &Value) -> Result<&Vec<Value>, crate::Error> {
+    match value.as_array() {
+        Some(values) if !values.is_empty() => Ok(values),
+        _ => Err(format!("expected a non-empty list, got {value}").into()),
+    }
+}
+
+/// A compiled `Match` op
+#[derive(Debug)]
+enum Pred {
+    Exists,
+    Equals(Scalar),
+    NotEquals(Scalar),
+    Contains(String),
+    ContainsAny(Vec<String>),
+    StartsWith(String),
+    EndsWith(String),
+    Matches(Regex),
+    In(Vec<Scalar>),
+    HasAny(Vec<Scalar>),
+    Compare(Op, f64),
+    HasInvite,
+    HasLink,
+}
+
+impl Pred {
+    fn compile(op: Op, value: &Value, ci: bool) -> Result<Self, crate::Error> {
+        let pred = match op {
+            Op::Exists => Self::Exists,
+            Op::HasInvite => Self::HasInvite,
+            Op::HasLink => Self::HasLink,
+            Op::Equals => Self::Equals(Scalar::from_json(value, ci)?),
+            Op::NotEquals => Self::NotEquals(Scalar::from_json(value, ci)?),
+            Op::Contains => Self::Contains(fold(text(value)?, ci).into_owned()),
+            Op::StartsWith => Self::StartsWith(fold(text(value)?, ci).into_owned()),
+            Op::EndsWith => Self::EndsWith(fold(text(value)?, ci).into_owned()),
+            Op::ContainsAny => Self::ContainsAny(
+                list(value)?.iter().map(|v| Ok(fold(text(v)?, ci).into_owned())).collect::<Result<_, crate::Error>>()?,
+            ),
+            Op::Matches => Self::Matches(
+                RegexBuilder::new(text(value)?)
+                    .case_insensitive(ci)
+                    .size_limit(MAX_PATTERN_SIZE)
+                    .build()
+                    .map_err(|e| format!("invalid pattern: {e}"))?,
+            ),
+            Op::In => Self::In(list(value)?.iter().map(|v| Scalar::from_json(v, ci)).collect::<Result<_, _>>()?),
+            Op::HasAny => Self::HasAny(list(value)?.iter().map(|v| Scalar::from_json(v, ci)).collect::<Result<_, _>>()?),
+            Op::Gt | Op::Gte | Op::Lt | Op::Lte => {
+                Self::Compare(op, value.as_f64().ok_or_else(|| format!("expected a number, got {value}"))?)
+            }
+        };
+        Ok(pred)
+    }
+
+    fn test(&self, value: &FieldValue, ci: bool) -> bool {
+        // Ops on strings also hold for lists with any element they hold for
+        let any_text = |f: &dyn Fn(&str) -> bool| match value {
+            FieldValue::Text(s) => f(&fold(s, ci)),
+            FieldValue::List(items) => items.iter().any(|i| matches!(i, FieldValue::Text(s) if f(&fold(s, ci)))),
+            _ => false,
+        };
+
+        match self {
+            Self::Exists => !matches!(value, FieldValue::Missing),
+            Self::Equals(s) => s.matches(value, ci),
+            Self::NotEquals(s) => !s.matches(value, ci),
+            Self::Contains(needle) => match value {
+                FieldValue::Text(s) => fold(s, ci).contains(needle.as_str()),
+                FieldValue::List(items) => items.iter().any(|i| matches!(i, FieldValue::Text(s) if fold(s, ci) == needle.as_str())),
+                _ => false,
+            },
+            Self::ContainsAny(needles) => match value {
+                FieldValue::Text(s) => {
+                    let s = fold(s, ci);
+                    needles.iter().any(|n| s.contains(n.as_str()))
+                }
+                FieldValue::List(items) => items.iter().any(|i| matches!(i, FieldValue::Text(s) if needles.iter().any(|n| fold(s, ci) == n.as_str()))),
+                _ => false,
+            },
+            Self::StartsWith(prefix) => matches!(value, FieldValue::Text(s) if fold(s, ci).starts_with(prefix.as_str())),
+            Self::EndsWith(suffix) => matches!(value, FieldValue::Text(s) if fold(s, ci).ends_with(suffix.as_str())),
+            // Case insensitivity is compiled into the pattern
+            Self::Matches(re) => any_text(&|s| re.is_match(s)),
+            Self::HasInvite => any_text(&|s| INVITE_RE.is_match(s)),
+            Self::HasLink => any_text(&|s| LINK_RE.is_match(s)),
+            Self::In(options) => options.iter().any(|o| o.matches(value, ci)),
+            Self::HasAny(options) => match value {
+                FieldValue::List(items) => items.iter().any(|i| options.iter().any(|o| o.matches(i, ci))),
+                _ => false,
+            },
+            Self::Compare(op, bound) => {
+                let n = match value {
+                    FieldValue::Number(n) => *n,
+                    FieldValue::Text(s) => s.chars().count() as f64,
+                    FieldValue::List(items) => items.len() as f64,
+                    _ => return false,
+                };
+                match op {
+                    Op::Gt => n > *bound,
+                    Op::Gte => n >= *bound,
+                    Op::Lt => n < *bound,
+                    _ => n <= *bound,
+                }
+            }
+        }
+    }
+}
+
+/// A compiled condition
+#[derive(Debug)]
+enum Cond {
+    All(Vec<Cond>),
+    Any(Vec<Cond>),
+    None(Vec<Cond>),
+    Match { path: Vec<String>, pred: Pred, ci: bool },
+    Hook { name: String, args: Value },
+}
+
+impl Cond {
+    fn compile(cond: &Condition, depth: usize, count: &mut usize) -> Result<Self, crate::Error> {
+        *count += 1;
+        if *count > MAX_CONDITIONS {
+            return Err(format!("rules can have at most {MAX_CONDITIONS} conditions").into());
+        }
+        if depth > MAX_CONDITION_DEPTH {
+            return Err(format!("conditions can be nested at most {MAX_CONDITION_DEPTH} deep").into());
+        }
+
+        let cond = match cond {
+            Condition::All(conds) => Self::All(conds.iter().map(|c| Self::compile(c, depth + 1, count)).collect::<Result<_, _>>()?),
+            Condition::Any(conds) => {
+                if conds.is_empty() {
+                    return Err("any conditions must have at least one condition".into());
+                }
+                Self::Any(conds.iter().map(|c| Self::compile(c, depth + 1, count)).collect::<Result<_, _>>()?)
+            }
+            Condition::None(conds) => Self::None(conds.iter().map(|c| Self::compile(c, depth + 1, count)).collect::<Result<_, _>>()?),
+            Condition::Match(m) => {
+                validate_name("field", &m.field)?;
+                let path = m.field.split('.').map(str::to_string).collect::<Vec<_>>();
+                if path.iter().any(|s| s.is_empty()) {
+                    return Err(format!("invalid field {}", m.field).into());
+                }
+                let pred = Pred::compile(m.op, &m.value, m.case_insensitive)
+                    .map_err(|e| format!("{} {}: {e}", m.field, m.op.name()))?;
+                Self::Match { path, pred, ci: m.case_insensitive }
+            }
+            Condition::Hook(hook) => {
+                validate_name("hook", &hook.name)?;
+                Self::Hook { name: hook.name.clone(), args: hook.args.clone() }
+            }
+        };
+        Ok(cond)
+    }
+
+    fn eval<'a, L, H>(&self, lookup: &mut L, hook: &mut H) -> Result<bool, crate::Error>
+    where
+        L: FnMut(&[String]) -> Result<FieldValue<'a>, crate::Error>,
+        H: FnMut(&str, &Value) -> Result<bool, crate::Error>,
+    {
+        match self {
+            Self::All(conds) => {
+                for c in conds {
+                    if !c.eval(lookup, hook)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Self::Any(conds) => {
+                for c in conds {
+                    if c.eval(lookup, hook)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Self::None(conds) => {
+                for c in conds {
+                    if c.eval(lookup, hook)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Self::Match { path, pred, ci } => Ok(pred.test(&lookup(path)?, *ci)),
+            Self::Hook { name, args } => hook(name, args),
+        }
+    }
+}
+
+struct CompiledRule {
+    name: String,
+    when: Cond,
+    actions: Vec<Action>,
+    stop: bool,
+}
+
+/// A compiled rule set, evaluated in order against inputs
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    /// Validates and compiles a rule set, disabled rules are validated but skipped
+    pub fn compile(rules: Vec<Rule>) -> Result<Self, crate::Error> {
+        if rules.len() > MAX_RULES {
+            return Err(format!("rule sets can have at most {MAX_RULES} rules").into());
+        }
+
+        let mut names = HashSet::new();
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            validate_name("rule", &rule.name)?;
+            if !names.insert(rule.name.clone()) {
+                return Err(format!("rule names must be unique, {} is used twice", rule.name).into());
+            }
+            if rule.actions.is_empty() || rule.actions.len() > MAX_ACTIONS {
+                return Err(format!("rule {}: rules must have between 1 and {MAX_ACTIONS} actions", rule.name).into());
+            }
+            for action in rule.actions.iter() {
+                action.validate().map_err(|e| format!("rule {}: {e}", rule.name))?;
+            }
+            let when = Cond::compile(&rule.when, 0, &mut 0).map_err(|e| format!("rule {}: {e}", rule.name))?;
+            if rule.enabled {
+                compiled.push(CompiledRule { name: rule.name, when, actions: rule.actions, stop: rule.stop });
+            }
+        }
+        Ok(Self { rules: compiled })
+    }
+
+    /// Evaluates the rule set, returning the names and actions of the matching rules in order
+    ///
+    /// `lookup` returns the value of the input at a field path and `hook` calls a condition hook of the template
+    pub fn evaluate<'a, L, H>(&self, mut lookup: L, mut hook: H) -> Result<Vec<(&str, &[Action])>, crate::Error>
+    where
+        L: FnMut(&[String]) -> Result<FieldValue<'a>, crate::Error>,
+        H: FnMut(&str, &Value) -> Result<bool, crate::Error>,
+    {
+        let mut matched = Vec::new();
+        for rule in self.rules.iter() {
+            if rule.when.eval(&mut lookup, &mut hook).map_err(|e| format!("rule {}: {e}", rule.name))? {
+                matched.push((rule.name.as_str(), rule.actions.as_slice()));
+                if rule.stop {
+                    break;
+                }
+            }
+        }
+        Ok(matched)
+    }
+}
+
+/// Returns the JSON schema of rule sets, for the dashboard to build and validate rules with
+///
+/// Ops are described under `x-ops` with the kind of value each takes
+pub fn schema() -> Value {
+    let ops = Op::ALL.iter().map(|op| {
+        let (description, value) = op.describe();
+        json!({ "op": op.name(), "description": description, "value": value })
+    }).collect::<Vec<_>>();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Rule set",
+        "type": "array",
+        "maxItems": MAX_RULES,
+        "items": { "$ref": "#/$defs/rule" },
+        "$defs": {
+            "rule": rule_schema(),
+            "condition": condition_schema(),
+            "action": action_schema(),
+        },
+        "x-ops": ops,
+    })
+}
+
+fn rule_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["name", "when", "actions"],
+        "additionalProperties": false,
+        "properties": {
+            "name": { "type": "string", "minLength": 1, "maxLength": MAX_NAME_LENGTH },
+            "enabled": { "type": "boolean", "default": true },
+            "when": { "$ref": "#/$defs/condition" },
+            "actions": { "type": "array", "minItems": 1, "maxItems": MAX_ACTIONS, "items": { "$ref": "#/$defs/action" } },
+            "stop": { "type": "boolean", "default": false, "description": "Skip later rules once this rule matches" },
+        },
+    })
+}
+
+fn condition_schema() -> Value {
+    let ops = Op::ALL.iter().map(|op| op.name()).collect::<Vec<_>>();
+    let one = |key: &str, schema: Value| json!({
+        "type": "object",
+        "required": [key],
+        "additionalProperties": false,
+        "properties": { key: schema },
+    });
+
+    json!({
+        "oneOf": [
+            one("all", json!({ "type": "array", "items": { "$ref": "#/$defs/condition" } })),
+            one("any", json!({ "type": "array", "minItems": 1, "items": { "$ref": "#/$defs/condition" } })),
+            one("none", json!({ "type": "array", "items": { "$ref": "#/$defs/condition" } })),
+            one("match", json!({
+                "type": "object",
+                "required": ["field", "op"],
+                "additionalProperties": false,
+                "properties": {
+                    "field": { "type": "string", "minLength": 1, "maxLength": MAX_NAME_LENGTH, "description": "Dotted path of the field of the input, such as author.roles" },
+                    "op": { "enum": ops },
+                    "value": {},
+                    "case_insensitive": { "type": "boolean", "default": false },
+                },
+            })),
+            one("hook", json!({
+                "type": "object",
+                "required": ["name"],
+                "additionalProperties": false,
+                "properties": {
+                    "name": { "type": "string", "minLength": 1, "maxLength": MAX_NAME_LENGTH },
+                    "args": {},
+                },
+            })),
+        ],
+    })
+}
+
+fn action_schema() -> Value {
+    let action = |typ: &str, required: &[&str], properties: Value| {
+        let mut properties = properties;
+        properties["type"] = json!({ "const": typ });
+        let mut required = required.to_vec();
+        required.push("type");
+        json!({ "type": "object", "required": required, "additionalProperties": false, "properties": properties })
+    };
+    let reason = json!({ "type": ["string", "null"] });
+
+    json!({
+        "oneOf": [
+            action("delete_message", &[], json!({})),
+            action("sting", &[], json!({
+                "stings": { "type": "integer", "minimum": 1, "default": 1 },
+                "reason": reason,
+                "expiry_secs": { "type": ["integer", "null"], "minimum": 1 },
+            })),
+            action("timeout", &["duration_secs"], json!({
+                "duration_secs": { "type": "integer", "minimum": 1, "maximum": MAX_TIMEOUT_SECS },
+                "reason": reason,
+            })),
+            action("kick", &[], json!({ "reason": reason })),
+            action("ban", &[], json!({
+                "reason": reason,
+                "delete_message_secs": { "type": ["integer", "null"], "minimum": 0, "maximum": 7 * 24 * 60 * 60 },
+            })),
+            action("reply", &["content"], json!({ "content": { "type": "string", "minLength": 1, "maxLength": 2000 } })),
+            action("log", &["message"], json!({ "message": { "type": "string", "minLength": 1, "maxLength": 2000 } })),
+            action("hook", &["name"], json!({
+                "name": { "type": "string", "minLength": 1, "maxLength": MAX_NAME_LENGTH },
+                "args": {},
+            })),
+        ],
+    })
+}
@@ -0,0 +1,489 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use dapi::ChannelId;
+use khronos_runtime::chrono_tz::Tz;
+use khronos_runtime::core::datetime::DateTime as LuaDateTime;
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Maximum number of scheduled messages per guild
+pub const MAX_SCHEDULED_MESSAGES: i64 = 50;
+
+/// Maximum length of the names of scheduled messages
+pub const MAX_SCHEDULE_NAME_LENGTH: usize = 64;
+
+/// Maximum number of minutes of an hour a schedule may fire at
+pub const MAX_SCHEDULE_MINUTES: u32 = 12;
+
+/// Max length of the content of a Discord message
+const MAX_CONTENT_LENGTH: usize = 2000;
+
+/// Max number of embeds of a Discord message
+const MAX_EMBEDS: usize = 10;
+
+/// How far ahead the next run of a schedule is searched for, long enough for schedules only firing on leap days
+const MAX_SEARCH_DAYS: u32 = 5 * 366;
+
+/// Columns of `scheduled_messages` selected into a `ScheduledMessage`
+pub const SCHEDULE_COLUMNS: &str = "id, guild_id, name, channel_id, message, cron, timezone, paused, next_run_at, attempts, last_error, last_run_at, created_at, updated_at";
+
+/// A 5 field cron expression (minute, hour, day of month, month and day of week)
+///
+/// Fields are numeric and support `*`, lists (`1,15`), ranges (`1-5`) and steps (`*/15`, `0-30/10`, `5/20`).
+/// Days of week run from 0 (Sunday) to 7 (Sunday again). As in cron, if both the day of month and the day of
+/// week are restricted a day matching either runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<Self, crate::Error> {
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err("Cron schedules must have 5 fields (minute, hour, day of month, month and day of week)".into());
+        };
+
+        let mut weekdays = parse_field(weekday, 0, 7, "day of week")?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, "minute")?,
+            hours: parse_field(hour, 0, 23, "hour")?,
+            days: parse_field(day, 1, 31, "day of month")?,
+            months: parse_field(month, 1, 12, "month")?,
+            weekdays,
+            days_restricted: !day.starts_with('*'),
+            weekdays_restricted: !weekday.starts_with('*'),
+        })
+    }
+
+    /// Returns the first time after `after` the schedule runs at in the timezone
+    ///
+    /// Times skipped by a daylight saving transition are skipped, times repeated by one run once. Returns None if the
+    /// schedule does not run within `MAX_SEARCH_DAYS` (e.g. on the 30th of February)
+    pub fn next_after(&self, after: DateTime<Utc>, tz: Tz) -> Option<DateTime<Utc>> {
+        let mut date = after.with_timezone(&tz).date_naive();
+        for _ in 0..MAX_SEARCH_DAYS {
+            if self.matches_date(date) {
+                for hour in bits(self.hours, 0, 23) {
+                    for minute in bits(self.minutes, 0, 59) {
+                        let Some(at) = date.and_hms_opt(hour, minute, 0).and_then(|t| tz.from_local_datetime(&t).earliest()) else {
+                            continue;
+                        };
+                        let at = at.with_timezone(&Utc);
+                        if at > after {
+                            return Some(at);
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+}
+
+/// Parses a field of a cron expression into a bitmask of the values it matches
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<u64, crate::Error> {
+    let value = |v: &str| v.parse::<u32>().map_err(|_| format!("Invalid value {v} in the {name} field of the cron schedule"));
+
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = value(step)?;
+                if step == 0 {
+                    return Err(format!("Steps in the {name} field of the cron schedule must be at least 1").into());
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start)?, value(end)?)
+        } else {
+            // A single value with a step (`5/20`) runs from the value to the end of the field
+            let start = value(range)?;
+            (start, if part.contains('/') { max } else { start })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("The {name} field of the cron schedule must be between {min} and {max}").into());
+        }
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+fn bits(mask: u64, min: u32, max: u32) -> impl Iterator<Item = u32> {
+    (min..=max).filter(move |v| mask & (1 << v) != 0)
+}
+
+/// Parses an IANA timezone (e.g. `Europe/Berlin`)
+pub fn parse_timezone(timezone: &str) -> Result<Tz, crate::Error> {
+    timezone.parse::<Tz>().map_err(|_| format!("Unknown timezone {timezone}").into())
+}
+
+/// The message posted by a schedule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledMessageContent {
+    #[serde(default)]
+    pub content: String,
+    /// Discord embed objects
+    #[serde(default)]
+    pub embeds: Vec<serde_json::Value>,
+    /// Discord allowed mentions object, nothing is mentioned if unset
+    #[serde(default)]
+    pub allowed_mentions: Option<serde_json::Value>,
+}
+
+impl ScheduledMessageContent {
+    /// Renders the message into the Discord request posting it to a channel
+    pub fn to_request(&self, channel_id: ChannelId) -> Result<dapi::apilist::API, crate::Error> {
+        let req = json!({
+            "op": "CreateMessage",
+            "data": {
+                "channel_id": channel_id,
+                "data": {
+                    "content": self.content,
+                    "embeds": self.embeds,
+                    "allowed_mentions": self.allowed_mentions.clone().unwrap_or_else(|| json!({ "parse": [] })),
+                }
+            }
+        });
+        serde_json::from_value(req).map_err(|e| format!("Invalid scheduled message: {e}").into())
+    }
+}
+
+/// A scheduled message, as set through the API or by templates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleSpec {
+    pub channel_id: ChannelId,
+    pub message: ScheduledMessageContent,
+    /// Cron expression of when the message is posted, see `CronSchedule`
+    pub cron: String,
+    /// IANA timezone the cron expression is in
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+impl ScheduleSpec {
+    /// Validates the schedule, returning when it first runs
+    pub fn validate(&self) -> Result<DateTime<Utc>, crate::Error> {
+        if self.message.content.chars().count() > MAX_CONTENT_LENGTH {
+            return Err(format!("Scheduled messages can have at most {MAX_CONTENT_LENGTH} characters").into());
+        }
+        if self.message.embeds.len() > MAX_EMBEDS {
+            return Err(format!("Scheduled messages can have at most {MAX_EMBEDS} embeds").into());
+        }
+        if self.message.content.trim().is_empty() && self.message.embeds.is_empty() {
+            return Err("Scheduled messages must have content or embeds".into());
+        }
+        self.message.to_request(self.channel_id)?;
+
+        let cron = CronSchedule::parse(&self.cron)?;
+        if cron.minutes.count_ones() > MAX_SCHEDULE_MINUTES {
+            return Err(format!("Schedules can run at most {MAX_SCHEDULE_MINUTES} times an hour").into());
+        }
+        cron.next_after(Utc::now(), parse_timezone(&self.timezone)?)
+            .ok_or_else(|| "The cron schedule never runs".into())
+    }
+}
+
+/// Validates the name of a scheduled message
+pub fn validate_name(name: &str) -> Result<(), crate::Error> {
+    if name.is_empty() || name.len() > MAX_SCHEDULE_NAME_LENGTH {
+        return Err(format!("Schedule names must be between 1 and {MAX_SCHEDULE_NAME_LENGTH} characters").into());
+    }
+    Ok(())
+}
+
+/// A message posted to a channel of a guild on a cron schedule
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub guild_id: String,
+    /// Name of the schedule, unique within the guild
+    pub name: String,
+    pub channel_id: String,
+    #[sqlx(json)]
+    pub message: ScheduledMessageContent,
+    pub cron: String,
+    pub timezone: String,
+    pub paused: bool,
+    /// When the message is next posted, unset while paused (or until the scheduler picks up a resumed schedule)
+    pub next_run_at: Option<DateTime<Utc>>,
+    /// Failed attempts at posting the current run
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ScheduledMessage {
+    /// Returns when the schedule runs next after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, crate::Error> {
+        Ok(CronSchedule::parse(&self.cron)?.next_after(after, parse_timezone(&self.timezone)?))
+    }
+}
+
+impl IntoLua for ScheduledMessage {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        table.set("id", self.id)?;
+        table.set("name", self.name)?;
+        table.set("channel_id", self.channel_id)?;
+        table.set("message", lua.to_value_with(&self.message, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("cron", self.cron)?;
+        table.set("timezone", self.timezone)?;
+        table.set("paused", self.paused)?;
+        table.set("next_run_at", self.next_run_at.map(LuaDateTime::from_utc))?;
+        table.set("attempts", self.attempts)?;
+        table.set("last_error", self.last_error)?;
+        table.set("last_run_at", self.last_run_at.map(LuaDateTime::from_utc))?;
+        table.set("created_at", LuaDateTime::from_utc(self.created_at))?;
+        table.set("updated_at", LuaDateTime::from_utc(self.updated_at))?;
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Data of `$ScheduledMessage`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledMessageRun {
+    pub id: i64,
+    pub name: String,
+    pub channel_id: ChannelId,
+    pub message: ScheduledMessageContent,
+    /// Attempts at posting the run, including this one
+    pub attempt: i32,
+}
+
+/// Lists the scheduled messages of a guild
+pub async fn list<'c, E>(executor: E, guild_id: &str) -> Result<Vec<ScheduledMessage>, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    let schedules = sqlx::query_as(&format!("SELECT {SCHEDULE_COLUMNS} FROM scheduled_messages WHERE guild_id = $1 ORDER BY name"))
+        .bind(guild_id)
+        .fetch_all(executor)
+        .await?;
+    Ok(schedules)
+}
+
+/// Creates or replaces a scheduled message of a guild, keeping it paused if it was
+///
+/// The limit is checked in the insert itself, so replacing a schedule works at the limit
+pub async fn set<'c, E>(executor: E, guild_id: &str, name: &str, spec: &ScheduleSpec) -> Result<ScheduledMessage, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    validate_name(name)?;
+    let next_run_at = spec.validate()?;
+
+    let schedule: Option<ScheduledMessage> = sqlx::query_as(&format!(
+        "INSERT INTO scheduled_messages (guild_id, name, channel_id, message, cron, timezone, next_run_at)
+        SELECT $1, $2, $3, $4, $5, $6, $7
+        WHERE (SELECT COUNT(*) FROM scheduled_messages WHERE guild_id = $1) < $8
+        OR EXISTS (SELECT 1 FROM scheduled_messages WHERE guild_id = $1 AND name = $2)
+        ON CONFLICT (guild_id, name) DO UPDATE SET channel_id = EXCLUDED.channel_id, message = EXCLUDED.message, cron = EXCLUDED.cron,
+        timezone = EXCLUDED.timezone, next_run_at = CASE WHEN scheduled_messages.paused THEN NULL ELSE EXCLUDED.next_run_at END,
+        attempts = 0, last_error = NULL, updated_at = NOW()
+        RETURNING {SCHEDULE_COLUMNS}"
+    ))
+    .bind(guild_id)
+    .bind(name)
+    .bind(spec.channel_id.to_string())
+    .bind(serde_json::to_value(&spec.message)?)
+    .bind(&spec.cron)
+    .bind(&spec.timezone)
+    .bind(next_run_at)
+    .bind(MAX_SCHEDULED_MESSAGES)
+    .fetch_optional(executor)
+    .await?;
+
+    schedule.ok_or_else(|| format!("Cannot have more than {MAX_SCHEDULED_MESSAGES} scheduled messages").into())
+}
+
+/// Deletes a scheduled message of a guild, returning whether it existed
+pub async fn delete<'c, E>(executor: E, guild_id: &str, name: &str) -> Result<bool, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    let res = sqlx::query("DELETE FROM scheduled_messages WHERE guild_id = $1 AND name = $2")
+        .bind(guild_id)
+        .bind(name)
+        .execute(executor)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Pauses or resumes a scheduled message of a guild, returning whether it changed (false if it does not exist or
+/// already was)
+///
+/// Resumed schedules are given their next run by the scheduler, so runs missed while paused are not posted
+pub async fn set_paused<'c, E>(executor: E, guild_id: &str, name: &str, paused: bool) -> Result<bool, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    let res = sqlx::query(
+        "UPDATE scheduled_messages SET paused = $3, next_run_at = NULL, attempts = 0, updated_at = NOW()
+        WHERE guild_id = $1 AND name = $2 AND paused IS DISTINCT FROM $3"
+    )
+    .bind(guild_id)
+    .bind(name)
+    .bind(paused)
+    .execute(executor)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Access to scheduled messages for the master's scheduler and the API
+///
+/// Templates manage the scheduled messages of their guild through state ops (see `StateOp::ScheduleSet` and friends)
+#[derive(Clone)]
+pub struct ScheduleDb {
+    pool: sqlx::PgPool,
+}
+
+impl ScheduleDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn list(&self, guild_id: &str) -> Result<Vec<ScheduledMessage>, crate::Error> {
+        list(&self.pool, guild_id).await
+    }
+
+    pub async fn set(&self, guild_id: &str, name: &str, spec: &ScheduleSpec) -> Result<ScheduledMessage, crate::Error> {
+        set(&self.pool, guild_id, name, spec).await
+    }
+
+    pub async fn delete(&self, guild_id: &str, name: &str) -> Result<bool, crate::Error> {
+        delete(&self.pool, guild_id, name).await
+    }
+
+    pub async fn set_paused(&self, guild_id: &str, name: &str, paused: bool) -> Result<bool, crate::Error> {
+        set_paused(&self.pool, guild_id, name, paused).await
+    }
+
+    /// Gives up to `limit` active schedules without a next run (resumed ones) their next run, returning how many
+    /// were rescheduled
+    pub async fn reschedule(&self, limit: i64) -> Result<usize, crate::Error> {
+        let unscheduled: Vec<ScheduledMessage> = sqlx::query_as(&format!(
+            "SELECT {SCHEDULE_COLUMNS} FROM scheduled_messages WHERE NOT paused AND next_run_at IS NULL LIMIT $1"
+        ))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = Utc::now();
+        for schedule in unscheduled.iter() {
+            let next_run_at = schedule.next_after(now).unwrap_or_else(|e| {
+                log::error!("Invalid scheduled message {}: {e}", schedule.id);
+                None
+            });
+            // Schedules which never run again are paused so they are not picked up on every poll
+            sqlx::query(
+                "UPDATE scheduled_messages SET next_run_at = $3, paused = ($3 IS NULL)
+                WHERE id = $1 AND updated_at = $2 AND NOT paused AND next_run_at IS NULL"
+            )
+            .bind(schedule.id)
+            .bind(schedule.updated_at)
+            .bind(next_run_at)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(unscheduled.len())
+    }
+
+    /// Claims up to `limit` due schedules for posting
+    ///
+    /// Claimed schedules are leased for `lease`, if the master dies before recording the result of the run it is
+    /// retried once the lease expires
+    pub async fn claim_due(&self, limit: i64, lease: Duration) -> Result<Vec<ScheduledMessage>, crate::Error> {
+        let schedules = sqlx::query_as(&format!(
+            "UPDATE scheduled_messages SET attempts = attempts + 1, next_run_at = $2
+            WHERE id IN (
+                SELECT id FROM scheduled_messages WHERE NOT paused AND next_run_at <= NOW()
+                ORDER BY next_run_at LIMIT $1 FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {SCHEDULE_COLUMNS}"
+        ))
+        .bind(limit)
+        .bind(Utc::now() + chrono::Duration::from_std(lease)?)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(schedules)
+    }
+
+    /// Records a posted run of a claimed schedule (unless it was changed since being claimed), pausing it if it
+    /// never runs again
+    pub async fn mark_posted(&self, schedule: &ScheduledMessage, next_run_at: Option<DateTime<Utc>>) -> Result<(), crate::Error> {
+        sqlx::query(
+            "UPDATE scheduled_messages SET attempts = 0, last_error = NULL, last_run_at = NOW(), next_run_at = $3, paused = ($3 IS NULL)
+            WHERE id = $1 AND updated_at = $2"
+        )
+        .bind(schedule.id)
+        .bind(schedule.updated_at)
+        .bind(next_run_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Records a failed run of a claimed schedule (unless it was changed since being claimed), retrying it after
+    /// `delay` or pausing the schedule if `delay` is `None`
+    ///
+    /// Returns whether the failure was recorded
+    pub async fn mark_failed(&self, schedule: &ScheduledMessage, error: &str, delay: Option<Duration>) -> Result<bool, crate::Error> {
+        let res = match delay {
+            Some(delay) => {
+                sqlx::query("UPDATE scheduled_messages SET last_error = $3, next_run_at = $4 WHERE id = $1 AND updated_at = $2")
+                    .bind(schedule.id)
+                    .bind(schedule.updated_at)
+                    .bind(error)
+                    .bind(Utc::now() + chrono::Duration::from_std(delay)?)
+                    .execute(&self.pool)
+                    .await?
+            }
+            None => {
+                sqlx::query(
+                    "UPDATE scheduled_messages SET paused = TRUE, next_run_at = NULL, attempts = 0, last_error = $3, updated_at = NOW()
+                    WHERE id = $1 AND updated_at = $2"
+                )
+                .bind(schedule.id)
+                .bind(schedule.updated_at)
+                .bind(error)
+                .execute(&self.pool)
+                .await?
+            }
+        };
+        Ok(res.rows_affected() > 0)
+    }
+}
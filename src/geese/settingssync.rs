@@ -0,0 +1,96 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::geese::tenantstate::TenantState;
+use crate::worker::workervmmanager::Id;
+
+/// A versioned update of the tenant state of a tenant, sent by the master to the worker owning the tenant
+#[derive(Serialize, Deserialize)]
+pub enum TenantStateSync {
+    /// The whole tenant state, sent for the first update of a tenant and whenever the worker is out of sync
+    Full {
+        version: u64,
+        tenant_state: TenantState,
+    },
+    /// The fields of the tenant state changed since `base_version`, which the worker must have for it to apply
+    Diff {
+        base_version: u64,
+        version: u64,
+        changes: Map<String, Value>,
+    },
+}
+
+/// What a worker did with a `TenantStateSync`
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SyncOutcome {
+    /// The update was applied, `reloaded` if the VM of the tenant was dropped as a result
+    Applied { reloaded: bool },
+    /// The update was no newer than the tenant state of the worker and was ignored
+    Stale,
+    /// The worker is not at the base version of the diff, the full tenant state must be sent instead
+    Resync,
+}
+
+/// Returns the fields of `new` which differ from `old`
+pub fn diff(old: &TenantState, new: &TenantState) -> Result<Map<String, Value>, crate::Error> {
+    let (Value::Object(old), Value::Object(new)) = (serde_json::to_value(old)?, serde_json::to_value(new)?) else {
+        return Err("Tenant state did not serialize to an object".into());
+    };
+    Ok(new.into_iter().filter(|(field, value)| old.get(field) != Some(value)).collect())
+}
+
+/// Applies the changed fields of a diff to a tenant state
+pub fn apply(ts: &TenantState, changes: Map<String, Value>) -> Result<TenantState, crate::Error> {
+    let Value::Object(mut fields) = serde_json::to_value(ts)? else {
+        return Err("Tenant state did not serialize to an object".into());
+    };
+    fields.extend(changes);
+    Ok(serde_json::from_value(Value::Object(fields))?)
+}
+
+/// Tracks the tenant states the master last synced to a worker, to send diffs against
+///
+/// Versions are shared by all tenants of the worker and only ever increase. They start from the current time so
+/// the versions of a new connection to a worker are always ahead of those of a previous connection
+#[derive(Clone)]
+pub struct SyncTracker {
+    next_version: Arc<AtomicU64>,
+    synced: Arc<DashMap<Id, (u64, TenantState)>>,
+}
+
+impl Default for SyncTracker {
+    fn default() -> Self {
+        let start = chrono::Utc::now().timestamp_micros().max(0) as u64;
+        Self { next_version: Arc::new(AtomicU64::new(start)), synced: Arc::default() }
+    }
+}
+
+impl SyncTracker {
+    /// Records a new tenant state for a tenant, returning the update to send to the worker
+    pub fn update(&self, id: Id, ts: TenantState) -> Result<TenantStateSync, crate::Error> {
+        // Held across the diff so concurrent updates of a tenant are versioned in order
+        let mut entry = self.synced.entry(id).or_insert_with(|| (0, TenantState::default()));
+        let version = self.next_version.fetch_add(1, Ordering::Relaxed) + 1;
+        let sync = match entry.0 {
+            0 => TenantStateSync::Full { version, tenant_state: ts.clone() },
+            base_version => TenantStateSync::Diff { base_version, version, changes: diff(&entry.1, &ts)? },
+        };
+        *entry = (version, ts);
+        Ok(sync)
+    }
+
+    /// Returns the latest tenant state synced for a tenant in full, for workers out of sync
+    pub fn full(&self, id: Id) -> Option<TenantStateSync> {
+        let entry = self.synced.get(&id)?;
+        Some(TenantStateSync::Full { version: entry.0, tenant_state: entry.1.clone() })
+    }
+
+    /// Forgets a tenant which was dropped from the worker
+    pub fn forget(&self, id: Id) {
+        self.synced.remove(&id);
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use moka::Expiry;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::config::RedisConfig;
+
+/// Timeout of a Redis command, including (re)connecting
+const REDIS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Maximum number of entries of the in-memory cache
+const MEMORY_CAPACITY: u64 = 100_000;
+
+/// Number of claims after which the in-memory cache sweeps expired members out of every claim set
+const MEMORY_CLAIM_SWEEP_INTERVAL: usize = 1024;
+
+/// Claims a member of a claim set (a sorted set scored by expiry), see `SharedCache::claim`
+///
+/// Replies 1 if claimed, 0 if already claimed and -1 if the set is full
+const REDIS_CLAIM_SCRIPT: &str = r#"
+local now = tonumber(ARGV[2])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+if redis.call('ZSCORE', KEYS[1], ARGV[1]) then
+    return 0
+end
+if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[4]) then
+    return -1
+end
+local expiry = now + tonumber(ARGV[3])
+redis.call('ZADD', KEYS[1], expiry, ARGV[1])
+local last = redis.call('ZRANGE', KEYS[1], -1, -1, 'WITHSCORES')
+redis.call('PEXPIREAT', KEYS[1], last[2])
+return 1
+"#;
+
+static SHARED_CACHE: LazyLock<Arc<dyn SharedCache>> = LazyLock::new(<dyn SharedCache>::from_config);
+
+/// The outcome of claiming a member of a claim set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    /// The member was not claimed (or its claim expired) and is now claimed
+    Claimed,
+    /// The member is already claimed
+    Exists,
+    /// The member is not claimed but the set already has the maximum number of claimed members
+    Full,
+}
+
+/// A cache shared by every process using it, in-memory (per-process) for single-host deployments and Redis
+/// for multi-host ones
+///
+/// Values are opaque bytes, see the `*_json` helpers on `dyn SharedCache` for typed access
+#[tonic::async_trait]
+pub trait SharedCache: Send + Sync {
+    /// Returns the value of a key, if set and not expired
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, crate::Error>;
+
+    /// Sets the value of a key, expiring after `ttl`
+    async fn set(&self, key: &str, value: Bytes, ttl: Duration) -> Result<(), crate::Error>;
+
+    /// Sets the value of a key only if it is not set, returning whether it was set
+    ///
+    /// This is atomic across every process sharing the cache, making it usable for deduplication
+    async fn insert_new(&self, key: &str, value: Bytes, ttl: Duration) -> Result<bool, crate::Error>;
+
+    /// Deletes a key
+    async fn delete(&self, key: &str) -> Result<(), crate::Error>;
+
+    /// Claims a member of a set for `ttl`, unless already claimed or the set has `max` claimed members
+    ///
+    /// Like `insert_new` this is atomic across every process sharing the cache, but members of a set are counted
+    /// together so the number of claims can be bounded (e.g. per tenant)
+    async fn claim(&self, set: &str, member: &str, ttl: Duration, max: usize) -> Result<Claim, crate::Error>;
+}
+
+impl dyn SharedCache {
+    /// Creates the shared cache from the config, Redis if configured and in-memory otherwise
+    pub fn from_config() -> Arc<dyn SharedCache> {
+        match crate::CONFIG.redis.as_ref() {
+            Some(cfg) => Arc::new(RedisCache::new(cfg)),
+            None => Arc::new(MemoryCache::default()),
+        }
+    }
+
+    /// Returns the shared cache of the process, created from the config on first use
+    ///
+    /// Everything in a process should use this so the in-memory cache is actually shared within the process
+    pub fn global() -> Arc<dyn SharedCache> {
+        SHARED_CACHE.clone()
+    }
+
+    /// Returns the deserialized value of a key
+    ///
+    /// Errors (Redis being unreachable, undeserializable values) are logged and treated as a miss
+    pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        match self.get(key).await {
+            Ok(Some(value)) => match serde_json::from_slice(&value) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    log::warn!("Failed to deserialize cached value of {key}: {e}");
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(e) => {
+                log::warn!("Failed to get {key} from the shared cache: {e}");
+                None
+            }
+        }
+    }
+
+    /// Sets a key to the serialized value, errors are logged
+    pub async fn set_json<T: Serialize>(&self, key: &str, value: &T, ttl: Duration) {
+        let value = match serde_json::to_vec(value) {
+            Ok(value) => Bytes::from(value),
+            Err(e) => {
+                log::warn!("Failed to serialize value of {key} for the shared cache: {e}");
+                return;
+            }
+        };
+        if let Err(e) = self.set(key, value, ttl).await {
+            log::warn!("Failed to set {key} in the shared cache: {e}");
+        }
+    }
+
+    /// Returns the cached value of a key, or computes and caches it
+    ///
+    /// Unlike moka's `try_get_with`, concurrent misses are not coalesced (and can't be across processes)
+    pub async fn get_or_try_insert<T, F>(&self, key: &str, ttl: Duration, init: F) -> Result<T, crate::Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T, crate::Error>>,
+    {
+        if let Some(value) = self.get_json(key).await {
+            return Ok(value);
+        }
+        let value = init.await?;
+        self.set_json(key, &value, ttl).await;
+        Ok(value)
+    }
+}
+
+/// A cached value along with its time to live
+#[derive(Clone)]
+struct MemoryEntry {
+    value: Bytes,
+    ttl: Duration,
+}
+
+struct MemoryExpiry;
+
+impl Expiry<String, MemoryEntry> for MemoryExpiry {
+    fn expire_after_create(&self, _key: &String, value: &MemoryEntry, _created_at: Instant) -> Option<Duration> {
+        Some(value.ttl)
+    }
+
+    fn expire_after_update(&self, _key: &String, value: &MemoryEntry, _updated_at: Instant, _duration_until_expiry: Option<Duration>) -> Option<Duration> {
+        Some(value.ttl)
+    }
+}
+
+/// In-memory cache, only shared within the process
+pub struct MemoryCache {
+    cache: moka::future::Cache<String, MemoryEntry>,
+    /// Claim sets, mapping their members to when their claim expires
+    claims: Mutex<HashMap<String, HashMap<String, Instant>>>,
+    claim_count: AtomicUsize,
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self {
+            cache: moka::future::Cache::builder()
+                .max_capacity(MEMORY_CAPACITY)
+                .expire_after(MemoryExpiry)
+                .build(),
+            claims: Mutex::default(),
+            claim_count: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SharedCache for MemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, crate::Error> {
+        Ok(self.cache.get(key).await.map(|e| e.value))
+    }
+
+    async fn set(&self, key: &str, value: Bytes, ttl: Duration) -> Result<(), crate::Error> {
+        self.cache.insert(key.to_string(), MemoryEntry { value, ttl }).await;
+        Ok(())
+    }
+
+    async fn insert_new(&self, key: &str, value: Bytes, ttl: Duration) -> Result<bool, crate::Error> {
+        let entry = self.cache.entry(key.to_string()).or_insert_with(async { MemoryEntry { value, ttl } }).await;
+        Ok(entry.is_fresh())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), crate::Error> {
+        self.cache.invalidate(key).await;
+        Ok(())
+    }
+
+    async fn claim(&self, set: &str, member: &str, ttl: Duration, max: usize) -> Result<Claim, crate::Error> {
+        let now = Instant::now();
+        let mut claims = self.claims.lock().expect("claims lock poisoned");
+
+        // Sets which are no longer claimed into are otherwise never pruned
+        if self.claim_count.fetch_add(1, Ordering::Relaxed) % MEMORY_CLAIM_SWEEP_INTERVAL == 0 {
+            claims.retain(|_, members| {
+                members.retain(|_, expiry| *expiry > now);
+                !members.is_empty()
+            });
+        }
+
+        let members = claims.entry(set.to_string()).or_default();
+        members.retain(|_, expiry| *expiry > now);
+        if members.contains_key(member) {
+            return Ok(Claim::Exists);
+        }
+        if members.len() >= max {
+            return Ok(Claim::Full);
+        }
+        members.insert(member.to_string(), now + ttl);
+        Ok(Claim::Claimed)
+    }
+}
+
+/// Redis cache, shared by every process connected to the same Redis
+///
+/// Connections are opened lazily and reopened after any error
+pub struct RedisCache {
+    config: &'static RedisConfig,
+    conns: Vec<tokio::sync::Mutex<Option<RedisConn>>>,
+    next: AtomicUsize,
+}
+
+impl RedisCache {
+    pub fn new(config: &'static RedisConfig) -> Self {
+        Self {
+            config,
+            conns: (0..config.pool_size.max(1)).map(|_| tokio::sync::Mutex::new(None)).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Runs a command on the next connection of the pool
+    async fn cmd(&self, args: &[&[u8]]) -> Result<Reply, crate::Error> {
+        let slot = &self.conns[self.next.fetch_add(1, Ordering::Relaxed) % self.conns.len()];
+        let mut conn = slot.lock().await;
+
+        let res = tokio::time::timeout(REDIS_TIMEOUT, async {
+            if conn.is_none() {
+                *conn = Some(RedisConn::connect(self.config).await?);
+            }
+            conn.as_mut().expect("connected above").cmd(args).await
+        })
+        .await
+        .unwrap_or_else(|_| Err("Redis command timed out".into()));
+
+        if res.is_err() {
+            // The connection may be in an unknown state (e.g. a reply left unread), reconnect next time
+            *conn = None;
+        }
+        res
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{key}", self.config.key_prefix)
+    }
+}
+
+#[tonic::async_trait]
+impl SharedCache for RedisCache {
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, crate::Error> {
+        match self.cmd(&[b"GET", self.key(key).as_bytes()]).await? {
+            Reply::Bulk(value) => Ok(value),
+            r => Err(format!("Unexpected reply to GET: {r:?}").into()),
+        }
+    }
+
+    async fn set(&self, key: &str, value: Bytes, ttl: Duration) -> Result<(), crate::Error> {
+        let ttl = ttl.as_millis().max(1).to_string();
+        self.cmd(&[b"SET", self.key(key).as_bytes(), &value, b"PX", ttl.as_bytes()]).await?;
+        Ok(())
+    }
+
+    async fn insert_new(&self, key: &str, value: Bytes, ttl: Duration) -> Result<bool, crate::Error> {
+        let ttl = ttl.as_millis().max(1).to_string();
+        // SET NX replies OK if set and nil if the key already exists
+        match self.cmd(&[b"SET", self.key(key).as_bytes(), &value, b"PX", ttl.as_bytes(), b"NX"]).await? {
+            Reply::Status => Ok(true),
+            Reply::Bulk(None) => Ok(false),
+            r => Err(format!("Unexpected reply to SET NX: {r:?}").into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), crate::Error> {
+        self.cmd(&[b"DEL", self.key(key).as_bytes()]).await?;
+        Ok(())
+    }
+
+    async fn claim(&self, set: &str, member: &str, ttl: Duration, max: usize) -> Result<Claim, crate::Error> {
+        // Expiries are scored by the (wall clock) time of the process claiming, so hosts need synchronized clocks
+        let now = chrono::Utc::now().timestamp_millis().to_string();
+        let ttl = ttl.as_millis().max(1).to_string();
+        let max = max.to_string();
+        let reply = self.cmd(&[
+            b"EVAL", REDIS_CLAIM_SCRIPT.as_bytes(), b"1", self.key(set).as_bytes(),
+            member.as_bytes(), now.as_bytes(), ttl.as_bytes(), max.as_bytes(),
+        ]).await?;
+        match reply {
+            Reply::Integer(1) => Ok(Claim::Claimed),
+            Reply::Integer(0) => Ok(Claim::Exists),
+            Reply::Integer(-1) => Ok(Claim::Full),
+            r => Err(format!("Unexpected reply to claim script: {r:?}").into()),
+        }
+    }
+}
+
+/// A reply of Redis (RESP2), errors are returned as `Err`
+#[derive(Debug)]
+enum Reply {
+    /// A simple string (e.g. `OK`)
+    Status,
+    Integer(i64),
+    Bulk(Option<Bytes>),
+}
+
+/// Minimal Redis client, only supporting commands with non-array replies
+struct RedisConn {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl RedisConn {
+    async fn connect(config: &RedisConfig) -> Result<Self, crate::Error> {
+        let (read, writer) = TcpStream::connect(&config.addr).await?.into_split();
+        let mut conn = Self { reader: BufReader::new(read), writer };
+
+        if let Some(password) = config.password.as_deref() {
+            match config.username.as_deref() {
+                Some(username) => conn.cmd(&[b"AUTH", username.as_bytes(), password.as_bytes()]).await?,
+                None => conn.cmd(&[b"AUTH", password.as_bytes()]).await?,
+            };
+        }
+        if config.db != 0 {
+            conn.cmd(&[b"SELECT", config.db.to_string().as_bytes()]).await?;
+        }
+        Ok(conn)
+    }
+
+    async fn cmd(&mut self, args: &[&[u8]]) -> Result<Reply, crate::Error> {
+        let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            buf.extend_from_slice(arg);
+            buf.extend_from_slice(b"\r\n");
+        }
+        self.writer.write_all(&buf).await?;
+        self.read_reply().await
+    }
+
+    async fn read_reply(&mut self) -> Result<Reply, crate::Error> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err("Redis connection closed".into());
+        }
+        let line = line.trim_end();
+        let (kind, rest) = line.split_at_checked(1).ok_or("Empty reply from Redis")?;
+
+        match kind {
+            "+" => Ok(Reply::Status),
+            "-" => Err(format!("Redis error: {rest}").into()),
+            ":" => Ok(Reply::Integer(rest.parse()?)),
+            "$" => {
+                let len: i64 = rest.parse()?;
+                if len < 0 {
+                    return Ok(Reply::Bulk(None));
+                }
+                // Value followed by \r\n
+                let mut value = vec![0u8; len as usize + 2];
+                self.reader.read_exact(&mut value).await?;
+                value.truncate(len as usize);
+                Ok(Reply::Bulk(Some(Bytes::from(value))))
+            }
+            _ => Err(format!("Unsupported reply from Redis: {line}").into()),
+        }
+    }
+}
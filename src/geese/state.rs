@@ -1,4 +1,6 @@
 use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use khronos_runtime::primitives::blob::Blob;
@@ -6,14 +8,41 @@ use khronos_runtime::{primitives::opaque::Opaque, rt::mlua::prelude::*};
 use khronos_runtime::utils::khronos_value::KhronosValue;
 use khronos_runtime::core::datetime::DateTime as LuaDateTime;
 use rand::distr::{Alphanumeric, SampleString};
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, Postgres};
 
-use crate::geese::tenantstate::{DEFAULT_EVENTS, TenantState, TenantStateDb};
+use crate::geese::dbpools::PoolKind;
+use crate::geese::dbrouter::DbRouter;
+use crate::geese::namepolicy::NamePolicy;
+use crate::geese::schedules::{self, ScheduleSpec, ScheduledMessage};
+use crate::geese::nukeprotection::NukeProtectionConfig;
+use crate::geese::webhookspam::WebhookSpamConfig;
+use crate::geese::autopublish::AutoPublishConfig;
+use crate::geese::threadpolicies::ThreadPoliciesConfig;
+use crate::geese::emojiusage::{self, EmojiItem, EmojiKind, EmojiUsageConfig, EmojiUsageStats};
+use crate::geese::activity::{self, ActivityConfig, ActivityHeatmap};
+use crate::geese::auditcorrelation::AuditCorrelationConfig;
+use crate::geese::voiceidle::VoiceIdleConfig;
+use crate::geese::resultcache::ResultCacheConfig;
+use crate::geese::stickyroles::StickyRolesConfig;
+use crate::geese::sharedcache::{Claim, SharedCache};
+use crate::geese::tenantstate::{self, DEFAULT_EVENTS, TenantState, TenantStateDb};
 use crate::geese::urlsign::VerifiedUrl;
-use crate::worker::limits::{KV_MAX_KEY_LENGTH, KV_SIGN_URL_EXPIRATION_SECONDS, MAX_OBJ_STORAGE_BYTES};
+use crate::geese::watchlist::{self, MAX_WATCHLIST_ENTRIES, MAX_WATCHLIST_NOTE_LENGTH, Severity, WatchlistEntry};
+use crate::geese::workflows::{self, MAX_RUNNING_WORKFLOWS, WORKFLOW_COLUMNS, WorkflowInstance};
+use crate::worker::altdetect::AltSensitivity;
+use crate::worker::plugins::{PLUGINS, is_known_plugin};
+use crate::worker::limits::{KV_MAX_KEY_LENGTH, KV_SIGN_URL_EXPIRATION_SECONDS, MAX_OBJ_STORAGE_BYTES, MAX_ONCE_KEYS, MAX_ONCE_TTL};
 use crate::worker::workervmmanager::Id;
 
+/// KV scope stings are stored in (see `auxutils/stingmanager.luau`)
+pub const STINGS_SCOPE: &str = "builtins.stings";
+
+/// Internal KV scope secrets (such as webhook signing secrets) are stored in, never readable by templates
+pub const SECRETS_SCOPE: &str = "#secrets";
+
 /// StateOp main op enum that is accessible to luau
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "op")]
 pub enum StateOp {
     KvFind {
@@ -78,6 +107,245 @@ pub enum StateOp {
     UnsubscribeEvent {
         event: String,
         system: String,
+    },
+    /// Sets (or with None, clears) the channel modmail threads are opened in
+    SetModmailChannel {
+        channel_id: Option<String>,
+    },
+    /// Sets (or with None, clears) the channel appeals are posted to for review
+    SetAppealsChannel {
+        channel_id: Option<String>,
+    },
+    /// Sets (or with None, clears) the channel watchlist digests are posted to
+    SetWatchlistChannel {
+        channel_id: Option<String>,
+    },
+    /// Adds a user to the watchlist, updating their entry if already watchlisted
+    WatchlistAdd {
+        user_id: String,
+        severity: String,
+        note: String,
+        added_by: Option<String>,
+    },
+    /// Removes a user from the watchlist
+    WatchlistRemove {
+        user_id: String,
+    },
+    /// Returns the watchlist entry of a user, if they are watchlisted
+    WatchlistGet {
+        user_id: String,
+    },
+    /// Returns every watchlisted user
+    WatchlistList {},
+    /// Sets (or with None, disables) how readily joining members are flagged as alts
+    SetAltDetection {
+        sensitivity: Option<String>,
+    },
+    /// Enables or disables tracking of the invites members join through
+    SetInviteTracking {
+        enabled: bool,
+    },
+    /// Sets (or with None, clears) the rules the names of members must follow
+    SetNamePolicy {
+        policy: Option<NamePolicy>,
+    },
+    /// Sets (or with None, disables) how the roles of members leaving are restored when they rejoin
+    ///
+    /// Disabling sticky roles deletes the snapshots of members who left
+    SetStickyRoles {
+        config: Option<StickyRolesConfig>,
+    },
+    /// Enables or disables periodic snapshots of channel overwrites and role permissions
+    ///
+    /// Disabling permission snapshots deletes the last snapshot
+    SetPermissionSnapshots {
+        enabled: bool,
+    },
+    /// Sets (or with None, disables) how destructive actions are detected and responded to
+    SetNukeProtection {
+        config: Option<NukeProtectionConfig>,
+    },
+    /// Sets (or with None, disables) how spam sent through webhooks is detected
+    SetWebhookSpam {
+        config: Option<WebhookSpamConfig>,
+    },
+    /// Sets (or with None, disables) automatic publishing of messages sent in announcement channels
+    SetAutoPublish {
+        config: Option<AutoPublishConfig>,
+    },
+    /// Sets (or with no config, removes) the thread policies of a guild
+    SetThreadPolicies {
+        config: Option<ThreadPoliciesConfig>,
+    },
+    /// Sets (or with no config, disables) emoji and sticker usage tracking of a guild
+    SetEmojiUsage {
+        config: Option<EmojiUsageConfig>,
+    },
+    /// Sets (or with no config, disables) message activity tracking of a guild
+    SetActivityTracking {
+        config: Option<ActivityConfig>,
+    },
+    /// Sets (or with no config, disables) audit log correlation of the gateway events of a guild
+    SetAuditCorrelation {
+        config: Option<AuditCorrelationConfig>,
+    },
+    /// Sets (or with no config, disables) voice idle detection of a guild
+    SetVoiceIdle {
+        config: Option<VoiceIdleConfig>,
+    },
+    /// Sets (or clears) the templates whose results are cached
+    SetResultCache {
+        config: Option<ResultCacheConfig>,
+    },
+    /// Sets the plugins the templates of a guild may not use
+    SetDisabledPlugins {
+        plugins: Vec<String>,
+    },
+    /// Claims a key for `ttl` seconds, returning whether it was not already claimed
+    ///
+    /// Claims are kept in the shared cache rather than the database, so are not rolled back with the other ops
+    Once {
+        key: String,
+        ttl: u64,
+    },
+    /// Starts an instance of a workflow at `step`, unless an instance with the same key is already running
+    WorkflowStart {
+        workflow: String,
+        key: String,
+        step: String,
+        data: KhronosValue,
+    },
+    /// Returns the running instance of a workflow with the given key, if any
+    WorkflowGet {
+        workflow: String,
+        key: String,
+    },
+    /// Moves an instance on to `step`, which runs after `delay` seconds and/or once `signal` is sent
+    /// (immediately if neither is set)
+    WorkflowAdvance {
+        id: i64,
+        seq: i32,
+        step: String,
+        data: KhronosValue,
+        delay: Option<u64>,
+        signal: Option<String>,
+    },
+    /// Completes an instance
+    WorkflowComplete {
+        id: i64,
+        seq: i32,
+        data: KhronosValue,
+    },
+    /// Sends a signal to the running instance of a workflow with the given key, running its current step now if it
+    /// waits for the signal
+    WorkflowSignal {
+        workflow: String,
+        key: String,
+        signal: String,
+        data: KhronosValue,
+    },
+    /// Cancels the running instance of a workflow with the given key
+    WorkflowCancel {
+        workflow: String,
+        key: String,
+    },
+    /// Lists the scheduled messages of the guild
+    ScheduleList {},
+    /// Creates or replaces a scheduled message of the guild
+    ScheduleSet {
+        name: String,
+        schedule: ScheduleSpec,
+    },
+    /// Deletes a scheduled message of the guild
+    ScheduleDelete {
+        name: String,
+    },
+    /// Pauses or resumes a scheduled message of the guild
+    SchedulePause {
+        name: String,
+        paused: bool,
+    },
+    /// Returns the most used emojis and stickers of the guild over the last `days` days
+    EmojiUsageTop {
+        days: u32,
+        kind: Option<EmojiKind>,
+        limit: i64,
+    },
+    /// Returns which of the given emojis and stickers of the guild were not used in the last `days` days
+    EmojiUsageUnused {
+        days: u32,
+        items: Vec<EmojiItem>,
+    },
+    /// Returns the message activity of the guild (or one of its channels) over the last `days` days
+    ActivityHeatmap {
+        days: u32,
+        channel_id: Option<dapi::ChannelId>,
+    }
+}
+
+impl StateOp {
+    /// Returns the name of the op
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::KvFind { .. } => "KvFind",
+            Self::KvGet { .. } => "KvGet",
+            Self::KvGetWithBlob { .. } => "KvGetWithBlob",
+            Self::KvSignUrl { .. } => "KvSignUrl",
+            Self::KvSet { .. } => "KvSet",
+            Self::KvDelete { .. } => "KvDelete",
+            Self::GlobalKvFind { .. } => "GlobalKvFind",
+            Self::GlobalKvGet { .. } => "GlobalKvGet",
+            Self::GlobalKvCreate { .. } => "GlobalKvCreate",
+            Self::GlobalKvDelete { .. } => "GlobalKvDelete",
+            Self::GlobalKvGetData { .. } => "GlobalKvGetData",
+            Self::SubscribeEvent { .. } => "SubscribeEvent",
+            Self::UnsubscribeEvent { .. } => "UnsubscribeEvent",
+            Self::SetModmailChannel { .. } => "SetModmailChannel",
+            Self::SetAppealsChannel { .. } => "SetAppealsChannel",
+            Self::SetWatchlistChannel { .. } => "SetWatchlistChannel",
+            Self::WatchlistAdd { .. } => "WatchlistAdd",
+            Self::WatchlistRemove { .. } => "WatchlistRemove",
+            Self::WatchlistGet { .. } => "WatchlistGet",
+            Self::WatchlistList { .. } => "WatchlistList",
+            Self::SetAltDetection { .. } => "SetAltDetection",
+            Self::SetInviteTracking { .. } => "SetInviteTracking",
+            Self::SetNamePolicy { .. } => "SetNamePolicy",
+            Self::SetStickyRoles { .. } => "SetStickyRoles",
+            Self::SetPermissionSnapshots { .. } => "SetPermissionSnapshots",
+            Self::SetNukeProtection { .. } => "SetNukeProtection",
+            Self::SetWebhookSpam { .. } => "SetWebhookSpam",
+            Self::SetAutoPublish { .. } => "SetAutoPublish",
+            Self::SetThreadPolicies { .. } => "SetThreadPolicies",
+            Self::SetEmojiUsage { .. } => "SetEmojiUsage",
+            Self::SetActivityTracking { .. } => "SetActivityTracking",
+            Self::SetAuditCorrelation { .. } => "SetAuditCorrelation",
+            Self::SetVoiceIdle { .. } => "SetVoiceIdle",
+            Self::SetResultCache { .. } => "SetResultCache",
+            Self::SetDisabledPlugins { .. } => "SetDisabledPlugins",
+            Self::Once { .. } => "Once",
+            Self::WorkflowStart { .. } => "WorkflowStart",
+            Self::WorkflowGet { .. } => "WorkflowGet",
+            Self::WorkflowAdvance { .. } => "WorkflowAdvance",
+            Self::WorkflowComplete { .. } => "WorkflowComplete",
+            Self::WorkflowSignal { .. } => "WorkflowSignal",
+            Self::WorkflowCancel { .. } => "WorkflowCancel",
+            Self::ScheduleList { .. } => "ScheduleList",
+            Self::ScheduleSet { .. } => "ScheduleSet",
+            Self::ScheduleDelete { .. } => "ScheduleDelete",
+            Self::SchedulePause { .. } => "SchedulePause",
+            Self::EmojiUsageTop { .. } => "EmojiUsageTop",
+            Self::EmojiUsageUnused { .. } => "EmojiUsageUnused",
+            Self::ActivityHeatmap { .. } => "ActivityHeatmap",
+        }
+    }
+
+    /// Returns the (tenant) KV scope the op writes to, if any
+    pub fn written_scope(&self) -> Option<&str> {
+        match self {
+            Self::KvSet { scope, .. } => Some(scope),
+            Self::KvDelete { scope, .. } => Some(scope),
+            _ => None,
+        }
     }
 }
 
@@ -128,6 +396,10 @@ impl FastStateReq {
         for op in ops {
             match op {
                 Self::KvSignUrl { key, scope } => {
+                    // Faststate ops are only made by templates
+                    if !StateDbFlags::empty().can_read_scope(&scope) {
+                        return Err("Cannot read from this internal scope".into())
+                    }
                     let vurl = crate::geese::urlsign::create_url(tid, &key, &scope, KV_SIGN_URL_EXPIRATION_SECONDS)?;
                     results.push(StateExecResult::KvSignUrl { url: vurl, expiry: KV_SIGN_URL_EXPIRATION_SECONDS });
                 }
@@ -141,12 +413,62 @@ impl FastStateReq {
 impl StateOp {
     /// Returns true if the operation may alter the tenant state
     fn alters_tenant_state(&self) -> bool {
-        matches!(self, Self::SubscribeEvent { .. } | Self::UnsubscribeEvent { .. })
+        matches!(self, Self::SubscribeEvent { .. } | Self::UnsubscribeEvent { .. } | Self::SetModmailChannel { .. } | Self::SetAppealsChannel { .. }
+            | Self::SetWatchlistChannel { .. } | Self::SetAltDetection { .. } | Self::SetInviteTracking { .. }
+            | Self::SetNamePolicy { .. } | Self::SetStickyRoles { .. } | Self::SetPermissionSnapshots { .. }
+            | Self::SetNukeProtection { .. } | Self::SetWebhookSpam { .. } | Self::SetAutoPublish { .. } | Self::SetThreadPolicies { .. } | Self::SetEmojiUsage { .. } | Self::SetActivityTracking { .. } | Self::SetAuditCorrelation { .. } | Self::SetVoiceIdle { .. } | Self::SetResultCache { .. } | Self::SetDisabledPlugins { .. })
+    }
+
+    /// Returns true if the operation may alter the watchlist, which workers cache outside of the tenant state
+    pub fn alters_watchlist(&self) -> bool {
+        matches!(self, Self::WatchlistAdd { .. } | Self::WatchlistRemove { .. })
+    }
+
+    /// Returns the connection pool partition the op runs on
+    fn pool_kind(&self) -> PoolKind {
+        match self {
+            Self::KvFind { scope, .. }
+            | Self::KvGet { scope, .. }
+            | Self::KvGetWithBlob { scope, .. }
+            | Self::KvSignUrl { scope, .. }
+            | Self::KvDelete { scope, .. } if scope == STINGS_SCOPE => PoolKind::Stings,
+            Self::KvSet { scope, .. } if scope == STINGS_SCOPE => PoolKind::Stings,
+            Self::EmojiUsageTop { .. } | Self::EmojiUsageUnused { .. } | Self::ActivityHeatmap { .. } => PoolKind::Analytics,
+            Self::SubscribeEvent { .. } | Self::UnsubscribeEvent { .. } | Self::SetModmailChannel { .. } | Self::SetAppealsChannel { .. }
+            | Self::SetWatchlistChannel { .. } | Self::SetAltDetection { .. } | Self::SetInviteTracking { .. }
+            | Self::SetNamePolicy { .. } | Self::SetStickyRoles { .. } | Self::SetPermissionSnapshots { .. }
+            | Self::SetNukeProtection { .. } | Self::SetWebhookSpam { .. } | Self::SetAutoPublish { .. } | Self::SetThreadPolicies { .. } | Self::SetEmojiUsage { .. } | Self::SetActivityTracking { .. } | Self::SetAuditCorrelation { .. } | Self::SetVoiceIdle { .. } | Self::SetResultCache { .. } | Self::SetDisabledPlugins { .. } => PoolKind::Settings,
+            _ => PoolKind::Kv,
+        }
+    }
+
+    /// Returns the connection pool partition a set of ops runs on (in one transaction)
+    ///
+    /// Ops altering the tenant state need the tenant state to be refetched, so run on the settings partition
+    fn pool_kind_of(ops: &[StateOp]) -> PoolKind {
+        if ops.iter().any(|op| op.alters_tenant_state()) {
+            return PoolKind::Settings;
+        }
+        match ops.first().map(|op| op.pool_kind()) {
+            Some(kind) if ops.iter().all(|op| op.pool_kind() == kind) => kind,
+            _ => PoolKind::Kv,
+        }
+    }
+
+    /// Returns true if the operation has no side effects
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::KvFind { .. } | Self::KvGet { .. } | Self::KvGetWithBlob { .. } | Self::KvSignUrl { .. }
+            | Self::GlobalKvFind { .. } | Self::GlobalKvGet { .. } | Self::GlobalKvGetData { .. } | Self::WorkflowGet { .. }
+            | Self::ScheduleList { .. } | Self::EmojiUsageTop { .. } | Self::EmojiUsageUnused { .. } | Self::ActivityHeatmap { .. }
+            | Self::WatchlistGet { .. } | Self::WatchlistList { .. }
+        )
     }
 }
 
 impl FromLua for StateOp {
-    fn from_lua(value: LuaValue, _lua: &Lua) -> LuaResult<Self> {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
         let LuaValue::Table(tab) = value else {
             return Err(LuaError::FromLuaConversionError {
                 from: value.type_name(),
@@ -199,6 +521,184 @@ impl FromLua for StateOp {
                 let system = tab.get("system")?;
                 Ok(Self::UnsubscribeEvent { event, system })
             },
+            b"SetModmailChannel" => {
+                let channel_id = tab.get("channel_id")?;
+                Ok(Self::SetModmailChannel { channel_id })
+            },
+            b"SetAppealsChannel" => {
+                let channel_id = tab.get("channel_id")?;
+                Ok(Self::SetAppealsChannel { channel_id })
+            },
+            b"SetWatchlistChannel" => {
+                let channel_id = tab.get("channel_id")?;
+                Ok(Self::SetWatchlistChannel { channel_id })
+            },
+            b"WatchlistAdd" => {
+                let user_id = tab.get("user_id")?;
+                let severity = tab.get("severity")?;
+                let note = tab.get("note")?;
+                let added_by = tab.get("added_by")?;
+                Ok(Self::WatchlistAdd { user_id, severity, note, added_by })
+            },
+            b"WatchlistRemove" => {
+                let user_id = tab.get("user_id")?;
+                Ok(Self::WatchlistRemove { user_id })
+            },
+            b"WatchlistGet" => {
+                let user_id = tab.get("user_id")?;
+                Ok(Self::WatchlistGet { user_id })
+            },
+            b"WatchlistList" => Ok(Self::WatchlistList {}),
+            b"SetAltDetection" => {
+                let sensitivity = tab.get("sensitivity")?;
+                Ok(Self::SetAltDetection { sensitivity })
+            },
+            b"SetInviteTracking" => {
+                let enabled = tab.get("enabled")?;
+                Ok(Self::SetInviteTracking { enabled })
+            },
+            b"SetNamePolicy" => {
+                let policy: LuaValue = tab.get("policy")?;
+                let policy = lua.from_value(policy)?;
+                Ok(Self::SetNamePolicy { policy })
+            },
+            b"SetStickyRoles" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetStickyRoles { config })
+            },
+            b"SetPermissionSnapshots" => {
+                let enabled = tab.get("enabled")?;
+                Ok(Self::SetPermissionSnapshots { enabled })
+            },
+            b"SetNukeProtection" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetNukeProtection { config })
+            },
+            b"SetWebhookSpam" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetWebhookSpam { config })
+            },
+            b"SetAutoPublish" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetAutoPublish { config })
+            },
+            b"SetThreadPolicies" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetThreadPolicies { config })
+            },
+            b"SetEmojiUsage" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetEmojiUsage { config })
+            },
+            b"SetActivityTracking" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetActivityTracking { config })
+            },
+            b"SetAuditCorrelation" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetAuditCorrelation { config })
+            },
+            b"SetVoiceIdle" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetVoiceIdle { config })
+            },
+            b"SetResultCache" => {
+                let config: LuaValue = tab.get("config")?;
+                let config = lua.from_value(config)?;
+                Ok(Self::SetResultCache { config })
+            },
+            b"SetDisabledPlugins" => {
+                let plugins = tab.get("plugins")?;
+                Ok(Self::SetDisabledPlugins { plugins })
+            },
+            b"Once" => {
+                let key = tab.get("key")?;
+                let ttl = tab.get("ttl")?;
+                Ok(Self::Once { key, ttl })
+            },
+            b"WorkflowStart" => {
+                let workflow = tab.get("workflow")?;
+                let key = tab.get("key")?;
+                let step = tab.get("step")?;
+                let data = tab.get("data")?;
+                Ok(Self::WorkflowStart { workflow, key, step, data })
+            },
+            b"WorkflowGet" => {
+                let workflow = tab.get("workflow")?;
+                let key = tab.get("key")?;
+                Ok(Self::WorkflowGet { workflow, key })
+            },
+            b"WorkflowAdvance" => {
+                let id = tab.get("id")?;
+                let seq = tab.get("seq")?;
+                let step = tab.get("step")?;
+                let data = tab.get("data")?;
+                let delay = tab.get("delay")?;
+                let signal = tab.get("signal")?;
+                Ok(Self::WorkflowAdvance { id, seq, step, data, delay, signal })
+            },
+            b"WorkflowComplete" => {
+                let id = tab.get("id")?;
+                let seq = tab.get("seq")?;
+                let data = tab.get("data")?;
+                Ok(Self::WorkflowComplete { id, seq, data })
+            },
+            b"WorkflowSignal" => {
+                let workflow = tab.get("workflow")?;
+                let key = tab.get("key")?;
+                let signal = tab.get("signal")?;
+                let data = tab.get("data")?;
+                Ok(Self::WorkflowSignal { workflow, key, signal, data })
+            },
+            b"WorkflowCancel" => {
+                let workflow = tab.get("workflow")?;
+                let key = tab.get("key")?;
+                Ok(Self::WorkflowCancel { workflow, key })
+            },
+            b"ScheduleList" => Ok(Self::ScheduleList {}),
+            b"ScheduleSet" => {
+                let name = tab.get("name")?;
+                let schedule: LuaValue = tab.get("schedule")?;
+                let schedule = lua.from_value(schedule)?;
+                Ok(Self::ScheduleSet { name, schedule })
+            },
+            b"ScheduleDelete" => {
+                let name = tab.get("name")?;
+                Ok(Self::ScheduleDelete { name })
+            },
+            b"SchedulePause" => {
+                let name = tab.get("name")?;
+                let paused = tab.get("paused")?;
+                Ok(Self::SchedulePause { name, paused })
+            },
+            b"EmojiUsageTop" => {
+                let days = tab.get("days")?;
+                let kind: LuaValue = tab.get("kind")?;
+                let kind = lua.from_value(kind)?;
+                let limit = tab.get("limit")?;
+                Ok(Self::EmojiUsageTop { days, kind, limit })
+            },
+            b"EmojiUsageUnused" => {
+                let days = tab.get("days")?;
+                let items: LuaValue = tab.get("items")?;
+                let items = lua.from_value(items)?;
+                Ok(Self::EmojiUsageUnused { days, items })
+            },
+            b"ActivityHeatmap" => {
+                let days = tab.get("days")?;
+                let channel_id: LuaValue = tab.get("channel_id")?;
+                let channel_id = lua.from_value(channel_id)?;
+                Ok(Self::ActivityHeatmap { days, channel_id })
+            },
             b"GlobalKvFind" => {
                 let query = tab.get("query")?;
                 let scope = tab.get("scope")?;
@@ -266,18 +766,51 @@ impl StateDbFlags {
         }
         self.intersects(StateDbFlags::WORKER_INITIATED | StateDbFlags::ADMIN)
     }    
+
+    pub fn can_read_scope(self, scope: &str) -> bool {
+        if scope == SECRETS_SCOPE {
+            // Secrets are only ever read by the worker itself
+            return self.intersects(StateDbFlags::WORKER_INITIATED | StateDbFlags::ADMIN);
+        }
+        true
+    }
+}
+
+/// Closes a connection of a templates pool unless disarmed before being dropped
+///
+/// The connection may be in the middle of a statement, which the server cancels once it notices the client is gone
+/// (see `template_pool_options`). Closing it also ensures the connection is never reused for another query
+struct StatementGuard {
+    conn: PoolConnection<Postgres>,
+    armed: bool,
+}
+
+impl StatementGuard {
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for StatementGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            self.conn.close_on_drop();
+        }
+    }
 }
 
 #[derive(Clone)]
 /// A simple wrapper around the database pool that provides luau state manipulation functionality
 pub struct StateDb {
-    pool: sqlx::PgPool,
-    tsdb: TenantStateDb
+    db: DbRouter,
+    tsdb: TenantStateDb,
+    /// Stores the claims of `Once` ops
+    shared_cache: Arc<dyn SharedCache>,
 }
 
 impl StateDb {
-    pub fn new(pool: sqlx::PgPool) -> Self {
-        StateDb { pool: pool.clone(), tsdb: TenantStateDb::new(pool) }
+    pub fn new(db: DbRouter) -> Self {
+        StateDb { tsdb: TenantStateDb::new(db.pool(PoolKind::Settings).pool().clone()), db, shared_cache: <dyn SharedCache>::global() }
     }
     
     /// Fetch data on a presigned URL
@@ -292,8 +825,8 @@ impl StateDb {
         .bind(vurl.id.tenant_id())
         .bind(vurl.id.tenant_type())
         .bind(&vurl.key)
-        .bind(vurl.scope)
-        .fetch_optional(&self.pool)
+        .bind(&vurl.scope)
+        .fetch_optional(self.db.pool(if vurl.scope == STINGS_SCOPE { PoolKind::Stings } else { PoolKind::Kv }).pool())
         .await?;
         match rec {
             Some(rec) => Ok(rec.blob.map(|b| (b, vurl.key))),
@@ -302,23 +835,74 @@ impl StateDb {
     }
 
     /// Perform execution of an op
+    ///
+    /// Ops not made by admins originate from templates, so run on the templates partition whose statements are
+    /// limited to `MAX_TEMPLATE_STATEMENT_TIME` (see `apply_guarded`)
     pub async fn do_op(&self, tid: Id, op: Vec<StateOp>, flags: StateDbFlags) -> Result<StateExecResponse, crate::Error> {
         let mut result = StateExecResponse { results: vec![], tenant_state_changed: false, new_tenant_state: None };
         // fast path of no explicit transaction can only be applied if none of the inner ops alter the tenant state
         let fastpath = op.len() <= 1 && op.iter().all(|x| !x.alters_tenant_state());
+        let guarded = !flags.contains(StateDbFlags::ADMIN);
+
+        // Once ops only use the shared cache, so don't need a connection if they are alone
+        if op.iter().all(|x| matches!(x, StateOp::Once { .. })) {
+            for op in op {
+                let StateOp::Once { key, ttl } = op else { unreachable!() };
+                result.results.push(self.claim_once(tid, &key, ttl).await?);
+            }
+            return Ok(result)
+        }
+
+        let read_only = op.iter().all(|x| x.is_read_only());
+        if !read_only {
+            self.db.wrote(tid);
+        }
+
+        // Read-only ops may go to the replica, retried on the primary if the replica fails
+        if fastpath && read_only && let Some(replica) = self.db.reads_for(tid, guarded) {
+            let mut replica_result = StateExecResponse { results: vec![], tenant_state_changed: false, new_tenant_state: None };
+            let res = if guarded {
+                match replica.acquire().await {
+                    Ok(conn) => self.apply_guarded(conn, tid, op.clone(), &mut replica_result, flags, true).await,
+                    Err(e) => Err(e.into()),
+                }
+            } else {
+                let mut res = Ok(());
+                for op in op.iter().cloned() {
+                    res = self.apply_op(replica, tid, op, &mut replica_result, flags).await;
+                    if res.is_err() {
+                        break;
+                    }
+                }
+                res
+            };
+            match res {
+                Ok(()) => return Ok(replica_result),
+                Err(e) if e.downcast_ref::<sqlx::Error>().is_some() => self.db.replica_failed(&e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if guarded {
+            let conn = self.db.pool(PoolKind::Templates).acquire().await?;
+            self.apply_guarded(conn, tid, op, &mut result, flags, fastpath).await?;
+            return Ok(result)
+        }
 
+        let pool = self.db.pool(StateOp::pool_kind_of(&op));
         if fastpath {
+            let mut conn = pool.acquire().await?;
             for op in op {
-                Self::apply_op(&self.pool, tid, op, &mut result, flags).await?
+                self.apply_op(&mut *conn, tid, op, &mut result, flags).await?
             }
             if result.tenant_state_changed {
                 return Err("internal error: tenant state changed in unsupported fastpath".into());
             }
         } else {
             // atomic
-            let mut tx = self.pool.begin().await?;
+            let mut tx = pool.begin().await?;
             for op in op {
-                Self::apply_op(&mut *tx, tid, op, &mut result, flags).await?
+                self.apply_op(&mut *tx, tid, op, &mut result, flags).await?
             }
 
             if result.tenant_state_changed {
@@ -331,7 +915,62 @@ impl StateDb {
         return Ok(result)
     }
 
+    /// Applies the ops of a template on a connection of a templates pool, in a transaction unless `fastpath` is set
+    ///
+    /// The connection already limits its statements to `MAX_TEMPLATE_STATEMENT_TIME`. If the returned future is
+    /// dropped mid-statement (the worker aborted the execution and with it the request), the connection is closed
+    /// rather than returned to the pool, which cancels the statement
+    async fn apply_guarded(
+        &self,
+        conn: PoolConnection<Postgres>,
+        tid: Id,
+        op: Vec<StateOp>,
+        result: &mut StateExecResponse,
+        flags: StateDbFlags,
+        fastpath: bool,
+    ) -> Result<(), crate::Error> {
+        let mut guard = StatementGuard { conn, armed: true };
+
+        let res: Result<(), crate::Error> = async {
+            if fastpath {
+                for op in op {
+                    self.apply_op(&mut *guard.conn, tid, op, result, flags).await?
+                }
+                if result.tenant_state_changed {
+                    return Err("internal error: tenant state changed in unsupported fastpath".into());
+                }
+                return Ok(())
+            }
+
+            // atomic
+            let mut tx = guard.conn.begin().await?;
+            let res: Result<(), crate::Error> = async {
+                for op in op {
+                    self.apply_op(&mut *tx, tid, op, result, flags).await?
+                }
+
+                if result.tenant_state_changed {
+                    result.new_tenant_state = self.tsdb.get_tenant_state_for(&mut tx, tid).await?;
+                }
+                Ok(())
+            }.await;
+
+            match res {
+                Ok(()) => tx.commit().await?,
+                Err(e) => {
+                    tx.rollback().await?;
+                    return Err(e);
+                }
+            }
+            Ok(())
+        }.await;
+
+        guard.disarm();
+        res
+    }
+
     async fn apply_op<'c, E>(
+        &self,
         executor: E, 
         tid: Id, 
         op: StateOp,
@@ -343,6 +982,9 @@ impl StateDb {
     {
         match op {
             StateOp::KvFind { query, scope } => {
+                if !flags.can_read_scope(&scope) {
+                    return Err("Cannot read from this internal scope".into())
+                }
                 let rec: Vec<KvLookup> = {
                     if query == "%%" {
                         // Fast path, omit ILIKE if '%%' is used
@@ -371,6 +1013,9 @@ impl StateDb {
                 KvLookup::apply(state, rec);
             }
             StateOp::KvGet { key, scope } => {
+                if !flags.can_read_scope(&scope) {
+                    return Err("Cannot read from this internal scope".into())
+                }
                 if let Some(rec) = sqlx::query_as(
                     "SELECT key, value, scope, created_at, last_updated_at FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND key = $3 AND scope = $4",
                     )
@@ -384,6 +1029,9 @@ impl StateDb {
                     }
             }
             StateOp::KvGetWithBlob { key, scope } => {
+                if !flags.can_read_scope(&scope) {
+                    return Err("Cannot read from this internal scope".into())
+                }
                 if let Some(rec) = sqlx::query_as(
                     "SELECT key, value, blob, scope, created_at, last_updated_at FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND key = $3 AND scope = $4",
                     )
@@ -397,6 +1045,9 @@ impl StateDb {
                     }
             }
             StateOp::KvSignUrl { key, scope } => {
+                if !flags.can_read_scope(&scope) {
+                    return Err("Cannot read from this internal scope".into())
+                }
                 let vurl = crate::geese::urlsign::create_url(tid, &key, &scope, KV_SIGN_URL_EXPIRATION_SECONDS)?;
                 state.results.push(StateExecResult::KvSignUrl { url: vurl, expiry: KV_SIGN_URL_EXPIRATION_SECONDS });
             }
@@ -494,6 +1145,289 @@ impl StateDb {
                     state.tenant_state_changed = true;
                 }
             }
+            StateOp::SetModmailChannel { channel_id } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Modmail can only be set up in a guild".into())
+                }
+                if let Some(ref channel_id) = channel_id && channel_id.parse::<dapi::ChannelId>().is_err() {
+                    return Err("Invalid modmail channel ID".into())
+                }
+
+                if tenantstate::set_setting(executor, tid, "modmail_channel_id", channel_id.map(serde_json::Value::String)).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetAppealsChannel { channel_id } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Appeals can only be set up in a guild".into())
+                }
+                if let Some(ref channel_id) = channel_id && channel_id.parse::<dapi::ChannelId>().is_err() {
+                    return Err("Invalid appeals channel ID".into())
+                }
+
+                if tenantstate::set_setting(executor, tid, "appeals_channel_id", channel_id.map(serde_json::Value::String)).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetWatchlistChannel { channel_id } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Watchlists can only be set up in a guild".into())
+                }
+                if let Some(ref channel_id) = channel_id && channel_id.parse::<dapi::ChannelId>().is_err() {
+                    return Err("Invalid watchlist channel ID".into())
+                }
+
+                if tenantstate::set_setting(executor, tid, "watchlist_channel_id", channel_id.map(serde_json::Value::String)).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::WatchlistAdd { user_id, severity, note, added_by } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Watchlists can only be used in a guild".into())
+                }
+                if user_id.parse::<dapi::UserId>().is_err() {
+                    return Err("Invalid watchlist user ID".into())
+                }
+                if let Some(ref added_by) = added_by && added_by.parse::<dapi::UserId>().is_err() {
+                    return Err("Invalid watchlist added_by user ID".into())
+                }
+                let severity = Severity::parse(&severity)?;
+                if note.chars().count() > MAX_WATCHLIST_NOTE_LENGTH {
+                    return Err(format!("Watchlist notes can be at most {MAX_WATCHLIST_NOTE_LENGTH} characters").into())
+                }
+
+                let res = sqlx::query(
+                    r#"
+                    INSERT INTO watchlist (guild_id, user_id, severity, note, added_by)
+                    SELECT $1, $2, $3, $4, $5
+                    WHERE (SELECT COUNT(*) FROM watchlist WHERE guild_id = $1) < $6
+                    OR EXISTS (SELECT 1 FROM watchlist WHERE guild_id = $1 AND user_id = $2)
+                    ON CONFLICT (guild_id, user_id) DO UPDATE SET severity = EXCLUDED.severity, note = EXCLUDED.note,
+                    added_by = COALESCE(EXCLUDED.added_by, watchlist.added_by), updated_at = NOW()
+                    "#
+                )
+                .bind(tid.tenant_id())
+                .bind(&user_id)
+                .bind(severity.as_str())
+                .bind(&note)
+                .bind(&added_by)
+                .bind(MAX_WATCHLIST_ENTRIES)
+                .execute(executor)
+                .await?;
+
+                if res.rows_affected() == 0 {
+                    return Err(format!("Watchlists can have at most {MAX_WATCHLIST_ENTRIES} users").into())
+                }
+            }
+            StateOp::WatchlistRemove { user_id } => {
+                sqlx::query("DELETE FROM watchlist WHERE guild_id = $1 AND user_id = $2 AND $3 = 'guild'")
+                    .bind(tid.tenant_id())
+                    .bind(&user_id)
+                    .bind(tid.tenant_type())
+                    .execute(executor)
+                    .await?;
+            }
+            StateOp::WatchlistGet { user_id } => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Watchlists can only be used in a guild".into())
+                };
+                if let Some(e) = watchlist::get(executor, &guild_id.to_string(), &user_id).await? {
+                    state.results.push(StateExecResult::Watchlist { e });
+                }
+            }
+            StateOp::WatchlistList {} => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Watchlists can only be used in a guild".into())
+                };
+                for e in watchlist::list(executor, &guild_id.to_string()).await? {
+                    state.results.push(StateExecResult::Watchlist { e });
+                }
+            }
+            StateOp::SetAltDetection { sensitivity } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Alt detection can only be set up in a guild".into())
+                }
+                let sensitivity = sensitivity.as_deref().map(AltSensitivity::parse).transpose()?;
+
+                if tenantstate::set_setting(executor, tid, "alt_sensitivity", sensitivity.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetInviteTracking { enabled } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Invite tracking can only be set up in a guild".into())
+                }
+
+                if tenantstate::set_setting(executor, tid, "invite_tracking", enabled.then_some(serde_json::Value::Bool(true))).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetNamePolicy { policy } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Name policies can only be set up in a guild".into())
+                }
+                if let Some(ref policy) = policy {
+                    policy.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "name_policy", policy.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetStickyRoles { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Sticky roles can only be set up in a guild".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                // Snapshots are deleted along with disabling
+                if tenantstate::set_setting_with(
+                    executor, tid, "sticky_roles", config.map(serde_json::to_value).transpose()?,
+                    "WITH cleared AS (DELETE FROM sticky_roles WHERE guild_id = $1 AND $4::JSONB IS NULL)", "",
+                ).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetPermissionSnapshots { enabled } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Permission snapshots can only be set up in a guild".into())
+                }
+
+                // The last snapshot is deleted along with disabling, enabling has a snapshot taken on the next poll
+                if tenantstate::set_setting_with(
+                    executor, tid, "permission_snapshots", enabled.then_some(serde_json::Value::Bool(true)),
+                    "WITH cleared AS (DELETE FROM permission_snapshots WHERE guild_id = $1 AND $4::JSONB IS NULL)", ", permission_snapshot_at = NULL",
+                ).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetNukeProtection { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Nuke protection can only be set up in a guild".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "nuke_protection", config.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetWebhookSpam { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Webhook spam detection can only be set up in a guild".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "webhook_spam", config.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetAutoPublish { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Auto publishing can only be set up in a guild".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "auto_publish", config.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetThreadPolicies { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Thread policies can only be set for guilds".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting_with(
+                    executor, tid, "thread_policies", config.map(serde_json::to_value).transpose()?,
+                    "", ", thread_policy_swept_at = NULL",
+                ).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetEmojiUsage { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Emoji usage tracking can only be set for guilds".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "emoji_usage", config.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetActivityTracking { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Activity tracking can only be set for guilds".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "activity", config.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetAuditCorrelation { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Audit log correlation can only be set for guilds".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "audit_correlation", config.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetVoiceIdle { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Voice idle detection can only be set for guilds".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "voice_idle", config.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetResultCache { config } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Result caching can only be set up for guilds".into())
+                }
+                if let Some(ref config) = config {
+                    config.validate()?;
+                }
+
+                if tenantstate::set_setting(executor, tid, "result_cache", config.map(serde_json::to_value).transpose()?).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
+            StateOp::SetDisabledPlugins { mut plugins } => {
+                if !matches!(tid, Id::Guild(_)) {
+                    return Err("Plugins can only be disabled for guilds".into())
+                }
+                if let Some(plugin) = plugins.iter().find(|p| !is_known_plugin(p)) {
+                    return Err(format!("Unknown plugin {plugin}, built in plugins are {}", PLUGINS.join(", ")).into())
+                }
+                plugins.sort();
+                plugins.dedup();
+
+                if tenantstate::set_setting(executor, tid, "disabled_plugins", (!plugins.is_empty()).then(|| serde_json::Value::from(plugins))).await? {
+                    state.tenant_state_changed = true;
+                }
+            }
             StateOp::GlobalKvFind { query, scope } => {
                 let items: Vec<GlobalKv> = if query == "%%" {
                     sqlx::query_as(
@@ -596,10 +1530,225 @@ impl StateDb {
                     GlobalKvData::apply_one(state, rec);
                 }
             }
+            StateOp::Once { key, ttl } => {
+                state.results.push(self.claim_once(tid, &key, ttl).await?);
+            }
+            StateOp::WorkflowStart { workflow, key, step, data } => {
+                workflows::validate_name("workflow", &workflow)?;
+                workflows::validate_name("step", &step)?;
+                if key.len() > KV_MAX_KEY_LENGTH {
+                    return Err(format!("key length exceeds {KV_MAX_KEY_LENGTH} chars").into())
+                }
+
+                // The limit is checked in the insert itself so the running instance with the key is returned if it
+                // exists (in which case nothing is inserted). Concurrent starts may exceed the limit slightly
+                #[derive(sqlx::FromRow)]
+                struct Row {
+                    #[sqlx(flatten)]
+                    w: WorkflowInstance,
+                    created: bool,
+                }
+                let row: Option<Row> = sqlx::query_as(&format!(
+                    "WITH ins AS (
+                        INSERT INTO workflow_instances (owner_id, owner_type, workflow, key, step, data)
+                        SELECT $1, $2, $3, $4, $5, $6
+                        WHERE (SELECT COUNT(*) FROM workflow_instances WHERE owner_id = $1 AND owner_type = $2 AND state = 'running') < $7
+                        ON CONFLICT (owner_id, owner_type, workflow, key) WHERE state = 'running' DO NOTHING
+                        RETURNING {WORKFLOW_COLUMNS}
+                    )
+                    SELECT *, TRUE AS created FROM ins
+                    UNION ALL
+                    SELECT {WORKFLOW_COLUMNS}, FALSE AS created FROM workflow_instances
+                    WHERE owner_id = $1 AND owner_type = $2 AND workflow = $3 AND key = $4 AND state = 'running'"
+                ))
+                .bind(tid.tenant_id())
+                .bind(tid.tenant_type())
+                .bind(&workflow)
+                .bind(&key)
+                .bind(&step)
+                .bind(workflows::encode_data(&data)?)
+                .bind(MAX_RUNNING_WORKFLOWS)
+                .fetch_optional(executor)
+                .await?;
+
+                let Some(row) = row else {
+                    return Err(format!("Cannot have more than {MAX_RUNNING_WORKFLOWS} running workflow instances").into())
+                };
+                state.results.push(StateExecResult::WorkflowStarted { w: row.w, created: row.created });
+            }
+            StateOp::WorkflowGet { workflow, key } => {
+                let w: Option<WorkflowInstance> = sqlx::query_as(&format!(
+                    "SELECT {WORKFLOW_COLUMNS} FROM workflow_instances WHERE owner_id = $1 AND owner_type = $2 AND workflow = $3 AND key = $4 AND state = 'running'"
+                ))
+                .bind(tid.tenant_id())
+                .bind(tid.tenant_type())
+                .bind(&workflow)
+                .bind(&key)
+                .fetch_optional(executor)
+                .await?;
+
+                if let Some(w) = w {
+                    state.results.push(StateExecResult::Workflow { w });
+                }
+            }
+            StateOp::WorkflowAdvance { id, seq, step, data, delay, signal } => {
+                workflows::validate_name("step", &step)?;
+                if let Some(ref signal) = signal {
+                    workflows::validate_name("signal", signal)?;
+                }
+
+                // Steps waiting for a signal without a timeout have no wake time, steps not waiting at all are due now
+                let (trigger, wake_at) = match (delay, &signal) {
+                    (Some(delay), _) => ("timer", Some(Utc::now() + workflows::step_delay(delay)?)),
+                    (None, Some(_)) => ("timer", None),
+                    (None, None) => ("step", Some(Utc::now())),
+                };
+
+                let res = sqlx::query(
+                    "UPDATE workflow_instances SET step = $5, data = $6, trigger = $7, wake_at = $8, wait_signal = $9, signal_data = NULL,
+                    seq = seq + 1, attempts = 0, last_error = NULL, updated_at = NOW()
+                    WHERE id = $1 AND owner_id = $2 AND owner_type = $3 AND seq = $4 AND state = 'running'"
+                )
+                .bind(id)
+                .bind(tid.tenant_id())
+                .bind(tid.tenant_type())
+                .bind(seq)
+                .bind(&step)
+                .bind(workflows::encode_data(&data)?)
+                .bind(trigger)
+                .bind(wake_at)
+                .bind(signal)
+                .execute(executor)
+                .await?;
+
+                if res.rows_affected() == 0 {
+                    return Err("Workflow instance is not running or was already moved on by another execution".into());
+                }
+            }
+            StateOp::WorkflowComplete { id, seq, data } => {
+                let res = sqlx::query(
+                    "UPDATE workflow_instances SET state = 'completed', data = $5, wake_at = NULL, wait_signal = NULL,
+                    seq = seq + 1, updated_at = NOW(), completed_at = NOW()
+                    WHERE id = $1 AND owner_id = $2 AND owner_type = $3 AND seq = $4 AND state = 'running'"
+                )
+                .bind(id)
+                .bind(tid.tenant_id())
+                .bind(tid.tenant_type())
+                .bind(seq)
+                .bind(workflows::encode_data(&data)?)
+                .execute(executor)
+                .await?;
+
+                if res.rows_affected() == 0 {
+                    return Err("Workflow instance is not running or was already moved on by another execution".into());
+                }
+            }
+            StateOp::WorkflowSignal { workflow, key, signal, data } => {
+                let res = sqlx::query(
+                    "UPDATE workflow_instances SET trigger = 'signal', wake_at = NOW(), wait_signal = NULL, signal_data = $5,
+                    seq = seq + 1, updated_at = NOW()
+                    WHERE owner_id = $1 AND owner_type = $2 AND workflow = $3 AND key = $4 AND wait_signal = $6 AND state = 'running'"
+                )
+                .bind(tid.tenant_id())
+                .bind(tid.tenant_type())
+                .bind(&workflow)
+                .bind(&key)
+                .bind(workflows::encode_data(&data)?)
+                .bind(&signal)
+                .execute(executor)
+                .await?;
+
+                state.results.push(StateExecResult::WorkflowUpdated { updated: res.rows_affected() > 0 });
+            }
+            StateOp::WorkflowCancel { workflow, key } => {
+                let res = sqlx::query(
+                    "UPDATE workflow_instances SET state = 'cancelled', wake_at = NULL, wait_signal = NULL, seq = seq + 1, updated_at = NOW(), completed_at = NOW()
+                    WHERE owner_id = $1 AND owner_type = $2 AND workflow = $3 AND key = $4 AND state = 'running'"
+                )
+                .bind(tid.tenant_id())
+                .bind(tid.tenant_type())
+                .bind(&workflow)
+                .bind(&key)
+                .execute(executor)
+                .await?;
+
+                state.results.push(StateExecResult::WorkflowUpdated { updated: res.rows_affected() > 0 });
+            }
+            StateOp::ScheduleList {} => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Scheduled messages can only be used in a guild".into())
+                };
+                for s in schedules::list(executor, &guild_id.to_string()).await? {
+                    state.results.push(StateExecResult::Schedule { s });
+                }
+            }
+            StateOp::ScheduleSet { name, schedule } => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Scheduled messages can only be used in a guild".into())
+                };
+                let s = schedules::set(executor, &guild_id.to_string(), &name, &schedule).await?;
+                state.results.push(StateExecResult::Schedule { s });
+            }
+            StateOp::ScheduleDelete { name } => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Scheduled messages can only be used in a guild".into())
+                };
+                let updated = schedules::delete(executor, &guild_id.to_string(), &name).await?;
+                state.results.push(StateExecResult::ScheduleUpdated { updated });
+            }
+            StateOp::SchedulePause { name, paused } => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Scheduled messages can only be used in a guild".into())
+                };
+                let updated = schedules::set_paused(executor, &guild_id.to_string(), &name, paused).await?;
+                state.results.push(StateExecResult::ScheduleUpdated { updated });
+            }
+            StateOp::EmojiUsageTop { days, kind, limit } => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Emoji usage can only be queried in a guild".into())
+                };
+                for u in emojiusage::top(executor, guild_id, days, kind, limit).await? {
+                    state.results.push(StateExecResult::EmojiUsage { u });
+                }
+            }
+            StateOp::EmojiUsageUnused { days, items } => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Emoji usage can only be queried in a guild".into())
+                };
+                for u in emojiusage::unused(executor, guild_id, days, &items).await? {
+                    state.results.push(StateExecResult::EmojiUsage { u });
+                }
+            }
+            StateOp::ActivityHeatmap { days, channel_id } => {
+                let Id::Guild(guild_id) = tid else {
+                    return Err("Activity can only be queried in a guild".into())
+                };
+                let h = activity::heatmap(executor, guild_id, days, channel_id).await?;
+                state.results.push(StateExecResult::ActivityHeatmap { h });
+            }
         }
 
         Ok(())
     }
+
+    /// Claims a key of a tenant in the shared cache (see `StateOp::Once`)
+    async fn claim_once(&self, tid: Id, key: &str, ttl: u64) -> Result<StateExecResult, crate::Error> {
+        if key.len() > KV_MAX_KEY_LENGTH {
+            return Err(format!("key length exceeds {KV_MAX_KEY_LENGTH} chars").into())
+        }
+        let ttl = Duration::from_secs(ttl);
+        if ttl.is_zero() || ttl > MAX_ONCE_TTL {
+            return Err(format!("ttl must be between 1 and {} seconds", MAX_ONCE_TTL.as_secs()).into())
+        }
+
+        let set = format!("once:{}:{}", tid.tenant_type(), tid.tenant_id());
+        let first = match self.shared_cache.claim(&set, key, ttl, MAX_ONCE_KEYS).await? {
+            Claim::Claimed => true,
+            Claim::Exists => false,
+            Claim::Full => return Err(format!("cannot have more than {MAX_ONCE_KEYS} unexpired once keys").into()),
+        };
+        Ok(StateExecResult::Once { first })
+    }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -624,6 +1773,34 @@ pub enum StateExecResult {
     },
     GlobalKvDataOpaque {
         data: KhronosValue
+    },
+    Once {
+        first: bool
+    },
+    Workflow {
+        w: WorkflowInstance
+    },
+    WorkflowStarted {
+        w: WorkflowInstance,
+        created: bool
+    },
+    WorkflowUpdated {
+        updated: bool
+    },
+    Schedule {
+        s: ScheduledMessage
+    },
+    ScheduleUpdated {
+        updated: bool
+    },
+    EmojiUsage {
+        u: EmojiUsageStats
+    },
+    ActivityHeatmap {
+        h: ActivityHeatmap
+    },
+    Watchlist {
+        e: WatchlistEntry
     }
 }
 
@@ -690,6 +1867,43 @@ impl IntoLua for StateExecResult {
                 table.set("op", "GlobalKvDataOpaque")?;
                 table.set("data", Opaque::new(data))?;
             }
+            Self::Once { first } => {
+                table.set("op", "Once")?;
+                table.set("first", first)?;
+            }
+            Self::Workflow { w } => {
+                table.set("op", "Workflow")?;
+                table.set("workflow", w)?;
+            }
+            Self::WorkflowStarted { w, created } => {
+                table.set("op", "WorkflowStarted")?;
+                table.set("workflow", w)?;
+                table.set("created", created)?;
+            }
+            Self::WorkflowUpdated { updated } => {
+                table.set("op", "WorkflowUpdated")?;
+                table.set("updated", updated)?;
+            }
+            Self::Schedule { s } => {
+                table.set("op", "Schedule")?;
+                table.set("schedule", s)?;
+            }
+            Self::ScheduleUpdated { updated } => {
+                table.set("op", "ScheduleUpdated")?;
+                table.set("updated", updated)?;
+            }
+            Self::EmojiUsage { u } => {
+                table.set("op", "EmojiUsage")?;
+                table.set("usage", u)?;
+            }
+            Self::ActivityHeatmap { h } => {
+                table.set("op", "ActivityHeatmap")?;
+                table.set("heatmap", h)?;
+            }
+            Self::Watchlist { e } => {
+                table.set("op", "Watchlist")?;
+                table.set("entry", e)?;
+            }
         }
         table.set_readonly(true); // We want StateExecResult's to be immutable
         Ok(LuaValue::Table(table))
@@ -707,9 +1921,9 @@ pub struct StateExecResponse {
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
 pub struct KvLookup {
-    key: String,
+    pub key: String,
     #[sqlx(json)]
-    value: KhronosValue,
+    pub value: KhronosValue,
     scope: String,
     created_at: chrono::DateTime<chrono::Utc>,
     last_updated_at: chrono::DateTime<chrono::Utc>,
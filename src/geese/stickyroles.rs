@@ -0,0 +1,155 @@
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, RoleId, UserId};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of days snapshots can be kept for
+pub const MAX_STICKY_RETENTION_DAYS: u32 = 365;
+
+/// Maximum number of roles that can be excluded from restores
+pub const MAX_STICKY_EXCLUDED_ROLES: usize = 100;
+
+/// How the roles of members leaving a guild are restored when they rejoin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StickyRolesConfig {
+    /// How many days after leaving members get their roles back on rejoining
+    pub retention_days: u32,
+    /// Roles never restored
+    #[serde(default)]
+    pub excluded_roles: Vec<String>,
+    /// Whether roles with moderation or administration permissions are never restored either
+    #[serde(default = "default_true")]
+    pub exclude_dangerous: bool,
+    /// Whether the nickname is restored too
+    #[serde(default = "default_true")]
+    pub restore_nick: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl StickyRolesConfig {
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.retention_days == 0 || self.retention_days > MAX_STICKY_RETENTION_DAYS {
+            return Err(format!("Sticky roles can be kept for between 1 and {MAX_STICKY_RETENTION_DAYS} days").into());
+        }
+        if self.excluded_roles.len() > MAX_STICKY_EXCLUDED_ROLES {
+            return Err(format!("At most {MAX_STICKY_EXCLUDED_ROLES} roles can be excluded").into());
+        }
+        if self.excluded_roles.iter().any(|r| r.parse::<RoleId>().is_err()) {
+            return Err("Invalid excluded role ID".into());
+        }
+        Ok(())
+    }
+
+    pub fn retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.retention_days as i64)
+    }
+}
+
+/// The roles and nickname of a member as of leaving a guild
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StickySnapshot {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    pub roles: Vec<RoleId>,
+    pub nick: Option<String>,
+    pub left_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct StickySnapshotRow {
+    guild_id: String,
+    user_id: String,
+    roles: Vec<String>,
+    nick: Option<String>,
+    left_at: DateTime<Utc>,
+}
+
+impl TryFrom<StickySnapshotRow> for StickySnapshot {
+    type Error = crate::Error;
+
+    fn try_from(row: StickySnapshotRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            guild_id: row.guild_id.parse()?,
+            user_id: row.user_id.parse()?,
+            roles: row.roles.iter().map(|r| r.parse()).collect::<Result<_, _>>()?,
+            nick: row.nick,
+            left_at: row.left_at,
+        })
+    }
+}
+
+/// A sticky roles request from a worker to the master
+#[derive(Debug, Serialize, Deserialize)]
+pub enum StickyRolesReq {
+    /// Saves the snapshot of a member leaving, replacing any earlier snapshot of the member and pruning the
+    /// snapshots of the guild older than `retention_days`
+    Save { snapshot: StickySnapshot, retention_days: u32 },
+    /// Takes (returns and deletes) the snapshot of a member rejoining, if taken in the last `retention_days`
+    Take { guild_id: GuildId, user_id: UserId, retention_days: u32 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum StickyRolesResp {
+    Ack,
+    Snapshot { snapshot: Option<StickySnapshot> },
+}
+
+/// Storage of the roles of members who left guilds with sticky roles
+///
+/// Snapshots are taken and restored by the worker of the guild (see `worker::stickyroles`), the master only stores them
+#[derive(Clone)]
+pub struct StickyRolesDb {
+    pool: sqlx::PgPool,
+}
+
+impl StickyRolesDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Handles a sticky roles request from a worker
+    pub async fn handle(&self, req: StickyRolesReq) -> Result<StickyRolesResp, crate::Error> {
+        match req {
+            StickyRolesReq::Save { snapshot, retention_days } => {
+                let mut tx = self.pool.begin().await?;
+                sqlx::query(
+                    "INSERT INTO sticky_roles (guild_id, user_id, roles, nick, left_at) VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (guild_id, user_id) DO UPDATE SET roles = EXCLUDED.roles, nick = EXCLUDED.nick, left_at = EXCLUDED.left_at"
+                )
+                .bind(snapshot.guild_id.to_string())
+                .bind(snapshot.user_id.to_string())
+                .bind(snapshot.roles.iter().map(|r| r.to_string()).collect::<Vec<_>>())
+                .bind(snapshot.nick)
+                .bind(snapshot.left_at)
+                .execute(&mut *tx)
+                .await?;
+
+                sqlx::query("DELETE FROM sticky_roles WHERE guild_id = $1 AND left_at < NOW() - make_interval(days => $2)")
+                    .bind(snapshot.guild_id.to_string())
+                    .bind(retention_days as i32)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+                Ok(StickyRolesResp::Ack)
+            }
+            StickyRolesReq::Take { guild_id, user_id, retention_days } => {
+                let row: Option<StickySnapshotRow> = sqlx::query_as(
+                    "DELETE FROM sticky_roles WHERE guild_id = $1 AND user_id = $2
+                    RETURNING guild_id, user_id, roles, nick, left_at"
+                )
+                .bind(guild_id.to_string())
+                .bind(user_id.to_string())
+                .fetch_optional(&self.pool)
+                .await?;
+
+                let snapshot = row
+                    .filter(|row| Utc::now() - row.left_at <= chrono::Duration::days(retention_days as i64))
+                    .map(StickySnapshot::try_from)
+                    .transpose()?;
+                Ok(StickyRolesResp::Snapshot { snapshot })
+            }
+        }
+    }
+}
@@ -1,18 +1,21 @@
 use std::sync::Arc;
 
 use dapi::{ChannelId, GuildId, UserId, dhttp::{Client, HttpCall, HttpError, ResultJson}, types::User};
+use opentelemetry::{Context, KeyValue, trace::{SpanKind, TraceContextExt}};
 use serde_json::Value;
 use stratum_client::{BulkIsResourceInCacheRequest, GetResourceRequest, IsResourceInCacheRequest, StratumClient};
 use stratum_common::{GuildFetchOpts, pb};
 use tokio::sync::watch;
 
-use crate::{Error, worker::{workerdispatch::SimpleEvent, workerthread::WorkerThread, workervmmanager::Id}};
+use crate::{Error, geese::{gateway::GatewayHealth, telemetry}, mesophyll::client::MesophyllClient, worker::{workerdispatch::SimpleEvent, workerthread::WorkerThread, workervmmanager::Id}};
 
 #[derive(Clone)]
 pub struct Stratum {
     client: Arc<StratumClient>,
     http: Client,
-    current_user: Arc<User>
+    current_user: Arc<User>,
+    /// Health of the Stratum connection, Stratum's cache is bypassed while the direct gateway fallback is active
+    health: Arc<GatewayHealth>,
 }
 
 impl std::ops::Deref for Stratum {
@@ -25,16 +28,18 @@ impl std::ops::Deref for Stratum {
 
 impl Stratum {
     pub fn new(client: StratumClient, http: Client, current_user: User) -> Self {
-        Self { client: Arc::new(client), http, current_user: Arc::new(current_user) }
+        Self { client: Arc::new(client), http, current_user: Arc::new(current_user), health: Arc::default() }
     }
 
     /// Starts listening for discord events and pushing them to worker thread
-    pub async fn listen_discord_events(&self, wt: WorkerThread, shutdown: watch::Receiver<bool>) {
+    ///
+    /// Events for tenants not owned by the worker are forwarded to the master through `mesophyll`
+    pub async fn listen_discord_events(&self, wt: WorkerThread, mesophyll: Arc<MesophyllClient>, shutdown: watch::Receiver<bool>) {
         loop {
             if *shutdown.borrow() {
                 return;
             }
-            match self.listen_discord_events_impl(wt.clone(), shutdown.clone()).await {
+            match self.listen_discord_events_impl(wt.clone(), mesophyll.clone(), shutdown.clone()).await {
                 Ok(_) => break,
                 Err(e) => {
                     log::error!("[Worker {wid}] Error in stream {e:?}, retrying in 5 seconds", wid=wt.id());
@@ -45,14 +50,14 @@ impl Stratum {
     }
 
     /// Helper method to start the event stream and listen in calling `discord_event_dispatch` for every message
-    async fn listen_discord_events_impl(&self, wt: WorkerThread, shutdown: watch::Receiver<bool>) -> Result<(), crate::Error> {
+    async fn listen_discord_events_impl(&self, wt: WorkerThread, mesophyll: Arc<MesophyllClient>, shutdown: watch::Receiver<bool>) -> Result<(), crate::Error> {
         let bot_id = self.current_user.id;
 
         let stream = self.event_stream(wt.id().try_into()?).await?;
         log::info!("[Worker {wid}] Started event stream", wid=wt.id());
         self.listen_to_stream(stream, Some(shutdown), move |evt| {
             //log::info!("[Worker {wid}] Got event: {} json_ok({})", evt.event_name, value.is_ok());
-            if let Err(e) = Self::discord_event_dispatch(&wt, bot_id, evt) {
+            if let Err(e) = Self::discord_event_dispatch(&wt, &mesophyll, bot_id, evt) {
                 log::error!("Error dispatching event: {:?}", e);
             }
             false
@@ -60,8 +65,9 @@ impl Stratum {
         Ok(())
     }
 
-    fn discord_event_dispatch(
+    pub(crate) fn discord_event_dispatch(
         wt: &WorkerThread,
+        mesophyll: &Arc<MesophyllClient>,
         bot_id: UserId,
         evt: pb::DiscordEvent,
     ) -> Result<(), crate::Error> {
@@ -71,6 +77,8 @@ impl Stratum {
             Id::Guild(GuildId::new(evt.guild_id))
         } else if evt.target_user != 0 {
             Id::User(UserId::new(evt.target_user)) // User installed app
+        } else if evt.msg_author != 0 && evt.msg_author != u64::MAX {
+            Id::User(UserId::new(evt.msg_author)) // DM, run in the context of the user messaging the bot
         } else {
             return Ok(()); // No routing info
         };
@@ -79,14 +87,32 @@ impl Stratum {
             return Ok(()); // avoid self-bot related footguns
         }  
 
-        wt.dispatch_event_nowait(
-            id,
-            SimpleEvent::new_json_string(
-                evt.event_name,
-                None,
-                evt.payload,
-            ),
-        )?;
+        // Root span for the event, the worker side spans are parented to this
+        let cx = telemetry::child(&Context::new(), "gateway.receive", SpanKind::Consumer, vec![
+            KeyValue::new("event", evt.event_name.clone()),
+            KeyValue::new("tenant", id.tenant_id()),
+        ]);
+        let traceparent = telemetry::inject(&cx);
+        cx.span().end();
+
+        let event = SimpleEvent::new_json_string(
+            evt.event_name,
+            None,
+            evt.payload,
+        ).with_traceparent(traceparent);
+
+        // Stratum shards events by its own formula, so the tenant may be routed to another worker
+        if !mesophyll.routing.owns(wt.id(), id) {
+            let mesophyll = mesophyll.clone();
+            tokio::spawn(async move {
+                if let Err(e) = mesophyll.forward_event(id, event).await {
+                    log::error!("Error forwarding event for {id:?} to master: {e}");
+                }
+            });
+            return Ok(());
+        }
+
+        wt.dispatch_event_nowait(id, event)?;
 
         Ok(())
     }
@@ -119,6 +145,10 @@ impl Stratum {
         &self,
         guild: GuildId,
     ) -> Result<bool, Error> {
+        if self.health.is_direct() {
+            return Ok(self.guild(guild).await?.is_some());
+        }
+
         let resp = self.client.is_resource_in_cache(IsResourceInCacheRequest::Guild {
             guild_id: guild.get()
         }).await?;
@@ -130,6 +160,14 @@ impl Stratum {
         &self,
         guilds: &[GuildId],
     ) -> Result<Vec<bool>, Error> {
+        if self.health.is_direct() {
+            let mut has = Vec::with_capacity(guilds.len());
+            for guild in guilds {
+                has.push(self.guild(*guild).await?.is_some());
+            }
+            return Ok(has);
+        }
+
         let guilds = guilds.iter().map(|x| x.get()).collect::<Vec<_>>();
         let resp = self.client.bulk_is_resource_in_cache(BulkIsResourceInCacheRequest::Guild {
             guild_id: guilds
@@ -142,8 +180,8 @@ impl Stratum {
         &self,
         guild_id: GuildId,
     ) -> Result<Option<Value>, Error> {
-        // First try normal fetch
-        if let Some(guild) = self.get_resource_from_cache(GetResourceRequest::Guild { guild_id: guild_id.get(), flags: GuildFetchOpts::empty() }).await? {
+        // First try normal fetch (skipping the cache while Stratum is down)
+        if !self.health.is_direct() && let Some(guild) = self.get_resource_from_cache(GetResourceRequest::Guild { guild_id: guild_id.get(), flags: GuildFetchOpts::empty() }).await? {
             return Ok(Some(guild));
         }
 
@@ -158,8 +196,8 @@ impl Stratum {
         guild_id: GuildId,
         user_id: UserId,
     ) -> Result<Option<Value>, Error> {
-        // First try normal fetch
-        if let Some(member) = self.get_resource_from_cache(GetResourceRequest::GuildMember { guild_id: guild_id.get(), user_id: user_id.get() }).await? {
+        // First try normal fetch (skipping the cache while Stratum is down)
+        if !self.health.is_direct() && let Some(member) = self.get_resource_from_cache(GetResourceRequest::GuildMember { guild_id: guild_id.get(), user_id: user_id.get() }).await? {
             return Ok(Some(member));
         }
 
@@ -173,8 +211,8 @@ impl Stratum {
         &self,
         guild_id: GuildId,
     ) -> Result<Option<Value>, Error> {
-        // First try normal fetch
-        if let Some(roles) = self.get_resource_from_cache(GetResourceRequest::GuildRoles { guild_id: guild_id.get() }).await? {
+        // First try normal fetch (skipping the cache while Stratum is down)
+        if !self.health.is_direct() && let Some(roles) = self.get_resource_from_cache(GetResourceRequest::GuildRoles { guild_id: guild_id.get() }).await? {
             return Ok(Some(roles));
         }
 
@@ -188,8 +226,8 @@ impl Stratum {
         &self,
         guild_id: GuildId,
     ) -> Result<Option<Value>, Error> {
-        // First try normal fetch
-        if let Some(channels) = self.get_resource_from_cache(GetResourceRequest::GuildChannels { guild_id: guild_id.get() }).await? {
+        // First try normal fetch (skipping the cache while Stratum is down)
+        if !self.health.is_direct() && let Some(channels) = self.get_resource_from_cache(GetResourceRequest::GuildChannels { guild_id: guild_id.get() }).await? {
             return Ok(Some(channels));
         }
 
@@ -203,8 +241,8 @@ impl Stratum {
         &self,
         channel_id: ChannelId,
     ) -> Result<Option<Value>, Error> {
-        // First try normal fetch
-        if let Some(channel) = self.get_resource_from_cache(GetResourceRequest::Channel { channel_id: channel_id.get() }).await? {
+        // First try normal fetch (skipping the cache while Stratum is down)
+        if !self.health.is_direct() && let Some(channel) = self.get_resource_from_cache(GetResourceRequest::Channel { channel_id: channel_id.get() }).await? {
             return Ok(Some(channel));
         }
 
@@ -217,6 +255,10 @@ impl Stratum {
         &self.http
     }
 
+    pub fn gateway_health(&self) -> &Arc<GatewayHealth> {
+        &self.health
+    }
+
     pub fn current_user(&self) -> &Arc<User> {
         &self.current_user
     }
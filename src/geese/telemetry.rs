@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{SpanKind, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+
+use crate::CONFIG;
+
+/// Sets up the global OTLP tracer provider if `otlp_endpoint` is configured
+///
+/// The returned provider should be shut down on exit to flush any pending spans. If no
+/// endpoint is configured, the global (no-op) provider is left as-is and all spans are free
+pub fn init(service_name: &'static str) -> Result<Option<SdkTracerProvider>, crate::Error> {
+    let Some(ref endpoint) = CONFIG.otlp_endpoint else {
+        return Ok(None);
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint.clone())
+        .build()?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build();
+
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+/// Returns the tracer used for all template-worker spans
+pub fn tracer() -> BoxedTracer {
+    global::tracer("template-worker")
+}
+
+/// Serializes the span context of `cx` into a W3C `traceparent` for propagation over mesophyll
+pub fn inject(cx: &Context) -> Option<String> {
+    if !cx.span().span_context().is_valid() {
+        return None;
+    }
+
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(cx, &mut carrier);
+    carrier.remove("traceparent")
+}
+
+/// Parses a W3C `traceparent` into a context, returning an empty context if none/invalid
+pub fn extract(traceparent: Option<&str>) -> Context {
+    let Some(traceparent) = traceparent else {
+        return Context::new();
+    };
+
+    let mut carrier = HashMap::new();
+    carrier.insert("traceparent".to_string(), traceparent.to_string());
+    TraceContextPropagator::new().extract(&carrier)
+}
+
+/// Starts a new span as a child of `parent`, returning the context containing it
+pub fn child(parent: &Context, name: &'static str, kind: SpanKind, attributes: Vec<KeyValue>) -> Context {
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_kind(kind)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, parent);
+    parent.with_span(span)
+}
+
+/// Records an (already finished) span that started at `started_at` and ends now
+///
+/// Useful for measuring time spent waiting in queues where no code runs during the span
+pub fn record_since(parent: &Context, name: &'static str, started_at: SystemTime, attributes: Vec<KeyValue>) {
+    let tracer = tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Internal)
+        .with_start_time(started_at)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, parent);
+    let cx = parent.with_span(span);
+    cx.span().end();
+}
@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::worker::workervmmanager::Id;
+
+/// KV scope the templates of a tenant are stored in (see `auxutils/scriptmanager.luau`)
+pub const TEMPLATES_SCOPE: &str = "builtins.templates";
+
+/// Tells workers the cached templates of a tenant are stale
+///
+/// Invalidations are numbered by the master so workers can tell if they missed one (in which case every
+/// tenant on the worker is invalidated as it is unknown which tenant the missed invalidation was for)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateInvalidation {
+    /// Random per master process, changes when the master restarts (and so `seq` restarts)
+    pub epoch: u64,
+    /// Sequence number of the invalidation within the epoch, starting at 1
+    pub seq: u64,
+    pub id: Id,
+}
+
+/// Master-side numbering of template invalidations
+pub struct TemplateInvalidator {
+    epoch: u64,
+    seq: AtomicU64,
+}
+
+impl Default for TemplateInvalidator {
+    fn default() -> Self {
+        Self { epoch: rand::random(), seq: AtomicU64::new(0) }
+    }
+}
+
+impl TemplateInvalidator {
+    /// Returns the next invalidation for a tenant
+    pub fn next(&self, id: Id) -> TemplateInvalidation {
+        TemplateInvalidation { epoch: self.epoch, seq: self.seq.fetch_add(1, Ordering::SeqCst) + 1, id }
+    }
+}
+
+/// What a worker should invalidate after receiving an invalidation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalidate {
+    /// Only the tenant of the invalidation
+    Tenant(Id),
+    /// Invalidations were missed, every tenant
+    All,
+}
+
+/// Worker-side tracking of the last invalidation received
+#[derive(Default)]
+pub struct TemplateVersions {
+    last: Mutex<Option<(u64, u64)>>,
+}
+
+impl TemplateVersions {
+    /// Records an invalidation, returning what it invalidates
+    pub fn observe(&self, inv: &TemplateInvalidation) -> Invalidate {
+        let mut last = self.last.lock();
+        let res = match *last {
+            // First invalidation since the worker started, nothing could have been missed as all caches are fresh
+            None => Invalidate::Tenant(inv.id),
+            Some((epoch, seq)) if epoch == inv.epoch => {
+                if inv.seq <= seq {
+                    // Reordered (concurrent broadcasts) or duplicate, reloading the tenant again is harmless
+                    return Invalidate::Tenant(inv.id);
+                } else if inv.seq == seq + 1 {
+                    Invalidate::Tenant(inv.id)
+                } else {
+                    Invalidate::All
+                }
+            }
+            // The master restarted, invalidations sent while it was down (or before it caught up) may have been lost
+            Some(_) => Invalidate::All,
+        };
+        *last = Some((inv.epoch, inv.seq));
+        res
+    }
+}
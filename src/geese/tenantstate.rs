@@ -1,7 +1,23 @@
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
+use dapi::ChannelId;
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
 use khronos_runtime::rt::mlua::prelude::*;
 
+use crate::geese::namepolicy::NamePolicy;
+use crate::geese::nukeprotection::NukeProtectionConfig;
+use crate::geese::webhookspam::WebhookSpamConfig;
+use crate::geese::autopublish::AutoPublishConfig;
+use crate::geese::threadpolicies::ThreadPoliciesConfig;
+use crate::geese::emojiusage::EmojiUsageConfig;
+use crate::geese::activity::ActivityConfig;
+use crate::geese::auditcorrelation::AuditCorrelationConfig;
+use crate::geese::voiceidle::VoiceIdleConfig;
+use crate::geese::resultcache::ResultCacheConfig;
+use crate::geese::stickyroles::StickyRolesConfig;
+use crate::worker::altdetect::AltSensitivity;
+use crate::worker::limits::{MAX_TEMPLATE_MEMORY_USAGE, MAX_TEMPLATES_EXECUTION_TIME, MAX_TEMPLATES_RETURN_WAIT, MAX_TENANT_EXECUTION_TIME, MAX_TENANT_MEMORY_USAGE, MAX_TENANT_RETURN_WAIT, MAX_USER_TEMPLATE_MEMORY_USAGE, MAX_USER_TEMPLATES_EXECUTION_TIME, MAX_USER_TEMPLATES_RETURN_WAIT};
 use crate::worker::workervmmanager::Id;
 
 #[derive(Clone)]
@@ -10,14 +26,34 @@ pub struct TenantStateDb {
     pool: sqlx::PgPool,
 }
 
+/// Columns of tenant_state loaded into a `TenantStatePartial`
+const TENANT_STATE_COLUMNS: &str = "owner_id, owner_type, modflags, memory_limit, execution_time_limit_ms, return_wait_ms, settings, archived_at";
+
 #[derive(sqlx::FromRow)]
 /// Internally used for storing raw tenant state without refs
 struct TenantStatePartial {
     modflags: i32,
+    memory_limit: Option<i64>,
+    execution_time_limit_ms: Option<i64>,
+    return_wait_ms: Option<i64>,
+    settings: serde_json::Value,
+    archived_at: Option<chrono::DateTime<chrono::Utc>>,
     owner_id: String,
     owner_type: String,
 }
 
+impl TenantStatePartial {
+    fn into_tenant_state(self) -> TenantState {
+        TenantState {
+            events: HashMap::new(),
+            modflags: ModFlags::from_bits_truncate(self.modflags.try_into().unwrap_or(0)),
+            limits: TenantLimits::from_partial(&self),
+            settings: TenantSettings::parse(&format!("{}/{}", self.owner_type, self.owner_id), self.settings),
+            archived_at: self.archived_at,
+        }
+    }
+}
+
 #[derive(sqlx::FromRow)]
 /// Internally used for storing tenant state event refs
 struct TenantStateEventRefs {
@@ -35,11 +71,10 @@ impl TenantStateDb {
 
     /// Returns the tenant state(s) for all tenant in the database
     /// 
-    /// Should only be called once, on startup, to initialize the tenant state cache
-    pub async fn get_tenant_state(&self, id: i64, num_workers: i64) -> Result<HashMap<Id, TenantState>, crate::Error> {
-        let partials: Vec<TenantStatePartial> = sqlx::query_as("SELECT owner_id, owner_type, modflags FROM tenant_state WHERE ((owner_id::bigint >> 22) % $1 = $2)")
-            .bind(num_workers)
-            .bind(id)
+    /// Used to initialize the tenant state cache of workers (filtered down to the tenants routed to them)
+    /// and when rebalancing tenants between workers
+    pub async fn get_tenant_state(&self) -> Result<HashMap<Id, TenantState>, crate::Error> {
+        let partials: Vec<TenantStatePartial> = sqlx::query_as(&format!("SELECT {TENANT_STATE_COLUMNS} FROM tenant_state"))
             .fetch_all(&self.pool)
             .await?;
 
@@ -52,11 +87,8 @@ impl TenantStateDb {
             FROM tenant_state_events tse
             JOIN tenant_state ts 
                 ON ts.owner_id = tse.owner_id AND ts.owner_type = tse.owner_type
-            WHERE ((ts.owner_id::bigint >> 22) % $1 = $2)
             GROUP BY tse.owner_id, tse.owner_type, tse.event
         ")
-            .bind(num_workers)
-            .bind(id)
             .fetch_all(&self.pool)
             .await?;
 
@@ -67,7 +99,7 @@ impl TenantStateDb {
     /// 
     /// Should only be called once, on startup, to initialize the tenant state cache
     pub async fn get_tenant_state_for<'c>(&self, tx: &mut sqlx::Transaction<'c, sqlx::Postgres>, tid: Id) -> Result<Option<TenantState>, crate::Error> {
-        let Some(partials) = sqlx::query_as("SELECT owner_id, owner_type, modflags FROM tenant_state WHERE owner_id = $1 AND owner_type = $2")
+        let Some(partial) = sqlx::query_as(&format!("SELECT {TENANT_STATE_COLUMNS} FROM tenant_state WHERE owner_id = $1 AND owner_type = $2"))
             .bind(tid.tenant_id())
             .bind(tid.tenant_type())
             .fetch_optional(&mut **tx)
@@ -85,7 +117,8 @@ impl TenantStateDb {
             .bind(tid.tenant_type())
             .fetch_all(&mut **tx)
             .await?;
-        Ok(Some(TenantStateDb::into_tenant_state_single(partials, partial_refs)))
+
+        Ok(Some(TenantStateDb::into_tenant_state_single(partial, partial_refs)))
     }
 
     fn into_tenant_state(partials: Vec<TenantStatePartial>, partial_refs: Vec<TenantStateEventRefs>) -> HashMap<Id, TenantState> {
@@ -94,12 +127,7 @@ impl TenantStateDb {
             let Some(id) = Id::from_parts(&partial.owner_type, &partial.owner_id) else {
                 continue;
             };
-            let state = TenantState {
-                events: HashMap::new(),
-                modflags: ModFlags::from_bits_truncate(partial.modflags.try_into().unwrap_or(0))
-            };
-
-            states.insert(id, state);
+            states.insert(id, partial.into_tenant_state());
         }
 
         for refs in partial_refs {
@@ -114,10 +142,7 @@ impl TenantStateDb {
     }
 
     fn into_tenant_state_single(partial: TenantStatePartial, partial_refs: Vec<TenantStateEventRefs>) -> TenantState {
-        let mut state =  TenantState {
-            events: HashMap::new(),
-            modflags: ModFlags::from_bits_truncate(partial.modflags.try_into().unwrap_or(0))
-        };
+        let mut state = partial.into_tenant_state();
 
         for refs in partial_refs {
             state.events.insert(refs.event, HashSet::from_iter(refs.systems));
@@ -144,32 +169,232 @@ impl Default for ModFlags {
     }
 }
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+/// Per-tenant overrides of the VM limits (e.g. for premium tiers)
+///
+/// Unset limits fall back to the worker defaults (see `for_tenant` for user tenants) and set limits are capped
+/// to the `MAX_TENANT_*` limits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TenantLimits {
+    /// Maximum memory usage of the tenants VM in bytes
+    pub memory_limit: Option<u64>,
+    /// Maximum execution time (in milliseconds) before a scheduler yield must happen
+    pub execution_time_limit_ms: Option<u64>,
+    /// Maximum time (in milliseconds) to wait for a dispatched event to return
+    pub return_wait_ms: Option<u64>,
+}
+
+impl TenantLimits {
+    fn from_partial(partial: &TenantStatePartial) -> Self {
+        Self {
+            memory_limit: partial.memory_limit.and_then(|v| v.try_into().ok()),
+            execution_time_limit_ms: partial.execution_time_limit_ms.and_then(|v| v.try_into().ok()),
+            return_wait_ms: partial.return_wait_ms.and_then(|v| v.try_into().ok()),
+        }
+    }
+
+    /// Fills in the unset limits of a user tenant with the (smaller) user tenant defaults
+    pub fn for_tenant(mut self, id: Id) -> Self {
+        if let Id::User(_) = id {
+            self.memory_limit.get_or_insert(MAX_USER_TEMPLATE_MEMORY_USAGE as u64);
+            self.execution_time_limit_ms.get_or_insert(MAX_USER_TEMPLATES_EXECUTION_TIME.as_millis() as u64);
+            self.return_wait_ms.get_or_insert(MAX_USER_TEMPLATES_RETURN_WAIT.as_millis() as u64);
+        }
+        self
+    }
+
+    /// Returns the memory limit of the tenants VM
+    pub fn memory_limit(&self) -> usize {
+        match self.memory_limit {
+            Some(limit) => usize::try_from(limit).unwrap_or(usize::MAX).min(MAX_TENANT_MEMORY_USAGE),
+            None => MAX_TEMPLATE_MEMORY_USAGE,
+        }
+    }
+
+    /// Returns the execution time limit of the tenants VM
+    pub fn execution_time(&self) -> Duration {
+        match self.execution_time_limit_ms {
+            Some(ms) => Duration::from_millis(ms).min(MAX_TENANT_EXECUTION_TIME),
+            None => MAX_TEMPLATES_EXECUTION_TIME,
+        }
+    }
+
+    /// Returns how long to wait for a dispatched event to return
+    pub fn return_wait(&self) -> Duration {
+        match self.return_wait_ms {
+            Some(ms) => Duration::from_millis(ms).min(MAX_TENANT_RETURN_WAIT),
+            None => MAX_TEMPLATES_RETURN_WAIT,
+        }
+    }
+}
+
+impl IntoLua for TenantLimits {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table_with_capacity(0, 3)?;
+        table.set("memory_limit", self.memory_limit())?;
+        table.set("execution_time_limit_ms", self.execution_time().as_millis() as u64)?;
+        table.set("return_wait_ms", self.return_wait().as_millis() as u64)?;
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct TenantState {
     pub events: HashMap<String, HashSet<String>>,
     pub modflags: ModFlags,
+    #[serde(default)]
+    pub limits: TenantLimits,
+    #[serde(flatten)]
+    pub settings: TenantSettings,
+    /// When the templates and key-value data of the tenant were moved to cold storage, unset if not archived
+    ///
+    /// Archived tenants are rehydrated on their next GUILD_CREATE, see `ArchiveDb`
+    #[serde(default)]
+    pub archived_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-impl Default for TenantState {
-    fn default() -> Self {
-        Self {
-            events: HashMap::new(),
-            modflags: ModFlags::empty()
-        }
+/// The config of the subsystems of a tenant, stored as the `settings` object of tenant_state keyed by field name
+///
+/// Subsystems are disabled when their key is unset. Keys which fail to deserialize are logged and treated as unset
+/// instead of failing to load the tenant states of every tenant. Keys are written by `set_setting`
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TenantSettings {
+    /// Channel modmail threads are opened in, modmail is disabled if unset
+    pub modmail_channel_id: Option<ChannelId>,
+    /// Channel appeals are posted to for review, appeals are disabled if unset
+    pub appeals_channel_id: Option<ChannelId>,
+    /// Channel watchlist digests are posted to, digests are disabled if unset
+    pub watchlist_channel_id: Option<ChannelId>,
+    /// How readily joining members are flagged as alts, alt detection is disabled if unset
+    pub alt_sensitivity: Option<AltSensitivity>,
+    /// Whether the invites members join through are tracked
+    pub invite_tracking: bool,
+    /// Rules the names of members must follow, names are not checked if unset
+    pub name_policy: Option<NamePolicy>,
+    /// How the roles of members leaving are restored when they rejoin, roles are not restored if unset
+    pub sticky_roles: Option<StickyRolesConfig>,
+    /// Whether channel overwrites and role permissions are periodically snapshotted and diffed
+    pub permission_snapshots: bool,
+    /// How destructive actions are detected and responded to, actions are not counted if unset
+    pub nuke_protection: Option<NukeProtectionConfig>,
+    /// How spam sent through webhooks is detected, webhook messages are not counted if unset
+    pub webhook_spam: Option<WebhookSpamConfig>,
+    /// Which messages of announcement channels are published, none are if unset
+    pub auto_publish: Option<AutoPublishConfig>,
+    /// How the threads of forum and text channels are archived, tagged and pinned
+    pub thread_policies: Option<ThreadPoliciesConfig>,
+    /// How custom emoji and sticker usage is tracked, untracked if unset
+    pub emoji_usage: Option<EmojiUsageConfig>,
+    /// How message activity is tracked for heatmaps, untracked if unset
+    pub activity: Option<ActivityConfig>,
+    /// Which gateway events have their executor looked up in the audit log, none if unset
+    pub audit_correlation: Option<AuditCorrelationConfig>,
+    /// How members idling in voice channels are detected, undetected if unset
+    pub voice_idle: Option<VoiceIdleConfig>,
+    /// Templates whose results are cached by workers, see `ResultCacheConfig`
+    pub result_cache: Option<ResultCacheConfig>,
+    /// Plugins the guild has disabled for its templates, on top of those disabled by the config
+    pub disabled_plugins: Vec<String>,
+}
+
+impl TenantSettings {
+    /// Parses the settings object of a tenant, leaving out (and warning about) keys which fail to deserialize
+    fn parse(id: &str, settings: serde_json::Value) -> Self {
+        let err = match serde_json::from_value(settings.clone()) {
+            Ok(parsed) => return parsed,
+            Err(e) => e,
+        };
+        let serde_json::Value::Object(keys) = settings else {
+            log::warn!("Ignoring settings of {id}, not an object: {err}");
+            return Self::default();
+        };
+
+        let valid = keys.into_iter().filter(|(key, value)| {
+            let single = serde_json::Value::Object(serde_json::Map::from_iter([(key.clone(), value.clone())]));
+            match serde_json::from_value::<Self>(single) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::warn!("Ignoring invalid setting {key} of {id}, the subsystem is disabled until it is set again: {e}");
+                    false
+                }
+            }
+        }).collect();
+        serde_json::from_value(serde_json::Value::Object(valid)).unwrap_or_default()
     }
 }
 
+/// Sets one key of the settings of a tenant, unsetting it if `value` is `None`, and returns whether it changed
+///
+/// `key` must be a field of `TenantSettings`
+pub async fn set_setting<'c, E>(executor: E, tid: Id, key: &str, value: Option<serde_json::Value>) -> Result<bool, crate::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    set_setting_with(executor, tid, key, value, "", "").await
+}
+
+/// Like `set_setting`, with `with` prepended to the upsert and `reset` appended to its SET clause
+///
+/// `with` (e.g. a CTE deleting the data of a subsystem being disabled) may use `$1` for the tenant ID and `$4` for the
+/// new value. `reset` (e.g. `, swept_at = NULL`) only applies when the setting changed
+pub async fn set_setting_with<'c, E>(executor: E, tid: Id, key: &str, value: Option<serde_json::Value>, with: &str, reset: &str) -> Result<bool, crate::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let res = sqlx::query(&format!(
+        r#"
+        {with}
+        INSERT INTO tenant_state (owner_id, owner_type, settings)
+        VALUES ($1, $2, jsonb_strip_nulls(jsonb_build_object($3::TEXT, $4::JSONB)))
+        ON CONFLICT (owner_id, owner_type) DO UPDATE SET settings = CASE
+            WHEN $4::JSONB IS NULL THEN tenant_state.settings - $3::TEXT
+            ELSE tenant_state.settings || jsonb_build_object($3::TEXT, $4::JSONB)
+        END{reset}
+        WHERE tenant_state.settings -> $3::TEXT IS DISTINCT FROM $4::JSONB
+        "#
+    ))
+    .bind(tid.tenant_id())
+    .bind(tid.tenant_type())
+    .bind(key)
+    .bind(value)
+    .execute(executor)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
 impl IntoLua for TenantState {
     fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
-        let table = lua.create_table_with_capacity(0, 3)?;
+        let table = lua.create_table_with_capacity(0, 21)?;
+        let settings = self.settings;
 
         table.set("events", self.events)?;
         table.set("modflags", self.modflags.bits())?;
+        table.set("limits", self.limits)?;
+        table.set("modmail_channel_id", settings.modmail_channel_id.map(|c| c.to_string()))?;
+        table.set("appeals_channel_id", settings.appeals_channel_id.map(|c| c.to_string()))?;
+        table.set("watchlist_channel_id", settings.watchlist_channel_id.map(|c| c.to_string()))?;
+        table.set("alt_sensitivity", settings.alt_sensitivity.map(|s| s.as_str()))?;
+        table.set("invite_tracking", settings.invite_tracking)?;
+        table.set("name_policy", lua.to_value_with(&settings.name_policy, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("sticky_roles", lua.to_value_with(&settings.sticky_roles, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("permission_snapshots", settings.permission_snapshots)?;
+        table.set("nuke_protection", lua.to_value_with(&settings.nuke_protection, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("webhook_spam", lua.to_value_with(&settings.webhook_spam, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("auto_publish", lua.to_value_with(&settings.auto_publish, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("thread_policies", lua.to_value_with(&settings.thread_policies, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("emoji_usage", lua.to_value_with(&settings.emoji_usage, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("activity", lua.to_value_with(&settings.activity, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("audit_correlation", lua.to_value_with(&settings.audit_correlation, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("voice_idle", lua.to_value_with(&settings.voice_idle, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("result_cache", lua.to_value_with(&settings.result_cache, LUA_SERIALIZE_OPTIONS)?)?;
+        table.set("disabled_plugins", settings.disabled_plugins)?;
         Ok(LuaValue::Table(table))
     }
 }
 
 // DEFAULT_EVENTS is handled by WorkerDispatch directly
-pub static DEFAULT_EVENTS: [&str; 3] = [
-    "INTERACTION_CREATE", "WebSettings", "$UpdateTenantState"
+pub static DEFAULT_EVENTS: [&str; 12] = [
+    "INTERACTION_CREATE", "WebSettings", "WebPolicyTest", "$UpdateTenantState", "$OnboardingStep", "OnboardingCompleted", "$InvalidateTemplates", "$ImportStings", "$NamePolicySting", "$Backup", "$NukeLockdown", "$TemplateEnv"
 ];
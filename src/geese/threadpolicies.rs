@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::Utc;
+use dapi::{ChannelId, GuildId};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of channels with thread policies
+pub const MAX_THREAD_POLICIES: usize = 25;
+
+/// Maximum number of tag or pin rules of a thread policy
+pub const MAX_THREAD_RULES: usize = 20;
+
+/// Maximum length of a title pattern
+pub const MAX_THREAD_PATTERN_LENGTH: usize = 200;
+
+/// Maximum size of a compiled title pattern
+pub const MAX_THREAD_PATTERN_SIZE: usize = 64 * 1024;
+
+/// Maximum number of days threads may stay inactive before being archived
+pub const MAX_ARCHIVE_AFTER_DAYS: u32 = 365;
+
+/// Maximum open thread limit of a channel
+pub const MAX_OPEN_THREADS: u32 = 1000;
+
+/// The thread policies of a guild, one per forum or text channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreadPoliciesConfig {
+    pub policies: Vec<ThreadPolicy>,
+}
+
+/// How the threads of a forum or text channel are managed
+///
+/// Pinned threads are never archived by a policy nor counted towards its open thread limit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreadPolicy {
+    /// The forum or text channel whose threads the policy applies to
+    pub channel_id: ChannelId,
+    /// Threads without messages for this many days are archived
+    #[serde(default)]
+    pub archive_after_days: Option<u32>,
+    /// Whether threads archived by the policy are also locked, so only moderators can reopen them
+    #[serde(default)]
+    pub lock_on_archive: bool,
+    /// Tags applied to new (forum) threads whose title matches
+    #[serde(default)]
+    pub tag_rules: Vec<ThreadTagRule>,
+    /// Whether new (forum) threads are pinned or unpinned by title, the first matching rule applies
+    #[serde(default)]
+    pub pin_rules: Vec<ThreadPinRule>,
+    /// Once more threads are open, the least recently active ones are archived
+    #[serde(default)]
+    pub max_open_threads: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreadTagRule {
+    /// Case insensitive regex pattern matched against the thread title
+    pub pattern: String,
+    /// The forum tag applied
+    pub tag_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreadPinRule {
+    /// Case insensitive regex pattern matched against the thread title
+    pub pattern: String,
+    /// Whether matching threads are pinned (else unpinned)
+    pub pin: bool,
+}
+
+/// Compiles a title pattern of a tag or pin rule
+pub fn compile_pattern(pattern: &str) -> Result<regex::Regex, crate::Error> {
+    Ok(regex::RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(MAX_THREAD_PATTERN_SIZE)
+        .build()?)
+}
+
+impl ThreadPoliciesConfig {
+    /// Returns the policy of a channel
+    pub fn policy(&self, channel_id: ChannelId) -> Option<&ThreadPolicy> {
+        self.policies.iter().find(|p| p.channel_id == channel_id)
+    }
+
+    /// Validates the policies, including that all of their patterns compile
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.policies.is_empty() {
+            return Err("At least one thread policy must be set".into());
+        }
+        if self.policies.len() > MAX_THREAD_POLICIES {
+            return Err(format!("At most {MAX_THREAD_POLICIES} channels can have thread policies").into());
+        }
+        if self.policies.iter().map(|p| p.channel_id).collect::<HashSet<_>>().len() != self.policies.len() {
+            return Err("Channels can only have one thread policy".into());
+        }
+
+        for policy in self.policies.iter() {
+            let channel_id = policy.channel_id;
+            if policy.archive_after_days.is_some_and(|d| d == 0 || d > MAX_ARCHIVE_AFTER_DAYS) {
+                return Err(format!("Threads of channel {channel_id} must be archived after between 1 and {MAX_ARCHIVE_AFTER_DAYS} days").into());
+            }
+            if policy.max_open_threads.is_some_and(|m| m == 0 || m > MAX_OPEN_THREADS) {
+                return Err(format!("The open thread limit of channel {channel_id} must be between 1 and {MAX_OPEN_THREADS}").into());
+            }
+            if policy.tag_rules.len() > MAX_THREAD_RULES || policy.pin_rules.len() > MAX_THREAD_RULES {
+                return Err(format!("Thread policies can have at most {MAX_THREAD_RULES} tag and {MAX_THREAD_RULES} pin rules").into());
+            }
+            if policy.tag_rules.iter().any(|r| r.tag_id.parse::<u64>().is_err()) {
+                return Err(format!("Invalid tag ID in thread policy of channel {channel_id}").into());
+            }
+
+            let patterns = policy.tag_rules.iter().map(|r| &r.pattern).chain(policy.pin_rules.iter().map(|r| &r.pattern));
+            for pattern in patterns {
+                if pattern.is_empty() || pattern.len() > MAX_THREAD_PATTERN_LENGTH {
+                    return Err(format!("Title patterns must be between 1 and {MAX_THREAD_PATTERN_LENGTH} characters").into());
+                }
+                compile_pattern(pattern).map_err(|e| format!("Invalid title pattern {pattern:?}: {e}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ThreadPolicyDb {
+    pool: sqlx::PgPool,
+}
+
+impl ThreadPolicyDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Claims up to `limit` guilds with thread policies whose threads were last swept more than `interval` ago
+    pub async fn claim_due(&self, limit: i64, interval: Duration) -> Result<Vec<GuildId>, crate::Error> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            "UPDATE tenant_state ts SET thread_policy_swept_at = NOW()
+            FROM (
+                SELECT owner_id, owner_type FROM tenant_state
+                WHERE owner_type = 'guild' AND settings ? 'thread_policies' AND (thread_policy_swept_at IS NULL OR thread_policy_swept_at <= $2)
+                ORDER BY thread_policy_swept_at NULLS FIRST LIMIT $1 FOR UPDATE SKIP LOCKED
+            ) due
+            WHERE ts.owner_id = due.owner_id AND ts.owner_type = due.owner_type
+            RETURNING ts.owner_id"
+        )
+        .bind(limit)
+        .bind(Utc::now() - chrono::Duration::from_std(interval)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(guild_id,)| Ok(guild_id.parse()?))
+            .collect()
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::geese::dbpools::PoolKind;
+use crate::geese::dbrouter::DbRouter;
+use crate::worker::workervmmanager::Id;
+
+/// Maximum length of a template name to account usage under
+pub const MAX_USAGE_SOURCE_LENGTH: usize = 128;
+
+/// Usage counters of a single template for a single day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TemplateUsage {
+    /// Number of events dispatched to the template
+    pub executions: u64,
+    /// Number of executions which errored
+    pub errors: u64,
+    /// Total wall time spent executing the template (including time spent yielding)
+    pub wall_time_ms: u64,
+    /// Highest VM memory usage observed after an execution of the template
+    ///
+    /// All templates of a tenant share a VM so this is the memory of the whole VM at the time, not of the template alone
+    pub memory_peak_bytes: u64,
+    /// Number of Discord API calls made
+    pub discord_api_calls: u64,
+    /// Number of key-value/state operations performed
+    pub kv_ops: u64,
+}
+
+impl TemplateUsage {
+    fn merge(&mut self, other: &TemplateUsage) {
+        self.executions += other.executions;
+        self.errors += other.errors;
+        self.wall_time_ms += other.wall_time_ms;
+        self.memory_peak_bytes = self.memory_peak_bytes.max(other.memory_peak_bytes);
+        self.discord_api_calls += other.discord_api_calls;
+        self.kv_ops += other.kv_ops;
+    }
+}
+
+/// A usage rollup for a template on a given day, sent from workers to the master
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub id: Id,
+    pub template: String,
+    pub day: NaiveDate,
+    pub usage: TemplateUsage,
+}
+
+/// Worker-side accumulator of template usage, periodically flushed to the master
+#[derive(Default)]
+pub struct UsageTracker {
+    pending: Mutex<HashMap<(Id, String, NaiveDate), TemplateUsage>>,
+}
+
+impl UsageTracker {
+    /// How often pending usage is flushed to the master
+    pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+    fn with(&self, id: Id, template: &str, f: impl FnOnce(&mut TemplateUsage)) {
+        if template.len() > MAX_USAGE_SOURCE_LENGTH {
+            return;
+        }
+
+        let day = chrono::Utc::now().date_naive();
+        let mut pending = self.pending.lock();
+        f(pending.entry((id, template.to_string(), day)).or_default());
+    }
+
+    /// Records a single execution of a template
+    pub fn record_execution(&self, id: Id, template: &str, wall_time_ms: u64, ok: bool, memory_bytes: usize) {
+        self.with(id, template, |u| {
+            u.executions += 1;
+            if !ok {
+                u.errors += 1;
+            }
+            u.wall_time_ms += wall_time_ms;
+            u.memory_peak_bytes = u.memory_peak_bytes.max(memory_bytes as u64);
+        })
+    }
+
+    /// Records a Discord API call made by a template
+    pub fn record_discord_call(&self, id: Id, template: &str) {
+        self.with(id, template, |u| u.discord_api_calls += 1)
+    }
+
+    /// Records key-value/state operations performed by a template
+    pub fn record_kv_ops(&self, id: Id, template: &str, ops: usize) {
+        self.with(id, template, |u| u.kv_ops += ops as u64)
+    }
+
+    /// Takes all pending usage records
+    pub fn take(&self) -> Vec<UsageRecord> {
+        let pending = std::mem::take(&mut *self.pending.lock());
+        pending.into_iter()
+            .map(|((id, template, day), usage)| UsageRecord { id, template, day, usage })
+            .collect()
+    }
+
+    /// Re-queues records which failed to be flushed
+    pub fn requeue(&self, records: Vec<UsageRecord>) {
+        let mut pending = self.pending.lock();
+        for record in records {
+            pending.entry((record.id, record.template, record.day)).or_default().merge(&record.usage);
+        }
+    }
+}
+
+/// A row of the daily usage rollup table
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TemplateUsageRow {
+    pub template: String,
+    pub day: NaiveDate,
+    pub executions: i64,
+    pub errors: i64,
+    pub wall_time_ms: i64,
+    pub memory_peak_bytes: i64,
+    pub discord_api_calls: i64,
+    pub kv_ops: i64,
+}
+
+#[derive(Clone)]
+pub struct UsageDb {
+    pool: sqlx::PgPool,
+    db: DbRouter,
+}
+
+impl UsageDb {
+    pub fn new(db: DbRouter) -> Self {
+        Self { pool: db.pool(PoolKind::Analytics).pool().clone(), db }
+    }
+
+    /// Adds the given usage records to the daily rollups
+    pub async fn record(&self, records: Vec<UsageRecord>) -> Result<(), crate::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for record in records {
+            sqlx::query(
+                "INSERT INTO template_usage_daily (owner_id, owner_type, template, day, executions, errors, wall_time_ms, memory_peak_bytes, discord_api_calls, kv_ops)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                ON CONFLICT (owner_id, owner_type, template, day) DO UPDATE SET
+                    executions = template_usage_daily.executions + EXCLUDED.executions,
+                    errors = template_usage_daily.errors + EXCLUDED.errors,
+                    wall_time_ms = template_usage_daily.wall_time_ms + EXCLUDED.wall_time_ms,
+                    memory_peak_bytes = GREATEST(template_usage_daily.memory_peak_bytes, EXCLUDED.memory_peak_bytes),
+                    discord_api_calls = template_usage_daily.discord_api_calls + EXCLUDED.discord_api_calls,
+                    kv_ops = template_usage_daily.kv_ops + EXCLUDED.kv_ops"
+            )
+            .bind(record.id.tenant_id())
+            .bind(record.id.tenant_type())
+            .bind(record.template)
+            .bind(record.day)
+            .bind(record.usage.executions as i64)
+            .bind(record.usage.errors as i64)
+            .bind(record.usage.wall_time_ms as i64)
+            .bind(record.usage.memory_peak_bytes as i64)
+            .bind(record.usage.discord_api_calls as i64)
+            .bind(record.usage.kv_ops as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns the daily usage of all templates of a tenant over the last `days` days (oldest first)
+    pub async fn get_usage(&self, id: Id, days: u32) -> Result<Vec<TemplateUsageRow>, crate::Error> {
+        let since = chrono::Utc::now().date_naive() - chrono::Days::new(days.into());
+        if let Some(replica) = self.db.reads() {
+            match Self::fetch_usage(replica, id, since).await {
+                Ok(rows) => return Ok(rows),
+                Err(e) => self.db.replica_failed(&e.into()),
+            }
+        }
+        Ok(Self::fetch_usage(&self.pool, id, since).await?)
+    }
+
+    async fn fetch_usage(pool: &sqlx::PgPool, id: Id, since: NaiveDate) -> Result<Vec<TemplateUsageRow>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT template, day, executions, errors, wall_time_ms, memory_peak_bytes, discord_api_calls, kv_ops FROM template_usage_daily
+            WHERE owner_id = $1 AND owner_type = $2 AND day > $3 ORDER BY day ASC, template ASC"
+        )
+        .bind(id.tenant_id())
+        .bind(id.tenant_type())
+        .bind(since)
+        .fetch_all(pool)
+        .await
+    }
+}
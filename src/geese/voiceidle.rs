@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+
+use dapi::ChannelId;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of thresholds of a guild
+pub const MAX_VOICE_IDLE_THRESHOLDS: usize = 10;
+
+/// Maximum number of minutes of a threshold (a day)
+pub const MAX_VOICE_IDLE_MINUTES: u32 = 24 * 60;
+
+/// Maximum number of voice channels excluded from tracking
+pub const MAX_VOICE_IDLE_IGNORED_CHANNELS: usize = 50;
+
+/// What a member is doing for a threshold to be counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VoiceIdleKind {
+    /// Self-muted or self-deafened (or only self-deafened, see `VoiceIdleConfig::deafened_only`)
+    Idle,
+    /// In the same voice channel, whatever they are doing
+    Camping,
+}
+
+/// A threshold of a guild, crossed once per member per idle spell (or stay in a channel)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoiceIdleThreshold {
+    pub kind: VoiceIdleKind,
+    pub minutes: u32,
+}
+
+/// How members idling in the voice channels of a guild are detected
+///
+/// Voice activity is only kept in the memory of the worker, for as long as members are in voice, and is never stored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoiceIdleConfig {
+    pub thresholds: Vec<VoiceIdleThreshold>,
+    /// Whether only self-deafened members are idle, rather than self-muted ones too
+    #[serde(default)]
+    pub deafened_only: bool,
+    /// Voice channels members are not tracked in, e.g. the AFK channel
+    #[serde(default)]
+    pub ignored_channels: Vec<ChannelId>,
+}
+
+impl VoiceIdleConfig {
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.thresholds.is_empty() || self.thresholds.len() > MAX_VOICE_IDLE_THRESHOLDS {
+            return Err(format!("Between 1 and {MAX_VOICE_IDLE_THRESHOLDS} voice idle thresholds must be set").into());
+        }
+        if self.thresholds.iter().any(|t| t.minutes == 0 || t.minutes > MAX_VOICE_IDLE_MINUTES) {
+            return Err(format!("Voice idle thresholds must be between 1 and {MAX_VOICE_IDLE_MINUTES} minutes").into());
+        }
+        if self.thresholds.iter().map(|t| (t.kind, t.minutes)).collect::<HashSet<_>>().len() != self.thresholds.len() {
+            return Err("Voice idle thresholds must be unique".into());
+        }
+        if self.ignored_channels.len() > MAX_VOICE_IDLE_IGNORED_CHANNELS {
+            return Err(format!("At most {MAX_VOICE_IDLE_IGNORED_CHANNELS} voice channels can be ignored").into());
+        }
+        Ok(())
+    }
+}
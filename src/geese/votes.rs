@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, UserId};
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::{VoteAuth, VoteListConfig};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Event dispatched to the guild a vote was made for
+pub const VOTE_RECEIVED_EVENT: &str = "VoteReceived";
+
+/// Header containing the signature of `VoteAuth::HmacSha256` webhooks
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+/// Vote webhook body, covering the formats of the common bot lists
+///
+/// top.gg sends `user`, `type` and `isWeekend` along with `guild` for server votes (and the query string of the
+/// vote page as `query`), discordbotlist.com sends the voter as `id`
+#[derive(Debug, Deserialize)]
+struct VoteWebhook {
+    #[serde(alias = "id", alias = "userId")]
+    user: String,
+    #[serde(default, alias = "guildId", alias = "server")]
+    guild: Option<String>,
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    #[serde(default, rename = "isWeekend")]
+    is_weekend: bool,
+    #[serde(default)]
+    query: Option<String>,
+}
+
+/// A vote received from a bot list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vote {
+    /// The bot list the vote came from, as named in the config
+    pub list: String,
+    pub user_id: UserId,
+    /// The guild the vote was made for. For votes of the bot itself, this is the `guild` query parameter of
+    /// the vote link (e.g. `https://top.gg/bot/<id>/vote?guild=<guild id>`)
+    pub guild_id: Option<GuildId>,
+    /// Test votes sent from the dashboard of the bot list
+    pub test: bool,
+    /// Whether the vote counts double (top.gg weekends)
+    pub weekend: bool,
+    pub voted_at: DateTime<Utc>,
+}
+
+impl Vote {
+    /// Parses the body of a vote webhook
+    pub fn parse(list: &str, body: &[u8]) -> Result<Self, crate::Error> {
+        let webhook: VoteWebhook = serde_json::from_slice(body)?;
+        let user_id = webhook.user.parse::<UserId>().map_err(|_| "Vote has an invalid user id")?;
+
+        let query_guild = webhook.query.as_deref().and_then(|q| {
+            q.trim_start_matches('?')
+                .split('&')
+                .find_map(|kv| kv.strip_prefix("guild="))
+                .map(|g| g.to_string())
+        });
+        let guild_id = match webhook.guild.or(query_guild) {
+            Some(g) => Some(g.parse::<GuildId>().map_err(|_| "Vote has an invalid guild id")?),
+            None => None,
+        };
+
+        Ok(Self {
+            list: list.to_string(),
+            user_id,
+            guild_id,
+            test: webhook.kind.as_deref() == Some("test"),
+            weekend: webhook.is_weekend,
+            voted_at: Utc::now(),
+        })
+    }
+}
+
+/// Data of `VoteReceived` events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteReceived {
+    #[serde(flatten)]
+    pub vote: Vote,
+    /// Number of votes of the user for the guild over the last 30 days, including this one
+    pub votes_30d: i64,
+}
+
+/// Verifies a vote webhook was sent by the bot list, given its `Authorization` and signature headers
+pub fn verify(cfg: &VoteListConfig, authorization: Option<&str>, signature: Option<&str>, body: &[u8]) -> bool {
+    match cfg.auth {
+        VoteAuth::Authorization => {
+            let Some(authorization) = authorization else {
+                return false;
+            };
+            // Compare MACs of the header and secret rather than the secret itself to keep the comparison constant-time
+            let mac = |v: &str| {
+                let mut mac = HmacSha256::new_from_slice(cfg.secret.as_bytes()).expect("HMAC accepts keys of any size");
+                mac.update(v.as_bytes());
+                mac
+            };
+            mac(authorization).verify_slice(&mac(&cfg.secret).finalize().into_bytes()).is_ok()
+        }
+        VoteAuth::HmacSha256 => {
+            let Some(sig) = signature.and_then(|s| hex::decode(s.trim_start_matches("sha256=")).ok()) else {
+                return false;
+            };
+            let mut mac = HmacSha256::new_from_slice(cfg.secret.as_bytes()).expect("HMAC accepts keys of any size");
+            mac.update(body);
+            mac.verify_slice(&sig).is_ok()
+        }
+    }
+}
+
+/// Stores votes received from bot lists
+#[derive(Clone)]
+pub struct VoteDb {
+    pool: sqlx::PgPool,
+}
+
+impl VoteDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Records a vote
+    pub async fn record(&self, vote: &Vote) -> Result<(), crate::Error> {
+        sqlx::query("INSERT INTO bot_votes (list, user_id, guild_id, is_test, is_weekend, created_at) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(&vote.list)
+            .bind(vote.user_id.to_string())
+            .bind(vote.guild_id.map(|g| g.to_string()))
+            .bind(vote.test)
+            .bind(vote.weekend)
+            .bind(vote.voted_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the number of (non-test) votes of a user since `since`, optionally only those made for a guild
+    pub async fn count(&self, user_id: UserId, guild_id: Option<GuildId>, since: DateTime<Utc>) -> Result<i64, crate::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM bot_votes WHERE user_id = $1 AND ($2::TEXT IS NULL OR guild_id = $2) AND created_at >= $3 AND NOT is_test"
+        )
+        .bind(user_id.to_string())
+        .bind(guild_id.map(|g| g.to_string()))
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
+    }
+}
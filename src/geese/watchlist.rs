@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, UserId};
+use khronos_runtime::core::datetime::DateTime as LuaDateTime;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of watchlisted users per guild, workers cache the watchlists of the guilds they dispatch to
+pub const MAX_WATCHLIST_ENTRIES: i64 = 500;
+
+/// Maximum length of the note of a watchlist entry
+pub const MAX_WATCHLIST_NOTE_LENGTH: usize = 500;
+
+/// How suspicious a watchlisted user is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, crate::Error> {
+        match s {
+            "low" => Ok(Self::Low),
+            "medium" => Ok(Self::Medium),
+            "high" => Ok(Self::High),
+            _ => Err(format!("Unknown watchlist severity: {s}").into()),
+        }
+    }
+}
+
+/// A user on the watchlist of a guild
+///
+/// Events performed by watchlisted users carry their entry (as `watchlist`) when dispatched to templates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchlistEntry {
+    pub user_id: UserId,
+    pub severity: Severity,
+    pub note: String,
+    /// The moderator who added (or last updated) the entry, if known
+    pub added_by: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct WatchlistRow {
+    user_id: String,
+    severity: String,
+    note: String,
+    added_by: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<WatchlistRow> for WatchlistEntry {
+    type Error = crate::Error;
+
+    fn try_from(row: WatchlistRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            user_id: row.user_id.parse()?,
+            severity: Severity::parse(&row.severity)?,
+            note: row.note,
+            added_by: row.added_by.map(|u| u.parse()).transpose()?,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+impl IntoLua for WatchlistEntry {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table_with_capacity(0, 6)?;
+        table.set("user_id", self.user_id.to_string())?;
+        table.set("severity", self.severity.as_str())?;
+        table.set("note", self.note)?;
+        table.set("added_by", self.added_by.map(|u| u.to_string()))?;
+        table.set("created_at", LuaDateTime::from_utc(self.created_at))?;
+        table.set("updated_at", LuaDateTime::from_utc(self.updated_at))?;
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Columns of `watchlist` selected into a `WatchlistRow`
+const WATCHLIST_COLUMNS: &str = "user_id, severity, note, added_by, created_at, updated_at";
+
+/// Lists the watchlist of a guild
+pub async fn list<'c, E>(executor: E, guild_id: &str) -> Result<Vec<WatchlistEntry>, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    let rows: Vec<WatchlistRow> = sqlx::query_as(&format!("SELECT {WATCHLIST_COLUMNS} FROM watchlist WHERE guild_id = $1 ORDER BY user_id"))
+        .bind(guild_id)
+        .fetch_all(executor)
+        .await?;
+    rows.into_iter().map(WatchlistEntry::try_from).collect()
+}
+
+/// Returns the watchlist entry of a user in a guild, if they are watchlisted
+pub async fn get<'c, E>(executor: E, guild_id: &str, user_id: &str) -> Result<Option<WatchlistEntry>, crate::Error>
+where E: sqlx::PgExecutor<'c> {
+    let row: Option<WatchlistRow> = sqlx::query_as(&format!("SELECT {WATCHLIST_COLUMNS} FROM watchlist WHERE guild_id = $1 AND user_id = $2"))
+        .bind(guild_id)
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await?;
+    row.map(WatchlistEntry::try_from).transpose()
+}
+
+/// Data of `$WatchlistDigest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchlistDigestReq {
+    /// When the previous digest was posted, entries added or updated since are highlighted
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Claims of watchlist digests for the master's digest scheduler
+#[derive(Clone)]
+pub struct WatchlistDb {
+    pool: sqlx::PgPool,
+}
+
+impl WatchlistDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Claims up to `limit` guilds with a watchlist channel whose last digest is older than `interval`, returning
+    /// them along with when their previous digest was posted
+    pub async fn claim_due_digests(&self, limit: i64, interval: Duration) -> Result<Vec<(GuildId, Option<DateTime<Utc>>)>, crate::Error> {
+        let rows: Vec<(String, Option<DateTime<Utc>>)> = sqlx::query_as(
+            "UPDATE tenant_state ts SET watchlist_digest_at = NOW()
+            FROM (
+                SELECT owner_id, owner_type, watchlist_digest_at FROM tenant_state
+                WHERE owner_type = 'guild' AND settings ? 'watchlist_channel_id' AND (watchlist_digest_at IS NULL OR watchlist_digest_at <= $2)
+                ORDER BY watchlist_digest_at NULLS FIRST LIMIT $1 FOR UPDATE SKIP LOCKED
+            ) due
+            WHERE ts.owner_id = due.owner_id AND ts.owner_type = due.owner_type
+            RETURNING ts.owner_id, due.watchlist_digest_at"
+        )
+        .bind(limit)
+        .bind(Utc::now() - chrono::Duration::from_std(interval)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(guild_id, since)| Ok((guild_id.parse()?, since)))
+            .collect()
+    }
+}
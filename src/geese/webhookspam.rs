@@ -0,0 +1,64 @@
+use dapi::ChannelId;
+use serde::{Deserialize, Serialize};
+
+/// Maximum window webhook messages are counted in
+pub const MAX_WEBHOOK_SPAM_WINDOW_SECS: u32 = 300;
+
+/// Maximum number of webhooks exempt from spam detection
+pub const MAX_WEBHOOK_SPAM_EXEMPT: usize = 50;
+
+/// How spam sent through webhooks is detected
+///
+/// A webhook is deemed compromised once it sends `max_messages` messages (or `max_duplicates` messages with the same
+/// content) within the window, or any message mentioning everyone if `block_mass_mentions` is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookSpamConfig {
+    /// How many seconds messages are counted for
+    pub window_secs: u32,
+    /// How many messages a webhook may send within the window, across all channels
+    pub max_messages: u32,
+    /// How many messages with the same content a webhook may send within the window, unlimited if unset
+    #[serde(default)]
+    pub max_duplicates: Option<u32>,
+    /// Whether messages mentioning everyone (or here) are spam
+    #[serde(default)]
+    pub block_mass_mentions: bool,
+    /// Whether compromised webhooks are deleted
+    #[serde(default = "default_true")]
+    pub revoke: bool,
+    /// The channel compromised webhooks are reported to, if any
+    #[serde(default)]
+    pub log_channel_id: Option<ChannelId>,
+    /// Webhooks whose messages are never counted
+    #[serde(default)]
+    pub exempt_webhooks: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl WebhookSpamConfig {
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.window_secs == 0 || self.window_secs > MAX_WEBHOOK_SPAM_WINDOW_SECS {
+            return Err(format!("The webhook spam window must be between 1 and {MAX_WEBHOOK_SPAM_WINDOW_SECS} seconds").into());
+        }
+        if self.max_messages < 2 {
+            return Err("Webhooks must be allowed at least 2 messages within the window".into());
+        }
+        if self.max_duplicates.is_some_and(|d| d < 2) {
+            return Err("Webhooks must be allowed at least 2 duplicate messages within the window".into());
+        }
+        if self.exempt_webhooks.len() > MAX_WEBHOOK_SPAM_EXEMPT {
+            return Err(format!("At most {MAX_WEBHOOK_SPAM_EXEMPT} webhooks can be exempt from spam detection").into());
+        }
+        if self.exempt_webhooks.iter().any(|w| w.parse::<u64>().is_err()) {
+            return Err("Invalid exempt webhook ID".into());
+        }
+        Ok(())
+    }
+
+    pub fn window(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.window_secs as i64)
+    }
+}
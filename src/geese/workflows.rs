@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use khronos_runtime::core::datetime::DateTime as LuaDateTime;
+use khronos_runtime::rt::mlua::prelude::*;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::{Deserialize, Serialize};
+
+use crate::worker::workervmmanager::Id;
+
+/// Event dispatched to the templates of a tenant when a step of one of its workflows is due
+pub const WORKFLOW_STEP_EVENT: &str = "WorkflowStep";
+
+/// Maximum number of running workflow instances per tenant
+pub const MAX_RUNNING_WORKFLOWS: i64 = 1000;
+
+/// Maximum length of the names of workflows, steps and signals
+pub const MAX_WORKFLOW_NAME_LENGTH: usize = 64;
+
+/// Maximum size of the (JSON encoded) data of a workflow instance or signal
+pub const MAX_WORKFLOW_DATA_BYTES: usize = 64 * 1024; // 64kb
+
+/// Maximum time a step may wait before running
+pub const MAX_WORKFLOW_DELAY: Duration = Duration::from_secs(30 * 24 * 60 * 60); // 30 days
+
+/// Columns of `workflow_instances` selected into a `WorkflowInstance`
+pub const WORKFLOW_COLUMNS: &str = "id, owner_id, owner_type, workflow, key, step, data, state, seq, trigger, wake_at, wait_signal, signal_data, attempts, last_error, created_at, updated_at, completed_at";
+
+/// An instance of a workflow, a named set of steps a template moves through across events and restarts
+///
+/// Every transition bumps `seq`, transitions made with an outdated `seq` (e.g. by a step retried after its
+/// first attempt already moved the instance on) are rejected
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct WorkflowInstance {
+    pub id: i64,
+    pub owner_id: String,
+    pub owner_type: String,
+    /// Name of the workflow
+    pub workflow: String,
+    /// Key of the instance, unique among the running instances of the workflow (e.g. the user being moderated)
+    pub key: String,
+    /// The current step
+    pub step: String,
+    #[sqlx(json)]
+    pub data: KhronosValue,
+    /// `running`, `completed`, `cancelled` or `failed`
+    pub state: String,
+    pub seq: i32,
+    /// Why the current step runs: `start`, `step` (the previous step moved on immediately), `timer` or `signal`
+    pub trigger: String,
+    /// When the current step is due, unset if it only waits for a signal
+    pub wake_at: Option<DateTime<Utc>>,
+    /// The signal the current step waits for, if any
+    pub wait_signal: Option<String>,
+    /// Data of the signal which triggered the current step
+    pub signal_data: Option<serde_json::Value>,
+    /// Attempts at running the current step (claims by the workflow scheduler)
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl IntoLua for WorkflowInstance {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let signal_data = self.signal_data
+            .map(serde_json::from_value::<KhronosValue>)
+            .transpose()
+            .map_err(LuaError::external)?;
+
+        let table = lua.create_table()?;
+        table.set("id", self.id)?;
+        table.set("workflow", self.workflow)?;
+        table.set("key", self.key)?;
+        table.set("step", self.step)?;
+        table.set("data", self.data)?;
+        table.set("state", self.state)?;
+        table.set("seq", self.seq)?;
+        table.set("trigger", self.trigger)?;
+        table.set("wake_at", self.wake_at.map(LuaDateTime::from_utc))?;
+        table.set("wait_signal", self.wait_signal)?;
+        table.set("signal_data", signal_data)?;
+        table.set("attempts", self.attempts)?;
+        table.set("last_error", self.last_error)?;
+        table.set("created_at", LuaDateTime::from_utc(self.created_at))?;
+        table.set("updated_at", LuaDateTime::from_utc(self.updated_at))?;
+        table.set("completed_at", self.completed_at.map(LuaDateTime::from_utc))?;
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Data of `WorkflowStep` events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowStep {
+    pub id: i64,
+    pub workflow: String,
+    pub key: String,
+    pub step: String,
+    /// Passed back when moving the instance on, see `WorkflowInstance`
+    pub seq: i32,
+    pub trigger: String,
+    pub data: KhronosValue,
+    pub signal_data: Option<serde_json::Value>,
+    /// Attempts at running the step, including this one
+    pub attempt: i32,
+}
+
+impl From<WorkflowInstance> for WorkflowStep {
+    fn from(w: WorkflowInstance) -> Self {
+        Self {
+            id: w.id,
+            workflow: w.workflow,
+            key: w.key,
+            step: w.step,
+            seq: w.seq,
+            trigger: w.trigger,
+            data: w.data,
+            signal_data: w.signal_data,
+            attempt: w.attempts,
+        }
+    }
+}
+
+/// Validates the name of a workflow, step or signal
+pub fn validate_name(kind: &str, name: &str) -> Result<(), crate::Error> {
+    if name.is_empty() || name.len() > MAX_WORKFLOW_NAME_LENGTH {
+        return Err(format!("{kind} names must be between 1 and {MAX_WORKFLOW_NAME_LENGTH} characters").into());
+    }
+    Ok(())
+}
+
+/// Encodes the data of a workflow instance or signal, enforcing `MAX_WORKFLOW_DATA_BYTES`
+pub fn encode_data(data: &KhronosValue) -> Result<serde_json::Value, crate::Error> {
+    let data = serde_json::to_value(data)?;
+    if serde_json::to_vec(&data)?.len() > MAX_WORKFLOW_DATA_BYTES {
+        return Err(format!("workflow data exceeds {MAX_WORKFLOW_DATA_BYTES} bytes").into());
+    }
+    Ok(data)
+}
+
+/// Converts a step delay in seconds, enforcing `MAX_WORKFLOW_DELAY`
+pub fn step_delay(delay: u64) -> Result<chrono::Duration, crate::Error> {
+    let delay = Duration::from_secs(delay);
+    if delay > MAX_WORKFLOW_DELAY {
+        return Err(format!("workflow steps may wait at most {} seconds", MAX_WORKFLOW_DELAY.as_secs()).into());
+    }
+    Ok(chrono::Duration::from_std(delay)?)
+}
+
+/// Access to workflow instances for the master's workflow scheduler and the API
+///
+/// Templates create and move their instances through state ops (see `StateOp::WorkflowStart` and friends)
+#[derive(Clone)]
+pub struct WorkflowDb {
+    pool: sqlx::PgPool,
+}
+
+impl WorkflowDb {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Claims up to `limit` instances whose step is due for running
+    ///
+    /// Claimed instances stop waiting for signals and are leased for `lease`, if their step does not move them on
+    /// (and no failure is recorded) they become due again once the lease expires
+    pub async fn claim_due(&self, limit: i64, lease: Duration) -> Result<Vec<WorkflowInstance>, crate::Error> {
+        let instances = sqlx::query_as(&format!(
+            "UPDATE workflow_instances SET attempts = attempts + 1, wake_at = $2, wait_signal = NULL
+            WHERE id IN (
+                SELECT id FROM workflow_instances WHERE state = 'running' AND wake_at <= NOW()
+                ORDER BY wake_at LIMIT $1 FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {WORKFLOW_COLUMNS}"
+        ))
+        .bind(limit)
+        .bind(Utc::now() + chrono::Duration::from_std(lease)?)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(instances)
+    }
+
+    /// Records a failed attempt at the step of an instance (unless the step moved it on since being claimed),
+    /// retrying the step after `delay` or failing the instance if `delay` is `None`
+    ///
+    /// Returns whether the failure was recorded
+    pub async fn mark_failed(&self, id: i64, seq: i32, error: &str, delay: Option<Duration>) -> Result<bool, crate::Error> {
+        let res = match delay {
+            Some(delay) => {
+                sqlx::query("UPDATE workflow_instances SET last_error = $3, wake_at = $4 WHERE id = $1 AND seq = $2 AND state = 'running'")
+                    .bind(id)
+                    .bind(seq)
+                    .bind(error)
+                    .bind(Utc::now() + chrono::Duration::from_std(delay)?)
+                    .execute(&self.pool)
+                    .await?
+            }
+            None => {
+                sqlx::query(
+                    "UPDATE workflow_instances SET state = 'failed', last_error = $3, wake_at = NULL, seq = seq + 1, updated_at = NOW(), completed_at = NOW()
+                    WHERE id = $1 AND seq = $2 AND state = 'running'"
+                )
+                .bind(id)
+                .bind(seq)
+                .bind(error)
+                .execute(&self.pool)
+                .await?
+            }
+        };
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Lists the most recently created instances of a tenant, only running ones if `running_only` is set
+    pub async fn list(&self, tenant: Id, running_only: bool, limit: i64) -> Result<Vec<WorkflowInstance>, crate::Error> {
+        let instances = sqlx::query_as(&format!(
+            "SELECT {WORKFLOW_COLUMNS} FROM workflow_instances WHERE owner_id = $1 AND owner_type = $2 AND (NOT $3 OR state = 'running')
+            ORDER BY created_at DESC LIMIT $4"
+        ))
+        .bind(tenant.tenant_id())
+        .bind(tenant.tenant_type())
+        .bind(running_only)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(instances)
+    }
+
+    /// Returns an instance of a tenant
+    pub async fn get(&self, tenant: Id, id: i64) -> Result<Option<WorkflowInstance>, crate::Error> {
+        let instance = sqlx::query_as(&format!(
+            "SELECT {WORKFLOW_COLUMNS} FROM workflow_instances WHERE id = $1 AND owner_id = $2 AND owner_type = $3"
+        ))
+        .bind(id)
+        .bind(tenant.tenant_id())
+        .bind(tenant.tenant_type())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(instance)
+    }
+
+    /// Cancels a running instance of a tenant, returning whether it was running
+    pub async fn cancel(&self, tenant: Id, id: i64) -> Result<bool, crate::Error> {
+        let res = sqlx::query(
+            "UPDATE workflow_instances SET state = 'cancelled', wake_at = NULL, wait_signal = NULL, seq = seq + 1, updated_at = NOW(), completed_at = NOW()
+            WHERE id = $1 AND owner_id = $2 AND owner_type = $3 AND state = 'running'"
+        )
+        .bind(id)
+        .bind(tenant.tenant_id())
+        .bind(tenant.tenant_type())
+        .execute(&self.pool)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Deletes finished instances older than `retention`, returning the number deleted
+    pub async fn delete_finished(&self, retention: Duration) -> Result<u64, crate::Error> {
+        let res = sqlx::query("DELETE FROM workflow_instances WHERE state != 'running' AND completed_at < $1")
+            .bind(Utc::now() - chrono::Duration::from_std(retention)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use crate::geese::activity::ActivityDb;
+
+/// How often activity past its retention is deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Deletes the hourly activity of guilds once past their retention (and all activity of guilds no longer tracking
+/// it), so the rollups stay bounded to `MAX_ACTIVITY_RETENTION_DAYS` days
+pub struct ActivityPruner {
+    db: ActivityDb,
+}
+
+impl ActivityPruner {
+    pub fn new(db: ActivityDb) -> Self {
+        Self { db }
+    }
+
+    /// Spawns the background task pruning activity
+    pub fn spawn(self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                match self.db.prune().await {
+                    Ok(0) => {}
+                    Ok(n) => log::info!("Pruned {n} hours of channel activity"),
+                    Err(e) => log::error!("Failed to prune channel activity: {e}"),
+                }
+            }
+        });
+    }
+}
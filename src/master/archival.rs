@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::ArchivalConfig;
+use crate::geese::archival::{ArchiveDb, ArchiveOutcome, TenantArchive};
+use crate::master::workerpool::WorkerPool;
+use crate::worker::workervmmanager::Id;
+
+/// Archives the data of a tenant into cold storage, dropping its VM so its templates are unloaded
+pub async fn archive(worker_pool: &WorkerPool, id: Id, config: &ArchivalConfig, actor: Option<String>) -> Result<Option<TenantArchive>, crate::Error> {
+    let outcome = worker_pool.mesophyll().archive_db().archive(id, &config.dir).await?;
+    let details = outcome.archive.as_ref().map(|a| format!("{} records, {} bytes", a.kv_rows, a.size_bytes));
+    finish(worker_pool, id, outcome, "archived", actor, details).await
+}
+
+/// Restores the data of an archived tenant from cold storage
+pub async fn restore(worker_pool: &WorkerPool, id: Id, actor: Option<String>) -> Result<Option<TenantArchive>, crate::Error> {
+    let outcome = worker_pool.mesophyll().archive_db().restore(id).await?;
+    let details = outcome.archive.as_ref().map(|a| format!("{} records", a.kv_rows));
+    finish(worker_pool, id, outcome, "archive_restored", actor, details).await
+}
+
+/// Pushes the new tenant state of an archived or restored tenant to its worker and records the action
+async fn finish(worker_pool: &WorkerPool, id: Id, outcome: ArchiveOutcome, action: &str, actor: Option<String>, details: Option<String>) -> Result<Option<TenantArchive>, crate::Error> {
+    if let Some(ts) = outcome.tenant_state {
+        worker_pool.update_tenant_state(id, ts).await?;
+    }
+    if outcome.archive.is_some() {
+        worker_pool.drop_tenant(id).await?;
+        worker_pool.mesophyll().data_lifecycle_db().record_audit(None, id, action, actor, details).await?;
+    }
+    Ok(outcome.archive)
+}
+
+/// Periodically moves the data of guilds inactive for `inactive_days` into cold storage
+///
+/// Archived guilds are restored by their worker on their next GUILD_CREATE
+pub struct Archiver {
+    db: ArchiveDb,
+    worker_pool: Arc<WorkerPool>,
+    config: &'static ArchivalConfig,
+}
+
+impl Archiver {
+    pub fn new(worker_pool: Arc<WorkerPool>, config: &'static ArchivalConfig) -> Self {
+        Self {
+            db: worker_pool.mesophyll().archive_db().clone(),
+            worker_pool,
+            config,
+        }
+    }
+
+    /// Spawns the background task archiving inactive guilds
+    pub fn spawn(self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs.max(1)));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                self.tick().await;
+            }
+        });
+    }
+
+    async fn tick(&self) {
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(self.config.inactive_days as i64);
+        let ids = match self.db.inactive_tenants(cutoff, self.config.batch_size).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                log::error!("Failed to fetch inactive tenants to archive: {e}");
+                return;
+            }
+        };
+
+        for id in ids {
+            match archive(&self.worker_pool, id, self.config, None).await {
+                Ok(Some(archive)) => log::info!("Archived {} records of {id:?} to {}", archive.kv_rows, archive.path),
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to archive {id:?}: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use opentelemetry::{Context, KeyValue, trace::{SpanKind, TraceContextExt}};
+
+use crate::config::AutoscaleConfig;
+use crate::geese::telemetry;
+use crate::master::workerpool::WorkerPool;
+use crate::worker::load::WorkerLoad;
+
+/// Load of the workers on the ring, aggregated over a sample
+#[derive(Debug)]
+struct RingLoad {
+    /// Average time events waited for a VM thread, weighted by the number of events each worker dispatched
+    avg_queue_wait_ms: f64,
+    /// Highest time an event waited for a VM thread on any worker
+    max_queue_wait_ms: u64,
+    /// Average number of in-flight dispatches per worker
+    avg_inflight: f64,
+    /// Total number of VMs on the ring
+    vms: usize,
+}
+
+impl RingLoad {
+    fn new(loads: &[WorkerLoad]) -> Self {
+        let dispatched = loads.iter().map(|l| l.dispatched).sum::<u64>();
+        let avg_queue_wait_ms = if dispatched == 0 {
+            0.0
+        } else {
+            loads.iter().map(|l| l.avg_queue_wait_ms * l.dispatched as f64).sum::<f64>() / dispatched as f64
+        };
+
+        Self {
+            avg_queue_wait_ms,
+            max_queue_wait_ms: loads.iter().map(|l| l.max_queue_wait_ms).max().unwrap_or_default(),
+            avg_inflight: loads.iter().map(|l| l.inflight).sum::<usize>() as f64 / loads.len().max(1) as f64,
+            vms: loads.iter().map(|l| l.vms).sum(),
+        }
+    }
+}
+
+/// Grows or shrinks the set of workers on the hash ring based on the load of the workers
+///
+/// All worker processes are always running, workers taken off the ring simply hold no tenants (and so no VMs)
+/// until they are added back. Scaling moves tenants using a regular rebalance
+pub struct Autoscaler {
+    worker_pool: Arc<WorkerPool>,
+    config: &'static AutoscaleConfig,
+    last_scaled: Option<Instant>,
+}
+
+impl Autoscaler {
+    pub fn new(worker_pool: Arc<WorkerPool>, config: &'static AutoscaleConfig) -> Self {
+        Self { worker_pool, config, last_scaled: None }
+    }
+
+    /// Spawns the background task sampling worker load and scaling the ring
+    pub fn spawn(mut self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs.max(1)));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                self.tick().await;
+            }
+        });
+    }
+
+    /// Returns the bounds on the number of workers on the ring
+    fn bounds(&self) -> (usize, usize) {
+        let pool_size = self.worker_pool.pool_size();
+        let max = self.config.max_workers.unwrap_or(pool_size).clamp(1, pool_size.max(1));
+        (self.config.min_workers.clamp(1, max), max)
+    }
+
+    async fn tick(&mut self) {
+        let workers = self.worker_pool.mesophyll().router().ring().workers().to_vec();
+
+        let mut loads = Vec::with_capacity(workers.len());
+        for &worker_id in workers.iter() {
+            let Some(conn) = self.worker_pool.mesophyll().get_connection(worker_id) else {
+                log::warn!("Skipping autoscaling, worker {worker_id} is not connected");
+                return;
+            };
+            match conn.get_load().await {
+                Ok(load) => {
+                    log::debug!("Sampled load of worker {worker_id}: {load:?}");
+                    loads.push(load)
+                }
+                Err(e) => {
+                    log::warn!("Skipping autoscaling, failed to sample the load of worker {worker_id}: {e}");
+                    return;
+                }
+            }
+        }
+
+        let load = RingLoad::new(&loads);
+        log::debug!("Sampled load of {} workers: {load:?}", workers.len());
+
+        let Some((target, reason)) = self.decide(&workers, &load) else {
+            return;
+        };
+
+        self.scale(&workers, target, reason, &load).await;
+    }
+
+    /// Returns the workers the ring should be moved to (and why), if it should be scaled
+    fn decide(&self, workers: &[usize], load: &RingLoad) -> Option<(Vec<usize>, &'static str)> {
+        let (min, max) = self.bounds();
+        let pool_size = self.worker_pool.pool_size();
+
+        // Adds the lowest worker not on the ring
+        let grow = || {
+            let mut target = workers.to_vec();
+            target.push((0..pool_size).find(|w| !workers.contains(w))?);
+            Some(target)
+        };
+        // Removes the highest worker on the ring
+        let shrink = || Some(workers[..workers.len() - 1].to_vec());
+
+        // Bounds are enforced even while cooling down (e.g. after they were changed or a manual rebalance)
+        if workers.len() < min {
+            return grow().map(|t| (t, "below minimum workers"));
+        }
+        if workers.len() > max {
+            return shrink().map(|t| (t, "above maximum workers"));
+        }
+
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        if self.last_scaled.is_some_and(|t| t.elapsed() < cooldown) {
+            return None;
+        }
+
+        if load.avg_queue_wait_ms > self.config.scale_up_wait_ms as f64 && workers.len() < max {
+            return grow().map(|t| (t, "queue wait above threshold"));
+        }
+        if load.max_queue_wait_ms < self.config.scale_down_wait_ms
+            && load.avg_inflight < self.config.scale_down_inflight
+            && workers.len() > min
+        {
+            return shrink().map(|t| (t, "workers underutilized"));
+        }
+
+        None
+    }
+
+    async fn scale(&mut self, workers: &[usize], target: Vec<usize>, reason: &'static str, load: &RingLoad) {
+        log::info!(
+            "Autoscaling ring from {} to {} workers ({reason}): avg queue wait {:.1}ms, max queue wait {}ms, {:.2} in-flight per worker, {} VMs",
+            workers.len(), target.len(), load.avg_queue_wait_ms, load.max_queue_wait_ms, load.avg_inflight, load.vms
+        );
+
+        let cx = telemetry::child(&Context::current(), "master.autoscale", SpanKind::Internal, vec![
+            KeyValue::new("reason", reason),
+            KeyValue::new("from_workers", workers.len() as i64),
+            KeyValue::new("to_workers", target.len() as i64),
+            KeyValue::new("avg_queue_wait_ms", load.avg_queue_wait_ms),
+            KeyValue::new("max_queue_wait_ms", load.max_queue_wait_ms as i64),
+            KeyValue::new("avg_inflight", load.avg_inflight),
+            KeyValue::new("vms", load.vms as i64),
+        ]);
+
+        // Count failed attempts too so a failing rebalance is not retried every tick
+        self.last_scaled = Some(Instant::now());
+
+        let res = async {
+            let tenant_states = self.worker_pool.mesophyll().tenant_state_db().get_tenant_state().await?;
+            self.worker_pool.rebalance(&target, tenant_states).await
+        }.await;
+
+        match res {
+            Ok(result) => {
+                cx.span().set_attribute(KeyValue::new("moved", result.moved as i64));
+                log::info!("Autoscaled ring onto workers {target:?}: {} moved, {} failed", result.moved, result.failed.len());
+            }
+            Err(e) => {
+                cx.span().set_status(opentelemetry::trace::Status::error(e.to_string()));
+                log::error!("Failed to autoscale ring onto workers {target:?}: {e}");
+            }
+        }
+        cx.span().end();
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgListener, PgNotification};
+
+use crate::geese::templatecache::TEMPLATES_SCOPE;
+use crate::geese::tenantstate::TenantStateDb;
+use crate::master::workerpool::WorkerPool;
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// Channel notified (by the `change_notify` triggers) on changes to `tenant_state` and `tenant_state_events`
+const TENANT_STATE_CHANNEL: &str = "tw_tenant_state";
+
+/// Channel notified on changes to the templates of a tenant
+const TEMPLATES_CHANNEL: &str = "tw_templates";
+
+/// Channel notified on changes to feature flags
+const FEATURE_FLAGS_CHANNEL: &str = "tw_feature_flags";
+
+/// Channel notified on creations, deletions and review state changes of shop listings
+const GLOBAL_KV_CHANNEL: &str = "tw_global_kv";
+
+/// Event dispatched to the owner of a shop listing when it is reviewed (or deleted)
+pub const SHOP_LISTING_CHANGED_EVENT: &str = "ShopListingChanged";
+
+/// How often everything is reconciled against the database, catching changes whose notification was lost
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Delay before retrying after the listener fails
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Payload of tenant state and template notifications
+#[derive(Deserialize)]
+struct TenantNotification {
+    owner_type: String,
+    owner_id: String,
+}
+
+/// Data of `ShopListingChanged` events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShopListingChanged {
+    /// `insert`, `update` (review state changed) or `delete`
+    pub op: String,
+    pub key: String,
+    pub version: i32,
+    pub scope: String,
+    pub owner_type: String,
+    pub owner_id: String,
+    pub review_state: String,
+}
+
+/// Fingerprints of what workers were last told about, to find what changed without a notification
+struct Fingerprints {
+    tenant_states: HashMap<Id, String>,
+    templates: HashMap<Id, String>,
+}
+
+/// Pushes changes made to the database (by this master, another master or anything else) to the workers
+///
+/// Changes are received through Postgres LISTEN/NOTIFY. As notifications sent while the listener is
+/// disconnected are lost, everything is also reconciled against the database after reconnecting and
+/// periodically. Changes made by this master are pushed twice (once directly and once when notified), which
+/// is harmless as reloading tenant states and templates is idempotent
+pub struct ChangeListener {
+    pool: sqlx::PgPool,
+    worker_pool: Arc<WorkerPool>,
+    tsdb: TenantStateDb,
+    fingerprints: tokio::sync::Mutex<Option<Fingerprints>>,
+}
+
+impl ChangeListener {
+    pub fn new(pool: sqlx::PgPool, worker_pool: Arc<WorkerPool>) -> Self {
+        Self { tsdb: TenantStateDb::new(pool.clone()), pool, worker_pool, fingerprints: tokio::sync::Mutex::new(None) }
+    }
+
+    /// Spawns the listener and the periodic reconciliation
+    pub fn spawn(self) {
+        let this = Arc::new(self);
+
+        let listener = this.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = listener.listen().await {
+                    log::error!("Change listener failed, retrying in {RETRY_DELAY:?}: {e}");
+                }
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                this.reconcile().await;
+            }
+        });
+    }
+
+    async fn listen(&self) -> Result<(), crate::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen_all([TENANT_STATE_CHANNEL, TEMPLATES_CHANNEL, FEATURE_FLAGS_CHANNEL, GLOBAL_KV_CHANNEL]).await?;
+        log::info!("Listening for database changes");
+
+        // Changes may have been missed while (re)connecting
+        self.reconcile().await;
+
+        loop {
+            match listener.try_recv().await? {
+                Some(notification) => {
+                    if let Err(e) = self.handle(&notification).await {
+                        log::error!("Failed to handle {} notification: {e}", notification.channel());
+                    }
+                }
+                None => {
+                    // The connection was lost (and is reestablished on the next call), notifications sent in the
+                    // meantime are lost
+                    log::warn!("Change listener lost its connection, reconciling");
+                    self.reconcile().await;
+                }
+            }
+        }
+    }
+
+    async fn handle(&self, notification: &PgNotification) -> Result<(), crate::Error> {
+        match notification.channel() {
+            TENANT_STATE_CHANNEL => {
+                let Some(id) = Self::tenant(notification.payload())? else {
+                    return Ok(());
+                };
+                self.push_tenant_state(id).await
+            }
+            TEMPLATES_CHANNEL => {
+                let Some(id) = Self::tenant(notification.payload())? else {
+                    return Ok(());
+                };
+                self.worker_pool.mesophyll().broadcast_template_invalidation(id).await;
+                Ok(())
+            }
+            FEATURE_FLAGS_CHANNEL => self.worker_pool.mesophyll().broadcast_feature_flags().await,
+            GLOBAL_KV_CHANNEL => {
+                let change: ShopListingChanged = serde_json::from_str(notification.payload())?;
+                let Some(id) = Id::from_parts(&change.owner_type, &change.owner_id) else {
+                    return Ok(());
+                };
+                let event = SimpleEvent::new_json_string(SHOP_LISTING_CHANGED_EVENT.to_string(), None, serde_json::to_string(&change)?);
+
+                // Don't hold up other notifications while the event runs
+                let worker_pool = self.worker_pool.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = worker_pool.dispatch_event(id, event).await {
+                        log::warn!("Failed to dispatch {SHOP_LISTING_CHANGED_EVENT} to {id:?}: {e}");
+                    }
+                });
+                Ok(())
+            }
+            channel => Err(format!("Unknown channel {channel}").into()),
+        }
+    }
+
+    fn tenant(payload: &str) -> Result<Option<Id>, crate::Error> {
+        let n: TenantNotification = serde_json::from_str(payload)?;
+        Ok(Id::from_parts(&n.owner_type, &n.owner_id))
+    }
+
+    /// Pushes the current tenant state of a tenant (the default one if it was deleted) to its worker
+    async fn push_tenant_state(&self, id: Id) -> Result<(), crate::Error> {
+        let mut tx = self.pool.begin().await?;
+        let ts = self.tsdb.get_tenant_state_for(&mut tx, id).await?.unwrap_or_default();
+        tx.commit().await?;
+        self.worker_pool.update_tenant_state(id, ts).await?;
+        Ok(())
+    }
+
+    /// Pushes tenant states and invalidates templates changed since the last reconciliation
+    ///
+    /// The first reconciliation only records the current fingerprints, as workers load everything on startup
+    async fn reconcile(&self) {
+        let mut last = self.fingerprints.lock().await;
+        let current = match self.fetch_fingerprints().await {
+            Ok(current) => current,
+            Err(e) => {
+                log::error!("Failed to fetch fingerprints for reconciliation: {e}");
+                return;
+            }
+        };
+
+        if let Some(last) = last.as_ref() {
+            for id in changed(&last.tenant_states, &current.tenant_states) {
+                if let Err(e) = self.push_tenant_state(id).await {
+                    log::error!("Failed to reconcile tenant state of {id:?}: {e}");
+                }
+            }
+            for id in changed(&last.templates, &current.templates) {
+                self.worker_pool.mesophyll().broadcast_template_invalidation(id).await;
+            }
+            if let Err(e) = self.worker_pool.mesophyll().broadcast_feature_flags().await {
+                log::error!("Failed to reconcile feature flags: {e}");
+            }
+        }
+
+        *last = Some(current);
+    }
+
+    async fn fetch_fingerprints(&self) -> Result<Fingerprints, crate::Error> {
+        let tenant_states: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT ts.owner_type, ts.owner_id, md5(ts::text || COALESCE((
+                SELECT string_agg(e.event || ':' || e.system, ',' ORDER BY e.event, e.system)
+                FROM tenant_state_events e WHERE e.owner_id = ts.owner_id AND e.owner_type = ts.owner_type
+            ), '')) FROM tenant_state ts"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let templates: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT owner_type, owner_id, md5(string_agg(key || ':' || last_updated_at::text, ',' ORDER BY key))
+            FROM tenant_kv WHERE scope = $1 GROUP BY owner_type, owner_id"
+        )
+        .bind(TEMPLATES_SCOPE)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let into_map = |rows: Vec<(String, String, String)>| {
+            rows.into_iter()
+                .filter_map(|(owner_type, owner_id, fp)| Some((Id::from_parts(&owner_type, &owner_id)?, fp)))
+                .collect::<HashMap<_, _>>()
+        };
+
+        Ok(Fingerprints { tenant_states: into_map(tenant_states), templates: into_map(templates) })
+    }
+}
+
+/// Returns the tenants added, removed or changed between two sets of fingerprints
+fn changed(last: &HashMap<Id, String>, current: &HashMap<Id, String>) -> Vec<Id> {
+    let mut ids = current.iter()
+        .filter(|(id, fp)| last.get(id) != Some(fp))
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    ids.extend(last.keys().filter(|id| !current.contains_key(id)));
+    ids
+}
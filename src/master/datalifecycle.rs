@@ -0,0 +1,131 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::geese::datalifecycle::{DELETION_STEPS, DataJob, DataJobKind, DataJobState, DataLifecycleDb, REASON_BOT_REMOVED};
+use crate::geese::stratum::Stratum;
+use crate::geese::tenantstate::TenantState;
+use crate::master::archival;
+use crate::master::workerpool::WorkerPool;
+use crate::worker::workervmmanager::Id;
+
+/// How often due jobs are checked for
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a failed job waits before being retried
+const RETRY_DELAY: Duration = Duration::from_secs(15 * 60);
+
+/// Runs deletions and exports of tenant data once they are due
+///
+/// Jobs are tracked in the database, so jobs interrupted by a restart are resumed on the next poll
+pub struct DataLifecycle {
+    db: DataLifecycleDb,
+    worker_pool: Arc<WorkerPool>,
+    stratum: Stratum,
+}
+
+impl DataLifecycle {
+    pub fn new(worker_pool: Arc<WorkerPool>, stratum: Stratum) -> Self {
+        Self {
+            db: worker_pool.mesophyll().data_lifecycle_db().clone(),
+            worker_pool,
+            stratum,
+        }
+    }
+
+    /// Spawns the background task running due jobs
+    pub fn spawn(self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                self.tick().await;
+            }
+        });
+    }
+
+    async fn tick(&self) {
+        match self.db.delete_expired_exports().await {
+            Ok(0) => {}
+            Ok(n) => log::info!("Deleted {n} expired data exports"),
+            Err(e) => log::error!("Failed to delete expired data exports: {e}"),
+        }
+
+        match self.db.delete_expired_recordings().await {
+            Ok(0) => {}
+            Ok(n) => log::info!("Deleted {n} expired execution recordings"),
+            Err(e) => log::error!("Failed to delete expired execution recordings: {e}"),
+        }
+
+        let jobs = match self.db.due_jobs().await {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                log::error!("Failed to fetch due data jobs: {e}");
+                return;
+            }
+        };
+
+        for job in jobs {
+            let res = match job.kind {
+                DataJobKind::Deletion => self.run_deletion(&job).await,
+                DataJobKind::Export => self.run_export(&job).await,
+            };
+
+            if let Err(e) = res {
+                log::error!("Data job {} ({}) of {:?} failed, retrying later: {e}", job.job_id, job.kind.as_str(), job.id);
+                if let Err(e) = self.db.retry_later(job.job_id, &e.to_string(), RETRY_DELAY).await {
+                    log::error!("Failed to reschedule data job {}: {e}", job.job_id);
+                }
+                let action = format!("{}_failed", job.kind.as_str());
+                if let Err(e) = self.db.record_audit(Some(job.job_id), job.id, &action, None, Some(e.to_string())).await {
+                    log::error!("Failed to record audit entry for data job {}: {e}", job.job_id);
+                }
+            }
+        }
+    }
+
+    async fn run_deletion(&self, job: &DataJob) -> Result<(), crate::Error> {
+        // The bot may have been re-added during the grace period
+        if job.reason == REASON_BOT_REMOVED && job.state == DataJobState::Pending && let Id::Guild(guild_id) = job.id
+            && self.stratum.has_guild(guild_id).await? {
+            self.db.cancel_deletion(job.id, None, Some("bot was re-added".to_string())).await?;
+            log::info!("Cancelled deletion of {:?} as the bot was re-added", job.id);
+            return Ok(());
+        }
+
+        if job.state == DataJobState::Pending {
+            self.db.set_state(job.job_id, DataJobState::Running).await?;
+            self.db.record_audit(Some(job.job_id), job.id, "deletion_started", None, None).await?;
+        }
+
+        // The archive in cold storage goes before its record in the database, so a failed removal is retried
+        self.worker_pool.mesophyll().archive_db().delete_archive(job.id).await?;
+
+        for (step, stmt) in DELETION_STEPS {
+            if job.steps_done.iter().any(|s| s == step) {
+                continue;
+            }
+            let rows = self.db.run_deletion_step(job, step, stmt).await?;
+            self.db.record_audit(Some(job.job_id), job.id, "deletion_step", None, Some(format!("{step}: {rows} rows deleted"))).await?;
+        }
+
+        // Drop the now stale state of the tenant from its worker
+        self.worker_pool.update_tenant_state(job.id, TenantState::default()).await?;
+        self.worker_pool.drop_tenant(job.id).await?;
+
+        self.db.set_state(job.job_id, DataJobState::Completed).await?;
+        self.db.record_audit(Some(job.job_id), job.id, "deletion_completed", None, None).await?;
+        log::info!("Deleted the data of {:?}", job.id);
+        Ok(())
+    }
+
+    async fn run_export(&self, job: &DataJob) -> Result<(), crate::Error> {
+        self.db.set_state(job.job_id, DataJobState::Running).await?;
+        // Archived data is not in the database, so bring it back before exporting
+        archival::restore(&self.worker_pool, job.id, None).await?;
+        self.db.run_export(job).await?;
+        self.db.set_state(job.job_id, DataJobState::Completed).await?;
+        self.db.record_audit(Some(job.job_id), job.id, "export_completed", None, None).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::CONFIG;
+
+/// The Discord epoch (first second of 2015) in milliseconds
+const DISCORD_EPOCH: u64 = 1420070400000;
+
+/// Prefix of the Discord API paths served
+const API_PREFIX: &str = "/api/v10";
+
+/// Maximum number of requests kept for inspection, older requests are dropped first
+const MAX_RECORDED_REQUESTS: usize = 1000;
+
+/// A request made to the fake Discord API
+#[derive(Clone, Serialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path without the API prefix, such as `/channels/123/messages`
+    pub path: String,
+    pub query: Option<String>,
+    /// Decoded `X-Audit-Log-Reason` header
+    pub reason: Option<String>,
+    pub body: Option<Value>,
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    /// Objects by their API path, such as `/channels/123/messages/456`
+    objects: HashMap<String, Value>,
+    requests: VecDeque<RecordedRequest>,
+    next_id: u64,
+}
+
+impl FakeState {
+    fn next_id(&mut self) -> String {
+        let now = (chrono::Utc::now().timestamp_millis().max(0) as u64).saturating_sub(DISCORD_EPOCH) << 22;
+        self.next_id = self.next_id.max(now) + 1;
+        self.next_id.to_string()
+    }
+}
+
+/// An in-process fake of the Discord HTTP API for end-to-end tests of dispatch → VM → Discord plugin, enabled by
+/// the `fake-discord` feature and the `fake_discord` config
+///
+/// The proxy of the config is pointed at the fake (see `Config::load`) so the master and workers make all their
+/// Discord calls to it. Objects are kept in memory by path: POST creates an object with a new ID under the path,
+/// PUT stores the body at the path, PATCH merges the body into the stored object, GET returns it and DELETE removes
+/// it. Objects not created through the API (guilds, members etc.) must be seeded first
+///
+/// Tests inspect and reset the fake through:
+/// - `GET /_fake/requests` returns the requests made, oldest first
+/// - `DELETE /_fake/requests` clears the requests made
+/// - `PUT /_fake/objects/{path}` seeds the object at an API path (without the `/api/v10` prefix)
+/// - `DELETE /_fake/objects` removes all objects
+#[derive(Clone, Default)]
+pub struct FakeDiscord {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeDiscord {
+    /// Serves the fake on `bind` in the background
+    pub async fn spawn(self, bind: &str) -> Result<(), crate::Error> {
+        let listener = tokio::net::TcpListener::bind(bind).await?;
+        log::info!("Serving fake Discord API on {bind}");
+
+        let router = Router::new()
+            .route("/_fake/requests", get(Self::requests).delete(Self::clear_requests))
+            .route("/_fake/objects", axum::routing::delete(Self::clear_objects))
+            .route("/_fake/objects/{*path}", put(Self::seed))
+            .fallback(Self::api)
+            .with_state(self);
+
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                log::error!("Fake Discord API stopped: {e}");
+            }
+        });
+        Ok(())
+    }
+
+    async fn requests(State(fake): State<Self>) -> Json<Vec<RecordedRequest>> {
+        Json(fake.state.lock().requests.iter().cloned().collect())
+    }
+
+    async fn clear_requests(State(fake): State<Self>) -> StatusCode {
+        fake.state.lock().requests.clear();
+        StatusCode::NO_CONTENT
+    }
+
+    async fn clear_objects(State(fake): State<Self>) -> StatusCode {
+        fake.state.lock().objects.clear();
+        StatusCode::NO_CONTENT
+    }
+
+    async fn seed(State(fake): State<Self>, Path(path): Path<String>, Json(object): Json<Value>) -> StatusCode {
+        fake.state.lock().objects.insert(format!("/{}", path.trim_matches('/')), object);
+        StatusCode::NO_CONTENT
+    }
+
+    async fn api(State(fake): State<Self>, method: Method, uri: Uri, headers: HeaderMap, body: axum::body::Bytes) -> Response {
+        let Some(path) = uri.path().strip_prefix(API_PREFIX) else {
+            return not_found();
+        };
+        let path = path.trim_end_matches('/').to_string();
+        let body: Option<Value> = serde_json::from_slice(&body).ok();
+        let reason = headers.get("X-Audit-Log-Reason")
+            .and_then(|r| r.to_str().ok())
+            .map(percent_decode);
+
+        let mut state = fake.state.lock();
+        if state.requests.len() >= MAX_RECORDED_REQUESTS {
+            state.requests.pop_front();
+        }
+        state.requests.push_back(RecordedRequest {
+            method: method.to_string(),
+            path: path.clone(),
+            query: uri.query().map(|q| q.to_string()),
+            reason,
+            body: body.clone(),
+            at: chrono::Utc::now(),
+        });
+
+        match method.as_str() {
+            "GET" => match state.objects.get(&path) {
+                Some(object) => Json(object.clone()).into_response(),
+                None => not_found(),
+            },
+            "POST" if path.ends_with("/callback") => {
+                // The message of a response is the original response of the interaction
+                if let Some(token) = path.split('/').nth(3)
+                    && let Some(Value::Object(mut message)) = body.and_then(|mut b| b.get_mut("data").map(Value::take))
+                {
+                    let original = format!("/webhooks/{}/{token}/messages/@original", CONFIG.client_id);
+                    message.insert("id".to_string(), state.next_id().into());
+                    message_defaults(&original, &mut message);
+                    state.objects.insert(original, Value::Object(message));
+                }
+                StatusCode::NO_CONTENT.into_response()
+            }
+            "POST" if path.ends_with("/bulk-delete") => {
+                if let Some(ids) = body.as_ref().and_then(|b| b["messages"].as_array()) {
+                    let channel = path.trim_end_matches("/bulk-delete");
+                    for id in ids.iter().filter_map(|id| id.as_str()) {
+                        state.objects.remove(&format!("{channel}/{id}"));
+                    }
+                }
+                StatusCode::NO_CONTENT.into_response()
+            }
+            "POST" => {
+                let mut object = match body {
+                    Some(Value::Object(fields)) => fields,
+                    _ => Map::new(),
+                };
+                let id = state.next_id();
+                object.insert("id".to_string(), id.clone().into());
+
+                // Executing a webhook creates a message of it
+                let collection = if path.starts_with("/webhooks/") && !path.ends_with("/messages") {
+                    format!("{path}/messages")
+                } else {
+                    path
+                };
+                if collection.ends_with("/messages") {
+                    message_defaults(&collection, &mut object);
+                }
+
+                let object = Value::Object(object);
+                state.objects.insert(format!("{collection}/{id}"), object.clone());
+                Json(object).into_response()
+            }
+            "PUT" => {
+                state.objects.insert(path, body.unwrap_or_else(|| json!({})));
+                StatusCode::NO_CONTENT.into_response()
+            }
+            "PATCH" => {
+                let Some(Value::Object(existing)) = state.objects.get_mut(&path) else {
+                    return not_found();
+                };
+                if let Some(Value::Object(fields)) = body {
+                    existing.extend(fields);
+                }
+                Json(Value::Object(existing.clone())).into_response()
+            }
+            "DELETE" => {
+                state.objects.remove(&path);
+                StatusCode::NO_CONTENT.into_response()
+            }
+            _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+        }
+    }
+}
+
+/// Fills in the fields Discord always returns for messages, as sent by the bot
+fn message_defaults(path: &str, message: &mut Map<String, Value>) {
+    let channel_id = path.strip_prefix("/channels/").and_then(|p| p.split('/').next());
+    let defaults = json!({
+        "channel_id": channel_id,
+        "author": { "id": CONFIG.client_id.to_string(), "username": "fake-bot", "discriminator": "0", "avatar": null, "bot": true },
+        "content": "",
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": 0,
+    });
+    if let Value::Object(defaults) = defaults {
+        for (field, value) in defaults {
+            message.entry(field).or_insert(value);
+        }
+    }
+}
+
+fn not_found() -> Response {
+    (StatusCode::NOT_FOUND, Json(json!({ "message": "404: Not Found", "code": 0 }))).into_response()
+}
+
+/// Decodes a percent-encoded audit log reason
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = bytes.get(i + 1..i + 3)
+            && let Ok(b) = u8::from_str_radix(&String::from_utf8_lossy(hex), 16)
+        {
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
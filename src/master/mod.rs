@@ -2,4 +2,18 @@ pub mod workerprocesshandle;
 pub mod workerpool;
 pub mod syscall;
 pub mod mainthread;
-pub mod register;
\ No newline at end of file
+pub mod register;
+pub mod datalifecycle;
+pub mod autoscaler;
+pub mod outbox;
+pub mod modimport;
+pub mod changelistener;
+pub mod workflows;
+pub mod watchlist;
+pub mod permsnapshots;
+pub mod schedules;
+pub mod threadpolicies;
+pub mod activity;
+pub mod archival;
+#[cfg(feature = "fake-discord")]
+pub mod fakediscord;
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dapi::UserId;
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+
+use crate::geese::modimport::{ImportFormat, ImportParse, ImportedSting};
+use crate::master::workerpool::WorkerPool;
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// Internal event importing a batch of stings, only dispatched to the builtins
+pub const IMPORT_STINGS_EVENT: &str = "$ImportStings";
+
+/// Number of stings imported per dispatch
+const BATCH_SIZE: usize = 100;
+
+/// How many times a batch is dispatched before the import fails
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before retrying a failed batch (e.g. while the tenant is being moved to another worker)
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportJobState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Progress of the sting import of a tenant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportJob {
+    pub job_id: uuid::Uuid,
+    pub format: ImportFormat,
+    pub state: ImportJobState,
+    /// Number of stings to import
+    pub total: usize,
+    /// Number of stings dispatched to the builtins so far, stings already imported by an earlier run are
+    /// counted but left untouched
+    pub processed: usize,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// A sting as sent to the builtins in `$ImportStings`
+#[derive(Serialize)]
+struct ImportStingData<'a> {
+    stingid: &'a str,
+    source: &'a str,
+    userid: String,
+    modid: Option<String>,
+    stings: u32,
+    reason: &'a str,
+    /// Unix timestamp of the original case
+    created_at: i64,
+    /// Unix timestamp
+    expires_at: i64,
+}
+
+impl<'a> From<&'a ImportedSting> for ImportStingData<'a> {
+    fn from(s: &'a ImportedSting) -> Self {
+        Self {
+            stingid: &s.source,
+            source: &s.source,
+            userid: s.userid.to_string(),
+            modid: s.modid.map(|m| m.to_string()),
+            stings: s.stings,
+            reason: &s.reason,
+            created_at: s.created_at.timestamp(),
+            expires_at: s.expires_at.timestamp(),
+        }
+    }
+}
+
+/// Imports the stings of a parsed export into a tenant in the background, one import per tenant at a time
+///
+/// Stings are keyed by their provenance tag, so an import that failed (or was lost to a master restart, as
+/// jobs are only kept in memory) can simply be started again without duplicating the stings already imported
+#[derive(Clone)]
+pub struct ModImporter {
+    worker_pool: Arc<WorkerPool>,
+    jobs: Arc<DashMap<Id, ImportJob>>,
+}
+
+impl ModImporter {
+    pub fn new(worker_pool: Arc<WorkerPool>) -> Self {
+        Self { worker_pool, jobs: DashMap::new().into() }
+    }
+
+    /// Returns the most recent import of a tenant
+    pub fn job(&self, id: Id) -> Option<ImportJob> {
+        self.jobs.get(&id).map(|j| j.clone())
+    }
+
+    /// Starts importing the stings of a parsed export into a tenant
+    pub fn start(&self, id: Id, author: Option<UserId>, parse: ImportParse) -> Result<ImportJob, crate::Error> {
+        let job = ImportJob {
+            job_id: uuid::Uuid::now_v7(),
+            format: parse.format,
+            state: ImportJobState::Running,
+            total: parse.stings.len(),
+            processed: 0,
+            error: None,
+            started_at: Utc::now(),
+            finished_at: None,
+        };
+
+        match self.jobs.entry(id) {
+            Entry::Occupied(e) if e.get().state == ImportJobState::Running => {
+                return Err("An import is already running for this tenant".into());
+            }
+            Entry::Occupied(mut e) => { e.insert(job.clone()); }
+            Entry::Vacant(e) => { e.insert(job.clone()); }
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            let res = this.run(id, author, &parse.stings).await;
+            if let Some(mut job) = this.jobs.get_mut(&id) {
+                job.finished_at = Some(Utc::now());
+                match res {
+                    Ok(()) => {
+                        log::info!("Imported {} stings from {} into {id:?}", job.total, job.format.name());
+                        job.state = ImportJobState::Completed;
+                    }
+                    Err(e) => {
+                        log::error!("Failed to import stings from {} into {id:?}: {e}", job.format.name());
+                        job.state = ImportJobState::Failed;
+                        job.error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+
+        Ok(job)
+    }
+
+    async fn run(&self, id: Id, author: Option<UserId>, stings: &[ImportedSting]) -> Result<(), crate::Error> {
+        for batch in stings.chunks(BATCH_SIZE) {
+            let data = serde_json::to_string(&serde_json::json!({
+                "stings": batch.iter().map(ImportStingData::from).collect::<Vec<_>>(),
+            }))?;
+
+            let mut attempt = 1;
+            loop {
+                let event = SimpleEvent::new_json_string(IMPORT_STINGS_EVENT.to_string(), author, data.clone());
+                match self.worker_pool.dispatch_event(id, event).await {
+                    Ok(_) => break,
+                    Err(e) if attempt < MAX_ATTEMPTS => {
+                        log::warn!("Failed to import batch of stings into {id:?} (attempt {attempt}), retrying: {e}");
+                        attempt += 1;
+                        tokio::time::sleep(RETRY_DELAY).await;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if let Some(mut job) = self.jobs.get_mut(&id) {
+                job.processed += batch.len();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+use dapi::context::DiscordContext;
+use opentelemetry::{Context, KeyValue, trace::{FutureExt, SpanKind, TraceContextExt}};
+
+use crate::geese::outbox::{OutboxDb, OutboxEntry};
+use crate::geese::stratum::Stratum;
+use crate::geese::telemetry;
+use crate::worker::syscall::discord::ArDiscordProvider;
+
+/// How often due actions are checked for
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many actions are claimed per poll
+const BATCH_SIZE: i64 = 32;
+
+/// How long a claimed action is leased for before another sender may retry it
+const LEASE: Duration = Duration::from_secs(5 * 60);
+
+/// How many times an action is attempted before it is given up on
+const MAX_ATTEMPTS: i32 = 6;
+
+/// Delay before the first retry of a failed action, doubled on every further attempt
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long sent and failed actions (and so their idempotency keys) are kept
+const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How often finished actions past their retention are deleted
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Sends the Discord actions enqueued into the outbox by templates
+///
+/// An action is enqueued at most once per idempotency key and is only marked sent once Discord has accepted
+/// it. If the master dies while sending, the action is retried once its lease expires, so actions are
+/// delivered at least once and should be idempotent on Discord's side (bans, kicks, role and member updates)
+pub struct OutboxSender {
+    db: OutboxDb,
+    stratum: Stratum,
+}
+
+impl OutboxSender {
+    pub fn new(db: OutboxDb, stratum: Stratum) -> Self {
+        Self { db, stratum }
+    }
+
+    /// Spawns the background task sending due actions
+    pub fn spawn(self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut last_cleanup: Option<Instant> = None;
+            loop {
+                interval.tick().await;
+                self.tick().await;
+
+                if last_cleanup.is_none_or(|t| t.elapsed() >= CLEANUP_INTERVAL) {
+                    last_cleanup = Some(Instant::now());
+                    match self.db.delete_finished(RETENTION).await {
+                        Ok(0) => {}
+                        Ok(n) => log::info!("Deleted {n} finished outbox actions"),
+                        Err(e) => log::error!("Failed to delete finished outbox actions: {e}"),
+                    }
+                }
+            }
+        });
+    }
+
+    async fn tick(&self) {
+        let entries = match self.db.claim_due(BATCH_SIZE, LEASE).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("Failed to claim due outbox actions: {e}");
+                return;
+            }
+        };
+
+        for entry in entries {
+            let res = match self.send(&entry).await {
+                Ok(result) => self.db.mark_sent(entry.id, &result).await,
+                Err(e) => {
+                    let delay = (entry.attempts < MAX_ATTEMPTS).then(|| RETRY_DELAY * 2u32.pow((entry.attempts - 1).max(0) as u32));
+                    match delay {
+                        Some(delay) => log::warn!("Outbox action {} ({}) of {:?} failed, retrying in {delay:?}: {e}", entry.id, entry.key, entry.tenant),
+                        None => log::error!("Outbox action {} ({}) of {:?} failed {} times, giving up: {e}", entry.id, entry.key, entry.tenant, entry.attempts),
+                    }
+                    self.db.mark_failed(entry.id, &e.to_string(), delay).await
+                }
+            };
+
+            if let Err(e) = res {
+                log::error!("Failed to record result of outbox action {}: {e}", entry.id);
+            }
+        }
+    }
+
+    /// Makes the Discord call of an action, returning the response of Discord
+    async fn send(&self, entry: &OutboxEntry) -> Result<serde_json::Value, crate::Error> {
+        let op: dapi::apilist::API = serde_json::from_value(entry.action.clone())?;
+        let op_name = op.api_name();
+
+        let cx = telemetry::child(&Context::current(), "discord.outbox", SpanKind::Client, vec![
+            KeyValue::new("discord.op", op_name),
+            KeyValue::new("tenant", entry.tenant.tenant_id()),
+            KeyValue::new("attempt", entry.attempts as i64),
+        ]);
+        let dp = DiscordContext::new(ArDiscordProvider { id: entry.tenant, stratum: self.stratum.clone() });
+        let res = op.execute(&dp).with_context(cx.clone()).await;
+        if let Err(ref e) = res {
+            cx.span().set_status(opentelemetry::trace::Status::error(e.to_string()));
+        }
+        cx.span().end();
+
+        let (value, _) = res?;
+        Ok(value)
+    }
+}
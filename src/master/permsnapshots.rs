@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::geese::permsnapshots::{PermissionSnapshotDb, PermissionSnapshotTrigger};
+use crate::master::workerpool::WorkerPool;
+use crate::worker::permsnapshots::PERMISSION_SNAPSHOT_EVENT;
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// How often due snapshots are checked for
+const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How many snapshots are claimed per poll
+const BATCH_SIZE: i64 = 64;
+
+/// How often the permissions of a guild are snapshotted
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Has the workers of guilds with permission snapshots snapshot their permissions every `SNAPSHOT_INTERVAL`
+///
+/// Snapshots are claimed before being taken, a snapshot which fails is skipped until the next interval
+pub struct PermissionSnapshotter {
+    db: PermissionSnapshotDb,
+    worker_pool: Arc<WorkerPool>,
+}
+
+impl PermissionSnapshotter {
+    pub fn new(db: PermissionSnapshotDb, worker_pool: Arc<WorkerPool>) -> Self {
+        Self { db, worker_pool }
+    }
+
+    /// Spawns the background task taking due snapshots
+    pub fn spawn(self) {
+        let this = Arc::new(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                this.tick().await;
+            }
+        });
+    }
+
+    async fn tick(self: &Arc<Self>) {
+        let due = match self.db.claim_due(BATCH_SIZE, SNAPSHOT_INTERVAL).await {
+            Ok(due) => due,
+            Err(e) => {
+                log::error!("Failed to claim due permission snapshots: {e}");
+                return;
+            }
+        };
+
+        for guild_id in due {
+            let this = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = this.dispatch(Id::Guild(guild_id)).await {
+                    log::error!("Failed to snapshot permissions of guild {guild_id}: {e}");
+                }
+            });
+        }
+    }
+
+    async fn dispatch(&self, tenant: Id) -> Result<(), crate::Error> {
+        let req = PermissionSnapshotTrigger { manual: false };
+        let event = SimpleEvent::new_json_string(PERMISSION_SNAPSHOT_EVENT.to_string(), None, serde_json::to_string(&req)?);
+        self.worker_pool.dispatch_event(tenant, event).await?;
+        Ok(())
+    }
+}
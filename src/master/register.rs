@@ -1,9 +1,15 @@
 use crate::master::mainthread::{run_in_thread, RunInThreadFn};
 use crate::worker::builtins::BUILTINS;
+use crate::CONFIG;
+use base64::{Engine, engine::general_purpose::STANDARD};
 use dapi::types::CreateCommand;
 use khronos_runtime::rt::mlua::prelude::*;
 use khronos_runtime::rt::KhronosRuntime;
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, LazyLock};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -36,3 +42,191 @@ fn register() -> Result<RegisterResult, crate::Error> {
         ()
     ))
 }
+
+const BANNER_ASSET: &str = "banner";
+const EMOJI_ASSET_PREFIX: &str = "emoji:";
+const MAX_DESCRIPTION_LENGTH: usize = 400;
+
+/// What a branding sync changed (or would change on a dry run)
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BrandingSyncResult {
+    pub description_updated: bool,
+    pub banner_updated: bool,
+    pub emojis_created: Vec<String>,
+    pub emojis_updated: Vec<String>,
+    pub emojis_deleted: Vec<String>,
+}
+
+/// An image loaded from the branding config
+struct BrandingAsset {
+    data_uri: String,
+    digest: String,
+}
+
+impl BrandingAsset {
+    fn load(path: &Path) -> Result<Self, crate::Error> {
+        let mime = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            _ => return Err(format!("Unsupported image type for {}", path.display()).into()),
+        };
+        let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        Ok(Self {
+            data_uri: format!("data:{mime};base64,{}", STANDARD.encode(&data)),
+            digest: hex::encode(Sha256::digest(&data)),
+        })
+    }
+}
+
+/// Syncs the bot's profile and application emojis with the branding config
+///
+/// Images are diffed against the digests recorded in `bot_branding` on the last sync (as Discord re-encodes
+/// uploads) and the description against the current application, so only what changed is sent to Discord
+pub async fn sync_branding(reqwest: &reqwest::Client, pool: &sqlx::PgPool, dry_run: bool) -> Result<BrandingSyncResult, crate::Error> {
+    let Some(branding) = CONFIG.branding.as_ref() else {
+        return Err("Branding is not configured".into());
+    };
+
+    // Validate everything up front so a bad config doesn't leave a half-applied sync behind
+    if let Some(description) = &branding.description && description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        return Err(format!("Application description must be at most {MAX_DESCRIPTION_LENGTH} characters").into());
+    }
+    let banner = branding.banner.as_deref().map(BrandingAsset::load).transpose()?;
+    let mut emojis = Vec::with_capacity(branding.emojis.len());
+    for (name, path) in &branding.emojis {
+        if name.len() < 2 || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid emoji name {name}: must be 2-32 alphanumeric characters or underscores").into());
+        }
+        emojis.push((name, BrandingAsset::load(path)?));
+    }
+
+    let synced: HashMap<String, String> = sqlx::query_as::<_, (String, String)>("SELECT asset, digest FROM bot_branding")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut result = BrandingSyncResult::default();
+
+    if let Some(description) = &branding.description {
+        let app: Value = discord_rest(reqwest, reqwest::Method::GET, "/applications/@me")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if app.get("description").and_then(Value::as_str) != Some(description.as_str()) {
+            result.description_updated = true;
+            if !dry_run {
+                discord_rest(reqwest, reqwest::Method::PATCH, "/applications/@me")
+                    .json(&json!({ "description": description }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+    }
+
+    if let Some(banner) = banner && synced.get(BANNER_ASSET) != Some(&banner.digest) {
+        result.banner_updated = true;
+        if !dry_run {
+            discord_rest(reqwest, reqwest::Method::PATCH, "/users/@me")
+                .json(&json!({ "banner": banner.data_uri }))
+                .send()
+                .await?
+                .error_for_status()?;
+            record_asset(pool, BANNER_ASSET, &banner.digest, None).await?;
+        }
+    }
+
+    let emojis_path = format!("/applications/{}/emojis", CONFIG.client_id);
+    let current: Value = discord_rest(reqwest, reqwest::Method::GET, &emojis_path)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let current: HashMap<&str, &str> = current.get("items")
+        .and_then(Value::as_array)
+        .map(|items| items.iter().filter_map(|e| Some((e.get("name")?.as_str()?, e.get("id")?.as_str()?))).collect())
+        .unwrap_or_default();
+
+    for (name, asset) in emojis {
+        let key = format!("{EMOJI_ASSET_PREFIX}{name}");
+        let existing = current.get(name.as_str()).copied();
+        if existing.is_some() && synced.get(&key) == Some(&asset.digest) {
+            continue;
+        }
+
+        match existing {
+            Some(_) => result.emojis_updated.push(name.clone()),
+            None => result.emojis_created.push(name.clone()),
+        }
+        if dry_run {
+            continue;
+        }
+
+        // The image of an emoji can't be edited, so changed emojis are recreated
+        if let Some(id) = existing {
+            discord_rest(reqwest, reqwest::Method::DELETE, &format!("{emojis_path}/{id}"))
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+
+        let created: Value = discord_rest(reqwest, reqwest::Method::POST, &emojis_path)
+            .json(&json!({ "name": name, "image": asset.data_uri }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        record_asset(pool, &key, &asset.digest, created.get("id").and_then(Value::as_str)).await?;
+    }
+
+    // Only emojis synced from the config before are deleted, emojis added through the Discord UI are left alone
+    for key in synced.keys() {
+        let Some(name) = key.strip_prefix(EMOJI_ASSET_PREFIX) else {
+            continue;
+        };
+        if branding.emojis.contains_key(name) {
+            continue;
+        }
+
+        result.emojis_deleted.push(name.to_string());
+        if dry_run {
+            continue;
+        }
+
+        if let Some(id) = current.get(name) {
+            discord_rest(reqwest, reqwest::Method::DELETE, &format!("{emojis_path}/{id}"))
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        sqlx::query("DELETE FROM bot_branding WHERE asset = $1")
+            .bind(key)
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(result)
+}
+
+async fn record_asset(pool: &sqlx::PgPool, asset: &str, digest: &str, discord_id: Option<&str>) -> Result<(), crate::Error> {
+    sqlx::query("INSERT INTO bot_branding (asset, digest, discord_id, synced_at) VALUES ($1, $2, $3, NOW()) ON CONFLICT (asset) DO UPDATE SET digest = EXCLUDED.digest, discord_id = EXCLUDED.discord_id, synced_at = EXCLUDED.synced_at")
+        .bind(asset)
+        .bind(digest)
+        .bind(discord_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+fn discord_rest(reqwest: &reqwest::Client, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    reqwest.request(method, format!("{}/api/v10{path}", CONFIG.proxy))
+        .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::geese::schedules::{ScheduleDb, ScheduledMessage, ScheduledMessageRun};
+use crate::master::workerpool::WorkerPool;
+use crate::worker::schedules::SCHEDULED_MESSAGE_EVENT;
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// How often due messages are checked for
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How many messages are claimed (and resumed schedules rescheduled) per poll
+const BATCH_SIZE: i64 = 64;
+
+/// How long a claimed message is leased for before it is posted again
+const LEASE: Duration = Duration::from_secs(5 * 60);
+
+/// How many times a run is attempted before its schedule is paused
+const MAX_ATTEMPTS: i32 = 5;
+
+/// Delay before the first retry of a failed run, doubled on every further attempt
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Posts the due scheduled messages of guilds by dispatching `$ScheduledMessage` to their worker
+///
+/// Failed runs are retried with backoff, after `MAX_ATTEMPTS` the schedule is paused with the last error so the
+/// guild can fix it (e.g. a deleted channel) and resume it. Runs missed while the master was down are posted once
+pub struct ScheduledMessenger {
+    db: ScheduleDb,
+    worker_pool: Arc<WorkerPool>,
+}
+
+impl ScheduledMessenger {
+    pub fn new(db: ScheduleDb, worker_pool: Arc<WorkerPool>) -> Self {
+        Self { db, worker_pool }
+    }
+
+    /// Spawns the background task posting due messages
+    pub fn spawn(self) {
+        let this = Arc::new(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                this.tick().await;
+            }
+        });
+    }
+
+    async fn tick(self: &Arc<Self>) {
+        if let Err(e) = self.db.reschedule(BATCH_SIZE).await {
+            log::error!("Failed to reschedule resumed scheduled messages: {e}");
+        }
+
+        let due = match self.db.claim_due(BATCH_SIZE, LEASE).await {
+            Ok(due) => due,
+            Err(e) => {
+                log::error!("Failed to claim due scheduled messages: {e}");
+                return;
+            }
+        };
+
+        for schedule in due {
+            let this = self.clone();
+            tokio::spawn(async move { this.run(schedule).await });
+        }
+    }
+
+    async fn run(&self, schedule: ScheduledMessage) {
+        let (id, attempts) = (schedule.id, schedule.attempts);
+        let error = match self.dispatch(&schedule).await {
+            Ok(()) => {
+                let next_run_at = schedule.next_after(Utc::now()).unwrap_or_else(|e| {
+                    log::error!("Invalid scheduled message {id}: {e}");
+                    None
+                });
+                if let Err(e) = self.db.mark_posted(&schedule, next_run_at).await {
+                    log::error!("Failed to record run of scheduled message {id}: {e}");
+                }
+                return;
+            }
+            Err(e) => e.to_string(),
+        };
+
+        let delay = (attempts < MAX_ATTEMPTS).then(|| RETRY_DELAY * 2u32.pow((attempts - 1).max(0) as u32));
+        match self.db.mark_failed(&schedule, &error, delay).await {
+            Ok(false) => {}
+            Ok(true) => match delay {
+                Some(delay) => log::warn!("Scheduled message {id} failed to post, retrying in {delay:?}: {error}"),
+                None => log::error!("Scheduled message {id} failed to post {attempts} times, pausing it: {error}"),
+            },
+            Err(e) => log::error!("Failed to record failed run of scheduled message {id}: {e}"),
+        }
+    }
+
+    async fn dispatch(&self, schedule: &ScheduledMessage) -> Result<(), crate::Error> {
+        let run = ScheduledMessageRun {
+            id: schedule.id,
+            name: schedule.name.clone(),
+            channel_id: schedule.channel_id.parse()?,
+            message: schedule.message.clone(),
+            attempt: schedule.attempts,
+        };
+        let tenant = Id::Guild(schedule.guild_id.parse()?);
+        let event = SimpleEvent::new_json_string(SCHEDULED_MESSAGE_EVENT.to_string(), None, serde_json::to_string(&run)?);
+        self.worker_pool.dispatch_event(tenant, event).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use dapi::{ChannelId, GuildId};
+use dapi::types::PartialGuild;
+use serde::{Deserialize, Serialize};
+use crate::geese::activity::ActivityHeatmap;
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+
+/// Reports on the message activity of a guild, e.g. to find its quietest hours
+///
+/// Activity is only tracked for guilds with activity tracking set up (see `StateOp::SetActivityTracking`). Only the
+/// guild owner may view reports outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MActivitySyscall {
+    /// Returns the message activity of a guild (or one of its channels) over the last `days` days (1-90) as a heatmap
+    /// of the hours of the week
+    Heatmap {
+        guild_id: GuildId,
+        days: u32,
+        #[serde(default)]
+        channel_id: Option<ChannelId>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MActivitySyscallRet {
+    Heatmap {
+        heatmap: ActivityHeatmap
+    },
+}
+
+impl MActivitySyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MActivitySyscallRet, MSyscallError> {
+        match self {
+            Self::Heatmap { guild_id, days, channel_id } => {
+                if !ctx.is_secure() {
+                    handler.limit(&ctx, "Activity")?;
+                    let owner_id = ctx.into_user_id()?;
+                    let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                        return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+                    };
+                    let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+                    if guild.owner_id != owner_id {
+                        return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can view activity" });
+                    }
+                }
+
+                let heatmap = handler.worker_pool.mesophyll().activity_db().heatmap(guild_id, days, channel_id).await?;
+                Ok(MActivitySyscallRet::Heatmap { heatmap })
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use dapi::{GuildId, UserId};
+use dapi::types::PartialGuild;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::{Deserialize, Serialize};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::altdetect::{ALT_EXPLAIN_EVENT, AltExplainReq};
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// Transparency into the alt detection of a guild
+///
+/// Only the guild owner may have members of a guild explained outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MAltDetectSyscall {
+    /// Explains how members are assessed in a guild, along with the latest assessment of a member if the guild's
+    /// worker still has it
+    Explain {
+        guild_id: GuildId,
+        user_id: UserId,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MAltDetectSyscallRet {
+    Explanation {
+        explanation: KhronosValue
+    },
+}
+
+impl MAltDetectSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MAltDetectSyscallRet, MSyscallError> {
+        match self {
+            Self::Explain { guild_id, user_id } => {
+                if !ctx.is_secure() {
+                    let owner_id = ctx.into_user_id()?;
+                    handler.limit(&ctx, "AltDetect")?;
+                    let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                        return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+                    };
+
+                    let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+                    if guild.owner_id != owner_id {
+                        return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can have alt detection explained" });
+                    }
+                }
+
+                let req = AltExplainReq { user_id };
+                let event = SimpleEvent::new_json_string(ALT_EXPLAIN_EVENT.to_string(), None, serde_json::to_string(&req)?);
+                let explanation = handler.worker_pool.dispatch_event(Id::Guild(guild_id), event).await?;
+                Ok(MAltDetectSyscallRet::Explanation { explanation })
+            }
+        }
+    }
+}
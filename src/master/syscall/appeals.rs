@@ -0,0 +1,80 @@
+use dapi::GuildId;
+use dapi::types::PartialGuild;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::{Deserialize, Serialize};
+use crate::geese::appeals::{self, Appeal};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::appeals::{APPEAL_REQUEST_EVENT, AppealRequest, SOURCE_WEB};
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// Maximum number of appeals returned by `List`
+const MAX_LISTED_APPEALS: i64 = 100;
+
+/// Submission of appeals from the website and listing of the appeals of a guild
+///
+/// Any user may submit an appeal for themselves (the guild checks they are banned or timed out), only the guild
+/// owner may list the appeals of a guild outside of secure contexts. Moderators act on appeals in Discord
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MAppealSyscall {
+    /// Submits an appeal of the user against their ban or timeout in a guild
+    Submit {
+        guild_id: GuildId,
+        reason: String,
+        /// Links supporting the appeal
+        #[serde(default)]
+        evidence: Vec<String>,
+    },
+    /// Lists the most recent appeals of a guild
+    List {
+        guild_id: GuildId,
+        /// Only list appeals which are open or under review
+        #[serde(default)]
+        unresolved_only: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MAppealSyscallRet {
+    Submitted {
+        appeal: KhronosValue
+    },
+    Appeals {
+        appeals: Vec<Appeal>
+    },
+}
+
+impl MAppealSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MAppealSyscallRet, MSyscallError> {
+        match self {
+            Self::Submit { guild_id, reason, evidence } => {
+                let user_id = ctx.into_user_id()?;
+                handler.limit(&ctx, "AppealSubmit")?;
+                appeals::validate(&reason, &evidence)?;
+
+                let req = AppealRequest { user_id, reason, evidence, source: SOURCE_WEB.to_string() };
+                let event = SimpleEvent::new_json_string(APPEAL_REQUEST_EVENT.to_string(), None, serde_json::to_string(&req)?);
+                let appeal = handler.worker_pool.dispatch_event(Id::Guild(guild_id), event).await?;
+                Ok(MAppealSyscallRet::Submitted { appeal })
+            }
+            Self::List { guild_id, unresolved_only } => {
+                if !ctx.is_secure() {
+                    let user_id = ctx.into_user_id()?;
+                    handler.limit(&ctx, "Appeals")?;
+                    let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                        return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+                    };
+
+                    let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+                    if guild.owner_id != user_id {
+                        return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can list appeals" });
+                    }
+                }
+
+                Ok(MAppealSyscallRet::Appeals { appeals: handler.appeal_db.list(guild_id, unresolved_only, MAX_LISTED_APPEALS).await? })
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use crate::CONFIG;
+use crate::geese::archival::TenantArchive;
+use crate::master::archival;
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::workervmmanager::Id;
+
+/// Archival of the data of tenants into cold storage (works in secure contexts only)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MArchivalSyscall {
+    /// Returns the archive of a tenant, if it was ever archived
+    GetArchive {
+        id: Id
+    },
+    /// Archives the data of a tenant now, regardless of its activity
+    Archive {
+        id: Id
+    },
+    /// Restores the data of an archived tenant now
+    Restore {
+        id: Id
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MArchivalSyscallRet {
+    Archive {
+        archive: Option<TenantArchive>
+    },
+}
+
+impl MArchivalSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MArchivalSyscallRet, MSyscallError> {
+        if !ctx.is_secure() {
+            return Err(MSyscallError::ContextInsecure);
+        }
+
+        let actor = ctx.into_user_id().ok().map(|u| u.to_string());
+        match self {
+            Self::GetArchive { id } => {
+                let archive = handler.worker_pool.mesophyll().archive_db().get(id).await?;
+                Ok(MArchivalSyscallRet::Archive { archive })
+            }
+            Self::Archive { id } => {
+                let Some(config) = CONFIG.archival.as_ref() else {
+                    return Err("Archival is not configured".into());
+                };
+                let archive = archival::archive(&handler.worker_pool, id, config, actor).await?;
+                Ok(MArchivalSyscallRet::Archive { archive })
+            }
+            Self::Restore { id } => {
+                let archive = archival::restore(&handler.worker_pool, id, actor).await?;
+                Ok(MArchivalSyscallRet::Archive { archive })
+            }
+        }
+    }
+}
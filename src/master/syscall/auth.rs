@@ -1,5 +1,9 @@
+use std::time::Duration;
+
+use bytes::Bytes;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use dapi::UserId;
 use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler, internal::auth as iauth, types::auth::UserSession};
 use super::types::discord::*;
@@ -68,12 +72,12 @@ impl MAuthSyscall {
                     return Err(MSyscallError::AuthError { reason: AuthError::CodeTooShort });
                 }
 
-                if handler.oauth2_code_cache.contains_key(&code) {
+                // Atomic across hosts so a code can't be used twice by racing two of them
+                let code_key = format!("oauth2_code:{}", hex::encode(Sha256::digest(code.as_bytes())));
+                if !handler.shared_cache.insert_new(&code_key, Bytes::new(), Duration::from_secs(60 * 10)).await? {
                     return Err(MSyscallError::AuthError { reason: AuthError::CodeReuseDetected });
                 }
 
-                handler.oauth2_code_cache.insert(code.clone(), ()).await;
-
                 let app_login = redirect_uri == Self::APP_OAUTH2_REDIRECT_URI && code_verifier.is_some();
 
                 #[derive(serde::Serialize)]
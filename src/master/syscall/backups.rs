@@ -0,0 +1,112 @@
+use dapi::{GuildId, UserId};
+use dapi::types::PartialGuild;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::{Deserialize, Serialize};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// Internal event dispatched to the builtins of a guild to list, create or restore its backups
+pub const BACKUP_EVENT: &str = "$Backup";
+
+/// Backups of the structure (settings, roles, channels and overwrites) of a guild
+///
+/// Backups are made and restored by the builtins of the guild, the same as with the backup commands. Only the guild
+/// owner may use backups outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MBackupSyscall {
+    /// Lists the backups of a guild
+    List {
+        guild_id: GuildId,
+    },
+    /// Backs up the structure of a guild now, returning the ID of the backup
+    Create {
+        guild_id: GuildId,
+    },
+    /// Diffs the current structure of a guild against a backup, restoring the backup only if `confirm` is set
+    Restore {
+        guild_id: GuildId,
+        backup_id: String,
+        /// Channels never deleted by the restore
+        #[serde(default)]
+        protected_channels: Vec<String>,
+        /// Roles never deleted by the restore
+        #[serde(default)]
+        protected_roles: Vec<String>,
+        #[serde(default)]
+        confirm: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MBackupSyscallRet {
+    Backups {
+        backups: KhronosValue
+    },
+    Created {
+        backup: KhronosValue
+    },
+    Restore {
+        restore: KhronosValue
+    },
+}
+
+/// Data of `$Backup`
+#[derive(Serialize)]
+#[serde(tag = "op")]
+enum BackupEvent {
+    List,
+    Create {
+        created_by: Option<UserId>,
+    },
+    Restore {
+        backup_id: String,
+        protected_channels: Vec<String>,
+        protected_roles: Vec<String>,
+        confirm: bool,
+    },
+}
+
+impl MBackupSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MBackupSyscallRet, MSyscallError> {
+        let guild_id = match self {
+            Self::List { guild_id } | Self::Create { guild_id } | Self::Restore { guild_id, .. } => guild_id,
+        };
+
+        if !ctx.is_secure() {
+            let owner_id = ctx.into_user_id()?;
+            handler.limit(&ctx, "Backups")?;
+            let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+            };
+
+            let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+            if guild.owner_id != owner_id {
+                return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can manage backups" });
+            }
+        }
+
+        match self {
+            Self::List { .. } => {
+                let backups = dispatch(handler, guild_id, BackupEvent::List).await?;
+                Ok(MBackupSyscallRet::Backups { backups })
+            }
+            Self::Create { .. } => {
+                let backup = dispatch(handler, guild_id, BackupEvent::Create { created_by: ctx.into_user_id().ok() }).await?;
+                Ok(MBackupSyscallRet::Created { backup })
+            }
+            Self::Restore { backup_id, protected_channels, protected_roles, confirm, .. } => {
+                let event = BackupEvent::Restore { backup_id, protected_channels, protected_roles, confirm };
+                let restore = dispatch(handler, guild_id, event).await?;
+                Ok(MBackupSyscallRet::Restore { restore })
+            }
+        }
+    }
+}
+
+async fn dispatch(handler: &MSyscallHandler, guild_id: GuildId, event: BackupEvent) -> Result<KhronosValue, MSyscallError> {
+    let event = SimpleEvent::new_json_string(BACKUP_EVENT.to_string(), None, serde_json::to_string(&event)?);
+    Ok(handler.worker_pool.dispatch_event(Id::Guild(guild_id), event).await?)
+}
@@ -1,10 +1,10 @@
 use std::sync::Arc;
 
-use dapi::types::CreateCommand;
+use dapi::types::{CreateCommand, PartialGuild};
 use khronos_runtime::{utils::khronos_value::{CKhronosValue, KhronosValue}};
 use serde::{Deserialize, Serialize};
-use dapi::UserId;
-use crate::{geese::{state::{StateDbFlags, StateExecResult, StateOp}, tenantstate::{ModFlags, TenantState}}, master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler, types::bot::{BotStatus, ShardConn}}, worker::{workerdispatch::SimpleEvent, workervmmanager::Id}};
+use dapi::{GuildId, UserId};
+use crate::{geese::{botstatus::{self, DispatchLatency}, dbpools::PoolKind, dbrouter::ReplicaStats, entitlements::Entitlement, fixtures::{self, FixtureContext, FixtureIds}, pluginusage::PluginUsageReportRow, rules::{self, Rule, RuleSet}, usage::TemplateUsageRow, state::{StateDbFlags, StateExecResult, StateOp}, templatecache::TEMPLATES_SCOPE, tenantstate::{ModFlags, TenantLimits, TenantState}}, master::{register::BrandingSyncResult, syscall::{MSyscallContext, MSyscallError, MSyscallHandler, types::bot::{BotStatus, RecentError, ShardConn, StatusPage}}}, worker::{eventtypes::{self, EVENT_SCHEMA_VERSION}, replay::{REPLAY_SCOPE, Recording}, workerdispatch::{SimpleEvent, WorkerDispatch}, workervmmanager::Id}};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "op")]
@@ -15,6 +15,30 @@ pub enum MBotSyscall {
     GetBotConfig {},
     /// Returns the bots status
     GetBotStatus {},
+    /// Returns the data of the status page: shard health, recent dispatch latency and whether (and why) the bot is
+    /// degraded, plus the recent template errors of a guild if set. Only the guild owner may view its errors outside
+    /// of secure contexts
+    GetStatusPage {
+        guild_id: Option<GuildId>,
+    },
+    /// Returns the JSON schema of `@antiraid/rules` rule sets, for building rules in the dashboard
+    GetRulesSchema {},
+    /// Returns the schemas (fields of the payload) of gateway events for every event version, for documentation
+    /// tooling. Templates pin the version they are written against with `pragma.eventversion`
+    GetEventSchemas {},
+    /// Validates a `@antiraid/rules` rule set, returning why it is invalid if it is
+    ValidateRules {
+        rules: serde_json::Value
+    },
+    /// Builds a synthetic payload of a gateway event for testing templates (see `@antiraid/fixtures`), the user
+    /// defaults to the caller
+    GetEventFixture {
+        /// Name of the gateway event, such as `MESSAGE_CREATE`
+        event: String,
+        /// JSON merge patch applied to the payload
+        overrides: Option<serde_json::Value>,
+        ids: Option<FixtureIds>,
+    },
     /// Dispatch an event to a worker process
     DispatchEvent {
         /// Tenant ID to dispatch the event to
@@ -39,6 +63,15 @@ pub enum MBotSyscall {
         /// The topics the user is requesting to subscribe to
         requested_topics: Vec<String>
     },
+    /// Returns the daily per-template usage (wall time, memory, Discord API calls, KV ops) of a tenant
+    ///
+    /// Only the guild owner (or the user themselves for user tenants) may view usage outside of secure contexts
+    GetTemplateUsage {
+        /// Tenant ID to fetch usage for
+        id: Id,
+        /// Number of days of usage to return (1-90)
+        days: u32,
+    },
     /// Verify a presigned URL and return the decoded payload
     GetBlobData {
         /// Payload 
@@ -61,16 +94,44 @@ pub enum MBotSyscall {
         /// The author ID to mock
         mock_id: Option<String>
     },
+    /// Admin API to dispatch a synthetic gateway event (as built by `GetEventFixture`) to a tenant, for testing
+    /// templates from the shell (works in secure contexts only)
+    AdminDispatchEventFixture {
+        /// Tenant ID to dispatch the event to, the guild of the payload defaults to it
+        id: Id,
+        event: String,
+        overrides: Option<serde_json::Value>,
+        ids: Option<FixtureIds>,
+    },
     /// Returns the uncached bot status (works in secure contexts only)
     AdminGetUncachedBotStatus {},
     /// Admin API to drop a tenant (works in secure contexts only)
     AdminDropTenant { id: Id },
     /// Admin API to set tenant state moderation flags (ban them etc.) (works in secure contexts only)
     AdminSetTenantStateModFlags { id: Id, modflags: ModFlags },
+    /// Admin API to set the VM limit overrides of a tenant, applied live to running VMs (works in secure contexts only)
+    AdminSetTenantLimits { id: Id, limits: TenantLimits },
     /// Admin API to run a set of state ops on a tenant (works in secure contexts only)
     AdminState { id: Id, ops: Vec<StateOp> },
     /// Admin API to fetch tenant state for a tenant (works in secure contexts only)
-    AdminFetchTenantState { id: Id }
+    AdminFetchTenantState { id: Id },
+    /// Admin API to replay a recorded execution of a tenant in a throwaway VM (works in secure contexts only)
+    ///
+    /// Executions are recorded for tenants with the `replay:record` feature flag enabled
+    AdminReplayExecution { id: Id, key: String },
+    /// Admin API to sync the bot's profile and application emojis with the branding config (works in secure contexts only)
+    ///
+    /// With `dry_run`, returns what would change without changing anything
+    AdminSyncBranding { dry_run: bool },
+    /// Admin API to grant a tenant a test entitlement to a SKU, for developing premium features (works in secure contexts only)
+    AdminCreateTestEntitlement { id: Id, sku_id: String },
+    /// Admin API to delete a test entitlement (works in secure contexts only)
+    AdminDeleteTestEntitlement { entitlement_id: String },
+    /// Admin API to report how often each plugin method was called over the last `days` days (1-90), optionally
+    /// of a single plugin (syscall category) only (works in secure contexts only)
+    AdminGetPluginUsage { days: u32, plugin: Option<String> },
+    /// Admin API to report the replication lag of the read replica and how reads were routed (works in secure contexts only)
+    AdminGetReadReplicaStats {}
 }
 
 #[derive(Serialize, Deserialize)]
@@ -92,6 +153,10 @@ pub enum MBotSyscallRet {
     BotStatus {
         status: BotStatus
     },
+    /// Status page data
+    StatusPage {
+        status: StatusPage
+    },
     /// Khronos value response
     KhronosValue {
         data: KhronosValue
@@ -117,6 +182,45 @@ pub enum MBotSyscallRet {
         payload: String,
         sig: String
     },
+    /// Daily per-template usage of a tenant (oldest first)
+    TemplateUsage {
+        usage: Vec<TemplateUsageRow>
+    },
+    /// Branding sync result (admin only)
+    BrandingSync {
+        result: BrandingSyncResult
+    },
+    /// Entitlement response (admin only)
+    Entitlement {
+        entitlement: Entitlement
+    },
+    /// Plugin usage report, most used methods first (admin only)
+    PluginUsage {
+        usage: Vec<PluginUsageReportRow>
+    },
+    /// Read replica stats, unset if no read replica is configured (admin only)
+    ReadReplicaStats {
+        stats: Option<ReplicaStats>
+    },
+    /// JSON schema of rule sets
+    RulesSchema {
+        schema: serde_json::Value
+    },
+    /// Schemas of gateway events
+    EventSchemas {
+        /// Latest event version
+        version: u32,
+        schemas: serde_json::Value
+    },
+    /// Rule set validation result, the error is unset if the rule set is valid
+    RulesValidation {
+        error: Option<String>
+    },
+    /// Synthetic gateway event payload
+    EventFixture {
+        event: String,
+        data: serde_json::Value
+    },
     Ack,
 }
 
@@ -132,30 +236,66 @@ impl MBotSyscall {
                     support_server_invite: crate::CONFIG.support_server_invite.clone(),
                 })
             }
+            Self::GetRulesSchema {} => {
+                Ok(MBotSyscallRet::RulesSchema { schema: rules::schema() })
+            }
+            Self::GetEventSchemas {} => {
+                Ok(MBotSyscallRet::EventSchemas { version: EVENT_SCHEMA_VERSION, schemas: serde_json::to_value(eventtypes::schemas())? })
+            }
+            Self::ValidateRules { rules } => {
+                let error = serde_json::from_value::<Vec<Rule>>(rules)
+                    .map_err(crate::Error::from)
+                    .and_then(RuleSet::compile)
+                    .err()
+                    .map(|e| e.to_string());
+                Ok(MBotSyscallRet::RulesValidation { error })
+            }
+            Self::GetEventFixture { event, overrides, ids } => {
+                let mut base = FixtureContext { application_id: handler.stratum.current_user().id, ..Default::default() };
+                if let Ok(user_id) = ctx.into_user_id() {
+                    base.user_id = user_id;
+                }
+                let data = fixtures::build(&event, &base.with_ids(ids.unwrap_or_default()), overrides)?;
+                Ok(MBotSyscallRet::EventFixture { event, data })
+            }
             Self::GetBotStatus {  } => {
-                let status = handler.status_cache.try_get_with::<_, crate::Error>((), async move {
-                    let raw_stats = handler.stratum.get_status().await?;
-                    let uptime = chrono::Utc::now()
-                        .signed_duration_since(crate::CONFIG.start_time)
-                        .num_seconds()
-                        .max(0) as u64;
-
-                    let stats = BotStatus {
-                        shard_conns: raw_stats.shards.into_iter().map(|shard| {
-                            (shard.shard_id, ShardConn {
-                                status: shard.state().as_str_name().to_string(),
-                                latency: shard.latency,
-                            })
-                        }).collect(),
-                        total_guilds: raw_stats.guild_count,
-                        total_users: raw_stats.user_count,
-                        uptime
-                    };
-
-                    Ok(stats)
-                }).await?;
+                Ok(MBotSyscallRet::BotStatus { status: bot_status(handler).await? })
+            }
+            Self::GetStatusPage { guild_id } => {
+                let mut recent_errors = Vec::new();
+                if let Some(guild_id) = guild_id {
+                    if !ctx.is_secure() {
+                        handler.limit(&ctx, "GetStatusPage")?;
+                        let owner_id = ctx.into_user_id()?;
+                        let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                            return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+                        };
+                        let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+                        if guild.owner_id != owner_id {
+                            return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can view the errors of the guild" });
+                        }
+                    }
 
-                Ok(MBotSyscallRet::BotStatus { status })
+                    recent_errors = self::recent_errors(handler, guild_id).await?;
+                }
+
+                let status = bot_status(handler).await?;
+                let dispatch = dispatch_latency(handler).await?;
+                let gateway = handler.stratum.gateway_health().status();
+                let degraded_reasons = botstatus::degraded_reasons(
+                    status.shard_conns.values().map(|s| s.latency),
+                    &dispatch,
+                    handler.stratum.gateway_health().is_direct(),
+                );
+
+                Ok(MBotSyscallRet::StatusPage { status: StatusPage {
+                    degraded: !degraded_reasons.is_empty(),
+                    degraded_reasons,
+                    shard_conns: status.shard_conns,
+                    gateway,
+                    dispatch,
+                    recent_errors,
+                } })
             }
             Self::DispatchEvent { id, name, data } => {
                 if !name.starts_with("Web") {
@@ -241,6 +381,32 @@ impl MBotSyscall {
                 let (payload, sig) = crate::geese::feedticket::create_feedticket(id, user_id, requested_topics)?;
                 Ok(MBotSyscallRet::FeedTicket { payload, sig }) 
             }
+            Self::GetTemplateUsage { id, days } => {
+                if !ctx.is_secure() {
+                    let user_id = ctx.into_user_id()?;
+                    handler.limit(&ctx, "GetTemplateUsage")?;
+                    match id {
+                        Id::Guild(guild_id) => {
+                            let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                                return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+                            };
+
+                            let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+                            if guild.owner_id != user_id {
+                                return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can view template usage" });
+                            }
+                        }
+                        Id::User(id) => {
+                            if user_id != id {
+                                return Err(MSyscallError::Unauthorized { reason: "Cannot view the template usage of users who are not yourself" });
+                            }
+                        }
+                    }
+                }
+
+                let usage = handler.worker_pool.mesophyll().usage_db().get_usage(id, days.clamp(1, 90)).await?;
+                Ok(MBotSyscallRet::TemplateUsage { usage })
+            }
             Self::AdminRelaxedDispatchEvent { id, name, data, allow_non_web_event_names, allow_self_event, mock_id } => {
                 if !ctx.is_secure() {
                     return Err(MSyscallError::ContextInsecure);
@@ -275,6 +441,26 @@ impl MBotSyscall {
 
                 Ok(MBotSyscallRet::KhronosValue { data: handler.worker_pool.dispatch_event(id, event).await? })
             }
+            Self::AdminDispatchEventFixture { id, event, overrides, ids } => {
+                if !ctx.is_secure() {
+                    return Err(MSyscallError::ContextInsecure);
+                }
+
+                let mut base = FixtureContext { application_id: handler.stratum.current_user().id, ..Default::default() };
+                match id {
+                    Id::Guild(guild_id) => {
+                        if !handler.has_bot_single(guild_id).await? {
+                            return Err(MSyscallError::BotNotOnGuild);
+                        }
+                        base.guild_id = guild_id;
+                    }
+                    Id::User(user_id) => base.user_id = user_id,
+                }
+
+                let data = fixtures::build(&event, &base.with_ids(ids.unwrap_or_default()), overrides)?;
+                let event = SimpleEvent::new_json_string(event, None, serde_json::to_string(&data)?);
+                Ok(MBotSyscallRet::KhronosValue { data: handler.worker_pool.dispatch_event(id, event).await? })
+            }
             Self::AdminGetUncachedBotStatus {  } => {
                 if !ctx.is_secure() {
                     return Err(MSyscallError::ContextInsecure);
@@ -330,11 +516,36 @@ impl MBotSyscall {
                 handler.worker_pool.update_tenant_state(id, ts).await?;
                 Ok(MBotSyscallRet::Ack)
             }
+            Self::AdminSetTenantLimits { id, limits } => {
+                if !ctx.is_secure() {
+                    return Err(MSyscallError::ContextInsecure);
+                }
+                let mut tx = handler.pool.begin().await?;
+                sqlx::query("INSERT INTO tenant_state (owner_id, owner_type, memory_limit, execution_time_limit_ms, return_wait_ms) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (owner_id, owner_type) DO UPDATE SET memory_limit = EXCLUDED.memory_limit, execution_time_limit_ms = EXCLUDED.execution_time_limit_ms, return_wait_ms = EXCLUDED.return_wait_ms")
+                    .bind(id.tenant_id())
+                    .bind(id.tenant_type())
+                    .bind(limits.memory_limit.map(|v| v as i64))
+                    .bind(limits.execution_time_limit_ms.map(|v| v as i64))
+                    .bind(limits.return_wait_ms.map(|v| v as i64))
+                    .execute(&mut *tx)
+                    .await?;
+
+                // Refresh tenant state now
+                let Some(ts) = handler.tsdb.get_tenant_state_for(&mut tx, id).await? else {
+                    return Err("failed to find tenant state after update".into())
+                };
+
+                tx.commit().await?;
+                
+                handler.worker_pool.update_tenant_state(id, ts).await?;
+                Ok(MBotSyscallRet::Ack)
+            }
             Self::AdminState { id, ops } => {
                 if !ctx.is_secure() {
                     return Err(MSyscallError::ContextInsecure);
                 }
 
+                let templates_changed = ops.iter().any(|op| op.written_scope() == Some(TEMPLATES_SCOPE));
                 let res = handler.statedb.do_op(id, ops, StateDbFlags::ADMIN).await?;
 
                 // inform worker of new tenant state if we have a new tenant state
@@ -342,6 +553,11 @@ impl MBotSyscall {
                     handler.worker_pool.update_tenant_state(id, new_ts.clone()).await?;
                 }
 
+                // templates were changed outside the tenants VM, make it reload them
+                if templates_changed {
+                    handler.worker_pool.mesophyll().broadcast_template_invalidation(id).await;
+                }
+
                 Ok(MBotSyscallRet::State { res: res.results, new_tenant_state: res.new_tenant_state })
             }
             Self::AdminFetchTenantState { id } => {
@@ -359,6 +575,161 @@ impl MBotSyscall {
 
                 Ok(MBotSyscallRet::TenantState { ts })
             }
+            Self::AdminReplayExecution { id, key } => {
+                if !ctx.is_secure() {
+                    return Err(MSyscallError::ContextInsecure);
+                }
+
+                let ops = vec![StateOp::KvGetWithBlob { key, scope: REPLAY_SCOPE.to_string() }];
+                let res = handler.statedb.do_op(id, ops, StateDbFlags::ADMIN).await?;
+                let Some(StateExecResult::KvWithBlob { blob: Some(blob), .. }) = res.results.into_iter().next() else {
+                    return Err(MSyscallError::EntityNotFound { reason: "recording not found" });
+                };
+
+                let recording: Recording = rmp_serde::from_slice(&blob)
+                    .map_err(|e| format!("Failed to decode recording: {e}"))?;
+                if recording.id != id {
+                    return Err(MSyscallError::EntityNotFound { reason: "recording not found" });
+                }
+
+                let event = SimpleEvent::new_replay(recording);
+                Ok(MBotSyscallRet::KhronosValue { data: handler.worker_pool.dispatch_event(id, event).await? })
+            }
+            Self::AdminSyncBranding { dry_run } => {
+                if !ctx.is_secure() {
+                    return Err(MSyscallError::ContextInsecure);
+                }
+
+                let result = crate::master::register::sync_branding(&handler.reqwest, &handler.pool, dry_run).await?;
+                Ok(MBotSyscallRet::BrandingSync { result })
+            }
+            Self::AdminCreateTestEntitlement { id, sku_id } => {
+                if !ctx.is_secure() {
+                    return Err(MSyscallError::ContextInsecure);
+                }
+
+                let owner_type = match id {
+                    Id::Guild(_) => 1,
+                    Id::User(_) => 2,
+                };
+                let resp = handler.reqwest.post(format!("{}/api/v10/applications/{}/entitlements", crate::CONFIG.proxy, crate::CONFIG.client_id))
+                    .header("Authorization", format!("Bot {}", crate::CONFIG.nirn_token))
+                    .json(&serde_json::json!({ "sku_id": sku_id, "owner_id": id.tenant_id(), "owner_type": owner_type }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+
+                // Workers pick the entitlement up through the ENTITLEMENT_CREATE event
+                Ok(MBotSyscallRet::Entitlement { entitlement: resp.json().await? })
+            }
+            Self::AdminDeleteTestEntitlement { entitlement_id } => {
+                if !ctx.is_secure() {
+                    return Err(MSyscallError::ContextInsecure);
+                }
+
+                handler.reqwest.delete(format!("{}/api/v10/applications/{}/entitlements/{entitlement_id}", crate::CONFIG.proxy, crate::CONFIG.client_id))
+                    .header("Authorization", format!("Bot {}", crate::CONFIG.nirn_token))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+
+                Ok(MBotSyscallRet::Ack)
+            }
+            Self::AdminGetPluginUsage { days, plugin } => {
+                if !ctx.is_secure() {
+                    return Err(MSyscallError::ContextInsecure);
+                }
+
+                let usage = handler.worker_pool.mesophyll().plugin_usage_db().report(days.clamp(1, 90), plugin.as_deref()).await?;
+                Ok(MBotSyscallRet::PluginUsage { usage })
+            }
+            Self::AdminGetReadReplicaStats {} => {
+                if !ctx.is_secure() {
+                    return Err(MSyscallError::ContextInsecure);
+                }
+
+                Ok(MBotSyscallRet::ReadReplicaStats { stats: handler.db.stats() })
+            }
         }
     }
 }
+
+/// Maximum number of recent errors returned on the status page
+const MAX_STATUS_PAGE_ERRORS: i64 = 20;
+
+/// Returns the status of the bot, cached for a while
+async fn bot_status(handler: &MSyscallHandler) -> Result<BotStatus, MSyscallError> {
+    let status = handler.status_cache.try_get_with::<_, crate::Error>((), async move {
+        let raw_stats = handler.stratum.get_status().await?;
+        let uptime = chrono::Utc::now()
+            .signed_duration_since(crate::CONFIG.start_time)
+            .num_seconds()
+            .max(0) as u64;
+
+        let stats = BotStatus {
+            shard_conns: raw_stats.shards.into_iter().map(|shard| {
+                (shard.shard_id, ShardConn {
+                    status: shard.state().as_str_name().to_string(),
+                    latency: shard.latency,
+                })
+            }).collect(),
+            total_guilds: raw_stats.guild_count,
+            total_users: raw_stats.user_count,
+            uptime
+        };
+
+        Ok(stats)
+    }).await?;
+
+    Ok(status)
+}
+
+/// Returns the latency of the recent dispatches of all workers on the ring, cached for a while
+///
+/// Workers failing to respond are left out
+async fn dispatch_latency(handler: &MSyscallHandler) -> Result<DispatchLatency, MSyscallError> {
+    let latency = handler.dispatch_latency_cache.try_get_with::<_, crate::Error>((), async move {
+        let workers = handler.worker_pool.mesophyll().router().ring().workers().to_vec();
+
+        let mut samples = Vec::new();
+        for worker_id in workers {
+            let Some(conn) = handler.worker_pool.mesophyll().get_connection(worker_id) else {
+                continue;
+            };
+            match conn.get_recent_dispatches().await {
+                Ok(recent) => samples.extend(recent),
+                Err(e) => log::warn!("Failed to get the recent dispatches of worker {worker_id}: {e}"),
+            }
+        }
+
+        Ok(DispatchLatency::from_samples(samples))
+    }).await?;
+
+    Ok(latency)
+}
+
+#[derive(sqlx::FromRow)]
+struct ErrorRow {
+    #[sqlx(json)]
+    value: KhronosValue,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Returns the errors of the templates of a guild over the last day, newest first
+async fn recent_errors(handler: &MSyscallHandler, guild_id: GuildId) -> Result<Vec<RecentError>, crate::Error> {
+    let id = Id::Guild(guild_id);
+    let rows: Vec<ErrorRow> = sqlx::query_as(
+        "SELECT value, created_at FROM tenant_kv WHERE owner_id = $1 AND owner_type = $2 AND scope = $3 AND created_at > NOW() - INTERVAL '1 day' ORDER BY created_at DESC LIMIT $4",
+    )
+    .bind(id.tenant_id())
+    .bind(id.tenant_type())
+    .bind(WorkerDispatch::ERR_SCOPE)
+    .bind(MAX_STATUS_PAGE_ERRORS)
+    .fetch_all(handler.db.pool(PoolKind::Kv).pool())
+    .await?;
+
+    Ok(rows.into_iter().filter_map(|row| match row.value {
+        KhronosValue::Text(error) => Some(RecentError { error: error.to_string(), created_at: row.created_at }),
+        _ => None,
+    }).collect())
+}
@@ -0,0 +1,109 @@
+use dapi::types::PartialGuild;
+use serde::{Deserialize, Serialize};
+use crate::geese::datalifecycle::{DataAuditEntry, DataJob, REASON_REQUESTED};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::workervmmanager::Id;
+
+/// Deletion and export of the stored data of a tenant
+///
+/// Only the guild owner (or the user themselves) may manage the data of a tenant outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MDataSyscall {
+    /// Schedules deletion of all data of a tenant once the grace period is over
+    RequestDeletion {
+        id: Id
+    },
+    /// Cancels the pending deletion of a tenant
+    CancelDeletion {
+        id: Id
+    },
+    /// Starts an export of all data of a tenant, downloadable through `ListJobs` once completed
+    RequestExport {
+        id: Id
+    },
+    /// Returns the most recent deletions and exports of a tenant
+    ListJobs {
+        id: Id
+    },
+    /// Returns the audit log of actions taken on the data of a tenant
+    GetAuditLog {
+        id: Id
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MDataSyscallRet {
+    Job {
+        job: DataJob
+    },
+    Jobs {
+        jobs: Vec<DataJob>
+    },
+    AuditLog {
+        entries: Vec<DataAuditEntry>
+    },
+    Ack {},
+}
+
+impl MDataSyscall {
+    fn id(&self) -> Id {
+        match self {
+            Self::RequestDeletion { id }
+            | Self::CancelDeletion { id }
+            | Self::RequestExport { id }
+            | Self::ListJobs { id }
+            | Self::GetAuditLog { id } => *id,
+        }
+    }
+
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MDataSyscallRet, MSyscallError> {
+        if !ctx.is_secure() {
+            let user_id = ctx.into_user_id()?;
+            handler.limit(&ctx, "DataLifecycle")?;
+            match self.id() {
+                Id::Guild(guild_id) => {
+                    let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                        return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+                    };
+
+                    let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+                    if guild.owner_id != user_id {
+                        return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can manage the data of a guild" });
+                    }
+                }
+                Id::User(id) => {
+                    if user_id != id {
+                        return Err(MSyscallError::Unauthorized { reason: "Cannot manage the data of users who are not yourself" });
+                    }
+                }
+            }
+        }
+
+        let db = handler.worker_pool.mesophyll().data_lifecycle_db();
+        let actor = ctx.into_user_id().ok().map(|u| u.to_string());
+        match self {
+            Self::RequestDeletion { id } => {
+                let job = db.schedule_deletion(id, REASON_REQUESTED, actor).await?;
+                Ok(MDataSyscallRet::Job { job })
+            }
+            Self::CancelDeletion { id } => {
+                if !db.cancel_deletion(id, actor, None).await? {
+                    return Err(MSyscallError::EntityNotFound { reason: "No pending deletion to cancel" });
+                }
+                Ok(MDataSyscallRet::Ack {})
+            }
+            Self::RequestExport { id } => {
+                let job = db.request_export(id, actor).await?;
+                Ok(MDataSyscallRet::Job { job })
+            }
+            Self::ListJobs { id } => {
+                Ok(MDataSyscallRet::Jobs { jobs: db.list_jobs(id).await? })
+            }
+            Self::GetAuditLog { id } => {
+                Ok(MDataSyscallRet::AuditLog { entries: db.audit_log(id).await? })
+            }
+        }
+    }
+}
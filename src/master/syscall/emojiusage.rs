@@ -0,0 +1,72 @@
+use dapi::GuildId;
+use dapi::types::PartialGuild;
+use serde::{Deserialize, Serialize};
+use crate::geese::emojiusage::{EmojiKind, EmojiUsageStats, guild_items};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+
+/// Reports on the custom emoji and sticker usage of a guild, so unused slots can be pruned
+///
+/// Usage is only tracked for guilds with emoji usage tracking set up (see `StateOp::SetEmojiUsage`). Only the guild
+/// owner may view reports outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MEmojiUsageSyscall {
+    /// Returns the most used emojis and stickers of a guild over the last `days` days (1-90)
+    Top {
+        guild_id: GuildId,
+        days: u32,
+        #[serde(default)]
+        kind: Option<EmojiKind>,
+        limit: i64,
+    },
+    /// Returns the emojis and stickers of a guild not used in the last `days` days (1-90), least recently used first
+    Unused {
+        guild_id: GuildId,
+        days: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MEmojiUsageSyscallRet {
+    Usage {
+        usage: Vec<EmojiUsageStats>
+    },
+}
+
+impl MEmojiUsageSyscall {
+    fn guild_id(&self) -> GuildId {
+        match self {
+            Self::Top { guild_id, .. } | Self::Unused { guild_id, .. } => *guild_id,
+        }
+    }
+
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MEmojiUsageSyscallRet, MSyscallError> {
+        let guild_id = self.guild_id();
+        if !ctx.is_secure() {
+            handler.limit(&ctx, "EmojiUsage")?;
+        }
+        // Also needed for the emojis and stickers of the guild
+        let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+            return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+        };
+        if !ctx.is_secure() {
+            let owner_id = ctx.into_user_id()?;
+            let guild = serde_json::from_value::<PartialGuild>(guild_json.clone())?;
+            if guild.owner_id != owner_id {
+                return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can view emoji usage" });
+            }
+        }
+
+        let db = handler.worker_pool.mesophyll().emoji_usage_db();
+        match self {
+            Self::Top { days, kind, limit, .. } => {
+                Ok(MEmojiUsageSyscallRet::Usage { usage: db.top(guild_id, days, kind, limit).await? })
+            }
+            Self::Unused { days, .. } => {
+                let items = guild_items(&guild_json);
+                Ok(MEmojiUsageSyscallRet::Usage { usage: db.unused(guild_id, days, &items).await? })
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use crate::geese::featureflags::FeatureFlag;
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+
+/// Feature flag management (works in secure contexts only)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MFeatureFlagSyscall {
+    /// Lists all feature flags
+    ListFeatureFlags {},
+    /// Creates or updates a feature flag, pushing the change to all workers
+    SetFeatureFlag {
+        flag: FeatureFlag
+    },
+    /// Deletes a feature flag, pushing the change to all workers
+    DeleteFeatureFlag {
+        name: String
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MFeatureFlagSyscallRet {
+    FeatureFlagList {
+        flags: Vec<FeatureFlag>
+    },
+    Ack,
+}
+
+impl MFeatureFlagSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MFeatureFlagSyscallRet, MSyscallError> {
+        if !ctx.is_secure() {
+            return Err(MSyscallError::ContextInsecure);
+        }
+
+        let mesophyll = handler.worker_pool.mesophyll();
+        match self {
+            Self::ListFeatureFlags {} => {
+                let mut flags = mesophyll.feature_flag_db().list().await?.into_vec();
+                flags.sort_by(|a, b| a.name.cmp(&b.name));
+                Ok(MFeatureFlagSyscallRet::FeatureFlagList { flags })
+            }
+            Self::SetFeatureFlag { flag } => {
+                if flag.name.is_empty() {
+                    return Err("Feature flag name cannot be empty".into());
+                }
+
+                mesophyll.feature_flag_db().set(&flag).await?;
+                mesophyll.broadcast_feature_flags().await?;
+                Ok(MFeatureFlagSyscallRet::Ack)
+            }
+            Self::DeleteFeatureFlag { name } => {
+                if !mesophyll.feature_flag_db().delete(&name).await? {
+                    return Err(MSyscallError::EntityNotFound { reason: "Feature flag with this name was not found" });
+                }
+
+                mesophyll.broadcast_feature_flags().await?;
+                Ok(MFeatureFlagSyscallRet::Ack)
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use dapi::GuildId;
+use dapi::types::PartialGuild;
+use serde::{Deserialize, Serialize};
+use crate::geese::inboundwebhooks::InboundWebhook;
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+
+/// Management of the inbound webhooks of a guild, which let external services dispatch `SignedWebhook`
+/// to its templates
+///
+/// Only the guild owner may manage inbound webhooks outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MInboundWebhookSyscall {
+    /// Creates an inbound webhook, returning its URL and secret
+    Create {
+        guild_id: GuildId,
+        name: String
+    },
+    /// Lists the inbound webhooks of a guild
+    List {
+        guild_id: GuildId
+    },
+    /// Deletes an inbound webhook
+    Delete {
+        guild_id: GuildId,
+        webhook_id: String
+    },
+    /// Replaces the secret of an inbound webhook, returning the new secret
+    RotateSecret {
+        guild_id: GuildId,
+        webhook_id: String
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MInboundWebhookSyscallRet {
+    Created {
+        webhook: InboundWebhook,
+        url: String,
+        /// Only returned on creation, store it securely
+        secret: String
+    },
+    Webhooks {
+        webhooks: Vec<InboundWebhook>
+    },
+    Secret {
+        secret: String
+    },
+    Ack {},
+}
+
+impl MInboundWebhookSyscall {
+    fn guild_id(&self) -> GuildId {
+        match self {
+            Self::Create { guild_id, .. }
+            | Self::List { guild_id }
+            | Self::Delete { guild_id, .. }
+            | Self::RotateSecret { guild_id, .. } => *guild_id,
+        }
+    }
+
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MInboundWebhookSyscallRet, MSyscallError> {
+        if !ctx.is_secure() {
+            let user_id = ctx.into_user_id()?;
+            handler.limit(&ctx, "InboundWebhooks")?;
+            let Some(guild_json) = handler.stratum.guild(self.guild_id()).await? else {
+                return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+            };
+
+            let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+            if guild.owner_id != user_id {
+                return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can manage inbound webhooks" });
+            }
+        }
+
+        let db = &handler.inbound_webhook_db;
+        match self {
+            Self::Create { guild_id, name } => {
+                let (webhook, secret) = db.create(guild_id, name.trim(), ctx.into_user_id().ok()).await?;
+                Ok(MInboundWebhookSyscallRet::Created { url: webhook.url(), webhook, secret })
+            }
+            Self::List { guild_id } => {
+                Ok(MInboundWebhookSyscallRet::Webhooks { webhooks: db.list(guild_id).await? })
+            }
+            Self::Delete { guild_id, webhook_id } => {
+                if !db.delete(guild_id, &webhook_id).await? {
+                    return Err(MSyscallError::EntityNotFound { reason: "Inbound webhook not found" });
+                }
+                Ok(MInboundWebhookSyscallRet::Ack {})
+            }
+            Self::RotateSecret { guild_id, webhook_id } => {
+                let Some(secret) = db.rotate_secret(guild_id, &webhook_id).await? else {
+                    return Err(MSyscallError::EntityNotFound { reason: "Inbound webhook not found" });
+                };
+                Ok(MInboundWebhookSyscallRet::Secret { secret })
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use dapi::{GuildId, UserId};
+use dapi::types::PartialGuild;
+use serde::{Deserialize, Serialize};
+use crate::geese::invites::{InviteAttribution, InviteStats};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+
+/// Maximum number of joins returned by `List`
+const MAX_LISTED_JOINS: i64 = 100;
+
+/// Maximum number of invites returned by `Stats`
+const MAX_LISTED_INVITES: i64 = 100;
+
+/// Querying of the invites members joined a guild through
+///
+/// Only the guild owner may query the joins of a guild outside of secure contexts. Joins are only recorded while
+/// invite tracking is enabled for the guild (see `StateOp::SetInviteTracking`)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MInviteSyscall {
+    /// Lists the most recent joins of a guild along with the invite they were attributed to
+    List {
+        guild_id: GuildId,
+        /// Only list the join of this member
+        #[serde(default)]
+        user_id: Option<UserId>,
+        /// Only list joins through this invite (or vanity) code
+        #[serde(default)]
+        code: Option<String>,
+    },
+    /// Returns the number of joins of a guild per invite
+    Stats {
+        guild_id: GuildId,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MInviteSyscallRet {
+    Joins {
+        joins: Vec<InviteAttribution>
+    },
+    Stats {
+        invites: Vec<InviteStats>
+    },
+}
+
+impl MInviteSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MInviteSyscallRet, MSyscallError> {
+        let guild_id = match self {
+            Self::List { guild_id, .. } | Self::Stats { guild_id } => guild_id,
+        };
+        if !ctx.is_secure() {
+            let user_id = ctx.into_user_id()?;
+            handler.limit(&ctx, "Invites")?;
+            let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+            };
+
+            let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+            if guild.owner_id != user_id {
+                return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can query invite tracking" });
+            }
+        }
+
+        match self {
+            Self::List { guild_id, user_id, code } => {
+                Ok(MInviteSyscallRet::Joins { joins: handler.invite_db.list(guild_id, user_id, code.as_deref(), MAX_LISTED_JOINS).await? })
+            }
+            Self::Stats { guild_id } => {
+                Ok(MInviteSyscallRet::Stats { invites: handler.invite_db.stats(guild_id, MAX_LISTED_INVITES).await? })
+            }
+        }
+    }
+}
@@ -3,7 +3,23 @@ pub mod discord;
 pub mod types;
 pub mod bot;
 pub mod gkv;
+pub mod featureflags;
+pub mod routing;
+pub mod data;
 pub mod webapi;
+pub mod modimport;
+pub mod inboundwebhooks;
+pub mod workflows;
+pub mod appeals;
+pub mod altdetect;
+pub mod invites;
+pub mod permsnapshots;
+pub mod backups;
+pub mod schedules;
+pub mod emojiusage;
+pub mod activity;
+pub mod templateenv;
+pub mod archival;
 pub(super) mod internal;
 
 use std::{sync::Arc, time::Duration};
@@ -13,10 +29,21 @@ use governor::clock::QuantaClock;
 use moka::future::Cache;
 use serde::{Deserialize, Serialize};
 use dapi::{GuildId, UserId};
+use crate::geese::dbpools::PoolKind;
+use crate::geese::dbrouter::DbRouter;
 use crate::geese::ratelimit::Ratelimiter;
+use crate::geese::sharedcache::SharedCache;
 use crate::geese::state::StateDb;
 use crate::geese::tenantstate::TenantStateDb;
-use crate::{geese::stratum::Stratum, master::{syscall::{auth::{AuthError, MAuthSyscall, MAuthSyscallRet}, bot::{MBotSyscall, MBotSyscallRet}, discord::{MDiscordSyscall, MDiscordSyscallRet}, gkv::{MGkvSyscall, MGkvSyscallRet}, types::bot::BotStatus}, workerpool::WorkerPool}};
+use crate::geese::votes::VoteDb;
+use crate::geese::inboundwebhooks::InboundWebhookDb;
+use crate::geese::workflows::WorkflowDb;
+use crate::geese::schedules::ScheduleDb;
+use crate::geese::appeals::AppealDb;
+use crate::geese::botstatus::DispatchLatency;
+use crate::geese::invites::InviteDb;
+use crate::master::modimport::ModImporter;
+use crate::{geese::stratum::Stratum, master::{syscall::{auth::{AuthError, MAuthSyscall, MAuthSyscallRet}, bot::{MBotSyscall, MBotSyscallRet}, discord::{MDiscordSyscall, MDiscordSyscallRet}, featureflags::{MFeatureFlagSyscall, MFeatureFlagSyscallRet}, routing::{MRoutingSyscall, MRoutingSyscallRet}, data::{MDataSyscall, MDataSyscallRet}, modimport::{MModImportSyscall, MModImportSyscallRet}, inboundwebhooks::{MInboundWebhookSyscall, MInboundWebhookSyscallRet}, workflows::{MWorkflowSyscall, MWorkflowSyscallRet}, appeals::{MAppealSyscall, MAppealSyscallRet}, altdetect::{MAltDetectSyscall, MAltDetectSyscallRet}, invites::{MInviteSyscall, MInviteSyscallRet}, permsnapshots::{MPermissionSnapshotSyscall, MPermissionSnapshotSyscallRet}, backups::{MBackupSyscall, MBackupSyscallRet}, schedules::{MScheduleSyscall, MScheduleSyscallRet}, emojiusage::{MEmojiUsageSyscall, MEmojiUsageSyscallRet}, activity::{MActivitySyscall, MActivitySyscallRet}, templateenv::{MTemplateEnvSyscall, MTemplateEnvSyscallRet}, archival::{MArchivalSyscall, MArchivalSyscallRet}, gkv::{MGkvSyscall, MGkvSyscallRet}, types::bot::BotStatus}, workerpool::WorkerPool}};
 
 /// The context in which the syscall is executing in
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -96,6 +123,70 @@ pub enum MSyscallArgs {
     /// A global-kv specific syscall
     Gkv {
         req: MGkvSyscall
+    },
+    /// A feature flag specific syscall
+    FeatureFlags {
+        req: MFeatureFlagSyscall
+    },
+    /// A tenant routing specific syscall
+    Routing {
+        req: MRoutingSyscall
+    },
+    /// A tenant data deletion/export specific syscall
+    Data {
+        req: MDataSyscall
+    },
+    /// A sting import specific syscall
+    ModImport {
+        req: MModImportSyscall
+    },
+    /// A inbound webhook specific syscall
+    InboundWebhooks {
+        req: MInboundWebhookSyscall
+    },
+    /// A workflow instance specific syscall
+    Workflows {
+        req: MWorkflowSyscall
+    },
+    /// An appeal specific syscall
+    Appeals {
+        req: MAppealSyscall
+    },
+    /// An alt detection specific syscall
+    AltDetect {
+        req: MAltDetectSyscall
+    },
+    /// An invite tracking specific syscall
+    Invites {
+        req: MInviteSyscall
+    },
+    /// A permission snapshot specific syscall
+    PermissionSnapshots {
+        req: MPermissionSnapshotSyscall
+    },
+    /// A guild backup specific syscall
+    Backups {
+        req: MBackupSyscall
+    },
+    /// A scheduled message specific syscall
+    Schedules {
+        req: MScheduleSyscall
+    },
+    /// An emoji and sticker usage specific syscall
+    EmojiUsage {
+        req: MEmojiUsageSyscall
+    },
+    /// A message activity specific syscall
+    Activity {
+        req: MActivitySyscall
+    },
+    /// A template environment variable specific syscall
+    TemplateEnv {
+        req: MTemplateEnvSyscall
+    },
+    /// A cold storage archival specific syscall
+    Archival {
+        req: MArchivalSyscall
     }
 }
 
@@ -113,6 +204,54 @@ pub enum MSyscallRet {
     },
     Gkv {
         data: MGkvSyscallRet
+    },
+    FeatureFlags {
+        data: MFeatureFlagSyscallRet
+    },
+    Routing {
+        data: MRoutingSyscallRet
+    },
+    Data {
+        data: MDataSyscallRet
+    },
+    ModImport {
+        data: MModImportSyscallRet
+    },
+    InboundWebhooks {
+        data: MInboundWebhookSyscallRet
+    },
+    Workflows {
+        data: MWorkflowSyscallRet
+    },
+    Appeals {
+        data: MAppealSyscallRet
+    },
+    AltDetect {
+        data: MAltDetectSyscallRet
+    },
+    Invites {
+        data: MInviteSyscallRet
+    },
+    PermissionSnapshots {
+        data: MPermissionSnapshotSyscallRet
+    },
+    Backups {
+        data: MBackupSyscallRet
+    },
+    Schedules {
+        data: MScheduleSyscallRet
+    },
+    EmojiUsage {
+        data: MEmojiUsageSyscallRet
+    },
+    Activity {
+        data: MActivitySyscallRet
+    },
+    TemplateEnv {
+        data: MTemplateEnvSyscallRet
+    },
+    Archival {
+        data: MArchivalSyscallRet
     }
 }
 
@@ -159,13 +298,23 @@ pub struct MSyscallHandler {
     pub(super) worker_pool: Arc<WorkerPool>,
     pub(super) stratum: Stratum,
     pub(super) pool: sqlx::PgPool,
-    pub(super) bot_has_guild_cache: Cache<GuildId, bool>,
-    pub(super) guild_members_cache: Cache<(GuildId, UserId), Option<Member>>,
-    pub(super) oauth2_code_cache: Cache<String, ()>,
+    /// Caches shared across hosts (bot guild membership, guild members, used OAuth2 codes)
+    pub(super) shared_cache: Arc<dyn SharedCache>,
     pub(super) user_rl: Arc<Ratelimiter<UserId>>,
     pub(super) status_cache: Cache<(), BotStatus>,
+    /// Latency of the recent dispatches of all workers, for the status page
+    pub(super) dispatch_latency_cache: Cache<(), DispatchLatency>,
     pub(super) tsdb: TenantStateDb,
     pub(super) statedb: StateDb,
+    pub(super) importer: ModImporter,
+    pub(super) vote_db: VoteDb,
+    pub(super) inbound_webhook_db: InboundWebhookDb,
+    pub(super) workflow_db: WorkflowDb,
+    pub(super) schedule_db: ScheduleDb,
+    pub(super) appeal_db: AppealDb,
+    pub(super) invite_db: InviteDb,
+    pub(super) guild_rl: Arc<Ratelimiter<GuildId>>,
+    pub(super) db: DbRouter,
 }
 
 impl MSyscallHandler {
@@ -174,20 +323,29 @@ impl MSyscallHandler {
         worker_pool: Arc<WorkerPool>,
         stratum: Stratum,
         reqwest: reqwest::Client,
-        pool: sqlx::PgPool,
+        db: DbRouter,
     ) -> Self {
+        let pool = db.primary().clone();
         Self { 
             pool: pool.clone(), 
             reqwest,
             stratum,
+            importer: ModImporter::new(worker_pool.clone()),
             worker_pool,
-            bot_has_guild_cache: Cache::builder().time_to_idle(Duration::from_secs(60)).build(),
-            guild_members_cache: Cache::builder().time_to_idle(Duration::from_mins(5)).build(),
-            oauth2_code_cache: Cache::builder().time_to_live(Duration::from_secs(60 * 10)).build(),
+            shared_cache: <dyn SharedCache>::global(),
             user_rl: Self::user_limits().expect("Failed to build user limits").into(),
             status_cache: Cache::builder().time_to_live(Duration::from_secs(100)).build(),
-            tsdb: TenantStateDb::new(pool.clone()),
-            statedb: StateDb::new(pool),
+            dispatch_latency_cache: Cache::builder().time_to_live(Duration::from_secs(30)).build(),
+            tsdb: TenantStateDb::new(db.pool(PoolKind::Settings).pool().clone()),
+            vote_db: VoteDb::new(pool.clone()),
+            inbound_webhook_db: InboundWebhookDb::new(pool.clone()),
+            workflow_db: WorkflowDb::new(pool.clone()),
+            schedule_db: ScheduleDb::new(pool.clone()),
+            appeal_db: AppealDb::new(pool.clone()),
+            invite_db: InviteDb::new(pool.clone()),
+            guild_rl: Self::guild_limits().expect("Failed to build guild limits").into(),
+            statedb: StateDb::new(db.clone()),
+            db,
         }
     }
 
@@ -202,10 +360,61 @@ impl MSyscallHandler {
         // GetGuildInfo
         let ggi1 = Ratelimiter::limit(2, Duration::from_secs(5));
 
+        // GetTemplateUsage
+        let gtu1 = Ratelimiter::limit(3, Duration::from_secs(10));
+
+        // DataLifecycle
+        let dl1 = Ratelimiter::limit(3, Duration::from_secs(10));
+        let dl2 = Ratelimiter::limit(20, Duration::from_mins(10));
+
         // SearchGuildMembers
         let sgm1 = Ratelimiter::limit(1, Duration::from_secs(4));
         let sgm2 = Ratelimiter::limit(5, Duration::from_mins(1));
 
+        // ModImport
+        let mi1 = Ratelimiter::limit(3, Duration::from_secs(10));
+        let mi2 = Ratelimiter::limit(10, Duration::from_mins(10));
+
+        // InboundWebhooks
+        let iw1 = Ratelimiter::limit(5, Duration::from_secs(10));
+
+        // Workflows
+        let wf1 = Ratelimiter::limit(5, Duration::from_secs(10));
+
+        // Appeals
+        let ap1 = Ratelimiter::limit(5, Duration::from_secs(10));
+
+        // AppealSubmit
+        let aps1 = Ratelimiter::limit(1, Duration::from_secs(10));
+        let aps2 = Ratelimiter::limit(5, Duration::from_hours(1));
+
+        // AltDetect
+        let ad1 = Ratelimiter::limit(5, Duration::from_secs(10));
+
+        // Invites
+        let inv1 = Ratelimiter::limit(5, Duration::from_secs(10));
+
+        // PermissionSnapshots
+        let ps1 = Ratelimiter::limit(2, Duration::from_secs(60));
+
+        // Backups
+        let bk1 = Ratelimiter::limit(3, Duration::from_secs(60));
+
+        // Schedules
+        let sc1 = Ratelimiter::limit(5, Duration::from_secs(10));
+
+        // EmojiUsage
+        let eu1 = Ratelimiter::limit(3, Duration::from_secs(10));
+
+        // Activity
+        let ac1 = Ratelimiter::limit(3, Duration::from_secs(10));
+
+        // TemplateEnv
+        let te1 = Ratelimiter::limit(5, Duration::from_secs(10));
+
+        // GetStatusPage
+        let gsp1 = Ratelimiter::limit(5, Duration::from_secs(10));
+
         // Create the clock
         let clock = QuantaClock::default();
 
@@ -214,15 +423,46 @@ impl MSyscallHandler {
             per_bucket: indexmap::indexmap!(
                 "GetUserGuilds__Refresh" => vec![gug_refresh1],
                 "GetGuildInfo" => vec![ggi1],
-                "SearchGuildMembers" => vec![sgm1, sgm2]
+                "GetTemplateUsage" => vec![gtu1],
+                "DataLifecycle" => vec![dl1, dl2],
+                "SearchGuildMembers" => vec![sgm1, sgm2],
+                "ModImport" => vec![mi1, mi2],
+                "InboundWebhooks" => vec![iw1],
+                "Workflows" => vec![wf1],
+                "Appeals" => vec![ap1],
+                "AppealSubmit" => vec![aps1, aps2],
+                "AltDetect" => vec![ad1],
+                "Invites" => vec![inv1],
+                "PermissionSnapshots" => vec![ps1],
+                "Backups" => vec![bk1],
+                "Schedules" => vec![sc1],
+                "EmojiUsage" => vec![eu1],
+                "Activity" => vec![ac1],
+                "TemplateEnv" => vec![te1],
+                "GetStatusPage" => vec![gsp1]
             ),
             clock,
         })
     }
 
+    /// Helper method to return per-guild ratelimits (for requests not made by a user)
+    fn guild_limits() -> Result<Ratelimiter<GuildId>, crate::Error> {
+        // Calls of inbound webhooks
+        let iwc1 = Ratelimiter::limit(10, Duration::from_secs(10));
+        let iwc2 = Ratelimiter::limit(300, Duration::from_hours(1));
+
+        Ok(Ratelimiter {
+            global: vec![],
+            per_bucket: indexmap::indexmap!(
+                "InboundWebhookCall" => vec![iwc1, iwc2]
+            ),
+            clock: QuantaClock::default(),
+        })
+    }
+
     /// Helper function to check if the bot is in a single guild
     async fn has_bot_single(&self, guild: GuildId) -> Result<bool, MSyscallError> {
-        let hb = self.bot_has_guild_cache.try_get_with::<_, crate::Error>(guild, async move {
+        let hb = self.shared_cache.get_or_try_insert(&format!("bot_has_guild:{guild}"), Duration::from_secs(60), async move {
             self.stratum.has_guild(guild).await
         })
         .await?;
@@ -232,12 +472,7 @@ impl MSyscallHandler {
     /// Helper function to check if the bot is in a list of guilds
     async fn has_bot(&self, guilds: &[GuildId]) -> Result<Vec<bool>, MSyscallError> {
         if guilds.len() == 1 {
-            let hb = self.bot_has_guild_cache.try_get_with::<_, crate::Error>(guilds[0], async move {
-                self.stratum.has_guild(guilds[0]).await
-            })
-            .await?;
-
-            return Ok(vec![hb])
+            return Ok(vec![self.has_bot_single(guilds[0]).await?])
         };
         let guild_exists = self.stratum.has_guilds(guilds).await?;
         if guild_exists.len() != guilds.len() {
@@ -249,7 +484,7 @@ impl MSyscallHandler {
 
     /// Helper method to get user guild member with caching
     async fn guild_member(&self, guild_id: GuildId, user_id: UserId) -> Result<Option<Member>, MSyscallError> {
-        let hb = self.guild_members_cache.try_get_with::<_, crate::Error>((guild_id, user_id), async move {
+        let hb = self.shared_cache.get_or_try_insert(&format!("guild_member:{guild_id}:{user_id}"), Duration::from_mins(5), async move {
             let mem_v = self.stratum.guild_member(guild_id, user_id).await?;
             if let Some(mem_v) = mem_v {
                 let v = serde_json::from_value(mem_v)?;
@@ -303,6 +538,54 @@ impl MSyscallHandler {
             MSyscallArgs::Gkv { req } => {
                 Ok(MSyscallRet::Gkv { data: req.exec(self, ctx).await? })
             }
+            MSyscallArgs::FeatureFlags { req } => {
+                Ok(MSyscallRet::FeatureFlags { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Routing { req } => {
+                Ok(MSyscallRet::Routing { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Data { req } => {
+                Ok(MSyscallRet::Data { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::ModImport { req } => {
+                Ok(MSyscallRet::ModImport { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::InboundWebhooks { req } => {
+                Ok(MSyscallRet::InboundWebhooks { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Workflows { req } => {
+                Ok(MSyscallRet::Workflows { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Appeals { req } => {
+                Ok(MSyscallRet::Appeals { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::AltDetect { req } => {
+                Ok(MSyscallRet::AltDetect { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Invites { req } => {
+                Ok(MSyscallRet::Invites { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::PermissionSnapshots { req } => {
+                Ok(MSyscallRet::PermissionSnapshots { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Backups { req } => {
+                Ok(MSyscallRet::Backups { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Schedules { req } => {
+                Ok(MSyscallRet::Schedules { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::EmojiUsage { req } => {
+                Ok(MSyscallRet::EmojiUsage { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Activity { req } => {
+                Ok(MSyscallRet::Activity { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::TemplateEnv { req } => {
+                Ok(MSyscallRet::TemplateEnv { data: req.exec(self, ctx).await? })
+            }
+            MSyscallArgs::Archival { req } => {
+                Ok(MSyscallRet::Archival { data: req.exec(self, ctx).await? })
+            }
         }
     }
 }
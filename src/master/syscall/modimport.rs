@@ -0,0 +1,89 @@
+use dapi::types::PartialGuild;
+use serde::{Deserialize, Serialize};
+use crate::geese::modimport::{self, ImportFormat, ImportOptions, ImportPreview};
+use crate::master::modimport::ImportJob;
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::workervmmanager::Id;
+
+/// Import of the stings (warns, mutes, kicks and bans) of a guild from the moderation export of another bot
+///
+/// Only the guild owner may import stings outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MModImportSyscall {
+    /// Parses an export and returns what would be imported, without importing anything
+    Preview {
+        id: Id,
+        format: ImportFormat,
+        content: String,
+        #[serde(default)]
+        options: ImportOptions,
+    },
+    /// Starts importing the stings of an export in the background
+    Start {
+        id: Id,
+        format: ImportFormat,
+        content: String,
+        #[serde(default)]
+        options: ImportOptions,
+    },
+    /// Returns the progress of the most recent import of a guild
+    Status {
+        id: Id
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MModImportSyscallRet {
+    Preview {
+        preview: ImportPreview
+    },
+    Job {
+        job: Option<ImportJob>
+    },
+}
+
+impl MModImportSyscall {
+    fn id(&self) -> Id {
+        match self {
+            Self::Preview { id, .. }
+            | Self::Start { id, .. }
+            | Self::Status { id } => *id,
+        }
+    }
+
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MModImportSyscallRet, MSyscallError> {
+        let Id::Guild(guild_id) = self.id() else {
+            return Err(MSyscallError::Generic { message: "Stings can only be imported into guilds".to_string() });
+        };
+
+        if !ctx.is_secure() {
+            let user_id = ctx.into_user_id()?;
+            handler.limit(&ctx, "ModImport")?;
+            let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+            };
+
+            let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+            if guild.owner_id != user_id {
+                return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can import stings" });
+            }
+        }
+
+        match self {
+            Self::Preview { format, content, options, .. } => {
+                let parse = modimport::parse(format, &content, &options)?;
+                Ok(MModImportSyscallRet::Preview { preview: parse.preview() })
+            }
+            Self::Start { id, format, content, options } => {
+                let parse = modimport::parse(format, &content, &options)?;
+                let job = handler.importer.start(id, ctx.into_user_id().ok(), parse)?;
+                Ok(MModImportSyscallRet::Job { job: Some(job) })
+            }
+            Self::Status { id } => {
+                Ok(MModImportSyscallRet::Job { job: handler.importer.job(id) })
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use dapi::GuildId;
+use dapi::types::PartialGuild;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::{Deserialize, Serialize};
+use crate::geese::permsnapshots::PermissionSnapshotTrigger;
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::permsnapshots::PERMISSION_SNAPSHOT_EVENT;
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// On demand permission snapshots of a guild
+///
+/// Only the guild owner may snapshot the permissions of a guild outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MPermissionSnapshotSyscall {
+    /// Snapshots the permissions of a guild now, returning (and firing `PermissionDrift` with) the changes since
+    /// the last snapshot
+    Take {
+        guild_id: GuildId,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MPermissionSnapshotSyscallRet {
+    Drift {
+        drift: KhronosValue
+    },
+}
+
+impl MPermissionSnapshotSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MPermissionSnapshotSyscallRet, MSyscallError> {
+        match self {
+            Self::Take { guild_id } => {
+                if !ctx.is_secure() {
+                    let owner_id = ctx.into_user_id()?;
+                    handler.limit(&ctx, "PermissionSnapshots")?;
+                    let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                        return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+                    };
+
+                    let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+                    if guild.owner_id != owner_id {
+                        return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can snapshot permissions" });
+                    }
+                }
+
+                let req = PermissionSnapshotTrigger { manual: true };
+                let event = SimpleEvent::new_json_string(PERMISSION_SNAPSHOT_EVENT.to_string(), None, serde_json::to_string(&req)?);
+                let drift = handler.worker_pool.dispatch_event(Id::Guild(guild_id), event).await?;
+                Ok(MPermissionSnapshotSyscallRet::Drift { drift })
+            }
+        }
+    }
+}
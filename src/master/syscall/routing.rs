@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::mesophyll::router::RebalanceResult;
+use crate::worker::workervmmanager::Id;
+
+/// Tenant routing management (works in secure contexts only)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MRoutingSyscall {
+    /// Returns the workers currently on the hash ring and any tenants pinned to a worker
+    GetRouting {},
+    /// Returns the worker a tenant is routed to
+    GetTenantWorker {
+        id: Id
+    },
+    /// Moves the hash ring to the given set of workers, gracefully handing off all tenants that change worker
+    ///
+    /// Only the tenants of added/removed workers are moved. The ring is reset to all workers on restart
+    /// and, if autoscaling is enabled, may be changed again by the autoscaler once its cooldown has passed
+    Rebalance {
+        workers: Vec<usize>
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MRoutingSyscallRet {
+    Routing {
+        pool_size: usize,
+        workers: Vec<usize>,
+        pinned: Vec<(Id, usize)>,
+    },
+    TenantWorker {
+        worker_id: usize
+    },
+    Rebalanced {
+        result: RebalanceResult
+    },
+}
+
+impl MRoutingSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MRoutingSyscallRet, MSyscallError> {
+        if !ctx.is_secure() {
+            return Err(MSyscallError::ContextInsecure);
+        }
+
+        let router = handler.worker_pool.mesophyll().router();
+        match self {
+            Self::GetRouting {} => {
+                Ok(MRoutingSyscallRet::Routing {
+                    pool_size: handler.worker_pool.pool_size(),
+                    workers: router.ring().workers().to_vec(),
+                    pinned: router.pinned(),
+                })
+            }
+            Self::GetTenantWorker { id } => {
+                Ok(MRoutingSyscallRet::TenantWorker { worker_id: router.worker_for(id)? })
+            }
+            Self::Rebalance { workers } => {
+                let tenant_states = handler.tsdb.get_tenant_state().await?;
+                let result = handler.worker_pool.rebalance(&workers, tenant_states).await?;
+                log::info!("Rebalanced tenants onto workers {workers:?}: {} moved, {} failed", result.moved, result.failed.len());
+                Ok(MRoutingSyscallRet::Rebalanced { result })
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use dapi::GuildId;
+use dapi::types::PartialGuild;
+use serde::{Deserialize, Serialize};
+use crate::geese::schedules::{ScheduleSpec, ScheduledMessage};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+
+/// Management of the scheduled messages (recurring announcements) of a guild
+///
+/// Only the guild owner may manage scheduled messages outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MScheduleSyscall {
+    /// Lists the scheduled messages of a guild
+    List {
+        guild_id: GuildId,
+    },
+    /// Creates or replaces a scheduled message of a guild, keeping it paused if it was
+    Set {
+        guild_id: GuildId,
+        name: String,
+        schedule: ScheduleSpec,
+    },
+    /// Deletes a scheduled message of a guild
+    Delete {
+        guild_id: GuildId,
+        name: String,
+    },
+    /// Pauses a scheduled message of a guild, nothing is posted until it is resumed
+    Pause {
+        guild_id: GuildId,
+        name: String,
+    },
+    /// Resumes a paused scheduled message of a guild from its next run, runs missed while paused are not posted
+    Resume {
+        guild_id: GuildId,
+        name: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MScheduleSyscallRet {
+    Schedules {
+        schedules: Vec<ScheduledMessage>
+    },
+    Schedule {
+        schedule: ScheduledMessage
+    },
+    Ack {},
+}
+
+impl MScheduleSyscall {
+    fn guild_id(&self) -> GuildId {
+        match self {
+            Self::List { guild_id }
+            | Self::Set { guild_id, .. }
+            | Self::Delete { guild_id, .. }
+            | Self::Pause { guild_id, .. }
+            | Self::Resume { guild_id, .. } => *guild_id,
+        }
+    }
+
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MScheduleSyscallRet, MSyscallError> {
+        let guild_id = self.guild_id();
+        if !ctx.is_secure() {
+            let owner_id = ctx.into_user_id()?;
+            handler.limit(&ctx, "Schedules")?;
+            let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+            };
+
+            let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+            if guild.owner_id != owner_id {
+                return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can manage scheduled messages" });
+            }
+        }
+
+        let db = &handler.schedule_db;
+        let guild_id = guild_id.to_string();
+        match self {
+            Self::List { .. } => {
+                Ok(MScheduleSyscallRet::Schedules { schedules: db.list(&guild_id).await? })
+            }
+            Self::Set { name, schedule, .. } => {
+                Ok(MScheduleSyscallRet::Schedule { schedule: db.set(&guild_id, &name, &schedule).await? })
+            }
+            Self::Delete { name, .. } => {
+                if !db.delete(&guild_id, &name).await? {
+                    return Err(MSyscallError::EntityNotFound { reason: "Scheduled message not found" });
+                }
+                Ok(MScheduleSyscallRet::Ack {})
+            }
+            Self::Pause { name, .. } => {
+                if !db.set_paused(&guild_id, &name, true).await? {
+                    return Err(MSyscallError::EntityNotFound { reason: "No running scheduled message to pause" });
+                }
+                Ok(MScheduleSyscallRet::Ack {})
+            }
+            Self::Resume { name, .. } => {
+                if !db.set_paused(&guild_id, &name, false).await? {
+                    return Err(MSyscallError::EntityNotFound { reason: "No paused scheduled message to resume" });
+                }
+                Ok(MScheduleSyscallRet::Ack {})
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use dapi::GuildId;
+use dapi::types::PartialGuild;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::{Deserialize, Serialize};
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// Internal event dispatched to the builtins of a guild to read or set the environment variables of its templates
+pub const TEMPLATE_ENV_EVENT: &str = "$TemplateEnv";
+
+/// Environment variables of the templates of a guild (declared by templates in `pragma.env`), exposed to templates as
+/// `ctx.env` so they don't have to hardcode channel, role and user IDs
+///
+/// Variables are read and set by the builtins of the guild, which check channel, role and user values exist in the
+/// guild. Only the guild owner may manage them outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MTemplateEnvSyscall {
+    /// Returns the variables a template declares, the values set for them and the required variables still unset
+    /// (templates don't run until all are set)
+    Get {
+        guild_id: GuildId,
+        template: String,
+    },
+    /// Sets the values of the variables of a template, replacing all previously set values
+    Set {
+        guild_id: GuildId,
+        template: String,
+        values: HashMap<String, serde_json::Value>,
+    },
+    /// Returns the variables a shop template declares, to ask for their values when installing it into a guild
+    Shop {
+        guild_id: GuildId,
+        key: String,
+        version: i32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MTemplateEnvSyscallRet {
+    Env {
+        env: KhronosValue
+    },
+}
+
+/// Shop template of `$TemplateEnv`
+#[derive(Serialize)]
+struct ShopTemplateRef {
+    key: String,
+    version: i32,
+}
+
+/// Data of `$TemplateEnv`
+#[derive(Serialize)]
+#[serde(tag = "op")]
+enum TemplateEnvEvent {
+    Get {
+        template: String,
+    },
+    Set {
+        template: String,
+        values: HashMap<String, serde_json::Value>,
+    },
+    Shop {
+        base: ShopTemplateRef,
+    },
+}
+
+impl MTemplateEnvSyscall {
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MTemplateEnvSyscallRet, MSyscallError> {
+        let guild_id = match self {
+            Self::Get { guild_id, .. } | Self::Set { guild_id, .. } | Self::Shop { guild_id, .. } => guild_id,
+        };
+
+        if !ctx.is_secure() {
+            let owner_id = ctx.into_user_id()?;
+            handler.limit(&ctx, "TemplateEnv")?;
+            let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+            };
+
+            let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+            if guild.owner_id != owner_id {
+                return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can manage template environments" });
+            }
+        }
+
+        let event = match self {
+            Self::Get { template, .. } => TemplateEnvEvent::Get { template },
+            Self::Set { template, values, .. } => TemplateEnvEvent::Set { template, values },
+            Self::Shop { key, version, .. } => TemplateEnvEvent::Shop { base: ShopTemplateRef { key, version } },
+        };
+        let event = SimpleEvent::new_json_string(TEMPLATE_ENV_EVENT.to_string(), None, serde_json::to_string(&event)?);
+        let env = handler.worker_pool.dispatch_event(Id::Guild(guild_id), event).await?;
+        Ok(MTemplateEnvSyscallRet::Env { env })
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::geese::{botstatus::DispatchLatency, gateway::GatewayStatus};
+
 /// A shard connection (for bot statistics)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShardConn {
@@ -23,3 +25,26 @@ pub struct BotStatus {
     /// The current uptime of the bot process in seconds
     pub uptime: u64,
 }
+
+/// An error of the templates of a guild
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentError {
+    pub error: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Data of the status page of the website
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusPage {
+    /// Whether the bot is degraded, templates may be slow to respond or miss events while it is
+    pub degraded: bool,
+    /// Why the bot is degraded, empty if it isn't
+    pub degraded_reasons: Vec<String>,
+    /// A map of shard group ID to shard connection information
+    pub shard_conns: HashMap<u32, ShardConn>,
+    pub gateway: GatewayStatus,
+    /// Latency of the recent dispatches of all workers
+    pub dispatch: DispatchLatency,
+    /// The most recent errors of the templates of the guild (newest first), empty if no guild was requested
+    pub recent_errors: Vec<RecentError>,
+}
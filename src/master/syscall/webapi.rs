@@ -5,17 +5,32 @@ use axum::extract::ws::{CloseFrame, Message, Utf8Bytes, WebSocket};
 use axum::response::Response;
 use axum::routing::{get, post};
 use axum::{extract::{State, FromRequestParts, Json}, Router, response::IntoResponse};
-use dapi::UserId;
+use dapi::{GuildId, UserId};
 use khronos_runtime::futures_util::{SinkExt, StreamExt};
 use khronos_runtime::utils::khronos_value::CKhronosValue;
 use reqwest::{StatusCode, header};
 use reqwest::header::AUTHORIZATION;
 use serde::{Deserialize, Serialize};
 use tower_http::cors::MaxAge;
+use crate::geese::dbpools::PoolStatus;
 use crate::geese::feedticket::FeedTicket;
+use crate::geese::gateway::GatewayStatus;
+use crate::geese::votes::{self, Vote, VoteReceived, VOTE_RECEIVED_EVENT};
+use crate::geese::inboundwebhooks::{self, MAX_INBOUND_WEBHOOK_BODY_SIZE, SIGNED_WEBHOOK_EVENT, SignedWebhook};
 use crate::master::syscall::bot::{MBotSyscall, MBotSyscallRet};
 use crate::master::syscall::{MSyscallArgs, MSyscallContext, MSyscallRet};
 use crate::master::syscall::{MSyscallError, MSyscallHandler, internal::auth as iauth};
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+use crate::CONFIG;
+
+/// Response of the health check endpoint
+#[derive(Serialize)]
+struct HealthStatus {
+    gateway: GatewayStatus,
+    /// Status of the database pools, `saturated` ones are making their subsystem wait for connections
+    db: Vec<PoolStatus>,
+}
 
 impl IntoResponse for MSyscallRet {
     fn into_response(self) -> Response {
@@ -163,6 +178,118 @@ pub fn create(handler: MSyscallHandler) -> axum::routing::IntoMakeService<Router
         }
     }
 
+    async fn vote_webhook(
+        State(handler): State<MSyscallHandler>,
+        axum::extract::Path(list): axum::extract::Path<String>,
+        headers: header::HeaderMap,
+        body: axum::body::Bytes,
+    ) -> Response {
+        let Some(cfg) = CONFIG.votes.as_ref().and_then(|v| v.lists.get(&list)) else {
+            return (StatusCode::NOT_FOUND, "Unknown bot list").into_response();
+        };
+
+        let get_header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+        if !votes::verify(cfg, get_header(AUTHORIZATION.as_str()), get_header(votes::SIGNATURE_HEADER), &body) {
+            return (StatusCode::UNAUTHORIZED, "Invalid vote webhook authorization").into_response();
+        }
+
+        let vote = match Vote::parse(&list, &body) {
+            Ok(vote) => vote,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid vote: {e}")).into_response(),
+        };
+
+        // Bot lists retry webhooks which failed, so only acknowledge votes once they are recorded
+        if let Err(e) = handler.vote_db.record(&vote).await {
+            log::error!("Failed to record vote of {} from {list}: {e}", vote.user_id);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to record vote").into_response();
+        }
+
+        if let Some(guild_id) = vote.guild_id {
+            tokio::spawn(async move {
+                match handler.has_bot_single(guild_id).await {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(e) => {
+                        log::error!("Failed to check if guild {guild_id} has the bot for a vote: {e:?}");
+                        return;
+                    }
+                }
+
+                let since = vote.voted_at - chrono::Duration::days(30);
+                let votes_30d = match handler.vote_db.count(vote.user_id, Some(guild_id), since).await {
+                    Ok(count) => count,
+                    Err(e) => {
+                        log::error!("Failed to count votes of {} for guild {guild_id}: {e}", vote.user_id);
+                        return;
+                    }
+                };
+
+                let author = Some(vote.user_id);
+                let data = match serde_json::to_string(&VoteReceived { vote, votes_30d }) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        log::error!("Failed to serialize vote: {e}");
+                        return;
+                    }
+                };
+                let event = SimpleEvent::new_json_string(VOTE_RECEIVED_EVENT.to_string(), author, data);
+                if let Err(e) = handler.worker_pool.dispatch_event(Id::Guild(guild_id), event).await {
+                    log::warn!("Failed to dispatch {VOTE_RECEIVED_EVENT} to guild {guild_id}: {e}");
+                }
+            });
+        }
+
+        StatusCode::NO_CONTENT.into_response()
+    }
+
+    async fn inbound_webhook(
+        State(handler): State<MSyscallHandler>,
+        axum::extract::Path(id): axum::extract::Path<String>,
+        headers: header::HeaderMap,
+        body: axum::body::Bytes,
+    ) -> Response {
+        let (webhook, secret) = match handler.inbound_webhook_db.get(&id).await {
+            Ok(Some(webhook)) => webhook,
+            Ok(None) => return (StatusCode::NOT_FOUND, "Unknown webhook").into_response(),
+            Err(e) => {
+                log::error!("Failed to get inbound webhook: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get webhook").into_response();
+            }
+        };
+
+        let get_header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok()).unwrap_or_default();
+        if let Err(e) = inboundwebhooks::verify(&secret, get_header("X-AntiRaid-Timestamp"), get_header("X-AntiRaid-Signature"), &body) {
+            return (StatusCode::UNAUTHORIZED, e).into_response();
+        }
+
+        let Ok(guild_id) = webhook.guild_id.parse::<GuildId>() else {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Webhook has an invalid guild").into_response();
+        };
+        if let Err(e) = handler.guild_rl.check("InboundWebhookCall", guild_id) {
+            return MSyscallError::Ratelimited { retry_after: e.dur.as_secs_f32(), bucket: e.bucket, req_bucket: e.req_bucket }.into_response();
+        }
+
+        let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(body) => body,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Body must be JSON: {e}")).into_response(),
+        };
+
+        if let Err(e) = handler.inbound_webhook_db.mark_used(&webhook.id).await {
+            log::warn!("Failed to mark inbound webhook {} used: {e}", webhook.id);
+        }
+
+        let data = SignedWebhook { webhook_id: webhook.id, name: webhook.name, body, received_at: chrono::Utc::now() };
+        let data = match serde_json::to_string(&data) {
+            Ok(data) => data,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize event: {e}")).into_response(),
+        };
+        let event = SimpleEvent::new_json_string(SIGNED_WEBHOOK_EVENT.to_string(), None, data);
+        match handler.worker_pool.dispatch_event(Id::Guild(guild_id), event).await {
+            Ok(_) => StatusCode::NO_CONTENT.into_response(),
+            Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed to dispatch event: {e}")).into_response(),
+        }
+    }
+
     async fn ws(
         ws: WebSocketUpgrade,
         State(state): State<MSyscallHandler>,
@@ -251,10 +378,17 @@ pub fn create(handler: MSyscallHandler) -> axum::routing::IntoMakeService<Router
     let mut router = Router::new();
 
     router = router
-        .route("/healthcheck", post(|| async { Json(()) }))
+        .route("/healthcheck", post(|State(handler): State<MSyscallHandler>| async move {
+            Json(HealthStatus { gateway: handler.stratum.gateway_health().status(), db: handler.db.pools().status() })
+        }))
         .route("/msyscall", post(msyscall))
         .route("/blob", get(get_presigned))
         .route("/ws", get(ws))
+        .route("/webhooks/votes/{list}", post(vote_webhook))
+        .route(
+            "/webhooks/inbound/{id}",
+            post(inbound_webhook).layer(axum::extract::DefaultBodyLimit::max(MAX_INBOUND_WEBHOOK_BODY_SIZE))
+        )
         .fallback(get(|| async {
             (
                 StatusCode::NOT_FOUND,
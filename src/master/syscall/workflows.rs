@@ -0,0 +1,98 @@
+use dapi::types::PartialGuild;
+use serde::{Deserialize, Serialize};
+use crate::geese::workflows::WorkflowInstance;
+use crate::master::syscall::{MSyscallContext, MSyscallError, MSyscallHandler};
+use crate::worker::workervmmanager::Id;
+
+/// Maximum number of workflow instances returned by `List`
+const MAX_LISTED_WORKFLOWS: i64 = 100;
+
+/// Inspection and cancellation of the workflow instances of a tenant
+///
+/// Only the guild owner (or the user themselves) may manage the workflows of a tenant outside of secure contexts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MWorkflowSyscall {
+    /// Lists the most recently started workflow instances of a tenant
+    List {
+        id: Id,
+        /// Only list running instances
+        #[serde(default)]
+        running_only: bool,
+    },
+    /// Returns a workflow instance
+    Get {
+        id: Id,
+        workflow_id: i64
+    },
+    /// Cancels a running workflow instance, none of its remaining steps are run
+    Cancel {
+        id: Id,
+        workflow_id: i64
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum MWorkflowSyscallRet {
+    Workflows {
+        workflows: Vec<WorkflowInstance>
+    },
+    Workflow {
+        workflow: WorkflowInstance
+    },
+    Ack {},
+}
+
+impl MWorkflowSyscall {
+    fn id(&self) -> Id {
+        match self {
+            Self::List { id, .. }
+            | Self::Get { id, .. }
+            | Self::Cancel { id, .. } => *id,
+        }
+    }
+
+    pub(super) async fn exec(self, handler: &MSyscallHandler, ctx: MSyscallContext) -> Result<MWorkflowSyscallRet, MSyscallError> {
+        if !ctx.is_secure() {
+            let user_id = ctx.into_user_id()?;
+            handler.limit(&ctx, "Workflows")?;
+            match self.id() {
+                Id::Guild(guild_id) => {
+                    let Some(guild_json) = handler.stratum.guild(guild_id).await? else {
+                        return Err(MSyscallError::EntityNotFound { reason: "Failed to fetch guild data from stratum" });
+                    };
+
+                    let guild = serde_json::from_value::<PartialGuild>(guild_json)?;
+                    if guild.owner_id != user_id {
+                        return Err(MSyscallError::Unauthorized { reason: "Only the guild owner can manage the workflows of a guild" });
+                    }
+                }
+                Id::User(id) => {
+                    if user_id != id {
+                        return Err(MSyscallError::Unauthorized { reason: "Cannot manage the workflows of users who are not yourself" });
+                    }
+                }
+            }
+        }
+
+        let db = &handler.workflow_db;
+        match self {
+            Self::List { id, running_only } => {
+                Ok(MWorkflowSyscallRet::Workflows { workflows: db.list(id, running_only, MAX_LISTED_WORKFLOWS).await? })
+            }
+            Self::Get { id, workflow_id } => {
+                let Some(workflow) = db.get(id, workflow_id).await? else {
+                    return Err(MSyscallError::EntityNotFound { reason: "Workflow instance not found" });
+                };
+                Ok(MWorkflowSyscallRet::Workflow { workflow })
+            }
+            Self::Cancel { id, workflow_id } => {
+                if !db.cancel(id, workflow_id).await? {
+                    return Err(MSyscallError::EntityNotFound { reason: "No running workflow instance to cancel" });
+                }
+                Ok(MWorkflowSyscallRet::Ack {})
+            }
+        }
+    }
+}
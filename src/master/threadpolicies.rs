@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::geese::threadpolicies::ThreadPolicyDb;
+use crate::master::workerpool::WorkerPool;
+use crate::worker::threadpolicies::THREAD_POLICY_SWEEP_EVENT;
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// How often due sweeps are checked for
+const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How many sweeps are claimed per poll
+const BATCH_SIZE: i64 = 64;
+
+/// How often the threads of a guild are swept
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Has the workers of guilds with thread policies archive their inactive threads (and threads beyond their open
+/// thread limits) every `SWEEP_INTERVAL`
+///
+/// Sweeps are claimed before being run, a sweep which fails is skipped until the next interval
+pub struct ThreadPolicySweeper {
+    db: ThreadPolicyDb,
+    worker_pool: Arc<WorkerPool>,
+}
+
+impl ThreadPolicySweeper {
+    pub fn new(db: ThreadPolicyDb, worker_pool: Arc<WorkerPool>) -> Self {
+        Self { db, worker_pool }
+    }
+
+    /// Spawns the background task running due sweeps
+    pub fn spawn(self) {
+        let this = Arc::new(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                this.tick().await;
+            }
+        });
+    }
+
+    async fn tick(self: &Arc<Self>) {
+        let due = match self.db.claim_due(BATCH_SIZE, SWEEP_INTERVAL).await {
+            Ok(due) => due,
+            Err(e) => {
+                log::error!("Failed to claim due thread policy sweeps: {e}");
+                return;
+            }
+        };
+
+        for guild_id in due {
+            let this = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = this.dispatch(Id::Guild(guild_id)).await {
+                    log::error!("Failed to sweep threads of guild {guild_id}: {e}");
+                }
+            });
+        }
+    }
+
+    async fn dispatch(&self, tenant: Id) -> Result<(), crate::Error> {
+        let event = SimpleEvent::new_json_string(THREAD_POLICY_SWEEP_EVENT.to_string(), None, "{}".to_string());
+        self.worker_pool.dispatch_event(tenant, event).await?;
+        Ok(())
+    }
+}
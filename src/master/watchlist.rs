@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::geese::watchlist::{WatchlistDb, WatchlistDigestReq};
+use crate::master::workerpool::WorkerPool;
+use crate::worker::watchlist::WATCHLIST_DIGEST_EVENT;
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// How often due digests are checked for
+const POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How many digests are claimed per poll
+const BATCH_SIZE: i64 = 64;
+
+/// How often the watchlist digest of a guild is posted
+const DIGEST_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Has the workers of guilds with a watchlist channel post their watchlist digest every `DIGEST_INTERVAL`
+///
+/// Digests are claimed before being posted, a digest which fails to post is skipped until the next interval
+pub struct WatchlistDigester {
+    db: WatchlistDb,
+    worker_pool: Arc<WorkerPool>,
+}
+
+impl WatchlistDigester {
+    pub fn new(db: WatchlistDb, worker_pool: Arc<WorkerPool>) -> Self {
+        Self { db, worker_pool }
+    }
+
+    /// Spawns the background task posting due digests
+    pub fn spawn(self) {
+        let this = Arc::new(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                this.tick().await;
+            }
+        });
+    }
+
+    async fn tick(self: &Arc<Self>) {
+        let due = match self.db.claim_due_digests(BATCH_SIZE, DIGEST_INTERVAL).await {
+            Ok(due) => due,
+            Err(e) => {
+                log::error!("Failed to claim due watchlist digests: {e}");
+                return;
+            }
+        };
+
+        for (guild_id, since) in due {
+            let this = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = this.dispatch(Id::Guild(guild_id), WatchlistDigestReq { since }).await {
+                    log::error!("Failed to post watchlist digest of guild {guild_id}: {e}");
+                }
+            });
+        }
+    }
+
+    async fn dispatch(&self, tenant: Id, req: WatchlistDigestReq) -> Result<(), crate::Error> {
+        let event = SimpleEvent::new_json_string(WATCHLIST_DIGEST_EVENT.to_string(), None, serde_json::to_string(&req)?);
+        self.worker_pool.dispatch_event(tenant, event).await?;
+        Ok(())
+    }
+}
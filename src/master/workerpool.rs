@@ -1,21 +1,30 @@
 use khronos_runtime::utils::khronos_value::KhronosValue;
+use opentelemetry::{Context, KeyValue, trace::{FutureExt, SpanKind, Status, TraceContextExt}};
 use tokio::time::sleep;
 
 
+use crate::geese::telemetry;
 use crate::geese::tenantstate::TenantState;
 use crate::master::workerprocesshandle::{ExpBackoff, WorkerProcessHandle};
 use crate::mesophyll::connman::SockFile;
-use crate::mesophyll::server::{TopicGuard, MesophyllServer};
+use crate::mesophyll::router::RebalanceResult;
+use crate::mesophyll::server::{TopicGuard, MesophyllServer, WorkerConn};
+use crate::worker::shutdown::DrainReport;
 use crate::worker::workerdispatch::SimpleEvent;
 use crate::worker::workervmmanager::Id;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use parking_lot::{Mutex, RwLock};
 use tokio::sync::{mpsc, oneshot};
 
+/// How long workers are given to finish running executions on shutdown
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
-/// A WorkerPool stores a pool of workers in which servers are evenly distributed via
-/// the Discord Id sharding formula
+/// A WorkerPool stores a pool of workers in which servers are distributed via
+/// consistent hashing (see `Router`)
 pub struct WorkerPool {
     /// Pool size
     pool_size: usize,
@@ -136,9 +145,42 @@ impl WorkerPool {
         }
     }
 
-    /// Explicitly kills all workers with open kill switches
+    /// Gracefully shuts down all workers, draining them before killing all workers with open kill switches
+    ///
+    /// New dispatches are rejected once shutdown starts
     pub async fn shutdown_all(&self) -> Result<(), crate::Error> {
         self.is_shutting_down.store(true, Ordering::SeqCst);
+
+        let mut total = DrainReport::default();
+        let mut failed = Vec::new();
+        for (worker_id, res) in self.drain_all().await {
+            match res {
+                Ok(report) => {
+                    log::info!("Drained worker {worker_id}: {report:?}");
+                    total.completed += report.completed;
+                    total.abandoned += report.abandoned;
+                    total.usage_flushed += report.usage_flushed;
+                    total.usage_abandoned += report.usage_abandoned;
+                    total.plugin_usage_abandoned += report.plugin_usage_abandoned;
+                    total.emoji_usage_abandoned += report.emoji_usage_abandoned;
+                    total.activity_abandoned += report.activity_abandoned;
+                }
+                Err(e) => {
+                    log::error!("Failed to drain worker {worker_id}: {e}");
+                    failed.push(worker_id);
+                }
+            }
+        }
+
+        if total.abandoned > 0 || total.usage_abandoned > 0 || total.plugin_usage_abandoned > 0 || total.emoji_usage_abandoned > 0 || total.activity_abandoned > 0 || !failed.is_empty() {
+            log::warn!(
+                "Shutting down with work abandoned: {} executions, {} usage records, {} plugin usage records, {} emoji usage records, {} activity records, undrained workers {failed:?}",
+                total.abandoned, total.usage_abandoned, total.plugin_usage_abandoned, total.emoji_usage_abandoned, total.activity_abandoned
+            );
+        } else {
+            log::info!("Drained all workers: {} executions finished, {} usage records flushed", total.completed, total.usage_flushed);
+        }
+
         let mut kills_guard = self.kill_switches.lock();
         for kill_opt in kills_guard.iter_mut() {
             if let Some(tx) = kill_opt.take() {
@@ -146,39 +188,88 @@ impl WorkerPool {
             }
         }
 
-        sleep(std::time::Duration::from_secs(5)).await; // wait for workers to shut down. TODO: make this more robust by tracking worker shutdowns in the supervisor loop
+        sleep(Duration::from_secs(5)).await; // wait for workers to shut down. TODO: make this more robust by tracking worker shutdowns in the supervisor loop
 
         Ok(())
     }
 
+    /// Drains all connected workers concurrently, returning the result for each worker
+    async fn drain_all(&self) -> Vec<(usize, Result<DrainReport, crate::Error>)> {
+        let mut tasks = tokio::task::JoinSet::new();
+        for worker_id in 0..self.pool_size {
+            let Some(conn) = self.mesophyll.get_connection(worker_id) else {
+                continue;
+            };
+            tasks.spawn(async move { (worker_id, conn.drain(DRAIN_TIMEOUT).await) });
+        }
+        tasks.join_all().await
+    }
+
+    /// Returns the number of worker processes in the pool
+    pub fn pool_size(&self) -> usize {
+        self.pool_size
+    }
+
     pub fn mesophyll(&self) -> &MesophyllServer {
         &self.mesophyll
     }
 
+    /// Returns the Mesophyll connection to a worker
+    fn connection(&self, worker_id: usize) -> Result<WorkerConn, crate::Error> {
+        self.mesophyll.get_connection(worker_id)
+            .ok_or_else(|| format!("No Mesophyll connection found for worker process with ID: {}", worker_id).into())
+    }
+
     pub async fn dispatch_event(&self, id: Id, event: SimpleEvent) -> Result<KhronosValue, crate::Error> {
-        let worker_id = id.worker_id(self.pool_size);
-        let r = self.mesophyll.get_connection(worker_id)
-            .ok_or_else(|| format!("No Mesophyll connection found for worker process with ID: {}", worker_id))?;
-        r.dispatch_event(id, event).await
+        if self.is_shutting_down.load(Ordering::Relaxed) {
+            return Err("Worker pool is shutting down".into());
+        }
+        let route = self.mesophyll.router().route(id).await?;
+        let worker_id = route.worker_id;
+        let r = self.connection(worker_id)?;
+
+        let cx = telemetry::child(&Context::current(), "master.dispatch_event", SpanKind::Producer, vec![
+            KeyValue::new("tenant", id.tenant_id()),
+            KeyValue::new("worker_id", worker_id as i64),
+        ]);
+        let res = r.dispatch_event(id, event).with_context(cx.clone()).await;
+        if let Err(ref e) = res {
+            cx.span().set_status(Status::error(e.to_string()));
+        }
+        cx.span().end();
+        res
     }
 
     pub async fn drop_tenant(&self, id: Id) -> Result<(), crate::Error> {
-        let worker_id = id.worker_id(self.pool_size);
-        let r = self.mesophyll.get_connection(worker_id)
-            .ok_or_else(|| format!("No Mesophyll connection found for worker process with ID: {}", worker_id))?;
-        r.drop_tenant(id).await
+        let route = self.mesophyll.router().route(id).await?;
+        self.connection(route.worker_id)?.drop_tenant(id).await
     }
 
     pub async fn update_tenant_state(&self, id: Id, ts: TenantState) -> Result<bool, crate::Error> {
-        let worker_id = id.worker_id(self.pool_size);
-        let r = self.mesophyll.get_connection(worker_id)
-            .ok_or_else(|| format!("No Mesophyll connection found for worker process with ID: {}", worker_id))?;
-        r.update_tenant_state(id, ts).await
+        let route = self.mesophyll.router().route(id).await?;
+        self.connection(route.worker_id)?.update_tenant_state(id, ts).await
     }
 
     pub async fn subscribe_topics(&self, id: Id, topics: &[String]) -> Result<(TopicGuard, Vec<(String, tokio::sync::broadcast::Receiver<KhronosValue>)>), crate::Error> {
-        let r = self.mesophyll.get_connection(id.worker_id(self.pool_size)).ok_or("Failed to get worker")?;
-        r.subscribe_topics(id, topics).await
+        let route = self.mesophyll.router().route(id).await?;
+        self.connection(route.worker_id)?.subscribe_topics(id, topics).await
+    }
+
+    /// Moves the hash ring to the given set of workers, gracefully handing off tenants that change worker
+    ///
+    /// Handing off a tenant loads its state into the new worker and then drops its VM on the old worker
+    pub async fn rebalance(&self, workers: &[usize], tenant_states: HashMap<Id, TenantState>) -> Result<RebalanceResult, crate::Error> {
+        let tenants = tenant_states.keys().copied().collect::<Vec<_>>();
+        self.mesophyll.router().rebalance(workers, tenants, |id, old_worker, new_worker| {
+            let ts = tenant_states.get(&id).cloned();
+            let pool = self.clone();
+            async move {
+                if let Some(ts) = ts {
+                    pool.connection(new_worker)?.update_tenant_state(id, ts).await?;
+                }
+                pool.connection(old_worker)?.drop_tenant(id).await
+            }
+        }).await
     }
 }
 
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use khronos_runtime::utils::khronos_value::KhronosValue;
+
+use crate::geese::workflows::{WORKFLOW_STEP_EVENT, WorkflowDb, WorkflowInstance, WorkflowStep};
+use crate::master::workerpool::WorkerPool;
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// How often due steps are checked for
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many steps are claimed per poll
+const BATCH_SIZE: i64 = 32;
+
+/// How long a claimed step is leased for before it is run again, longer than the longest time an event may take
+const LEASE: Duration = Duration::from_secs(10 * 60);
+
+/// How many times a step is attempted before its instance is failed
+const MAX_ATTEMPTS: i32 = 5;
+
+/// Delay before the first retry of a failed step, doubled on every further attempt
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long finished instances are kept for inspection
+const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How often finished instances past their retention are deleted
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Runs the due steps of the workflow instances of templates by dispatching `WorkflowStep` to their tenant
+///
+/// A step is expected to move its instance on (or complete it) through a state op. Steps which don't (their
+/// handler errored, or no template handles the workflow anymore) are retried with backoff and fail their
+/// instance after `MAX_ATTEMPTS`. As a step may run again if the master dies while it runs, steps should be
+/// idempotent
+pub struct WorkflowScheduler {
+    db: WorkflowDb,
+    worker_pool: Arc<WorkerPool>,
+}
+
+impl WorkflowScheduler {
+    pub fn new(db: WorkflowDb, worker_pool: Arc<WorkerPool>) -> Self {
+        Self { db, worker_pool }
+    }
+
+    /// Spawns the background task running due steps
+    pub fn spawn(self) {
+        let this = Arc::new(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut last_cleanup: Option<Instant> = None;
+            loop {
+                interval.tick().await;
+                this.tick().await;
+
+                if last_cleanup.is_none_or(|t| t.elapsed() >= CLEANUP_INTERVAL) {
+                    last_cleanup = Some(Instant::now());
+                    match this.db.delete_finished(RETENTION).await {
+                        Ok(0) => {}
+                        Ok(n) => log::info!("Deleted {n} finished workflow instances"),
+                        Err(e) => log::error!("Failed to delete finished workflow instances: {e}"),
+                    }
+                }
+            }
+        });
+    }
+
+    async fn tick(self: &Arc<Self>) {
+        let instances = match self.db.claim_due(BATCH_SIZE, LEASE).await {
+            Ok(instances) => instances,
+            Err(e) => {
+                log::error!("Failed to claim due workflow steps: {e}");
+                return;
+            }
+        };
+
+        // Steps may take as long as their event, so run them concurrently rather than holding up the next poll
+        for instance in instances {
+            let this = self.clone();
+            tokio::spawn(async move { this.run(instance).await });
+        }
+    }
+
+    async fn run(&self, instance: WorkflowInstance) {
+        let (id, seq, attempts) = (instance.id, instance.seq, instance.attempts);
+        let error = match self.dispatch(instance).await {
+            Ok(()) => "Step did not move the workflow on (its handler errored or no template handles the workflow)".to_string(),
+            Err(e) => format!("Failed to dispatch step: {e}"),
+        };
+
+        // Nothing is recorded if the step moved the instance on
+        let delay = (attempts < MAX_ATTEMPTS).then(|| RETRY_DELAY * 2u32.pow((attempts - 1).max(0) as u32));
+        match self.db.mark_failed(id, seq, &error, delay).await {
+            Ok(false) => {}
+            Ok(true) => match delay {
+                Some(delay) => log::warn!("Workflow instance {id} failed its step, retrying in {delay:?}: {error}"),
+                None => log::error!("Workflow instance {id} failed its step {attempts} times, failing it: {error}"),
+            },
+            Err(e) => log::error!("Failed to record result of step of workflow instance {id}: {e}"),
+        }
+    }
+
+    async fn dispatch(&self, instance: WorkflowInstance) -> Result<(), crate::Error> {
+        let tenant = Id::from_parts(&instance.owner_type, &instance.owner_id).ok_or("Invalid tenant of workflow instance")?;
+        let data: KhronosValue = serde_json::from_value(serde_json::to_value(WorkflowStep::from(instance))?)?;
+        let event = SimpleEvent::new_khronos_value(WORKFLOW_STEP_EVENT.to_string(), None, data);
+        self.worker_pool.dispatch_event(tenant, event).await?;
+        Ok(())
+    }
+}
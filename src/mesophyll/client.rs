@@ -1,11 +1,13 @@
 use std::time::Duration;
 use std::collections::HashMap;
 use std::sync::{Arc, OnceLock};
-use crate::{geese::{state::{StateDbFlags, StateExecResponse, StateOp}, tenantstate::TenantState}, mesophyll::connman::{SockFile, new_sockfile_rooted}, worker::{workerthread::WorkerThread, workervmmanager::Id}};
+use crate::{geese::{featureflags::{FeatureFlagCache, FeatureFlags}, modmail::{ModmailReq, ModmailResp}, appeals::{AppealReq, AppealResp}, invites::{InviteReq, InviteResp}, stickyroles::{StickyRolesReq, StickyRolesResp}, permsnapshots::{PermissionSnapshotReq, PermissionSnapshotResp}, outbox::{OutboxEnqueue, OutboxReceipt}, telemetry, state::{StateDbFlags, StateExecResponse, StateOp}, tenantstate::TenantState, pluginusage::PluginUsageRecord, emojiusage::EmojiUsageRecord, activity::ActivityRecord, templatecache::{Invalidate, TemplateInvalidation, TemplateVersions}, usage::UsageRecord}, mesophyll::{connman::{SockFile, new_sockfile_rooted}, router::{RoutingCache, RoutingTable}}, worker::{shutdown::{self, DrainReq}, workerdispatch::SimpleEvent, workerthread::WorkerThread, workervmmanager::Id}};
 use crate::mesophyll::server::pb;
 use rand::distr::{Alphanumeric, SampleString};
 use tokio::net::UnixListener;
+use tokio::sync::watch;
 use tokio_stream::wrappers::UnixListenerStream;
+use opentelemetry::Context;
 use tonic::Status;
 
 /// Mesophyll client
@@ -15,6 +17,16 @@ pub struct MesophyllClient {
     sock_file: Arc<SockFile>,
     client: pb::mesophyll_master_client::MesophyllMasterClient<tonic::transport::Channel>,
     wt: Arc<OnceLock<WorkerThread>>,
+    /// Feature flags pushed by the master
+    pub feature_flags: Arc<FeatureFlagCache>,
+    /// Routing table pushed by the master
+    pub routing: Arc<RoutingCache>,
+    /// Last template invalidation pushed by the master
+    template_versions: Arc<TemplateVersions>,
+    /// Set when the master drains the worker, closes the gateway sessions of the worker
+    shutdown: Arc<watch::Sender<bool>>,
+    /// Set once a drain requested by the master has finished
+    drained: Arc<watch::Sender<bool>>,
 }
 
 impl MesophyllClient {
@@ -26,7 +38,12 @@ impl MesophyllClient {
             sock_file: Arc::new(new_sockfile_rooted(master_sockfile.dir.clone(), Alphanumeric.sample_string(&mut rand::rng(), 16))?),
             worker_id,
             client: client.clone(),
-            wt: OnceLock::new().into()
+            wt: OnceLock::new().into(),
+            feature_flags: Arc::new(FeatureFlagCache::default()),
+            routing: Arc::new(RoutingCache::default()),
+            template_versions: Arc::new(TemplateVersions::default()),
+            shutdown: Arc::new(watch::Sender::new(false)),
+            drained: Arc::new(watch::Sender::new(false)),
         };
 
         // Setup UDS stream
@@ -54,6 +71,12 @@ impl MesophyllClient {
             }
         }
 
+        // Fetch the initial feature flags, later changes are pushed by the master
+        s.feature_flags.set(s.list_feature_flags().await?);
+
+        // Same for the routing table
+        s.routing.set(s.get_routing_table().await?);
+
         Ok(s)
     }
 
@@ -62,6 +85,17 @@ impl MesophyllClient {
         Ok(())
     }
 
+    /// Returns a receiver that is set once the worker is being drained for shutdown
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// Waits until the worker has been drained by the master
+    pub async fn wait_drained(&self) {
+        // The sender lives as long as self, so this can't fail
+        let _ = self.drained.subscribe().wait_for(|drained| *drained).await;
+    }
+
     fn try_wt(&self) -> Result<&WorkerThread, Status> {
         self.wt.get().ok_or_else(|| Status::internal("WorkerThread not up yet!"))
     }
@@ -76,6 +110,192 @@ impl MesophyllClient {
             .to_real_exec()
     }
 
+    /// Returns all feature flags from the Mesophyll server
+    pub async fn list_feature_flags(&self) -> Result<FeatureFlags, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.list_feature_flags(pb::Empty {})
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner()
+            .to_real_exec()
+    }
+
+    /// Returns the current routing table from the Mesophyll server
+    pub async fn get_routing_table(&self) -> Result<RoutingTable, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.get_routing_table(pb::Empty {})
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner()
+            .to_real_exec()
+    }
+
+    /// Forwards an event for a tenant this worker does not own to the master, which dispatches it to the owning worker
+    pub async fn forward_event(&self, id: Id, event: SimpleEvent) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        let traceparent = event.traceparent().unwrap_or_default().to_string();
+        cli.forward_event(pb::DispatchEventReq {
+            id: Some(pb::Id::from_real_id(&id)),
+            event_payload: Some(pb::AnyValue::from_real_exec(&event)?),
+            traceparent,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Tells the master the bot was removed from a tenant, scheduling deletion of its data
+    pub async fn tenant_removed(&self, id: Id) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        cli.tenant_removed(pb::WtmTenantRemoved {
+            worker_id: self.worker_id,
+            id: Some(pb::Id::from_real_id(&id)),
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Tells the master an archived tenant is back, restoring its data from cold storage and returning its new tenant state
+    pub async fn restore_archive(&self, id: Id) -> Result<Option<TenantState>, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.restore_archive(pb::WtmRestoreArchive {
+            worker_id: self.worker_id,
+            id: Some(pb::Id::from_real_id(&id)),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .to_real_exec()
+    }
+
+    /// Sends a modmail request to the master
+    pub async fn modmail(&self, req: &ModmailReq) -> Result<ModmailResp, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.modmail(pb::WtmModmail {
+            worker_id: self.worker_id,
+            req: Some(pb::AnyValue::from_real_exec(req)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .to_real_exec()
+    }
+
+    /// Sends an appeal request to the master
+    pub async fn appeals(&self, req: &AppealReq) -> Result<AppealResp, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.appeals(pb::WtmAppeals {
+            worker_id: self.worker_id,
+            req: Some(pb::AnyValue::from_real_exec(req)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .to_real_exec()
+    }
+
+    /// Sends an invite tracking request to the master
+    pub async fn invites(&self, req: &InviteReq) -> Result<InviteResp, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.invites(pb::WtmInvites {
+            worker_id: self.worker_id,
+            req: Some(pb::AnyValue::from_real_exec(req)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .to_real_exec()
+    }
+
+    /// Sends a sticky roles request to the master
+    pub async fn sticky_roles(&self, req: &StickyRolesReq) -> Result<StickyRolesResp, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.sticky_roles(pb::WtmStickyRoles {
+            worker_id: self.worker_id,
+            req: Some(pb::AnyValue::from_real_exec(req)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .to_real_exec()
+    }
+
+    /// Sends a permission snapshot request to the master
+    pub async fn permission_snapshots(&self, req: &PermissionSnapshotReq) -> Result<PermissionSnapshotResp, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.permission_snapshots(pb::WtmPermissionSnapshots {
+            worker_id: self.worker_id,
+            req: Some(pb::AnyValue::from_real_exec(req)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .to_real_exec()
+    }
+
+    /// Durably enqueues a Discord action of a tenant into the outbox
+    pub async fn enqueue_outbox(&self, id: Id, req: &OutboxEnqueue) -> Result<OutboxReceipt, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.enqueue_outbox(pb::WtmEnqueueOutbox {
+            worker_id: self.worker_id,
+            id: Some(pb::Id::from_real_id(&id)),
+            req: Some(pb::AnyValue::from_real_exec(req)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .to_real_exec()
+    }
+
+    /// Flushes template usage records to the master
+    pub async fn record_usage(&self, records: &[UsageRecord]) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        cli.record_usage(pb::WtmRecordUsage {
+            worker_id: self.worker_id,
+            records: Some(pb::AnyValue::from_real_exec(&records)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Flushes plugin usage records to the master
+    pub async fn record_plugin_usage(&self, records: &[PluginUsageRecord]) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        cli.record_plugin_usage(pb::WtmRecordUsage {
+            worker_id: self.worker_id,
+            records: Some(pb::AnyValue::from_real_exec(&records)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Flushes emoji usage records to the master
+    pub async fn record_emoji_usage(&self, records: &[EmojiUsageRecord]) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        cli.record_emoji_usage(pb::WtmRecordUsage {
+            worker_id: self.worker_id,
+            records: Some(pb::AnyValue::from_real_exec(&records)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Flushes activity records to the master
+    pub async fn record_activity(&self, records: &[ActivityRecord]) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        cli.record_activity(pb::WtmRecordUsage {
+            worker_id: self.worker_id,
+            records: Some(pb::AnyValue::from_real_exec(&records)?),
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
     /// Sets the tenant state for a given tenant ID
     pub async fn exec_state_op(&self, id: Id, state_op: Vec<StateOp>, flags: StateDbFlags) -> Result<StateExecResponse, crate::Error> {
         let mut cli = self.client.clone();
@@ -83,7 +303,8 @@ impl MesophyllClient {
             worker_id: self.worker_id, 
             id: Some(pb::Id::from_real_id(&id)),
             state_op: Some(pb::AnyValue::from_real_exec(&state_op)?),
-            flags: flags.bits()
+            flags: flags.bits(),
+            traceparent: telemetry::inject(&Context::current()).unwrap_or_default(),
         })
         .await
         .map_err(|e| e.to_string())?
@@ -121,7 +342,8 @@ impl pb::mesophyll_worker_server::MesophyllWorker for MesophyllClient {
     async fn dispatch_event(&self, request: tonic::Request<pb::DispatchEventReq>) -> Result<tonic::Response<pb::AnyValue>, Status> {
         let req = request.into_inner();
         let id = req.id.ok_or_else(|| Status::invalid_argument("Missing ID"))?.to_real_id();
-        let evt = req.event_payload.ok_or_else(|| Status::invalid_argument("Missing event_payload"))?.to_real()?;
+        let evt: SimpleEvent = req.event_payload.ok_or_else(|| Status::invalid_argument("Missing event_payload"))?.to_real()?;
+        let evt = evt.with_traceparent(Some(req.traceparent).filter(|t| !t.is_empty()));
         let wt = self.try_wt()?;
         match wt.dispatch_event(id, evt).await {
             Ok(result) => Ok(tonic::Response::new(pb::AnyValue::from_real(&result)?)),
@@ -150,14 +372,60 @@ impl pb::mesophyll_worker_server::MesophyllWorker for MesophyllClient {
         Ok(tonic::Response::new(pb::Empty {}))
     }
 
-    async fn update_tenant_state(&self, request: tonic::Request<pb::UpdateTenantStateReq>) -> Result<tonic::Response<pb::Bool>, Status> {
+    async fn update_tenant_state(&self, request: tonic::Request<pb::UpdateTenantStateReq>) -> Result<tonic::Response<pb::AnyValue>, Status> {
         let req = request.into_inner();
         let id = req.id.ok_or_else(|| Status::invalid_argument("Missing ID"))?.to_real_id();
-        let ts = req.new_tenant_state.ok_or_else(|| Status::invalid_argument("Missing new_tenant_state"))?.to_real()?;
+        let sync = req.sync.ok_or_else(|| Status::invalid_argument("Missing sync"))?.to_real()?;
         let wt = self.try_wt()?;
-        match wt.update_tenant_state(id, ts).await {
-            Ok(result) => Ok(tonic::Response::new(pb::Bool { b: result })),
+        match wt.update_tenant_state(id, sync).await {
+            Ok(outcome) => Ok(tonic::Response::new(pb::AnyValue::from_real(&outcome)?)),
             Err(e) => Err(Status::internal(e.to_string())),
         }
     }
+
+    async fn update_feature_flags(&self, request: tonic::Request<pb::AnyValue>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let flags: FeatureFlags = request.into_inner().to_real()?;
+        self.feature_flags.set(flags);
+        Ok(tonic::Response::new(pb::Empty {}))
+    }
+
+    async fn update_routing_table(&self, request: tonic::Request<pb::AnyValue>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let table: RoutingTable = request.into_inner().to_real()?;
+        self.routing.set(table);
+        Ok(tonic::Response::new(pb::Empty {}))
+    }
+
+    async fn get_load(&self, _request: tonic::Request<pb::Empty>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let wt = self.try_wt()?;
+        Ok(tonic::Response::new(pb::AnyValue::from_real(&wt.load().sample())?))
+    }
+
+    async fn get_recent_dispatches(&self, _request: tonic::Request<pb::Empty>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let wt = self.try_wt()?;
+        Ok(tonic::Response::new(pb::AnyValue::from_real(&wt.load().recent_dispatches())?))
+    }
+
+    async fn invalidate_templates(&self, request: tonic::Request<pb::AnyValue>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let inv: TemplateInvalidation = request.into_inner().to_real()?;
+        let wt = self.try_wt()?;
+        let invalidate = self.template_versions.observe(&inv);
+        if invalidate == Invalidate::All {
+            log::warn!("Missed template invalidations before {}:{}, invalidating all tenants", inv.epoch, inv.seq);
+        }
+        if let Err(e) = wt.invalidate_templates(invalidate) {
+            return Err(Status::internal(e.to_string()));
+        }
+        Ok(tonic::Response::new(pb::Empty {}))
+    }
+
+    async fn drain(&self, request: tonic::Request<pb::AnyValue>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let req: DrainReq = request.into_inner().to_real()?;
+        let wt = self.try_wt()?;
+        log::info!("Mesophyll server requested drain");
+        self.shutdown.send_replace(true);
+        let report = shutdown::drain(wt, self, Duration::from_millis(req.timeout_ms)).await;
+        log::info!("Drained worker: {report:?}");
+        self.drained.send_replace(true);
+        Ok(tonic::Response::new(pb::AnyValue::from_real(&report)?))
+    }
 }
@@ -1,4 +1,5 @@
 /// Mesophyll provides a coordination layer between the master process and the worker processes holding Luau VMs.
 pub mod client;
 pub mod server;
-pub mod connman;
\ No newline at end of file
+pub mod connman;
+pub mod router;
\ No newline at end of file
@@ -0,0 +1,328 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OwnedRwLockReadGuard, RwLock as AsyncRwLock, watch};
+
+use crate::worker::workervmmanager::Id;
+
+/// Number of points each worker gets on the hash ring
+///
+/// More points spread tenants more evenly between workers at the cost of a larger ring
+const POINTS_PER_WORKER: u64 = 160;
+
+/// How long a rebalance waits for in-flight dispatches to a tenant to finish before giving up on moving it
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many tenants a rebalance hands off at once
+const MAX_CONCURRENT_HANDOFFS: usize = 32;
+
+/// Per-tenant locks, held for reading by in-flight requests and for writing while a tenant is handed off
+///
+/// Locks only exist while held or waited for
+type TenantLocks = DashMap<Id, Arc<AsyncRwLock<()>>>;
+
+/// Removes the lock of a tenant if nobody holds or waits for it
+fn prune_lock(locks: &TenantLocks, id: Id) {
+    locks.remove_if(&id, |_, lock| Arc::strong_count(lock) == 1);
+}
+
+/// splitmix64, used as a stable hash so routes are the same across restarts and builds
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn tenant_hash(id: Id) -> u64 {
+    match id {
+        Id::Guild(guild_id) => mix(guild_id.get()),
+        Id::User(user_id) => mix(user_id.get() ^ 0x5553455200000000), // keep users and guilds with the same id apart
+    }
+}
+
+/// A consistent hash ring of workers
+///
+/// Each worker is placed at `POINTS_PER_WORKER` points on the ring and a tenant belongs to the worker owning the
+/// first point at or after the tenant's hash. Adding or removing a worker hence only moves the tenants
+/// of that worker's points, unlike modulo sharding where almost every tenant moves
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    points: Vec<(u64, usize)>,
+    workers: Vec<usize>,
+}
+
+impl HashRing {
+    /// Creates a hash ring of the given workers
+    pub fn new(workers: &[usize]) -> Self {
+        let mut workers = workers.to_vec();
+        workers.sort_unstable();
+        workers.dedup();
+
+        let mut points = Vec::with_capacity(workers.len() * POINTS_PER_WORKER as usize);
+        for &worker in workers.iter() {
+            for point in 0..POINTS_PER_WORKER {
+                points.push((mix(((worker as u64) << 32) | point), worker));
+            }
+        }
+        points.sort_unstable();
+
+        Self { points, workers }
+    }
+
+    /// Returns the workers on the ring, sorted by ID
+    pub fn workers(&self) -> &[usize] {
+        &self.workers
+    }
+
+    /// Returns the worker a tenant belongs to, if the ring has any workers
+    pub fn worker_for(&self, id: Id) -> Option<usize> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let hash = tenant_hash(id);
+        let idx = self.points.partition_point(|(point, _)| *point < hash);
+        Some(self.points[idx % self.points.len()].1)
+    }
+}
+
+impl Default for HashRing {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+/// The routing state shared with workers
+///
+/// Gateway events are sent to workers by Stratum, which knows nothing of the ring, so workers use this to tell
+/// which tenants they own (and forward events of all other tenants to the master)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoutingTable {
+    /// Workers on the ring
+    pub workers: Vec<usize>,
+    /// Tenants pinned to a worker, routed by the master only
+    pub pinned: Vec<Id>,
+}
+
+/// A workers view of the routing state, pushed by the master
+#[derive(Default)]
+pub struct RoutingCache {
+    state: RwLock<Arc<(HashRing, HashSet<Id>)>>,
+}
+
+impl RoutingCache {
+    /// Replaces the current routing state
+    pub fn set(&self, table: RoutingTable) {
+        let state = (HashRing::new(&table.workers), table.pinned.into_iter().collect());
+        *self.state.write() = Arc::new(state);
+    }
+
+    /// Returns whether events of a tenant can be dispatched directly on the given worker
+    pub fn owns(&self, worker_id: usize, id: Id) -> bool {
+        let state = self.state.read();
+        !state.1.contains(&id) && state.0.worker_for(id) == Some(worker_id)
+    }
+}
+
+/// A route to a tenant's worker
+///
+/// The tenant will not be moved to another worker while the route is held
+pub struct Route {
+    pub worker_id: usize,
+    id: Id,
+    locks: Arc<TenantLocks>,
+    guard: Option<OwnedRwLockReadGuard<()>>,
+}
+
+impl Drop for Route {
+    fn drop(&mut self) {
+        // The guard holds the lock too, so must be released before pruning
+        self.guard.take();
+        prune_lock(&self.locks, self.id);
+    }
+}
+
+/// Outcome of a rebalance
+#[derive(Debug, Clone, Serialize)]
+pub struct RebalanceResult {
+    /// Tenants moved to a new worker
+    pub moved: usize,
+    /// Tenants that could not be moved (and so remain on their old worker until the next rebalance)
+    pub failed: Vec<RebalanceFailure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RebalanceFailure {
+    pub id: Id,
+    pub error: String,
+}
+
+/// Routes tenants to workers using a consistent hash ring
+///
+/// Tenants being moved by a rebalance are pinned to their old worker until they have been handed off
+pub struct Router {
+    /// Number of workers in the pool, the ring may only contain workers below this
+    num_workers: usize,
+    ring: RwLock<Arc<HashRing>>,
+    /// Tenants pinned to a worker other than the one the ring gives
+    pinned: DashMap<Id, usize>,
+    locks: Arc<TenantLocks>,
+    /// Ensures only one rebalance runs at a time
+    rebalance_lock: Mutex<()>,
+    /// Notified with the new routing table whenever the ring or pins change
+    table_tx: watch::Sender<RoutingTable>,
+}
+
+impl Router {
+    /// Creates a router with all `num_workers` workers on the ring
+    pub fn new(num_workers: usize) -> Self {
+        let workers = (0..num_workers).collect::<Vec<_>>();
+        Self {
+            num_workers,
+            ring: RwLock::new(Arc::new(HashRing::new(&workers))),
+            pinned: DashMap::new(),
+            locks: Arc::default(),
+            rebalance_lock: Mutex::new(()),
+            table_tx: watch::Sender::new(RoutingTable { workers, pinned: Vec::new() }),
+        }
+    }
+
+    /// Returns the current routing table
+    pub fn table(&self) -> RoutingTable {
+        RoutingTable {
+            workers: self.ring().workers().to_vec(),
+            pinned: self.pinned.iter().map(|r| *r.key()).collect(),
+        }
+    }
+
+    /// Subscribes to changes of the routing table
+    pub fn subscribe(&self) -> watch::Receiver<RoutingTable> {
+        self.table_tx.subscribe()
+    }
+
+    /// Returns the current hash ring
+    pub fn ring(&self) -> Arc<HashRing> {
+        self.ring.read().clone()
+    }
+
+    /// Returns the tenants currently pinned to a worker along with said worker
+    pub fn pinned(&self) -> Vec<(Id, usize)> {
+        self.pinned.iter().map(|r| (*r.key(), *r.value())).collect()
+    }
+
+    /// Returns the worker a tenant currently belongs to
+    ///
+    /// Use `route` instead when sending requests to the worker so the tenant is not moved mid-request
+    pub fn worker_for(&self, id: Id) -> Result<usize, crate::Error> {
+        if let Some(worker_id) = self.pinned.get(&id) {
+            return Ok(*worker_id);
+        }
+        self.ring.read().worker_for(id).ok_or_else(|| "No workers available to route to".into())
+    }
+
+    /// Routes a request to a tenant, waiting for any ongoing handoff of the tenant to finish
+    pub async fn route(&self, id: Id) -> Result<Route, crate::Error> {
+        let lock = self.locks.entry(id).or_default().clone();
+        let guard = lock.read_owned().await;
+        Ok(Route { worker_id: self.worker_for(id)?, id, locks: self.locks.clone(), guard: Some(guard) })
+    }
+
+    /// Moves the ring to a new set of workers, handing off every tenant whose worker changes
+    ///
+    /// `tenants` are the tenants with stored state, which is loaded into their new worker. Tenants without
+    /// stored state that have requests in flight are moved as well.
+    ///
+    /// Each moved tenant is handed off as follows, up to `MAX_CONCURRENT_HANDOFFS` tenants at a time:
+    /// 1. The tenant is pinned to its old worker and the new ring is installed (so no other tenant is affected)
+    /// 2. New requests to the tenant are held and in-flight ones are drained
+    /// 3. `handoff(id, old_worker, new_worker)` is awaited, which should load the tenant on the new worker and drop
+    ///    its VM on the old one
+    /// 4. The pin is removed and held requests continue on the new worker
+    pub async fn rebalance<F, Fut>(self: &Arc<Self>, workers: &[usize], tenants: impl IntoIterator<Item = Id>, handoff: F) -> Result<RebalanceResult, crate::Error>
+    where
+        F: Fn(Id, usize, usize) -> Fut,
+        Fut: Future<Output = Result<(), crate::Error>> + Send + 'static,
+    {
+        if workers.is_empty() {
+            return Err("At least one worker must be on the ring".into());
+        }
+        if let Some(worker) = workers.iter().find(|w| **w >= self.num_workers) {
+            return Err(format!("Invalid worker ID: {worker}, exceeds number of workers in pool").into());
+        }
+
+        let _rebalance_guard = self.rebalance_lock.lock().await;
+
+        let new_ring = Arc::new(HashRing::new(workers));
+        let mut moves = HashMap::new();
+        let in_flight = self.locks.iter().map(|r| *r.key()).collect::<Vec<_>>();
+        for id in tenants.into_iter().chain(in_flight) {
+            let old_worker = self.worker_for(id)?;
+            let Some(new_worker) = new_ring.worker_for(id) else {
+                continue;
+            };
+            if old_worker != new_worker {
+                moves.insert(id, (old_worker, new_worker));
+            } else {
+                // A tenant pinned by a failed previous rebalance that now belongs to its pinned worker
+                self.pinned.remove(&id);
+            }
+        }
+
+        // Pin before installing the new ring, so moved tenants keep going to their old worker until handed off
+        for (id, (old_worker, _)) in moves.iter() {
+            self.pinned.insert(*id, *old_worker);
+        }
+        *self.ring.write() = new_ring;
+        self.table_tx.send_replace(self.table());
+
+        let mut result = RebalanceResult { moved: 0, failed: Vec::new() };
+        let mut moves = moves.into_iter();
+        let mut tasks = tokio::task::JoinSet::new();
+        loop {
+            while tasks.len() < MAX_CONCURRENT_HANDOFFS && let Some((id, (old_worker, new_worker))) = moves.next() {
+                let router = self.clone();
+                let handoff = handoff(id, old_worker, new_worker);
+                tasks.spawn(async move { (id, router.hand_off(id, old_worker, new_worker, handoff).await) });
+            }
+
+            let Some(res) = tasks.join_next().await else {
+                break;
+            };
+            match res {
+                Ok((_, Ok(()))) => result.moved += 1,
+                Ok((id, Err(error))) => result.failed.push(RebalanceFailure { id, error }),
+                // The tenant stays pinned to its old worker
+                Err(e) => log::error!("Handoff task failed: {e}"),
+            }
+        }
+
+        self.table_tx.send_replace(self.table());
+        Ok(result)
+    }
+
+    /// Drains the in-flight requests of a moved tenant and hands it off, unpinning it once handed off
+    async fn hand_off(&self, id: Id, old_worker: usize, new_worker: usize, handoff: impl Future<Output = Result<(), crate::Error>>) -> Result<(), String> {
+        let lock = self.locks.entry(id).or_default().clone();
+        let res = match tokio::time::timeout(DRAIN_TIMEOUT, lock.write_owned()).await {
+            Ok(_guard) => match handoff.await {
+                Ok(()) => {
+                    self.pinned.remove(&id);
+                    Ok(())
+                }
+                Err(e) => {
+                    log::error!("Failed to hand off tenant {id:?} from worker {old_worker} to {new_worker}: {e}");
+                    Err(e.to_string())
+                }
+            },
+            Err(_) => Err("timed out draining in-flight requests".to_string()),
+        };
+
+        prune_lock(&self.locks, id);
+        res
+    }
+}
@@ -2,11 +2,17 @@ use dapi::{UserId, GuildId};
 use rand::distr::{Alphanumeric, SampleString};
 use tokio_stream::wrappers::UnixListenerStream;
 use tonic::Status;
-use crate::{geese::{state::{StateDb, StateDbFlags}, tenantstate::{TenantState, TenantStateDb}}, mesophyll::connman::{SockFile, new_sockfile}, worker::{workerdispatch::SimpleEvent, workervmmanager::Id as RealId}};
+use crate::{geese::{archival::ArchiveDb, dbpools::PoolKind, dbrouter::DbRouter, datalifecycle::{DataLifecycleDb, REASON_BOT_REMOVED}, featureflags::{FeatureFlagDb, FeatureFlags}, settingssync::{SyncOutcome, SyncTracker, TenantStateSync}, modmail::{ModmailDb, ModmailReq}, appeals::{AppealDb, AppealReq}, invites::{InviteDb, InviteReq}, stickyroles::{StickyRolesDb, StickyRolesReq}, permsnapshots::{PermissionSnapshotDb, PermissionSnapshotReq}, outbox::{OutboxDb, OutboxEnqueue}, telemetry, pluginusage::{PluginUsageDb, PluginUsageRecord}, emojiusage::{EmojiUsageDb, EmojiUsageRecord}, activity::{ActivityDb, ActivityRecord}, usage::{UsageDb, UsageRecord}, state::{StateDb, StateDbFlags}, templatecache::{TemplateInvalidation, TemplateInvalidator}, tenantstate::{TenantState, TenantStateDb}}, mesophyll::{connman::{SockFile, new_sockfile}, router::{Router, RoutingTable}}, worker::{load::WorkerLoad, shutdown::{DrainReport, DrainReq}, workerdispatch::SimpleEvent, workervmmanager::Id as RealId}};
 use khronos_runtime::utils::khronos_value::KhronosValue as RealKhronosValue;
 use dashmap::DashMap;
+use opentelemetry::{Context, KeyValue, trace::{FutureExt, SpanKind, Status as SpanStatus, TraceContextExt}};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::{net::UnixListener, sync::broadcast};
+use std::time::{Duration, Instant};
+use tokio::{net::UnixListener, sync::{Mutex, broadcast}};
+
+/// How long a load of every tenant state is reused for workers listing their tenant states
+const TENANT_STATES_REUSE: Duration = Duration::from_secs(5);
 
 /// Internal transport layer
 pub(super) mod pb {
@@ -75,23 +81,81 @@ impl pb::Id {
     }
 } 
 
+/// Every tenant state, shared by workers listing their tenant states at about the same time (on startup every worker
+/// does) so the table is scanned once rather than once per worker
+#[derive(Default)]
+struct TenantStatesSnapshot {
+    loaded: Mutex<Option<(Instant, Arc<HashMap<RealId, TenantState>>)>>,
+}
+
+impl TenantStatesSnapshot {
+    /// Returns every tenant state, loading them unless loaded within `TENANT_STATES_REUSE`
+    async fn get(&self, db: &TenantStateDb) -> Result<Arc<HashMap<RealId, TenantState>>, crate::Error> {
+        // Held while loading, so concurrent callers wait for the load instead of starting their own
+        let mut loaded = self.loaded.lock().await;
+        if let Some((at, states)) = loaded.as_ref()
+            && at.elapsed() < TENANT_STATES_REUSE
+        {
+            return Ok(states.clone());
+        }
+
+        let states = Arc::new(db.get_tenant_state().await?);
+        *loaded = Some((Instant::now(), states.clone()));
+        Ok(states)
+    }
+}
+
 #[derive(Clone)]
 pub struct MesophyllServer {
     conns: Arc<DashMap<usize, WorkerConnGuard>>,
     tenant_state_db: TenantStateDb,
+    tenant_states: Arc<TenantStatesSnapshot>,
     state_db: StateDb,
+    feature_flag_db: FeatureFlagDb,
+    usage_db: UsageDb,
+    plugin_usage_db: PluginUsageDb,
+    emoji_usage_db: EmojiUsageDb,
+    activity_db: ActivityDb,
+    data_lifecycle_db: DataLifecycleDb,
+    archive_db: ArchiveDb,
+    modmail_db: ModmailDb,
+    appeal_db: AppealDb,
+    invite_db: InviteDb,
+    sticky_roles_db: StickyRolesDb,
+    permission_snapshot_db: PermissionSnapshotDb,
+    outbox_db: OutboxDb,
     num_workers: usize,
+    router: Arc<Router>,
+    template_invalidator: Arc<TemplateInvalidator>,
     sock_file: Arc<SockFile>,
     attached_streams: AttachedStreams,
 }
 
 impl MesophyllServer {
-    pub async fn new(num_workers: usize, pool: sqlx::PgPool) -> Result<Self, crate::Error> {
+    pub async fn new(num_workers: usize, db: DbRouter) -> Result<Self, crate::Error> {
+        let pool = db.primary().clone();
+        let settings_pool = db.pool(PoolKind::Settings).pool().clone();
         let s = Self {
             conns: Arc::new(DashMap::new()),
-            tenant_state_db: TenantStateDb::new(pool.clone()),
-            state_db: StateDb::new(pool),
+            tenant_state_db: TenantStateDb::new(settings_pool.clone()),
+            tenant_states: Arc::default(),
+            feature_flag_db: FeatureFlagDb::new(settings_pool),
+            usage_db: UsageDb::new(db.clone()),
+            plugin_usage_db: PluginUsageDb::new(db.clone()),
+            emoji_usage_db: EmojiUsageDb::new(db.clone()),
+            activity_db: ActivityDb::new(db.clone()),
+            data_lifecycle_db: DataLifecycleDb::new(pool.clone()),
+            archive_db: ArchiveDb::new(pool.clone()),
+            modmail_db: ModmailDb::new(pool.clone()),
+            appeal_db: AppealDb::new(pool.clone()),
+            invite_db: InviteDb::new(pool.clone()),
+            sticky_roles_db: StickyRolesDb::new(pool.clone()),
+            permission_snapshot_db: PermissionSnapshotDb::new(pool.clone()),
+            outbox_db: OutboxDb::new(pool.clone()),
+            state_db: StateDb::new(db),
             num_workers,
+            router: Arc::new(Router::new(num_workers)),
+            template_invalidator: Arc::new(TemplateInvalidator::default()),
             sock_file: Arc::new(new_sockfile(Alphanumeric.sample_string(&mut rand::rng(), 16), Alphanumeric.sample_string(&mut rand::rng(), 16))?),
             attached_streams: Arc::new(DashMap::new()),
         };
@@ -109,6 +173,16 @@ impl MesophyllServer {
             .unwrap();
         });
 
+        // Push routing table changes to all workers
+        let s_ref = s.clone();
+        let mut table_rx = s.router.subscribe();
+        tokio::spawn(async move {
+            while table_rx.changed().await.is_ok() {
+                let table = table_rx.borrow_and_update().clone();
+                s_ref.broadcast_routing_table(&table).await;
+            }
+        });
+
         Ok(s)
     }
 
@@ -120,6 +194,82 @@ impl MesophyllServer {
         self.conns.get(&worker_id).map(|r| r.value().conn.clone())
     }
 
+    /// Returns the router used to route tenants to workers
+    pub fn router(&self) -> &Arc<Router> {
+        &self.router
+    }
+
+    pub fn feature_flag_db(&self) -> &FeatureFlagDb {
+        &self.feature_flag_db
+    }
+
+    pub fn usage_db(&self) -> &UsageDb {
+        &self.usage_db
+    }
+
+    pub fn plugin_usage_db(&self) -> &PluginUsageDb {
+        &self.plugin_usage_db
+    }
+
+    pub fn emoji_usage_db(&self) -> &EmojiUsageDb {
+        &self.emoji_usage_db
+    }
+
+    pub fn activity_db(&self) -> &ActivityDb {
+        &self.activity_db
+    }
+
+    pub fn data_lifecycle_db(&self) -> &DataLifecycleDb {
+        &self.data_lifecycle_db
+    }
+
+    pub fn archive_db(&self) -> &ArchiveDb {
+        &self.archive_db
+    }
+
+    pub fn outbox_db(&self) -> &OutboxDb {
+        &self.outbox_db
+    }
+
+    pub fn tenant_state_db(&self) -> &TenantStateDb {
+        &self.tenant_state_db
+    }
+
+    /// Reloads feature flags from the database and pushes them to all connected workers
+    pub async fn broadcast_feature_flags(&self) -> Result<(), crate::Error> {
+        let flags = self.feature_flag_db.list().await?;
+        let conns = self.conns.iter().map(|r| r.value().conn.clone()).collect::<Vec<_>>();
+        for conn in conns {
+            if let Err(e) = conn.update_feature_flags(&flags).await {
+                log::error!("Failed to update feature flags on worker {}: {e}", conn.id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells all connected workers the cached templates of a tenant are stale
+    ///
+    /// Sent to every worker (not just the one owning the tenant) so numbering stays gapless on each worker
+    pub async fn broadcast_template_invalidation(&self, id: RealId) {
+        let inv = self.template_invalidator.next(id);
+        let conns = self.conns.iter().map(|r| r.value().conn.clone()).collect::<Vec<_>>();
+        for conn in conns {
+            if let Err(e) = conn.invalidate_templates(&inv).await {
+                log::error!("Failed to invalidate templates of {id:?} on worker {}: {e}", conn.id);
+            }
+        }
+    }
+
+    /// Pushes a routing table to all connected workers
+    async fn broadcast_routing_table(&self, table: &RoutingTable) {
+        let conns = self.conns.iter().map(|r| r.value().conn.clone()).collect::<Vec<_>>();
+        for conn in conns {
+            if let Err(e) = conn.update_routing_table(table).await {
+                log::error!("Failed to update routing table on worker {}: {e}", conn.id);
+            }
+        }
+    }
+
     fn verify_worker(&self, worker: u64) -> Result<usize, Status> {
         let id = worker.try_into().map_err(|_e| tonic::Status::internal("WID not a u64"))?;
         if id > self.num_workers {
@@ -135,12 +285,21 @@ impl pb::mesophyll_master_server::MesophyllMaster for MesophyllServer {
         let req = request.into_inner();
         let wid = self.verify_worker(req.worker_id)?;
         let id = req.id.ok_or_else(|| Status::invalid_argument("Missing ID"))?.to_real_id();
-        if id.worker_id(self.num_workers) != wid {
+        if self.router.worker_for(id).map_err(|e| Status::internal(e.to_string()))? != wid {
             return Err(Status::internal("ID expected worker_id and actual worker_id mismatched"));
         }
         let state_op = req.state_op.ok_or_else(|| Status::invalid_argument("Missing state_op"))?.to_real()?;
         let sdb_flags = StateDbFlags::from_bits(req.flags).ok_or_else(|| Status::invalid_argument("Invalid flags"))?;
-        match self.state_db.do_op(id, state_op, sdb_flags).await {
+
+        let parent = telemetry::extract(Some(req.traceparent.as_str()).filter(|t| !t.is_empty()));
+        let cx = telemetry::child(&parent, "mesophyll.exec_state_op", SpanKind::Server, vec![KeyValue::new("worker_id", req.worker_id as i64)]);
+        let res = self.state_db.do_op(id, state_op, sdb_flags).with_context(cx.clone()).await;
+        if let Err(ref e) = res {
+            cx.span().set_status(SpanStatus::error(e.to_string()));
+        }
+        cx.span().end();
+
+        match res {
             Ok(result) => Ok(tonic::Response::new(pb::AnyValue::from_real(&result)?)),
             Err(e) => Err(Status::internal(e.to_string())),
         }
@@ -149,8 +308,14 @@ impl pb::mesophyll_master_server::MesophyllMaster for MesophyllServer {
     async fn list_tenant_states(&self, request: tonic::Request<pb::WtmListTenantStates>) -> Result<tonic::Response<pb::AnyValue>, Status> {
         let req = request.into_inner();
         let wid = self.verify_worker(req.worker_id)?;
-        match self.tenant_state_db.get_tenant_state(wid as i64, self.num_workers as i64).await {
-            Ok(ts) => Ok(tonic::Response::new(pb::AnyValue::from_real(&ts)?)),
+        match self.tenant_states.get(&self.tenant_state_db).await {
+            Ok(states) => {
+                // Partitioned on every call, as the ring may have changed since the states were loaded
+                let ts = states.iter()
+                    .filter(|(id, _)| self.router.worker_for(**id).is_ok_and(|w| w == wid))
+                    .collect::<HashMap<_, _>>();
+                Ok(tonic::Response::new(pb::AnyValue::from_real(&ts)?))
+            },
             Err(e) => Err(Status::internal(e.to_string())),
         }
     }
@@ -187,6 +352,191 @@ impl pb::mesophyll_master_server::MesophyllMaster for MesophyllServer {
         }
         Ok(tonic::Response::new(pb::Empty {}))
     }
+
+    async fn record_usage(&self, request: tonic::Request<pb::WtmRecordUsage>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let req = request.into_inner();
+        let wid = self.verify_worker(req.worker_id)?;
+        let records: Vec<UsageRecord> = req.records.ok_or_else(|| Status::invalid_argument("Missing records"))?.to_real()?;
+
+        // Workers may only record usage for their own tenants
+        let records = records.into_iter()
+            .filter(|r| self.router.worker_for(r.id).is_ok_and(|w| w == wid))
+            .collect::<Vec<_>>();
+
+        match self.usage_db.record(records).await {
+            Ok(()) => Ok(tonic::Response::new(pb::Empty {})),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn record_plugin_usage(&self, request: tonic::Request<pb::WtmRecordUsage>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let req = request.into_inner();
+        self.verify_worker(req.worker_id)?;
+        let records: Vec<PluginUsageRecord> = req.records.ok_or_else(|| Status::invalid_argument("Missing records"))?.to_real()?;
+
+        match self.plugin_usage_db.record(records).await {
+            Ok(()) => Ok(tonic::Response::new(pb::Empty {})),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn record_emoji_usage(&self, request: tonic::Request<pb::WtmRecordUsage>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let req = request.into_inner();
+        let wid = self.verify_worker(req.worker_id)?;
+        let records: Vec<EmojiUsageRecord> = req.records.ok_or_else(|| Status::invalid_argument("Missing records"))?.to_real()?;
+
+        // Workers may only record usage for their own guilds
+        let records = records.into_iter()
+            .filter(|r| self.router.worker_for(RealId::Guild(r.guild_id)).is_ok_and(|w| w == wid))
+            .collect::<Vec<_>>();
+
+        match self.emoji_usage_db.record(records).await {
+            Ok(()) => Ok(tonic::Response::new(pb::Empty {})),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn record_activity(&self, request: tonic::Request<pb::WtmRecordUsage>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let req = request.into_inner();
+        let wid = self.verify_worker(req.worker_id)?;
+        let records: Vec<ActivityRecord> = req.records.ok_or_else(|| Status::invalid_argument("Missing records"))?.to_real()?;
+
+        // Workers may only record activity for their own guilds
+        let records = records.into_iter()
+            .filter(|r| self.router.worker_for(RealId::Guild(r.guild_id)).is_ok_and(|w| w == wid))
+            .collect::<Vec<_>>();
+
+        match self.activity_db.record(records).await {
+            Ok(()) => Ok(tonic::Response::new(pb::Empty {})),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn tenant_removed(&self, request: tonic::Request<pb::WtmTenantRemoved>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let req = request.into_inner();
+        self.verify_worker(req.worker_id)?;
+        let id = req.id.ok_or_else(|| Status::invalid_argument("Missing id"))?.to_real_id();
+
+        match self.data_lifecycle_db.schedule_deletion(id, REASON_BOT_REMOVED, None).await {
+            Ok(job) => {
+                log::info!("Scheduled deletion of the data of {id:?} after {}", job.execute_after);
+                Ok(tonic::Response::new(pb::Empty {}))
+            }
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn restore_archive(&self, request: tonic::Request<pb::WtmRestoreArchive>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let req = request.into_inner();
+        self.verify_worker(req.worker_id)?;
+        let id = req.id.ok_or_else(|| Status::invalid_argument("Missing id"))?.to_real_id();
+
+        let outcome = self.archive_db.restore(id).await.map_err(|e| Status::internal(e.to_string()))?;
+        if let Some(ref archive) = outcome.archive {
+            log::info!("Rehydrated {id:?} from its archive of {} records", archive.kv_rows);
+            let details = format!("rehydrated on GUILD_CREATE: {} records", archive.kv_rows);
+            if let Err(e) = self.data_lifecycle_db.record_audit(None, id, "archive_restored", None, Some(details)).await {
+                log::error!("Failed to record audit entry for restore of {id:?}: {e}");
+            }
+        }
+        Ok(tonic::Response::new(pb::AnyValue::from_real(&outcome.tenant_state)?))
+    }
+
+    async fn modmail(&self, request: tonic::Request<pb::WtmModmail>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let req = request.into_inner();
+        self.verify_worker(req.worker_id)?;
+        let modmail_req: ModmailReq = req.req.ok_or_else(|| Status::invalid_argument("Missing req"))?.to_real()?;
+
+        match self.modmail_db.handle(modmail_req).await {
+            Ok(resp) => Ok(tonic::Response::new(pb::AnyValue::from_real(&resp)?)),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn appeals(&self, request: tonic::Request<pb::WtmAppeals>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let req = request.into_inner();
+        self.verify_worker(req.worker_id)?;
+        let appeal_req: AppealReq = req.req.ok_or_else(|| Status::invalid_argument("Missing req"))?.to_real()?;
+
+        match self.appeal_db.handle(appeal_req).await {
+            Ok(resp) => Ok(tonic::Response::new(pb::AnyValue::from_real(&resp)?)),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn invites(&self, request: tonic::Request<pb::WtmInvites>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let req = request.into_inner();
+        self.verify_worker(req.worker_id)?;
+        let invite_req: InviteReq = req.req.ok_or_else(|| Status::invalid_argument("Missing req"))?.to_real()?;
+
+        match self.invite_db.handle(invite_req).await {
+            Ok(resp) => Ok(tonic::Response::new(pb::AnyValue::from_real(&resp)?)),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn sticky_roles(&self, request: tonic::Request<pb::WtmStickyRoles>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let req = request.into_inner();
+        self.verify_worker(req.worker_id)?;
+        let sticky_req: StickyRolesReq = req.req.ok_or_else(|| Status::invalid_argument("Missing req"))?.to_real()?;
+
+        match self.sticky_roles_db.handle(sticky_req).await {
+            Ok(resp) => Ok(tonic::Response::new(pb::AnyValue::from_real(&resp)?)),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn permission_snapshots(&self, request: tonic::Request<pb::WtmPermissionSnapshots>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let req = request.into_inner();
+        self.verify_worker(req.worker_id)?;
+        let snapshot_req: PermissionSnapshotReq = req.req.ok_or_else(|| Status::invalid_argument("Missing req"))?.to_real()?;
+
+        match self.permission_snapshot_db.handle(snapshot_req).await {
+            Ok(resp) => Ok(tonic::Response::new(pb::AnyValue::from_real(&resp)?)),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn enqueue_outbox(&self, request: tonic::Request<pb::WtmEnqueueOutbox>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        let req = request.into_inner();
+        let wid = self.verify_worker(req.worker_id)?;
+        let id = req.id.ok_or_else(|| Status::invalid_argument("Missing ID"))?.to_real_id();
+        if self.router.worker_for(id).map_err(|e| Status::internal(e.to_string()))? != wid {
+            return Err(Status::internal("ID expected worker_id and actual worker_id mismatched"));
+        }
+        let enqueue: OutboxEnqueue = req.req.ok_or_else(|| Status::invalid_argument("Missing req"))?.to_real()?;
+
+        match self.outbox_db.enqueue(id, &enqueue).await {
+            Ok(receipt) => Ok(tonic::Response::new(pb::AnyValue::from_real(&receipt)?)),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn list_feature_flags(&self, _request: tonic::Request<pb::Empty>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        match self.feature_flag_db.list().await {
+            Ok(flags) => Ok(tonic::Response::new(pb::AnyValue::from_real(&flags)?)),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    async fn get_routing_table(&self, _request: tonic::Request<pb::Empty>) -> Result<tonic::Response<pb::AnyValue>, Status> {
+        Ok(tonic::Response::new(pb::AnyValue::from_real(&self.router.table())?))
+    }
+
+    async fn forward_event(&self, request: tonic::Request<pb::DispatchEventReq>) -> Result<tonic::Response<pb::Empty>, Status> {
+        let req = request.into_inner();
+        let id = req.id.ok_or_else(|| Status::invalid_argument("Missing ID"))?.to_real_id();
+        let evt: SimpleEvent = req.event_payload.ok_or_else(|| Status::invalid_argument("Missing event_payload"))?.to_real()?;
+        let parent = telemetry::extract(Some(req.traceparent.as_str()).filter(|t| !t.is_empty()));
+
+        let route = self.router.route(id).await.map_err(|e| Status::internal(e.to_string()))?;
+        let conn = self.get_connection(route.worker_id)
+            .ok_or_else(|| Status::unavailable(format!("No Mesophyll connection found for worker process with ID: {}", route.worker_id)))?;
+        match conn.dispatch_event(id, evt).with_context(parent).await {
+            Ok(_) => Ok(tonic::Response::new(pb::Empty {})),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
 }
 
 type AttachedStreams = Arc<DashMap<RealId, DashMap<String, broadcast::Sender<RealKhronosValue>>>>;
@@ -211,12 +561,14 @@ impl Drop for WorkerConnGuard {
 pub struct WorkerConn {
     id: u64,
     client: pb::mesophyll_worker_client::MesophyllWorkerClient<tonic::transport::Channel>,
-    attached_streams: AttachedStreams
+    attached_streams: AttachedStreams,
+    /// Tenant states last synced to the worker
+    synced: SyncTracker,
 }
 
 impl WorkerConn {
     fn new(id: u64, client: pb::mesophyll_worker_client::MesophyllWorkerClient<tonic::transport::Channel>, attached_streams: AttachedStreams) -> Self {
-        Self { id, client, attached_streams }
+        Self { id, client, attached_streams, synced: SyncTracker::default() }
     }
 
     pub async fn dispatch_event(&self, id: RealId, event: SimpleEvent) -> Result<RealKhronosValue, crate::Error> {
@@ -225,6 +577,7 @@ impl WorkerConn {
         let msg = pb::DispatchEventReq {
             id: Some(pb::Id::from_real_id(&id)),
             event_payload: Some(pb_event),
+            traceparent: telemetry::inject(&Context::current()).unwrap_or_default(),
         };
 
         let mut cli = self.client.clone();
@@ -236,6 +589,7 @@ impl WorkerConn {
     }
     
     pub async fn drop_tenant(&self, id: RealId) -> Result<(), crate::Error> {
+        self.synced.forget(id);
         let mut cli = self.client.clone();
         cli.drop_tenant(pb::Id::from_real_id(&id))
             .await
@@ -243,17 +597,92 @@ impl WorkerConn {
         Ok(())
     }
 
+    /// Syncs the tenant state of a tenant to the worker, as a diff against what the worker last acknowledged
+    /// where possible
+    ///
+    /// Returns if the VM of the tenant was dropped as a result
     pub async fn update_tenant_state(&self, id: RealId, tenant_state: TenantState) -> Result<bool, crate::Error> {
+        let sync = self.synced.update(id, tenant_state)?;
+        match self.send_tenant_state(id, &sync).await? {
+            SyncOutcome::Applied { reloaded } => Ok(reloaded),
+            SyncOutcome::Stale => Ok(false),
+            SyncOutcome::Resync => {
+                // The latest tenant state, which may be newer than this update
+                let sync = self.synced.full(id).ok_or("Tenant state to resync was forgotten")?;
+                match self.send_tenant_state(id, &sync).await? {
+                    SyncOutcome::Applied { reloaded } => Ok(reloaded),
+                    SyncOutcome::Stale => Ok(false),
+                    SyncOutcome::Resync => Err(format!("Worker {} requested a resync of a full tenant state", self.id).into()),
+                }
+            }
+        }
+    }
+
+    async fn send_tenant_state(&self, id: RealId, sync: &TenantStateSync) -> Result<SyncOutcome, crate::Error> {
         let msg = pb::UpdateTenantStateReq {
             id: Some(pb::Id::from_real_id(&id)),
-            new_tenant_state: Some(pb::AnyValue::from_real(&tenant_state)?)
+            sync: Some(pb::AnyValue::from_real(sync)?)
         };
         let mut cli = self.client.clone();
-        let resp = cli.update_tenant_state(msg)
+        cli.update_tenant_state(msg)
             .await
             .map_err(|e| e.to_string())?
-            .into_inner();
-        Ok(resp.b)
+            .into_inner()
+            .to_real_exec()
+    }
+
+    pub async fn update_feature_flags(&self, flags: &FeatureFlags) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        cli.update_feature_flags(pb::AnyValue::from_real(flags)?)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub async fn update_routing_table(&self, table: &RoutingTable) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        cli.update_routing_table(pb::AnyValue::from_real(table)?)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    pub async fn invalidate_templates(&self, inv: &TemplateInvalidation) -> Result<(), crate::Error> {
+        let mut cli = self.client.clone();
+        cli.invalidate_templates(pb::AnyValue::from_real(inv)?)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Drains the worker before shutdown, waiting up to `timeout` for running executions
+    pub async fn drain(&self, timeout: std::time::Duration) -> Result<DrainReport, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.drain(pb::AnyValue::from_real(&DrainReq { timeout_ms: timeout.as_millis() as u64 })?)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner()
+            .to_real_exec()
+    }
+
+    /// Samples the load of the worker since the last sample
+    pub async fn get_load(&self) -> Result<WorkerLoad, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.get_load(pb::Empty {})
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner()
+            .to_real_exec()
+    }
+
+    /// Returns the durations (in milliseconds) of the most recent dispatches of the worker, oldest first
+    pub async fn get_recent_dispatches(&self) -> Result<Vec<u64>, crate::Error> {
+        let mut cli = self.client.clone();
+        cli.get_recent_dispatches(pb::Empty {})
+            .await
+            .map_err(|e| e.to_string())?
+            .into_inner()
+            .to_real_exec()
     }
 
     pub async fn shutdown(&self) -> Result<(), crate::Error> {
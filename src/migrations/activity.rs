@@ -0,0 +1,32 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "activity",
+    description: "Add channel_activity_hourly rollup table",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE channel_activity_hourly (
+                    guild_id TEXT NOT NULL,
+                    channel_id TEXT NOT NULL,
+                    hour TIMESTAMPTZ NOT NULL,
+                    messages BIGINT NOT NULL DEFAULT 0,
+                    PRIMARY KEY (guild_id, channel_id, hour)
+                );",
+                "CREATE INDEX channel_activity_hourly_guild_hour_idx ON channel_activity_hourly (guild_id, hour);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
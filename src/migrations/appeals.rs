@@ -0,0 +1,43 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "appeals",
+    description: "Add appeals table",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE appeals (
+                    id UUID PRIMARY KEY,
+                    guild_id TEXT NOT NULL,
+                    user_id TEXT NOT NULL,
+                    punishment TEXT NOT NULL,
+                    reason TEXT NOT NULL,
+                    evidence TEXT[] NOT NULL DEFAULT '{}',
+                    source TEXT NOT NULL,
+                    state TEXT NOT NULL DEFAULT 'open',
+                    channel_id TEXT,
+                    message_id TEXT,
+                    moderator_id TEXT,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    resolved_at TIMESTAMPTZ
+                );",
+                // A user can only have one unresolved appeal per guild at a time
+                "CREATE UNIQUE INDEX appeals_unresolved_user_idx ON appeals (guild_id, user_id) WHERE state IN ('open', 'under_review');",
+                "CREATE INDEX appeals_guild_idx ON appeals (guild_id, created_at);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
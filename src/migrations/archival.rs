@@ -0,0 +1,35 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "archival",
+    description: "Add tenant_archives for cold storage of inactive tenants and archived_at to tenant_state",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "ALTER TABLE tenant_state ADD COLUMN archived_at TIMESTAMPTZ;",
+                "CREATE TABLE tenant_archives (
+                    owner_id TEXT NOT NULL,
+                    owner_type TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    size_bytes BIGINT NOT NULL,
+                    kv_rows BIGINT NOT NULL,
+                    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    restored_at TIMESTAMPTZ,
+                    PRIMARY KEY (owner_id, owner_type)
+                );",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,31 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "bot_branding",
+    description: "Add bot_branding table tracking the branding assets last synced to Discord",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                // One row per synced asset (`banner` or `emoji:{name}`), digest is the sha256 of the uploaded image
+                "CREATE TABLE bot_branding (
+                    asset TEXT PRIMARY KEY,
+                    digest TEXT NOT NULL,
+                    discord_id TEXT,
+                    synced_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                );",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,35 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "bot_votes",
+    description: "Add bot_votes table for votes received from bot lists",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE bot_votes (
+                    id BIGSERIAL PRIMARY KEY,
+                    list TEXT NOT NULL,
+                    user_id TEXT NOT NULL,
+                    guild_id TEXT,
+                    is_test BOOLEAN NOT NULL DEFAULT FALSE,
+                    is_weekend BOOLEAN NOT NULL DEFAULT FALSE,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                );",
+                "CREATE INDEX bot_votes_user_idx ON bot_votes (user_id, created_at);",
+                "CREATE INDEX bot_votes_guild_idx ON bot_votes (guild_id, created_at) WHERE guild_id IS NOT NULL;",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,78 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "change_notify",
+    description: "Add triggers notifying masters of tenant state, template, feature flag and shop changes",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                r#"
+                CREATE FUNCTION tw_notify_tenant_state() RETURNS trigger AS $$
+                DECLARE
+                    r RECORD;
+                BEGIN
+                    IF TG_OP = 'DELETE' THEN r := OLD; ELSE r := NEW; END IF;
+                    PERFORM pg_notify('tw_tenant_state', json_build_object('owner_type', r.owner_type, 'owner_id', r.owner_id)::text);
+                    RETURN NULL;
+                END;
+                $$ LANGUAGE plpgsql;
+                "#,
+                "CREATE TRIGGER tenant_state_notify AFTER INSERT OR UPDATE OR DELETE ON tenant_state FOR EACH ROW EXECUTE FUNCTION tw_notify_tenant_state();",
+                "CREATE TRIGGER tenant_state_events_notify AFTER INSERT OR UPDATE OR DELETE ON tenant_state_events FOR EACH ROW EXECUTE FUNCTION tw_notify_tenant_state();",
+                r#"
+                CREATE FUNCTION tw_notify_templates() RETURNS trigger AS $$
+                DECLARE
+                    r RECORD;
+                BEGIN
+                    IF TG_OP = 'DELETE' THEN r := OLD; ELSE r := NEW; END IF;
+                    IF r.scope = 'builtins.templates' THEN
+                        PERFORM pg_notify('tw_templates', json_build_object('owner_type', r.owner_type, 'owner_id', r.owner_id)::text);
+                    END IF;
+                    RETURN NULL;
+                END;
+                $$ LANGUAGE plpgsql;
+                "#,
+                "CREATE TRIGGER tenant_kv_templates_notify AFTER INSERT OR UPDATE OR DELETE ON tenant_kv FOR EACH ROW EXECUTE FUNCTION tw_notify_templates();",
+                r#"
+                CREATE FUNCTION tw_notify_feature_flags() RETURNS trigger AS $$
+                BEGIN
+                    PERFORM pg_notify('tw_feature_flags', '');
+                    RETURN NULL;
+                END;
+                $$ LANGUAGE plpgsql;
+                "#,
+                "CREATE TRIGGER feature_flags_notify AFTER INSERT OR UPDATE OR DELETE ON feature_flags FOR EACH STATEMENT EXECUTE FUNCTION tw_notify_feature_flags();",
+                r#"
+                CREATE FUNCTION tw_notify_global_kv() RETURNS trigger AS $$
+                DECLARE
+                    r RECORD;
+                BEGIN
+                    IF TG_OP = 'DELETE' THEN r := OLD; ELSE r := NEW; END IF;
+                    IF TG_OP = 'UPDATE' AND OLD.review_state IS NOT DISTINCT FROM NEW.review_state THEN
+                        RETURN NULL;
+                    END IF;
+                    PERFORM pg_notify('tw_global_kv', json_build_object(
+                        'op', lower(TG_OP), 'key', r.key, 'version', r.version, 'scope', r.scope,
+                        'owner_type', r.owner_type, 'owner_id', r.owner_id, 'review_state', r.review_state
+                    )::text);
+                    RETURN NULL;
+                END;
+                $$ LANGUAGE plpgsql;
+                "#,
+                "CREATE TRIGGER global_kv_notify AFTER INSERT OR UPDATE OR DELETE ON global_kv FOR EACH ROW EXECUTE FUNCTION tw_notify_global_kv();",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
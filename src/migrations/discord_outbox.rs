@@ -0,0 +1,41 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "discord_outbox",
+    description: "Add discord_outbox table for durably queued Discord actions",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE discord_outbox (
+                    id BIGSERIAL PRIMARY KEY,
+                    owner_id TEXT NOT NULL,
+                    owner_type TEXT NOT NULL,
+                    idempotency_key TEXT NOT NULL,
+                    action JSONB NOT NULL,
+                    state TEXT NOT NULL DEFAULT 'pending',
+                    attempts INTEGER NOT NULL DEFAULT 0,
+                    last_error TEXT,
+                    result JSONB,
+                    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    completed_at TIMESTAMPTZ
+                );",
+                // An action is only ever enqueued once per idempotency key
+                "CREATE UNIQUE INDEX discord_outbox_key_idx ON discord_outbox (owner_id, owner_type, idempotency_key);",
+                "CREATE INDEX discord_outbox_due_idx ON discord_outbox (next_attempt_at) WHERE state = 'pending';",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,35 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "emoji_usage",
+    description: "Add emoji_usage_daily rollup table",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE emoji_usage_daily (
+                    guild_id TEXT NOT NULL,
+                    kind TEXT NOT NULL,
+                    item_id TEXT NOT NULL,
+                    day TIMESTAMPTZ NOT NULL,
+                    name TEXT NOT NULL,
+                    message_uses BIGINT NOT NULL DEFAULT 0,
+                    reaction_uses BIGINT NOT NULL DEFAULT 0,
+                    PRIMARY KEY (guild_id, kind, item_id, day)
+                );",
+                "CREATE INDEX emoji_usage_daily_guild_day_idx ON emoji_usage_daily (guild_id, day);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,35 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "invite_tracking",
+    description: "Add invite_joins table",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE invite_joins (
+                    guild_id TEXT NOT NULL,
+                    user_id TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    code TEXT,
+                    inviter_id TEXT,
+                    joined_at TIMESTAMPTZ NOT NULL,
+                    PRIMARY KEY (guild_id, user_id)
+                );",
+                "CREATE INDEX invite_joins_guild_joined_idx ON invite_joins (guild_id, joined_at);",
+                "CREATE INDEX invite_joins_guild_code_idx ON invite_joins (guild_id, code);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
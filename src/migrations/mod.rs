@@ -11,6 +11,28 @@ mod user_oauth_v2;
 mod tenant_state_drop_flags;
 mod tenant_kv_add_bytea;
 mod migrate_backups;
+mod feature_flags;
+mod template_usage_daily;
+mod tenantstate_add_limits;
+mod tenant_data_jobs;
+mod modmail;
+mod bot_branding;
+mod plugin_usage_hourly;
+mod discord_outbox;
+mod bot_votes;
+mod inbound_webhooks;
+mod change_notify;
+mod workflows;
+mod appeals;
+mod watchlist;
+mod invites;
+mod stickyroles;
+mod permsnapshots;
+mod scheduled_messages;
+mod threadpolicies;
+mod emojiusage;
+mod activity;
+mod archival;
 
 use std::borrow::Cow;
 
@@ -27,7 +49,7 @@ use crate::master::mainthread::{RunInThreadFn, run_in_thread};
 
 // Rust migrations that must run before the luau migrations in `luau/twshell/migrations/`.
 // Do not change order without verifying dependencies.
-const MIGRATIONS: [MigrationType; 14] = [
+const MIGRATIONS: [MigrationType; 36] = [
     MigrationType::Rust(khronosvalue_v2::MIGRATION),
     MigrationType::Rust(kv_generic::MIGRATION),
     MigrationType::Rust(tenantstate::MIGRATION),
@@ -42,6 +64,28 @@ const MIGRATIONS: [MigrationType; 14] = [
     MigrationType::Luau(Cow::Borrowed("stings.luau")),
     MigrationType::Rust(kv_scope_unnest::MIGRATION),
     MigrationType::Rust(migrate_backups::MIGRATION),
+    MigrationType::Rust(feature_flags::MIGRATION),
+    MigrationType::Rust(template_usage_daily::MIGRATION),
+    MigrationType::Rust(tenantstate_add_limits::MIGRATION),
+    MigrationType::Rust(tenant_data_jobs::MIGRATION),
+    MigrationType::Rust(modmail::MIGRATION),
+    MigrationType::Rust(bot_branding::MIGRATION),
+    MigrationType::Rust(plugin_usage_hourly::MIGRATION),
+    MigrationType::Rust(discord_outbox::MIGRATION),
+    MigrationType::Rust(bot_votes::MIGRATION),
+    MigrationType::Rust(inbound_webhooks::MIGRATION),
+    MigrationType::Rust(change_notify::MIGRATION),
+    MigrationType::Rust(workflows::MIGRATION),
+    MigrationType::Rust(appeals::MIGRATION),
+    MigrationType::Rust(watchlist::MIGRATION),
+    MigrationType::Rust(invites::MIGRATION),
+    MigrationType::Rust(stickyroles::MIGRATION),
+    MigrationType::Rust(permsnapshots::MIGRATION),
+    MigrationType::Rust(scheduled_messages::MIGRATION),
+    MigrationType::Rust(threadpolicies::MIGRATION),
+    MigrationType::Rust(emojiusage::MIGRATION),
+    MigrationType::Rust(activity::MIGRATION),
+    MigrationType::Rust(archival::MIGRATION),
 ];
 
 #[derive(Embed, Debug)]
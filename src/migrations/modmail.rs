@@ -0,0 +1,50 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "modmail",
+    description: "Add settings (holding the modmail channel) to tenant_state and modmail_sessions/modmail_messages tables",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                // Per-subsystem config of the tenant (see `TenantSettings`), starting with the modmail channel
+                "ALTER TABLE tenant_state ADD COLUMN settings JSONB NOT NULL DEFAULT '{}';",
+                "CREATE TABLE modmail_sessions (
+                    id UUID PRIMARY KEY,
+                    guild_id TEXT NOT NULL,
+                    user_id TEXT NOT NULL,
+                    thread_id TEXT NOT NULL,
+                    state TEXT NOT NULL DEFAULT 'open',
+                    opened_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    closed_at TIMESTAMPTZ,
+                    closed_by TEXT,
+                    transcript_key TEXT
+                );",
+                // A user can only have one open session at a time, which their DMs are relayed to
+                "CREATE UNIQUE INDEX modmail_sessions_open_user_idx ON modmail_sessions (user_id) WHERE state = 'open';",
+                "CREATE INDEX modmail_sessions_thread_idx ON modmail_sessions (guild_id, thread_id);",
+                "CREATE TABLE modmail_messages (
+                    id BIGSERIAL PRIMARY KEY,
+                    session_id UUID NOT NULL REFERENCES modmail_sessions(id) ON DELETE CASCADE,
+                    direction TEXT NOT NULL,
+                    author_id TEXT NOT NULL,
+                    content TEXT NOT NULL,
+                    attachments TEXT[] NOT NULL DEFAULT '{}',
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                );",
+                "CREATE INDEX modmail_messages_session_idx ON modmail_messages (session_id, created_at);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,30 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "permission_snapshots",
+    description: "Add the last permission snapshot to tenant_state and permission_snapshots table",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "ALTER TABLE tenant_state ADD COLUMN permission_snapshot_at TIMESTAMPTZ;",
+                "CREATE TABLE permission_snapshots (
+                    guild_id TEXT PRIMARY KEY,
+                    snapshot JSONB NOT NULL,
+                    taken_at TIMESTAMPTZ NOT NULL
+                );",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
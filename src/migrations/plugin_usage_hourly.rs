@@ -0,0 +1,35 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "plugin_usage_hourly",
+    description: "Add plugin_usage_hourly rollup table for plugin method usage analytics",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                // bucket is a hash bucket of the tenant, tenant IDs are intentionally not stored
+                "CREATE TABLE plugin_usage_hourly (
+                    plugin TEXT NOT NULL,
+                    method TEXT NOT NULL,
+                    bucket SMALLINT NOT NULL,
+                    hour TIMESTAMPTZ NOT NULL,
+                    calls BIGINT NOT NULL DEFAULT 0,
+                    errors BIGINT NOT NULL DEFAULT 0,
+                    PRIMARY KEY (plugin, method, bucket, hour)
+                );",
+                "CREATE INDEX plugin_usage_hourly_hour_idx ON plugin_usage_hourly (hour);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
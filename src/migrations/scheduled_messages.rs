@@ -0,0 +1,42 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "scheduled_messages",
+    description: "Add scheduled_messages table for recurring announcements",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE scheduled_messages (
+                    id BIGSERIAL PRIMARY KEY,
+                    guild_id TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    channel_id TEXT NOT NULL,
+                    message JSONB NOT NULL,
+                    cron TEXT NOT NULL,
+                    timezone TEXT NOT NULL DEFAULT 'UTC',
+                    paused BOOLEAN NOT NULL DEFAULT FALSE,
+                    next_run_at TIMESTAMPTZ,
+                    attempts INTEGER NOT NULL DEFAULT 0,
+                    last_error TEXT,
+                    last_run_at TIMESTAMPTZ,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    UNIQUE (guild_id, name)
+                );",
+                "CREATE INDEX scheduled_messages_due_idx ON scheduled_messages (next_run_at) WHERE NOT paused;",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
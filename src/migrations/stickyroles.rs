@@ -0,0 +1,33 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "sticky_roles",
+    description: "Add sticky_roles table",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE sticky_roles (
+                    guild_id TEXT NOT NULL,
+                    user_id TEXT NOT NULL,
+                    roles TEXT[] NOT NULL,
+                    nick TEXT,
+                    left_at TIMESTAMPTZ NOT NULL,
+                    PRIMARY KEY (guild_id, user_id)
+                );",
+                "CREATE INDEX sticky_roles_guild_left_idx ON sticky_roles (guild_id, left_at);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,38 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "template_usage_daily",
+    description: "Add template_usage_daily rollup table for per-template execution accounting",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE template_usage_daily (
+                    owner_id TEXT NOT NULL,
+                    owner_type TEXT NOT NULL,
+                    template TEXT NOT NULL,
+                    day DATE NOT NULL,
+                    executions BIGINT NOT NULL DEFAULT 0,
+                    errors BIGINT NOT NULL DEFAULT 0,
+                    wall_time_ms BIGINT NOT NULL DEFAULT 0,
+                    memory_peak_bytes BIGINT NOT NULL DEFAULT 0,
+                    discord_api_calls BIGINT NOT NULL DEFAULT 0,
+                    kv_ops BIGINT NOT NULL DEFAULT 0,
+                    PRIMARY KEY (owner_id, owner_type, template, day)
+                );",
+                "CREATE INDEX template_usage_daily_day_idx ON template_usage_daily (day);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,53 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "tenant_data_jobs",
+    description: "Add tenant_data_jobs and tenant_data_audit tables for tenant data deletion and export",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE tenant_data_jobs (
+                    id UUID PRIMARY KEY,
+                    owner_id TEXT NOT NULL,
+                    owner_type TEXT NOT NULL,
+                    kind TEXT NOT NULL,
+                    state TEXT NOT NULL DEFAULT 'pending',
+                    reason TEXT NOT NULL,
+                    requested_by TEXT,
+                    execute_after TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    steps_done TEXT[] NOT NULL DEFAULT '{}',
+                    error TEXT,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    completed_at TIMESTAMPTZ
+                );",
+                // At most one outstanding job of each kind per tenant
+                "CREATE UNIQUE INDEX tenant_data_jobs_outstanding_idx ON tenant_data_jobs (owner_id, owner_type, kind) WHERE state IN ('pending', 'running');",
+                "CREATE INDEX tenant_data_jobs_due_idx ON tenant_data_jobs (execute_after) WHERE state IN ('pending', 'running');",
+                // Audit records are kept after a tenant's data is deleted and so must not contain any of it
+                "CREATE TABLE tenant_data_audit (
+                    id BIGSERIAL PRIMARY KEY,
+                    job_id UUID,
+                    owner_id TEXT NOT NULL,
+                    owner_type TEXT NOT NULL,
+                    action TEXT NOT NULL,
+                    actor TEXT,
+                    details TEXT,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                );",
+                "CREATE INDEX tenant_data_audit_owner_idx ON tenant_data_audit (owner_id, owner_type, created_at);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
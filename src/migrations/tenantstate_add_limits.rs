@@ -0,0 +1,27 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "tenantstate_add_limits",
+    description: "Add per-tenant VM limit overrides",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "ALTER TABLE tenant_state ADD COLUMN memory_limit BIGINT CHECK (memory_limit > 0);",
+                "ALTER TABLE tenant_state ADD COLUMN execution_time_limit_ms BIGINT CHECK (execution_time_limit_ms > 0);",
+                "ALTER TABLE tenant_state ADD COLUMN return_wait_ms BIGINT CHECK (return_wait_ms > 0);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,35 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "watchlist",
+    description: "Add watchlist table and the last watchlist digest to tenant_state",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "ALTER TABLE tenant_state ADD COLUMN watchlist_digest_at TIMESTAMPTZ;",
+                "CREATE TABLE watchlist (
+                    guild_id TEXT NOT NULL,
+                    user_id TEXT NOT NULL,
+                    severity TEXT NOT NULL,
+                    note TEXT NOT NULL,
+                    added_by TEXT,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    PRIMARY KEY (guild_id, user_id)
+                );",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
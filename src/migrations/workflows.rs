@@ -0,0 +1,48 @@
+use crate::migrations::Migration;
+
+pub static MIGRATION: Migration = Migration {
+    id: "workflows",
+    description: "Add workflow_instances table for persisted multi-step template workflows",
+    up: |pool| {
+        Box::pin(async move {
+            let mut tx = pool.begin().await?;
+
+            let stmts = [
+                "CREATE TABLE workflow_instances (
+                    id BIGSERIAL PRIMARY KEY,
+                    owner_id TEXT NOT NULL,
+                    owner_type TEXT NOT NULL,
+                    workflow TEXT NOT NULL,
+                    key TEXT NOT NULL,
+                    step TEXT NOT NULL,
+                    data JSONB NOT NULL,
+                    state TEXT NOT NULL DEFAULT 'running',
+                    seq INTEGER NOT NULL DEFAULT 0,
+                    trigger TEXT NOT NULL DEFAULT 'start',
+                    wake_at TIMESTAMPTZ DEFAULT NOW(),
+                    wait_signal TEXT,
+                    signal_data JSONB,
+                    attempts INTEGER NOT NULL DEFAULT 0,
+                    last_error TEXT,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    completed_at TIMESTAMPTZ
+                );",
+                // Only one running instance of a workflow per key
+                "CREATE UNIQUE INDEX workflow_instances_key_idx ON workflow_instances (owner_id, owner_type, workflow, key) WHERE state = 'running';",
+                "CREATE INDEX workflow_instances_due_idx ON workflow_instances (wake_at) WHERE state = 'running';",
+                "CREATE INDEX workflow_instances_owner_idx ON workflow_instances (owner_id, owner_type, created_at);",
+            ];
+
+            for stmt in stmts.iter() {
+                sqlx::query(stmt)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    },
+};
@@ -0,0 +1,63 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use tokio::sync::watch;
+
+/// Aborts the in-flight syscalls of a VM (and with them the SQL they run on the master) when its executions are
+/// aborted
+///
+/// Syscalls can't be attributed to the execution which made them as executions share the VM (and may spawn
+/// threads), so an execution timing out only aborts the syscalls of the VM if no other execution is running on it.
+/// Killing the VM always aborts them
+pub struct AbortSignal {
+    /// Bumped on every abort, syscalls are aborted if it changes while they run
+    tx: watch::Sender<u64>,
+    running: Cell<usize>,
+}
+
+/// Marks an execution as running until dropped
+pub struct ExecutionGuard(Rc<AbortSignal>);
+
+impl Drop for ExecutionGuard {
+    fn drop(&mut self) {
+        self.0.running.set(self.0.running.get() - 1);
+    }
+}
+
+impl AbortSignal {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Self { tx: watch::Sender::new(0), running: Cell::new(0) })
+    }
+
+    /// Marks an execution as running until the returned guard is dropped
+    pub fn execution(self: &Rc<Self>) -> ExecutionGuard {
+        self.running.set(self.running.get() + 1);
+        ExecutionGuard(self.clone())
+    }
+
+    /// Returns the number of executions running on the VM
+    pub fn running(&self) -> usize {
+        self.running.get()
+    }
+
+    /// Aborts the syscalls in flight, syscalls made afterwards are unaffected
+    pub fn abort(&self) {
+        self.tx.send_modify(|generation| *generation += 1);
+    }
+
+    /// Aborts the syscalls in flight if the (timed out) execution calling this is the only one running
+    pub fn abort_execution(&self) {
+        if self.running.get() <= 1 {
+            self.abort();
+        }
+    }
+
+    /// Runs a syscall, failing it (and dropping its future) if aborted before it completes
+    pub async fn guard<T>(&self, fut: impl Future<Output = Result<T, crate::Error>>) -> Result<T, crate::Error> {
+        let mut rx = self.tx.subscribe();
+        tokio::select! {
+            res = fut => res,
+            _ = rx.changed() => Err("Syscall aborted as its execution was aborted".into()),
+        }
+    }
+}
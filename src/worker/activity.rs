@@ -0,0 +1,30 @@
+use dapi::{ChannelId, GuildId};
+use serde_json::Value;
+
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Counts the messages sent per channel of guilds tracking message activity
+///
+/// Counts are accumulated per hour by the worker state and flushed to the master periodically. Messages of bots and
+/// webhooks are only counted if the guild includes them
+pub struct Activity;
+
+impl Activity {
+    /// Counts the message of a MESSAGE_CREATE payload
+    pub fn observe_message(dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.activity(Id::Guild(guild_id)) else {
+            return;
+        };
+        let bot = payload.get("author").and_then(|a| a.get("bot")).and_then(|v| v.as_bool()).unwrap_or(false)
+            || payload.get("webhook_id").is_some_and(|w| !w.is_null());
+        if bot && !config.include_bots {
+            return;
+        }
+        let Some(channel_id) = payload.get("channel_id").and_then(|v| v.as_str()).and_then(|v| v.parse::<ChannelId>().ok()) else {
+            return;
+        };
+
+        dispatch.worker_state.activity.record(guild_id, channel_id);
+    }
+}
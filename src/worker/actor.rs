@@ -0,0 +1,114 @@
+use dapi::UserId;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde_json::Value;
+
+/// Information about who (or what) performed the action that caused an event to be dispatched
+///
+/// This is derived from the event payload on a best-effort basis, events with no
+/// derivable actor (e.g. GUILD_MEMBER_UPDATE which may be caused by a moderator) will not have one set
+#[derive(Debug, Clone, Default)]
+pub struct EventActor {
+    /// The user who performed the action, if known
+    pub user_id: Option<UserId>,
+    /// Whether the action was performed by AntiRaid itself
+    pub is_self: bool,
+    /// Whether the action was performed by a bot (or webhook)
+    pub is_bot: bool,
+    /// The ID of the application responsible for the action, if derivable
+    pub application_id: Option<String>,
+}
+
+impl EventActor {
+    /// Creates an actor for an event which was explicitly authorized by a user (e.g. through the website)
+    pub fn from_author(author: UserId, bot_id: UserId) -> Self {
+        Self {
+            user_id: Some(author),
+            is_self: author == bot_id,
+            is_bot: author == bot_id,
+            application_id: None,
+        }
+    }
+
+    /// Creates an actor for an event whose executor was found in the audit log
+    pub fn from_audit_log(user_id: UserId, is_bot: bool, bot_id: UserId) -> Self {
+        let is_self = user_id == bot_id;
+        Self {
+            user_id: Some(user_id),
+            is_self,
+            is_bot: is_self || is_bot,
+            application_id: if is_self { Some(bot_id.to_string()) } else { None },
+        }
+    }
+
+    /// Derives the actor of a gateway event from its (json) payload
+    pub fn from_payload(event_name: &str, payload: &Value, bot_id: UserId) -> Option<Self> {
+        let (user_id, is_bot) = match event_name {
+            "MESSAGE_CREATE" | "MESSAGE_UPDATE" => Self::user_object(payload.get("author")?),
+            "INTERACTION_CREATE" => {
+                let user = payload.get("member").and_then(|m| m.get("user")).or_else(|| payload.get("user"))?;
+                Self::user_object(user)
+            }
+            "GUILD_MEMBER_ADD" => Self::user_object(payload.get("user")?),
+            "INVITE_CREATE" => Self::user_object(payload.get("inviter")?),
+            "GUILD_SCHEDULED_EVENT_CREATE" | "GUILD_SCHEDULED_EVENT_UPDATE" => {
+                match payload.get("creator") {
+                    Some(creator) => Self::user_object(creator),
+                    None => (Self::snowflake(payload.get("creator_id")?), None),
+                }
+            }
+            "AUTO_MODERATION_RULE_CREATE" | "AUTO_MODERATION_RULE_UPDATE" | "AUTO_MODERATION_RULE_DELETE" => {
+                (Self::snowflake(payload.get("creator_id")?), None)
+            }
+            "THREAD_CREATE" => (Self::snowflake(payload.get("owner_id")?), None),
+            "MESSAGE_REACTION_ADD" | "MESSAGE_REACTION_REMOVE" | "MESSAGE_POLL_VOTE_ADD" | "MESSAGE_POLL_VOTE_REMOVE"
+            | "TYPING_START" | "VOICE_STATE_UPDATE" | "GUILD_AUDIT_LOG_ENTRY_CREATE" => {
+                let is_bot = payload.get("member")
+                    .and_then(|m| m.get("user"))
+                    .and_then(|u| u.get("bot"))
+                    .and_then(|b| b.as_bool());
+                (Self::snowflake(payload.get("user_id")?), is_bot)
+            }
+            _ => return None,
+        };
+
+        let user_id = user_id?;
+        let is_self = user_id == bot_id;
+
+        // Messages sent by webhooks or through an application (interaction responses etc.) are bot-originated
+        let webhook = payload.get("webhook_id").is_some_and(|w| !w.is_null());
+        let application_id = match event_name {
+            "MESSAGE_CREATE" | "MESSAGE_UPDATE" => payload.get("application_id").and_then(|a| a.as_str()).map(|a| a.to_string()),
+            _ => None,
+        };
+
+        Some(Self {
+            user_id: Some(user_id),
+            is_self,
+            is_bot: is_self || webhook || is_bot.unwrap_or(false),
+            application_id: application_id.or_else(|| if is_self { Some(bot_id.to_string()) } else { None }),
+        })
+    }
+
+    /// Extracts the user ID and bot flag from a discord user object
+    fn user_object(user: &Value) -> (Option<UserId>, Option<bool>) {
+        let id = user.get("id").and_then(Self::snowflake);
+        let is_bot = user.get("bot").and_then(|b| b.as_bool());
+        (id, is_bot)
+    }
+
+    fn snowflake(value: &Value) -> Option<UserId> {
+        value.as_str()?.parse::<UserId>().ok()
+    }
+}
+
+impl IntoLua for EventActor {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table_with_capacity(0, 4)?;
+        table.set("user_id", self.user_id.map(|u| u.to_string()))?;
+        table.set("is_self", self.is_self)?;
+        table.set("is_bot", self.is_bot)?;
+        table.set("application_id", self.application_id)?;
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
@@ -0,0 +1,295 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, UserId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Event dispatched to a guild when a member who just joined is suspected to be an alt of other recent joiners
+pub const ALT_SUSPECTED_EVENT: &str = "AltSuspected";
+
+/// Internal event explaining the assessment of a member, dispatched to the guild by the API
+pub const ALT_EXPLAIN_EVENT: &str = "$AltExplain";
+
+/// How long joins are kept for correlating later joins with
+const JOIN_WINDOW: chrono::Duration = chrono::Duration::minutes(30);
+
+/// Maximum number of joins kept per guild, the oldest are dropped first
+const MAX_TRACKED_JOINS: usize = 200;
+
+/// Maximum number of assessments kept per guild for explaining
+const MAX_KEPT_ASSESSMENTS: usize = 100;
+
+/// Joins further apart than this are not considered close in time
+const PROXIMITY_WINDOW_SECS: f64 = 120.0;
+
+/// Minimum Jaro-Winkler similarity of two (normalized) usernames to be considered alike
+const MIN_USERNAME_SIMILARITY: f64 = 0.85;
+
+/// Weights of the signals, the confidence of a match is `1 - Π(1 - weight)` over the signals found
+const PROXIMITY_WEIGHT: f64 = 0.25;
+const USERNAME_WEIGHT: f64 = 0.35;
+const AVATAR_WEIGHT: f64 = 0.4;
+const INVITE_WEIGHT: f64 = 0.15;
+
+/// Matches below this confidence are not reported
+const MIN_MATCH_CONFIDENCE: f64 = 0.3;
+
+/// Maximum number of matches reported per assessment
+const MAX_MATCHES: usize = 5;
+
+/// How readily members are flagged as alts, alt detection is disabled for guilds without a sensitivity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AltSensitivity {
+    Low,
+    Medium,
+    High,
+}
+
+impl AltSensitivity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, crate::Error> {
+        match s {
+            "low" => Ok(Self::Low),
+            "medium" => Ok(Self::Medium),
+            "high" => Ok(Self::High),
+            _ => Err(format!("Unknown alt detection sensitivity: {s}").into()),
+        }
+    }
+
+    /// Confidence a member must be suspected with to be flagged
+    pub fn threshold(self) -> f64 {
+        match self {
+            Self::Low => 0.75,
+            Self::Medium => 0.6,
+            Self::High => 0.45,
+        }
+    }
+}
+
+/// A signal linking two joins
+#[derive(Debug, Clone, Serialize)]
+pub struct Evidence {
+    /// `join_proximity`, `username`, `avatar` or `invite`
+    pub signal: &'static str,
+    /// Human readable description of the signal
+    pub detail: String,
+    pub weight: f64,
+}
+
+/// A recent joiner a member is suspected to be an alt of (or share an owner with)
+#[derive(Debug, Clone, Serialize)]
+pub struct AltMatch {
+    pub user_id: UserId,
+    pub confidence: f64,
+    pub evidence: Vec<Evidence>,
+}
+
+/// The assessment of a member on joining
+#[derive(Debug, Clone, Serialize)]
+pub struct AltAssessment {
+    pub user_id: UserId,
+    /// Confidence of the strongest match
+    pub confidence: f64,
+    pub sensitivity: AltSensitivity,
+    pub threshold: f64,
+    /// Whether `AltSuspected` was dispatched for the member
+    pub flagged: bool,
+    /// Strongest matches first
+    pub matches: Vec<AltMatch>,
+    pub assessed_at: DateTime<Utc>,
+}
+
+/// Data of `AltSuspected`
+struct AltSuspectedData {
+    assessment: AltAssessment,
+}
+
+impl IntoLua for AltSuspectedData {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self.assessment, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Data of `$AltExplain`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AltExplainReq {
+    pub user_id: UserId,
+}
+
+struct Join {
+    user_id: UserId,
+    username: String,
+    avatar: Option<String>,
+    invite: Option<String>,
+    joined_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct GuildJoins {
+    joins: VecDeque<Join>,
+    assessments: VecDeque<AltAssessment>,
+}
+
+/// Flags probable alt accounts by correlating each member joining a guild with its other recent joiners
+///
+/// Joins are correlated by how close in time they were, how alike their usernames are, their avatars and the
+/// invite they used (as attributed by the `InviteTracker`). Joins and assessments are only kept in the memory of
+/// the guild's worker, so are lost on restart
+#[derive(Clone, Default)]
+pub struct AltDetector {
+    guilds: Rc<RefCell<HashMap<GuildId, GuildJoins>>>,
+}
+
+impl AltDetector {
+    /// Assesses a member in the background given its GUILD_MEMBER_ADD payload and the invite they used, if alt
+    /// detection is enabled
+    pub fn start(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value, invite: Option<String>) {
+        let Some(sensitivity) = dispatch.tenant_state.alt_sensitivity(Id::Guild(guild_id)) else {
+            return;
+        };
+        let Some(user) = payload.get("user") else {
+            return;
+        };
+        if user.get("bot").and_then(|v| v.as_bool()).unwrap_or(false) {
+            return;
+        }
+        let Some(user_id) = user.get("id").and_then(|v| v.as_str()).and_then(|v| v.parse::<UserId>().ok()) else {
+            return;
+        };
+        let username = user.get("username").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+        let avatar = user.get("avatar").and_then(|v| v.as_str()).map(|s| s.to_string());
+
+        let (self_ref, dispatch) = (self.clone(), dispatch.clone());
+        tokio::task::spawn_local(async move {
+            let join = Join { user_id, username, avatar, invite, joined_at: Utc::now() };
+            let assessment = self_ref.assess(guild_id, join, sensitivity);
+            if !assessment.flagged {
+                return;
+            }
+            if let Err(e) = dispatch.dispatch_event_complex(Id::Guild(guild_id), ALT_SUSPECTED_EVENT, None, AltSuspectedData { assessment }).await {
+                log::error!("Failed to dispatch {ALT_SUSPECTED_EVENT} for {user_id} in guild {guild_id}: {e}");
+            }
+        });
+    }
+
+    /// Returns how the assessment of a member works in a guild along with its latest assessment, if still kept
+    pub fn explain(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) -> Result<KhronosValue, crate::Error> {
+        let req: AltExplainReq = serde_json::from_value(payload.clone())?;
+        let sensitivity = dispatch.tenant_state.alt_sensitivity(Id::Guild(guild_id));
+        let assessment = self.guilds.borrow().get(&guild_id)
+            .and_then(|g| g.assessments.iter().rev().find(|a| a.user_id == req.user_id).cloned());
+
+        let explanation = json!({
+            "enabled": sensitivity.is_some(),
+            "sensitivity": sensitivity,
+            "threshold": sensitivity.map(|s| s.threshold()),
+            "window_secs": JOIN_WINDOW.num_seconds(),
+            "signals": {
+                "join_proximity": { "weight": PROXIMITY_WEIGHT, "description": format!("Joined within {PROXIMITY_WINDOW_SECS} seconds of each other, weighted by how close") },
+                "username": { "weight": USERNAME_WEIGHT, "description": format!("Usernames at least {}% alike once lowercased and stripped of symbols and trailing digits", MIN_USERNAME_SIMILARITY * 100.0) },
+                "avatar": { "weight": AVATAR_WEIGHT, "description": "Identical (non-default) avatars" },
+                "invite": { "weight": INVITE_WEIGHT, "description": "Joined through the same invite" },
+            },
+            "assessment": assessment,
+        });
+        Ok(serde_json::from_value(explanation)?)
+    }
+
+    /// Correlates a join with the recent joins of the guild, recording both
+    fn assess(&self, guild_id: GuildId, join: Join, sensitivity: AltSensitivity) -> AltAssessment {
+        let mut guilds = self.guilds.borrow_mut();
+        let guild = guilds.entry(guild_id).or_default();
+        while guild.joins.front().is_some_and(|j| join.joined_at - j.joined_at > JOIN_WINDOW) || guild.joins.len() >= MAX_TRACKED_JOINS {
+            guild.joins.pop_front();
+        }
+
+        let mut matches = guild.joins.iter()
+            .filter(|other| other.user_id != join.user_id)
+            .filter_map(|other| correlate(&join, other))
+            .filter(|m| m.confidence >= MIN_MATCH_CONFIDENCE)
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        matches.truncate(MAX_MATCHES);
+
+        let confidence = matches.first().map(|m| m.confidence).unwrap_or_default();
+        let assessment = AltAssessment {
+            user_id: join.user_id,
+            confidence,
+            sensitivity,
+            threshold: sensitivity.threshold(),
+            flagged: confidence >= sensitivity.threshold(),
+            matches,
+            assessed_at: join.joined_at,
+        };
+
+        guild.joins.push_back(join);
+        if guild.assessments.len() >= MAX_KEPT_ASSESSMENTS {
+            guild.assessments.pop_front();
+        }
+        guild.assessments.push_back(assessment.clone());
+        assessment
+    }
+}
+
+/// Returns the signals linking a join to an earlier one, if any
+fn correlate(join: &Join, other: &Join) -> Option<AltMatch> {
+    let mut evidence = Vec::new();
+
+    let secs = (join.joined_at - other.joined_at).num_milliseconds() as f64 / 1000.0;
+    if secs <= PROXIMITY_WINDOW_SECS {
+        evidence.push(Evidence {
+            signal: "join_proximity",
+            detail: format!("Joined {secs:.0} seconds apart"),
+            weight: PROXIMITY_WEIGHT * (1.0 - secs / PROXIMITY_WINDOW_SECS),
+        });
+    }
+
+    let (a, b) = (normalize_username(&join.username), normalize_username(&other.username));
+    if a.len() >= 3 && b.len() >= 3 {
+        let similarity = strsim::jaro_winkler(&a, &b);
+        if similarity >= MIN_USERNAME_SIMILARITY {
+            evidence.push(Evidence {
+                signal: "username",
+                detail: format!("Usernames {} and {} are {:.0}% alike", join.username, other.username, similarity * 100.0),
+                weight: USERNAME_WEIGHT * similarity,
+            });
+        }
+    }
+
+    if let (Some(a), Some(b)) = (&join.avatar, &other.avatar) && a == b {
+        evidence.push(Evidence { signal: "avatar", detail: "Same avatar".to_string(), weight: AVATAR_WEIGHT });
+    }
+
+    if let (Some(a), Some(b)) = (&join.invite, &other.invite) && a == b {
+        evidence.push(Evidence { signal: "invite", detail: format!("Joined through the same invite ({a})"), weight: INVITE_WEIGHT });
+    }
+
+    if evidence.is_empty() {
+        return None;
+    }
+    let confidence = 1.0 - evidence.iter().map(|e| 1.0 - e.weight).product::<f64>();
+    Some(AltMatch { user_id: other.user_id, confidence, evidence })
+}
+
+/// Lowercases a username and strips it of symbols and trailing digits, so `Raider_01` and `raider.02` are alike
+fn normalize_username(username: &str) -> String {
+    let s = username.to_lowercase().chars().filter(|c| c.is_alphanumeric()).collect::<String>();
+    s.trim_end_matches(|c: char| c.is_ascii_digit()).to_string()
+}
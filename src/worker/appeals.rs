@@ -0,0 +1,434 @@
+use chrono::{DateTime, Utc};
+use dapi::{ChannelId, GuildId, UserId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::CONFIG;
+use crate::geese::appeals::{self, Appeal, AppealReq, AppealResp, AppealState, Punishment};
+use crate::worker::workerdispatch::{SimpleEvent, WorkerDispatch};
+use crate::worker::workervmmanager::Id;
+
+/// Message users send in DMs to appeal, followed by the server ID and the reason
+pub const APPEAL_COMMAND: &str = "!appeal";
+
+/// Internal event submitting an appeal, dispatched to the guild (whose worker checks the user is punished) from
+/// the users DMs or the API
+pub const APPEAL_REQUEST_EVENT: &str = "$AppealRequest";
+
+/// Event dispatched to a guild once an appeal has been submitted
+pub const APPEAL_SUBMITTED_EVENT: &str = "AppealSubmitted";
+
+/// Event dispatched to a guild once an appeal has been accepted or denied
+///
+/// The user is only messaged about the decision by AntiRaid if no template handles the event
+pub const APPEAL_RESOLVED_EVENT: &str = "AppealResolved";
+
+/// Prefix of the custom IDs of the buttons on appeal review messages, followed by `<action>:<appeal id>`
+const CUSTOM_ID_PREFIX: &str = "appeal:";
+
+/// Where an appeal submitted through DMs comes from
+pub const SOURCE_DM: &str = "dm";
+
+/// Where an appeal submitted through the API comes from
+pub const SOURCE_WEB: &str = "web";
+
+/// Max length of a Discord message
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// Discord permissions allowing to act on appeals
+const BAN_MEMBERS: u64 = 1 << 2;
+const ADMINISTRATOR: u64 = 1 << 3;
+
+/// Discord interaction type of message components
+const MESSAGE_COMPONENT: u64 = 3;
+
+/// Discord interaction callback types
+const CHANNEL_MESSAGE_WITH_SOURCE: u8 = 4;
+const UPDATE_MESSAGE: u8 = 7;
+
+/// Discord message flag making a response only visible to the user who interacted
+const EPHEMERAL: u64 = 1 << 6;
+
+/// Data of `$AppealRequest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppealRequest {
+    pub user_id: UserId,
+    pub reason: String,
+    pub evidence: Vec<String>,
+    /// `dm` or `web`
+    pub source: String,
+}
+
+/// Data of `AppealSubmitted` and `AppealResolved`
+struct AppealEventData {
+    appeal: Appeal,
+}
+
+impl IntoLua for AppealEventData {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table_with_capacity(0, 1)?;
+        tab.set("appeal", lua.to_value_with(&self.appeal, LUA_SERIALIZE_OPTIONS)?)?;
+        tab.set_readonly(true);
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+/// Handles appeals of users against their ban or timeout in a guild
+///
+/// Appeals are checked and posted to the guild's appeals channel by the worker of the guild, where moderators act
+/// on them through the buttons of the review message. Appeals themselves are stored by the master
+pub struct Appeals<'a> {
+    dispatch: &'a WorkerDispatch,
+}
+
+impl<'a> Appeals<'a> {
+    pub fn new(dispatch: &'a WorkerDispatch) -> Self {
+        Self { dispatch }
+    }
+
+    /// Handles a MESSAGE_CREATE in the background if it is an `APPEAL_COMMAND` DM, returning whether it was
+    pub fn start_dm(dispatch: &WorkerDispatch, id: Id, payload: &Value) -> bool {
+        if !matches!(id, Id::User(_)) || !payload.get("guild_id").is_none_or(|v| v.is_null()) {
+            return false;
+        }
+        let content = payload.get("content").and_then(|v| v.as_str()).unwrap_or_default();
+        let Some(args) = content.trim().strip_prefix(APPEAL_COMMAND) else {
+            return false;
+        };
+        let author = payload.get("author");
+        if author.and_then(|a| a.get("bot")).and_then(|v| v.as_bool()).unwrap_or(false) {
+            return false;
+        }
+        let (Some(user_id), Some(channel_id)) = (
+            author.and_then(|a| a.get("id")).and_then(|v| v.as_str()).and_then(|v| v.parse::<UserId>().ok()),
+            payload.get("channel_id").and_then(|v| v.as_str()).and_then(|v| v.parse::<ChannelId>().ok()),
+        ) else {
+            return false;
+        };
+
+        let args = args.trim().to_string();
+        let evidence = payload.get("attachments")
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|a| Some(a.get("url")?.as_str()?.to_string())).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            let appeals = Appeals::new(&dispatch);
+            if let Err(e) = appeals.forward_dm(user_id, channel_id, &args, evidence).await {
+                log::error!("Failed to handle appeal of user {user_id}: {e}");
+            }
+        });
+        true
+    }
+
+    /// Forwards an appeal made in DMs to the guild it is for
+    async fn forward_dm(&self, user_id: UserId, channel_id: ChannelId, args: &str, evidence: Vec<String>) -> Result<(), crate::Error> {
+        let (guild_id, reason) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let Ok(guild_id) = guild_id.parse::<GuildId>() else {
+            let usage = format!("To appeal your ban or timeout in a server, send `{APPEAL_COMMAND} <server ID> <reason>` here, attaching any evidence.");
+            return self.send_message(channel_id, &usage).await;
+        };
+
+        let req = AppealRequest { user_id, reason: reason.trim().to_string(), evidence, source: SOURCE_DM.to_string() };
+        if let Err(e) = appeals::validate(&req.reason, &req.evidence) {
+            return self.send_message(channel_id, &format!("Your appeal could not be submitted: {e}")).await;
+        }
+
+        // The guild may be owned by another worker, which messages the user once the appeal is submitted
+        let event = SimpleEvent::new_json_string(APPEAL_REQUEST_EVENT.to_string(), None, serde_json::to_string(&req)?);
+        if let Err(e) = self.dispatch.worker_state.mesophyll_client.forward_event(Id::Guild(guild_id), event).await {
+            self.send_message(channel_id, "Your appeal could not be submitted, please try again later.").await?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Submits an appeal given the data of `$AppealRequest`, returning the submitted appeal
+    ///
+    /// Users appealing through DMs are messaged if their appeal can't be submitted, API callers get the error
+    pub async fn request(&self, guild_id: GuildId, payload: &Value) -> Result<KhronosValue, crate::Error> {
+        let req: AppealRequest = serde_json::from_value(payload.clone())?;
+        let (user_id, from_dm) = (req.user_id, req.source == SOURCE_DM);
+        match self.submit(guild_id, req).await {
+            Ok(appeal) => Ok(serde_json::from_value(serde_json::to_value(&appeal)?)?),
+            Err(e) => {
+                if from_dm && let Err(e) = self.send_dm(user_id, &format!("Your appeal could not be submitted: {e}")).await {
+                    log::error!("Failed to message user {user_id} about their appeal: {e}");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn submit(&self, guild_id: GuildId, req: AppealRequest) -> Result<Appeal, crate::Error> {
+        let id = Id::Guild(guild_id);
+        let Some(channel_id) = self.dispatch.tenant_state.get_cached_tenant_state_for(id)?.settings.appeals_channel_id else {
+            return Err("Appeals are not enabled in this server".into());
+        };
+        let Some(punishment) = self.punishment(guild_id, req.user_id).await? else {
+            return Err("You have no ban or timeout to appeal in this server".into());
+        };
+
+        let appeal = match self.request_master(AppealReq::Submit {
+            guild_id,
+            user_id: req.user_id,
+            punishment,
+            reason: req.reason,
+            evidence: req.evidence,
+            source: req.source,
+        }).await? {
+            AppealResp::Submitted { appeal } => appeal,
+            AppealResp::AlreadyOpen => return Err("You already have an appeal under review in this server".into()),
+            resp => return Err(format!("Unexpected appeal response: {resp:?}").into()),
+        };
+
+        let message: Value = self.rest(reqwest::Method::POST, &format!("/channels/{channel_id}/messages"))
+            .json(&review_message(&appeal))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let message_id = message.get("id").and_then(|v| v.as_str()).ok_or("Created message has no ID")?.to_string();
+        self.request_master(AppealReq::SetMessage { appeal_id: appeal.id, channel_id, message_id }).await?;
+
+        self.send_dm(appeal.user_id, "Your appeal has been submitted to the staff of the server. You will be messaged once it has been reviewed.").await?;
+        self.notify(APPEAL_SUBMITTED_EVENT, &appeal).await;
+        Ok(appeal)
+    }
+
+    /// Returns what the user is punished with in the guild, if anything
+    async fn punishment(&self, guild_id: GuildId, user_id: UserId) -> Result<Option<Punishment>, crate::Error> {
+        let res = self.rest(reqwest::Method::GET, &format!("/guilds/{guild_id}/bans/{user_id}")).send().await?;
+        if res.status() != reqwest::StatusCode::NOT_FOUND {
+            res.error_for_status()?;
+            return Ok(Some(Punishment::Ban));
+        }
+
+        let Some(member) = self.dispatch.worker_state.stratum.guild_member(guild_id, user_id).await? else {
+            return Ok(None);
+        };
+        let timed_out = member.get("communication_disabled_until")
+            .and_then(|v| v.as_str())
+            .and_then(|v| v.parse::<DateTime<Utc>>().ok())
+            .is_some_and(|until| until > Utc::now());
+        Ok(timed_out.then_some(Punishment::Timeout))
+    }
+
+    /// Handles an INTERACTION_CREATE in the background if it is a button of an appeal review message, returning
+    /// whether it was (in which case it should not be dispatched to templates)
+    pub fn start_interaction(dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) -> bool {
+        if payload.get("type").and_then(|v| v.as_u64()) != Some(MESSAGE_COMPONENT) {
+            return false;
+        }
+        let custom_id = payload.get("data").and_then(|d| d.get("custom_id")).and_then(|v| v.as_str()).unwrap_or_default();
+        if !custom_id.starts_with(CUSTOM_ID_PREFIX) {
+            return false;
+        }
+
+        let (dispatch, payload) = (dispatch.clone(), payload.clone());
+        tokio::task::spawn_local(async move {
+            let appeals = Appeals::new(&dispatch);
+            if let Err(e) = appeals.handle_interaction(guild_id, &payload).await {
+                log::error!("Failed to handle appeal interaction in guild {guild_id}: {e}");
+            }
+        });
+        true
+    }
+
+    async fn handle_interaction(&self, guild_id: GuildId, payload: &Value) -> Result<(), crate::Error> {
+        let interaction_id = payload.get("id").and_then(|v| v.as_str()).ok_or("Interaction has no ID")?;
+        let token = payload.get("token").and_then(|v| v.as_str()).ok_or("Interaction has no token")?;
+        let callback = format!("/interactions/{interaction_id}/{token}/callback");
+
+        let custom_id = payload.get("data").and_then(|d| d.get("custom_id")).and_then(|v| v.as_str()).unwrap_or_default();
+        let Some((to, appeal_id)) = parse_custom_id(custom_id) else {
+            return self.respond_ephemeral(&callback, "Unknown appeal action.").await;
+        };
+
+        let member = payload.get("member").ok_or("Interaction has no member")?;
+        let permissions = member.get("permissions").and_then(|v| v.as_str()).and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+        if permissions & (BAN_MEMBERS | ADMINISTRATOR) == 0 {
+            return self.respond_ephemeral(&callback, "You need the Ban Members permission to act on appeals.").await;
+        }
+        let moderator_id: UserId = member.get("user")
+            .and_then(|u| u.get("id"))
+            .and_then(|v| v.as_str())
+            .ok_or("Interaction member has no user")?
+            .parse()?;
+
+        let appeal = match self.request_master(AppealReq::Transition { guild_id, appeal_id, to, moderator_id }).await {
+            Ok(AppealResp::Updated { appeal }) => appeal,
+            Ok(resp) => return Err(format!("Unexpected appeal response: {resp:?}").into()),
+            Err(e) => return self.respond_ephemeral(&callback, &e.to_string()).await,
+        };
+
+        let mut message = review_message(&appeal);
+        if appeal.state == AppealState::Accepted && let Err(e) = self.lift(&appeal).await {
+            log::error!("Failed to lift the {} of user {} in guild {guild_id}: {e}", appeal.punishment.as_str(), appeal.user_id);
+            message["content"] = Value::String(truncate(
+                &format!("{}\n**Failed to lift the {}**: {e}", message["content"].as_str().unwrap_or_default(), appeal.punishment.as_str()),
+                MAX_MESSAGE_LENGTH,
+            ));
+        }
+        self.rest(reqwest::Method::POST, &callback)
+            .json(&json!({ "type": UPDATE_MESSAGE, "data": message }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        if appeal.state.is_resolved() {
+            let handled = self.dispatch.tenant_state.registry_for(Id::Guild(guild_id)).handles(APPEAL_RESOLVED_EVENT);
+            if !handled {
+                let decision = match appeal.state {
+                    AppealState::Accepted => format!("Your appeal has been accepted and your {} has been lifted.", appeal.punishment.as_str()),
+                    _ => "Your appeal has been denied.".to_string(),
+                };
+                self.send_dm(appeal.user_id, &decision).await?;
+            }
+            self.notify(APPEAL_RESOLVED_EVENT, &appeal).await;
+        }
+        Ok(())
+    }
+
+    /// Lifts the punishment of an accepted appeal
+    async fn lift(&self, appeal: &Appeal) -> Result<(), crate::Error> {
+        let reason = format!("Appeal {} accepted by {}", appeal.id, appeal.moderator_id.map(|m| m.to_string()).unwrap_or_default());
+        let req = match appeal.punishment {
+            Punishment::Ban => self.rest(reqwest::Method::DELETE, &format!("/guilds/{}/bans/{}", appeal.guild_id, appeal.user_id)),
+            Punishment::Timeout => self.rest(reqwest::Method::PATCH, &format!("/guilds/{}/members/{}", appeal.guild_id, appeal.user_id))
+                .json(&json!({ "communication_disabled_until": null })),
+        };
+
+        let res = req.header("X-Audit-Log-Reason", reason).send().await?;
+        // Already lifted (or the user left)
+        if res.status() != reqwest::StatusCode::NOT_FOUND {
+            res.error_for_status()?;
+        }
+        Ok(())
+    }
+
+    async fn respond_ephemeral(&self, callback: &str, content: &str) -> Result<(), crate::Error> {
+        self.rest(reqwest::Method::POST, callback)
+            .json(&json!({
+                "type": CHANNEL_MESSAGE_WITH_SOURCE,
+                "data": { "content": truncate(content, MAX_MESSAGE_LENGTH), "flags": EPHEMERAL, "allowed_mentions": { "parse": [] } },
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn request_master(&self, req: AppealReq) -> Result<AppealResp, crate::Error> {
+        self.dispatch.worker_state.mesophyll_client.appeals(&req).await
+    }
+
+    /// Dispatches an appeal event to the guild of the appeal, which this worker owns
+    async fn notify(&self, name: &str, appeal: &Appeal) {
+        let data = AppealEventData { appeal: appeal.clone() };
+        if let Err(e) = self.dispatch.dispatch_event_complex(Id::Guild(appeal.guild_id), name, None, data).await {
+            log::error!("Failed to dispatch {name} for appeal {}: {e}", appeal.id);
+        }
+    }
+
+    /// Messages a user, creating the DM channel if needed
+    async fn send_dm(&self, user_id: UserId, content: &str) -> Result<(), crate::Error> {
+        let channel: Value = self.rest(reqwest::Method::POST, "/users/@me/channels")
+            .json(&json!({ "recipient_id": user_id }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let channel_id: ChannelId = channel.get("id")
+            .and_then(|v| v.as_str())
+            .ok_or("DM channel has no ID")?
+            .parse()?;
+        self.send_message(channel_id, content).await
+    }
+
+    /// Sends a message with all mentions disabled
+    async fn send_message(&self, channel_id: ChannelId, content: &str) -> Result<(), crate::Error> {
+        self.rest(reqwest::Method::POST, &format!("/channels/{channel_id}/messages"))
+            .json(&json!({ "content": truncate(content, MAX_MESSAGE_LENGTH), "allowed_mentions": { "parse": [] } }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn rest(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.dispatch.worker_state.reqwest.request(method, format!("{}/api/v10{path}", CONFIG.proxy))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+    }
+}
+
+/// Parses the custom ID of a review message button into the state to move the appeal to and the appeal
+fn parse_custom_id(custom_id: &str) -> Option<(AppealState, Uuid)> {
+    let (action, appeal_id) = custom_id.strip_prefix(CUSTOM_ID_PREFIX)?.split_once(':')?;
+    let to = match action {
+        "review" => AppealState::UnderReview,
+        "accept" => AppealState::Accepted,
+        "deny" => AppealState::Denied,
+        _ => return None,
+    };
+    Some((to, appeal_id.parse().ok()?))
+}
+
+/// Renders the review message of an appeal, with buttons to act on it until resolved
+fn review_message(appeal: &Appeal) -> Value {
+    let moderator = appeal.moderator_id.map(|m| format!("<@{m}>")).unwrap_or_default();
+    let status = match appeal.state {
+        AppealState::Open => "Open".to_string(),
+        AppealState::UnderReview => format!("Under review by {moderator}"),
+        AppealState::Accepted => format!("Accepted by {moderator}"),
+        AppealState::Denied => format!("Denied by {moderator}"),
+    };
+
+    let mut content = format!(
+        "**Appeal** from <@{user}> ({user}) against their {punishment}\n**Status**: {status}\n**Reason**: {reason}",
+        user = appeal.user_id,
+        punishment = appeal.punishment.as_str(),
+        reason = appeal.reason,
+    );
+    if !appeal.evidence.is_empty() {
+        content.push_str("\n**Evidence**:");
+        for evidence in &appeal.evidence {
+            content.push_str(&format!("\n- <{evidence}>"));
+        }
+    }
+
+    let button = |label: &str, style: u8, action: &str| json!({
+        "type": 2,
+        "style": style,
+        "label": label,
+        "custom_id": format!("{CUSTOM_ID_PREFIX}{action}:{}", appeal.id),
+    });
+    let components = if appeal.state.is_resolved() {
+        vec![]
+    } else {
+        let mut buttons = Vec::new();
+        if appeal.state == AppealState::Open {
+            buttons.push(button("Review", 2, "review"));
+        }
+        buttons.push(button("Accept", 3, "accept"));
+        buttons.push(button("Deny", 4, "deny"));
+        vec![json!({ "type": 1, "components": buttons })]
+    };
+
+    json!({
+        "content": truncate(&content, MAX_MESSAGE_LENGTH),
+        "components": components,
+        "allowed_mentions": { "parse": [] },
+    })
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    s.chars().take(max_chars).collect()
+}
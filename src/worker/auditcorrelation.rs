@@ -0,0 +1,260 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, UserId};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::CONFIG;
+use crate::geese::auditcorrelation::action_types;
+use crate::worker::actor::EventActor;
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Field of correlated event payloads the matching audit log entry is exposed as
+pub const AUDIT_LOG_FIELD: &str = "audit_log";
+
+/// How long the dispatches of a guild may be held up for finding audit log entries, a window shared by all events
+/// waiting in it which is followed by as long of not waiting at all
+const CORRELATION_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// How long to wait for the entry to arrive over the gateway before fetching the audit log
+const GATEWAY_GRACE: Duration = Duration::from_millis(500);
+
+/// Minimum time between audit log fetches of a guild, shared by all of its events
+const REFETCH_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// How often the recent entries are checked again while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Entries created this long before the event are not matched to it
+const MAX_ENTRY_AGE: Duration = Duration::from_secs(15);
+
+/// Number of entries fetched from the audit log at once
+const FETCH_LIMIT: usize = 100;
+
+/// Maximum number of recent entries kept per guild
+const MAX_RECENT_ENTRIES: usize = 200;
+
+/// Maximum number of guilds whose recent entries are kept, all are forgotten once exceeded
+const MAX_TRACKED_GUILDS: usize = 10_000;
+
+/// The Discord epoch (first second of 2015) in milliseconds
+const DISCORD_EPOCH: i64 = 1420070400000;
+
+/// The audit log entry of a correlated event, added to its payload
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditLogCorrelation {
+    /// ID of the audit log entry
+    pub entry_id: String,
+    pub action_type: u64,
+    /// The user who performed the action
+    pub user_id: Option<UserId>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    id: String,
+    action_type: u64,
+    target_id: Option<String>,
+    user_id: Option<UserId>,
+    reason: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl Entry {
+    fn parse(entry: &Value) -> Option<Self> {
+        let id = entry.get("id")?.as_str()?.to_string();
+        let created_at = snowflake_time(&id)?;
+        Some(Self {
+            action_type: entry.get("action_type")?.as_u64()?,
+            target_id: entry.get("target_id").and_then(|v| v.as_str()).map(|v| v.to_string()),
+            user_id: entry.get("user_id").and_then(|v| v.as_str()).and_then(|v| v.parse().ok()),
+            reason: entry.get("reason").and_then(|v| v.as_str()).map(|v| v.to_string()),
+            id,
+            created_at,
+        })
+    }
+}
+
+/// Recent audit log entries of a guild, newest first
+#[derive(Default)]
+struct GuildLog {
+    entries: VecDeque<Entry>,
+    /// Bot flags of the users of the entries
+    bots: HashMap<UserId, bool>,
+    last_fetch: Option<Instant>,
+    /// End of the current wait window
+    wait_until: Option<Instant>,
+}
+
+impl GuildLog {
+    fn insert(&mut self, entry: Entry) {
+        if self.entries.iter().any(|e| e.id == entry.id) {
+            return;
+        }
+        let pos = self.entries.iter().position(|e| e.created_at < entry.created_at).unwrap_or(self.entries.len());
+        self.entries.insert(pos, entry);
+        self.entries.truncate(MAX_RECENT_ENTRIES);
+    }
+
+    /// Returns until when an event may wait for its entry, joining the current wait window or opening one. Right
+    /// after a window has ended events only get the entries already known so a burst without entries (e.g. members
+    /// leaving on their own) can't keep holding up the guild
+    fn wait_deadline(&mut self, now: Instant) -> Instant {
+        match self.wait_until {
+            Some(until) if now < until + CORRELATION_TIMEOUT => until.max(now),
+            _ => {
+                let until = now + CORRELATION_TIMEOUT;
+                self.wait_until = Some(until);
+                until
+            }
+        }
+    }
+
+    fn find(&self, types: &[u64], target_id: &str, since: DateTime<Utc>) -> Option<&Entry> {
+        self.entries.iter()
+            .take_while(|e| e.created_at >= since)
+            .find(|e| types.contains(&e.action_type) && e.target_id.as_deref() == Some(target_id))
+    }
+}
+
+/// Returns when a snowflake was created
+fn snowflake_time(id: &str) -> Option<DateTime<Utc>> {
+    let id = id.parse::<u64>().ok()?;
+    DateTime::from_timestamp_millis((id >> 22) as i64 + DISCORD_EPOCH)
+}
+
+/// Attaches the executor and reason of gateway events which don't say who caused them (members being kicked,
+/// channels being deleted etc.) by finding their audit log entry
+///
+/// Entries are taken from GUILD_AUDIT_LOG_ENTRY_CREATE as they arrive and otherwise fetched from the audit log,
+/// at most once per `REFETCH_INTERVAL` per guild no matter how many events are waiting, so bursts (e.g. a mass
+/// kick) share fetches. Waiting is bounded per guild rather than per event: the events of a guild share one
+/// `CORRELATION_TIMEOUT` window, those without an entry by its end are dispatched without one and the guild's
+/// events don't wait again until as long has passed. Needs the View Audit Log permission
+#[derive(Clone, Default)]
+pub struct AuditCorrelator {
+    guilds: Rc<RefCell<HashMap<GuildId, GuildLog>>>,
+}
+
+impl AuditCorrelator {
+    /// Keeps the entry of a GUILD_AUDIT_LOG_ENTRY_CREATE payload for correlation, if the guild correlates events
+    pub fn observe(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        if dispatch.tenant_state.audit_correlation(Id::Guild(guild_id)).is_none() {
+            return;
+        }
+        let Some(entry) = Entry::parse(payload) else {
+            return;
+        };
+        let mut guilds = self.guilds.borrow_mut();
+        if guilds.len() >= MAX_TRACKED_GUILDS && !guilds.contains_key(&guild_id) {
+            guilds.clear();
+        }
+        guilds.entry(guild_id).or_default().insert(entry);
+    }
+
+    /// Finds the audit log entry of an event if the guild correlates it, adding it to the payload and returning the
+    /// actor it derives
+    pub async fn correlate(&self, dispatch: &WorkerDispatch, guild_id: GuildId, event: &str, payload: &mut Value) -> Option<EventActor> {
+        let config = dispatch.tenant_state.audit_correlation(Id::Guild(guild_id))?;
+        if !config.correlates(event) {
+            return None;
+        }
+        let types = action_types(event)?;
+        let target_id = target_id(event, payload)?;
+
+        let started = Instant::now();
+        let since = Utc::now() - chrono::Duration::from_std(MAX_ENTRY_AGE).ok()?;
+        let mut deadline = None;
+        let (correlation, is_bot) = loop {
+            let fetch = {
+                let mut guilds = self.guilds.borrow_mut();
+                if guilds.len() >= MAX_TRACKED_GUILDS && !guilds.contains_key(&guild_id) {
+                    guilds.clear();
+                }
+                let log = guilds.entry(guild_id).or_default();
+                let deadline = *deadline.get_or_insert_with(|| log.wait_deadline(started));
+                if let Some(entry) = log.find(types, &target_id, since) {
+                    let is_bot = entry.user_id.and_then(|u| log.bots.get(&u).copied());
+                    let correlation = AuditLogCorrelation {
+                        entry_id: entry.id.clone(),
+                        action_type: entry.action_type,
+                        user_id: entry.user_id,
+                        reason: entry.reason.clone(),
+                    };
+                    break (correlation, is_bot);
+                }
+
+                if Instant::now() >= deadline {
+                    log::debug!("No audit log entry found for {event} of {target_id} in guild {guild_id}");
+                    return None;
+                }
+                let due = started.elapsed() >= GATEWAY_GRACE && log.last_fetch.is_none_or(|t| t.elapsed() >= REFETCH_INTERVAL);
+                if due {
+                    // Claimed before fetching so other waiting events don't fetch too
+                    log.last_fetch = Some(Instant::now());
+                }
+                due
+            };
+
+            if fetch {
+                if let Err(e) = self.fetch(dispatch, guild_id).await {
+                    log::debug!("Failed to fetch audit log of guild {guild_id}: {e}");
+                }
+            } else {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        };
+
+        if let Some(obj) = payload.as_object_mut() && let Ok(value) = serde_json::to_value(&correlation) {
+            obj.insert(AUDIT_LOG_FIELD.to_string(), value);
+        }
+        let bot_id = dispatch.worker_state.stratum.current_user().id;
+        correlation.user_id.map(|user_id| EventActor::from_audit_log(user_id, is_bot.unwrap_or(false), bot_id))
+    }
+
+    /// Fetches the latest entries of the audit log of a guild into its recent entries
+    async fn fetch(&self, dispatch: &WorkerDispatch, guild_id: GuildId) -> Result<(), crate::Error> {
+        let res: Value = dispatch.worker_state.reqwest.get(format!("{}/api/v10/guilds/{guild_id}/audit-logs?limit={FETCH_LIMIT}", CONFIG.proxy))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let entries = res.get("audit_log_entries").and_then(|v| v.as_array()).into_iter().flatten().filter_map(Entry::parse);
+        let users = res.get("users").and_then(|v| v.as_array()).into_iter().flatten().filter_map(|u| {
+            let id = u.get("id")?.as_str()?.parse::<UserId>().ok()?;
+            Some((id, u.get("bot").and_then(|v| v.as_bool()).unwrap_or(false)))
+        });
+
+        let mut guilds = self.guilds.borrow_mut();
+        let log = guilds.entry(guild_id).or_default();
+        for entry in entries {
+            log.insert(entry);
+        }
+        if log.bots.len() >= MAX_RECENT_ENTRIES {
+            log.bots.clear();
+        }
+        log.bots.extend(users);
+        Ok(())
+    }
+}
+
+/// Returns the ID of what an event acted upon, the target of its audit log entry
+fn target_id(event: &str, payload: &Value) -> Option<String> {
+    let target = match event {
+        "GUILD_MEMBER_REMOVE" | "GUILD_MEMBER_UPDATE" | "GUILD_BAN_ADD" | "GUILD_BAN_REMOVE" => payload.get("user")?.get("id")?,
+        "CHANNEL_CREATE" | "CHANNEL_UPDATE" | "CHANNEL_DELETE" => payload.get("id")?,
+        "GUILD_ROLE_CREATE" | "GUILD_ROLE_UPDATE" => payload.get("role")?.get("id")?,
+        "GUILD_ROLE_DELETE" => payload.get("role_id")?,
+        _ => return None,
+    };
+    target.as_str().map(|t| t.to_string())
+}
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use chrono::{DateTime, Utc};
+use dapi::{ChannelId, GuildId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::CONFIG;
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Event dispatched to a guild when a message of one of its announcement channels is not published due to limits
+pub const AUTO_PUBLISH_SKIPPED_EVENT: &str = "AutoPublishSkipped";
+
+/// How many messages Discord allows to be published per announcement channel per hour
+const PUBLISHES_PER_HOUR: usize = 10;
+
+/// Maximum number of channels whose publishes are tracked, all are forgotten once exceeded
+const MAX_TRACKED_CHANNELS: usize = 10_000;
+
+/// Message types which can be published (default messages and replies)
+const PUBLISHABLE_TYPES: [u64; 2] = [0, 19];
+
+/// Message flags of messages already published (`CROSSPOSTED`) or published from another channel (`IS_CROSSPOST`)
+const CROSSPOST_FLAGS: u64 = (1 << 0) | (1 << 1);
+
+/// Why a message was not published
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoPublishSkipReason {
+    /// The channel already published `PUBLISHES_PER_HOUR` messages within the last hour
+    ChannelLimit,
+    /// Discord ratelimited the publish
+    Ratelimited,
+}
+
+/// Data of `AutoPublishSkipped`
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoPublishSkipped {
+    pub channel_id: ChannelId,
+    pub message_id: String,
+    pub reason: AutoPublishSkipReason,
+    /// Seconds until messages of the channel can be published again
+    pub retry_after_secs: u64,
+}
+
+impl IntoLua for AutoPublishSkipped {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Publishes (crossposts) the messages sent in the announcement channels of a guild to the channels following them
+///
+/// Publishes are counted per channel so messages beyond Discord's hourly limit are skipped (dispatching
+/// `AutoPublishSkipped`) rather than queued up behind the ratelimit. Messages can still be published manually
+#[derive(Clone, Default)]
+pub struct AutoPublisher {
+    /// When the messages of each channel published within the last hour were published
+    published: Rc<RefCell<HashMap<ChannelId, VecDeque<DateTime<Utc>>>>>,
+}
+
+impl AutoPublisher {
+    /// Publishes the message of a MESSAGE_CREATE payload in the background if sent in an announcement channel the
+    /// guild publishes from
+    pub fn observe(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.auto_publish(Id::Guild(guild_id)) else {
+            return;
+        };
+        let Some(channel_id) = payload.get("channel_id").and_then(|v| v.as_str()).and_then(|v| v.parse::<ChannelId>().ok()) else {
+            return;
+        };
+        if !config.channels.contains(&channel_id) {
+            return;
+        }
+        let Some(message_id) = payload.get("id").and_then(|v| v.as_str()).map(|v| v.to_string()) else {
+            return;
+        };
+        let kind = payload.get("type").and_then(|v| v.as_u64()).unwrap_or_default();
+        let flags = payload.get("flags").and_then(|v| v.as_u64()).unwrap_or_default();
+        if !PUBLISHABLE_TYPES.contains(&kind) || flags & CROSSPOST_FLAGS != 0 {
+            return;
+        }
+        let bot = payload.get("author").and_then(|a| a.get("bot")).and_then(|v| v.as_bool()).unwrap_or(false)
+            || payload.get("webhook_id").is_some_and(|w| !w.is_null());
+        if bot && !config.include_bots {
+            return;
+        }
+
+        let now = Utc::now();
+        let hour = chrono::Duration::hours(1);
+        let retry_after = {
+            let mut published = self.published.borrow_mut();
+            if published.len() >= MAX_TRACKED_CHANNELS && !published.contains_key(&channel_id) {
+                published.clear();
+            }
+            let times = published.entry(channel_id).or_default();
+            while times.front().is_some_and(|t| now - *t >= hour) {
+                times.pop_front();
+            }
+            match times.front() {
+                Some(first) if times.len() >= PUBLISHES_PER_HOUR => Some((*first + hour - now).num_seconds().max(1) as u64),
+                _ => {
+                    times.push_back(now);
+                    None
+                }
+            }
+        };
+
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            let res = match retry_after {
+                Some(retry_after_secs) => {
+                    let skipped = AutoPublishSkipped { channel_id, message_id, reason: AutoPublishSkipReason::ChannelLimit, retry_after_secs };
+                    dispatch.dispatch_event_complex(Id::Guild(guild_id), AUTO_PUBLISH_SKIPPED_EVENT, None, skipped).await.map(|_| ())
+                }
+                None => Self::publish(&dispatch, guild_id, channel_id, message_id).await,
+            };
+            if let Err(e) = res {
+                log::error!("Failed to auto publish message in channel {channel_id} of guild {guild_id}: {e}");
+            }
+        });
+    }
+
+    async fn publish(dispatch: &WorkerDispatch, guild_id: GuildId, channel_id: ChannelId, message_id: String) -> Result<(), crate::Error> {
+        let res = dispatch.worker_state.reqwest.post(format!("{}/api/v10/channels/{channel_id}/messages/{message_id}/crosspost", CONFIG.proxy))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+            .send()
+            .await?;
+
+        if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = res.json::<Value>().await.ok()
+                .and_then(|v| v.get("retry_after").and_then(|v| v.as_f64()))
+                .unwrap_or_default();
+            let skipped = AutoPublishSkipped { channel_id, message_id, reason: AutoPublishSkipReason::Ratelimited, retry_after_secs: retry_after.ceil() as u64 };
+            dispatch.dispatch_event_complex(Id::Guild(guild_id), AUTO_PUBLISH_SKIPPED_EVENT, None, skipped).await?;
+            return Ok(());
+        }
+        res.error_for_status()?;
+        Ok(())
+    }
+}
@@ -15,16 +15,26 @@ pub struct Builtins;
 #[prefix = "templating-types/"]
 pub struct TemplatingTypes;
 
+/// Standard library of helper modules templates can require through ``@std``
+#[derive(Embed, Debug)]
+#[folder = "$CARGO_MANIFEST_DIR/luau/bot/std"]
+#[prefix = "std/"]
+pub struct StdLib;
+
 pub static BUILTINS: LazyLock<Arc<mluau_require::Vfs>> = LazyLock::new(|| {
     Arc::new(create_memory_vfs_from_embedded::<Builtins>())
 });
 pub static TEMPLATING_TYPES: LazyLock<Arc<mluau_require::Vfs>> = LazyLock::new(|| {
     Arc::new(create_memory_vfs_from_embedded::<TemplatingTypes>())
 });
+pub static STDLIB: LazyLock<Arc<mluau_require::Vfs>> = LazyLock::new(|| {
+    Arc::new(create_memory_vfs_from_embedded::<StdLib>())
+});
 
 pub static EXPOSED_VFS: LazyLock<HashMap<String, Vfs>> = LazyLock::new(|| {
     let mut map = HashMap::new();
     map.insert("Builtins".to_string(), Vfs::new(BUILTINS.clone(), false));
     map.insert("TemplatingTypes".to_string(), Vfs::new(TEMPLATING_TYPES.clone(), false));
+    map.insert("Std".to_string(), Vfs::new(STDLIB.clone(), false));
     map
 });
\ No newline at end of file
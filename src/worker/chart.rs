@@ -0,0 +1,365 @@
+use std::{f32::consts::PI, time::Instant};
+
+use bytes::Bytes;
+use image::{Rgba, RgbaImage};
+
+use crate::worker::{
+    imggen::{self, Align, Color, FontWeight, OutputFormat},
+    limits::{MAX_CHART_DATASETS, MAX_CHART_POINTS, MAX_IMGGEN_DIMENSION, MAX_IMGGEN_TEXT_LENGTH},
+};
+
+/// Discord limits attachment descriptions (alt text) to 1024 characters
+const MAX_ALT_TEXT_LENGTH: usize = 1024;
+const PADDING: f32 = 16.0;
+const TITLE_SIZE: f32 = 20.0;
+const LABEL_SIZE: f32 = 13.0;
+const LEGEND_SWATCH: f32 = 12.0;
+
+/// Colors used for datasets (or pie slices) which don't set one
+const PALETTE: [[u8; 4]; 8] = [
+    [88, 101, 242, 255],
+    [87, 242, 135, 255],
+    [254, 231, 92, 255],
+    [237, 66, 69, 255],
+    [235, 69, 158, 255],
+    [52, 152, 219, 255],
+    [230, 126, 34, 255],
+    [155, 89, 182, 255],
+];
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChartKind {
+    Line,
+    Bar,
+    Pie,
+}
+
+impl ChartKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Line => "Line",
+            Self::Bar => "Bar",
+            Self::Pie => "Pie",
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Dataset {
+    pub label: String,
+    pub values: Vec<f64>,
+    pub color: Option<Color>,
+}
+
+fn default_width() -> u32 {
+    800
+}
+
+fn default_height() -> u32 {
+    400
+}
+
+fn default_background() -> Color {
+    Color(Rgba([43, 45, 49, 255]))
+}
+
+fn default_text_color() -> Color {
+    Color(Rgba([219, 222, 225, 255]))
+}
+
+/// A chart to render
+///
+/// Line and bar charts plot every dataset against `labels`, pie charts use the first dataset with a slice per label
+#[derive(Debug, serde::Deserialize)]
+pub struct ChartSpec {
+    #[serde(rename = "type")]
+    pub kind: ChartKind,
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[serde(default = "default_height")]
+    pub height: u32,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub datasets: Vec<Dataset>,
+    #[serde(default = "default_background")]
+    pub background: Color,
+    #[serde(default = "default_text_color")]
+    pub textcolor: Color,
+    #[serde(default)]
+    pub format: OutputFormat,
+}
+
+impl ChartSpec {
+    /// Checks the chart is within the render budget
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.width < 100 || self.height < 100 || self.width > MAX_IMGGEN_DIMENSION || self.height > MAX_IMGGEN_DIMENSION {
+            return Err(format!("Chart dimensions must be between 100 and {MAX_IMGGEN_DIMENSION}").into());
+        }
+        if self.datasets.is_empty() || self.datasets.len() > MAX_CHART_DATASETS {
+            return Err(format!("Charts must have between 1 and {MAX_CHART_DATASETS} datasets").into());
+        }
+        if self.labels.len() > MAX_CHART_POINTS {
+            return Err(format!("Charts cannot have more than {MAX_CHART_POINTS} labels").into());
+        }
+
+        let texts = self.title.iter().chain(self.labels.iter()).chain(self.datasets.iter().map(|d| &d.label));
+        for text in texts {
+            if text.chars().count() > MAX_IMGGEN_TEXT_LENGTH {
+                return Err(format!("Chart labels cannot be longer than {MAX_IMGGEN_TEXT_LENGTH} characters").into());
+            }
+        }
+
+        for dataset in &self.datasets {
+            if dataset.values.len() != self.labels.len() {
+                return Err(format!("Dataset '{}' has {} values but there are {} labels", dataset.label, dataset.values.len(), self.labels.len()).into());
+            }
+            if dataset.values.iter().any(|v| !v.is_finite()) {
+                return Err(format!("Dataset '{}' contains a non-finite value", dataset.label).into());
+            }
+        }
+
+        if matches!(self.kind, ChartKind::Pie) && self.datasets[0].values.iter().any(|v| *v < 0.0) {
+            return Err("Pie chart values cannot be negative".into());
+        }
+        Ok(())
+    }
+
+    fn dataset_color(&self, i: usize) -> Rgba<u8> {
+        self.datasets[i].color.map(|c| c.0).unwrap_or(Rgba(PALETTE[i % PALETTE.len()]))
+    }
+
+    /// Generates alt text describing the chart for accessibility
+    pub fn alt_text(&self) -> String {
+        let mut alt = format!("{} chart", self.kind.name());
+        if let Some(ref title) = self.title {
+            alt.push_str(&format!(" titled \"{title}\""));
+        }
+
+        match self.kind {
+            ChartKind::Pie => {
+                let values = &self.datasets[0].values;
+                let total: f64 = values.iter().sum();
+                alt.push_str(&format!(" of {} ({} total).", self.datasets[0].label, format_number(total)));
+                let mut slices: Vec<_> = self.labels.iter().zip(values).collect();
+                slices.sort_by(|a, b| b.1.total_cmp(a.1));
+                for (label, value) in slices {
+                    let pct = if total > 0.0 { value / total * 100.0 } else { 0.0 };
+                    alt.push_str(&format!(" {label}: {} ({pct:.1}%).", format_number(*value)));
+                }
+            }
+            ChartKind::Line | ChartKind::Bar => {
+                match (self.labels.first(), self.labels.last()) {
+                    (Some(first), Some(last)) => alt.push_str(&format!(" with {} points from {first} to {last}.", self.labels.len())),
+                    _ => alt.push_str(" with no data."),
+                }
+                for dataset in &self.datasets {
+                    let min = dataset.values.iter().enumerate().min_by(|a, b| a.1.total_cmp(b.1));
+                    let max = dataset.values.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1));
+                    if let (Some((min_i, min)), Some((max_i, max)), Some(latest)) = (min, max, dataset.values.last()) {
+                        alt.push_str(&format!(
+                            " {}: lowest {} ({}), highest {} ({}), latest {}.",
+                            dataset.label, format_number(*min), self.labels[min_i], format_number(*max), self.labels[max_i], format_number(*latest)
+                        ));
+                    }
+                }
+            }
+        }
+
+        if alt.chars().count() > MAX_ALT_TEXT_LENGTH {
+            alt = alt.chars().take(MAX_ALT_TEXT_LENGTH - 1).collect::<String>() + "…";
+        }
+        alt
+    }
+}
+
+/// Formats a number without trailing zeros (at most 2 decimal places)
+fn format_number(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 1e15 {
+        return format!("{v:.0}");
+    }
+    let s = format!("{v:.2}");
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Returns a "nice" tick step (1, 2 or 5 times a power of 10) splitting `range` into about `ticks` steps
+fn nice_step(range: f64, ticks: f64) -> f64 {
+    let raw = range / ticks;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let norm = raw / magnitude;
+    let nice = if norm <= 1.0 { 1.0 } else if norm <= 2.0 { 2.0 } else if norm <= 5.0 { 5.0 } else { 10.0 };
+    nice * magnitude
+}
+
+/// Renders a (validated) chart
+pub fn render(spec: &ChartSpec, deadline: Instant) -> Result<Bytes, crate::Error> {
+    let mut canvas = RgbaImage::from_pixel(spec.width, spec.height, spec.background.0);
+    let (width, height) = (spec.width as f32, spec.height as f32);
+    let text = spec.textcolor.0;
+
+    let mut top = PADDING;
+    if let Some(ref title) = spec.title {
+        imggen::draw_text(&mut canvas, FontWeight::Bold.font(), title, width / 2.0, top, TITLE_SIZE, text, Align::Center, Some(width - PADDING * 2.0));
+        top += TITLE_SIZE + PADDING;
+    }
+
+    match spec.kind {
+        ChartKind::Pie => draw_pie(&mut canvas, spec, top, deadline)?,
+        ChartKind::Line | ChartKind::Bar => {
+            // Legend along the bottom
+            let bottom = height - PADDING - LABEL_SIZE;
+            let mut x = PADDING;
+            for (i, dataset) in spec.datasets.iter().enumerate() {
+                imggen::fill_rounded_rect(&mut canvas, x, bottom, LEGEND_SWATCH, LEGEND_SWATCH, 2.0, spec.dataset_color(i));
+                x += LEGEND_SWATCH + 6.0;
+                imggen::draw_text(&mut canvas, FontWeight::Regular.font(), &dataset.label, x, bottom - 1.0, LABEL_SIZE, text, Align::Left, None);
+                x += imggen::text_width(FontWeight::Regular.font(), &dataset.label, LABEL_SIZE) + PADDING;
+            }
+
+            draw_axes_chart(&mut canvas, spec, top, bottom - PADDING, deadline)?;
+        }
+    }
+
+    imggen::encode(canvas, spec.format)
+}
+
+/// Draws the plot area of line and bar charts between `top` and `bottom`
+fn draw_axes_chart(canvas: &mut RgbaImage, spec: &ChartSpec, top: f32, bottom: f32, deadline: Instant) -> Result<(), crate::Error> {
+    let font = FontWeight::Regular.font();
+    let text = spec.textcolor.0;
+    let grid = Rgba([text.0[0], text.0[1], text.0[2], 40]);
+
+    let values = spec.datasets.iter().flat_map(|d| d.values.iter().copied());
+    let (min, max) = values.fold((0.0f64, 0.0f64), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let step = nice_step(if max > min { max - min } else { 1.0 }, 5.0);
+    let (min, max) = ((min / step).floor() * step, ((max / step).ceil() * step).max(step));
+
+    // Y axis labels and grid lines
+    let ticks: Vec<f64> = (0..).map(|i| min + step * i as f64).take_while(|v| *v <= max + step / 2.0).collect();
+    let axis_width = ticks.iter().map(|v| imggen::text_width(font, &format_number(*v), LABEL_SIZE)).fold(0.0, f32::max);
+    let (plot_left, plot_right) = (PADDING + axis_width + 8.0, canvas.width() as f32 - PADDING);
+    let (plot_top, plot_bottom) = (top + LABEL_SIZE / 2.0, bottom - LABEL_SIZE - 8.0);
+    if plot_right - plot_left < 10.0 || plot_bottom - plot_top < 10.0 {
+        return Err("Chart is too small to fit its labels".into());
+    }
+
+    let y_of = |v: f64| plot_bottom - ((v - min) / (max - min)) as f32 * (plot_bottom - plot_top);
+    for tick in &ticks {
+        let y = y_of(*tick);
+        imggen::draw_line(canvas, plot_left, y, plot_right, y, 1.0, grid);
+        imggen::draw_text(canvas, font, &format_number(*tick), plot_left - 8.0, y - LABEL_SIZE / 2.0 - 2.0, LABEL_SIZE, text, Align::Right, None);
+    }
+
+    let n = spec.labels.len();
+    if n == 0 {
+        return Ok(());
+    }
+
+    // Slot centers: bars are centered in equal slots, lines span the full width
+    let plot_width = plot_right - plot_left;
+    let x_of = |i: usize| match spec.kind {
+        ChartKind::Line if n > 1 => plot_left + plot_width * i as f32 / (n - 1) as f32,
+        _ => plot_left + plot_width * (i as f32 + 0.5) / n as f32,
+    };
+
+    // X axis labels, skipping labels so they don't overlap
+    let widest = spec.labels.iter().map(|l| imggen::text_width(font, l, LABEL_SIZE)).fold(0.0, f32::max) + 8.0;
+    let every = ((widest * n as f32 / plot_width).ceil() as usize).max(1);
+    for i in (0..n).step_by(every) {
+        imggen::draw_text(canvas, font, &spec.labels[i], x_of(i), plot_bottom + 6.0, LABEL_SIZE, text, Align::Center, Some(widest * every as f32 - 8.0));
+    }
+
+    for (d, dataset) in spec.datasets.iter().enumerate() {
+        imggen::check_deadline(deadline)?;
+        let color = spec.dataset_color(d);
+        match spec.kind {
+            ChartKind::Line => {
+                let points: Vec<(f32, f32)> = dataset.values.iter().enumerate().map(|(i, v)| (x_of(i), y_of(*v))).collect();
+                for w in points.windows(2) {
+                    imggen::draw_line(canvas, w[0].0, w[0].1, w[1].0, w[1].1, 2.5, color);
+                }
+                // Mark points unless they are too dense to tell apart
+                if plot_width / n as f32 >= 12.0 {
+                    for (x, y) in &points {
+                        imggen::fill_rounded_rect(canvas, x - 3.5, y - 3.5, 7.0, 7.0, 3.5, color);
+                    }
+                }
+            }
+            ChartKind::Bar => {
+                let slot = plot_width / n as f32;
+                let bar = (slot * 0.8) / spec.datasets.len() as f32;
+                let zero = y_of(0.0f64.clamp(min, max));
+                for (i, v) in dataset.values.iter().enumerate() {
+                    let x = x_of(i) - slot * 0.4 + bar * d as f32;
+                    let y = y_of(*v);
+                    imggen::fill_rounded_rect(canvas, x, y.min(zero), bar.max(1.0), (y - zero).abs(), 0.0, color);
+                }
+            }
+            ChartKind::Pie => unreachable!("pie charts have no axes"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Draws a pie chart of the first dataset below `top`, with a legend to the right
+fn draw_pie(canvas: &mut RgbaImage, spec: &ChartSpec, top: f32, deadline: Instant) -> Result<(), crate::Error> {
+    let font = FontWeight::Regular.font();
+    let values = &spec.datasets[0].values;
+    let total: f64 = values.iter().sum();
+    let slice_color = |i: usize| Rgba(PALETTE[i % PALETTE.len()]);
+
+    let (width, height) = (canvas.width() as f32, canvas.height() as f32);
+    let radius = ((height - top - PADDING) / 2.0).min(width / 4.0);
+    if radius < 10.0 {
+        return Err("Chart is too small to fit a pie".into());
+    }
+    let (cx, cy) = (PADDING + radius, top + radius);
+
+    // Legend to the right of the pie
+    let legend_x = cx + radius + PADDING * 2.0;
+    for (i, label) in spec.labels.iter().enumerate() {
+        let y = top + i as f32 * (LABEL_SIZE + 8.0);
+        if y + LABEL_SIZE > height - PADDING {
+            break;
+        }
+        let pct = if total > 0.0 { values[i] / total * 100.0 } else { 0.0 };
+        imggen::fill_rounded_rect(canvas, legend_x, y, LEGEND_SWATCH, LEGEND_SWATCH, 2.0, slice_color(i));
+        let entry = format!("{label} ({pct:.1}%)");
+        let x = legend_x + LEGEND_SWATCH + 6.0;
+        imggen::draw_text(canvas, font, &entry, x, y - 1.0, LABEL_SIZE, spec.textcolor.0, Align::Left, Some(width - PADDING - x));
+    }
+
+    if total <= 0.0 {
+        return Ok(());
+    }
+
+    // Cumulative end angle of each slice, clockwise from 12 o'clock
+    let mut ends = Vec::with_capacity(values.len());
+    let mut acc = 0.0;
+    for v in values {
+        acc += v / total;
+        ends.push((acc * 2.0 * std::f64::consts::PI) as f32);
+    }
+
+    let (min_y, max_y) = ((cy - radius).floor() as i64, (cy + radius).ceil() as i64);
+    for py in min_y..max_y {
+        imggen::check_deadline(deadline)?;
+        for px in (cx - radius).floor() as i64..(cx + radius).ceil() as i64 {
+            let (dx, dy) = (px as f32 + 0.5 - cx, py as f32 + 0.5 - cy);
+            let coverage = radius + 0.5 - dx.hypot(dy);
+            if coverage <= 0.0 {
+                continue;
+            }
+            let mut angle = dx.atan2(-dy);
+            if angle < 0.0 {
+                angle += 2.0 * PI;
+            }
+            let slice = ends.iter().position(|end| angle <= *end).unwrap_or(ends.len() - 1);
+            imggen::blend(canvas, px, py, slice_color(slice), coverage);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use base64::{Engine, engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}};
+use ed25519_dalek::{Signature, VerifyingKey};
+use hmac::{Hmac, KeyInit, Mac};
+use khronos_runtime::rt::mlua::prelude::*;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use sha3::{Sha3_256, Sha3_384, Sha3_512};
+use subtle::ConstantTimeEq;
+
+use crate::worker::limits::MAX_CRYPTO_INPUT_BYTES;
+
+/// Name of the VM global the `@antiraid/crypto` module is exposed as
+pub const CRYPTO_GLOBAL: &str = "__antiraid_crypto";
+
+/// Converts a string or buffer passed to the module to bytes, enforcing the input size cap
+fn input_bytes(value: LuaValue, what: &str) -> LuaResult<Vec<u8>> {
+    let bytes = match value {
+        LuaValue::String(s) => s.as_bytes().to_vec(),
+        LuaValue::Buffer(b) => b.to_vec(),
+        v => return Err(LuaError::external(format!("{what} must be a string or buffer, got {}", v.type_name()))),
+    };
+
+    if bytes.len() > MAX_CRYPTO_INPUT_BYTES {
+        return Err(LuaError::external(format!("{what} exceeds the maximum size of {MAX_CRYPTO_INPUT_BYTES} bytes")));
+    }
+
+    Ok(bytes)
+}
+
+/// Encodes a digest as requested (`hex` by default, `base64` or `raw` for a buffer)
+fn encode_output(lua: &Lua, bytes: Vec<u8>, encoding: Option<String>) -> LuaResult<LuaValue> {
+    match encoding.as_deref().unwrap_or("hex") {
+        "hex" => hex::encode(bytes).into_lua(lua),
+        "base64" => STANDARD.encode(bytes).into_lua(lua),
+        "raw" => lua.create_buffer(bytes).map(LuaValue::Buffer),
+        e => Err(LuaError::external(format!("Unsupported encoding: {e}"))),
+    }
+}
+
+fn digest(algorithm: &str, data: &[u8]) -> LuaResult<Vec<u8>> {
+    Ok(match algorithm {
+        "sha224" => Sha224::digest(data).to_vec(),
+        "sha256" => Sha256::digest(data).to_vec(),
+        "sha384" => Sha384::digest(data).to_vec(),
+        "sha512" => Sha512::digest(data).to_vec(),
+        "sha3-256" => Sha3_256::digest(data).to_vec(),
+        "sha3-384" => Sha3_384::digest(data).to_vec(),
+        "sha3-512" => Sha3_512::digest(data).to_vec(),
+        "blake3" => blake3::hash(data).as_bytes().to_vec(),
+        a => return Err(LuaError::external(format!("Unsupported hash algorithm: {a}"))),
+    })
+}
+
+fn hmac(algorithm: &str, key: &[u8], data: &[u8]) -> LuaResult<Vec<u8>> {
+    macro_rules! mac {
+        ($digest:ty) => {{
+            let mut mac = Hmac::<$digest>::new_from_slice(key)
+                .map_err(|e| LuaError::external(format!("Invalid HMAC key: {e}")))?;
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }};
+    }
+
+    Ok(match algorithm {
+        "sha224" => mac!(Sha224),
+        "sha256" => mac!(Sha256),
+        "sha384" => mac!(Sha384),
+        "sha512" => mac!(Sha512),
+        "sha3-256" => mac!(Sha3_256),
+        "sha3-384" => mac!(Sha3_384),
+        "sha3-512" => mac!(Sha3_512),
+        "blake3" => {
+            // BLAKE3 has its own keyed mode which requires a 32 byte key
+            let key: [u8; 32] = key.try_into()
+                .map_err(|_| LuaError::external("BLAKE3 keys must be exactly 32 bytes"))?;
+            blake3::keyed_hash(&key, data).as_bytes().to_vec()
+        }
+        a => return Err(LuaError::external(format!("Unsupported HMAC algorithm: {a}"))),
+    })
+}
+
+/// Verifies an Ed25519 signature (such as the `X-Signature-Ed25519` header of Discord interactions)
+fn ed25519_verify(public_key: &[u8], signature: &[u8], message: &[u8]) -> bool {
+    let Ok(public_key) = <[u8; 32]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(key) = VerifyingKey::from_bytes(&public_key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify_strict(message, &signature).is_ok()
+}
+
+/// Creates the `@antiraid/crypto` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.raw_set("hash", lua.create_function(|lua, (algorithm, data, encoding): (String, LuaValue, Option<String>)| {
+        let data = input_bytes(data, "data")?;
+        encode_output(lua, digest(&algorithm, &data)?, encoding)
+    })?)?;
+
+    module.raw_set("hmac", lua.create_function(|lua, (algorithm, key, data, encoding): (String, LuaValue, LuaValue, Option<String>)| {
+        let key = input_bytes(key, "key")?;
+        let data = input_bytes(data, "data")?;
+        encode_output(lua, hmac(&algorithm, &key, &data)?, encoding)
+    })?)?;
+
+    module.raw_set("constanteq", lua.create_function(|_, (a, b): (LuaValue, LuaValue)| {
+        let a = input_bytes(a, "a")?;
+        let b = input_bytes(b, "b")?;
+        Ok(bool::from(a.as_slice().ct_eq(b.as_slice())))
+    })?)?;
+
+    module.raw_set("ed25519verify", lua.create_function(|_, (public_key, signature, message): (LuaValue, LuaValue, LuaValue)| {
+        let public_key = input_bytes(public_key, "public key")?;
+        let signature = input_bytes(signature, "signature")?;
+        let message = input_bytes(message, "message")?;
+        Ok(ed25519_verify(&public_key, &signature, &message))
+    })?)?;
+
+    module.raw_set("hexencode", lua.create_function(|_, data: LuaValue| {
+        Ok(hex::encode(input_bytes(data, "data")?))
+    })?)?;
+
+    module.raw_set("hexdecode", lua.create_function(|lua, data: LuaValue| {
+        let bytes = hex::decode(input_bytes(data, "data")?)
+            .map_err(|e| LuaError::external(format!("Invalid hex: {e}")))?;
+        lua.create_buffer(bytes)
+    })?)?;
+
+    module.raw_set("base64encode", lua.create_function(|_, (data, urlsafe): (LuaValue, Option<bool>)| {
+        let data = input_bytes(data, "data")?;
+        Ok(if urlsafe.unwrap_or(false) { URL_SAFE_NO_PAD.encode(data) } else { STANDARD.encode(data) })
+    })?)?;
+
+    module.raw_set("base64decode", lua.create_function(|lua, (data, urlsafe): (LuaValue, Option<bool>)| {
+        let data = input_bytes(data, "data")?;
+        let bytes = (if urlsafe.unwrap_or(false) { URL_SAFE_NO_PAD.decode(data) } else { STANDARD.decode(data) })
+            .map_err(|e| LuaError::external(format!("Invalid base64: {e}")))?;
+        lua.create_buffer(bytes)
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
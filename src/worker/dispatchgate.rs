@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, OwnedSemaphorePermit, RwLock, Semaphore};
+
+use super::workervmmanager::Id;
+
+/// Gates the work a worker thread does for its tenants
+///
+/// Changes to the VM of a tenant (such as dropping it or reloading its tenant state) wait for the running dispatches
+/// of the tenant and hold off new ones, so its VM is not swapped out under a running dispatch. Dispatches of the same
+/// tenant otherwise run concurrently, serializing them is up to the execution locks of the templates (see `pragma`).
+/// At most `max_dispatches` dispatches run at once on the worker thread, further ones wait for a slot.
+///
+/// Tenant locks only exist while held or waited for
+#[derive(Clone)]
+pub struct DispatchGate {
+    tenants: Rc<RefCell<HashMap<Id, Arc<RwLock<()>>>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl DispatchGate {
+    pub fn new(max_dispatches: usize) -> Self {
+        Self {
+            tenants: Rc::default(),
+            permits: Arc::new(Semaphore::new(max_dispatches)),
+        }
+    }
+
+    fn lock(&self, id: Id) -> Arc<RwLock<()>> {
+        self.tenants.borrow_mut().entry(id).or_default().clone()
+    }
+
+    /// Waits for the running dispatches of the tenant to finish, returning a guard holding off new ones until dropped
+    ///
+    /// Waiters are served in the order they started waiting
+    pub async fn tenant(&self, id: Id) -> TenantGuard {
+        let guard = self.lock(id).write_owned().await;
+        TenantGuard { gate: self.clone(), id, shared: None, exclusive: Some(guard), _permit: None }
+    }
+
+    /// Waits for the VM of the tenant to be free of changes and for a dispatch slot, returning a guard holding both until dropped
+    pub async fn dispatch(&self, id: Id) -> TenantGuard {
+        let guard = self.lock(id).read_owned().await;
+        let mut guard = TenantGuard { gate: self.clone(), id, shared: Some(guard), exclusive: None, _permit: None };
+        let permit = self.permits.clone().acquire_owned().await.expect("dispatch semaphore is never closed");
+        guard._permit = Some(permit);
+        guard
+    }
+
+    /// Removes the lock of a tenant if nobody holds or waits for it
+    fn prune(&self, id: Id) {
+        let mut tenants = self.tenants.borrow_mut();
+        if tenants.get(&id).is_some_and(|l| Arc::strong_count(l) == 1) {
+            tenants.remove(&id);
+        }
+    }
+}
+
+/// A held tenant (and dispatch slot, if acquired through `DispatchGate::dispatch`)
+pub struct TenantGuard {
+    gate: DispatchGate,
+    id: Id,
+    /// Held by a dispatch, alongside other dispatches
+    shared: Option<OwnedRwLockReadGuard<()>>,
+    /// Held by a change to the VM of the tenant
+    exclusive: Option<OwnedRwLockWriteGuard<()>>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for TenantGuard {
+    fn drop(&mut self) {
+        // Release the lock before pruning it
+        let (shared, exclusive) = (self.shared.take(), self.exclusive.take());
+        if shared.is_some() || exclusive.is_some() {
+            drop((shared, exclusive));
+            self.gate.prune(self.id);
+        }
+    }
+}
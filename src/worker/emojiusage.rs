@@ -0,0 +1,92 @@
+use std::sync::LazyLock;
+
+use dapi::GuildId;
+use serde_json::Value;
+
+use crate::geese::emojiusage::{EmojiKind, EmojiUseSource};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Maximum number of distinct emojis counted per message, so spamming emojis can't flood the counters
+const MAX_EMOJIS_PER_MESSAGE: usize = 20;
+
+/// Custom emojis in message content (`<:name:id>` or `<a:name:id>`)
+static CUSTOM_EMOJI: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"<a?:(\w{2,32}):(\d{1,20})>").expect("Invalid custom emoji pattern")
+});
+
+/// Counts the custom emojis and stickers used in the messages and reactions of guilds tracking emoji usage
+///
+/// Only 1 in `sample_one_in` messages and reactions are scanned (with each use found weighted accordingly), so the
+/// counts of sampled guilds are estimates. Uses by bots are not counted. Counts are accumulated by the worker state
+/// and flushed to the master periodically
+pub struct EmojiUsage;
+
+impl EmojiUsage {
+    /// Counts the custom emojis in the content and the stickers of a MESSAGE_CREATE payload
+    pub fn observe_message(dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.emoji_usage(Id::Guild(guild_id)) else {
+            return;
+        };
+        if payload.get("author").and_then(|a| a.get("bot")).and_then(|v| v.as_bool()).unwrap_or(false) || !sampled(config.sample_one_in) {
+            return;
+        }
+
+        let tracker = &dispatch.worker_state.emoji_usage;
+        let weight = config.sample_one_in.into();
+        let content = payload.get("content").and_then(|v| v.as_str()).unwrap_or_default();
+        let mut seen = Vec::new();
+        for captures in CUSTOM_EMOJI.captures_iter(content) {
+            let (Some(name), Some(id)) = (captures.get(1), captures.get(2)) else {
+                continue;
+            };
+            let (name, id) = (name.as_str(), id.as_str());
+            if seen.contains(&id) {
+                continue;
+            }
+            if seen.len() >= MAX_EMOJIS_PER_MESSAGE {
+                break;
+            }
+            seen.push(id);
+            tracker.record(guild_id, EmojiKind::Emoji, id, name, EmojiUseSource::Message, weight);
+        }
+
+        let stickers = payload.get("sticker_items").and_then(|v| v.as_array()).into_iter().flatten();
+        for sticker in stickers {
+            let Some(id) = sticker.get("id").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let name = sticker.get("name").and_then(|v| v.as_str()).unwrap_or_default();
+            tracker.record(guild_id, EmojiKind::Sticker, id, name, EmojiUseSource::Message, weight);
+        }
+    }
+
+    /// Counts the custom emoji of a MESSAGE_REACTION_ADD payload
+    pub fn observe_reaction(dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.emoji_usage(Id::Guild(guild_id)) else {
+            return;
+        };
+        if !config.reactions {
+            return;
+        }
+        let bot = payload.get("member").and_then(|m| m.get("user")).and_then(|u| u.get("bot")).and_then(|v| v.as_bool()).unwrap_or(false);
+        let Some(emoji) = payload.get("emoji") else {
+            return;
+        };
+        // Unicode emojis have no ID
+        let Some(id) = emoji.get("id").and_then(|v| v.as_str()) else {
+            return;
+        };
+        if bot || !sampled(config.sample_one_in) {
+            return;
+        }
+
+        let name = emoji.get("name").and_then(|v| v.as_str()).unwrap_or_default();
+        dispatch.worker_state.emoji_usage.record(guild_id, EmojiKind::Emoji, id, name, EmojiUseSource::Reaction, config.sample_one_in.into());
+    }
+}
+
+/// Returns whether an event is scanned when sampling 1 in `one_in` events
+fn sampled(one_in: u32) -> bool {
+    one_in <= 1 || rand::random_range(0..one_in) == 0
+}
@@ -0,0 +1,275 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dapi::UserId;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::config::{EventSinkConfig, EventSinkTarget};
+use crate::worker::workervmmanager::Id;
+
+/// Version of the schema of published records, bumped on breaking changes to `SinkRecord`
+pub const SINK_SCHEMA_VERSION: u32 = 1;
+
+/// How many times a batch is published before it is dropped
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before retrying a failed batch, doubled on every further attempt
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Timeout of connecting to (and publishing a batch to) the sink
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Placeholder of redacted values
+const REDACTED: &str = "[redacted]";
+
+/// Result of the execution of a published event
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SinkResult {
+    Ok { value: Value },
+    Error { error: String },
+}
+
+/// A dispatched event as published to the sink
+#[derive(Debug, Clone, Serialize)]
+pub struct SinkRecord {
+    pub schema_version: u32,
+    pub worker_id: u64,
+    pub tenant: Id,
+    pub event: String,
+    pub author: Option<UserId>,
+    pub dispatched_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub data: Value,
+    /// Only set if results are enabled
+    pub result: Option<SinkResult>,
+}
+
+/// A redacted record waiting to be published
+struct QueuedRecord {
+    /// Key of the record (the tenant), so the events of a tenant stay ordered within a Kafka partition
+    key: String,
+    event: String,
+    value: Value,
+}
+
+/// Publishes dispatched events (and their results) to Kafka or NATS
+///
+/// Publishing is decoupled from dispatch through a bounded buffer: records are dropped (and counted) instead
+/// of slowing down dispatch when the sink can't keep up, and records still buffered when the worker is killed
+/// are lost. The sink is meant for analytics, not as a source of truth
+pub struct EventSink {
+    config: &'static EventSinkConfig,
+    worker_id: u64,
+    tx: mpsc::Sender<QueuedRecord>,
+    dropped: AtomicU64,
+}
+
+impl EventSink {
+    /// Creates the event sink and spawns the task publishing buffered records
+    pub fn new(config: &'static EventSinkConfig, worker_id: u64, reqwest: reqwest::Client) -> Self {
+        let (tx, rx) = mpsc::channel(config.buffer_size.max(1));
+        tokio::spawn(Publisher { config, reqwest, nats: None }.run(rx));
+        Self { config, worker_id, tx, dropped: AtomicU64::new(0) }
+    }
+
+    /// Returns whether an event is published
+    pub fn selects(&self, event: &str) -> bool {
+        self.config.events.is_empty() || self.config.events.iter().any(|e| match e.strip_suffix('*') {
+            Some(prefix) => event.starts_with(prefix),
+            None => e == event,
+        })
+    }
+
+    /// Returns whether results of executions are published
+    pub fn includes_results(&self) -> bool {
+        self.config.results
+    }
+
+    /// Buffers a record for publishing, redacting it first
+    pub fn publish(&self, tenant: Id, event: &str, author: Option<UserId>, dispatched_at: DateTime<Utc>, data: Value, result: Option<SinkResult>) {
+        let record = SinkRecord {
+            schema_version: SINK_SCHEMA_VERSION,
+            worker_id: self.worker_id,
+            tenant,
+            event: event.to_string(),
+            author,
+            duration_ms: (Utc::now() - dispatched_at).num_milliseconds().max(0) as u64,
+            dispatched_at,
+            data,
+            result,
+        };
+
+        let mut value = match serde_json::to_value(&record) {
+            Ok(value) => value,
+            Err(e) => {
+                log::error!("Failed to serialize {event} for the event sink: {e}");
+                return;
+            }
+        };
+        for path in self.config.redact.iter() {
+            redact_path(&mut value, &path.split('.').collect::<Vec<_>>());
+        }
+
+        let queued = QueuedRecord { key: tenant.tenant_id(), event: record.event, value };
+        if self.tx.try_send(queued).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                log::warn!("Event sink buffer is full, {dropped} records dropped so far");
+            }
+        }
+    }
+}
+
+/// Replaces the value at a path with `REDACTED`, `*` matching any key or array element
+fn redact_path(value: &mut Value, path: &[&str]) {
+    let Some((first, rest)) = path.split_first() else {
+        *value = Value::String(REDACTED.to_string());
+        return;
+    };
+
+    match (value, *first) {
+        (Value::Object(obj), "*") => obj.values_mut().for_each(|v| redact_path(v, rest)),
+        (Value::Array(arr), "*") => arr.iter_mut().for_each(|v| redact_path(v, rest)),
+        (Value::Object(obj), key) => {
+            if let Some(v) = obj.get_mut(key) {
+                redact_path(v, rest);
+            }
+        }
+        (Value::Array(arr), idx) => {
+            if let Some(v) = idx.parse::<usize>().ok().and_then(|i| arr.get_mut(i)) {
+                redact_path(v, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Background task publishing buffered records in batches
+struct Publisher {
+    config: &'static EventSinkConfig,
+    reqwest: reqwest::Client,
+    /// Connection to NATS, reconnected on the next batch if it fails
+    nats: Option<NatsConn>,
+}
+
+impl Publisher {
+    async fn run(mut self, mut rx: mpsc::Receiver<QueuedRecord>) {
+        let mut batch = Vec::with_capacity(self.config.batch_size);
+        while rx.recv_many(&mut batch, self.config.batch_size.max(1)).await > 0 {
+            let mut attempt = 1;
+            loop {
+                match tokio::time::timeout(PUBLISH_TIMEOUT, self.publish(&batch)).await.unwrap_or_else(|_| Err("Timed out".into())) {
+                    Ok(()) => break,
+                    Err(e) if attempt < MAX_ATTEMPTS => {
+                        log::warn!("Failed to publish {} records to the event sink (attempt {attempt}), retrying: {e}", batch.len());
+                        self.nats = None;
+                        tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempt - 1)).await;
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        log::error!("Failed to publish {} records to the event sink, dropping them: {e}", batch.len());
+                        self.nats = None;
+                        break;
+                    }
+                }
+            }
+            batch.clear();
+        }
+    }
+
+    async fn publish(&mut self, batch: &[QueuedRecord]) -> Result<(), crate::Error> {
+        let config = self.config;
+        match &config.target {
+            EventSinkTarget::Kafka { rest_proxy, topic } => {
+                let records = batch.iter().map(|r| serde_json::json!({ "key": r.key, "value": r.value })).collect::<Vec<_>>();
+                self.reqwest
+                    .post(format!("{}/topics/{topic}", rest_proxy.trim_end_matches('/')))
+                    .header(reqwest::header::CONTENT_TYPE, "application/vnd.kafka.json.v2+json")
+                    .json(&serde_json::json!({ "records": records }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(())
+            }
+            EventSinkTarget::Nats { addr, subject, token } => {
+                if self.nats.is_none() {
+                    self.nats = Some(NatsConn::connect(addr, token.as_deref()).await?);
+                }
+                let conn = self.nats.as_mut().expect("connected above");
+                conn.publish(subject, batch).await
+            }
+        }
+    }
+}
+
+/// Minimal NATS core client, only publishing
+struct NatsConn {
+    reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: tokio::net::tcp::OwnedWriteHalf,
+}
+
+impl NatsConn {
+    async fn connect(addr: &str, token: Option<&str>) -> Result<Self, crate::Error> {
+        let (read, writer) = TcpStream::connect(addr).await?.into_split();
+        let mut conn = Self { reader: BufReader::new(read), writer };
+
+        let info = conn.read_line().await?;
+        if !info.starts_with("INFO ") {
+            return Err(format!("Expected INFO from NATS, got: {info}").into());
+        }
+
+        let mut connect = serde_json::json!({ "verbose": false, "pedantic": false, "name": "template-worker", "lang": "rust" });
+        if let Some(token) = token {
+            connect["auth_token"] = token.into();
+        }
+        conn.writer.write_all(format!("CONNECT {connect}\r\nPING\r\n").as_bytes()).await?;
+        conn.await_pong().await?;
+        Ok(conn)
+    }
+
+    async fn read_line(&mut self) -> Result<String, crate::Error> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err("NATS connection closed".into());
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    /// Waits for the server to answer a PING, answering its own PINGs in the meantime
+    async fn await_pong(&mut self) -> Result<(), crate::Error> {
+        loop {
+            let line = self.read_line().await?;
+            match line.as_str() {
+                "PONG" => return Ok(()),
+                "PING" => self.writer.write_all(b"PONG\r\n").await?,
+                l if l.starts_with("-ERR") => return Err(format!("NATS error: {l}").into()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Publishes a batch, then flushes it with a PING so errors (and a dead connection) are noticed
+    async fn publish(&mut self, subject: &str, batch: &[QueuedRecord]) -> Result<(), crate::Error> {
+        let mut buf = Vec::new();
+        for record in batch {
+            let payload = serde_json::to_vec(&record.value)?;
+            buf.extend_from_slice(format!("PUB {subject}.{} {}\r\n", nats_token(&record.event), payload.len()).as_bytes());
+            buf.extend_from_slice(&payload);
+            buf.extend_from_slice(b"\r\n");
+        }
+        buf.extend_from_slice(b"PING\r\n");
+        self.writer.write_all(&buf).await?;
+        self.await_pong().await
+    }
+}
+
+/// Makes an event name usable as a NATS subject token
+fn nats_token(event: &str) -> String {
+    event.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect()
+}
@@ -0,0 +1,226 @@
+use std::rc::Rc;
+
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::Serialize;
+
+/// A gateway event with a typed accessor table
+struct EventType {
+    /// Name of the gateway event
+    event: &'static str,
+    /// Name of the payload type, as shown in errors
+    name: &'static str,
+    /// Top-level fields of the payload
+    fields: &'static [&'static str],
+}
+
+/// Fields shared by all guild member payloads
+macro_rules! guild_member_fields {
+    ($($extra:literal),*) => {
+        &[
+            "user", "nick", "avatar", "banner", "roles", "joined_at", "premium_since", "deaf", "mute", "flags",
+            "pending", "permissions", "communication_disabled_until", "avatar_decoration_data", $($extra),*
+        ]
+    };
+}
+
+/// Gateway events with typed accessors
+///
+/// Fields mirror the gateway payloads in `discord-luau-corrections/gatewayTypes.luau` and must be kept in sync with it
+const EVENT_TYPES: &[EventType] = &[
+    EventType {
+        event: "MESSAGE_CREATE",
+        name: "MessageCreate",
+        fields: &[
+            "id", "channel_id", "guild_id", "author", "member", "content", "timestamp", "edited_timestamp", "tts",
+            "mention_everyone", "mentions", "mention_roles", "mention_channels", "attachments", "embeds", "reactions",
+            "nonce", "pinned", "webhook_id", "type", "activity", "application", "application_id", "message_reference",
+            "message_snapshots", "flags", "referenced_message", "interaction_metadata", "interaction", "thread",
+            "components", "sticker_items", "stickers", "position", "role_subscription_data", "resolved", "poll", "call",
+        ],
+    },
+    EventType {
+        event: "GUILD_MEMBER_ADD",
+        name: "GuildMemberAdd",
+        fields: guild_member_fields!("guild_id", "invite"),
+    },
+    EventType {
+        event: "INTERACTION_CREATE",
+        name: "InteractionCreate",
+        fields: &[
+            "id", "application_id", "type", "data", "guild", "guild_id", "channel", "channel_id", "member", "user",
+            "token", "version", "message", "app_permissions", "locale", "guild_locale", "entitlements",
+            "authorizing_integration_owners", "context", "attachment_size_limit",
+        ],
+    },
+    EventType {
+        event: "GUILD_AUDIT_LOG_ENTRY_CREATE",
+        name: "GuildAuditLogEntryCreate",
+        fields: &["id", "guild_id", "target_id", "changes", "user_id", "action_type", "options", "reason"],
+    },
+];
+
+/// Version of the event payload shapes dispatched, bumped (with a `Change` describing how to undo it) whenever the
+/// payload of an event changes in a way which breaks templates
+///
+/// Templates pin the version they are written against with `pragma.eventversion`, and get payloads converted back
+/// to it
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Name of the (internal) VM global payloads are converted to the event version of a template with
+pub const EVENTSCHEMA_GLOBAL: &str = "__antiraid_eventschema";
+
+/// A change to the payload of an event, undone for templates pinned to an earlier version
+#[allow(dead_code)] // No changes have been made yet
+struct Change {
+    event: &'static str,
+    /// Version the change was made in
+    version: u32,
+    /// Fields added in this version, removed from the payloads of earlier versions
+    added: &'static [&'static str],
+    /// Fields renamed in this version as (old name, new name), renamed back for earlier versions
+    renamed: &'static [(&'static str, &'static str)],
+    /// Converts the rest of the payload back to the previous version, for changes that aren't additions or renames
+    downgrade: Option<fn(&Lua, &LuaTable) -> LuaResult<()>>,
+}
+
+/// Changes to the payloads of events, oldest first
+///
+/// Payload shapes haven't changed since versioning was introduced
+const CHANGES: &[Change] = &[];
+
+/// Returns the changes undone to convert a payload of an event to a version, newest first
+fn changes_since(event: &str, version: u32) -> impl Iterator<Item = &'static Change> {
+    CHANGES.iter().rev().filter(move |c| c.event == event && c.version > version)
+}
+
+/// The fields of an event payload as of a version
+#[derive(Debug, Clone, Serialize)]
+pub struct EventSchema {
+    pub event: &'static str,
+    /// Name of the payload type
+    pub name: &'static str,
+    pub version: u32,
+    /// Top-level fields of the payload
+    pub fields: Vec<&'static str>,
+}
+
+/// Returns the schema of an event as of a version, None if the event has no schema
+pub fn schema(event: &str, version: u32) -> Option<EventSchema> {
+    let typ = EVENT_TYPES.iter().find(|t| t.event == event)?;
+    let mut fields = typ.fields.to_vec();
+    for change in changes_since(event, version) {
+        fields.retain(|f| !change.added.contains(f));
+        for &(old, new) in change.renamed {
+            if let Some(f) = fields.iter_mut().find(|f| **f == new) {
+                *f = old;
+            }
+        }
+    }
+    Some(EventSchema { event: typ.event, name: typ.name, version, fields })
+}
+
+/// Returns the schemas of all events with one, for every version
+pub fn schemas() -> Vec<EventSchema> {
+    EVENT_TYPES.iter()
+        .flat_map(|t| (1..=EVENT_SCHEMA_VERSION).filter_map(|v| schema(t.event, v)))
+        .collect()
+}
+
+/// Creates the typed accessor table of an event, if the event has one
+///
+/// The accessor is a shallow copy of the payload where accessing or setting an unknown field errors instead of
+/// silently returning nil, known fields which are absent from the payload are still nil. As the copy holds the
+/// payload's fields directly, it can be passed anywhere the raw payload could (such as to `@antiraid/serde`)
+pub fn create_typed(lua: &Lua, event: &str, data: &LuaValue) -> LuaResult<Option<LuaTable>> {
+    create_typed_at(lua, event, data, EVENT_SCHEMA_VERSION)
+}
+
+/// Creates the typed accessor table of an event with the fields of a version
+fn create_typed_at(lua: &Lua, event: &str, data: &LuaValue, version: u32) -> LuaResult<Option<LuaTable>> {
+    let Some(schema) = schema(event, version).map(Rc::new) else {
+        return Ok(None);
+    };
+    let LuaValue::Table(data) = data else {
+        return Ok(None);
+    };
+
+    let typed = lua.create_table()?;
+    data.for_each(|k: LuaValue, v: LuaValue| typed.raw_set(k, v))?;
+
+    // __index and __newindex are only called for fields absent from the copy
+    let index_schema = schema.clone();
+    let metatable = lua.create_table()?;
+    metatable.raw_set("__index", lua.create_function(move |_, (_, key): (LuaValue, LuaValue)| {
+        check_field(&index_schema, &key)?;
+        Ok(LuaValue::Nil)
+    })?)?;
+    metatable.raw_set("__newindex", lua.create_function(move |_, (tab, key, value): (LuaTable, LuaValue, LuaValue)| {
+        check_field(&schema, &key)?;
+        tab.raw_set(key, value)
+    })?)?;
+    metatable.raw_set("__metatable", false)?;
+    metatable.set_readonly(true);
+    typed.set_metatable(Some(metatable))?;
+
+    Ok(Some(typed))
+}
+
+/// Errors if a key is not a field of the payload
+fn check_field(schema: &EventSchema, key: &LuaValue) -> LuaResult<()> {
+    if let LuaValue::String(s) = key && schema.fields.iter().any(|f| s.as_bytes().as_ref() == f.as_bytes()) {
+        return Ok(());
+    }
+    Err(LuaError::external(format!("{} has no field '{}'", schema.name, key.to_string()?)))
+}
+
+/// Converts an event (as passed to templates) to the payload shapes of an earlier version
+///
+/// The event is returned as is if none of the changes since the version affect it
+fn convert(lua: &Lua, event: LuaTable, version: u32) -> LuaResult<LuaTable> {
+    if version == 0 || version > EVENT_SCHEMA_VERSION {
+        return Err(LuaError::external(format!("pragma.eventversion must be between 1 and {EVENT_SCHEMA_VERSION}, got {version}")));
+    }
+
+    let name: String = event.get("name")?;
+    if changes_since(&name, version).next().is_none() {
+        return Ok(event);
+    }
+    let LuaValue::Table(data) = event.get::<LuaValue>("data")? else {
+        return Ok(event);
+    };
+
+    // Events and their payloads are read-only, so both are copied
+    let downgraded = lua.create_table()?;
+    data.for_each(|k: LuaValue, v: LuaValue| downgraded.raw_set(k, v))?;
+    for change in changes_since(&name, version) {
+        for field in change.added {
+            downgraded.raw_set(*field, LuaValue::Nil)?;
+        }
+        for &(old, new) in change.renamed {
+            let value: LuaValue = downgraded.raw_get(new)?;
+            downgraded.raw_set(new, LuaValue::Nil)?;
+            downgraded.raw_set(old, value)?;
+        }
+        if let Some(downgrade) = change.downgrade {
+            downgrade(lua, &downgraded)?;
+        }
+    }
+    downgraded.set_readonly(true);
+    let downgraded = LuaValue::Table(downgraded);
+
+    let converted = lua.create_table()?;
+    event.for_each(|k: LuaValue, v: LuaValue| converted.raw_set(k, v))?;
+    converted.raw_set("typed", create_typed_at(lua, &name, &downgraded, version)?)?;
+    converted.raw_set("data", downgraded)?;
+    converted.set_readonly(true);
+    Ok(converted)
+}
+
+/// Creates the module templates (through their isolate) convert events to the version they pin with
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+    module.set("version", EVENT_SCHEMA_VERSION)?;
+    module.set("convert", lua.create_function(|lua, (event, version): (LuaTable, u32)| convert(lua, event, version))?)?;
+    module.set_readonly(true);
+    Ok(module)
+}
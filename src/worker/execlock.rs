@@ -0,0 +1,74 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use khronos_ext::mlua_scheduler_ext::LuaSchedulerAsyncUserData;
+use khronos_runtime::rt::mlua::prelude::*;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Name of the VM global `@antiraid-ext/isolate` serializes executions through
+pub const EXECLOCK_GLOBAL: &str = "__antiraid_execlock";
+
+/// Locks serializing the executions of the templates of a VM, so events of the same guild don't race on KV
+///
+/// Locks are keyed by the template they serialize (or by `""` for every template of the guild serialized
+/// together, the default) and only exist while held or waited for
+#[derive(Clone, Default)]
+pub struct ExecLocks {
+    locks: Rc<RefCell<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+impl ExecLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes the lock of a key if nobody holds or waits for it
+    fn prune(&self, key: &str) {
+        let mut locks = self.locks.borrow_mut();
+        if locks.get(key).is_some_and(|l| Arc::strong_count(l) == 1) {
+            locks.remove(key);
+        }
+    }
+}
+
+impl LuaUserData for ExecLocks {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_scheduler_async_method("acquire", async |_, this, key: String| {
+            let lock = this.locks.borrow_mut().entry(key.clone()).or_default().clone();
+            let guard = lock.lock_owned().await;
+            Ok(ExecLockGuard { locks: ExecLocks::clone(&this), key, guard: Some(guard) })
+        });
+    }
+}
+
+/// A held execution lock, released by `release` (or once garbage collected)
+pub struct ExecLockGuard {
+    locks: ExecLocks,
+    key: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl ExecLockGuard {
+    fn release(&mut self) {
+        if self.guard.take().is_some() {
+            self.locks.prune(&self.key);
+        }
+    }
+}
+
+impl Drop for ExecLockGuard {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+impl LuaUserData for ExecLockGuard {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method_mut("release", |_, this, ()| {
+            this.release();
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,39 @@
+use dapi::UserId;
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+
+use crate::geese::fixtures::{self, FIXTURE_EVENTS, FixtureContext, FixtureIds};
+use crate::worker::workervmmanager::Id;
+
+/// Name of the VM global the `@antiraid/fixtures` module is exposed as
+pub const FIXTURES_GLOBAL: &str = "__antiraid_fixtures";
+
+/// Creates the `@antiraid/fixtures` module of a VM
+///
+/// Fixtures of guild tenants default to the guild itself, and interactions are addressed to the bot
+pub fn create_module(lua: &Lua, id: Id, bot_id: UserId) -> LuaResult<LuaTable> {
+    let mut base = FixtureContext { application_id: bot_id, ..Default::default() };
+    if let Id::Guild(guild_id) = id {
+        base.guild_id = guild_id;
+    }
+
+    let module = lua.create_table()?;
+
+    module.raw_set("build", lua.create_function(move |lua, (event, overrides, ids): (String, LuaValue, LuaValue)| {
+        let overrides = match overrides {
+            LuaValue::Nil => None,
+            overrides => Some(lua.from_value(overrides)?),
+        };
+        let ids: FixtureIds = match ids {
+            LuaValue::Nil => FixtureIds::default(),
+            ids => lua.from_value(ids)?,
+        };
+        let payload = fixtures::build(&event, &base.with_ids(ids), overrides).map_err(LuaError::external)?;
+        lua.to_value_with(&payload, LUA_SERIALIZE_OPTIONS)
+    })?)?;
+
+    module.raw_set("events", lua.create_function(|_, ()| Ok(FIXTURE_EVENTS.to_vec()))?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
@@ -0,0 +1,149 @@
+use std::collections::HashSet;
+
+use khronos_runtime::rt::mlua::prelude::*;
+
+use crate::worker::limits::{MAX_FUZZY_CANDIDATES, MAX_FUZZY_INPUT_CHARS};
+
+/// Name of the VM global the `@antiraid/fuzzy` module is exposed as
+pub const FUZZY_GLOBAL: &str = "__antiraid_fuzzy";
+
+/// Default n-gram size
+const DEFAULT_NGRAM_SIZE: usize = 3;
+
+#[derive(Clone, Copy)]
+enum Algorithm {
+    Levenshtein,
+    JaroWinkler,
+    Ngram(usize),
+}
+
+impl Algorithm {
+    fn parse(name: Option<&str>, n: Option<usize>) -> LuaResult<Self> {
+        match name.unwrap_or("jarowinkler") {
+            "levenshtein" => Ok(Self::Levenshtein),
+            "jarowinkler" => Ok(Self::JaroWinkler),
+            "ngram" => Ok(Self::Ngram(ngram_size(n)?)),
+            a => Err(LuaError::external(format!("Unknown similarity algorithm: {a}"))),
+        }
+    }
+
+    /// Similarity of two strings in [0, 1] (1 being identical)
+    fn similarity(self, a: &str, b: &str) -> f64 {
+        match self {
+            Self::Levenshtein => strsim::normalized_levenshtein(a, b),
+            Self::JaroWinkler => strsim::jaro_winkler(a, b),
+            Self::Ngram(n) => jaccard(&ngram_hashes(a, n), &ngram_hashes(b, n)),
+        }
+    }
+}
+
+fn ngram_size(n: Option<usize>) -> LuaResult<usize> {
+    match n.unwrap_or(DEFAULT_NGRAM_SIZE) {
+        n @ 1..=8 => Ok(n),
+        _ => Err(LuaError::external("n-gram size must be between 1 and 8")),
+    }
+}
+
+fn input(s: &LuaString) -> LuaResult<String> {
+    let s = s.to_str()?.to_string();
+    if s.chars().count() > MAX_FUZZY_INPUT_CHARS {
+        return Err(LuaError::external(format!("string exceeds the maximum length of {MAX_FUZZY_INPUT_CHARS} characters")));
+    }
+    Ok(s)
+}
+
+/// FNV-1a hash of a n-gram, truncated to 32 bits so it is exactly representable as a Luau number
+fn fnv1a(chars: &[char]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for c in chars {
+        for b in (*c as u32).to_le_bytes() {
+            hash ^= b as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+    }
+    hash
+}
+
+/// Returns the set of hashed character n-grams of a string
+///
+/// Strings shorter than `n` are treated as a single n-gram
+fn ngram_hashes(s: &str, n: usize) -> HashSet<u32> {
+    let chars: Vec<char> = s.chars().collect();
+    if chars.len() <= n {
+        return HashSet::from([fnv1a(&chars)]);
+    }
+    chars.windows(n).map(fnv1a).collect()
+}
+
+fn jaccard(a: &HashSet<u32>, b: &HashSet<u32>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/// Creates the `@antiraid/fuzzy` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.raw_set("levenshtein", lua.create_function(|_, (a, b): (LuaString, LuaString)| {
+        Ok(strsim::levenshtein(&input(&a)?, &input(&b)?))
+    })?)?;
+
+    module.raw_set("similarity", lua.create_function(|_, (a, b, algorithm, n): (LuaString, LuaString, Option<String>, Option<usize>)| {
+        let algorithm = Algorithm::parse(algorithm.as_deref(), n)?;
+        Ok(algorithm.similarity(&input(&a)?, &input(&b)?))
+    })?)?;
+
+    module.raw_set("ngramhash", lua.create_function(|_, (s, n): (LuaString, Option<usize>)| {
+        let mut hashes = ngram_hashes(&input(&s)?, ngram_size(n)?).into_iter().collect::<Vec<_>>();
+        hashes.sort_unstable();
+        Ok(hashes)
+    })?)?;
+
+    module.raw_set("nearest", lua.create_function(|lua, (query, candidates, options): (LuaString, Vec<LuaString>, Option<LuaTable>)| {
+        if candidates.len() > MAX_FUZZY_CANDIDATES {
+            return Err(LuaError::external(format!("Cannot search more than {MAX_FUZZY_CANDIDATES} candidates at once")));
+        }
+
+        let (algorithm, threshold, limit) = match options {
+            Some(opts) => (
+                Algorithm::parse(opts.get::<Option<String>>("algorithm")?.as_deref(), opts.get("n")?)?,
+                opts.get::<Option<f64>>("threshold")?.unwrap_or(0.0),
+                opts.get::<Option<usize>>("limit")?,
+            ),
+            None => (Algorithm::JaroWinkler, 0.0, None),
+        };
+
+        let query = input(&query)?;
+        let mut matches = Vec::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            let score = algorithm.similarity(&query, &input(candidate)?);
+            if score >= threshold {
+                matches.push((i, score));
+            }
+        }
+
+        // Highest score first, ties broken by position in the list
+        matches.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        if let Some(limit) = limit {
+            matches.truncate(limit);
+        }
+
+        let results = lua.create_table_with_capacity(matches.len(), 0)?;
+        for (i, score) in matches {
+            let m = lua.create_table_with_capacity(0, 3)?;
+            m.set("index", i + 1)?;
+            m.set("value", candidates[i].clone())?;
+            m.set("score", score)?;
+            m.set_readonly(true);
+            results.raw_push(m)?;
+        }
+        results.set_readonly(true);
+        Ok(results)
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
@@ -0,0 +1,477 @@
+use std::{collections::{HashMap, HashSet}, io::Cursor, sync::{Arc, LazyLock}, time::{Duration, Instant}};
+
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+use bytes::Bytes;
+use image::{ImageFormat, ImageReader, Limits, Rgba, RgbaImage, imageops::{self, FilterType}};
+use moka::future::Cache;
+use tokio::sync::Semaphore;
+
+use crate::worker::limits::{
+    MAX_IMGGEN_CONCURRENT_RENDERS, MAX_IMGGEN_DIMENSION, MAX_IMGGEN_IMAGES, MAX_IMGGEN_LAYERS,
+    MAX_IMGGEN_RENDER_TIME, MAX_IMGGEN_TEXT_LENGTH,
+};
+
+/// Maximum total size of the fetched image cache
+const IMAGE_CACHE_BYTES: u64 = 1024 * 1024 * 64; // 64MB
+/// How long fetched images (avatars etc.) are cached for
+const IMAGE_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+/// Maximum font size of text layers
+const MAX_FONT_SIZE: f32 = 256.0;
+
+static FONT_REGULAR: LazyLock<FontRef<'static>> = LazyLock::new(|| {
+    FontRef::try_from_slice(include_bytes!("../../assets/fonts/DejaVuSans.ttf")).expect("Failed to load regular font")
+});
+static FONT_BOLD: LazyLock<FontRef<'static>> = LazyLock::new(|| {
+    FontRef::try_from_slice(include_bytes!("../../assets/fonts/DejaVuSans-Bold.ttf")).expect("Failed to load bold font")
+});
+
+/// A RGBA color, parsed from `#rgb`, `#rrggbb` or `#rrggbbaa`
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub Rgba<u8>);
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let hex = s.strip_prefix('#').ok_or_else(|| format!("Color '{s}' must start with #"))?;
+        let expanded = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect::<String>() + "ff",
+            6 => format!("{hex}ff"),
+            8 => hex.to_string(),
+            _ => return Err(format!("Color '{s}' must be #rgb, #rrggbb or #rrggbbaa")),
+        };
+        let v = u32::from_str_radix(&expanded, 16).map_err(|_| format!("Color '{s}' is not valid hex"))?;
+        Ok(Self(Rgba(v.to_be_bytes())))
+    }
+}
+
+impl Color {
+    const WHITE: Self = Self(Rgba([255, 255, 255, 255]));
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontWeight {
+    #[default]
+    Regular,
+    Bold,
+}
+
+impl FontWeight {
+    pub fn font(self) -> &'static FontRef<'static> {
+        match self {
+            Self::Regular => &FONT_REGULAR,
+            Self::Bold => &FONT_BOLD,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Jpeg,
+}
+
+fn default_text_size() -> f32 {
+    16.0
+}
+
+fn default_text_color() -> Color {
+    Color::WHITE
+}
+
+/// A layer of an image, drawn in order on top of the layers before it
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Layer {
+    /// A filled (optionally rounded) rectangle
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: Color,
+        #[serde(default)]
+        radius: f32,
+    },
+    /// An image from the Discord CDN (such as an avatar) scaled to the given size
+    Image {
+        url: String,
+        x: f32,
+        y: f32,
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        circle: bool,
+    },
+    /// A single line of text, `y` is the top of the line
+    Text {
+        text: String,
+        x: f32,
+        y: f32,
+        #[serde(default = "default_text_size")]
+        size: f32,
+        #[serde(default = "default_text_color")]
+        color: Color,
+        #[serde(default)]
+        font: FontWeight,
+        #[serde(default)]
+        align: Align,
+        /// Text wider than this is truncated with an ellipsis
+        maxwidth: Option<f32>,
+    },
+    /// A progress bar filled to `value` (between 0 and 1)
+    Progress {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        value: f32,
+        color: Color,
+        background: Option<Color>,
+        #[serde(default)]
+        radius: f32,
+    },
+}
+
+/// A declarative description of an image to render
+#[derive(Debug, serde::Deserialize)]
+pub struct ImageSpec {
+    pub width: u32,
+    pub height: u32,
+    /// Background color of the canvas (defaults to transparent)
+    pub background: Option<Color>,
+    #[serde(default)]
+    pub layers: Vec<Layer>,
+    #[serde(default)]
+    pub format: OutputFormat,
+}
+
+impl ImageSpec {
+    /// Checks the spec is within the render budget
+    pub fn validate(&self) -> Result<(), crate::Error> {
+        if self.width == 0 || self.height == 0 || self.width > MAX_IMGGEN_DIMENSION || self.height > MAX_IMGGEN_DIMENSION {
+            return Err(format!("Image dimensions must be between 1 and {MAX_IMGGEN_DIMENSION}").into());
+        }
+        if self.layers.len() > MAX_IMGGEN_LAYERS {
+            return Err(format!("Images cannot have more than {MAX_IMGGEN_LAYERS} layers").into());
+        }
+        if self.image_urls().len() > MAX_IMGGEN_IMAGES {
+            return Err(format!("Images cannot use more than {MAX_IMGGEN_IMAGES} distinct source images").into());
+        }
+
+        for layer in &self.layers {
+            match layer {
+                Layer::Image { width, height, .. } => {
+                    if *width == 0 || *height == 0 || *width > MAX_IMGGEN_DIMENSION || *height > MAX_IMGGEN_DIMENSION {
+                        return Err(format!("Image layer dimensions must be between 1 and {MAX_IMGGEN_DIMENSION}").into());
+                    }
+                }
+                Layer::Text { text, size, .. } => {
+                    if text.chars().count() > MAX_IMGGEN_TEXT_LENGTH {
+                        return Err(format!("Text layers cannot be longer than {MAX_IMGGEN_TEXT_LENGTH} characters").into());
+                    }
+                    if !(*size > 0.0 && *size <= MAX_FONT_SIZE) {
+                        return Err(format!("Font size must be between 0 and {MAX_FONT_SIZE}").into());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the distinct urls of the image layers
+    pub fn image_urls(&self) -> HashSet<&str> {
+        self.layers.iter().filter_map(|l| match l {
+            Layer::Image { url, .. } => Some(url.as_str()),
+            _ => None,
+        }).collect()
+    }
+}
+
+/// Renders images from specs off the VM thread
+///
+/// Fetched source images are cached for a while as the same avatars tend to be rendered repeatedly (such as
+/// rank cards) and renders are limited to a fixed number running at once per worker
+pub struct ImgGen {
+    images: Cache<String, Bytes>,
+    renders: Arc<Semaphore>,
+}
+
+impl Default for ImgGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImgGen {
+    pub fn new() -> Self {
+        Self {
+            images: Cache::builder()
+                .max_capacity(IMAGE_CACHE_BYTES)
+                .weigher(|_, v: &Bytes| v.len().try_into().unwrap_or(u32::MAX))
+                .time_to_live(IMAGE_CACHE_TTL)
+                .build(),
+            renders: Arc::new(Semaphore::new(MAX_IMGGEN_CONCURRENT_RENDERS)),
+        }
+    }
+
+    /// Returns the cached image at `url`, fetching it with `fetch` if not cached
+    ///
+    /// Concurrent fetches of the same url are coalesced into one
+    pub async fn image(&self, url: &str, fetch: impl Future<Output = Result<Bytes, crate::Error>>) -> Result<Bytes, crate::Error> {
+        self.images.try_get_with_by_ref(url, fetch).await.map_err(|e| e.to_string().into())
+    }
+
+    /// Renders a (validated) spec using the fetched source `images`, returning the encoded image
+    pub async fn render(&self, spec: ImageSpec, images: HashMap<String, Bytes>) -> Result<Bytes, crate::Error> {
+        self.run(move |deadline| render(spec, &images, deadline)).await
+    }
+
+    /// Runs a render job on the blocking pool within the render budget
+    ///
+    /// `job` is given the deadline it must finish by and should check it periodically
+    pub async fn run<T: Send + 'static>(&self, job: impl FnOnce(Instant) -> Result<T, crate::Error> + Send + 'static) -> Result<T, crate::Error> {
+        let permit = self.renders.clone().acquire_owned().await?;
+        let deadline = Instant::now() + MAX_IMGGEN_RENDER_TIME;
+        let task = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            job(deadline)
+        });
+
+        // The job itself also checks the deadline so the blocking thread is freed soon after timing out
+        match tokio::time::timeout(MAX_IMGGEN_RENDER_TIME, task).await {
+            Ok(res) => res?,
+            Err(_) => Err("Image render exceeded its time budget".into()),
+        }
+    }
+}
+
+/// Returns an error if the render deadline has passed
+pub fn check_deadline(deadline: Instant) -> Result<(), crate::Error> {
+    if Instant::now() > deadline {
+        return Err("Image render exceeded its time budget".into());
+    }
+    Ok(())
+}
+
+/// Encodes a canvas in the given format
+pub fn encode(canvas: RgbaImage, format: OutputFormat) -> Result<Bytes, crate::Error> {
+    let mut out = Vec::new();
+    match format {
+        OutputFormat::Png => canvas.write_to(&mut Cursor::new(&mut out), ImageFormat::Png)?,
+        // Jpeg has no alpha channel
+        OutputFormat::Jpeg => image::DynamicImage::ImageRgba8(canvas).to_rgb8().write_to(&mut Cursor::new(&mut out), ImageFormat::Jpeg)?,
+    }
+    Ok(out.into())
+}
+
+fn render(spec: ImageSpec, images: &HashMap<String, Bytes>, deadline: Instant) -> Result<Bytes, crate::Error> {
+    let background = spec.background.map(|c| c.0).unwrap_or(Rgba([0, 0, 0, 0]));
+    let mut canvas = RgbaImage::from_pixel(spec.width, spec.height, background);
+
+    for layer in spec.layers {
+        check_deadline(deadline)?;
+
+        match layer {
+            Layer::Rect { x, y, width, height, color, radius } => {
+                fill_rounded_rect(&mut canvas, x, y, width, height, radius, color.0);
+            }
+            Layer::Image { url, x, y, width, height, circle } => {
+                let data = images.get(&url).ok_or_else(|| format!("Image {url} was not fetched"))?;
+                draw_image(&mut canvas, data, x, y, width, height, circle)?;
+            }
+            Layer::Text { text, x, y, size, color, font, align, maxwidth } => {
+                draw_text(&mut canvas, font.font(), &text, x, y, size, color.0, align, maxwidth);
+            }
+            Layer::Progress { x, y, width, height, value, color, background, radius } => {
+                if let Some(background) = background {
+                    fill_rounded_rect(&mut canvas, x, y, width, height, radius, background.0);
+                }
+                let filled = width * value.clamp(0.0, 1.0);
+                if filled > 0.0 {
+                    fill_rounded_rect(&mut canvas, x, y, filled, height, radius.min(filled / 2.0), color.0);
+                }
+            }
+        }
+    }
+
+    encode(canvas, spec.format)
+}
+
+/// Composites `color` with the given coverage over the pixel at (x, y) (source-over)
+pub fn blend(canvas: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>, coverage: f32) {
+    if x < 0 || y < 0 || x >= canvas.width() as i64 || y >= canvas.height() as i64 {
+        return;
+    }
+
+    let src_a = color.0[3] as f32 / 255.0 * coverage.clamp(0.0, 1.0);
+    if src_a <= 0.0 {
+        return;
+    }
+
+    let dst = canvas.get_pixel_mut(x as u32, y as u32);
+    let dst_a = dst.0[3] as f32 / 255.0;
+    let out_a = src_a + dst_a * (1.0 - src_a);
+    for i in 0..3 {
+        let c = (color.0[i] as f32 * src_a + dst.0[i] as f32 * dst_a * (1.0 - src_a)) / out_a;
+        dst.0[i] = c.round().clamp(0.0, 255.0) as u8;
+    }
+    dst.0[3] = (out_a * 255.0).round() as u8;
+}
+
+/// Returns the (anti-aliased) coverage of the pixel at (px, py) by a rounded rectangle
+fn rounded_rect_coverage(px: i64, py: i64, x: f32, y: f32, width: f32, height: f32, radius: f32) -> f32 {
+    let (hw, hh) = (width / 2.0, height / 2.0);
+    let r = radius.clamp(0.0, hw.min(hh));
+    let qx = (px as f32 + 0.5 - (x + hw)).abs() - (hw - r);
+    let qy = (py as f32 + 0.5 - (y + hh)).abs() - (hh - r);
+    let outside = qx.max(0.0).hypot(qy.max(0.0));
+    let inside = qx.max(qy).min(0.0);
+    (0.5 - (outside + inside - r)).clamp(0.0, 1.0)
+}
+
+/// Returns the pixel range covered by [start, start + len) clamped to [0, max)
+fn pixel_range(start: f32, len: f32, max: u32) -> std::ops::Range<i64> {
+    let from = start.floor().max(0.0) as i64;
+    let to = ((start + len).ceil() as i64).min(max as i64);
+    from..to.max(from)
+}
+
+pub fn fill_rounded_rect(canvas: &mut RgbaImage, x: f32, y: f32, width: f32, height: f32, radius: f32, color: Rgba<u8>) {
+    if width <= 0.0 || height <= 0.0 {
+        return;
+    }
+
+    for py in pixel_range(y, height, canvas.height()) {
+        for px in pixel_range(x, width, canvas.width()) {
+            let coverage = rounded_rect_coverage(px, py, x, y, width, height, radius);
+            blend(canvas, px, py, color, coverage);
+        }
+    }
+}
+
+/// Draws an anti-aliased line of the given thickness from (x0, y0) to (x1, y1)
+pub fn draw_line(canvas: &mut RgbaImage, x0: f32, y0: f32, x1: f32, y1: f32, thickness: f32, color: Rgba<u8>) {
+    let half = thickness / 2.0;
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let len_sq = dx * dx + dy * dy;
+    let (min_x, max_x) = (x0.min(x1) - half - 1.0, x0.max(x1) + half + 1.0);
+    let (min_y, max_y) = (y0.min(y1) - half - 1.0, y0.max(y1) + half + 1.0);
+
+    for py in pixel_range(min_y, max_y - min_y, canvas.height()) {
+        for px in pixel_range(min_x, max_x - min_x, canvas.width()) {
+            let (cx, cy) = (px as f32 + 0.5, py as f32 + 0.5);
+            // Distance from the pixel center to the closest point on the segment
+            let t = if len_sq > 0.0 { (((cx - x0) * dx + (cy - y0) * dy) / len_sq).clamp(0.0, 1.0) } else { 0.0 };
+            let d = (cx - (x0 + t * dx)).hypot(cy - (y0 + t * dy));
+            blend(canvas, px, py, color, half + 0.5 - d);
+        }
+    }
+}
+
+fn draw_image(canvas: &mut RgbaImage, data: &[u8], x: f32, y: f32, width: u32, height: u32, circle: bool) -> Result<(), crate::Error> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMGGEN_DIMENSION * 2);
+    limits.max_image_height = Some(MAX_IMGGEN_DIMENSION * 2);
+    limits.max_alloc = Some(1024 * 1024 * 128);
+
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    reader.limits(limits);
+    let source = reader.decode()?.to_rgba8();
+    let scaled = imageops::resize(&source, width, height, FilterType::Triangle);
+
+    let (ox, oy) = (x.round() as i64, y.round() as i64);
+    for (sx, sy, pixel) in scaled.enumerate_pixels() {
+        let coverage = if circle {
+            rounded_rect_coverage(sx as i64, sy as i64, 0.0, 0.0, width as f32, height as f32, f32::MAX)
+        } else {
+            1.0
+        };
+        blend(canvas, ox + sx as i64, oy + sy as i64, *pixel, coverage);
+    }
+    Ok(())
+}
+
+/// Returns the width of `text` when laid out in `font` at `size`
+pub fn text_width(font: &FontRef<'static>, text: &str, size: f32) -> f32 {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut prev = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(prev) = prev {
+            width += scaled.kern(prev, id);
+        }
+        width += scaled.h_advance(id);
+        prev = Some(id);
+    }
+    width
+}
+
+/// Truncates `text` with an ellipsis so it fits within `maxwidth`
+pub fn fit_text(font: &FontRef<'static>, text: &str, size: f32, maxwidth: f32) -> String {
+    if text_width(font, text, size) <= maxwidth {
+        return text.to_string();
+    }
+
+    let mut chars: Vec<char> = text.chars().collect();
+    while !chars.is_empty() {
+        chars.pop();
+        let candidate = format!("{}…", chars.iter().collect::<String>().trim_end());
+        if text_width(font, &candidate, size) <= maxwidth {
+            return candidate;
+        }
+    }
+    String::new()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn draw_text(canvas: &mut RgbaImage, font: &FontRef<'static>, text: &str, x: f32, y: f32, size: f32, color: Rgba<u8>, align: Align, maxwidth: Option<f32>) {
+    let text = match maxwidth {
+        Some(maxwidth) => fit_text(font, text, size, maxwidth),
+        None => text.to_string(),
+    };
+
+    let width = text_width(font, &text, size);
+    let mut pen_x = match align {
+        Align::Left => x,
+        Align::Center => x - width / 2.0,
+        Align::Right => x - width,
+    };
+
+    let scale = PxScale::from(size);
+    let scaled = font.as_scaled(scale);
+    let baseline = y + scaled.ascent();
+    let mut prev = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(prev) = prev {
+            pen_x += scaled.kern(prev, id);
+        }
+
+        let glyph = id.with_scale_and_position(scale, point(pen_x, baseline));
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                blend(canvas, bounds.min.x as i64 + gx as i64, bounds.min.y as i64 + gy as i64, color, coverage);
+            });
+        }
+
+        pen_x += scaled.h_advance(id);
+        prev = Some(id);
+    }
+}
@@ -0,0 +1,164 @@
+use khronos_runtime::rt::mlua::prelude::*;
+
+/// Name of the VM global the `@antiraid/int64` module is exposed as
+pub const INT64_GLOBAL: &str = "__antiraid_int64";
+
+/// Largest integer which can be represented exactly by a Luau number
+const MAX_SAFE_INTEGER: f64 = ((1u64 << 53) - 1) as f64;
+
+/// Signed 64 bit integer exposed to Luau
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(transparent)]
+pub struct I64(pub i64);
+
+/// Unsigned 64 bit integer exposed to Luau
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(transparent)]
+pub struct U64(pub u64);
+
+/// Adds a metamethod erroring on overflow (or division by zero) and a method returning nil instead
+macro_rules! checked_op {
+    ($methods:ident, $ty:ident, $name:literal, $meta:expr, $method:literal, $op:ident, $desc:literal) => {
+        $methods.add_meta_function($meta, |lua, (a, b): (LuaValue, LuaValue)| {
+            let (a, b) = ($ty::from_value(&a)?, $ty::from_value(&b)?);
+            let v = a.$op(b).ok_or_else(|| LuaError::external(concat!($name, " ", $desc)))?;
+            $ty::create(lua, v)
+        });
+        $methods.add_method($method, |lua, this, other: LuaValue| {
+            match this.0.$op($ty::from_value(&other)?) {
+                Some(v) => Ok(Some($ty::create(lua, v)?)),
+                None => Ok(None),
+            }
+        });
+    };
+}
+
+macro_rules! int_userdata {
+    ($ty:ident, $inner:ty, $name:literal) => {
+        impl $ty {
+            /// Converts a Luau value (the userdata itself, an exact integer number or a string) to the integer type
+            fn from_value(value: &LuaValue) -> LuaResult<$inner> {
+                match value {
+                    LuaValue::UserData(ud) => {
+                        if let Ok(v) = ud.borrow::<$ty>() {
+                            return Ok(v.0);
+                        }
+                        Err(LuaError::external(concat!("cannot mix ", $name, " with other integer types, convert explicitly first")))
+                    }
+                    LuaValue::Integer(i) => <$inner>::try_from(*i)
+                        .map_err(|_| LuaError::external(format!("{i} is out of range for {}", $name))),
+                    LuaValue::Number(n) => {
+                        if n.fract() != 0.0 || n.abs() > MAX_SAFE_INTEGER {
+                            return Err(LuaError::external(format!("{n} is not a safe integer, pass large integers as strings")));
+                        }
+                        <$inner>::try_from(*n as i64)
+                            .map_err(|_| LuaError::external(format!("{n} is out of range for {}", $name)))
+                    }
+                    LuaValue::String(s) => s.to_str()?.trim().parse::<$inner>()
+                        .map_err(|e| LuaError::external(format!("invalid {}: {e}", $name))),
+                    v => Err(LuaError::external(format!("expected {}, number or string, got {}", $name, v.type_name()))),
+                }
+            }
+
+            fn create(lua: &Lua, value: $inner) -> LuaResult<LuaAnyUserData> {
+                lua.create_ser_userdata($ty(value))
+            }
+        }
+
+        impl LuaUserData for $ty {
+            fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+                checked_op!(methods, $ty, $name, LuaMetaMethod::Add, "checkedadd", checked_add, "addition overflowed");
+                checked_op!(methods, $ty, $name, LuaMetaMethod::Sub, "checkedsub", checked_sub, "subtraction overflowed");
+                checked_op!(methods, $ty, $name, LuaMetaMethod::Mul, "checkedmul", checked_mul, "multiplication overflowed");
+                checked_op!(methods, $ty, $name, LuaMetaMethod::IDiv, "checkeddiv", checked_div, "division overflowed or divided by zero");
+                checked_op!(methods, $ty, $name, LuaMetaMethod::Mod, "checkedrem", checked_rem, "remainder overflowed or divided by zero");
+
+                // Integer division, as there is no exact result for `/`
+                methods.add_meta_function(LuaMetaMethod::Div, |lua, (a, b): (LuaValue, LuaValue)| {
+                    let (a, b) = ($ty::from_value(&a)?, $ty::from_value(&b)?);
+                    let v = a.checked_div(b).ok_or_else(|| LuaError::external(concat!($name, " division overflowed or divided by zero")))?;
+                    $ty::create(lua, v)
+                });
+
+                methods.add_meta_function(LuaMetaMethod::Eq, |_, (a, b): (LuaValue, LuaValue)| {
+                    Ok($ty::from_value(&a)? == $ty::from_value(&b)?)
+                });
+                methods.add_meta_function(LuaMetaMethod::Lt, |_, (a, b): (LuaValue, LuaValue)| {
+                    Ok($ty::from_value(&a)? < $ty::from_value(&b)?)
+                });
+                methods.add_meta_function(LuaMetaMethod::Le, |_, (a, b): (LuaValue, LuaValue)| {
+                    Ok($ty::from_value(&a)? <= $ty::from_value(&b)?)
+                });
+                methods.add_meta_method(LuaMetaMethod::ToString, |_, this, _: ()| Ok(this.0.to_string()));
+
+                methods.add_method("band", |lua, this, other: LuaValue| $ty::create(lua, this.0 & $ty::from_value(&other)?));
+                methods.add_method("bor", |lua, this, other: LuaValue| $ty::create(lua, this.0 | $ty::from_value(&other)?));
+                methods.add_method("bxor", |lua, this, other: LuaValue| $ty::create(lua, this.0 ^ $ty::from_value(&other)?));
+                methods.add_method("bnot", |lua, this, _: ()| $ty::create(lua, !this.0));
+                methods.add_method("shl", |lua, this, bits: u32| {
+                    let v = this.0.checked_shl(bits).ok_or_else(|| LuaError::external("shift amount must be less than 64"))?;
+                    $ty::create(lua, v)
+                });
+                methods.add_method("shr", |lua, this, bits: u32| {
+                    let v = this.0.checked_shr(bits).ok_or_else(|| LuaError::external("shift amount must be less than 64"))?;
+                    $ty::create(lua, v)
+                });
+
+                methods.add_method("tostring", |_, this, _: ()| Ok(this.0.to_string()));
+                // Errors instead of silently losing precision
+                methods.add_method("tonumber", |_, this, _: ()| {
+                    let n = this.0 as f64;
+                    if n.abs() > MAX_SAFE_INTEGER {
+                        return Err(LuaError::external(format!("{} cannot be represented exactly as a number", this.0)));
+                    }
+                    Ok(n)
+                });
+                methods.add_method("issafe", |_, this, _: ()| Ok((this.0 as f64).abs() <= MAX_SAFE_INTEGER));
+            }
+        }
+    };
+}
+
+int_userdata!(I64, i64, "I64");
+int_userdata!(U64, u64, "U64");
+
+/// Creates the `@antiraid/int64` module of a VM
+///
+/// The userdata are created as serializable userdata so they serialize as exact integers (such as when converted to
+/// JSON or stored in key-value API)
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.raw_set("i64", lua.create_function(|lua, value: LuaValue| {
+        let v = match &value {
+            LuaValue::UserData(ud) if ud.is::<U64>() => {
+                let v = ud.borrow::<U64>()?.0;
+                i64::try_from(v).map_err(|_| LuaError::external(format!("{v} is out of range for I64")))?
+            }
+            _ => I64::from_value(&value)?,
+        };
+        I64::create(lua, v)
+    })?)?;
+
+    module.raw_set("u64", lua.create_function(|lua, value: LuaValue| {
+        let v = match &value {
+            LuaValue::UserData(ud) if ud.is::<I64>() => {
+                let v = ud.borrow::<I64>()?.0;
+                u64::try_from(v).map_err(|_| LuaError::external(format!("{v} is out of range for U64")))?
+            }
+            _ => U64::from_value(&value)?,
+        };
+        U64::create(lua, v)
+    })?)?;
+
+    module.raw_set("isi64", lua.create_function(|_, value: LuaValue| {
+        Ok(matches!(value, LuaValue::UserData(ud) if ud.is::<I64>()))
+    })?)?;
+
+    module.raw_set("isu64", lua.create_function(|_, value: LuaValue| {
+        Ok(matches!(value, LuaValue::UserData(ud) if ud.is::<U64>()))
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
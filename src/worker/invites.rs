@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use chrono::Utc;
+use dapi::{GuildId, UserId};
+use serde_json::Value;
+
+use crate::CONFIG;
+use crate::geese::invites::{InviteAttribution, InviteReq, JoinSource};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Field of the GUILD_MEMBER_ADD payload the attribution of the join is exposed as
+pub const INVITE_FIELD: &str = "invite";
+
+/// How long the dispatch of a join may be held up for attributing it
+const ATTRIBUTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+struct TrackedInvite {
+    uses: u64,
+    /// 0 if unlimited
+    max_uses: u64,
+    inviter_id: Option<UserId>,
+}
+
+/// Invite uses of a guild as of its last join
+#[derive(Clone, Default)]
+struct Snapshot {
+    invites: HashMap<String, TrackedInvite>,
+    vanity: Option<(String, u64)>,
+}
+
+/// Attributes members joining a guild to the invite (or vanity URL) they used
+///
+/// Discord does not say which invite a member used, so the uses of the guild's invites are snapshotted on every
+/// join and diffed with the previous snapshot. Joins of a guild are attributed one at a time, a join is only
+/// attributed if exactly one invite was used since the previous join. Needs the Manage Server permission
+#[derive(Clone, Default)]
+pub struct InviteTracker {
+    guilds: Rc<RefCell<HashMap<GuildId, Rc<tokio::sync::Mutex<Option<Snapshot>>>>>>,
+}
+
+impl InviteTracker {
+    /// Attributes a join given its GUILD_MEMBER_ADD payload, if invite tracking (or alt detection, which uses the
+    /// invite as a signal) is enabled for the guild
+    ///
+    /// With invite tracking, the attribution is added to the payload and recorded by the master
+    pub async fn track(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &mut Value) -> Option<InviteAttribution> {
+        let id = Id::Guild(guild_id);
+        let tracking = dispatch.tenant_state.invite_tracking(id);
+        if !tracking && dispatch.tenant_state.alt_sensitivity(id).is_none() {
+            return None;
+        }
+        let user_id = payload.get("user").and_then(|u| u.get("id")).and_then(|v| v.as_str()).and_then(|v| v.parse::<UserId>().ok())?;
+
+        let attribution = match tokio::time::timeout(ATTRIBUTION_TIMEOUT, self.attribute(dispatch, guild_id, user_id)).await {
+            Ok(attribution) => attribution,
+            Err(_) => {
+                log::debug!("Timed out attributing join of {user_id} in guild {guild_id}");
+                InviteAttribution { guild_id, user_id, source: JoinSource::Unknown, code: None, inviter_id: None, joined_at: Utc::now() }
+            }
+        };
+
+        if tracking {
+            if let Some(obj) = payload.as_object_mut() && let Ok(value) = serde_json::to_value(&attribution) {
+                obj.insert(INVITE_FIELD.to_string(), value);
+            }
+
+            let (mesophyll_client, attribution) = (dispatch.worker_state.mesophyll_client.clone(), attribution.clone());
+            tokio::task::spawn_local(async move {
+                if let Err(e) = mesophyll_client.invites(&InviteReq::RecordJoin { attribution }).await {
+                    log::error!("Failed to record invite of {user_id} in guild {guild_id}: {e}");
+                }
+            });
+        }
+
+        Some(attribution)
+    }
+
+    async fn attribute(&self, dispatch: &WorkerDispatch, guild_id: GuildId, user_id: UserId) -> InviteAttribution {
+        let joined_at = Utc::now();
+        let guild = self.guilds.borrow_mut().entry(guild_id).or_default().clone();
+        let mut previous = guild.lock().await;
+
+        let current = match Self::snapshot(dispatch, guild_id).await {
+            Ok(current) => current,
+            Err(e) => {
+                log::debug!("Failed to snapshot invites of guild {guild_id}: {e}");
+                return InviteAttribution { guild_id, user_id, source: JoinSource::Unknown, code: None, inviter_id: None, joined_at };
+            }
+        };
+        let (source, code, inviter_id) = match previous.as_ref() {
+            Some(previous) => diff(previous, &current).unwrap_or((JoinSource::Unknown, None, None)),
+            None => (JoinSource::Unknown, None, None),
+        };
+        *previous = Some(current);
+
+        InviteAttribution { guild_id, user_id, source, code, inviter_id, joined_at }
+    }
+
+    async fn snapshot(dispatch: &WorkerDispatch, guild_id: GuildId) -> Result<Snapshot, crate::Error> {
+        let invites: Vec<Value> = dispatch.worker_state.reqwest.get(format!("{}/api/v10/guilds/{guild_id}/invites", CONFIG.proxy))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let invites = invites.iter()
+            .filter_map(|i| {
+                let invite = TrackedInvite {
+                    uses: i.get("uses")?.as_u64()?,
+                    max_uses: i.get("max_uses").and_then(|v| v.as_u64()).unwrap_or(0),
+                    inviter_id: i.get("inviter").and_then(|u| u.get("id")).and_then(|v| v.as_str()).and_then(|v| v.parse().ok()),
+                };
+                Some((i.get("code")?.as_str()?.to_string(), invite))
+            })
+            .collect();
+
+        // Guilds without a vanity URL error or have no code
+        let vanity = match dispatch.worker_state.reqwest.get(format!("{}/api/v10/guilds/{guild_id}/vanity-url", CONFIG.proxy))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+            .send()
+            .await?
+            .error_for_status()
+        {
+            Ok(res) => {
+                let vanity: Value = res.json().await?;
+                vanity.get("code").and_then(|v| v.as_str()).map(|c| (c.to_string(), vanity.get("uses").and_then(|v| v.as_u64()).unwrap_or(0)))
+            }
+            Err(_) => None,
+        };
+
+        Ok(Snapshot { invites, vanity })
+    }
+}
+
+/// Returns the invite used between two snapshots, if exactly one was
+fn diff(previous: &Snapshot, current: &Snapshot) -> Option<(JoinSource, Option<String>, Option<UserId>)> {
+    let mut used = Vec::new();
+    for (code, invite) in current.invites.iter() {
+        if invite.uses > previous.invites.get(code).map(|i| i.uses).unwrap_or(0) {
+            used.push((JoinSource::Invite, Some(code.clone()), invite.inviter_id));
+        }
+    }
+    // Invites reaching their max uses are deleted by Discord
+    for (code, invite) in previous.invites.iter() {
+        if !current.invites.contains_key(code) && invite.max_uses > 0 && invite.uses + 1 == invite.max_uses {
+            used.push((JoinSource::Invite, Some(code.clone()), invite.inviter_id));
+        }
+    }
+    if let Some((code, uses)) = &current.vanity && previous.vanity.as_ref().is_some_and(|(_, prev)| uses > prev) {
+        used.push((JoinSource::Vanity, Some(code.clone()), None));
+    }
+
+    if used.len() != 1 {
+        return None;
+    }
+    used.pop()
+}
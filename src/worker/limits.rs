@@ -8,12 +8,54 @@ pub const MAX_ATTACHMENT_SIZE: usize = 1024 * 1024 * 5; // 5MB maximum memory
 pub const MAX_VM_THREAD_STACK_SIZE: usize = 1024 * 1024 * 25; // 25MB maximum memory
 pub const MAX_TEMPLATES_EXECUTION_TIME: Duration = Duration::from_secs(10); // 10 seconds maximum execution time before sched yield must happen
 pub const TEMPLATE_GIVE_TIME: Duration = Duration::from_secs(1); // 1 second maximum time to give to a template to finish execution following a yield
+pub const MAX_TEMPLATES_RETURN_WAIT: Duration = Duration::from_secs(60); // 60 seconds maximum time to wait for a dispatched event to return
+pub const MAX_CONCURRENT_DISPATCHES: usize = 64; // max dispatches running at once per worker thread
+
+// Defaults for user tenants (user-installed apps and DMs), which are not tied to a guild and so get smaller defaults
+pub const MAX_USER_TEMPLATE_MEMORY_USAGE: usize = 1024 * 1024 * 10; // 10MB
+pub const MAX_USER_TEMPLATES_EXECUTION_TIME: Duration = Duration::from_secs(5);
+pub const MAX_USER_TEMPLATES_RETURN_WAIT: Duration = Duration::from_secs(30);
+
+// Upper bounds for per-tenant limit overrides (e.g. premium tiers)
+pub const MAX_TENANT_MEMORY_USAGE: usize = 1024 * 1024 * 256; // 256MB
+pub const MAX_TENANT_EXECUTION_TIME: Duration = Duration::from_secs(60);
+pub const MAX_TENANT_RETURN_WAIT: Duration = Duration::from_secs(300);
 
 pub const MAX_OBJ_STORAGE_PATH_LENGTH: usize = 2048;
 pub const MAX_OBJ_STORAGE_BYTES: usize = 512 * 1024; // 512kb max per object
 
 pub const KV_MAX_KEY_LENGTH: usize = 512;
 pub const KV_SIGN_URL_EXPIRATION_SECONDS: u64 = 5 * 60; // 5 minutes
+pub const MAX_ONCE_KEYS: usize = 10000; // max unexpired ctx:once keys per tenant
+pub const MAX_ONCE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60); // 7 days maximum ctx:once key expiry
+pub const MAX_TEMPLATE_STATEMENT_TIME: Duration = Duration::from_secs(10); // 10 seconds maximum per SQL statement of a template state op
+
+pub const MAX_RANDOM_BYTES: usize = 1024 * 1024; // 1MB max random bytes per call
+pub const MAX_CRYPTO_INPUT_BYTES: usize = 1024 * 1024 * 4; // 4MB max input per crypto call
+pub const MAX_STRING_UTILS_INPUT_BYTES: usize = 1024 * 64; // 64kb max input per string utils call
+pub const MAX_FUZZY_INPUT_CHARS: usize = 512; // max length of strings compared by the fuzzy plugin
+pub const MAX_FUZZY_CANDIDATES: usize = 10000; // max candidates per fuzzy nearest-match search
+pub const MAX_TABLEUTILS_ELEMENTS: usize = 100000; // max elements per table passed to a tableutils call
+pub const MAX_MARKDOWN_INPUT_BYTES: usize = 1024 * 16; // 16kb max content per markdown call
+pub const MAX_SERDE_INPUT_BYTES: usize = 1024 * 1024; // 1MB max input/output per toml/yaml/canonical json call
+pub const MAX_JSON_STREAM_INPUT_BYTES: usize = 1024 * 1024 * 16; // 16MB max document per streaming json parse
+pub const MAX_SCRATCH_BYTES: usize = 1024 * 1024 * 64; // 64MB max scratch space per VM
+pub const MAX_SCRATCH_FILES: usize = 16; // max scratch files open at once per VM
+pub const MAX_SCRATCH_READ_BYTES: usize = 1024 * 1024; // 1MB max read per scratch file read call
+pub const MAX_WASM_MODULE_BYTES: usize = MAX_OBJ_STORAGE_BYTES; // WASM modules are stored as KV blobs
+pub const MAX_WASM_MODULES: usize = 16; // max compiled WASM modules cached per VM
+pub const MAX_WASM_IO_BYTES: usize = 1024 * 1024 * 4; // 4MB max input/output per WASM invoke call
+pub const MAX_WASM_COMPILE_TIME: Duration = Duration::from_secs(5); // 5 seconds maximum to compile a WASM module
+pub const SCRATCH_SPILL_BYTES: usize = 1024 * 1024; // scratch files larger than 1MB are spilled to disk
+
+pub const MAX_IMGGEN_DIMENSION: u32 = 2048; // max width/height of generated images
+pub const MAX_IMGGEN_LAYERS: usize = 64; // max layers per generated image
+pub const MAX_IMGGEN_IMAGES: usize = 8; // max distinct source images (avatars etc.) per generated image
+pub const MAX_IMGGEN_TEXT_LENGTH: usize = 256; // max characters per text layer
+pub const MAX_IMGGEN_RENDER_TIME: Duration = Duration::from_secs(5); // 5 seconds maximum render time
+pub const MAX_IMGGEN_CONCURRENT_RENDERS: usize = 4; // max renders running at once per worker
+pub const MAX_CHART_DATASETS: usize = 8; // max datasets per chart
+pub const MAX_CHART_POINTS: usize = 500; // max labels (points per dataset) per chart
 
 pub type LuaRatelimits = Ratelimiter<()>;
 impl Ratelimits {
@@ -78,6 +120,34 @@ impl Ratelimits {
         let create_guild_command_lim1 =
             LuaRatelimits::limit(1, Duration::from_secs(300));
 
+        // create/edit/delete_stage_instance
+        let create_stage_instance_lim1 =
+            LuaRatelimits::limit(5, Duration::from_secs(60));
+        let edit_stage_instance_lim1 =
+            LuaRatelimits::limit(10, Duration::from_secs(60));
+        let delete_stage_instance_lim1 =
+            LuaRatelimits::limit(5, Duration::from_secs(60));
+
+        // edit_guild
+        let edit_guild_lim1 =
+            LuaRatelimits::limit(5, Duration::from_secs(60));
+
+        // get_prune_count/begin_prune
+        let get_prune_count_lim1 =
+            LuaRatelimits::limit(5, Duration::from_secs(60));
+        let begin_prune_lim1 =
+            LuaRatelimits::limit(1, Duration::from_secs(300));
+
+        // edit_welcome_screen/edit_onboarding
+        let edit_welcome_screen_lim1 =
+            LuaRatelimits::limit(5, Duration::from_secs(60));
+        let edit_onboarding_lim1 =
+            LuaRatelimits::limit(5, Duration::from_secs(60));
+
+        // parse_moderation_args (searches members when targeting by name)
+        let parse_moderation_args_lim1 =
+            LuaRatelimits::limit(10, Duration::from_secs(10));
+
         // Create the clock
         let clock = QuantaClock::default();
 
@@ -96,6 +166,15 @@ impl Ratelimits {
                 "DeleteFollowupMessage" => vec![delete_followup_message_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
                 "GetGuildCommands" => vec![get_guild_commands_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
                 "CreateGuildCommand" => vec![create_guild_command_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "CreateStageInstance" => vec![create_stage_instance_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "EditStageInstance" => vec![edit_stage_instance_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "DeleteStageInstance" => vec![delete_stage_instance_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "EditGuild" => vec![edit_guild_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "GetPruneCount" => vec![get_prune_count_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "BeginPrune" => vec![begin_prune_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "EditWelcomeScreen" => vec![edit_welcome_screen_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "EditOnboarding" => vec![edit_onboarding_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
+                "ParseModerationArgs" => vec![parse_moderation_args_lim1] as Vec<DefaultKeyedRateLimiter<()>>,
             ),
             clock,
         }
@@ -133,6 +212,42 @@ impl Ratelimits {
         }
     }
 
+    fn new_webhook_rl() -> LuaRatelimits {
+        // Create the global limit
+        let global1 =
+            LuaRatelimits::limit(5, Duration::from_secs(1));
+        let global2 =
+            LuaRatelimits::limit(60, Duration::from_secs(60));
+        let global = vec![global1, global2];
+
+        // Create the clock
+        let clock = QuantaClock::default();
+
+        LuaRatelimits {
+            global,
+            per_bucket: indexmap::indexmap!(),
+            clock,
+        }
+    }
+
+    fn new_imggen_rl() -> LuaRatelimits {
+        // Create the global limit
+        let global1 =
+            LuaRatelimits::limit(2, Duration::from_secs(1));
+        let global2 =
+            LuaRatelimits::limit(30, Duration::from_secs(60));
+        let global = vec![global1, global2];
+
+        // Create the clock
+        let clock = QuantaClock::default();
+
+        LuaRatelimits {
+            global,
+            per_bucket: indexmap::indexmap!(),
+            clock,
+        }
+    }
+
     fn new_cdn_rl() -> LuaRatelimits {
         // Create the global limit
         let global1 =
@@ -163,6 +278,12 @@ pub struct Ratelimits {
 
     /// Stores the runtime ratelimiters
     pub cdn: LuaRatelimits,
+
+    /// Stores the outbound webhook ratelimiters
+    pub webhook: LuaRatelimits,
+
+    /// Stores the image generation ratelimiters
+    pub imggen: LuaRatelimits,
 }
 
 impl Ratelimits {
@@ -172,6 +293,8 @@ impl Ratelimits {
             object_storage: Ratelimits::new_object_storage_rl(),
             runtime: Ratelimits::new_runtime_rl(),
             cdn: Ratelimits::new_cdn_rl(),
+            webhook: Ratelimits::new_webhook_rl(),
+            imggen: Ratelimits::new_imggen_rl(),
         }
     }
 }
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::geese::botstatus::RECENT_DISPATCHES;
+
+/// Load of a worker over the period since it was last sampled
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkerLoad {
+    /// Average time events waited for the VM thread to pick them up
+    pub avg_queue_wait_ms: f64,
+    /// Highest time an event waited for the VM thread to pick it up
+    pub max_queue_wait_ms: u64,
+    /// Number of events dispatched
+    pub dispatched: u64,
+    /// Number of dispatches running at the time of sampling
+    pub inflight: usize,
+    /// Number of VMs at the time of sampling
+    pub vms: usize,
+    /// Number of dispatches served from the template registry
+    pub registry_hits: u64,
+    /// Number of registry entries (re)built from the tenant state cache
+    pub registry_rebuilds: u64,
+    /// Oldest registry entry a dispatch was served from
+    pub registry_max_age_ms: u64,
+    /// Number of dispatches served from the result cache
+    pub result_cache_hits: u64,
+    /// Number of cacheable dispatches not found in the result cache
+    pub result_cache_misses: u64,
+}
+
+/// Tracks the load of a worker thread, sampled by the master to autoscale the ring
+#[derive(Default)]
+pub struct LoadTracker {
+    wait_total_us: AtomicU64,
+    wait_max_us: AtomicU64,
+    dispatched: AtomicU64,
+    inflight: AtomicUsize,
+    vms: AtomicUsize,
+    registry_hits: AtomicU64,
+    registry_rebuilds: AtomicU64,
+    registry_max_age_ms: AtomicU64,
+    result_cache_hits: AtomicU64,
+    result_cache_misses: AtomicU64,
+    /// Durations of the most recent dispatches in milliseconds, not reset by sampling
+    recent: Mutex<VecDeque<u64>>,
+}
+
+impl LoadTracker {
+    /// Records an event being picked up by the VM thread, returning a guard that counts the dispatch as in-flight until dropped
+    pub fn start_dispatch(self: &Arc<Self>, queued_at: SystemTime) -> InflightGuard {
+        let waited = queued_at.elapsed().unwrap_or_default().as_micros() as u64;
+        self.wait_total_us.fetch_add(waited, Ordering::Relaxed);
+        self.wait_max_us.fetch_max(waited, Ordering::Relaxed);
+        self.dispatched.fetch_add(1, Ordering::Relaxed);
+        self.inflight.fetch_add(1, Ordering::Relaxed);
+        InflightGuard { tracker: self.clone(), queued_at }
+    }
+
+    /// Sets the number of VMs on the worker
+    pub fn set_vms(&self, vms: usize) {
+        self.vms.store(vms, Ordering::Relaxed);
+    }
+
+    /// Returns the number of dispatches currently running
+    pub fn inflight(&self) -> usize {
+        self.inflight.load(Ordering::Relaxed)
+    }
+
+    /// Records a dispatch served from a template registry entry of the given age
+    pub fn record_registry_hit(&self, age_ms: u64) {
+        self.registry_hits.fetch_add(1, Ordering::Relaxed);
+        self.registry_max_age_ms.fetch_max(age_ms, Ordering::Relaxed);
+    }
+
+    /// Records a template registry entry being (re)built
+    pub fn record_registry_rebuild(&self) {
+        self.registry_rebuilds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a dispatch served from the result cache
+    pub fn record_result_cache_hit(&self) {
+        self.result_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a cacheable dispatch not found in the result cache
+    pub fn record_result_cache_miss(&self) {
+        self.result_cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the durations of the most recent dispatches in milliseconds, oldest first
+    pub fn recent_dispatches(&self) -> Vec<u64> {
+        self.recent.lock().iter().copied().collect()
+    }
+
+    fn record_dispatch(&self, queued_at: SystemTime) {
+        let mut recent = self.recent.lock();
+        if recent.len() >= RECENT_DISPATCHES {
+            recent.pop_front();
+        }
+        recent.push_back(queued_at.elapsed().unwrap_or_default().as_millis() as u64);
+    }
+
+    /// Returns the load since the last sample and resets the wait, registry and result cache counters
+    pub fn sample(&self) -> WorkerLoad {
+        let wait_total_us = self.wait_total_us.swap(0, Ordering::Relaxed);
+        let wait_max_us = self.wait_max_us.swap(0, Ordering::Relaxed);
+        let dispatched = self.dispatched.swap(0, Ordering::Relaxed);
+        WorkerLoad {
+            avg_queue_wait_ms: if dispatched == 0 { 0.0 } else { wait_total_us as f64 / dispatched as f64 / 1000.0 },
+            max_queue_wait_ms: wait_max_us / 1000,
+            dispatched,
+            inflight: self.inflight.load(Ordering::Relaxed),
+            vms: self.vms.load(Ordering::Relaxed),
+            registry_hits: self.registry_hits.swap(0, Ordering::Relaxed),
+            registry_rebuilds: self.registry_rebuilds.swap(0, Ordering::Relaxed),
+            registry_max_age_ms: self.registry_max_age_ms.swap(0, Ordering::Relaxed),
+            result_cache_hits: self.result_cache_hits.swap(0, Ordering::Relaxed),
+            result_cache_misses: self.result_cache_misses.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts a dispatch as in-flight while held, recording its duration once dropped
+pub struct InflightGuard {
+    tracker: Arc<LoadTracker>,
+    queued_at: SystemTime,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.tracker.inflight.fetch_sub(1, Ordering::Relaxed);
+        self.tracker.record_dispatch(self.queued_at);
+    }
+}
@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+
+use khronos_runtime::rt::mlua::prelude::*;
+
+use crate::worker::limits::MAX_MARKDOWN_INPUT_BYTES;
+
+/// Name of the VM global the `@antiraid/markdown` module is exposed as
+pub const MARKDOWN_GLOBAL: &str = "__antiraid_markdown";
+
+/// Maximum nesting of formatting, anything nested deeper is left as text
+const MAX_DEPTH: usize = 32;
+
+/// A node of parsed Discord message content
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Node {
+    Text { content: String },
+    Bold { children: Vec<Node> },
+    Italic { children: Vec<Node> },
+    Underline { children: Vec<Node> },
+    Strikethrough { children: Vec<Node> },
+    Spoiler { children: Vec<Node> },
+    InlineCode { content: String },
+    CodeBlock { language: Option<String>, content: String },
+    /// A quoted line (`> `) or, if `multiline`, the rest of the message (`>>> `)
+    BlockQuote {
+        children: Vec<Node>,
+        #[serde(default)]
+        multiline: bool,
+    },
+    Heading { level: u8, children: Vec<Node> },
+    Subtext { children: Vec<Node> },
+    /// A masked link (`[text](url)`)
+    Link { url: String, children: Vec<Node> },
+    /// A bare url, `suppressed` if wrapped in `<>` to suppress its embed
+    Url {
+        url: String,
+        #[serde(default)]
+        suppressed: bool,
+    },
+    UserMention { id: String },
+    RoleMention { id: String },
+    ChannelMention { id: String },
+    Everyone,
+    Here,
+    Emoji {
+        name: String,
+        id: String,
+        #[serde(default)]
+        animated: bool,
+    },
+    Timestamp { timestamp: i64, style: Option<String> },
+    /// A slash command mention (`</name:id>`)
+    Command { name: String, id: String },
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    BoldItalic,
+    Bold,
+    Underline,
+    Strikethrough,
+    Spoiler,
+    Italic,
+}
+
+/// Formatting delimiters, longest first so `**` is matched before `*`
+const DELIMITERS: [(&str, Format); 7] = [
+    ("***", Format::BoldItalic),
+    ("**", Format::Bold),
+    ("__", Format::Underline),
+    ("~~", Format::Strikethrough),
+    ("||", Format::Spoiler),
+    ("*", Format::Italic),
+    ("_", Format::Italic),
+];
+
+fn is_snowflake(s: &str) -> bool {
+    (1..=20).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn prev_char(s: &str, i: usize) -> Option<char> {
+    s[..i].chars().next_back()
+}
+
+/// Parses the construct inside `<...>` (mentions, emojis, timestamps, commands and embed-suppressed urls)
+fn parse_angle(rest: &str) -> Option<(Node, usize)> {
+    let end = rest.find('>')?;
+    let inner = &rest[1..end];
+    let len = end + 1;
+
+    // Command names can contain spaces (subcommands)
+    if let Some(cmd) = inner.strip_prefix('/') {
+        let (name, id) = cmd.rsplit_once(':')?;
+        if name.is_empty() || !is_snowflake(id) {
+            return None;
+        }
+        return Some((Node::Command { name: name.to_string(), id: id.to_string() }, len));
+    }
+
+    if inner.is_empty() || inner.contains(char::is_whitespace) {
+        return None;
+    }
+
+    if inner.starts_with("http://") || inner.starts_with("https://") {
+        return Some((Node::Url { url: inner.to_string(), suppressed: true }, len));
+    }
+
+    let node = if let Some(id) = inner.strip_prefix("@&") {
+        is_snowflake(id).then(|| Node::RoleMention { id: id.to_string() })
+    } else if let Some(id) = inner.strip_prefix("@!").or_else(|| inner.strip_prefix('@')) {
+        is_snowflake(id).then(|| Node::UserMention { id: id.to_string() })
+    } else if let Some(id) = inner.strip_prefix('#') {
+        is_snowflake(id).then(|| Node::ChannelMention { id: id.to_string() })
+    } else if let Some(ts) = inner.strip_prefix("t:") {
+        let (ts, style) = match ts.split_once(':') {
+            Some((ts, style)) if matches!(style, "t" | "T" | "d" | "D" | "f" | "F" | "R") => (ts, Some(style.to_string())),
+            Some(_) => return None,
+            None => (ts, None),
+        };
+        ts.parse().ok().map(|timestamp| Node::Timestamp { timestamp, style })
+    } else {
+        let (animated, emoji) = match inner.strip_prefix("a:") {
+            Some(emoji) => (true, emoji),
+            None => (false, inner.strip_prefix(':')?),
+        };
+        let (name, id) = emoji.split_once(':')?;
+        (!name.is_empty() && is_snowflake(id)).then(|| Node::Emoji { name: name.to_string(), id: id.to_string(), animated })
+    };
+    node.map(|n| (n, len))
+}
+
+/// Parses a masked link (`[text](url)`)
+fn parse_link(rest: &str, depth: usize) -> Option<(Node, usize)> {
+    let close = rest.find("](")?;
+    let text = &rest[1..close];
+    let after = &rest[close + 2..];
+    let end = after.find(')')?;
+    let target = &after[..end];
+    let url = target.strip_prefix('<').and_then(|t| t.strip_suffix('>')).unwrap_or(target);
+    if text.trim().is_empty() || text.contains('\n') || !(url.starts_with("http://") || url.starts_with("https://")) || url.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((Node::Link { url: url.to_string(), children: parse(text, depth + 1, false) }, close + 2 + end + 1))
+}
+
+/// Parses a bare url, trailing punctuation is not considered part of the url
+fn parse_url(rest: &str) -> Option<(Node, usize)> {
+    let end = rest.find(|c: char| c.is_whitespace() || c == '<').unwrap_or(rest.len());
+    let url = rest[..end].trim_end_matches(['.', ',', ':', ';', '!', '?', '"', '\'', ')', ']']);
+    let scheme_len = if url.starts_with("https://") { 8 } else { 7 };
+    if url.len() <= scheme_len {
+        return None;
+    }
+    Some((Node::Url { url: url.to_string(), suppressed: false }, url.len()))
+}
+
+/// Parses a code block, the language is the first line if it is a single word followed by a newline
+fn parse_code_block(rest: &str) -> Option<(Node, usize)> {
+    let end = rest[3..].find("```")?;
+    let inner = &rest[3..3 + end];
+    let (language, content) = match inner.split_once('\n') {
+        Some((lang, content)) if !lang.is_empty() && lang.chars().all(|c| c.is_ascii_alphanumeric() || "_+-.#".contains(c)) => {
+            (Some(lang.to_string()), content)
+        }
+        _ => (None, inner),
+    };
+    Some((Node::CodeBlock { language, content: content.to_string() }, end + 6))
+}
+
+fn parse_inline_code(rest: &str) -> Option<(Node, usize)> {
+    let ticks = if rest.starts_with("``") { 2 } else { 1 };
+    let delim = &rest[..ticks];
+    let end = rest[ticks..].find(delim)?;
+    let content = &rest[ticks..ticks + end];
+    if content.is_empty() {
+        return None;
+    }
+    let content = if ticks == 2 { content.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')).unwrap_or(content) } else { content };
+    Some((Node::InlineCode { content: content.to_string() }, ticks + end + ticks))
+}
+
+/// Finds the closing delimiter for formatting opened at `start` (the index after the opening delimiter)
+fn find_closing(s: &str, start: usize, delim: &str) -> Option<usize> {
+    let single = delim.len() == 1;
+    let dc = delim.as_bytes()[0];
+    let bytes = s.as_bytes();
+    let mut k = start;
+    while k < s.len() {
+        if bytes[k] == b'\\' {
+            k += 1 + s[k + 1..].chars().next().map_or(0, char::len_utf8);
+            continue;
+        }
+        if s[k..].starts_with(delim) && k > start {
+            // Runs of a single character delimiter belong to other formatting (such as `**` inside `*`)
+            if single && bytes.get(k + 1) == Some(&dc) {
+                k += 2;
+                continue;
+            }
+            let next = s[k + delim.len()..].chars().next();
+            let ok = match delim {
+                "_" => !next.is_some_and(char::is_alphanumeric),
+                "*" => !prev_char(s, k).is_some_and(char::is_whitespace),
+                _ => next != Some(dc as char),
+            };
+            if ok {
+                return Some(k);
+            }
+        }
+        k += s[k..].chars().next().map_or(1, char::len_utf8);
+    }
+    None
+}
+
+/// Parses constructs only allowed at the start of a line (quotes, headings and subtext)
+fn parse_line_start(rest: &str, depth: usize, quotes: bool) -> Option<(Node, usize)> {
+    if quotes && let Some(content) = rest.strip_prefix(">>> ") {
+        return Some((Node::BlockQuote { children: parse(content, depth + 1, false), multiline: true }, rest.len()));
+    }
+
+    let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+    if quotes && let Some(content) = line.strip_prefix("> ") {
+        return Some((Node::BlockQuote { children: parse(content, depth + 1, false), multiline: false }, line.len()));
+    }
+    if let Some(content) = line.strip_prefix("-# ") && !content.trim().is_empty() {
+        return Some((Node::Subtext { children: parse(content, depth + 1, false) }, line.len()));
+    }
+
+    let level = line.bytes().take_while(|b| *b == b'#').count();
+    if (1..=3).contains(&level) && let Some(content) = line[level..].strip_prefix(' ') && !content.trim().is_empty() {
+        return Some((Node::Heading { level: level as u8, children: parse(content, depth + 1, false) }, line.len()));
+    }
+    None
+}
+
+/// Parses `s` into nodes. Block quotes are only parsed if `quotes` is set as they cannot be nested
+pub fn parse(s: &str, depth: usize, quotes: bool) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+    // Position of the opening delimiter at which no closing delimiter was found, later openings can't be closed either
+    let mut unclosed: HashMap<&'static str, usize> = HashMap::new();
+    let bytes = s.as_bytes();
+    let mut i = 0;
+
+    while i < s.len() {
+        let rest = &s[i..];
+        let mut parsed = None;
+
+        if rest.starts_with('\\') && let Some(c) = rest[1..].chars().next() && c.is_ascii_punctuation() {
+            text.push(c);
+            i += 1 + c.len_utf8();
+            continue;
+        }
+
+        if depth < MAX_DEPTH && (i == 0 || bytes[i - 1] == b'\n') {
+            parsed = parse_line_start(rest, depth, quotes);
+        }
+
+        if parsed.is_none() {
+            parsed = match bytes[i] {
+                b'`' if rest.starts_with("```") => parse_code_block(rest),
+                b'`' => parse_inline_code(rest),
+                b'<' => parse_angle(rest),
+                b'@' if rest.starts_with("@everyone") => Some((Node::Everyone, 9)),
+                b'@' if rest.starts_with("@here") => Some((Node::Here, 5)),
+                b'[' if depth < MAX_DEPTH => parse_link(rest, depth),
+                b'h' if (rest.starts_with("https://") || rest.starts_with("http://")) && !prev_char(s, i).is_some_and(char::is_alphanumeric) => parse_url(rest),
+                b'*' | b'_' | b'~' | b'|' if depth < MAX_DEPTH => parse_format(s, i, depth, &mut unclosed),
+                _ => None,
+            };
+        }
+
+        match parsed {
+            Some((node, len)) => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text { content: std::mem::take(&mut text) });
+                }
+                nodes.push(node);
+                i += len;
+            }
+            None => {
+                let c = rest.chars().next().expect("i is within s");
+                text.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+
+    if !text.is_empty() {
+        nodes.push(Node::Text { content: text });
+    }
+    nodes
+}
+
+fn parse_format(s: &str, i: usize, depth: usize, unclosed: &mut HashMap<&'static str, usize>) -> Option<(Node, usize)> {
+    let rest = &s[i..];
+    for (delim, format) in DELIMITERS {
+        if !rest.starts_with(delim) || unclosed.get(delim).is_some_and(|p| *p <= i) {
+            continue;
+        }
+
+        let after = rest[delim.len()..].chars().next();
+        let opens = match delim {
+            "*" => after.is_some_and(|c| !c.is_whitespace()),
+            "_" => !prev_char(s, i).is_some_and(char::is_alphanumeric),
+            _ => true,
+        };
+        if !opens {
+            continue;
+        }
+
+        let start = i + delim.len();
+        let Some(end) = find_closing(s, start, delim) else {
+            unclosed.insert(delim, i);
+            continue;
+        };
+
+        let children = parse(&s[start..end], depth + 1, false);
+        let node = match format {
+            Format::BoldItalic => Node::Bold { children: vec![Node::Italic { children }] },
+            Format::Bold => Node::Bold { children },
+            Format::Underline => Node::Underline { children },
+            Format::Strikethrough => Node::Strikethrough { children },
+            Format::Spoiler => Node::Spoiler { children },
+            Format::Italic => Node::Italic { children },
+        };
+        return Some((node, end + delim.len() - i));
+    }
+    None
+}
+
+/// Escapes text so it renders literally, `line_start` is whether `s` starts at the start of a line
+fn escape_text(s: &str, mut line_start: bool, out: &mut String) {
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let rest = &s[i..];
+        if line_start && matches!(c, '>' | '#' | '-') {
+            out.push('\\');
+        } else if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|' | '<' | '[' | ']')
+            || (c == '@' && (rest.starts_with("@everyone") || rest.starts_with("@here")))
+            || (c == ':' && rest.starts_with("://"))
+        {
+            out.push('\\');
+        }
+        out.push(c);
+        line_start = c == '\n';
+    }
+}
+
+fn at_line_start(out: &str) -> bool {
+    out.is_empty() || out.ends_with('\n')
+}
+
+/// Renders nodes back to message content
+pub fn render(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        let wrap = |out: &mut String, delim: &str, children: &[Node]| {
+            out.push_str(delim);
+            render(children, out);
+            out.push_str(delim);
+        };
+
+        match node {
+            Node::Text { content } => escape_text(content, at_line_start(out), out),
+            Node::Bold { children } => wrap(out, "**", children),
+            Node::Italic { children } => wrap(out, "*", children),
+            Node::Underline { children } => wrap(out, "__", children),
+            Node::Strikethrough { children } => wrap(out, "~~", children),
+            Node::Spoiler { children } => wrap(out, "||", children),
+            Node::InlineCode { content } => {
+                if content.contains('`') {
+                    out.push_str(&format!("`` {content} ``"));
+                } else {
+                    out.push_str(&format!("`{content}`"));
+                }
+            }
+            Node::CodeBlock { language, content } => match language {
+                Some(language) => out.push_str(&format!("```{language}\n{content}```")),
+                None => out.push_str(&format!("```{content}```")),
+            },
+            Node::BlockQuote { children, multiline } => {
+                if !at_line_start(out) {
+                    out.push('\n');
+                }
+                out.push_str(if *multiline { ">>> " } else { "> " });
+                render(children, out);
+            }
+            Node::Heading { level, children } => {
+                if !at_line_start(out) {
+                    out.push('\n');
+                }
+                out.push_str(&"#".repeat((*level).clamp(1, 3) as usize));
+                out.push(' ');
+                render(children, out);
+            }
+            Node::Subtext { children } => {
+                if !at_line_start(out) {
+                    out.push('\n');
+                }
+                out.push_str("-# ");
+                render(children, out);
+            }
+            Node::Link { url, children } => {
+                out.push('[');
+                render(children, out);
+                out.push_str(&format!("]({url})"));
+            }
+            Node::Url { url, suppressed } => {
+                if *suppressed {
+                    out.push_str(&format!("<{url}>"));
+                } else {
+                    out.push_str(url);
+                }
+            }
+            Node::UserMention { id } => out.push_str(&format!("<@{id}>")),
+            Node::RoleMention { id } => out.push_str(&format!("<@&{id}>")),
+            Node::ChannelMention { id } => out.push_str(&format!("<#{id}>")),
+            Node::Everyone => out.push_str("@everyone"),
+            Node::Here => out.push_str("@here"),
+            Node::Emoji { name, id, animated } => out.push_str(&format!("<{}:{name}:{id}>", if *animated { "a" } else { "" })),
+            Node::Timestamp { timestamp, style } => match style {
+                Some(style) => out.push_str(&format!("<t:{timestamp}:{style}>")),
+                None => out.push_str(&format!("<t:{timestamp}>")),
+            },
+            Node::Command { name, id } => out.push_str(&format!("</{name}:{id}>")),
+        }
+    }
+}
+
+/// Renders nodes as plain text, dropping all formatting
+///
+/// Mentions, timestamps and commands are kept in their raw form, custom emojis become `:name:`
+fn strip(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text { content } | Node::InlineCode { content } | Node::CodeBlock { content, .. } => out.push_str(content),
+            Node::Bold { children }
+            | Node::Italic { children }
+            | Node::Underline { children }
+            | Node::Strikethrough { children }
+            | Node::Spoiler { children }
+            | Node::BlockQuote { children, .. }
+            | Node::Heading { children, .. }
+            | Node::Subtext { children }
+            | Node::Link { children, .. } => strip(children, out),
+            Node::Url { url, .. } => out.push_str(url),
+            Node::Emoji { name, .. } => out.push_str(&format!(":{name}:")),
+            node => render(std::slice::from_ref(node), out),
+        }
+    }
+}
+
+/// Calls `f` for every node, depth first
+fn walk(nodes: &[Node], f: &mut impl FnMut(&Node)) {
+    for node in nodes {
+        f(node);
+        match node {
+            Node::Bold { children }
+            | Node::Italic { children }
+            | Node::Underline { children }
+            | Node::Strikethrough { children }
+            | Node::Spoiler { children }
+            | Node::BlockQuote { children, .. }
+            | Node::Heading { children, .. }
+            | Node::Subtext { children }
+            | Node::Link { children, .. } => walk(children, f),
+            _ => {}
+        }
+    }
+}
+
+fn input(s: &LuaString) -> LuaResult<String> {
+    if s.as_bytes().len() > MAX_MARKDOWN_INPUT_BYTES {
+        return Err(LuaError::external(format!("content exceeds the maximum size of {MAX_MARKDOWN_INPUT_BYTES} bytes")));
+    }
+    Ok(s.to_str()?.to_string())
+}
+
+fn parse_content(s: &LuaString) -> LuaResult<Vec<Node>> {
+    Ok(parse(&input(s)?, 0, true))
+}
+
+/// Creates the `@antiraid/markdown` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+    let ser_opts = LuaSerializeOptions::new().serialize_none_to_null(false).serialize_unit_to_null(false);
+
+    module.raw_set("parse", lua.create_function(move |lua, s: LuaString| {
+        lua.to_value_with(&parse_content(&s)?, ser_opts)
+    })?)?;
+
+    module.raw_set("render", lua.create_function(|lua, nodes: LuaValue| {
+        let nodes: Vec<Node> = lua.from_value(nodes)?;
+        let mut out = String::new();
+        render(&nodes, &mut out);
+        Ok(out)
+    })?)?;
+
+    module.raw_set("strip", lua.create_function(|_, s: LuaString| {
+        let mut out = String::new();
+        strip(&parse_content(&s)?, &mut out);
+        Ok(out)
+    })?)?;
+
+    module.raw_set("escape", lua.create_function(|_, s: LuaString| {
+        let mut out = String::new();
+        escape_text(&input(&s)?, true, &mut out);
+        Ok(out)
+    })?)?;
+
+    module.raw_set("mentions", lua.create_function(|lua, s: LuaString| {
+        let (users, roles, channels) = (lua.create_table()?, lua.create_table()?, lua.create_table()?);
+        let (mut everyone, mut here) = (false, false);
+        let mut err = None;
+        walk(&parse_content(&s)?, &mut |node| {
+            let res = match node {
+                Node::UserMention { id } => users.raw_push(id.as_str()),
+                Node::RoleMention { id } => roles.raw_push(id.as_str()),
+                Node::ChannelMention { id } => channels.raw_push(id.as_str()),
+                Node::Everyone => { everyone = true; Ok(()) }
+                Node::Here => { here = true; Ok(()) }
+                _ => Ok(()),
+            };
+            if let Err(e) = res {
+                err.get_or_insert(e);
+            }
+        });
+        if let Some(err) = err {
+            return Err(err);
+        }
+
+        let mentions = lua.create_table_with_capacity(0, 5)?;
+        mentions.set("users", users)?;
+        mentions.set("roles", roles)?;
+        mentions.set("channels", channels)?;
+        mentions.set("everyone", everyone)?;
+        mentions.set("here", here)?;
+        Ok(mentions)
+    })?)?;
+
+    module.raw_set("links", lua.create_function(|_, s: LuaString| {
+        let mut links = Vec::new();
+        walk(&parse_content(&s)?, &mut |node| {
+            if let Node::Link { url, .. } | Node::Url { url, .. } = node {
+                links.push(url.clone());
+            }
+        });
+        Ok(links)
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
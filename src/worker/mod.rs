@@ -5,5 +5,54 @@ pub mod limits;
 pub mod builtins;
 pub mod worker;
 pub mod workerthread;
+pub mod load;
+pub mod shutdown;
+pub mod templateregistry;
 pub mod workertenantstate;
 pub mod syscall;
+pub mod actor;
+pub mod webhooks;
+pub mod timeslice;
+pub mod dispatchgate;
+pub mod abort;
+pub mod execlock;
+pub mod replay;
+pub mod random;
+pub mod crypto;
+pub mod validate;
+pub mod rules;
+pub mod fixtures;
+pub mod snowflake;
+pub mod int64;
+pub mod stringutils;
+pub mod fuzzy;
+pub mod tableutils;
+pub mod imggen;
+pub mod chart;
+pub mod markdown;
+pub mod serdeext;
+pub mod scratch;
+pub mod plugins;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod eventtypes;
+pub mod onboarding;
+pub mod modmail;
+pub mod appeals;
+pub mod watchlist;
+pub mod altdetect;
+pub mod invites;
+pub mod eventsink;
+pub mod namepolicy;
+pub mod stickyroles;
+pub mod permsnapshots;
+pub mod nukeprotection;
+pub mod webhookspam;
+pub mod schedules;
+pub mod autopublish;
+pub mod threadpolicies;
+pub mod emojiusage;
+pub mod activity;
+pub mod auditcorrelation;
+pub mod voiceidle;
+pub mod resultcache;
@@ -0,0 +1,347 @@
+use bytes::Bytes;
+use dapi::{ChannelId, GuildId, UserId};
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde_json::{Value, json};
+
+use crate::CONFIG;
+use crate::geese::modmail::{MODMAIL_SCOPE, ModmailMessage, ModmailReq, ModmailResp, ModmailSession, RelayDirection};
+use crate::geese::state::{StateDbFlags, StateOp};
+use crate::worker::limits::MAX_OBJ_STORAGE_BYTES;
+use crate::worker::syscall::cdn::download_file;
+use crate::worker::workerdispatch::{SimpleEvent, WorkerDispatch};
+use crate::worker::workervmmanager::Id;
+
+/// Message staff send in a modmail thread to close the session
+pub const CLOSE_COMMAND: &str = "!close";
+
+/// Event dispatched to a guild once a modmail session has been opened
+pub const MODMAIL_OPENED_EVENT: &str = "ModmailOpened";
+
+/// Event dispatched to a guild for every message relayed in a modmail session
+pub const MODMAIL_MESSAGE_RELAYED_EVENT: &str = "ModmailMessageRelayed";
+
+/// Event dispatched to a guild once a modmail session has been closed
+pub const MODMAIL_CLOSED_EVENT: &str = "ModmailClosed";
+
+/// Max length of a Discord message
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// Max length of a thread name
+const MAX_THREAD_NAME_LENGTH: usize = 100;
+
+/// Discord channel type of a public thread
+const PUBLIC_THREAD: u8 = 11;
+
+/// An attachment of a message to relay
+struct Attachment {
+    filename: String,
+    url: String,
+}
+
+/// The parts of a MESSAGE_CREATE payload modmail needs
+struct IncomingMessage {
+    channel_id: ChannelId,
+    author_id: UserId,
+    author_name: String,
+    content: String,
+    attachments: Vec<Attachment>,
+}
+
+impl IncomingMessage {
+    /// Parses a MESSAGE_CREATE payload, returning None for messages of bots and malformed payloads
+    fn parse(payload: &Value) -> Option<Self> {
+        let author = payload.get("author")?;
+        if author.get("bot").and_then(|v| v.as_bool()).unwrap_or(false) {
+            return None;
+        }
+
+        let attachments = payload.get("attachments")
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|a| Some(Attachment {
+                filename: a.get("filename")?.as_str()?.to_string(),
+                url: a.get("url")?.as_str()?.to_string(),
+            })).collect())
+            .unwrap_or_default();
+
+        Some(Self {
+            channel_id: payload.get("channel_id")?.as_str()?.parse().ok()?,
+            author_id: author.get("id")?.as_str()?.parse().ok()?,
+            author_name: author.get("username")?.as_str()?.to_string(),
+            content: payload.get("content").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            attachments,
+        })
+    }
+}
+
+/// Relays messages between the DMs of users and threads in the modmail channel of a guild
+///
+/// DMs are received by the users tenant and thread messages by the guild tenant (which may live on different
+/// workers), so sessions are stored by the master
+pub struct Modmail<'a> {
+    dispatch: &'a WorkerDispatch,
+}
+
+impl<'a> Modmail<'a> {
+    pub fn new(dispatch: &'a WorkerDispatch) -> Self {
+        Self { dispatch }
+    }
+
+    /// Handles a MESSAGE_CREATE in the background if it is a DM or a message in a modmail thread
+    pub fn start(dispatch: &WorkerDispatch, id: Id, payload: &Value) {
+        let is_dm = payload.get("guild_id").is_none_or(|v| v.is_null());
+        match id {
+            Id::User(_) if is_dm => {}
+            Id::Guild(_) if !is_dm => {}
+            _ => return,
+        }
+        let Some(msg) = IncomingMessage::parse(payload) else {
+            return;
+        };
+        if let Id::Guild(_) = id {
+            // Cheap check before any fetches, most guilds do not use modmail
+            match dispatch.tenant_state.get_cached_tenant_state_for(id) {
+                Ok(ts) if ts.settings.modmail_channel_id.is_some() => {}
+                _ => return,
+            }
+        }
+
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            let modmail = Modmail::new(&dispatch);
+            let res = match id {
+                Id::User(_) => modmail.handle_dm(msg).await,
+                Id::Guild(guild_id) => modmail.handle_thread_message(guild_id, msg).await,
+            };
+            if let Err(e) = res {
+                log::error!("Failed to handle modmail message for {id:?}: {e}");
+            }
+        });
+    }
+
+    /// Relays a DM of a user to their session, opening one if needed
+    async fn handle_dm(&self, msg: IncomingMessage) -> Result<(), crate::Error> {
+        // A DM consisting of only a server ID selects the server to contact
+        let guild_hint = msg.content.trim().parse::<GuildId>().ok();
+        let route = self.request(ModmailReq::Route { user_id: msg.author_id, guild_hint }).await?;
+
+        let session = match route {
+            ModmailResp::Routed { session } => session,
+            ModmailResp::NeedsSession { guild_id, channel_id } => {
+                if self.dispatch.worker_state.stratum.guild_member(guild_id, msg.author_id).await?.is_none() {
+                    self.send_message(msg.channel_id, "You are not a member of that server.", Vec::new()).await?;
+                    return Ok(());
+                }
+
+                let session = self.open(guild_id, channel_id, &msg).await?;
+                if guild_hint.is_some() && msg.attachments.is_empty() {
+                    self.send_message(msg.channel_id, "Your messages are now sent to the staff of the server, they will reply here.", Vec::new()).await?;
+                    return Ok(());
+                }
+                session
+            }
+            ModmailResp::Unrouted => {
+                self.send_message(msg.channel_id, "To contact the staff of a server, send its server ID here.", Vec::new()).await?;
+                return Ok(());
+            }
+            resp => return Err(format!("Unexpected modmail response: {resp:?}").into()),
+        };
+
+        let files = self.download_attachments(&msg.attachments).await?;
+        let content = format!("**{}**: {}", msg.author_name, msg.content);
+        self.send_message(session.thread_id, &content, files).await?;
+        self.record(&session, RelayDirection::User, &msg).await
+    }
+
+    /// Starts the thread of a new session in the modmail channel
+    async fn open(&self, guild_id: GuildId, channel_id: ChannelId, msg: &IncomingMessage) -> Result<ModmailSession, crate::Error> {
+        let name = truncate(&format!("{}-{}", msg.author_name, msg.author_id), MAX_THREAD_NAME_LENGTH);
+        let thread: Value = self.rest(reqwest::Method::POST, &format!("/channels/{channel_id}/threads"))
+            .json(&json!({ "name": name, "type": PUBLIC_THREAD }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let thread_id: ChannelId = thread.get("id")
+            .and_then(|v| v.as_str())
+            .ok_or("Created thread has no ID")?
+            .parse()?;
+
+        let session = match self.request(ModmailReq::Open { guild_id, user_id: msg.author_id, thread_id }).await? {
+            ModmailResp::Routed { session } => session,
+            resp => return Err(format!("Unexpected modmail response: {resp:?}").into()),
+        };
+
+        let intro = format!("Modmail opened by <@{id}> ({id}). Reply in this thread to respond, send `{CLOSE_COMMAND}` to close.", id = msg.author_id);
+        self.send_message(session.thread_id, &intro, Vec::new()).await?;
+        self.notify(guild_id, MODMAIL_OPENED_EVENT, json!({ "session": session })).await?;
+        Ok(session)
+    }
+
+    /// Relays a staff message in a modmail thread to the user, or closes the session on `CLOSE_COMMAND`
+    async fn handle_thread_message(&self, guild_id: GuildId, msg: IncomingMessage) -> Result<(), crate::Error> {
+        let tenant_state = self.dispatch.tenant_state.get_cached_tenant_state_for(Id::Guild(guild_id))?;
+        let Some(modmail_channel_id) = tenant_state.settings.modmail_channel_id else {
+            return Ok(());
+        };
+        let Some(channel) = self.dispatch.worker_state.stratum.channel(msg.channel_id).await? else {
+            return Ok(());
+        };
+        if channel.get("parent_id").and_then(|v| v.as_str()) != Some(modmail_channel_id.to_string().as_str()) {
+            return Ok(());
+        }
+
+        let session = match self.request(ModmailReq::SessionForThread { guild_id, thread_id: msg.channel_id }).await? {
+            ModmailResp::Session { session: Some(session) } => session,
+            ModmailResp::Session { session: None } => return Ok(()),
+            resp => return Err(format!("Unexpected modmail response: {resp:?}").into()),
+        };
+
+        if msg.content.trim() == CLOSE_COMMAND {
+            return self.close(session, msg.author_id).await;
+        }
+
+        let files = self.download_attachments(&msg.attachments).await?;
+        let dm_channel_id = self.dm_channel(session.user_id).await?;
+        self.send_message(dm_channel_id, &format!("**Staff**: {}", msg.content), files).await?;
+        self.record(&session, RelayDirection::Staff, &msg).await
+    }
+
+    /// Closes a session, saving its transcript and archiving its thread
+    async fn close(&self, session: ModmailSession, closed_by: UserId) -> Result<(), crate::Error> {
+        let (session, messages) = match self.request(ModmailReq::Close { session_id: session.id, closed_by }).await? {
+            ModmailResp::Closed { session, messages } => (session, messages),
+            resp => return Err(format!("Unexpected modmail response: {resp:?}").into()),
+        };
+
+        let id = Id::Guild(session.guild_id);
+        if let Some(ref key) = session.transcript_key {
+            let blob = transcript(&session, &messages);
+            let ops = vec![StateOp::KvSet {
+                key: key.clone(),
+                scope: MODMAIL_SCOPE.into(),
+                value: KhronosValue::Text(session.user_id.to_string().into()),
+                blob: Some(blob),
+            }];
+            let res = self.dispatch.worker_state.mesophyll_client.exec_state_op(id, ops, StateDbFlags::WORKER_INITIATED).await?;
+            if let Some(ref ts) = res.new_tenant_state {
+                self.dispatch.tenant_state.reload_for_tenant(id, ts)?;
+            }
+        }
+
+        let dm_channel_id = self.dm_channel(session.user_id).await?;
+        self.send_message(dm_channel_id, "This conversation has been closed by staff. Send another message to open a new one.", Vec::new()).await?;
+        self.rest(reqwest::Method::PATCH, &format!("/channels/{}", session.thread_id))
+            .json(&json!({ "archived": true, "locked": true }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        self.notify(session.guild_id, MODMAIL_CLOSED_EVENT, json!({ "session": session, "message_count": messages.len() })).await
+    }
+
+    /// Records a relayed message and notifies the guild of it
+    async fn record(&self, session: &ModmailSession, direction: RelayDirection, msg: &IncomingMessage) -> Result<(), crate::Error> {
+        let attachments = msg.attachments.iter().map(|a| a.filename.clone()).collect::<Vec<_>>();
+        self.request(ModmailReq::Record {
+            session_id: session.id,
+            direction,
+            author_id: msg.author_id,
+            content: msg.content.clone(),
+            attachments: attachments.clone(),
+        }).await?;
+
+        self.notify(session.guild_id, MODMAIL_MESSAGE_RELAYED_EVENT, json!({
+            "session": session,
+            "direction": direction,
+            "author_id": msg.author_id,
+            "content": msg.content,
+            "attachments": attachments,
+        })).await
+    }
+
+    async fn request(&self, req: ModmailReq) -> Result<ModmailResp, crate::Error> {
+        self.dispatch.worker_state.mesophyll_client.modmail(&req).await
+    }
+
+    /// Dispatches a modmail event to a guild, through the master as the guild may be owned by another worker
+    async fn notify(&self, guild_id: GuildId, name: &str, data: Value) -> Result<(), crate::Error> {
+        let event = SimpleEvent::new_json_string(name.to_string(), None, data.to_string());
+        self.dispatch.worker_state.mesophyll_client.forward_event(Id::Guild(guild_id), event).await
+    }
+
+    async fn download_attachments(&self, attachments: &[Attachment]) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        let mut files = Vec::with_capacity(attachments.len());
+        for attachment in attachments {
+            let bytes = download_file(&self.dispatch.worker_state.reqwest, &attachment.url).await?;
+            files.push((attachment.filename.clone(), bytes));
+        }
+        Ok(files)
+    }
+
+    /// Returns the DM channel with a user, creating it if needed
+    async fn dm_channel(&self, user_id: UserId) -> Result<ChannelId, crate::Error> {
+        let channel: Value = self.rest(reqwest::Method::POST, "/users/@me/channels")
+            .json(&json!({ "recipient_id": user_id }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(channel.get("id")
+            .and_then(|v| v.as_str())
+            .ok_or("DM channel has no ID")?
+            .parse()?)
+    }
+
+    /// Sends a message with the given files, with all mentions disabled
+    async fn send_message(&self, channel_id: ChannelId, content: &str, files: Vec<(String, Bytes)>) -> Result<(), crate::Error> {
+        let payload = json!({
+            "content": truncate(content, MAX_MESSAGE_LENGTH),
+            "allowed_mentions": { "parse": [] },
+        });
+
+        let mut form = reqwest::multipart::Form::new().text("payload_json", payload.to_string());
+        for (i, (filename, bytes)) in files.into_iter().enumerate() {
+            form = form.part(format!("files[{i}]"), reqwest::multipart::Part::stream(bytes).file_name(filename));
+        }
+
+        self.rest(reqwest::Method::POST, &format!("/channels/{channel_id}/messages"))
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn rest(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.dispatch.worker_state.reqwest.request(method, format!("{}/api/v10{path}", CONFIG.proxy))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+    }
+}
+
+/// Marker appended to transcripts cut off at the max blob size
+const TRUNCATED_MARKER: &str = "[transcript truncated]\n";
+
+/// Renders the transcript of a session, keeping it under the max blob size
+fn transcript(session: &ModmailSession, messages: &[ModmailMessage]) -> Bytes {
+    let mut out = format!("Modmail session {} with user {} in guild {}\n\n", session.id, session.user_id, session.guild_id);
+    for message in messages {
+        let mut line = format!("[{}] {} {}: {}", message.created_at.to_rfc3339(), message.direction.as_str(), message.author_id, message.content);
+        if !message.attachments.is_empty() {
+            line.push_str(&format!(" (attachments: {})", message.attachments.join(", ")));
+        }
+        line.push('\n');
+
+        if out.len() + line.len() + TRUNCATED_MARKER.len() > MAX_OBJ_STORAGE_BYTES {
+            out.push_str(TRUNCATED_MARKER);
+            break;
+        }
+        out.push_str(&line);
+    }
+    Bytes::from(out)
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    s.chars().take(max_chars).collect()
+}
@@ -0,0 +1,304 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use dapi::{GuildId, UserId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::CONFIG;
+use crate::geese::namepolicy::{MAX_NICKNAME_LENGTH, NameAction, NameCheck, NamePolicy, NameRule, compile_pattern};
+use crate::worker::stringutils::{clean_name, normalize};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Event dispatched to a guild when the name of a member violates its name policy
+pub const NAME_POLICY_VIOLATION_EVENT: &str = "NamePolicyViolation";
+
+/// Internal event stinging a member for their name, handled by the builtins (see `auxutils/namepolicysting.luau`)
+pub const NAME_POLICY_STING_EVENT: &str = "$NamePolicySting";
+
+/// Maximum number of checked names kept, all are forgotten once exceeded
+const MAX_CHECKED_NAMES: usize = 10_000;
+
+/// Name members are renamed to if their cleaned up name is empty or still violates the policy
+const FALLBACK_NAME: &str = "Renamed member";
+
+/// Audit log reason of renames
+const RENAME_REASON: &str = "Name policy violation";
+
+/// A name policy with the patterns of its rules compiled
+struct CompiledPolicy {
+    policy: NamePolicy,
+    /// Patterns of each rule, empty for rules other than blocklists
+    patterns: Vec<Vec<regex::Regex>>,
+}
+
+impl CompiledPolicy {
+    fn new(policy: NamePolicy) -> Self {
+        let patterns = policy.rules.iter()
+            .map(|rule| match rule.check {
+                // Patterns are validated on being set
+                NameCheck::Blocklist { ref patterns, .. } => patterns.iter().filter_map(|p| compile_pattern(p).ok()).collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+        Self { policy, patterns }
+    }
+}
+
+/// A rule violated by the name of a member
+#[derive(Debug, Clone, Serialize)]
+pub struct ViolatedRule {
+    pub rule: String,
+    /// `blocklist`, `prefix` or `readable`
+    pub check: &'static str,
+    pub actions: Vec<NameAction>,
+}
+
+/// Data of `NamePolicyViolation`
+#[derive(Debug, Clone, Serialize)]
+pub struct NamePolicyViolation {
+    pub user_id: UserId,
+    /// The violating name
+    pub name: String,
+    /// Whether the member just joined (else their name was changed)
+    pub joined: bool,
+    pub rules: Vec<ViolatedRule>,
+    /// The nickname the member was renamed to, if renamed
+    pub renamed_to: Option<String>,
+}
+
+impl IntoLua for NamePolicyViolation {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Data of `$NamePolicySting`
+#[derive(Serialize)]
+struct NamePolicySting {
+    user_id: UserId,
+    rule: String,
+    name: String,
+    stings: u32,
+    expiry: u64,
+}
+
+impl IntoLua for NamePolicySting {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Enforces the name policy of a guild on members joining and changing their name
+///
+/// Rules are checked in order against the name members are shown by, each rule with the `rename` action checking
+/// the name as renamed by the rules before it. The actions of all violated rules are taken by the guild's worker
+/// (so templates need not take them) before `NamePolicyViolation` is dispatched
+#[derive(Clone, Default)]
+pub struct NamePolicyEnforcer {
+    policies: Rc<RefCell<HashMap<GuildId, Rc<CompiledPolicy>>>>,
+    /// Names last checked per member, so member updates not changing the name (or caused by renames) are skipped
+    checked: Rc<RefCell<HashMap<(GuildId, UserId), String>>>,
+}
+
+impl NamePolicyEnforcer {
+    /// Checks the name of a member in the background given its GUILD_MEMBER_ADD or GUILD_MEMBER_UPDATE payload, if
+    /// the guild has a name policy
+    pub fn start(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value, joined: bool) {
+        let Some(policy) = dispatch.tenant_state.name_policy(Id::Guild(guild_id)) else {
+            return;
+        };
+        let Some(user) = payload.get("user") else {
+            return;
+        };
+        if user.get("bot").and_then(|v| v.as_bool()).unwrap_or(false) {
+            return;
+        }
+        let Some(user_id) = user.get("id").and_then(|v| v.as_str()).and_then(|v| v.parse::<UserId>().ok()) else {
+            return;
+        };
+        let exempt = payload.get("roles").and_then(|v| v.as_array())
+            .is_some_and(|roles| roles.iter().filter_map(|r| r.as_str()).any(|r| policy.exempt_roles.iter().any(|e| e == r)));
+        if exempt {
+            return;
+        }
+
+        let Some(name) = payload.get("nick").and_then(|v| v.as_str())
+            .or_else(|| user.get("global_name").and_then(|v| v.as_str()))
+            .or_else(|| user.get("username").and_then(|v| v.as_str()))
+            .filter(|n| !n.is_empty())
+            .map(|n| n.to_string()) else {
+            return;
+        };
+        let compiled = self.compiled(guild_id, policy);
+        if !self.mark_checked(guild_id, user_id, &name) {
+            return;
+        }
+
+        let (rules, renamed) = evaluate(&compiled, &name);
+        if rules.is_empty() {
+            return;
+        }
+        let renamed_to = renamed.filter(|r| *r != name);
+        if let Some(ref renamed_to) = renamed_to {
+            // The rename triggers a member update which should not be checked again
+            self.mark_checked(guild_id, user_id, renamed_to);
+        }
+
+        let violation = NamePolicyViolation { user_id, name, joined, rules, renamed_to };
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            if let Some(ref renamed_to) = violation.renamed_to && let Err(e) = rename(&dispatch, guild_id, user_id, renamed_to).await {
+                log::debug!("Failed to rename {user_id} in guild {guild_id}: {e}");
+            }
+            if let Err(e) = notify(&dispatch, &compiled.policy, &violation).await {
+                log::debug!("Failed to notify of name of {user_id} in guild {guild_id}: {e}");
+            }
+            for rule in compiled.policy.rules.iter().filter(|rule| rule.actions.contains(&NameAction::Sting) && violation.rules.iter().any(|r| r.rule == rule.name)) {
+                let sting = NamePolicySting { user_id, rule: rule.name.clone(), name: violation.name.clone(), stings: rule.stings, expiry: rule.sting_expiry };
+                if let Err(e) = dispatch.dispatch_event_complex(Id::Guild(guild_id), NAME_POLICY_STING_EVENT, None, sting).await {
+                    log::error!("Failed to sting {user_id} in guild {guild_id} for their name: {e}");
+                }
+            }
+            if let Err(e) = dispatch.dispatch_event_complex(Id::Guild(guild_id), NAME_POLICY_VIOLATION_EVENT, None, violation).await {
+                log::error!("Failed to dispatch {NAME_POLICY_VIOLATION_EVENT} for {user_id} in guild {guild_id}: {e}");
+            }
+        });
+    }
+
+    /// Records the name of a member as checked, returning false if it already was
+    fn mark_checked(&self, guild_id: GuildId, user_id: UserId, name: &str) -> bool {
+        let mut checked = self.checked.borrow_mut();
+        if checked.get(&(guild_id, user_id)).is_some_and(|n| n == name) {
+            return false;
+        }
+        if checked.len() >= MAX_CHECKED_NAMES {
+            checked.clear();
+        }
+        checked.insert((guild_id, user_id), name.to_string());
+        true
+    }
+
+    /// Returns the compiled policy of a guild, recompiling it (and forgetting the names checked against the old
+    /// policy) if the policy changed
+    fn compiled(&self, guild_id: GuildId, policy: NamePolicy) -> Rc<CompiledPolicy> {
+        let mut policies = self.policies.borrow_mut();
+        if let Some(compiled) = policies.get(&guild_id) && compiled.policy == policy {
+            return compiled.clone();
+        }
+        let compiled = Rc::new(CompiledPolicy::new(policy));
+        policies.insert(guild_id, compiled.clone());
+        self.checked.borrow_mut().retain(|(g, _), _| *g != guild_id);
+        compiled
+    }
+}
+
+/// Checks a name against the rules of a policy, returning the violated rules and the name renaming rules renamed
+/// it to (if any renamed it)
+fn evaluate(compiled: &CompiledPolicy, name: &str) -> (Vec<ViolatedRule>, Option<String>) {
+    let mut violated = Vec::new();
+    let mut current = name.to_string();
+    let mut renamed = false;
+    for (rule, patterns) in compiled.policy.rules.iter().zip(compiled.patterns.iter()) {
+        let Some(replacement) = check(rule, patterns, &current) else {
+            continue;
+        };
+        if rule.actions.contains(&NameAction::Rename) {
+            current = truncate(&replacement);
+            renamed = true;
+        }
+        violated.push(ViolatedRule { rule: rule.name.clone(), check: check_name(&rule.check), actions: rule.actions.clone() });
+    }
+    (violated, renamed.then_some(current))
+}
+
+/// Returns the name a name violating a rule would be renamed to, or None if the name follows the rule
+fn check(rule: &NameRule, patterns: &[regex::Regex], name: &str) -> Option<String> {
+    match rule.check {
+        NameCheck::Blocklist { normalize: normalized, .. } => {
+            let matches = |name: &str| {
+                let target = if normalized { normalize(name) } else { name.to_string() };
+                patterns.iter().any(|p| p.is_match(&target))
+            };
+            if !matches(name) {
+                return None;
+            }
+            if let Some(ref rename_to) = rule.rename_to {
+                return Some(rename_to.clone());
+            }
+            let cleaned = clean_name(name);
+            if cleaned.is_empty() || matches(&cleaned) {
+                return Some(FALLBACK_NAME.to_string());
+            }
+            Some(cleaned)
+        }
+        NameCheck::Prefix { ref prefix } => {
+            if name.starts_with(prefix.as_str()) {
+                return None;
+            }
+            Some(format!("{prefix}{name}"))
+        }
+        NameCheck::Readable => {
+            let cleaned = clean_name(name);
+            if cleaned == name {
+                return None;
+            }
+            if cleaned.is_empty() {
+                return Some(FALLBACK_NAME.to_string());
+            }
+            Some(cleaned)
+        }
+    }
+}
+
+fn check_name(check: &NameCheck) -> &'static str {
+    match check {
+        NameCheck::Blocklist { .. } => "blocklist",
+        NameCheck::Prefix { .. } => "prefix",
+        NameCheck::Readable => "readable",
+    }
+}
+
+/// Truncates a name to the maximum length of a nickname
+fn truncate(name: &str) -> String {
+    name.chars().take(MAX_NICKNAME_LENGTH).collect()
+}
+
+/// Renames a member whose name violated the policy
+async fn rename(dispatch: &WorkerDispatch, guild_id: GuildId, user_id: UserId, nick: &str) -> Result<(), crate::Error> {
+    dispatch.worker_state.reqwest.patch(format!("{}/api/v10/guilds/{guild_id}/members/{user_id}", CONFIG.proxy))
+        .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+        .header("X-Audit-Log-Reason", RENAME_REASON)
+        .json(&json!({ "nick": nick }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Posts a violation to the notify channel of the policy, if any violated rule has the `notify` action
+async fn notify(dispatch: &WorkerDispatch, policy: &NamePolicy, violation: &NamePolicyViolation) -> Result<(), crate::Error> {
+    let Some(channel_id) = policy.notify_channel_id else {
+        return Ok(());
+    };
+    if !violation.rules.iter().any(|r| r.actions.contains(&NameAction::Notify)) {
+        return Ok(());
+    }
+
+    let rules = violation.rules.iter().map(|r| r.rule.as_str()).collect::<Vec<_>>().join(", ");
+    let mut content = format!("**Name policy violation**: <@{}> ({}) violates {rules}", violation.user_id, violation.name);
+    if let Some(ref renamed_to) = violation.renamed_to {
+        content.push_str(&format!(", renamed to {renamed_to}"));
+    }
+    dispatch.worker_state.reqwest.post(format!("{}/api/v10/channels/{channel_id}/messages", CONFIG.proxy))
+        .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+        .json(&json!({ "content": content, "allowed_mentions": { "parse": [] } }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
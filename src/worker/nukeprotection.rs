@@ -0,0 +1,231 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, RoleId, UserId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::CONFIG;
+use crate::geese::nukeprotection::{DestructiveAction, NukeProtectionConfig};
+use crate::worker::stickyroles::DANGEROUS_PERMISSIONS;
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Event dispatched to a guild when a user trips a threshold of its nuke protection
+pub const NUKE_ATTEMPT_EVENT: &str = "NukeAttemptDetected";
+
+/// Internal event locking down a guild after a nuke attempt, handled by the builtins (see `auxutils/nukelockdown.luau`)
+pub const NUKE_LOCKDOWN_EVENT: &str = "$NukeLockdown";
+
+/// Maximum number of users whose destructive actions are tracked, all are forgotten once exceeded
+const MAX_TRACKED_ACTORS: usize = 10_000;
+
+/// Audit log reason of role removals and lockdowns
+const FREEZE_REASON: &str = "Nuke protection";
+
+/// A destructive action taken by a user, as derived from its audit log entry
+#[derive(Debug, Clone, Serialize)]
+pub struct DestructiveEntry {
+    pub action: DestructiveAction,
+    /// ID of the audit log entry
+    pub entry_id: String,
+    /// The channel, role, member or webhook acted upon
+    pub target_id: Option<String>,
+    pub reason: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// Data of `NukeAttemptDetected`
+#[derive(Debug, Clone, Serialize)]
+pub struct NukeAttempt {
+    pub user_id: UserId,
+    /// The actions whose thresholds were tripped
+    pub tripped: Vec<DestructiveAction>,
+    pub window_secs: u32,
+    /// The destructive actions of the user within the window
+    pub entries: Vec<DestructiveEntry>,
+    /// Roles with dangerous permissions removed from the user
+    pub stripped_roles: Vec<RoleId>,
+    /// Roles with dangerous permissions which could not be removed (managed or not below the top role of the bot)
+    pub unstrippable_roles: Vec<RoleId>,
+    /// Whether the guild was locked down
+    pub locked_down: bool,
+    /// Errors responding to the attempt
+    pub errors: Vec<String>,
+}
+
+impl IntoLua for NukeAttempt {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Data of `$NukeLockdown`
+#[derive(Serialize)]
+struct NukeLockdown {
+    user_id: UserId,
+    reason: String,
+}
+
+impl IntoLua for NukeLockdown {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Detects users rapidly taking destructive actions (deleting channels or roles, banning or kicking members, creating
+/// webhooks) from the audit log of a guild
+///
+/// Actions are counted per user within the window of the guild's config. Once a threshold is tripped, the counts of
+/// the user are reset and the roles with dangerous permissions of the user are removed (and the guild locked down,
+/// if configured) by the guild's worker before `NukeAttemptDetected` is dispatched with the evidence
+#[derive(Clone, Default)]
+pub struct NukeProtection {
+    actions: Rc<RefCell<HashMap<(GuildId, UserId), VecDeque<DestructiveEntry>>>>,
+}
+
+impl NukeProtection {
+    /// Counts the action of a GUILD_AUDIT_LOG_ENTRY_CREATE payload if destructive and the guild has nuke protection,
+    /// responding in the background if a threshold is tripped
+    pub fn observe(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.nuke_protection(Id::Guild(guild_id)) else {
+            return;
+        };
+        let Some(action) = payload.get("action_type").and_then(|v| v.as_u64()).and_then(DestructiveAction::from_audit_log_action) else {
+            return;
+        };
+        let Some(user_id) = payload.get("user_id").and_then(|v| v.as_str()).and_then(|v| v.parse::<UserId>().ok()) else {
+            return;
+        };
+        if user_id == dispatch.worker_state.stratum.current_user().id || config.exempt_users.iter().any(|u| *u == user_id.to_string()) {
+            return;
+        }
+
+        let now = Utc::now();
+        let entry = DestructiveEntry {
+            action,
+            entry_id: payload.get("id").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            target_id: payload.get("target_id").and_then(|v| v.as_str()).map(|t| t.to_string()),
+            reason: payload.get("reason").and_then(|v| v.as_str()).map(|r| r.to_string()),
+            at: now,
+        };
+
+        let (tripped, entries) = {
+            let mut actions = self.actions.borrow_mut();
+            if actions.len() >= MAX_TRACKED_ACTORS && !actions.contains_key(&(guild_id, user_id)) {
+                actions.clear();
+            }
+            let entries = actions.entry((guild_id, user_id)).or_default();
+            entries.push_back(entry);
+            while entries.front().is_some_and(|e| now - e.at > config.window()) {
+                entries.pop_front();
+            }
+
+            let mut tripped = Vec::new();
+            for e in entries.iter() {
+                if tripped.contains(&e.action) {
+                    continue;
+                }
+                let count = entries.iter().filter(|o| o.action == e.action).count();
+                if config.thresholds.get(e.action).is_some_and(|t| count >= t as usize) {
+                    tripped.push(e.action);
+                }
+            }
+            if tripped.is_empty() {
+                return;
+            }
+            (tripped, actions.remove(&(guild_id, user_id)).map(Vec::from).unwrap_or_default())
+        };
+
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = Self::respond(&dispatch, guild_id, user_id, &config, tripped, entries).await {
+                log::error!("Failed to respond to nuke attempt by {user_id} in guild {guild_id}: {e}");
+            }
+        });
+    }
+
+    async fn respond(dispatch: &WorkerDispatch, guild_id: GuildId, user_id: UserId, config: &NukeProtectionConfig, tripped: Vec<DestructiveAction>, entries: Vec<DestructiveEntry>) -> Result<(), crate::Error> {
+        let mut attempt = NukeAttempt {
+            user_id,
+            tripped,
+            window_secs: config.window_secs,
+            entries,
+            stripped_roles: Vec::new(),
+            unstrippable_roles: Vec::new(),
+            locked_down: false,
+            errors: Vec::new(),
+        };
+
+        if config.strip_roles && let Err(e) = Self::strip_roles(dispatch, guild_id, &mut attempt).await {
+            attempt.errors.push(format!("Failed to remove roles: {e}"));
+        }
+        if config.lockdown {
+            let lockdown = NukeLockdown { user_id, reason: format!("{FREEZE_REASON}: nuke attempt by {user_id}") };
+            match dispatch.dispatch_event_complex(Id::Guild(guild_id), NUKE_LOCKDOWN_EVENT, None, lockdown).await {
+                Ok(_) => attempt.locked_down = true,
+                Err(e) => attempt.errors.push(format!("Failed to lock down: {e}")),
+            }
+        }
+
+        dispatch.dispatch_event_complex(Id::Guild(guild_id), NUKE_ATTEMPT_EVENT, None, attempt).await?;
+        Ok(())
+    }
+
+    /// Removes the roles with dangerous permissions of the user of an attempt, one by one so a single role failing
+    /// does not keep the others
+    async fn strip_roles(dispatch: &WorkerDispatch, guild_id: GuildId, attempt: &mut NukeAttempt) -> Result<(), crate::Error> {
+        let user_id = attempt.user_id;
+        let stratum = &dispatch.worker_state.stratum;
+        let guild_roles = stratum.guild_roles(guild_id).await?.ok_or("Guild roles not found")?;
+        let guild_roles = guild_roles.as_array().ok_or("Guild roles are not an array")?;
+        let role = |id: &str| guild_roles.iter().find(|r| r.get("id").and_then(|v| v.as_str()) == Some(id));
+        let position = |r: &Value| r.get("position").and_then(|v| v.as_i64()).unwrap_or_default();
+        let member_roles = |member: &Value| -> Vec<String> {
+            member.get("roles").and_then(|v| v.as_array())
+                .map(|roles| roles.iter().filter_map(|r| r.as_str()).map(|r| r.to_string()).collect())
+                .unwrap_or_default()
+        };
+
+        let bot = stratum.guild_member(guild_id, stratum.current_user().id).await?.ok_or("Bot member not found")?;
+        let top_position = member_roles(&bot).iter()
+            .filter_map(|id| role(id).map(position))
+            .max()
+            .unwrap_or_default();
+        let member = stratum.guild_member(guild_id, user_id).await?.ok_or("Member not found")?;
+
+        for role_id in member_roles(&member) {
+            let Some(r) = role(&role_id) else {
+                continue;
+            };
+            let permissions = r.get("permissions").and_then(|v| v.as_str()).and_then(|v| v.parse::<u64>().ok()).unwrap_or_default();
+            if permissions & DANGEROUS_PERMISSIONS == 0 {
+                continue;
+            }
+            let role_id: RoleId = role_id.parse()?;
+            if r.get("managed").and_then(|v| v.as_bool()).unwrap_or(false) || position(r) >= top_position {
+                attempt.unstrippable_roles.push(role_id);
+                continue;
+            }
+
+            let res = dispatch.worker_state.reqwest.delete(format!("{}/api/v10/guilds/{guild_id}/members/{user_id}/roles/{role_id}", CONFIG.proxy))
+                .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+                .header("X-Audit-Log-Reason", FREEZE_REASON)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            match res {
+                Ok(_) => attempt.stripped_roles.push(role_id),
+                Err(e) => {
+                    attempt.unstrippable_roles.push(role_id);
+                    attempt.errors.push(format!("Failed to remove role {role_id}: {e}"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
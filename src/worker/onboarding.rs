@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use dapi::GuildId;
+use khronos_runtime::rt::mlua::prelude::*;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde_json::Value;
+
+use crate::CONFIG;
+use crate::config::OnboardingConfig;
+use crate::geese::state::{StateDbFlags, StateExecResult, StateOp};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Key-value scope onboarding progress is saved to
+pub const ONBOARDING_SCOPE: &str = "#onboarding";
+
+/// Key the next onboarding step to run is saved under
+const PROGRESS_KEY: &str = "progress";
+
+/// Internal event running a step of onboarding, only dispatched to the builtins
+pub const ONBOARDING_STEP_EVENT: &str = "$OnboardingStep";
+
+/// Event dispatched once a guild has been onboarded
+pub const ONBOARDING_COMPLETED_EVENT: &str = "OnboardingCompleted";
+
+/// A step of onboarding a guild, steps are run in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnboardingStep {
+    /// Creates the tenant state of the guild, subscribing the builtins to startup events
+    Settings,
+    /// Installs the starter templates of the builtins (if enabled)
+    Templates,
+    /// Posts the setup message to the guild's system channel (if enabled)
+    SetupMessage,
+    /// Dispatches `OnboardingCompleted`
+    Notify,
+    /// The guild has been onboarded
+    Completed,
+}
+
+impl OnboardingStep {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Settings => "settings",
+            Self::Templates => "templates",
+            Self::SetupMessage => "setupmessage",
+            Self::Notify => "notify",
+            Self::Completed => "completed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Settings, Self::Templates, Self::SetupMessage, Self::Notify, Self::Completed]
+            .into_iter()
+            .find(|s| s.name() == name)
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Self::Settings => Self::Templates,
+            Self::Templates => Self::SetupMessage,
+            Self::SetupMessage => Self::Notify,
+            Self::Notify | Self::Completed => Self::Completed,
+        }
+    }
+}
+
+/// Onboards guilds on their first GUILD_CREATE
+///
+/// The next step to run is saved to key-value API once a step finishes, so onboarding resumes where it left off if
+/// the worker restarts mid-onboarding (with the interrupted step being run again)
+#[derive(Clone, Default)]
+pub struct Onboarding {
+    /// Guilds onboarded or being onboarded by this worker
+    ///
+    /// GUILD_CREATE is sent for every guild on each reconnect, this keeps the progress lookup to once per guild
+    seen: Rc<RefCell<HashSet<GuildId>>>,
+}
+
+impl Onboarding {
+    /// Onboards a guild in the background given its GUILD_CREATE payload, if onboarding is enabled and the
+    /// guild has not been onboarded yet
+    pub fn start(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(cfg) = CONFIG.onboarding.as_ref() else {
+            return;
+        };
+        if payload.get("unavailable").and_then(|v| v.as_bool()).unwrap_or(false) {
+            return; // Outage, the guild is sent again once available
+        }
+        if !self.seen.borrow_mut().insert(guild_id) {
+            return;
+        }
+
+        let system_channel_id = payload.get("system_channel_id").and_then(|v| v.as_str()).map(|s| s.to_string());
+        let (self_ref, dispatch) = (self.clone(), dispatch.clone());
+        tokio::task::spawn_local(async move {
+            if let Err(e) = Self::run(&dispatch, guild_id, cfg, system_channel_id).await {
+                log::error!("Failed to onboard guild {guild_id}: {e}");
+                // Retry on the next GUILD_CREATE
+                self_ref.seen.borrow_mut().remove(&guild_id);
+            }
+        });
+    }
+
+    async fn run(dispatch: &WorkerDispatch, guild_id: GuildId, cfg: &OnboardingConfig, system_channel_id: Option<String>) -> Result<(), crate::Error> {
+        let id = Id::Guild(guild_id);
+        let mut step = Self::progress(dispatch, id).await?;
+        if step != OnboardingStep::Settings && step != OnboardingStep::Completed {
+            log::info!("Resuming onboarding of guild {guild_id} at step {}", step.name());
+        }
+
+        while step != OnboardingStep::Completed {
+            let mut ops = Vec::new();
+            match step {
+                OnboardingStep::Settings => {
+                    // Also creates the tenant state of the guild
+                    ops.push(StateOp::SubscribeEvent { event: "OnStartup".to_string(), system: "builtins".to_string() });
+                }
+                OnboardingStep::Templates if cfg.starter_templates => {
+                    Self::run_step(dispatch, id, step, system_channel_id.clone()).await?;
+                }
+                OnboardingStep::SetupMessage if cfg.setup_message && system_channel_id.is_some() => {
+                    Self::run_step(dispatch, id, step, system_channel_id.clone()).await?;
+                }
+                OnboardingStep::Notify => {
+                    let data = OnboardingCompletedData { guild_id, starter_templates: cfg.starter_templates };
+                    dispatch.dispatch_event_complex(id, ONBOARDING_COMPLETED_EVENT, None, data).await
+                        .map_err(|e| format!("Failed to dispatch {ONBOARDING_COMPLETED_EVENT}: {e}"))?;
+                }
+                _ => {}
+            }
+
+            step = step.next();
+            ops.push(StateOp::KvSet {
+                key: PROGRESS_KEY.to_string(),
+                scope: ONBOARDING_SCOPE.to_string(),
+                value: KhronosValue::Text(step.name().into()),
+                blob: None,
+            });
+            let res = dispatch.worker_state.mesophyll_client.exec_state_op(id, ops, StateDbFlags::WORKER_INITIATED).await?;
+            if let Some(ref ts) = res.new_tenant_state {
+                dispatch.tenant_state.reload_for_tenant(id, ts)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the next onboarding step to run for a tenant
+    async fn progress(dispatch: &WorkerDispatch, id: Id) -> Result<OnboardingStep, crate::Error> {
+        let ops = vec![StateOp::KvGet { key: PROGRESS_KEY.to_string(), scope: ONBOARDING_SCOPE.to_string() }];
+        let res = dispatch.worker_state.mesophyll_client.exec_state_op(id, ops, StateDbFlags::WORKER_INITIATED).await?;
+        match res.results.into_iter().next() {
+            Some(StateExecResult::Kv { l }) => match l.value {
+                KhronosValue::Text(name) => OnboardingStep::from_name(name.as_ref())
+                    .ok_or_else(|| format!("Unknown onboarding step: {name}").into()),
+                _ => Err("Onboarding progress is not a string".into()),
+            },
+            _ => Ok(OnboardingStep::Settings),
+        }
+    }
+
+    /// Runs a step of onboarding in the builtins
+    async fn run_step(dispatch: &WorkerDispatch, id: Id, step: OnboardingStep, channel_id: Option<String>) -> Result<(), crate::Error> {
+        dispatch.dispatch_event_complex(id, ONBOARDING_STEP_EVENT, None, OnboardingStepData { step, channel_id }).await
+            .map_err(|e| format!("Onboarding step {} failed: {e}", step.name()))?;
+        Ok(())
+    }
+}
+
+struct OnboardingStepData {
+    step: OnboardingStep,
+    /// The guild's system channel, if any
+    channel_id: Option<String>,
+}
+
+impl IntoLua for OnboardingStepData {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table_with_capacity(0, 2)?;
+        tab.set("step", self.step.name())?;
+        tab.set("channel_id", self.channel_id)?;
+        tab.set_readonly(true);
+        Ok(LuaValue::Table(tab))
+    }
+}
+
+struct OnboardingCompletedData {
+    guild_id: GuildId,
+    starter_templates: bool,
+}
+
+impl IntoLua for OnboardingCompletedData {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let tab = lua.create_table_with_capacity(0, 2)?;
+        tab.set("guild_id", self.guild_id.to_string())?;
+        tab.set("starter_templates", self.starter_templates)?;
+        tab.set_readonly(true);
+        Ok(LuaValue::Table(tab))
+    }
+}
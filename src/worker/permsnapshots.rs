@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use dapi::GuildId;
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::geese::permsnapshots::{ChannelPermissions, Overwrite, PermissionChange, PermissionSnapshot, PermissionSnapshotReq, PermissionSnapshotResp, PermissionSnapshotTrigger, RolePermissions};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Internal event dispatched to a guild by the master when its permissions are due to be snapshotted (or a snapshot
+/// was requested through the API)
+pub const PERMISSION_SNAPSHOT_EVENT: &str = "$PermissionSnapshot";
+
+/// Event dispatched to a guild when its permissions changed since the last snapshot
+pub const PERMISSION_DRIFT_EVENT: &str = "PermissionDrift";
+
+/// Data of `PermissionDrift`, also returned by `$PermissionSnapshot`
+#[derive(Debug, Clone, Serialize)]
+pub struct PermissionDrift {
+    /// When the snapshot diffed against was taken, unset for the first snapshot
+    pub since: Option<DateTime<Utc>>,
+    pub taken_at: DateTime<Utc>,
+    pub manual: bool,
+    pub changes: Vec<PermissionChange>,
+}
+
+impl IntoLua for PermissionDrift {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Snapshots the channel overwrites and role permissions of a guild, diffing them with its last snapshot
+///
+/// Only the last snapshot of a guild is kept (by the master), so drift is always relative to the previous snapshot.
+/// Changes made by lockdowns are drift like any other
+pub struct PermissionSnapshots<'a> {
+    dispatch: &'a WorkerDispatch,
+}
+
+impl<'a> PermissionSnapshots<'a> {
+    pub fn new(dispatch: &'a WorkerDispatch) -> Self {
+        Self { dispatch }
+    }
+
+    /// Handles a `$PermissionSnapshot` for a guild, dispatching `PermissionDrift` if anything changed and returning
+    /// the drift
+    pub async fn take(&self, guild_id: GuildId, payload: &Value) -> Result<KhronosValue, crate::Error> {
+        let trigger: PermissionSnapshotTrigger = serde_json::from_value(payload.clone())?;
+        if !self.dispatch.tenant_state.permission_snapshots(Id::Guild(guild_id)) {
+            return Err("Permission snapshots are not enabled for this guild".into());
+        }
+
+        let snapshot = self.capture(guild_id).await?;
+        let taken_at = snapshot.taken_at;
+        let req = PermissionSnapshotReq::Swap { snapshot: snapshot.clone() };
+        let PermissionSnapshotResp::Previous { snapshot: previous } = self.dispatch.worker_state.mesophyll_client.permission_snapshots(&req).await?;
+
+        let drift = PermissionDrift {
+            since: previous.as_ref().map(|p| p.taken_at),
+            taken_at,
+            manual: trigger.manual,
+            changes: previous.map(|p| p.diff(&snapshot)).unwrap_or_default(),
+        };
+        if !drift.changes.is_empty() {
+            self.dispatch.dispatch_event_complex(Id::Guild(guild_id), PERMISSION_DRIFT_EVENT, None, drift.clone()).await?;
+        }
+        Ok(serde_json::from_value(serde_json::to_value(&drift)?)?)
+    }
+
+    async fn capture(&self, guild_id: GuildId) -> Result<PermissionSnapshot, crate::Error> {
+        let stratum = &self.dispatch.worker_state.stratum;
+        let channels = stratum.guild_channels(guild_id).await?.ok_or("Guild channels not found")?;
+        let roles = stratum.guild_roles(guild_id).await?.ok_or("Guild roles not found")?;
+
+        let channels = channels.as_array().ok_or("Guild channels are not an array")?.iter()
+            .filter_map(|c| {
+                let overwrites = c.get("permission_overwrites").and_then(|v| v.as_array())?.iter()
+                    .filter_map(|o| {
+                        let overwrite = Overwrite {
+                            kind: o.get("type")?.as_u64()? as u8,
+                            allow: o.get("allow")?.as_str()?.to_string(),
+                            deny: o.get("deny")?.as_str()?.to_string(),
+                        };
+                        Some((o.get("id")?.as_str()?.to_string(), overwrite))
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                let name = c.get("name").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+                Some((c.get("id")?.as_str()?.to_string(), ChannelPermissions { name, overwrites }))
+            })
+            .collect();
+        let roles = roles.as_array().ok_or("Guild roles are not an array")?.iter()
+            .filter_map(|r| {
+                let role = RolePermissions {
+                    name: r.get("name").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+                    permissions: r.get("permissions")?.as_str()?.to_string(),
+                };
+                Some((r.get("id")?.as_str()?.to_string(), role))
+            })
+            .collect();
+
+        Ok(PermissionSnapshot { guild_id, channels, roles, taken_at: Utc::now() })
+    }
+}
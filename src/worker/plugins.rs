@@ -0,0 +1,178 @@
+use std::sync::OnceLock;
+
+use khronos_runtime::rt::mlua::prelude::*;
+use parking_lot::Mutex;
+
+use crate::CONFIG;
+use crate::worker::workervmmanager::Id;
+
+/// Name of the VM global the `@antiraid/plugins` module (giving access to registered plugins) is exposed as
+pub const REGISTERED_PLUGINS_GLOBAL: &str = "__antiraid_plugins";
+
+/// Maximum length of the name of a registered plugin
+const MAX_PLUGIN_NAME_LENGTH: usize = 64;
+
+/// Vendors reserved for the plugins and modules of AntiRaid itself
+const RESERVED_VENDORS: &[&str] = &["antiraid", "std", "self", "lune"];
+
+/// Creates the module of a registered plugin for the VM of a tenant
+pub type ModuleFn = fn(&Lua, Id) -> LuaResult<LuaValue>;
+
+struct RegisteredPlugin {
+    name: String,
+    create: ModuleFn,
+}
+
+/// Plugins registered with `register`, moved to `REGISTERED` once the first VM is created
+static PENDING: Mutex<Vec<RegisteredPlugin>> = Mutex::new(Vec::new());
+static REGISTERED: OnceLock<Vec<RegisteredPlugin>> = OnceLock::new();
+
+/// Plugins (the `@antiraid/*` modules the worker exposes as VM globals) which can be disabled, by name
+pub const PLUGINS: &[&str] = &[
+    "random",
+    "crypto",
+    "validate",
+    "rules",
+    "fixtures",
+    "snowflake",
+    "int64",
+    "stringutils",
+    "fuzzy",
+    "tableutils",
+    "markdown",
+    "serde",
+    "scratch",
+    "wasm",
+];
+
+/// Registers an additional plugin, for downstream builds (or optional cargo features) to extend templates with
+/// without patching the worker
+///
+/// `name` must be namespaced as `@vendor/name` (lowercase letters, digits, `-` and `_`), and the `antiraid`, `std`,
+/// `self` and `lune` vendors are reserved. Templates get the module through ``require("@antiraid/plugins").get(name)``,
+/// and it can be disabled like any other plugin (by its full name).
+///
+/// Plugins must be registered on startup of both the master and worker processes, before any VM is created. Registering
+/// afterwards errors
+pub fn register(name: &str, create: ModuleFn) -> Result<(), crate::Error> {
+    validate_name(name)?;
+
+    let mut pending = PENDING.lock();
+    if REGISTERED.get().is_some() {
+        return Err(format!("Plugin {name} must be registered before any VM is created").into());
+    }
+    if pending.iter().any(|p| p.name == name) {
+        return Err(format!("Plugin {name} is already registered").into());
+    }
+    pending.push(RegisteredPlugin { name: name.to_string(), create });
+    Ok(())
+}
+
+/// Returns the registered plugins, no more can be registered once called
+fn registered() -> &'static [RegisteredPlugin] {
+    REGISTERED.get_or_init(|| std::mem::take(&mut *PENDING.lock()))
+}
+
+/// Returns whether a plugin exists, built in (such as `crypto`) or registered (such as `@vendor/name`)
+pub fn is_known_plugin(name: &str) -> bool {
+    PLUGINS.contains(&name) || registered().iter().any(|p| p.name == name)
+}
+
+fn validate_name(name: &str) -> Result<(), crate::Error> {
+    if name.len() > MAX_PLUGIN_NAME_LENGTH {
+        return Err(format!("Plugin names must be at most {MAX_PLUGIN_NAME_LENGTH} bytes").into());
+    }
+    let Some((vendor, module)) = name.strip_prefix('@').and_then(|n| n.split_once('/')) else {
+        return Err(format!("Plugin {name} must be namespaced as @vendor/name").into());
+    };
+    let valid = |part: &str| {
+        part.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && part.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+    };
+    if !valid(vendor) || !valid(module) {
+        return Err(format!("Plugin {name} must only contain lowercase letters, digits, - and _").into());
+    }
+    if RESERVED_VENDORS.iter().any(|r| vendor == *r || vendor.starts_with(&format!("{r}-"))) {
+        return Err(format!("Vendor @{vendor} of plugin {name} is reserved").into());
+    }
+    Ok(())
+}
+
+/// Creates the `@antiraid/plugins` module, with the registered plugins enabled for the tenant
+pub fn create_module(lua: &Lua, id: Id, disabled_plugins: &[String]) -> LuaResult<LuaTable> {
+    let modules = lua.create_table()?;
+    for plugin in registered() {
+        if is_disabled(&plugin.name, disabled_plugins) {
+            modules.set(plugin.name.as_str(), DisabledPlugin(plugin.name.clone()))?;
+        } else {
+            modules.set(plugin.name.as_str(), (plugin.create)(lua, id)?)?;
+        }
+    }
+
+    let module = lua.create_table()?;
+    module.set("get", lua.create_function(move |_, name: String| {
+        match modules.raw_get::<LuaValue>(name.as_str())? {
+            LuaValue::Nil => Err(LuaError::runtime(format!("No plugin {name} is registered"))),
+            value => Ok(value),
+        }
+    })?)?;
+    module.set("list", lua.create_function(|_, ()| {
+        Ok(registered().iter().map(|p| p.name.clone()).collect::<Vec<_>>())
+    })?)?;
+    module.set_readonly(true);
+    Ok(module)
+}
+
+/// Returns whether a plugin is disabled for a tenant, by the config of the deployment or the settings of the tenant
+pub fn is_disabled(name: &str, disabled_plugins: &[String]) -> bool {
+    !CONFIG.plugins.allows(name) || disabled_plugins.iter().any(|p| p == name)
+}
+
+/// Sets the global of a plugin to its module, or to a stub erroring on any use if the plugin is disabled
+///
+/// The module is only created if the plugin is enabled
+pub fn set_plugin<T: IntoLua>(
+    gtab: &LuaTable,
+    disabled_plugins: &[String],
+    name: &str,
+    global: &str,
+    create: impl FnOnce() -> LuaResult<T>,
+) -> LuaResult<()> {
+    if is_disabled(name, disabled_plugins) {
+        gtab.set(global, DisabledPlugin(name.to_string()))
+    } else {
+        gtab.set(global, create()?)
+    }
+}
+
+/// Stands in for the module of a disabled plugin, erroring with "plugin disabled by administrator" on any use
+struct DisabledPlugin(String);
+
+impl DisabledPlugin {
+    fn error(&self) -> LuaError {
+        LuaError::runtime(format!("Plugin {} is disabled by administrator", display_name(&self.0)))
+    }
+}
+
+impl LuaUserData for DisabledPlugin {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_meta_method(LuaMetaMethod::Index, |_, this, _: LuaValue| -> LuaResult<()> {
+            Err(this.error())
+        });
+        methods.add_meta_method(LuaMetaMethod::NewIndex, |_, this, _: LuaMultiValue| -> LuaResult<()> {
+            Err(this.error())
+        });
+        methods.add_meta_method(LuaMetaMethod::Call, |_, this, _: LuaMultiValue| -> LuaResult<()> {
+            Err(this.error())
+        });
+    }
+}
+
+/// Returns the name a plugin is required by, `@antiraid/crypto` for the built in `crypto`
+fn display_name(name: &str) -> String {
+    if name.starts_with('@') {
+        name.to_string()
+    } else {
+        format!("@antiraid/{name}")
+    }
+}
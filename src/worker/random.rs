@@ -0,0 +1,201 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use khronos_runtime::rt::mlua::prelude::*;
+use rand::{RngCore, SeedableRng, rngs::StdRng};
+
+use crate::worker::limits::MAX_RANDOM_BYTES;
+use crate::worker::replay::ReplayState;
+
+/// Name of the VM global the `@antiraid/random` module is exposed as
+pub const RANDOM_GLOBAL: &str = "__antiraid_random";
+
+/// Largest integer which can be represented exactly by a Luau number
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+enum Source {
+    /// Cryptographically secure randomness, recorded/replayed along with the other inputs of an execution
+    Secure(Rc<ReplayState>),
+    /// A seeded PRNG, deterministic for a given seed
+    Seeded(RefCell<StdRng>),
+}
+
+/// A random number generator exposed to Luau
+pub struct Generator {
+    source: Source,
+}
+
+impl Generator {
+    fn secure(replay: Rc<ReplayState>) -> Self {
+        Self { source: Source::Secure(replay) }
+    }
+
+    fn seeded(seed: u64) -> Self {
+        Self { source: Source::Seeded(RefCell::new(StdRng::seed_from_u64(seed))) }
+    }
+
+    fn fill(&self, buf: &mut [u8]) -> LuaResult<()> {
+        match &self.source {
+            Source::Secure(replay) => replay.entropy(buf, |buf| rand::rng().fill_bytes(buf)),
+            Source::Seeded(rng) => {
+                rng.borrow_mut().fill_bytes(buf);
+                Ok(())
+            }
+        }
+    }
+
+    fn next_u64(&self) -> LuaResult<u64> {
+        let mut buf = [0u8; 8];
+        self.fill(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Returns a uniformly distributed integer in `[min, max]`
+    fn int(&self, min: i64, max: i64) -> LuaResult<i64> {
+        if min > max {
+            return Err(LuaError::external("min must be less than or equal to max"));
+        }
+        if min < -MAX_SAFE_INTEGER || max > MAX_SAFE_INTEGER {
+            return Err(LuaError::external("min and max must be safe integers"));
+        }
+
+        // Rejection sampling to avoid modulo bias
+        let span = (max - min) as u64 + 1;
+        let zone = u64::MAX - (u64::MAX - span + 1) % span;
+        loop {
+            let v = self.next_u64()?;
+            if v <= zone {
+                return Ok(min + (v % span) as i64);
+            }
+        }
+    }
+
+    /// Returns a uniformly distributed float in `[0, 1)`
+    fn unit_float(&self) -> LuaResult<f64> {
+        Ok((self.next_u64()? >> 11) as f64 / (1u64 << 53) as f64)
+    }
+
+    fn float(&self, min: Option<f64>, max: Option<f64>) -> LuaResult<f64> {
+        let (min, max) = (min.unwrap_or(0.0), max.unwrap_or(1.0));
+        if !min.is_finite() || !max.is_finite() || min > max {
+            return Err(LuaError::external("min and max must be finite with min less than or equal to max"));
+        }
+        Ok(min + self.unit_float()? * (max - min))
+    }
+
+    fn bytes(&self, lua: &Lua, len: usize) -> LuaResult<LuaBuffer> {
+        if len > MAX_RANDOM_BYTES {
+            return Err(LuaError::external(format!("Cannot generate more than {MAX_RANDOM_BYTES} random bytes at once")));
+        }
+        let mut buf = vec![0u8; len];
+        self.fill(&mut buf)?;
+        lua.create_buffer(buf)
+    }
+
+    fn uuidv4(&self) -> LuaResult<String> {
+        let mut buf = [0u8; 16];
+        self.fill(&mut buf)?;
+        Ok(uuid::Builder::from_random_bytes(buf).into_uuid().to_string())
+    }
+
+    fn uuidv7(&self) -> LuaResult<String> {
+        let millis = match &self.source {
+            Source::Secure(replay) => replay.unix_millis()? as u64,
+            Source::Seeded(_) => chrono::Utc::now().timestamp_millis() as u64,
+        };
+        let mut buf = [0u8; 10];
+        self.fill(&mut buf)?;
+        Ok(uuid::Builder::from_unix_timestamp_millis(millis, &buf).into_uuid().to_string())
+    }
+
+    /// Picks a random element of `items`, weighted by `weights` if set
+    fn choice(&self, items: LuaTable, weights: Option<Vec<f64>>) -> LuaResult<LuaValue> {
+        let len = items.raw_len();
+        if len == 0 {
+            return Err(LuaError::external("Cannot choose from an empty table"));
+        }
+
+        let Some(weights) = weights else {
+            let i = self.int(1, len as i64)?;
+            return items.raw_get(i);
+        };
+
+        if weights.len() != len {
+            return Err(LuaError::external("weights must have the same length as items"));
+        }
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(LuaError::external("weights must be finite and non-negative"));
+        }
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 || !total.is_finite() {
+            return Err(LuaError::external("weights must sum to a positive finite number"));
+        }
+
+        let mut target = self.unit_float()? * total;
+        for (i, w) in weights.iter().enumerate() {
+            if *w > 0.0 && target < *w {
+                return items.raw_get(i + 1);
+            }
+            target -= w;
+        }
+
+        // Floating point error, fall back to the last item with a non-zero weight
+        let last = weights.iter().rposition(|w| *w > 0.0).unwrap_or(len - 1);
+        items.raw_get(last + 1)
+    }
+
+    /// Shuffles `items` in place (Fisher-Yates)
+    fn shuffle(&self, items: LuaTable) -> LuaResult<LuaTable> {
+        let len = items.raw_len() as i64;
+        for i in (2..=len).rev() {
+            let j = self.int(1, i)?;
+            if i != j {
+                let a: LuaValue = items.raw_get(i)?;
+                let b: LuaValue = items.raw_get(j)?;
+                items.raw_set(i, b)?;
+                items.raw_set(j, a)?;
+            }
+        }
+        Ok(items)
+    }
+}
+
+impl LuaUserData for Generator {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("int", |_, this, (min, max): (i64, i64)| this.int(min, max));
+        methods.add_method("float", |_, this, (min, max): (Option<f64>, Option<f64>)| this.float(min, max));
+        methods.add_method("bytes", |lua, this, len: usize| this.bytes(lua, len));
+        methods.add_method("uuidv4", |_, this, _: ()| this.uuidv4());
+        methods.add_method("uuidv7", |_, this, _: ()| this.uuidv7());
+        methods.add_method("choice", |_, this, (items, weights): (LuaTable, Option<Vec<f64>>)| this.choice(items, weights));
+        methods.add_method("shuffle", |_, this, items: LuaTable| this.shuffle(items));
+    }
+}
+
+/// Creates the `@antiraid/random` module of a VM
+///
+/// Module level functions use the secure generator, `seeded` creates deterministic generators
+pub fn create_module(lua: &Lua, replay: Rc<ReplayState>) -> LuaResult<LuaTable> {
+    let secure = Rc::new(Generator::secure(replay));
+    let module = lua.create_table()?;
+
+    let g = secure.clone();
+    module.raw_set("int", lua.create_function(move |_, (min, max): (i64, i64)| g.int(min, max))?)?;
+    let g = secure.clone();
+    module.raw_set("float", lua.create_function(move |_, (min, max): (Option<f64>, Option<f64>)| g.float(min, max))?)?;
+    let g = secure.clone();
+    module.raw_set("bytes", lua.create_function(move |lua, len: usize| g.bytes(lua, len))?)?;
+    let g = secure.clone();
+    module.raw_set("uuidv4", lua.create_function(move |_, _: ()| g.uuidv4())?)?;
+    let g = secure.clone();
+    module.raw_set("uuidv7", lua.create_function(move |_, _: ()| g.uuidv7())?)?;
+    let g = secure.clone();
+    module.raw_set("choice", lua.create_function(move |_, (items, weights): (LuaTable, Option<Vec<f64>>)| g.choice(items, weights))?)?;
+    let g = secure;
+    module.raw_set("shuffle", lua.create_function(move |_, items: LuaTable| g.shuffle(items))?)?;
+
+    module.raw_set("seeded", lua.create_function(|_, seed: i64| Ok(Generator::seeded(seed as u64)))?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
@@ -0,0 +1,280 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::worker::syscall::{SyscallArgs, SyscallRet};
+use crate::worker::workerdispatch::SimpleEvent;
+use crate::worker::workervmmanager::Id;
+
+/// Feature flag enabling recording of executions for a tenant
+pub const RECORD_FLAG: &str = "replay:record";
+
+/// Key-value scope recordings are saved to
+pub const REPLAY_SCOPE: &str = "#replay";
+
+/// Maximum number of recordings kept per tenant, the oldest are evicted once exceeded
+pub const MAX_RECORDINGS: usize = 100;
+
+/// How long recordings are kept for
+pub const MAX_RECORDING_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A syscall made during a recorded execution
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedSyscall {
+    /// Category of the syscall, used to detect divergence on replay
+    pub category: String,
+    /// The (msgpack encoded) syscall result or the error it failed with
+    pub ret: Result<Bytes, String>,
+}
+
+/// A recording of all non-deterministic inputs of an execution
+///
+/// Recorded inputs are the event payload, the results of all syscalls (Discord API responses, key-value reads etc.),
+/// the values returned by `os.time()`/`os.clock()` and the randomness drawn from `@antiraid/random`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub id: Id,
+    /// The dispatched event
+    pub event: SimpleEvent,
+    pub recorded_at: chrono::DateTime<chrono::Utc>,
+    /// Syscalls made in the order they were made
+    pub syscalls: Vec<RecordedSyscall>,
+    /// Clock readings in the order they were taken
+    pub clock: Vec<f64>,
+    /// Random bytes drawn from the secure generator of `@antiraid/random` in the order they were drawn
+    #[serde(default)]
+    pub entropy: Vec<Bytes>,
+    /// The error the execution failed with, if any
+    pub error: Option<String>,
+}
+
+impl Recording {
+    /// Returns when a recording was recorded at (in unix milliseconds) from its key, recordings are keyed by
+    /// `{recorded_at}-{random}`
+    pub fn recorded_at_millis(key: &str) -> i64 {
+        key.split_once('-').and_then(|(millis, _)| millis.parse().ok()).unwrap_or_default()
+    }
+}
+
+enum ReplayMode {
+    /// Inputs are neither recorded nor replayed
+    Live,
+    /// Inputs of an execution are being recorded
+    Recording {
+        execution: u64,
+        syscalls: Vec<RecordedSyscall>,
+        clock: Vec<f64>,
+        entropy: Vec<Bytes>,
+    },
+    /// A replay VM which is being set up, syscalls with side effects are rejected
+    Sealed,
+    /// Inputs are fed back from a recording to an execution
+    Replaying {
+        execution: u64,
+        syscalls: VecDeque<RecordedSyscall>,
+        clock: VecDeque<f64>,
+        entropy: VecDeque<Bytes>,
+    },
+}
+
+/// The record/replay state of a VM
+///
+/// Recorded (and replayed) executions are given an execution ID, which is passed to the event and tagged onto every
+/// syscall made through the context of the event (see `ExecutionContext` in `@antiraid-core/setup`), so only the
+/// syscalls of the execution itself are recorded. Clock readings and randomness can't be attributed to an execution,
+/// so those taken by executions running concurrently on the VM (such as background tasks) are recorded as well and
+/// replays of such executions may diverge
+pub struct ReplayState {
+    mode: RefCell<ReplayMode>,
+    next_execution: Cell<u64>,
+}
+
+impl ReplayState {
+    /// Creates the replay state of a normal VM
+    pub fn live() -> Rc<Self> {
+        Rc::new(Self { mode: RefCell::new(ReplayMode::Live), next_execution: Cell::new(1) })
+    }
+
+    /// Creates the replay state of a VM being set up for a replay
+    pub fn sealed() -> Rc<Self> {
+        Rc::new(Self { mode: RefCell::new(ReplayMode::Sealed), next_execution: Cell::new(1) })
+    }
+
+    fn new_execution(&self) -> u64 {
+        let execution = self.next_execution.get();
+        self.next_execution.set(execution + 1);
+        execution
+    }
+
+    /// Returns whether inputs are being recorded
+    pub fn is_recording(&self) -> bool {
+        matches!(&*self.mode.borrow(), ReplayMode::Recording { .. })
+    }
+
+    /// Starts recording inputs, returning the ID of the execution being recorded
+    pub fn start_recording(&self) -> u64 {
+        let execution = self.new_execution();
+        *self.mode.borrow_mut() = ReplayMode::Recording { execution, syscalls: Vec::new(), clock: Vec::new(), entropy: Vec::new() };
+        execution
+    }
+
+    /// Stops recording inputs, returning the recorded syscalls, clock readings and random bytes
+    pub fn finish_recording(&self) -> (Vec<RecordedSyscall>, Vec<f64>, Vec<Bytes>) {
+        match std::mem::replace(&mut *self.mode.borrow_mut(), ReplayMode::Live) {
+            ReplayMode::Recording { syscalls, clock, entropy, .. } => (syscalls, clock, entropy),
+            _ => (Vec::new(), Vec::new(), Vec::new()),
+        }
+    }
+
+    /// Starts feeding back the inputs of a recording, returning the ID of the execution being replayed
+    pub fn start_replay(&self, recording: &Recording) -> u64 {
+        let execution = self.new_execution();
+        *self.mode.borrow_mut() = ReplayMode::Replaying {
+            execution,
+            syscalls: recording.syscalls.iter().cloned().collect(),
+            clock: recording.clock.iter().copied().collect(),
+            entropy: recording.entropy.iter().cloned().collect(),
+        };
+        execution
+    }
+
+    /// Returns the number of recorded syscalls which were not replayed
+    pub fn remaining_syscalls(&self) -> usize {
+        match &*self.mode.borrow() {
+            ReplayMode::Replaying { syscalls, .. } => syscalls.len(),
+            _ => 0,
+        }
+    }
+
+    /// Intercepts a syscall made by the given execution (if known), returning its result if it should not be executed
+    pub fn intercept_syscall(&self, args: &SyscallArgs, execution: Option<u64>) -> Option<Result<SyscallRet, crate::Error>> {
+        match &mut *self.mode.borrow_mut() {
+            ReplayMode::Live | ReplayMode::Recording { .. } => None,
+            ReplayMode::Sealed => {
+                match args {
+                    SyscallArgs::State { ops } if ops.iter().all(|op| op.is_read_only()) => None,
+                    _ => Some(Err("Syscalls with side effects are disabled while setting up a replay".into())),
+                }
+            }
+            ReplayMode::Replaying { execution: replayed, .. } if execution != Some(*replayed) => {
+                // Not made by the replayed execution, so not recorded either
+                match args {
+                    SyscallArgs::State { ops } if ops.iter().all(|op| op.is_read_only()) => None,
+                    _ => Some(Err("Syscalls with side effects made outside of the replayed execution are disabled during a replay".into())),
+                }
+            }
+            ReplayMode::Replaying { syscalls, .. } => {
+                let category = args.category();
+                let Some(recorded) = syscalls.pop_front() else {
+                    return Some(Err(format!("Replay diverged: unexpected {category} syscall after all recorded syscalls were replayed").into()));
+                };
+
+                if recorded.category != category {
+                    return Some(Err(format!("Replay diverged: expected a {} syscall, got a {category} syscall", recorded.category).into()));
+                }
+
+                Some(match recorded.ret {
+                    Ok(ret) => rmp_serde::from_slice(&ret).map_err(|e| format!("Failed to decode recorded syscall: {e}").into()),
+                    Err(e) => Err(e.into()),
+                })
+            }
+        }
+    }
+
+    /// Records the result of a syscall made by the given execution (if it is being recorded)
+    pub fn record_syscall(&self, category: &str, execution: Option<u64>, res: &Result<SyscallRet, crate::Error>) {
+        let mut mode = self.mode.borrow_mut();
+        let ReplayMode::Recording { execution: recorded, syscalls, .. } = &mut *mode else {
+            return;
+        };
+        if execution != Some(*recorded) {
+            return;
+        }
+
+        let ret = match res {
+            Ok(ret) => rmp_serde::to_vec(ret).map(Bytes::from).map_err(|e| format!("Failed to encode syscall result: {e}")),
+            Err(e) => Err(e.to_string()),
+        };
+
+        syscalls.push(RecordedSyscall { category: category.to_string(), ret });
+    }
+
+    /// Returns a clock reading, recording or replaying it as needed
+    fn clock(&self, read: impl FnOnce() -> LuaResult<f64>) -> LuaResult<f64> {
+        match &mut *self.mode.borrow_mut() {
+            ReplayMode::Live | ReplayMode::Sealed => read(),
+            ReplayMode::Recording { clock, .. } => {
+                let value = read()?;
+                clock.push(value);
+                Ok(value)
+            }
+            ReplayMode::Replaying { clock, .. } => {
+                clock.pop_front().ok_or_else(|| LuaError::external("Replay diverged: clock read after all recorded clock readings were replayed"))
+            }
+        }
+    }
+
+    /// Returns the current unix timestamp in milliseconds, recording or replaying it as needed
+    pub fn unix_millis(&self) -> LuaResult<f64> {
+        self.clock(|| Ok(chrono::Utc::now().timestamp_millis() as f64))
+    }
+
+    /// Fills `buf` with random bytes from `fill`, recording or replaying them as needed
+    pub fn entropy(&self, buf: &mut [u8], fill: impl FnOnce(&mut [u8])) -> LuaResult<()> {
+        match &mut *self.mode.borrow_mut() {
+            ReplayMode::Live | ReplayMode::Sealed => fill(buf),
+            ReplayMode::Recording { entropy, .. } => {
+                fill(buf);
+                entropy.push(Bytes::copy_from_slice(buf));
+            }
+            ReplayMode::Replaying { entropy, .. } => {
+                let Some(recorded) = entropy.pop_front() else {
+                    return Err(LuaError::external("Replay diverged: randomness drawn after all recorded random bytes were replayed"));
+                };
+                if recorded.len() != buf.len() {
+                    return Err(LuaError::external(format!("Replay diverged: expected {} random bytes to be drawn, got {}", recorded.len(), buf.len())));
+                }
+                buf.copy_from_slice(&recorded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces `os.time()` and `os.clock()` in the given global table with versions which are recorded/replayed
+    pub fn install_clock(self: &Rc<Self>, lua: &Lua, global_table: &LuaTable) -> LuaResult<()> {
+        let Some(os) = global_table.get::<Option<LuaTable>>("os")? else {
+            return Ok(());
+        };
+
+        let new_os = lua.create_table()?;
+        for pair in os.pairs::<LuaValue, LuaValue>() {
+            let (k, v) = pair?;
+            new_os.raw_set(k, v)?;
+        }
+
+        let time: LuaFunction = os.get("time")?;
+        let this = self.clone();
+        new_os.raw_set("time", lua.create_function(move |_, args: LuaMultiValue| {
+            if !args.is_empty() {
+                // os.time(t) is deterministic
+                return time.call::<LuaValue>(args);
+            }
+            this.clock(|| time.call::<f64>(())).map(LuaValue::Number)
+        })?)?;
+
+        let clock: LuaFunction = os.get("clock")?;
+        let this = self.clone();
+        new_os.raw_set("clock", lua.create_function(move |_, _: ()| {
+            this.clock(|| clock.call::<f64>(()))
+        })?)?;
+
+        new_os.set_readonly(true);
+        global_table.set("os", new_os)?;
+        Ok(())
+    }
+}
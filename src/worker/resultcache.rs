@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use dapi::UserId;
+use khronos_runtime::utils::khronos_value::KhronosValue;
+use serde_json::Value;
+
+use crate::worker::workertenantstate::WorkerTenantState;
+use crate::worker::workervmmanager::Id;
+
+/// Field of event payloads naming the isolate the event is dispatched to
+const TEMPLATE_ID_FIELD: &str = "__tloop_template_id";
+
+/// Prefix of the isolate IDs of templates
+const TEMPLATE_ID_PREFIX: &str = "template/";
+
+/// Maximum number of results cached on a worker, all are forgotten once exceeded
+const MAX_CACHED_RESULTS: usize = 10_000;
+
+/// Identifies the result of an event dispatched to a template whose results are cached
+pub struct CacheKey {
+    id: Id,
+    template: String,
+    hash: u64,
+    ttl: Duration,
+    /// Generation of the cache when the key was made, results of executions started before an invalidation are
+    /// not cached
+    generation: u64,
+}
+
+struct Entry {
+    value: KhronosValue,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<(Id, String, u64), Entry>,
+    /// Templates which made syscalls with side effects since the VM of their tenant was created or its templates
+    /// were last invalidated
+    impure: HashSet<(Id, String)>,
+    generation: u64,
+}
+
+/// Caches the results of templates set up for result caching (see `ResultCacheConfig`), shared by all VMs of the
+/// VM manager
+///
+/// A template is bypassed as soon as any of its syscalls may have side effects, so only the results of executions
+/// of templates which have only ever queried are cached. Entries of a tenant are dropped whenever its templates (or
+/// their environments) change or its VM is recreated
+#[derive(Clone, Default)]
+pub struct ResultCache {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl ResultCache {
+    /// Returns the cache key of an event if it targets a template of the tenant whose results are cached and which
+    /// has not made any syscalls with side effects
+    pub fn key(&self, wts: &WorkerTenantState, id: Id, name: &str, author: Option<UserId>, payload: &Value) -> Option<CacheKey> {
+        let template = payload.get(TEMPLATE_ID_FIELD)?.as_str()?.strip_prefix(TEMPLATE_ID_PREFIX)?;
+        let ttl = wts.result_cache(id)?.ttl(template)?;
+
+        let inner = self.inner.borrow();
+        if inner.impure.contains(&(id, template.to_string())) {
+            return None;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        author.hash(&mut hasher);
+        payload.to_string().hash(&mut hasher);
+        Some(CacheKey {
+            id,
+            template: template.to_string(),
+            hash: hasher.finish(),
+            ttl: Duration::from_secs(ttl.into()),
+            generation: inner.generation,
+        })
+    }
+
+    /// Returns the cached result of an event, if not expired
+    pub fn get(&self, key: &CacheKey) -> Option<KhronosValue> {
+        let mut inner = self.inner.borrow_mut();
+        let map_key = (key.id, key.template.clone(), key.hash);
+        let entry = inner.entries.get(&map_key)?;
+        if entry.expires > Instant::now() {
+            return Some(entry.value.clone());
+        }
+        inner.entries.remove(&map_key);
+        None
+    }
+
+    /// Caches the result of an event, unless the template made a syscall with side effects or the tenant was
+    /// invalidated while it executed
+    pub fn insert(&self, key: CacheKey, value: KhronosValue) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.generation != key.generation || inner.impure.contains(&(key.id, key.template.clone())) {
+            return;
+        }
+        if inner.entries.len() >= MAX_CACHED_RESULTS {
+            inner.entries.clear();
+        }
+        inner.entries.insert((key.id, key.template, key.hash), Entry { value, expires: Instant::now() + key.ttl });
+    }
+
+    /// Bypasses the cache for a template, as it made a syscall with side effects
+    pub fn mark_impure(&self, id: Id, template: &str) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.impure.insert((id, template.to_string())) {
+            inner.entries.retain(|(entry_id, entry_template, _), _| *entry_id != id || entry_template != template);
+        }
+    }
+
+    /// Drops the cached results of a tenant and forgets which of its templates have side effects
+    pub fn invalidate(&self, id: Id) {
+        let mut inner = self.inner.borrow_mut();
+        inner.generation += 1;
+        inner.entries.retain(|(entry_id, _, _), _| *entry_id != id);
+        inner.impure.retain(|(impure_id, _)| *impure_id != id);
+    }
+}
@@ -0,0 +1,104 @@
+use std::borrow::Cow;
+
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+
+use crate::geese::rules::{self, FieldValue, Rule, RuleSet};
+
+/// Name of the VM global the `@antiraid/rules` module is exposed as
+pub const RULES_GLOBAL: &str = "__antiraid_rules";
+
+/// Maximum number of elements of a list field tested by a rule
+const MAX_LIST_ELEMENTS: usize = 1000;
+
+/// Returns the value of the input at a field path, nil if any part of the path is missing
+///
+/// Tables are read raw, so typed event accessors return nil for absent fields instead of erroring
+fn lookup(input: &LuaValue, path: &[String]) -> LuaResult<LuaValue> {
+    let mut current = input.clone();
+    for segment in path {
+        let LuaValue::Table(tab) = current else {
+            return Ok(LuaValue::Nil);
+        };
+        current = match segment.parse::<i64>() {
+            Ok(i) => tab.raw_get(i)?,
+            Err(_) => tab.raw_get(segment.as_str())?,
+        };
+    }
+    Ok(current)
+}
+
+/// Converts a Luau value into the value rules test, sequential tables become lists of their (non-table) elements
+fn field_value(value: LuaValue, nested: bool) -> LuaResult<FieldValue<'static>> {
+    Ok(match value {
+        LuaValue::Nil => FieldValue::Missing,
+        LuaValue::Boolean(b) => FieldValue::Bool(b),
+        LuaValue::Integer(i) => FieldValue::Number(i as f64),
+        LuaValue::Number(n) => FieldValue::Number(n),
+        LuaValue::String(s) => FieldValue::Text(Cow::Owned(s.to_string_lossy())),
+        LuaValue::Table(tab) if !nested && (tab.raw_len() > 0 || tab.is_empty()) => {
+            let len = tab.raw_len().min(MAX_LIST_ELEMENTS);
+            let mut items = Vec::with_capacity(len);
+            for i in 1..=len {
+                items.push(field_value(tab.raw_get(i)?, true)?);
+            }
+            FieldValue::List(items)
+        }
+        _ => FieldValue::Other,
+    })
+}
+
+/// A compiled rule set exposed to Luau
+struct CompiledRules(RuleSet);
+
+impl CompiledRules {
+    fn evaluate(&self, lua: &Lua, input: LuaValue, hooks: Option<LuaTable>) -> LuaResult<LuaTable> {
+        let matched = self.0.evaluate(
+            |path| {
+                let value = lookup(&input, path).map_err(|e| e.to_string())?;
+                field_value(value, false).map_err(|e| e.to_string().into())
+            },
+            |name, args| {
+                let hook = match &hooks {
+                    Some(hooks) => hooks.raw_get::<Option<LuaFunction>>(name).map_err(|e| e.to_string())?,
+                    None => None,
+                };
+                let hook = hook.ok_or_else(|| format!("condition hook {name} is not defined"))?;
+                let args = lua.to_value(args).map_err(|e| e.to_string())?;
+                hook.call::<bool>((input.clone(), args)).map_err(|e| format!("condition hook {name}: {e}").into())
+            },
+        ).map_err(LuaError::external)?;
+
+        let tab = lua.create_table_with_capacity(matched.len(), 0)?;
+        for (rule, actions) in matched {
+            let m = lua.create_table_with_capacity(0, 2)?;
+            m.set("rule", rule)?;
+            m.set("actions", lua.to_value_with(actions, LUA_SERIALIZE_OPTIONS)?)?;
+            tab.raw_push(m)?;
+        }
+        Ok(tab)
+    }
+}
+
+impl LuaUserData for CompiledRules {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("evaluate", |lua, this, (input, hooks): (LuaValue, Option<LuaTable>)| this.evaluate(lua, input, hooks));
+    }
+}
+
+/// Creates the `@antiraid/rules` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.raw_set("compile", lua.create_function(|lua, rules: LuaValue| {
+        let rules: Vec<Rule> = lua.from_value(rules)?;
+        Ok(CompiledRules(RuleSet::compile(rules).map_err(LuaError::external)?))
+    })?)?;
+
+    module.raw_set("schema", lua.create_function(|lua, ()| {
+        lua.to_value_with(&rules::schema(), LUA_SERIALIZE_OPTIONS)
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
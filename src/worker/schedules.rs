@@ -0,0 +1,35 @@
+use dapi::GuildId;
+use dapi::context::DiscordContext;
+use serde_json::Value;
+
+use crate::geese::schedules::ScheduledMessageRun;
+use crate::worker::syscall::discord::ArDiscordProvider;
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Internal event dispatched to a guild by the master when one of its scheduled messages is due
+pub const SCHEDULED_MESSAGE_EVENT: &str = "$ScheduledMessage";
+
+/// Posts the scheduled messages of a guild
+///
+/// Messages are sent through the Discord executor of the guild, the same as messages sent by templates, so are
+/// subject to its safety checks. Errors are returned to the master, which retries the run
+pub struct ScheduledMessages<'a> {
+    dispatch: &'a WorkerDispatch,
+}
+
+impl<'a> ScheduledMessages<'a> {
+    pub fn new(dispatch: &'a WorkerDispatch) -> Self {
+        Self { dispatch }
+    }
+
+    /// Handles a `$ScheduledMessage` for a guild
+    pub async fn post(&self, guild_id: GuildId, payload: &Value) -> Result<(), crate::Error> {
+        let run: ScheduledMessageRun = serde_json::from_value(payload.clone())?;
+        let op = run.message.to_request(run.channel_id)?;
+
+        let dp = DiscordContext::new(ArDiscordProvider { id: Id::Guild(guild_id), stratum: self.dispatch.worker_state.stratum.clone() });
+        op.execute(&dp).await.map_err(|e| format!("Failed to post scheduled message {}: {e}", run.name))?;
+        Ok(())
+    }
+}
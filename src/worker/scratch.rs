@@ -0,0 +1,250 @@
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::rc::{Rc, Weak};
+
+use khronos_runtime::rt::mlua::prelude::*;
+use rand::distr::{Alphanumeric, SampleString};
+
+use crate::worker::limits::{MAX_SCRATCH_BYTES, MAX_SCRATCH_FILES, MAX_SCRATCH_READ_BYTES, SCRATCH_SPILL_BYTES};
+
+/// Name of the VM global the `@antiraid/scratch` module is exposed as
+pub const SCRATCH_GLOBAL: &str = "__antiraid_scratch";
+
+/// Contents of a scratch file
+enum Storage {
+    Memory(Vec<u8>),
+    /// Spilled to a temporary file, which is unlinked on creation so it is removed once closed (even if the
+    /// worker crashes)
+    Disk { file: File, len: usize },
+}
+
+impl Storage {
+    fn len(&self) -> usize {
+        match self {
+            Self::Memory(buf) => buf.len(),
+            Self::Disk { len, .. } => *len,
+        }
+    }
+
+    /// Moves the contents to a temporary file
+    fn spill(&mut self) -> std::io::Result<()> {
+        let Self::Memory(buf) = self else {
+            return Ok(());
+        };
+
+        let path = std::env::temp_dir().join(format!("tw-scratch-{}", Alphanumeric.sample_string(&mut rand::rng(), 16)));
+        let mut file = File::options().read(true).write(true).create_new(true).open(&path)?;
+        std::fs::remove_file(&path)?;
+        file.write_all(buf)?;
+        *self = Self::Disk { file, len: buf.len() };
+        Ok(())
+    }
+
+    fn write_at(&mut self, pos: usize, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Memory(buf) => {
+                let end = pos + data.len();
+                if end > buf.len() {
+                    buf.resize(end, 0);
+                }
+                buf[pos..end].copy_from_slice(data);
+            }
+            Self::Disk { file, len } => {
+                file.seek(SeekFrom::Start(pos as u64))?;
+                file.write_all(data)?;
+                *len = (*len).max(pos + data.len());
+            }
+        }
+        Ok(())
+    }
+
+    fn read_at(&mut self, pos: usize, n: usize) -> std::io::Result<Vec<u8>> {
+        let end = (pos + n).min(self.len());
+        match self {
+            Self::Memory(buf) => Ok(buf[pos..end].to_vec()),
+            Self::Disk { file, .. } => {
+                let mut out = vec![0; end - pos];
+                file.seek(SeekFrom::Start(pos as u64))?;
+                file.read_exact(&mut out)?;
+                Ok(out)
+            }
+        }
+    }
+
+    fn truncate(&mut self, new_len: usize) -> std::io::Result<()> {
+        match self {
+            Self::Memory(buf) => buf.truncate(new_len),
+            Self::Disk { file, len } => {
+                file.set_len(new_len as u64)?;
+                *len = new_len;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct ScratchData {
+    storage: Storage,
+    pos: usize,
+}
+
+/// Scratch space of a VM, accounting the size of its files against `MAX_SCRATCH_BYTES`
+///
+/// Files are closed when their execution ends. As files can't be attributed to the execution which created them
+/// (executions share the VM), this happens once no execution is running on the VM
+#[derive(Default)]
+pub struct ScratchSpace {
+    used: Cell<usize>,
+    /// Open files, `None` once closed
+    files: RefCell<Vec<Weak<RefCell<Option<ScratchData>>>>>,
+}
+
+impl ScratchSpace {
+    pub fn new() -> Rc<Self> {
+        Rc::default()
+    }
+
+    /// Closes every file, freeing their space
+    pub fn clear(&self) {
+        for file in self.files.take() {
+            if let Some(file) = file.upgrade() {
+                self.close(&file);
+            }
+        }
+    }
+
+    fn close(&self, file: &RefCell<Option<ScratchData>>) {
+        if let Some(data) = file.borrow_mut().take() {
+            self.used.set(self.used.get() - data.storage.len());
+        }
+    }
+
+    fn reserve(&self, bytes: usize) -> LuaResult<()> {
+        let used = self.used.get() + bytes;
+        if used > MAX_SCRATCH_BYTES {
+            return Err(LuaError::external(format!("scratch space exceeds the maximum of {MAX_SCRATCH_BYTES} bytes")));
+        }
+        self.used.set(used);
+        Ok(())
+    }
+}
+
+/// A file in the scratch space
+pub struct ScratchFile {
+    space: Rc<ScratchSpace>,
+    data: Rc<RefCell<Option<ScratchData>>>,
+}
+
+impl ScratchFile {
+    fn with<T>(&self, f: impl FnOnce(&mut ScratchData) -> LuaResult<T>) -> LuaResult<T> {
+        match self.data.borrow_mut().as_mut() {
+            Some(data) => f(data),
+            None => Err(LuaError::external("scratch file is closed")),
+        }
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        self.space.close(&self.data);
+    }
+}
+
+impl LuaUserData for ScratchFile {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("write", |_, this, data: LuaString| {
+            this.with(|d| {
+                let data = data.as_bytes();
+                let grown = (d.pos + data.len()).saturating_sub(d.storage.len());
+                this.space.reserve(grown)?;
+                let res = (|| {
+                    if d.storage.len() + grown > SCRATCH_SPILL_BYTES {
+                        d.storage.spill()?;
+                    }
+                    d.storage.write_at(d.pos, &data)
+                })();
+                if let Err(e) = res {
+                    // Nothing (or an unknown part) was written, keep the accounting in line with the size
+                    this.space.used.set(this.space.used.get() - grown);
+                    return Err(LuaError::external(format!("failed to write scratch file: {e}")));
+                }
+                d.pos += data.len();
+                Ok(())
+            })
+        });
+
+        methods.add_method("read", |lua, this, n: Option<usize>| {
+            this.with(|d| {
+                if d.pos >= d.storage.len() {
+                    return Ok(None);
+                }
+                let n = n.unwrap_or(MAX_SCRATCH_READ_BYTES).min(MAX_SCRATCH_READ_BYTES);
+                let out = d.storage.read_at(d.pos, n)
+                    .map_err(|e| LuaError::external(format!("failed to read scratch file: {e}")))?;
+                d.pos += out.len();
+                Ok(Some(lua.create_string(out)?))
+            })
+        });
+
+        methods.add_method("seek", |_, this, pos: usize| {
+            this.with(|d| {
+                if pos > d.storage.len() {
+                    return Err(LuaError::external(format!("cannot seek to {pos}, past the end of the file ({} bytes)", d.storage.len())));
+                }
+                d.pos = pos;
+                Ok(())
+            })
+        });
+
+        methods.add_method("tell", |_, this, ()| this.with(|d| Ok(d.pos)));
+
+        methods.add_method("size", |_, this, ()| this.with(|d| Ok(d.storage.len())));
+
+        methods.add_method("truncate", |_, this, len: Option<usize>| {
+            this.with(|d| {
+                let len = len.unwrap_or(d.pos);
+                let old_len = d.storage.len();
+                if len > old_len {
+                    return Err(LuaError::external(format!("cannot truncate to {len}, past the end of the file ({old_len} bytes)")));
+                }
+                d.storage.truncate(len)
+                    .map_err(|e| LuaError::external(format!("failed to truncate scratch file: {e}")))?;
+                this.space.used.set(this.space.used.get() - (old_len - len));
+                d.pos = d.pos.min(len);
+                Ok(())
+            })
+        });
+
+        methods.add_method("close", |_, this, ()| {
+            this.space.close(&this.data);
+            Ok(())
+        });
+
+        methods.add_method("isclosed", |_, this, ()| Ok(this.data.borrow().is_none()));
+    }
+}
+
+/// Creates the `@antiraid/scratch` module of a VM
+pub fn create_module(lua: &Lua, space: Rc<ScratchSpace>) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    let create_space = space.clone();
+    module.raw_set("create", lua.create_function(move |_, ()| {
+        let mut files = create_space.files.borrow_mut();
+        files.retain(|f| f.upgrade().is_some_and(|f| f.borrow().is_some()));
+        if files.len() >= MAX_SCRATCH_FILES {
+            return Err(LuaError::external(format!("cannot have more than {MAX_SCRATCH_FILES} scratch files open")));
+        }
+
+        let data = Rc::new(RefCell::new(Some(ScratchData { storage: Storage::Memory(Vec::new()), pos: 0 })));
+        files.push(Rc::downgrade(&data));
+        Ok(ScratchFile { space: create_space.clone(), data })
+    })?)?;
+
+    module.raw_set("used", lua.create_function(move |_, ()| Ok(space.used.get()))?)?;
+    module.raw_set("quota", MAX_SCRATCH_BYTES)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
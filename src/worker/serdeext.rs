@@ -0,0 +1,209 @@
+use std::{cell::Cell, rc::Rc};
+
+use khronos_runtime::{primitives::LUA_SERIALIZE_OPTIONS, rt::mlua::prelude::*};
+use serde_json::Value;
+
+use crate::worker::limits::{MAX_JSON_STREAM_INPUT_BYTES, MAX_SERDE_INPUT_BYTES};
+
+/// Name of the VM global the `@antiraid/serde` module is exposed as
+pub const SERDE_GLOBAL: &str = "__antiraid_serde";
+
+fn input(s: &LuaString, max: usize) -> LuaResult<String> {
+    if s.as_bytes().len() > max {
+        return Err(LuaError::external(format!("input exceeds the maximum size of {max} bytes")));
+    }
+    Ok(s.to_str()?.to_string())
+}
+
+fn output(s: String) -> LuaResult<String> {
+    if s.len() > MAX_SERDE_INPUT_BYTES {
+        return Err(LuaError::external(format!("output exceeds the maximum size of {MAX_SERDE_INPUT_BYTES} bytes")));
+    }
+    Ok(s)
+}
+
+/// Converts a TOML value to JSON, datetimes become strings
+fn toml_to_json(value: toml::Value) -> LuaResult<Value> {
+    Ok(match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::Number(serde_json::Number::from_f64(f).ok_or_else(|| LuaError::external(format!("{f} cannot be represented")))?),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(a) => Value::Array(a.into_iter().map(toml_to_json).collect::<LuaResult<_>>()?),
+        toml::Value::Table(t) => Value::Object(t.into_iter().map(|(k, v)| Ok((k, toml_to_json(v)?))).collect::<LuaResult<_>>()?),
+    })
+}
+
+/// Writes `value` as canonical JSON: no insignificant whitespace and object keys sorted, so equal values always
+/// produce identical output (suitable for hashing or signing)
+fn write_canonical(value: &Value, out: &mut String) -> LuaResult<()> {
+    match value {
+        Value::Array(a) => {
+            out.push('[');
+            for (i, v) in a.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(v, out)?;
+            }
+            out.push(']');
+        }
+        Value::Object(o) => {
+            let mut entries: Vec<_> = o.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(k).map_err(LuaError::external)?);
+                out.push(':');
+                write_canonical(v, out)?;
+            }
+            out.push('}');
+        }
+        // Integral floats are written as integers so 1 and 1.0 are canonically equal
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && f.fract() == 0.0 && f.abs() < 9007199254740992.0 => out.push_str(&(f as i64).to_string()),
+            _ => out.push_str(&n.to_string()),
+        },
+        v => out.push_str(&serde_json::to_string(v).map_err(LuaError::external)?),
+    }
+    Ok(())
+}
+
+/// Returns the byte offset of the next non-whitespace character at or after `pos`
+fn skip_whitespace(s: &str, pos: usize) -> usize {
+    pos + s[pos..].len() - s[pos..].trim_start().len()
+}
+
+/// Iterates over a JSON document without parsing it all at once
+///
+/// A top-level array is iterated element by element, anything else is treated as a stream of concatenated (such
+/// as newline-delimited) values
+struct JsonStream {
+    s: Rc<str>,
+    pos: Cell<usize>,
+    in_array: Cell<bool>,
+    done: Cell<bool>,
+    index: Cell<usize>,
+}
+
+impl JsonStream {
+    fn new(s: String) -> Self {
+        let start = skip_whitespace(&s, 0);
+        let in_array = s[start..].starts_with('[');
+        Self {
+            pos: Cell::new(if in_array { start + 1 } else { start }),
+            in_array: Cell::new(in_array),
+            s: s.into(),
+            done: Cell::new(false),
+            index: Cell::new(0),
+        }
+    }
+
+    fn next(&self) -> LuaResult<Option<Value>> {
+        if self.done.get() {
+            return Ok(None);
+        }
+
+        let s = &*self.s;
+        let mut pos = skip_whitespace(s, self.pos.get());
+        if self.in_array.get() && s[pos..].starts_with(']') {
+            self.done.set(true);
+            if !s[pos + 1..].trim().is_empty() {
+                return Err(LuaError::external("trailing characters after JSON array"));
+            }
+            return Ok(None);
+        }
+        if pos >= s.len() {
+            self.done.set(true);
+            if self.in_array.get() {
+                return Err(LuaError::external("unterminated JSON array"));
+            }
+            return Ok(None);
+        }
+
+        let mut de = serde_json::Deserializer::from_str(&s[pos..]).into_iter::<Value>();
+        let value = match de.next() {
+            Some(Ok(value)) => value,
+            Some(Err(e)) => {
+                self.done.set(true);
+                return Err(LuaError::external(format!("invalid JSON at byte {}: {e}", pos + de.byte_offset())));
+            }
+            None => {
+                self.done.set(true);
+                return Ok(None);
+            }
+        };
+        pos = skip_whitespace(s, pos + de.byte_offset());
+
+        if self.in_array.get() {
+            match s[pos..].chars().next() {
+                Some(',') => pos += 1,
+                Some(']') => {}
+                _ => {
+                    self.done.set(true);
+                    return Err(LuaError::external(format!("expected ',' or ']' at byte {pos}")));
+                }
+            }
+        }
+        self.pos.set(pos);
+        self.index.set(self.index.get() + 1);
+        Ok(Some(value))
+    }
+}
+
+/// Creates the `@antiraid/serde` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    let toml = lua.create_table()?;
+    toml.raw_set("encode", lua.create_function(|lua, (value, pretty): (LuaValue, Option<bool>)| {
+        let value: Value = lua.from_value(value)?;
+        let s = if pretty.unwrap_or(false) { toml::to_string_pretty(&value) } else { toml::to_string(&value) };
+        output(s.map_err(LuaError::external)?)
+    })?)?;
+    toml.raw_set("decode", lua.create_function(|lua, s: LuaString| {
+        let table: toml::Table = toml::from_str(&input(&s, MAX_SERDE_INPUT_BYTES)?).map_err(LuaError::external)?;
+        lua.to_value_with(&toml_to_json(toml::Value::Table(table))?, LUA_SERIALIZE_OPTIONS)
+    })?)?;
+    toml.set_readonly(true);
+    module.raw_set("toml", toml)?;
+
+    let yaml = lua.create_table()?;
+    yaml.raw_set("encode", lua.create_function(|lua, value: LuaValue| {
+        let value: Value = lua.from_value(value)?;
+        output(serde_yaml_ng::to_string(&value).map_err(LuaError::external)?)
+    })?)?;
+    yaml.raw_set("decode", lua.create_function(|lua, s: LuaString| {
+        let value: Value = serde_yaml_ng::from_str(&input(&s, MAX_SERDE_INPUT_BYTES)?).map_err(LuaError::external)?;
+        lua.to_value_with(&value, LUA_SERIALIZE_OPTIONS)
+    })?)?;
+    yaml.set_readonly(true);
+    module.raw_set("yaml", yaml)?;
+
+    let json = lua.create_table()?;
+    json.raw_set("canonical", lua.create_function(|lua, value: LuaValue| {
+        let value: Value = lua.from_value(value)?;
+        let mut out = String::new();
+        write_canonical(&value, &mut out)?;
+        output(out)
+    })?)?;
+    json.raw_set("stream", lua.create_function(|lua, s: LuaString| {
+        let stream = JsonStream::new(input(&s, MAX_JSON_STREAM_INPUT_BYTES)?);
+        // Returns (index, value) like ipairs so null values don't end iteration
+        lua.create_function(move |lua, _: LuaMultiValue| {
+            match stream.next()? {
+                Some(value) => Ok((Some(stream.index.get()), lua.to_value_with(&value, LUA_SERIALIZE_OPTIONS)?)),
+                None => Ok((None, LuaValue::Nil)),
+            }
+        })
+    })?)?;
+    json.set_readonly(true);
+    module.raw_set("json", json)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mesophyll::client::MesophyllClient;
+use crate::worker::workerthread::WorkerThread;
+
+/// How often in-flight executions are checked while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Request from the master to drain a worker before it is shut down
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrainReq {
+    /// How long to wait for in-flight executions to finish
+    pub timeout_ms: u64,
+}
+
+/// What a worker finished (and abandoned) while draining
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DrainReport {
+    /// Executions that finished while draining
+    pub completed: usize,
+    /// Executions still running when the timeout elapsed, these are lost when the worker is killed
+    pub abandoned: usize,
+    /// Template usage records flushed to the master
+    pub usage_flushed: usize,
+    /// Template usage records that failed to be flushed and are lost
+    pub usage_abandoned: usize,
+    /// Plugin usage records that failed to be flushed and are lost
+    pub plugin_usage_abandoned: usize,
+    /// Emoji usage records that failed to be flushed and are lost
+    #[serde(default)]
+    pub emoji_usage_abandoned: usize,
+    /// Activity records that failed to be flushed and are lost
+    #[serde(default)]
+    pub activity_abandoned: usize,
+}
+
+/// Drains a worker: stops it accepting dispatches, waits (up to `timeout`) for running executions and then
+/// flushes its usage queues to the master
+///
+/// KV writes are made synchronously by the execution performing them, so they are persisted once the
+/// execution finishes. The caller is responsible for closing the gateway sessions of the worker
+pub async fn drain(wt: &WorkerThread, mesophyll: &MesophyllClient, timeout: Duration) -> DrainReport {
+    wt.stop_accepting();
+
+    let mut report = DrainReport::default();
+    let inflight = wt.load().inflight();
+    let deadline = Instant::now() + timeout;
+    while wt.load().inflight() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+    report.abandoned = wt.load().inflight();
+    report.completed = inflight.saturating_sub(report.abandoned);
+
+    let records = wt.usage().take();
+    if !records.is_empty() {
+        match mesophyll.record_usage(&records).await {
+            Ok(_) => report.usage_flushed = records.len(),
+            Err(e) => {
+                log::error!("Failed to flush template usage while draining: {e}");
+                report.usage_abandoned = records.len();
+            }
+        }
+    }
+
+    let records = wt.plugin_usage().take();
+    if !records.is_empty() && let Err(e) = mesophyll.record_plugin_usage(&records).await {
+        log::error!("Failed to flush plugin usage while draining: {e}");
+        report.plugin_usage_abandoned = records.len();
+    }
+
+    let records = wt.emoji_usage().take();
+    if !records.is_empty() && let Err(e) = mesophyll.record_emoji_usage(&records).await {
+        log::error!("Failed to flush emoji usage while draining: {e}");
+        report.emoji_usage_abandoned = records.len();
+    }
+
+    let records = wt.activity().take();
+    if !records.is_empty() && let Err(e) = mesophyll.record_activity(&records).await {
+        log::error!("Failed to flush activity while draining: {e}");
+        report.activity_abandoned = records.len();
+    }
+
+    report
+}
@@ -0,0 +1,124 @@
+use khronos_runtime::rt::mlua::prelude::*;
+
+/// Name of the VM global the `@antiraid/snowflake` module is exposed as
+pub const SNOWFLAKE_GLOBAL: &str = "__antiraid_snowflake";
+
+/// The Discord epoch (first second of 2015) in milliseconds
+const DISCORD_EPOCH: u64 = 1420070400000;
+
+/// Largest integer which can be represented exactly by a Luau number
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Size of a Discord message bucket (10 days) in milliseconds
+const DEFAULT_BUCKET_SIZE: u64 = 1000 * 60 * 60 * 24 * 10;
+
+/// A Discord snowflake
+///
+/// Snowflakes do not fit in a Luau number without losing precision, so they are kept as a u64 and only
+/// converted to strings or (small, exact) numbers when handed back to Luau
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Snowflake(u64);
+
+impl Snowflake {
+    fn parse(s: &str) -> LuaResult<Self> {
+        s.trim().parse::<u64>()
+            .map(Self)
+            .map_err(|_| LuaError::external(format!("invalid snowflake: {s:?}")))
+    }
+
+    /// Returns the smallest snowflake created at the given unix timestamp (in milliseconds)
+    fn from_timestamp(millis: f64) -> LuaResult<Self> {
+        if !millis.is_finite() || millis < DISCORD_EPOCH as f64 {
+            return Err(LuaError::external("timestamp must be after the Discord epoch"));
+        }
+        let since_epoch = millis as u64 - DISCORD_EPOCH;
+        if since_epoch >= 1 << 42 {
+            return Err(LuaError::external("timestamp is too far in the future"));
+        }
+        Ok(Self(since_epoch << 22))
+    }
+
+    /// Unix timestamp (in milliseconds) the snowflake was created at
+    fn timestamp(self) -> u64 {
+        (self.0 >> 22) + DISCORD_EPOCH
+    }
+}
+
+impl FromLua for Snowflake {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::UserData(ud) => Ok(*ud.borrow::<Snowflake>()?),
+            LuaValue::String(s) => Self::parse(&s.to_str()?),
+            LuaValue::Integer(i) if i >= 0 => Ok(Self(i as u64)),
+            LuaValue::Number(n) if n >= 0.0 && n.fract() == 0.0 && n <= MAX_SAFE_INTEGER as f64 => Ok(Self(n as u64)),
+            LuaValue::Number(_) => Err(LuaError::external("snowflakes passed as numbers must be non-negative safe integers, pass them as strings instead")),
+            v => Err(LuaError::external(format!("expected a snowflake or string, got {}", v.type_name()))),
+        }
+    }
+}
+
+impl LuaUserData for Snowflake {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("timestamp", |_, this, _: ()| Ok(this.timestamp() as f64));
+        methods.add_method("workerid", |_, this, _: ()| Ok((this.0 >> 17) & 0x1F));
+        methods.add_method("processid", |_, this, _: ()| Ok((this.0 >> 12) & 0x1F));
+        methods.add_method("increment", |_, this, _: ()| Ok(this.0 & 0xFFF));
+        methods.add_method("shard", |_, this, num_shards: u64| {
+            if num_shards == 0 {
+                return Err(LuaError::external("number of shards must be positive"));
+            }
+            Ok((this.0 >> 22) % num_shards)
+        });
+        methods.add_method("bucket", |_, this, size: Option<u64>| {
+            let size = size.unwrap_or(DEFAULT_BUCKET_SIZE);
+            if size == 0 {
+                return Err(LuaError::external("bucket size must be positive"));
+            }
+            Ok((this.0 >> 22) / size)
+        });
+        methods.add_method("tostring", |_, this, _: ()| Ok(this.0.to_string()));
+        methods.add_meta_method(LuaMetaMethod::ToString, |_, this, _: ()| Ok(this.0.to_string()));
+        methods.add_meta_method(LuaMetaMethod::Eq, |_, this, other: Snowflake| Ok(*this == other));
+        methods.add_meta_method(LuaMetaMethod::Lt, |_, this, other: Snowflake| Ok(*this < other));
+        methods.add_meta_method(LuaMetaMethod::Le, |_, this, other: Snowflake| Ok(*this <= other));
+    }
+}
+
+/// Creates the `@antiraid/snowflake` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.raw_set("new", lua.create_function(|_, value: Snowflake| Ok(value))?)?;
+
+    module.raw_set("fromtimestamp", lua.create_function(|_, millis: f64| Snowflake::from_timestamp(millis))?)?;
+
+    // Snowflake bounds for "created between start and end" queries (such as the after/before
+    // parameters of GetChannelMessages), both bounds are exclusive as in the Discord API
+    module.raw_set("range", lua.create_function(|_, (start, end): (f64, f64)| {
+        if start > end {
+            return Err(LuaError::external("start must be before end"));
+        }
+        let after = Snowflake::from_timestamp(start)?;
+        let before = Snowflake::from_timestamp(end)?;
+        Ok((Snowflake(after.0.saturating_sub(1)), Snowflake((before.0 | ((1 << 22) - 1)).saturating_add(1))))
+    })?)?;
+
+    module.raw_set("compare", lua.create_function(|_, (a, b): (Snowflake, Snowflake)| {
+        Ok(match a.cmp(&b) {
+            std::cmp::Ordering::Less => -1,
+            std::cmp::Ordering::Equal => 0,
+            std::cmp::Ordering::Greater => 1,
+        })
+    })?)?;
+
+    module.raw_set("isvalid", lua.create_function(|_, value: LuaValue| {
+        Ok(match value {
+            LuaValue::String(s) => s.to_str().is_ok_and(|s| s.trim().parse::<u64>().is_ok()),
+            LuaValue::UserData(ud) => ud.is::<Snowflake>(),
+            _ => false,
+        })
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
@@ -0,0 +1,260 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use chrono::{DateTime, Utc};
+use dapi::{GuildId, RoleId, UserId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use khronos_runtime::utils::khronos_value::{CKhronosValue, KhronosValue};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::CONFIG;
+use crate::geese::stickyroles::{StickyRolesConfig, StickyRolesReq, StickyRolesResp, StickySnapshot};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Event dispatched to a guild before the roles of a rejoining member are restored, templates can return `false`
+/// (or `{ veto = true }`) to veto the restore
+pub const STICKY_ROLES_RESTORE_EVENT: &str = "StickyRolesRestore";
+
+/// Maximum number of members whose roles are remembered, all are forgotten once exceeded
+const MAX_REMEMBERED_MEMBERS: usize = 100_000;
+
+/// Permissions of roles excluded from restores by `exclude_dangerous`: kick, ban, administrator, manage channels,
+/// manage server, manage messages, mention everyone, manage roles, manage webhooks and timeout members
+pub(crate) const DANGEROUS_PERMISSIONS: u64 = (1 << 1) | (1 << 2) | (1 << 3) | (1 << 4) | (1 << 5) | (1 << 13) | (1 << 17) | (1 << 28) | (1 << 29) | (1 << 40);
+
+/// Audit log reason of restores
+const RESTORE_REASON: &str = "Sticky roles restore";
+
+/// Why a role of a snapshot is not restored
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The role was deleted
+    Deleted,
+    /// The role is excluded by the config, or has dangerous permissions
+    Excluded,
+    /// The role is managed by an integration or not below the top role of the bot
+    Hierarchy,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedRole {
+    pub role_id: RoleId,
+    pub reason: SkipReason,
+}
+
+/// Data of `StickyRolesRestore`
+#[derive(Debug, Clone, Serialize)]
+pub struct StickyRolesRestore {
+    pub user_id: UserId,
+    /// The roles to be restored
+    pub roles: Vec<RoleId>,
+    /// Roles of the snapshot which are not restored
+    pub skipped: Vec<SkippedRole>,
+    /// The nickname to be restored, if any
+    pub nick: Option<String>,
+    pub left_at: DateTime<Utc>,
+}
+
+impl IntoLua for StickyRolesRestore {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+#[derive(Clone)]
+struct Member {
+    roles: Vec<RoleId>,
+    nick: Option<String>,
+}
+
+/// Restores the roles (and nickname) of members rejoining a guild they left within its retention window
+///
+/// GUILD_MEMBER_REMOVE does not carry the roles of the member, so the roles of members are remembered from their
+/// joins and updates, falling back to the cache for members not seen since the worker started. Only roles below the
+/// top role of the bot (and not managed by an integration) are restored
+#[derive(Clone, Default)]
+pub struct StickyRoles {
+    members: Rc<RefCell<HashMap<(GuildId, UserId), Member>>>,
+}
+
+impl StickyRoles {
+    /// Remembers the roles of a member given its GUILD_MEMBER_ADD or GUILD_MEMBER_UPDATE payload, if the guild has
+    /// sticky roles
+    pub fn observe(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        if dispatch.tenant_state.sticky_roles(Id::Guild(guild_id)).is_none() {
+            return;
+        }
+        let Some(user_id) = member_user_id(payload) else {
+            return;
+        };
+
+        let mut members = self.members.borrow_mut();
+        if members.len() >= MAX_REMEMBERED_MEMBERS {
+            members.clear();
+        }
+        members.insert((guild_id, user_id), parse_member(payload));
+    }
+
+    /// Saves the roles of a member in the background given its GUILD_MEMBER_REMOVE payload, if the guild has
+    /// sticky roles
+    pub fn save(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.sticky_roles(Id::Guild(guild_id)) else {
+            return;
+        };
+        let Some(user_id) = member_user_id(payload) else {
+            return;
+        };
+
+        let remembered = self.members.borrow_mut().remove(&(guild_id, user_id));
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            let member = match remembered {
+                Some(member) => member,
+                None => match dispatch.worker_state.stratum.guild_member(guild_id, user_id).await {
+                    Ok(Some(member)) => parse_member(&member),
+                    Ok(None) => return,
+                    Err(e) => {
+                        log::debug!("Failed to fetch roles of {user_id} leaving guild {guild_id}: {e}");
+                        return;
+                    }
+                },
+            };
+            if member.roles.is_empty() && member.nick.is_none() {
+                return;
+            }
+
+            let snapshot = StickySnapshot { guild_id, user_id, roles: member.roles, nick: member.nick, left_at: Utc::now() };
+            if let Err(e) = dispatch.worker_state.mesophyll_client.sticky_roles(&StickyRolesReq::Save { snapshot, retention_days: config.retention_days }).await {
+                log::error!("Failed to save roles of {user_id} leaving guild {guild_id}: {e}");
+            }
+        });
+    }
+
+    /// Restores the roles of a member in the background given its GUILD_MEMBER_ADD payload, if the guild has sticky
+    /// roles and a snapshot of the member
+    pub fn restore(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.sticky_roles(Id::Guild(guild_id)) else {
+            return;
+        };
+        let Some(user_id) = member_user_id(payload) else {
+            return;
+        };
+        let held = parse_member(payload).roles;
+
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            if let Err(e) = Self::restore_member(&dispatch, guild_id, user_id, &config, &held).await {
+                log::debug!("Failed to restore roles of {user_id} in guild {guild_id}: {e}");
+            }
+        });
+    }
+
+    async fn restore_member(dispatch: &WorkerDispatch, guild_id: GuildId, user_id: UserId, config: &StickyRolesConfig, held: &[RoleId]) -> Result<(), crate::Error> {
+        let req = StickyRolesReq::Take { guild_id, user_id, retention_days: config.retention_days };
+        let StickyRolesResp::Snapshot { snapshot: Some(snapshot) } = dispatch.worker_state.mesophyll_client.sticky_roles(&req).await? else {
+            return Ok(());
+        };
+
+        let (roles, skipped) = Self::restorable(dispatch, guild_id, config, &snapshot).await?;
+        let roles = roles.into_iter().filter(|r| !held.contains(r)).collect::<Vec<_>>();
+        let nick = snapshot.nick.filter(|_| config.restore_nick);
+        if roles.is_empty() && nick.is_none() {
+            return Ok(());
+        }
+
+        let restore = StickyRolesRestore { user_id, roles, skipped, nick, left_at: snapshot.left_at };
+        let res = dispatch.dispatch_event_complex(Id::Guild(guild_id), STICKY_ROLES_RESTORE_EVENT, None, restore.clone()).await?;
+        if vetoed(&res) {
+            return Ok(());
+        }
+
+        // Roles are added one by one so roles assigned since the join are kept
+        for role_id in restore.roles.iter() {
+            dispatch.worker_state.reqwest.put(format!("{}/api/v10/guilds/{guild_id}/members/{user_id}/roles/{role_id}", CONFIG.proxy))
+                .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+                .header("X-Audit-Log-Reason", RESTORE_REASON)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        if let Some(ref nick) = restore.nick {
+            dispatch.worker_state.reqwest.patch(format!("{}/api/v10/guilds/{guild_id}/members/{user_id}", CONFIG.proxy))
+                .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+                .header("X-Audit-Log-Reason", RESTORE_REASON)
+                .json(&json!({ "nick": nick }))
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        Ok(())
+    }
+
+    /// Splits the roles of a snapshot into those which can be restored and those which cannot
+    async fn restorable(dispatch: &WorkerDispatch, guild_id: GuildId, config: &StickyRolesConfig, snapshot: &StickySnapshot) -> Result<(Vec<RoleId>, Vec<SkippedRole>), crate::Error> {
+        let stratum = &dispatch.worker_state.stratum;
+        let guild_roles = stratum.guild_roles(guild_id).await?.ok_or("Guild roles not found")?;
+        let guild_roles = guild_roles.as_array().ok_or("Guild roles are not an array")?;
+        let role = |id: RoleId| guild_roles.iter().find(|r| r.get("id").and_then(|v| v.as_str()) == Some(id.to_string().as_str()));
+        let position = |r: &Value| r.get("position").and_then(|v| v.as_i64()).unwrap_or_default();
+
+        let bot = stratum.guild_member(guild_id, stratum.current_user().id).await?.ok_or("Bot member not found")?;
+        let top_position = parse_member(&bot).roles.into_iter()
+            .filter_map(|id| role(id).map(position))
+            .max()
+            .unwrap_or_default();
+
+        let (mut roles, mut skipped) = (Vec::new(), Vec::new());
+        for &role_id in snapshot.roles.iter() {
+            let Some(r) = role(role_id) else {
+                skipped.push(SkippedRole { role_id, reason: SkipReason::Deleted });
+                continue;
+            };
+            let permissions = r.get("permissions").and_then(|v| v.as_str()).and_then(|v| v.parse::<u64>().ok()).unwrap_or_default();
+            let reason = if config.excluded_roles.iter().any(|e| *e == role_id.to_string()) || (config.exclude_dangerous && permissions & DANGEROUS_PERMISSIONS != 0) {
+                Some(SkipReason::Excluded)
+            } else if r.get("managed").and_then(|v| v.as_bool()).unwrap_or(false) || position(r) >= top_position {
+                Some(SkipReason::Hierarchy)
+            } else {
+                None
+            };
+            match reason {
+                Some(reason) => skipped.push(SkippedRole { role_id, reason }),
+                None => roles.push(role_id),
+            }
+        }
+        Ok((roles, skipped))
+    }
+}
+
+/// Returns the user ID of a member payload, unless the member is a bot
+fn member_user_id(payload: &Value) -> Option<UserId> {
+    let user = payload.get("user")?;
+    if user.get("bot").and_then(|v| v.as_bool()).unwrap_or(false) {
+        return None;
+    }
+    user.get("id").and_then(|v| v.as_str()).and_then(|v| v.parse().ok())
+}
+
+fn parse_member(payload: &Value) -> Member {
+    let roles = payload.get("roles").and_then(|v| v.as_array())
+        .map(|roles| roles.iter().filter_map(|r| r.as_str()).filter_map(|r| r.parse().ok()).collect())
+        .unwrap_or_default();
+    let nick = payload.get("nick").and_then(|v| v.as_str()).map(|n| n.to_string());
+    Member { roles, nick }
+}
+
+/// Returns whether any template vetoed a restore, by returning `false` or `{ veto = true }`
+fn vetoed(res: &KhronosValue) -> bool {
+    let Ok(Value::Array(results)) = serde_json::to_value(CKhronosValue(res.clone())) else {
+        return false;
+    };
+    results.iter()
+        .filter(|r| r.get("type").and_then(|v| v.as_str()) == Some("ok"))
+        .filter_map(|r| r.get("value"))
+        .any(|v| *v == Value::Bool(false) || v.get("veto").and_then(|v| v.as_bool()).unwrap_or(false))
+}
@@ -0,0 +1,123 @@
+use caseless::default_case_fold_str;
+use khronos_runtime::rt::mlua::prelude::*;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use unicode_security::skeleton;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::worker::limits::MAX_STRING_UTILS_INPUT_BYTES;
+
+/// Name of the VM global the `@antiraid/stringutils` module is exposed as
+pub const STRINGUTILS_GLOBAL: &str = "__antiraid_stringutils";
+
+/// Returns true for invisible characters commonly used to evade filters (zero-width characters,
+/// bidi controls, joiners etc.)
+fn is_invisible(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}' // soft hyphen
+        | '\u{034F}' // combining grapheme joiner
+        | '\u{061C}' // arabic letter mark
+        | '\u{115F}' | '\u{1160}' // hangul fillers
+        | '\u{17B4}' | '\u{17B5}' // khmer inherent vowels
+        | '\u{180B}'..='\u{180F}' // mongolian variation selectors and vowel separator
+        | '\u{200B}'..='\u{200F}' // zero-width space/joiners, lrm/rlm
+        | '\u{202A}'..='\u{202E}' // bidi embedding/override
+        | '\u{2060}'..='\u{2064}' // word joiner, invisible operators
+        | '\u{2066}'..='\u{206F}' // bidi isolates, deprecated format characters
+        | '\u{3164}' // hangul filler
+        | '\u{FE00}'..='\u{FE0F}' // variation selectors
+        | '\u{FEFF}' // zero-width no-break space
+        | '\u{FFA0}' // halfwidth hangul filler
+        | '\u{E0000}'..='\u{E007F}' // tags
+    )
+}
+
+fn strip_invisible(s: &str) -> String {
+    s.chars().filter(|c| !is_invisible(*c)).collect()
+}
+
+/// Normalizes a string for comparison: invisible characters are stripped, compatibility characters (such as
+/// fullwidth or mathematical letters) are decomposed, homoglyphs are mapped to their prototypes and case is folded
+///
+/// Two strings which look alike normalize to the same string, so this can be used to match raid names
+pub(crate) fn normalize(s: &str) -> String {
+    let stripped: String = strip_invisible(s).nfkc().collect();
+    let skel: String = skeleton(&stripped).collect();
+    default_case_fold_str(&skel).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Cleans up a name for display: invisible characters are stripped, fancy letters (such as fullwidth or
+/// mathematical letters) are mapped to plain ones, stacked combining marks (zalgo) are dropped and whitespace
+/// is collapsed
+///
+/// Unlike `normalize`, case and accents are kept, so readable names are left as is
+pub(crate) fn clean_name(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut marks = 0;
+    for c in strip_invisible(s).nfkd() {
+        if is_combining_mark(c) {
+            marks += 1;
+            if marks > 1 {
+                continue;
+            }
+        } else {
+            marks = 0;
+        }
+        out.push(c);
+    }
+    out.nfkc().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn input(s: &LuaString) -> LuaResult<String> {
+    if s.as_bytes().len() > MAX_STRING_UTILS_INPUT_BYTES {
+        return Err(LuaError::external(format!("string exceeds the maximum size of {MAX_STRING_UTILS_INPUT_BYTES} bytes")));
+    }
+    Ok(s.to_str()?.to_string())
+}
+
+/// Truncates `s` to at most `max` graphemes (including the suffix, if truncated)
+fn truncate(s: &str, max: usize, suffix: &str) -> String {
+    let len = s.graphemes(true).count();
+    if len <= max {
+        return s.to_string();
+    }
+
+    let suffix_len = suffix.graphemes(true).count();
+    if suffix_len >= max {
+        return s.graphemes(true).take(max).collect();
+    }
+
+    let mut out: String = s.graphemes(true).take(max - suffix_len).collect();
+    out.push_str(suffix);
+    out
+}
+
+/// Creates the `@antiraid/stringutils` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.raw_set("normalize", lua.create_function(|_, s: LuaString| Ok(normalize(&input(&s)?)))?)?;
+
+    module.raw_set("skeleton", lua.create_function(|_, s: LuaString| Ok(skeleton(&input(&s)?).collect::<String>()))?)?;
+
+    module.raw_set("isconfusable", lua.create_function(|_, (a, b): (LuaString, LuaString)| {
+        Ok(normalize(&input(&a)?) == normalize(&input(&b)?))
+    })?)?;
+
+    module.raw_set("stripinvisible", lua.create_function(|_, s: LuaString| Ok(strip_invisible(&input(&s)?)))?)?;
+
+    module.raw_set("hasinvisible", lua.create_function(|_, s: LuaString| Ok(input(&s)?.chars().any(is_invisible)))?)?;
+
+    module.raw_set("nfkc", lua.create_function(|_, s: LuaString| Ok(input(&s)?.nfkc().collect::<String>()))?)?;
+
+    module.raw_set("casefold", lua.create_function(|_, s: LuaString| Ok(default_case_fold_str(&input(&s)?)))?)?;
+
+    module.raw_set("graphemes", lua.create_function(|_, s: LuaString| Ok(input(&s)?.graphemes(true).count()))?)?;
+
+    module.raw_set("truncate", lua.create_function(|_, (s, max, suffix): (LuaString, usize, Option<String>)| {
+        Ok(truncate(&input(&s)?, max, suffix.as_deref().unwrap_or("")))
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
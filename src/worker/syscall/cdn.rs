@@ -39,6 +39,8 @@ impl FromLua for CdnCall {
     }
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op")]
 pub enum CdnResult {
     Buffer {
         data: Bytes
@@ -64,53 +66,59 @@ impl CdnCall {
         match self {
             Self::DownloadFile { url } => {
                 handler.ratelimits.cdn.check("DownloadFile", ()).map_err(RlExceededError)?;
-                if !url.is_ascii() {
-                    return Err("Url must be ascii-only".into());
-                }
+                let bytes = download_file(&handler.state.reqwest, &url).await?;
+                Ok(CdnResult::Buffer { data: bytes })
+            }
+        }
+    }
+}
 
-                let parsed_url = Url::parse(&url)?;
-                
-                if parsed_url.scheme() != "https" {
-                    return Err("HTTPS required".into());
-                }
+/// Downloads a file from the Discord CDN, only Discord CDN urls are allowed
+pub(crate) async fn download_file(reqwest: &reqwest::Client, url: &str) -> Result<Bytes, crate::Error> {
+    if !url.is_ascii() {
+        return Err("Url must be ascii-only".into());
+    }
 
-                match parsed_url.domain() {
-                    Some("cdn.discordapp.com") => {}
-                    _ => return Err("Invalid Discord CDN domain".into()),
-                }
+    let parsed_url = Url::parse(url)?;
+    
+    if parsed_url.scheme() != "https" {
+        return Err("HTTPS required".into());
+    }
 
-                if parsed_url.path().contains("..") {
-                    return Err("Path traversal denied".into());
-                }
+    match parsed_url.domain() {
+        Some("cdn.discordapp.com") => {}
+        _ => return Err("Invalid Discord CDN domain".into()),
+    }
 
-                if parsed_url.host_str().is_none() {
-                    return Err("URL does not have a valid host".into());
-                }
+    if parsed_url.path().contains("..") {
+        return Err("Path traversal denied".into());
+    }
 
-                if parsed_url.port().is_some() {
-                    return Err("URL cannot have a port".into());
-                }
+    if parsed_url.host_str().is_none() {
+        return Err("URL does not have a valid host".into());
+    }
 
-                let resp = handler.state.reqwest.get(parsed_url).send().await?;
-                
-                let Some(content_length) = get_content_length_from_headers(&resp) else {
-                    return Err("No content length set".into());
-                };
+    if parsed_url.port().is_some() {
+        return Err("URL cannot have a port".into());
+    }
 
-                if content_length > MAX_ATTACHMENT_SIZE {
-                    return Err(format!("Max attachment size of {MAX_ATTACHMENT_SIZE} reached").into());
-                }
+    let resp = reqwest.get(parsed_url).send().await?;
+    
+    let Some(content_length) = get_content_length_from_headers(&resp) else {
+        return Err("No content length set".into());
+    };
 
-                let bytes = resp.bytes().await?;
+    if content_length > MAX_ATTACHMENT_SIZE {
+        return Err(format!("Max attachment size of {MAX_ATTACHMENT_SIZE} reached").into());
+    }
 
-                if bytes.len() > MAX_ATTACHMENT_SIZE {
-                    return Err(format!("Max attachment size of {MAX_ATTACHMENT_SIZE} reached").into());
-                }
+    let bytes = resp.bytes().await?;
 
-                Ok(CdnResult::Buffer { data: bytes })
-            }
-        }
+    if bytes.len() > MAX_ATTACHMENT_SIZE {
+        return Err(format!("Max attachment size of {MAX_ATTACHMENT_SIZE} reached").into());
     }
+
+    Ok(bytes)
 }
 
 fn get_content_length_from_headers(resp: &reqwest::Response) -> Option<usize> {
@@ -1,12 +1,13 @@
-use crate::worker::workerstate::WorkerState;
+use crate::geese::stratum::Stratum;
 use crate::worker::workervmmanager::Id;
 use dapi::{ChannelId, GuildId, UserId, controller::{DiscordProvider, DiscordProviderContext}, dhttp::Client};
 use serde_json::Value;
 
+/// Discord provider of a tenant, used by templates (and the master's outbox sender)
 #[derive(Clone)]
-pub(super) struct ArDiscordProvider {
+pub(crate) struct ArDiscordProvider {
     pub id: Id,
-    pub state: WorkerState,
+    pub stratum: Stratum,
 }
 
 impl ArDiscordProvider {
@@ -37,7 +38,7 @@ impl DiscordProvider for ArDiscordProvider {
         &self,
     ) -> Result<Value, crate::Error> {
         let guild_id = self.guild_id()?;
-        let obj = self.state.stratum.guild(guild_id).await?;
+        let obj = self.stratum.guild(guild_id).await?;
         let Some(obj) = obj else { return Ok(serde_json::Value::Null) };
         Ok(obj)
     }
@@ -47,7 +48,7 @@ impl DiscordProvider for ArDiscordProvider {
         user_id: UserId,
     ) -> Result<Value, crate::Error> {
         let guild_id = self.guild_id()?;
-        let obj = self.state.stratum.guild_member(guild_id, user_id).await?;
+        let obj = self.stratum.guild_member(guild_id, user_id).await?;
         let Some(obj) = obj else { return Ok(serde_json::Value::Null) };
         Ok(obj)
     }
@@ -56,7 +57,7 @@ impl DiscordProvider for ArDiscordProvider {
         &self,
     ) -> Result<Value, crate::Error> {
         let guild_id = self.guild_id()?;
-        let obj = self.state.stratum.guild_channels(guild_id).await?;
+        let obj = self.stratum.guild_channels(guild_id).await?;
         let Some(obj) = obj else { return Ok(serde_json::Value::Null) };
         Ok(obj)
     }
@@ -66,7 +67,7 @@ impl DiscordProvider for ArDiscordProvider {
     ) -> Result<Value, crate::Error>
     {
         let guild_id = self.guild_id()?;
-        let obj = self.state.stratum.guild_roles(guild_id).await?;
+        let obj = self.stratum.guild_roles(guild_id).await?;
         let Some(obj) = obj else { return Ok(serde_json::Value::Null) };
         Ok(obj)
     }
@@ -76,7 +77,7 @@ impl DiscordProvider for ArDiscordProvider {
         channel_id: ChannelId,
     ) -> Result<Value, crate::Error> {
         let guild_id = self.guild_id()?;
-        let channel = self.state.stratum.channel(channel_id).await?;
+        let channel = self.stratum.channel(channel_id).await?;
 
         let Some(channel) = channel else {
             return Ok(serde_json::Value::Null);
@@ -98,6 +99,6 @@ impl DiscordProvider for ArDiscordProvider {
     }
 
     fn dhttp(&self) -> &Client {
-        &self.state.stratum.discord_http()
+        &self.stratum.discord_http()
     }
 }
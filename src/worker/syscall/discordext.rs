@@ -0,0 +1,757 @@
+use std::collections::HashSet;
+
+use dapi::{ChannelId, GuildId, Permissions, RoleId, UserId, dhttp::HttpCall, types::{Channel, Member, PartialGuild}};
+
+use khronos_runtime::rt::mluau::prelude::*;
+use serde_json::{Value, json};
+
+use crate::{CONFIG, geese::{modargs::{self, Target}, ratelimit::RlExceededError}, worker::{syscall::SyscallHandler, workervmmanager::Id}};
+
+/// Discord channel types
+const GUILD_TEXT: u8 = 0;
+const GUILD_VOICE: u8 = 2;
+const GUILD_STAGE_VOICE: u8 = 13;
+
+/// Only privacy level Discord still allows for stage instances (GUILD_ONLY)
+const STAGE_PRIVACY_GUILD_ONLY: u8 = 2;
+
+const MAX_STAGE_TOPIC_LENGTH: usize = 120;
+const MAX_AUDIT_LOG_REASON_LENGTH: usize = 512;
+
+const MIN_GUILD_NAME_LENGTH: usize = 2;
+const MAX_GUILD_NAME_LENGTH: usize = 100;
+
+/// Highest verification level (VERY_HIGH, verified phone number)
+const MAX_VERIFICATION_LEVEL: u8 = 4;
+
+/// All system channel flags Discord currently defines (bits 0-5)
+const SYSTEM_CHANNEL_FLAGS_ALL: u64 = 0b11_1111;
+
+/// AFK timeouts (in seconds) Discord allows
+const AFK_TIMEOUTS: [u32; 5] = [60, 300, 900, 1800, 3600];
+
+/// Days of inactivity Discord allows pruning members by
+const MIN_PRUNE_DAYS: u8 = 1;
+const MAX_PRUNE_DAYS: u8 = 30;
+
+const MAX_WELCOME_CHANNELS: usize = 5;
+const MAX_WELCOME_DESCRIPTION_LENGTH: usize = 140;
+const MAX_ONBOARDING_PROMPTS: usize = 15;
+const MAX_ONBOARDING_PROMPT_OPTIONS: usize = 50;
+const MAX_ONBOARDING_TITLE_LENGTH: usize = 100;
+
+/// Members searched for when resolving a target by name
+const MOD_TARGET_SEARCH_LIMIT: u16 = 25;
+
+/// A channel shown on the welcome screen
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct WelcomeScreenChannel {
+    channel_id: ChannelId,
+    description: String,
+    emoji_id: Option<String>,
+    emoji_name: Option<String>,
+}
+
+/// A prompt of the onboarding flow
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct OnboardingPrompt {
+    /// Existing prompts keep their ID, new prompts need any unique ID (Discord replaces it)
+    id: String,
+    /// 0 for multiple choice, 1 for dropdown
+    r#type: u8,
+    title: String,
+    options: Vec<OnboardingPromptOption>,
+    #[serde(default)]
+    single_select: bool,
+    #[serde(default)]
+    required: bool,
+    #[serde(default)]
+    in_onboarding: bool,
+}
+
+/// An option of an onboarding prompt
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct OnboardingPromptOption {
+    id: Option<String>,
+    #[serde(default)]
+    channel_ids: Vec<ChannelId>,
+    #[serde(default)]
+    role_ids: Vec<RoleId>,
+    emoji_id: Option<String>,
+    emoji_name: Option<String>,
+    emoji_animated: Option<bool>,
+    title: String,
+    description: Option<String>,
+}
+
+/// Discord API calls not covered by the Discord plugin
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "op")]
+pub enum DiscordExtCall {
+    /// Starts a stage instance in a stage channel
+    CreateStageInstance {
+        channel_id: ChannelId,
+        topic: String,
+        privacy_level: Option<u8>,
+        /// Notify @everyone that the stage started (needs Mention Everyone)
+        send_start_notification: Option<bool>,
+        /// Scheduled event the stage instance belongs to
+        guild_scheduled_event_id: Option<String>,
+        reason: Option<String>,
+    },
+    /// Returns the stage instance of a stage channel
+    GetStageInstance {
+        channel_id: ChannelId,
+    },
+    /// Edits the stage instance of a stage channel
+    EditStageInstance {
+        channel_id: ChannelId,
+        topic: Option<String>,
+        privacy_level: Option<u8>,
+        reason: Option<String>,
+    },
+    /// Ends the stage instance of a stage channel
+    DeleteStageInstance {
+        channel_id: ChannelId,
+        reason: Option<String>,
+    },
+    /// Edits the settings of the guild
+    ///
+    /// Unlike the Discord plugin's `ModifyGuild`, only settings useful for automation (e.g. raising the verification
+    /// level during a raid) can be changed, not e.g. the icon, owner or safety settings
+    EditGuild {
+        name: Option<String>,
+        verification_level: Option<u8>,
+        system_channel_id: Option<ChannelId>,
+        system_channel_flags: Option<u64>,
+        afk_channel_id: Option<ChannelId>,
+        afk_timeout: Option<u32>,
+        reason: Option<String>,
+    },
+    /// Returns the vanity invite of the guild
+    GetVanityUrl {},
+    /// Returns how many members a prune with the same parameters would kick, without kicking anyone
+    GetPruneCount {
+        /// Days of inactivity
+        days: u8,
+        /// Also prune members with these roles (by default only members without roles are pruned)
+        include_roles: Option<Vec<RoleId>>,
+    },
+    /// Kicks members inactive for `days`
+    BeginPrune {
+        /// Days of inactivity
+        days: u8,
+        /// Also prune members with these roles (by default only members without roles are pruned)
+        include_roles: Option<Vec<RoleId>>,
+        /// Abort if the prune would kick more members than this (as counted by `GetPruneCount`)
+        max_count: Option<u64>,
+        reason: Option<String>,
+    },
+    /// Returns the welcome screen of the guild
+    GetWelcomeScreen {},
+    /// Edits the welcome screen of the guild
+    EditWelcomeScreen {
+        enabled: Option<bool>,
+        /// Replaces the channels shown (up to 5)
+        welcome_channels: Option<Vec<WelcomeScreenChannel>>,
+        description: Option<String>,
+        reason: Option<String>,
+    },
+    /// Returns the onboarding flow of the guild
+    GetOnboarding {},
+    /// Edits the onboarding flow of the guild
+    EditOnboarding {
+        /// Replaces all prompts
+        prompts: Option<Vec<OnboardingPrompt>>,
+        default_channel_ids: Option<Vec<ChannelId>>,
+        enabled: Option<bool>,
+        /// 0 to only count default channels towards the onboarding requirements, 1 to also count prompts
+        mode: Option<u8>,
+        reason: Option<String>,
+    },
+    /// Parses the arguments of a moderation command such as `@user 2h spamming links` into its target, duration
+    /// and reason, resolving the target to a member of the guild
+    ParseModerationArgs {
+        input: String,
+    },
+}
+
+impl DiscordExtCall {
+    /// Returns the name of the op, used as its Discord ratelimit bucket
+    pub fn api_name(&self) -> &'static str {
+        match self {
+            Self::CreateStageInstance { .. } => "CreateStageInstance",
+            Self::GetStageInstance { .. } => "GetStageInstance",
+            Self::EditStageInstance { .. } => "EditStageInstance",
+            Self::DeleteStageInstance { .. } => "DeleteStageInstance",
+            Self::EditGuild { .. } => "EditGuild",
+            Self::GetVanityUrl { .. } => "GetVanityUrl",
+            Self::GetPruneCount { .. } => "GetPruneCount",
+            Self::BeginPrune { .. } => "BeginPrune",
+            Self::GetWelcomeScreen { .. } => "GetWelcomeScreen",
+            Self::EditWelcomeScreen { .. } => "EditWelcomeScreen",
+            Self::GetOnboarding { .. } => "GetOnboarding",
+            Self::EditOnboarding { .. } => "EditOnboarding",
+            Self::ParseModerationArgs { .. } => "ParseModerationArgs",
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op")]
+pub enum DiscordExtResult {
+    StageInstance {
+        data: Value,
+    },
+    Deleted {},
+    Guild {
+        data: Value,
+    },
+    VanityUrl {
+        data: Value,
+    },
+    PruneCount {
+        /// Number of members pruned (or that would be pruned)
+        pruned: u64,
+    },
+    WelcomeScreen {
+        data: Value,
+    },
+    Onboarding {
+        data: Value,
+    },
+    ModerationArgs {
+        user_id: UserId,
+        /// The targeted member, None if a user not in the guild was targeted by mention or ID
+        member: Option<Value>,
+        duration_secs: Option<u64>,
+        permanent: bool,
+        reason: Option<String>,
+    },
+}
+
+impl IntoLua for DiscordExtResult {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        match self {
+            Self::StageInstance { data } => {
+                table.set("op", "StageInstance")?;
+                table.set("data", lua.to_value_with(&data, khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS)?)?;
+            }
+            Self::Deleted {} => {
+                table.set("op", "Deleted")?;
+            }
+            Self::Guild { data } => {
+                table.set("op", "Guild")?;
+                table.set("data", lua.to_value_with(&data, khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS)?)?;
+            }
+            Self::VanityUrl { data } => {
+                table.set("op", "VanityUrl")?;
+                table.set("data", lua.to_value_with(&data, khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS)?)?;
+            }
+            Self::PruneCount { pruned } => {
+                table.set("op", "PruneCount")?;
+                table.set("pruned", pruned)?;
+            }
+            Self::WelcomeScreen { data } => {
+                table.set("op", "WelcomeScreen")?;
+                table.set("data", lua.to_value_with(&data, khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS)?)?;
+            }
+            Self::Onboarding { data } => {
+                table.set("op", "Onboarding")?;
+                table.set("data", lua.to_value_with(&data, khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS)?)?;
+            }
+            Self::ModerationArgs { user_id, member, duration_secs, permanent, reason } => {
+                table.set("op", "ModerationArgs")?;
+                table.set("user_id", user_id.to_string())?;
+                if let Some(member) = member {
+                    table.set("member", lua.to_value_with(&member, khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS)?)?;
+                }
+                table.set("duration_secs", duration_secs)?;
+                table.set("permanent", permanent)?;
+                table.set("reason", reason)?;
+            }
+        }
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
+
+impl DiscordExtCall {
+    pub(super) async fn exec(self, id: Id, handler: &SyscallHandler) -> Result<DiscordExtResult, crate::Error> {
+        let Id::Guild(guild_id) = id else {
+            return Err(format!("{} can only be used in servers", self.api_name()).into());
+        };
+        handler.ratelimits.discord.check(self.api_name(), ()).map_err(RlExceededError)?;
+
+        match self {
+            Self::CreateStageInstance { channel_id, topic, privacy_level, send_start_notification, guild_scheduled_event_id, reason } => {
+                validate_topic(&topic)?;
+                validate_privacy_level(privacy_level)?;
+                let send_start_notification = send_start_notification.unwrap_or(false);
+                let needed = if send_start_notification { stage_moderator_permissions() | Permissions::MENTION_EVERYONE } else { stage_moderator_permissions() };
+                check_stage_channel(handler, guild_id, channel_id, needed).await?;
+
+                let data = rest(handler, reqwest::Method::POST, "/stage-instances", reason.as_deref())?
+                    .json(&json!({
+                        "channel_id": channel_id,
+                        "topic": topic,
+                        "privacy_level": privacy_level.unwrap_or(STAGE_PRIVACY_GUILD_ONLY),
+                        "send_start_notification": send_start_notification,
+                        "guild_scheduled_event_id": guild_scheduled_event_id,
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::StageInstance { data })
+            }
+            Self::GetStageInstance { channel_id } => {
+                // Reading needs no permissions beyond the channel belonging to the server
+                check_stage_channel(handler, guild_id, channel_id, Permissions::empty()).await?;
+
+                let data = rest(handler, reqwest::Method::GET, &format!("/stage-instances/{channel_id}"), None)?
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::StageInstance { data })
+            }
+            Self::EditStageInstance { channel_id, topic, privacy_level, reason } => {
+                if let Some(ref topic) = topic {
+                    validate_topic(topic)?;
+                }
+                validate_privacy_level(privacy_level)?;
+                check_stage_channel(handler, guild_id, channel_id, stage_moderator_permissions()).await?;
+
+                let mut body = serde_json::Map::new();
+                if let Some(topic) = topic {
+                    body.insert("topic".to_string(), Value::String(topic));
+                }
+                if let Some(privacy_level) = privacy_level {
+                    body.insert("privacy_level".to_string(), privacy_level.into());
+                }
+
+                let data = rest(handler, reqwest::Method::PATCH, &format!("/stage-instances/{channel_id}"), reason.as_deref())?
+                    .json(&body)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::StageInstance { data })
+            }
+            Self::DeleteStageInstance { channel_id, reason } => {
+                check_stage_channel(handler, guild_id, channel_id, stage_moderator_permissions()).await?;
+
+                rest(handler, reqwest::Method::DELETE, &format!("/stage-instances/{channel_id}"), reason.as_deref())?
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(DiscordExtResult::Deleted {})
+            }
+            Self::EditGuild { name, verification_level, system_channel_id, system_channel_flags, afk_channel_id, afk_timeout, reason } => {
+                check_manage_guild(handler, guild_id).await?;
+
+                let mut body = serde_json::Map::new();
+                if let Some(name) = name {
+                    let len = name.chars().count();
+                    if !(MIN_GUILD_NAME_LENGTH..=MAX_GUILD_NAME_LENGTH).contains(&len) {
+                        return Err(format!("Server name must be between {MIN_GUILD_NAME_LENGTH} and {MAX_GUILD_NAME_LENGTH} characters").into());
+                    }
+                    body.insert("name".to_string(), Value::String(name));
+                }
+                if let Some(verification_level) = verification_level {
+                    if verification_level > MAX_VERIFICATION_LEVEL {
+                        return Err(format!("Verification level must be between 0 and {MAX_VERIFICATION_LEVEL}").into());
+                    }
+                    body.insert("verification_level".to_string(), verification_level.into());
+                }
+                if let Some(system_channel_id) = system_channel_id {
+                    let channel = guild_channel(handler, guild_id, system_channel_id).await?;
+                    if channel.kind.0 != GUILD_TEXT {
+                        return Err("System channel must be a text channel".into());
+                    }
+                    body.insert("system_channel_id".to_string(), system_channel_id.to_string().into());
+                }
+                if let Some(system_channel_flags) = system_channel_flags {
+                    if system_channel_flags & !SYSTEM_CHANNEL_FLAGS_ALL != 0 {
+                        return Err("Unknown system channel flags".into());
+                    }
+                    body.insert("system_channel_flags".to_string(), system_channel_flags.into());
+                }
+                if let Some(afk_channel_id) = afk_channel_id {
+                    let channel = guild_channel(handler, guild_id, afk_channel_id).await?;
+                    if channel.kind.0 != GUILD_VOICE {
+                        return Err("AFK channel must be a voice channel".into());
+                    }
+                    body.insert("afk_channel_id".to_string(), afk_channel_id.to_string().into());
+                }
+                if let Some(afk_timeout) = afk_timeout {
+                    if !AFK_TIMEOUTS.contains(&afk_timeout) {
+                        return Err(format!("AFK timeout must be one of {AFK_TIMEOUTS:?} seconds").into());
+                    }
+                    body.insert("afk_timeout".to_string(), afk_timeout.into());
+                }
+                if body.is_empty() {
+                    return Err("No server settings to edit were provided".into());
+                }
+
+                let data = rest(handler, reqwest::Method::PATCH, &format!("/guilds/{guild_id}"), reason.as_deref())?
+                    .json(&body)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::Guild { data })
+            }
+            Self::GetVanityUrl {} => {
+                check_manage_guild(handler, guild_id).await?;
+
+                let data = rest(handler, reqwest::Method::GET, &format!("/guilds/{guild_id}/vanity-url"), None)?
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::VanityUrl { data })
+            }
+            Self::GetPruneCount { days, include_roles } => {
+                validate_prune_days(days)?;
+                check_bot_permissions(handler, guild_id, Permissions::KICK_MEMBERS).await?;
+
+                let pruned = prune_count(handler, guild_id, days, include_roles.as_deref()).await?;
+                Ok(DiscordExtResult::PruneCount { pruned })
+            }
+            Self::BeginPrune { days, include_roles, max_count, reason } => {
+                validate_prune_days(days)?;
+                check_bot_permissions(handler, guild_id, Permissions::KICK_MEMBERS | Permissions::MANAGE_GUILD).await?;
+
+                if let Some(max_count) = max_count {
+                    let count = prune_count(handler, guild_id, days, include_roles.as_deref()).await?;
+                    if count > max_count {
+                        return Err(format!("Prune would kick {count} members, which is more than the maximum of {max_count}").into());
+                    }
+                }
+
+                let res: Value = rest(handler, reqwest::Method::POST, &format!("/guilds/{guild_id}/prune"), reason.as_deref())?
+                    .json(&json!({
+                        "days": days,
+                        "include_roles": include_roles.unwrap_or_default(),
+                        // Counting is slow on large servers and the count is known beforehand if max_count is set
+                        "compute_prune_count": max_count.is_none(),
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+
+                let pruned = res.get("pruned").and_then(|v| v.as_u64()).unwrap_or_default();
+                Ok(DiscordExtResult::PruneCount { pruned })
+            }
+            Self::GetWelcomeScreen {} => {
+                check_manage_guild(handler, guild_id).await?;
+
+                let data = rest(handler, reqwest::Method::GET, &format!("/guilds/{guild_id}/welcome-screen"), None)?
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::WelcomeScreen { data })
+            }
+            Self::EditWelcomeScreen { enabled, welcome_channels, description, reason } => {
+                check_manage_guild(handler, guild_id).await?;
+
+                let mut body = serde_json::Map::new();
+                if let Some(enabled) = enabled {
+                    body.insert("enabled".to_string(), enabled.into());
+                }
+                if let Some(description) = description {
+                    if description.chars().count() > MAX_WELCOME_DESCRIPTION_LENGTH {
+                        return Err(format!("Welcome screen description must be at most {MAX_WELCOME_DESCRIPTION_LENGTH} characters").into());
+                    }
+                    body.insert("description".to_string(), Value::String(description));
+                }
+                if let Some(welcome_channels) = welcome_channels {
+                    if welcome_channels.len() > MAX_WELCOME_CHANNELS {
+                        return Err(format!("Welcome screen can show at most {MAX_WELCOME_CHANNELS} channels").into());
+                    }
+                    let channel_ids = guild_entity_ids(handler.state.stratum.guild_channels(guild_id).await?);
+                    for channel in &welcome_channels {
+                        if !channel_ids.contains(&channel.channel_id.to_string()) {
+                            return Err(format!("Welcome channel {} does not belong to the guild", channel.channel_id).into());
+                        }
+                        if channel.description.is_empty() {
+                            return Err(format!("Welcome channel {} needs a description", channel.channel_id).into());
+                        }
+                    }
+                    body.insert("welcome_channels".to_string(), serde_json::to_value(welcome_channels)?);
+                }
+                if body.is_empty() {
+                    return Err("No welcome screen settings to edit were provided".into());
+                }
+
+                let data = rest(handler, reqwest::Method::PATCH, &format!("/guilds/{guild_id}/welcome-screen"), reason.as_deref())?
+                    .json(&body)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::WelcomeScreen { data })
+            }
+            Self::GetOnboarding {} => {
+                check_manage_guild(handler, guild_id).await?;
+
+                let data = rest(handler, reqwest::Method::GET, &format!("/guilds/{guild_id}/onboarding"), None)?
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::Onboarding { data })
+            }
+            Self::EditOnboarding { prompts, default_channel_ids, enabled, mode, reason } => {
+                // Onboarding can grant roles, so the bot must be able to manage them too
+                check_bot_permissions(handler, guild_id, Permissions::MANAGE_GUILD | Permissions::MANAGE_ROLES).await?;
+
+                let channel_ids = guild_entity_ids(handler.state.stratum.guild_channels(guild_id).await?);
+                let mut body = serde_json::Map::new();
+                if let Some(prompts) = prompts {
+                    let role_ids = guild_entity_ids(handler.state.stratum.guild_roles(guild_id).await?);
+                    validate_onboarding_prompts(&prompts, &channel_ids, &role_ids)?;
+                    body.insert("prompts".to_string(), serde_json::to_value(prompts)?);
+                }
+                if let Some(default_channel_ids) = default_channel_ids {
+                    if let Some(channel_id) = default_channel_ids.iter().find(|c| !channel_ids.contains(&c.to_string())) {
+                        return Err(format!("Default channel {channel_id} does not belong to the guild").into());
+                    }
+                    body.insert("default_channel_ids".to_string(), serde_json::to_value(default_channel_ids)?);
+                }
+                if let Some(enabled) = enabled {
+                    body.insert("enabled".to_string(), enabled.into());
+                }
+                if let Some(mode) = mode {
+                    if mode > 1 {
+                        return Err("Onboarding mode must be 0 or 1".into());
+                    }
+                    body.insert("mode".to_string(), mode.into());
+                }
+                if body.is_empty() {
+                    return Err("No onboarding settings to edit were provided".into());
+                }
+
+                let data = rest(handler, reqwest::Method::PUT, &format!("/guilds/{guild_id}/onboarding"), reason.as_deref())?
+                    .json(&body)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(DiscordExtResult::Onboarding { data })
+            }
+            Self::ParseModerationArgs { input } => {
+                let args = modargs::parse(&input)?;
+                let (user_id, member) = match args.target {
+                    Target::Id(user_id) => (user_id, handler.state.stratum.guild_member(guild_id, user_id).await?),
+                    Target::Name { name, discriminator } => {
+                        let member = find_member_by_name(handler, guild_id, &name, discriminator.as_deref()).await?;
+                        let user_id: UserId = serde_json::from_value(member["user"]["id"].clone())?;
+                        (user_id, Some(member))
+                    }
+                };
+                Ok(DiscordExtResult::ModerationArgs { user_id, member, duration_secs: args.duration_secs, permanent: args.permanent, reason: args.reason })
+            }
+        }
+    }
+}
+
+/// Finds the one member of the guild going by `name`, erroring if none or several do
+async fn find_member_by_name(handler: &SyscallHandler, guild_id: GuildId, name: &str, discriminator: Option<&str>) -> Result<Value, crate::Error> {
+    let res = handler.state.stratum.discord_http().call_json(HttpCall::SearchGuildMembers { guild_id, query: name, limit: Some(MOD_TARGET_SEARCH_LIMIT) }).await?;
+    let Value::Array(members) = res else {
+        return Err("Invalid member search response".into());
+    };
+
+    let mut matched = members.into_iter().filter(|m| modargs::member_matches(m, name, discriminator));
+    match (matched.next(), matched.next()) {
+        (Some(member), None) => Ok(member),
+        (None, _) => Err(format!("No member named {name} was found").into()),
+        (Some(_), Some(_)) => Err(format!("Several members are named {name}, mention them or use their ID instead").into()),
+    }
+}
+
+/// Permissions Discord requires to manage the stage instance of a channel (being a stage moderator)
+fn stage_moderator_permissions() -> Permissions {
+    Permissions::MANAGE_CHANNELS | Permissions::MUTE_MEMBERS | Permissions::MOVE_MEMBERS
+}
+
+fn validate_topic(topic: &str) -> Result<(), crate::Error> {
+    let len = topic.chars().count();
+    if len == 0 || len > MAX_STAGE_TOPIC_LENGTH {
+        return Err(format!("Stage topic must be between 1 and {MAX_STAGE_TOPIC_LENGTH} characters").into());
+    }
+    Ok(())
+}
+
+fn validate_privacy_level(privacy_level: Option<u8>) -> Result<(), crate::Error> {
+    if let Some(privacy_level) = privacy_level && privacy_level != STAGE_PRIVACY_GUILD_ONLY {
+        return Err(format!("Stage privacy level must be {STAGE_PRIVACY_GUILD_ONLY} (guild only)").into());
+    }
+    Ok(())
+}
+
+/// Ensures a channel is a stage channel of the guild and the bot has `needed` permissions in it
+async fn check_stage_channel(handler: &SyscallHandler, guild_id: GuildId, channel_id: ChannelId, needed: Permissions) -> Result<(), crate::Error> {
+    let channel = guild_channel(handler, guild_id, channel_id).await?;
+    if channel.kind.0 != GUILD_STAGE_VOICE {
+        return Err(format!("Channel {channel_id} is not a stage channel").into());
+    }
+    if needed.is_empty() {
+        return Ok(());
+    }
+
+    let perms = bot_permissions(handler, guild_id, Some(&channel)).await?;
+    if !perms.contains(needed) {
+        return Err(format!("The bot is missing permissions in the stage channel: {:?}", needed - perms).into());
+    }
+    Ok(())
+}
+
+/// Fetches a channel, ensuring it belongs to the guild
+async fn guild_channel(handler: &SyscallHandler, guild_id: GuildId, channel_id: ChannelId) -> Result<Channel, crate::Error> {
+    let channel: Channel = serde_json::from_value(handler.state.stratum.channel(channel_id).await?.ok_or("Channel not found")?)?;
+    if channel.guild_id != Some(guild_id) {
+        return Err(format!("Channel {channel_id} does not belong to the guild").into());
+    }
+    Ok(channel)
+}
+
+/// Returns the permissions of the bot in the guild, or in `channel` if set
+async fn bot_permissions(handler: &SyscallHandler, guild_id: GuildId, channel: Option<&Channel>) -> Result<Permissions, crate::Error> {
+    let stratum = &handler.state.stratum;
+    let guild: PartialGuild = serde_json::from_value(stratum.guild(guild_id).await?.ok_or("Guild not found")?)?;
+    let bot_id = stratum.current_user().id;
+    let bot_member: Member = serde_json::from_value(stratum.guild_member(guild_id, bot_id).await?.ok_or("Failed to find bot user info")?)?;
+
+    Ok(match channel {
+        Some(channel) => guild.user_permissions_in(channel, &bot_member),
+        None => guild.member_permissions(&bot_member),
+    })
+}
+
+/// Ensures the bot has Manage Server, which all guild settings calls need
+async fn check_manage_guild(handler: &SyscallHandler, guild_id: GuildId) -> Result<(), crate::Error> {
+    if !bot_permissions(handler, guild_id, None).await?.contains(Permissions::MANAGE_GUILD) {
+        return Err("The bot needs the Manage Server permission to manage server settings".into());
+    }
+    Ok(())
+}
+
+/// Ensures the bot has `needed` permissions in the guild
+async fn check_bot_permissions(handler: &SyscallHandler, guild_id: GuildId, needed: Permissions) -> Result<(), crate::Error> {
+    let perms = bot_permissions(handler, guild_id, None).await?;
+    if !perms.contains(needed) {
+        return Err(format!("The bot is missing permissions: {:?}", needed - perms).into());
+    }
+    Ok(())
+}
+
+fn validate_prune_days(days: u8) -> Result<(), crate::Error> {
+    if !(MIN_PRUNE_DAYS..=MAX_PRUNE_DAYS).contains(&days) {
+        return Err(format!("Prune days must be between {MIN_PRUNE_DAYS} and {MAX_PRUNE_DAYS}").into());
+    }
+    Ok(())
+}
+
+/// Returns how many members a prune would kick
+async fn prune_count(handler: &SyscallHandler, guild_id: GuildId, days: u8, include_roles: Option<&[RoleId]>) -> Result<u64, crate::Error> {
+    let mut query = vec![("days", days.to_string())];
+    if let Some(include_roles) = include_roles && !include_roles.is_empty() {
+        query.push(("include_roles", include_roles.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(",")));
+    }
+
+    let res: Value = rest(handler, reqwest::Method::GET, &format!("/guilds/{guild_id}/prune"), None)?
+        .query(&query)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(res.get("pruned").and_then(|v| v.as_u64()).unwrap_or_default())
+}
+
+/// Returns the IDs of a list of guild channels or roles
+fn guild_entity_ids(list: Option<Value>) -> HashSet<String> {
+    let entities: Vec<&Value> = match &list {
+        Some(Value::Array(a)) => a.iter().collect(),
+        Some(Value::Object(m)) => m.values().collect(),
+        _ => Vec::new(),
+    };
+    entities.into_iter().filter_map(|e| Some(e.get("id")?.as_str()?.to_string())).collect()
+}
+
+/// Validates onboarding prompts, ensuring all channels and roles they reference belong to the guild
+fn validate_onboarding_prompts(prompts: &[OnboardingPrompt], channel_ids: &HashSet<String>, role_ids: &HashSet<String>) -> Result<(), crate::Error> {
+    if prompts.len() > MAX_ONBOARDING_PROMPTS {
+        return Err(format!("Onboarding can have at most {MAX_ONBOARDING_PROMPTS} prompts").into());
+    }
+
+    for prompt in prompts {
+        if prompt.r#type > 1 {
+            return Err(format!("Prompt {}: type must be 0 (multiple choice) or 1 (dropdown)", prompt.title).into());
+        }
+        if prompt.title.is_empty() || prompt.title.chars().count() > MAX_ONBOARDING_TITLE_LENGTH {
+            return Err(format!("Prompt titles must be between 1 and {MAX_ONBOARDING_TITLE_LENGTH} characters").into());
+        }
+        if prompt.options.is_empty() || prompt.options.len() > MAX_ONBOARDING_PROMPT_OPTIONS {
+            return Err(format!("Prompt {}: must have between 1 and {MAX_ONBOARDING_PROMPT_OPTIONS} options", prompt.title).into());
+        }
+
+        for option in &prompt.options {
+            if option.title.is_empty() || option.title.chars().count() > MAX_ONBOARDING_TITLE_LENGTH {
+                return Err(format!("Prompt {}: option titles must be between 1 and {MAX_ONBOARDING_TITLE_LENGTH} characters", prompt.title).into());
+            }
+            if option.channel_ids.is_empty() && option.role_ids.is_empty() {
+                return Err(format!("Prompt {}: option {} must grant at least one channel or role", prompt.title, option.title).into());
+            }
+            if let Some(channel_id) = option.channel_ids.iter().find(|c| !channel_ids.contains(&c.to_string())) {
+                return Err(format!("Prompt {}: channel {channel_id} does not belong to the guild", prompt.title).into());
+            }
+            if let Some(role_id) = option.role_ids.iter().find(|r| !role_ids.contains(&r.to_string())) {
+                return Err(format!("Prompt {}: role {role_id} does not belong to the guild", prompt.title).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rest(handler: &SyscallHandler, method: reqwest::Method, path: &str, reason: Option<&str>) -> Result<reqwest::RequestBuilder, crate::Error> {
+    let mut req = handler.state.reqwest.request(method, format!("{}/api/v10{path}", CONFIG.proxy))
+        .header("Authorization", format!("Bot {}", CONFIG.nirn_token));
+    if let Some(reason) = reason {
+        if reason.chars().count() > MAX_AUDIT_LOG_REASON_LENGTH {
+            return Err(format!("Audit log reason must be at most {MAX_AUDIT_LOG_REASON_LENGTH} characters").into());
+        }
+        req = req.header("X-Audit-Log-Reason", percent_encode(reason));
+    }
+    Ok(req)
+}
+
+/// Percent-encodes an audit log reason, Discord expects it url-encoded to allow non-ASCII reasons
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
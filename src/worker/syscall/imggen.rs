@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use khronos_runtime::rt::mluau::prelude::*;
+
+use crate::{geese::ratelimit::RlExceededError, worker::{chart::{self, ChartSpec}, imggen::ImageSpec, syscall::{SyscallHandler, cdn::download_file}, workervmmanager::Id}};
+
+/// Image generation syscalls
+#[derive(Debug)]
+pub enum ImgGenCall {
+    /// Renders an image from a declarative spec
+    Render {
+        spec: ImageSpec,
+    },
+    /// Renders a line, bar or pie chart
+    Chart {
+        spec: ChartSpec,
+    },
+}
+
+impl FromLua for ImgGenCall {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let LuaValue::Table(tab) = value else {
+            return Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "ImgGenCall".to_string(),
+                message: Some("expected a table".to_string()),
+            })
+        };
+
+        let typ: LuaString = tab.get("op")?;
+        match typ.as_bytes().as_ref() {
+            b"Render" => {
+                let spec: LuaValue = tab.get("spec")?;
+                Ok(ImgGenCall::Render { spec: lua.from_value(spec)? })
+            },
+            b"Chart" => {
+                let spec: LuaValue = tab.get("spec")?;
+                Ok(ImgGenCall::Chart { spec: lua.from_value(spec)? })
+            },
+            _ => {
+                Err(LuaError::FromLuaConversionError {
+                    from: "table",
+                    to: "ImgGenCall".to_string(),
+                    message: Some("invalid op provided".to_string()),
+                })
+            }
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op")]
+pub enum ImgGenResult {
+    Image {
+        data: Bytes
+    },
+    Chart {
+        data: Bytes,
+        alt: String,
+    },
+}
+
+impl IntoLua for ImgGenResult {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        match self {
+            Self::Image { data } => {
+                table.set("op", "Image")?;
+                table.set("data", lua.create_external_buffer(data)?)?;
+            }
+            Self::Chart { data, alt } => {
+                table.set("op", "Chart")?;
+                table.set("data", lua.create_external_buffer(data)?)?;
+                table.set("alt", alt)?;
+            }
+        }
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
+
+impl ImgGenCall {
+    pub(super) async fn exec(self, _id: Id, handler: &SyscallHandler) -> Result<ImgGenResult, crate::Error> {
+        match self {
+            Self::Render { spec } => {
+                handler.ratelimits.imggen.check("Render", ()).map_err(RlExceededError)?;
+                spec.validate()?;
+
+                let imggen = &handler.state.imggen;
+                let mut images = HashMap::new();
+                for url in spec.image_urls() {
+                    let data = imggen.image(url, download_file(&handler.state.reqwest, url)).await?;
+                    images.insert(url.to_string(), data);
+                }
+
+                let data = imggen.render(spec, images).await?;
+                Ok(ImgGenResult::Image { data })
+            }
+            Self::Chart { spec } => {
+                handler.ratelimits.imggen.check("Chart", ()).map_err(RlExceededError)?;
+                spec.validate()?;
+
+                let alt = spec.alt_text();
+                let data = handler.state.imggen.run(move |deadline| chart::render(&spec, deadline)).await?;
+                Ok(ImgGenResult::Chart { data, alt })
+            }
+        }
+    }
+}
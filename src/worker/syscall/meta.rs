@@ -1,11 +1,13 @@
 use khronos_runtime::{core::datetime::DateTime, rt::mluau::prelude::*};
 
-use crate::{geese::ratelimit::RlExceededError, worker::{syscall::SyscallHandler, workervmmanager::Id}};
+use crate::{geese::{botstatus::{self, DispatchLatency}, ratelimit::RlExceededError}, worker::{syscall::SyscallHandler, workervmmanager::Id}};
 
 /// Metadata syscalls
 #[derive(Debug)]
 pub enum MetaCall {
     GetStats {},
+    /// Returns whether the bot is degraded, for templates to warn users during degraded periods
+    GetBotStatus {},
 }
 
 impl FromLua for MetaCall {
@@ -23,6 +25,9 @@ impl FromLua for MetaCall {
             b"GetStats" => {
                 Ok(MetaCall::GetStats { })
             },
+            b"GetBotStatus" => {
+                Ok(MetaCall::GetBotStatus { })
+            },
             _ => {
                 Err(LuaError::FromLuaConversionError {
                     from: "table",
@@ -34,12 +39,22 @@ impl FromLua for MetaCall {
     }
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op")]
 pub enum MetaResult {
     Stats {
         total_guilds: u64,
         total_users: u64,
         last_started_at: chrono::DateTime<chrono::Utc>,
-    }
+    },
+    BotStatus {
+        degraded: bool,
+        degraded_reasons: Vec<String>,
+        /// Status and latency of each shard by shard ID
+        shards: Vec<(u32, String, f64)>,
+        /// Latency of the recent dispatches of the worker
+        dispatch: DispatchLatency,
+    },
 }
 
 impl IntoLua for MetaResult {
@@ -52,6 +67,30 @@ impl IntoLua for MetaResult {
                 table.set("total_users", total_users)?;
                 table.set("last_started_at", DateTime::from_utc(last_started_at))?;
             },
+            Self::BotStatus { degraded, degraded_reasons, shards, dispatch } => {
+                table.set("op", "BotStatus")?;
+                table.set("degraded", degraded)?;
+                table.set("degraded_reasons", degraded_reasons)?;
+
+                let shard_tab = lua.create_table()?;
+                for (shard_id, status, latency) in shards {
+                    let shard = lua.create_table()?;
+                    shard.set("status", status)?;
+                    shard.set("latency", latency)?;
+                    shard.set_readonly(true);
+                    shard_tab.set(shard_id, shard)?;
+                }
+                shard_tab.set_readonly(true);
+                table.set("shards", shard_tab)?;
+
+                let dispatch_tab = lua.create_table()?;
+                dispatch_tab.set("samples", dispatch.samples)?;
+                dispatch_tab.set("p50_ms", dispatch.p50_ms)?;
+                dispatch_tab.set("p95_ms", dispatch.p95_ms)?;
+                dispatch_tab.set("max_ms", dispatch.max_ms)?;
+                dispatch_tab.set_readonly(true);
+                table.set("dispatch", dispatch_tab)?;
+            },
         }
         table.set_readonly(true); // We want StateExecResult's to be immutable
         Ok(LuaValue::Table(table))
@@ -72,6 +111,20 @@ impl MetaCall {
                     last_started_at: crate::CONFIG.start_time,
                 })
             }
+            Self::GetBotStatus {} => {
+                handler.ratelimits.runtime.check("GetBotStatus", ()).map_err(RlExceededError)?;
+                let resp = handler.state.stratum.get_status().await?;
+                let dispatch = DispatchLatency::from_samples(handler.state.load.recent_dispatches());
+
+                // Whether the direct gateway fallback is active is only known to the master
+                let degraded_reasons = botstatus::degraded_reasons(resp.shards.iter().map(|s| s.latency), &dispatch, false);
+                Ok(MetaResult::BotStatus {
+                    degraded: !degraded_reasons.is_empty(),
+                    degraded_reasons,
+                    shards: resp.shards.into_iter().map(|s| (s.shard_id, s.state().as_str_name().to_string(), s.latency)).collect(),
+                    dispatch,
+                })
+            }
         }
     }
 }
\ No newline at end of file
@@ -1,14 +1,18 @@
-mod cdn;
-mod discord;
+pub(crate) mod cdn;
+pub(crate) mod discord;
+mod discordext;
+mod imggen;
 mod meta;
+mod webhook;
 
-use std::sync::Arc;
+use std::{borrow::Cow, cell::RefCell, rc::Rc, sync::Arc};
 
-use crate::{geese::{ratelimit::RlExceededError, state::{FastStateReq, StateDbFlags, StateExecResult, StateOp}, tenantstate::TenantState}, worker::{limits::Ratelimits, syscall::{cdn::{CdnCall, CdnResult}, discord::ArDiscordProvider, meta::{MetaCall, MetaResult}}, workerstate::WorkerState, workertenantstate::WorkerTenantState, workervmmanager::Id}};
+use crate::{geese::{outbox::{OUTBOX_OPS, OutboxEnqueue, OutboxReceipt}, ratelimit::RlExceededError, telemetry, state::{FastStateReq, StateDbFlags, StateExecResult, StateOp}, tenantstate::TenantState}, worker::{abort::AbortSignal, limits::Ratelimits, syscall::{cdn::{CdnCall, CdnResult}, discord::ArDiscordProvider, discordext::{DiscordExtCall, DiscordExtResult}, imggen::{ImgGenCall, ImgGenResult}, meta::{MetaCall, MetaResult}, webhook::{WebhookCall, WebhookResult}}, replay::ReplayState, resultcache::ResultCache, workerstate::WorkerState, workertenantstate::WorkerTenantState, workervmmanager::Id}};
 use dapi::context::DiscordContext;
 use khronos_ext::mlua_scheduler_ext::LuaSchedulerAsyncUserData;
 use khronos_runtime::{primitives::lazy::Lazy, rt::mluau::prelude::*};
 use log::info;
+use opentelemetry::{Context, KeyValue, trace::{FutureExt, SpanKind, Status, TraceContextExt}};
 
 /// The core underlying syscall
 #[derive(Debug)]
@@ -21,11 +25,79 @@ pub enum SyscallArgs {
         op: CdnCall
     },
     Discord {
-        op: dapi::apilist::API
+        op: dapi::apilist::API,
+        /// Set if the call should be durably enqueued into the outbox instead of being made directly
+        outbox: Option<OutboxEnqueue>,
+    },
+    DiscordExt {
+        op: DiscordExtCall
     },
     Meta {
         op: MetaCall
     },
+    Webhook {
+        op: WebhookCall
+    },
+    ImgGen {
+        op: ImgGenCall
+    },
+}
+
+impl SyscallArgs {
+    /// Returns the name of the syscall category, used for feature flag gating (`syscall:{category}`)
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::State { .. } => "State",
+            Self::Cdn { .. } => "Cdn",
+            Self::Discord { .. } => "Discord",
+            Self::DiscordExt { .. } => "DiscordExt",
+            Self::Meta { .. } => "Meta",
+            Self::Webhook { .. } => "Webhook",
+            Self::ImgGen { .. } => "ImgGen",
+        }
+    }
+
+    /// Returns whether the syscall may have side effects, the results of templates making such syscalls are never cached
+    pub fn has_side_effects(&self) -> bool {
+        match self {
+            Self::State { ops } => !ops.iter().all(|op| op.is_read_only()),
+            Self::Discord { op, outbox } => {
+                let op_name = op.api_name();
+                outbox.is_some() || !(op_name.starts_with("Get") || op_name.starts_with("AntiRaid"))
+            }
+            Self::DiscordExt { op } => {
+                let op_name = op.api_name();
+                !(op_name.starts_with("Get") || op_name.starts_with("Parse"))
+            }
+            Self::Webhook { .. } => true,
+            Self::Cdn { .. } | Self::Meta { .. } | Self::ImgGen { .. } => false,
+        }
+    }
+
+    /// Returns the names of the methods called by the syscall, for plugin usage analytics
+    pub fn methods(&self) -> Vec<&'static str> {
+        match self {
+            Self::State { ops } => ops.iter().map(|op| op.name()).collect(),
+            Self::Cdn { op } => vec![match op {
+                CdnCall::DownloadFile { .. } => "DownloadFile",
+            }],
+            Self::Discord { op, .. } => vec![op.api_name()],
+            Self::DiscordExt { op } => vec![op.api_name()],
+            Self::Meta { op } => vec![match op {
+                MetaCall::GetStats {} => "GetStats",
+                MetaCall::GetBotStatus {} => "GetBotStatus",
+            }],
+            Self::Webhook { op } => vec![match op {
+                WebhookCall::SetSecret { .. } => "SetSecret",
+                WebhookCall::DeleteSecret { .. } => "DeleteSecret",
+                WebhookCall::Deliver { .. } => "Deliver",
+            }],
+            Self::ImgGen { op } => vec![match op {
+                ImgGenCall::Render { .. } => "Render",
+                ImgGenCall::Chart { .. } => "Chart",
+            }],
+        }
+    }
 }
 
 impl FromLua for SyscallArgs {
@@ -50,12 +122,29 @@ impl FromLua for SyscallArgs {
             },
             b"Discord" => {
                 let op: LuaValue = tab.get("req")?;
-                Ok(Self::Discord { op: lua.from_value(op)? })
+                // Outboxed calls are stored as sent and parsed again by the outbox sender
+                let outbox = match tab.get::<Option<bool>>("outbox")? {
+                    Some(true) => Some(OutboxEnqueue { key: tab.get("key")?, action: lua.from_value(op.clone())? }),
+                    _ => None,
+                };
+                Ok(Self::Discord { op: lua.from_value(op)?, outbox })
+            },
+            b"DiscordExt" => {
+                let op: LuaValue = tab.get("req")?;
+                Ok(Self::DiscordExt { op: lua.from_value(op)? })
             },
             b"Meta" => {
                 let op = tab.get("req")?;
                 Ok(Self::Meta { op })
             },
+            b"Webhook" => {
+                let op = tab.get("req")?;
+                Ok(Self::Webhook { op })
+            },
+            b"ImgGen" => {
+                let op = tab.get("req")?;
+                Ok(Self::ImgGen { op })
+            },
             _ => {
                 Err(LuaError::FromLuaConversionError {
                     from: "table",
@@ -67,6 +156,30 @@ impl FromLua for SyscallArgs {
     }
 }
 
+/// A syscall along with the source (template) it should be attributed to for usage accounting
+pub struct SyscallReq {
+    source: Option<String>,
+    /// The recorded (or replayed) execution the syscall was made by, if any
+    execution: Option<u64>,
+    args: SyscallArgs,
+}
+
+impl SyscallReq {
+    /// Source used for syscalls which don't set one (made by builtins)
+    const DEFAULT_SOURCE: &str = "builtins";
+}
+
+impl FromLua for SyscallReq {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let (source, execution) = match value {
+            LuaValue::Table(ref tab) => (tab.get("source")?, tab.get("execution")?),
+            _ => (None, None),
+        };
+        Ok(Self { source, execution, args: SyscallArgs::from_lua(value, lua)? })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum SyscallRet {
     State {
         res: Vec<StateExecResult>,
@@ -76,13 +189,26 @@ pub enum SyscallRet {
         res: CdnResult
     },
     Discord {
-        op: &'static str,
+        op: Cow<'static, str>,
         res: serde_json::Value, 
-        mrm: dapi::apilist::MapResponseMetadata
+        is_primitive_response: bool
+    },
+    /// A Discord call enqueued into the outbox
+    DiscordOutbox {
+        res: OutboxReceipt
+    },
+    DiscordExt {
+        res: DiscordExtResult
     },
     Meta {
         res: MetaResult
     },
+    Webhook {
+        res: WebhookResult
+    },
+    ImgGen {
+        res: ImgGenResult
+    },
 }
 
 impl IntoLua for SyscallRet {
@@ -98,10 +224,10 @@ impl IntoLua for SyscallRet {
                 table.set("op", "Cdn")?;
                 table.set("res", res)?;
             }
-            Self::Discord { op, res, mrm } => {                
+            Self::Discord { op, res, is_primitive_response } => {                
                 let res_table = lua.create_table()?;
-                res_table.set("op", op)?;
-                if mrm.is_primitive_response {
+                res_table.set("op", op.as_ref())?;
+                if is_primitive_response {
                     let v = lua.to_value_with(&res, khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS)?;
                     if !v.is_null() {
                         res_table.set("res", v)?;
@@ -114,10 +240,26 @@ impl IntoLua for SyscallRet {
                 table.set("op", "Discord")?;
                 table.set("res", res_table)?;
             }
+            Self::DiscordOutbox { res } => {
+                table.set("op", "DiscordOutbox")?;
+                table.set("res", lua.to_value(&res)?)?;
+            }
+            Self::DiscordExt { res } => {
+                table.set("op", "DiscordExt")?;
+                table.set("res", res)?;
+            }
             Self::Meta { res } => {
                 table.set("op", "Meta")?;
                 table.set("res", res)?;
             }
+            Self::Webhook { res } => {
+                table.set("op", "Webhook")?;
+                table.set("res", res)?;
+            }
+            Self::ImgGen { res } => {
+                table.set("op", "ImgGen")?;
+                table.set("res", res)?;
+            }
         }
         table.set_readonly(true);
         Ok(LuaValue::Table(table))
@@ -130,24 +272,56 @@ pub struct SyscallHandler {
     state: WorkerState,
     wts: WorkerTenantState,
     ratelimits: Arc<Ratelimits>,
-    id: Id
+    id: Id,
+    trace_cx: Rc<RefCell<Context>>,
+    replay: Rc<ReplayState>,
+    abort: Rc<AbortSignal>,
+    result_cache: ResultCache,
 }
 
 impl SyscallHandler {
     /// Creates a new syscall handler
-    pub fn new(state: WorkerState, wts: WorkerTenantState, ratelimits: Arc<Ratelimits>, id: Id) -> Self {
-        Self { state, wts, ratelimits, id }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(state: WorkerState, wts: WorkerTenantState, ratelimits: Arc<Ratelimits>, id: Id, trace_cx: Rc<RefCell<Context>>, replay: Rc<ReplayState>, abort: Rc<AbortSignal>, result_cache: ResultCache) -> Self {
+        Self { state, wts, ratelimits, id, trace_cx, replay, abort, result_cache }
     }
 
-    /// Handles a syscall
-    pub async fn handle_syscall(&self, args: SyscallArgs) -> Result<SyscallRet, crate::Error> {
+    /// Handles a syscall, attributing its usage to `source` and recording it as part of `execution` if that is being recorded
+    pub async fn handle_syscall(&self, args: SyscallArgs, source: &str, execution: Option<u64>) -> Result<SyscallRet, crate::Error> {
         if self.state.worker_print {
             info!("Executing syscall {args:?}");
         }
 
+        let category = args.category();
+        if !self.state.feature_flags.allows(&format!("syscall:{category}"), self.id) {
+            return Err(format!("Syscall category {category} is not enabled for this server").into());
+        }
+        if !self.state.entitlements.allows(&format!("syscall:{category}"), self.id) {
+            return Err(format!("Syscall category {category} requires premium").into());
+        }
+
+        if let Some(res) = self.replay.intercept_syscall(&args, execution) {
+            return res;
+        }
+
+        if args.has_side_effects() {
+            self.result_cache.mark_impure(self.id, source);
+        }
+
+        let methods = args.methods();
+        let res = self.exec_syscall(args, source).await;
+        for method in methods {
+            self.state.plugin_usage.record(self.id, category, method, res.is_ok());
+        }
+        self.replay.record_syscall(category, execution, &res);
+        res
+    }
+
+    async fn exec_syscall(&self, args: SyscallArgs, source: &str) -> Result<SyscallRet, crate::Error> {
         match args {
             SyscallArgs::State { ops } => {
                 self.ratelimits.object_storage.check("syscall", ()).map_err(RlExceededError)?;
+                self.state.usage.record_kv_ops(self.id, source, ops.len());
                 match FastStateReq::from_ops(ops) {
                     Ok(freq) => {
                         // faststate compatible, execute with faststate req and avoid mesophyll client call
@@ -155,8 +329,13 @@ impl SyscallHandler {
                         Ok(SyscallRet::State { res: results, new_tenant_state: None })
                     },
                     Err(ops) => {
-                        // non-faststate compatible, do normal mesophyll client call
-                        let res = self.state.mesophyll_client.exec_state_op(self.id, ops, StateDbFlags::empty()).await?;
+                        // non-faststate compatible, do normal mesophyll client call (cancelling its SQL on the master if aborted)
+                        let watchlist_changed = ops.iter().any(StateOp::alters_watchlist);
+                        let res = self.abort.guard(self.state.mesophyll_client.exec_state_op(self.id, ops, StateDbFlags::empty())).await;
+                        if watchlist_changed {
+                            self.wts.invalidate_watchlist(self.id);
+                        }
+                        let res = res?;
                         if let Some(ref ts) = res.new_tenant_state {
                             self.wts.reload_for_tenant(self.id, ts)?;
                         }
@@ -168,29 +347,58 @@ impl SyscallHandler {
                 let res = op.exec(self.id, self).await?;
                 Ok(SyscallRet::Cdn { res })
             }
-            SyscallArgs::Discord { op } => {
+            SyscallArgs::Discord { op, outbox } => {
                 let op_name = op.api_name();
                 if Ratelimits::DISCORD_GLOBAL_IGNORE.contains(&op_name) {
                     self.ratelimits.discord.sub_check(op_name, ()).map_err(RlExceededError)?;
                 } else {
                     self.ratelimits.discord.check(op_name, ()).map_err(RlExceededError)?;
                 }
-                let dp = DiscordContext::new(ArDiscordProvider { id: self.id, state: self.state.clone() });
-                let (value, mrm) = op.execute(&dp).await?;
-                Ok(SyscallRet::Discord { op: op_name, res: value, mrm })
+                self.state.usage.record_discord_call(self.id, source);
+                if let Some(req) = outbox {
+                    if !OUTBOX_OPS.contains(&op_name) {
+                        return Err(format!("{op_name} cannot be enqueued into the outbox, only {} can", OUTBOX_OPS.join(", ")).into());
+                    }
+                    let res = self.state.mesophyll_client.enqueue_outbox(self.id, &req).await?;
+                    return Ok(SyscallRet::DiscordOutbox { res });
+                }
+                let cx = telemetry::child(&Context::current(), "discord.api", SpanKind::Client, vec![KeyValue::new("discord.op", op_name)]);
+                let dp = DiscordContext::new(ArDiscordProvider { id: self.id, stratum: self.state.stratum.clone() });
+                let res = op.execute(&dp).with_context(cx.clone()).await;
+                if let Err(ref e) = res {
+                    cx.span().set_status(Status::error(e.to_string()));
+                }
+                cx.span().end();
+                let (value, mrm) = res?;
+                Ok(SyscallRet::Discord { op: op_name.into(), res: value, is_primitive_response: mrm.is_primitive_response })
+            }
+            SyscallArgs::DiscordExt { op } => {
+                self.state.usage.record_discord_call(self.id, source);
+                let res = op.exec(self.id, self).await?;
+                Ok(SyscallRet::DiscordExt { res })
             }
             SyscallArgs::Meta { op } => {
                 let res = op.exec(self.id, self).await?;
                 Ok(SyscallRet::Meta { res })
             }
+            SyscallArgs::Webhook { op } => {
+                let res = op.exec(self.id, source, self).await?;
+                Ok(SyscallRet::Webhook { res })
+            }
+            SyscallArgs::ImgGen { op } => {
+                let res = op.exec(self.id, self).await?;
+                Ok(SyscallRet::ImgGen { res })
+            }
         }
     }
 }
 
 impl LuaUserData for SyscallHandler {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        methods.add_scheduler_async_method("async", async |_lua, this, ops| {
-            let state = this.handle_syscall(ops).await.map_err(|x| LuaError::external(x.to_string()))?;
+        methods.add_scheduler_async_method("async", async |_lua, this, req: SyscallReq| {
+            let cx = this.trace_cx.borrow().clone();
+            let source = req.source.as_deref().unwrap_or(SyscallReq::DEFAULT_SOURCE);
+            let state = this.handle_syscall(req.args, source, req.execution).with_context(cx).await.map_err(|x| LuaError::external(x.to_string()))?;
             Ok(state)
         });
     }
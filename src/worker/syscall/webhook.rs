@@ -0,0 +1,142 @@
+use khronos_runtime::{rt::mluau::prelude::*, utils::khronos_value::KhronosValue};
+
+use crate::{geese::{ratelimit::RlExceededError, state::{SECRETS_SCOPE, StateDbFlags, StateExecResult, StateOp}}, worker::{syscall::SyscallHandler, workervmmanager::Id}};
+
+/// Maximum length of a webhook signing secret
+const MAX_SECRET_LENGTH: usize = 256;
+
+/// Outbound webhook syscalls
+///
+/// Signing secrets are kept in the secrets store of the tenant (which templates cannot read back) and are referred to by name.
+/// Names are namespaced by the source (template) making the call, so templates can only use and replace their own secrets
+#[derive(Debug)]
+pub enum WebhookCall {
+    /// Stores a signing secret under `name`, replacing any existing secret of that name
+    SetSecret {
+        name: String,
+        secret: String,
+    },
+    /// Removes the signing secret stored under `name`
+    DeleteSecret {
+        name: String,
+    },
+    /// Queues a delivery of `body` (as json) to `url`, signed with the secret stored under `secret_name`
+    Deliver {
+        url: String,
+        secret_name: String,
+        body: KhronosValue,
+    },
+}
+
+impl FromLua for WebhookCall {
+    fn from_lua(value: LuaValue, _lua: &Lua) -> LuaResult<Self> {
+        let LuaValue::Table(tab) = value else {
+            return Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "WebhookCall".to_string(),
+                message: Some("expected a table".to_string()),
+            })
+        };
+
+        let typ: LuaString = tab.get("op")?;
+        match typ.as_bytes().as_ref() {
+            b"SetSecret" => {
+                let name = tab.get("name")?;
+                let secret = tab.get("secret")?;
+                Ok(WebhookCall::SetSecret { name, secret })
+            },
+            b"DeleteSecret" => {
+                let name = tab.get("name")?;
+                Ok(WebhookCall::DeleteSecret { name })
+            },
+            b"Deliver" => {
+                let url = tab.get("url")?;
+                let secret_name = tab.get("secret_name")?;
+                let body = tab.get("body")?;
+                Ok(WebhookCall::Deliver { url, secret_name, body })
+            },
+            _ => {
+                Err(LuaError::FromLuaConversionError {
+                    from: "table",
+                    to: "WebhookCall".to_string(),
+                    message: Some("invalid op provided".to_string()),
+                })
+            }
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op")]
+pub enum WebhookResult {
+    SecretSet {},
+    SecretDeleted {},
+    Queued {}
+}
+
+impl IntoLua for WebhookResult {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let table = lua.create_table()?;
+        match self {
+            Self::SecretSet {} => {
+                table.set("op", "SecretSet")?;
+            }
+            Self::SecretDeleted {} => {
+                table.set("op", "SecretDeleted")?;
+            }
+            Self::Queued {} => {
+                table.set("op", "Queued")?;
+            }
+        }
+        table.set_readonly(true);
+        Ok(LuaValue::Table(table))
+    }
+}
+
+/// Returns the key the secret `name` of `source` is kept under in the secrets store
+fn secret_key(source: &str, name: &str) -> String {
+    format!("webhook/{source}/{name}")
+}
+
+impl WebhookCall {
+    pub(super) async fn exec(self, id: Id, source: &str, handler: &SyscallHandler) -> Result<WebhookResult, crate::Error> {
+        match self {
+            Self::SetSecret { name, secret } => {
+                handler.ratelimits.object_storage.check("syscall", ()).map_err(RlExceededError)?;
+                if secret.is_empty() {
+                    return Err("Webhook secret cannot be empty".into());
+                }
+                if secret.len() > MAX_SECRET_LENGTH {
+                    return Err(format!("Webhook secret exceeds maximum length of {MAX_SECRET_LENGTH} bytes").into());
+                }
+
+                let ops = vec![StateOp::KvSet { key: secret_key(source, &name), scope: SECRETS_SCOPE.to_string(), value: KhronosValue::Text(secret.into()), blob: None }];
+                handler.state.mesophyll_client.exec_state_op(id, ops, StateDbFlags::WORKER_INITIATED).await?;
+                Ok(WebhookResult::SecretSet {})
+            }
+            Self::DeleteSecret { name } => {
+                handler.ratelimits.object_storage.check("syscall", ()).map_err(RlExceededError)?;
+                let ops = vec![StateOp::KvDelete { key: secret_key(source, &name), scope: SECRETS_SCOPE.to_string() }];
+                handler.state.mesophyll_client.exec_state_op(id, ops, StateDbFlags::WORKER_INITIATED).await?;
+                Ok(WebhookResult::SecretDeleted {})
+            }
+            Self::Deliver { url, secret_name, body } => {
+                handler.ratelimits.webhook.check("Deliver", ()).map_err(RlExceededError)?;
+
+                let ops = vec![StateOp::KvGet { key: secret_key(source, &secret_name), scope: SECRETS_SCOPE.to_string() }];
+                let res = handler.state.mesophyll_client.exec_state_op(id, ops, StateDbFlags::WORKER_INITIATED).await?;
+                let secret = match res.results.into_iter().next() {
+                    Some(StateExecResult::Kv { l }) => match l.value {
+                        KhronosValue::Text(secret) => secret.to_string(),
+                        _ => return Err("Webhook secret is not a string".into()),
+                    },
+                    _ => return Err("No webhook secret is stored under this name".into()),
+                };
+
+                let body = serde_json::to_vec(&body)?;
+                handler.state.webhooks.deliver(id, &url, secret, body)?;
+                Ok(WebhookResult::Queued {})
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use khronos_runtime::rt::mlua::prelude::*;
+
+use crate::worker::limits::MAX_TABLEUTILS_ELEMENTS;
+
+/// Name of the VM global the `@antiraid/tableutils` module is exposed as
+pub const TABLEUTILS_GLOBAL: &str = "__antiraid_tableutils";
+
+/// A hashable key derived from a Luau value
+///
+/// Primitives are compared by value, everything else (tables, functions, userdata etc.) by identity
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Boolean(bool),
+    Number(u64),
+    String(Vec<u8>),
+    Ref(usize),
+}
+
+impl Key {
+    fn from_value(value: &LuaValue) -> LuaResult<Self> {
+        Ok(match value {
+            LuaValue::Nil => return Err(LuaError::external("keys cannot be nil")),
+            LuaValue::Boolean(b) => Self::Boolean(*b),
+            LuaValue::Integer(i) => Self::Number((*i as f64).to_bits()),
+            LuaValue::Number(n) => {
+                if n.is_nan() {
+                    return Err(LuaError::external("keys cannot be NaN"));
+                }
+                // -0 and 0 are the same key
+                Self::Number(if *n == 0.0 { 0.0f64.to_bits() } else { n.to_bits() })
+            }
+            LuaValue::String(s) => Self::String(s.as_bytes().to_vec()),
+            v => Self::Ref(v.to_pointer() as usize),
+        })
+    }
+}
+
+/// How to derive the key of an element: the element itself, a field of it or the result of a function
+enum KeyFn {
+    Identity,
+    Field(LuaValue),
+    Function(LuaFunction),
+}
+
+impl FromLua for KeyFn {
+    fn from_lua(value: LuaValue, _: &Lua) -> LuaResult<Self> {
+        Ok(match value {
+            LuaValue::Nil => Self::Identity,
+            LuaValue::Function(f) => Self::Function(f),
+            v @ (LuaValue::String(_) | LuaValue::Integer(_) | LuaValue::Number(_)) => Self::Field(v),
+            v => return Err(LuaError::external(format!("key must be a function, field name or nil, got {}", v.type_name()))),
+        })
+    }
+}
+
+impl KeyFn {
+    fn key_of(&self, value: &LuaValue) -> LuaResult<LuaValue> {
+        match self {
+            Self::Identity => Ok(value.clone()),
+            Self::Field(field) => match value {
+                LuaValue::Table(t) => t.get(field.clone()),
+                v => Err(LuaError::external(format!("cannot get a field of a {}", v.type_name()))),
+            },
+            Self::Function(f) => f.call(value.clone()),
+        }
+    }
+}
+
+/// Iterates over the array part of a table without copying it, enforcing the element limit
+fn for_each(tab: &LuaTable, mut f: impl FnMut(usize, LuaValue) -> LuaResult<()>) -> LuaResult<()> {
+    let len = tab.raw_len();
+    if len > MAX_TABLEUTILS_ELEMENTS {
+        return Err(LuaError::external(format!("table has more than {MAX_TABLEUTILS_ELEMENTS} elements")));
+    }
+    for i in 1..=len {
+        f(i, tab.raw_get(i)?)?;
+    }
+    Ok(())
+}
+
+fn key_set(tab: &LuaTable) -> LuaResult<HashSet<Key>> {
+    let mut set = HashSet::new();
+    for_each(tab, |_, v| {
+        set.insert(Key::from_value(&v)?);
+        Ok(())
+    })?;
+    Ok(set)
+}
+
+/// A sort key, numbers and strings can be sorted but not mixed
+#[derive(PartialEq, PartialOrd)]
+enum SortKey {
+    Number(f64),
+    String(Vec<u8>),
+}
+
+impl SortKey {
+    fn from_value(value: &LuaValue) -> LuaResult<Self> {
+        match value {
+            LuaValue::Integer(i) => Ok(Self::Number(*i as f64)),
+            LuaValue::Number(n) if !n.is_nan() => Ok(Self::Number(*n)),
+            LuaValue::String(s) => Ok(Self::String(s.as_bytes().to_vec())),
+            v => Err(LuaError::external(format!("sort keys must be numbers or strings, got {}", v.type_name()))),
+        }
+    }
+}
+
+/// Creates the `@antiraid/tableutils` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.raw_set("dedupe", lua.create_function(|lua, (tab, key): (LuaTable, KeyFn)| {
+        let out = lua.create_table()?;
+        let mut seen = HashSet::new();
+        for_each(&tab, |_, v| {
+            if seen.insert(Key::from_value(&key.key_of(&v)?)?) {
+                out.raw_push(v)?;
+            }
+            Ok(())
+        })?;
+        Ok(out)
+    })?)?;
+
+    module.raw_set("groupby", lua.create_function(|lua, (tab, key): (LuaTable, KeyFn)| {
+        let out = lua.create_table()?;
+        let mut groups: HashMap<Key, LuaTable> = HashMap::new();
+        for_each(&tab, |_, v| {
+            let k = key.key_of(&v)?;
+            let group = match groups.entry(Key::from_value(&k)?) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let group = lua.create_table()?;
+                    out.raw_set(k, &group)?;
+                    e.insert(group)
+                }
+            };
+            group.raw_push(v)
+        })?;
+        Ok(out)
+    })?)?;
+
+    module.raw_set("countby", lua.create_function(|lua, (tab, key): (LuaTable, KeyFn)| {
+        let out = lua.create_table()?;
+        let mut counts: HashMap<Key, (LuaValue, u64)> = HashMap::new();
+        for_each(&tab, |_, v| {
+            let k = key.key_of(&v)?;
+            counts.entry(Key::from_value(&k)?).or_insert((k, 0)).1 += 1;
+            Ok(())
+        })?;
+        for (_, (k, count)) in counts {
+            out.raw_set(k, count)?;
+        }
+        Ok(out)
+    })?)?;
+
+    module.raw_set("sortby", lua.create_function(|lua, (tab, key, descending): (LuaTable, KeyFn, Option<bool>)| {
+        let mut items = Vec::with_capacity(tab.raw_len().min(MAX_TABLEUTILS_ELEMENTS));
+        for_each(&tab, |_, v| {
+            items.push((SortKey::from_value(&key.key_of(&v)?)?, v));
+            Ok(())
+        })?;
+
+        if items.windows(2).any(|w| std::mem::discriminant(&w[0].0) != std::mem::discriminant(&w[1].0)) {
+            return Err(LuaError::external("cannot sort by a mix of number and string keys"));
+        }
+
+        // Stable sort so elements with equal keys keep their order
+        let descending = descending.unwrap_or(false);
+        items.sort_by(|a, b| {
+            let ord = a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal);
+            if descending { ord.reverse() } else { ord }
+        });
+
+        let out = lua.create_table_with_capacity(items.len(), 0)?;
+        for (_, v) in items {
+            out.raw_push(v)?;
+        }
+        Ok(out)
+    })?)?;
+
+    module.raw_set("union", lua.create_function(|lua, (a, b): (LuaTable, LuaTable)| {
+        let out = lua.create_table()?;
+        let mut seen = HashSet::new();
+        for tab in [&a, &b] {
+            for_each(tab, |_, v| {
+                if seen.insert(Key::from_value(&v)?) {
+                    out.raw_push(v)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(out)
+    })?)?;
+
+    module.raw_set("intersection", lua.create_function(|lua, (a, b): (LuaTable, LuaTable)| {
+        let other = key_set(&b)?;
+        let out = lua.create_table()?;
+        let mut seen = HashSet::new();
+        for_each(&a, |_, v| {
+            let k = Key::from_value(&v)?;
+            if other.contains(&k) && seen.insert(k) {
+                out.raw_push(v)?;
+            }
+            Ok(())
+        })?;
+        Ok(out)
+    })?)?;
+
+    module.raw_set("difference", lua.create_function(|lua, (a, b): (LuaTable, LuaTable)| {
+        let other = key_set(&b)?;
+        let out = lua.create_table()?;
+        let mut seen = HashSet::new();
+        for_each(&a, |_, v| {
+            let k = Key::from_value(&v)?;
+            if !other.contains(&k) && seen.insert(k) {
+                out.raw_push(v)?;
+            }
+            Ok(())
+        })?;
+        Ok(out)
+    })?)?;
+
+    module.raw_set("chunk", lua.create_function(|lua, (tab, size): (LuaTable, usize)| {
+        if size == 0 {
+            return Err(LuaError::external("chunk size must be positive"));
+        }
+        let capacity = size.min(tab.raw_len());
+        let out = lua.create_table()?;
+        let mut current = lua.create_table_with_capacity(capacity, 0)?;
+        for_each(&tab, |i, v| {
+            current.raw_push(v)?;
+            if i % size == 0 {
+                out.raw_push(std::mem::replace(&mut current, lua.create_table_with_capacity(capacity, 0)?))?;
+            }
+            Ok(())
+        })?;
+        if current.raw_len() > 0 {
+            out.raw_push(current)?;
+        }
+        Ok(out)
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
@@ -0,0 +1,62 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc, sync::Arc, time::Instant};
+
+use crate::geese::tenantstate::{DEFAULT_EVENTS, TenantLimits};
+use crate::worker::load::LoadTracker;
+use crate::worker::workervmmanager::Id;
+
+/// What dispatch needs to know about a tenant's templates, resolved once from its tenant state
+pub struct RegistryEntry {
+    /// Events the templates (and builtins) of the tenant are subscribed to
+    events: HashSet<String>,
+    /// Effective limits of the tenant
+    pub limits: TenantLimits,
+    built_at: Instant,
+}
+
+impl RegistryEntry {
+    /// Returns whether an event should be dispatched to the tenant's VM
+    pub fn handles(&self, event: &str) -> bool {
+        self.events.contains(event) || DEFAULT_EVENTS.contains(&event)
+    }
+
+    /// Returns how long ago the entry was resolved
+    pub fn age_ms(&self) -> u64 {
+        self.built_at.elapsed().as_millis() as u64
+    }
+}
+
+/// Per-tenant registry of templates cached on the VM thread so dispatching an event does not need to
+/// resolve (and copy) the tenant state every time
+///
+/// Entries are built lazily and invalidated whenever the tenant state of the tenant is pushed by the master
+/// or its premium tiers change
+#[derive(Clone)]
+pub struct TemplateRegistry {
+    entries: Rc<RefCell<HashMap<Id, Rc<RegistryEntry>>>>,
+    load: Arc<LoadTracker>,
+}
+
+impl TemplateRegistry {
+    pub fn new(load: Arc<LoadTracker>) -> Self {
+        Self { entries: Rc::default(), load }
+    }
+
+    /// Returns the entry of a tenant, building it with `build` if not cached
+    pub fn get_or_build(&self, id: Id, build: impl FnOnce() -> (HashSet<String>, TenantLimits)) -> Rc<RegistryEntry> {
+        if let Some(entry) = self.entries.borrow().get(&id) {
+            self.load.record_registry_hit(entry.age_ms());
+            return entry.clone();
+        }
+
+        let (events, limits) = build();
+        let entry = Rc::new(RegistryEntry { events, limits, built_at: Instant::now() });
+        self.entries.borrow_mut().insert(id, entry.clone());
+        self.load.record_registry_rebuild();
+        entry
+    }
+
+    /// Drops the entry of a tenant, it is rebuilt on its next dispatch
+    pub fn invalidate(&self, id: Id) {
+        self.entries.borrow_mut().remove(&id);
+    }
+}
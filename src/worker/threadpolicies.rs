@@ -0,0 +1,288 @@
+use chrono::{DateTime, Utc};
+use dapi::{ChannelId, GuildId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::CONFIG;
+use crate::geese::threadpolicies::{ThreadPolicy, compile_pattern};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Internal event dispatched to a guild by the master when the threads of its thread policies are due to be swept
+pub const THREAD_POLICY_SWEEP_EVENT: &str = "$ThreadPolicySweep";
+
+/// Event dispatched to a guild when a thread policy archived, tagged or (un)pinned one of its threads
+pub const THREAD_POLICY_APPLIED_EVENT: &str = "ThreadPolicyApplied";
+
+/// The `PINNED` channel flag of forum threads
+const PINNED_FLAG: u64 = 1 << 1;
+
+/// Maximum number of tags a forum thread can have
+const MAX_APPLIED_TAGS: usize = 5;
+
+/// The Discord epoch (first second of 2015) in milliseconds
+const DISCORD_EPOCH: i64 = 1420070400000;
+
+/// Audit log reason of thread changes
+const AUDIT_REASON: &str = "Thread policy";
+
+/// Why a thread policy archived a thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadArchiveReason {
+    /// The thread was inactive for longer than `archive_after_days`
+    Inactive,
+    /// The channel had more than `max_open_threads` open threads
+    MaxOpenThreads,
+}
+
+/// Data of `ThreadPolicyApplied`
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadPolicyApplied {
+    pub thread_id: ChannelId,
+    pub channel_id: ChannelId,
+    pub name: String,
+    /// Why the thread was archived, if archived
+    pub archived: Option<ThreadArchiveReason>,
+    pub locked: bool,
+    /// Tags applied by tag rules
+    pub added_tags: Vec<String>,
+    /// Whether the thread was pinned or unpinned by a pin rule, if either
+    pub pinned: Option<bool>,
+}
+
+impl IntoLua for ThreadPolicyApplied {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+#[derive(Deserialize)]
+struct ThreadMetadata {
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    create_timestamp: Option<DateTime<Utc>>,
+}
+
+/// The fields of a thread channel policies act on
+#[derive(Deserialize)]
+struct Thread {
+    id: ChannelId,
+    #[serde(default)]
+    parent_id: Option<ChannelId>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    applied_tags: Vec<String>,
+    #[serde(default)]
+    flags: u64,
+    #[serde(default)]
+    last_message_id: Option<String>,
+    #[serde(default)]
+    thread_metadata: Option<ThreadMetadata>,
+}
+
+impl Thread {
+    fn pinned(&self) -> bool {
+        self.flags & PINNED_FLAG != 0
+    }
+
+    fn archived(&self) -> bool {
+        self.thread_metadata.as_ref().is_some_and(|m| m.archived)
+    }
+
+    /// When a message was last sent in the thread, or it was created if none were
+    fn last_active(&self) -> Option<DateTime<Utc>> {
+        let created = self.thread_metadata.as_ref().and_then(|m| m.create_timestamp)
+            .or_else(|| snowflake_time(&self.id.to_string()));
+        let last_message = self.last_message_id.as_deref().and_then(snowflake_time);
+        created.max(last_message)
+    }
+}
+
+/// Returns when a snowflake was created
+fn snowflake_time(id: &str) -> Option<DateTime<Utc>> {
+    let id = id.parse::<u64>().ok()?;
+    DateTime::from_timestamp_millis((id >> 22) as i64 + DISCORD_EPOCH)
+}
+
+/// Enforces the thread policies of a guild: new threads are tagged and (un)pinned by title on creation while the
+/// master periodically has inactive threads and threads beyond the open thread limit archived
+///
+/// All changes to threads go through `apply` (a single edit per thread) which dispatches `ThreadPolicyApplied`, so
+/// templates see every action taken by a policy
+pub struct ThreadPolicies<'a> {
+    dispatch: &'a WorkerDispatch,
+}
+
+impl<'a> ThreadPolicies<'a> {
+    pub fn new(dispatch: &'a WorkerDispatch) -> Self {
+        Self { dispatch }
+    }
+
+    /// Applies the thread policy of its channel to a newly created thread in the background given its THREAD_CREATE
+    /// payload
+    pub fn start(dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        if !payload.get("newly_created").and_then(|v| v.as_bool()).unwrap_or(false) {
+            return;
+        }
+        let Some(config) = dispatch.tenant_state.thread_policies(Id::Guild(guild_id)) else {
+            return;
+        };
+        let Ok(thread) = serde_json::from_value::<Thread>(payload.clone()) else {
+            return;
+        };
+        let Some(policy) = thread.parent_id.and_then(|c| config.policy(c)).cloned() else {
+            return;
+        };
+
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            let this = ThreadPolicies::new(&dispatch);
+            if let Err(e) = this.created(guild_id, &policy, thread).await {
+                log::error!("Failed to apply thread policy of channel {} in guild {guild_id}: {e}", policy.channel_id);
+            }
+        });
+    }
+
+    async fn created(&self, guild_id: GuildId, policy: &ThreadPolicy, thread: Thread) -> Result<(), crate::Error> {
+        // Patterns are validated on being set
+        let mut added_tags = Vec::new();
+        for rule in policy.tag_rules.iter() {
+            let full = thread.applied_tags.len() + added_tags.len() >= MAX_APPLIED_TAGS;
+            if full || thread.applied_tags.contains(&rule.tag_id) || added_tags.contains(&rule.tag_id) {
+                continue;
+            }
+            if compile_pattern(&rule.pattern).is_ok_and(|p| p.is_match(&thread.name)) {
+                added_tags.push(rule.tag_id.clone());
+            }
+        }
+        let pinned = policy.pin_rules.iter()
+            .find(|rule| compile_pattern(&rule.pattern).is_ok_and(|p| p.is_match(&thread.name)))
+            .map(|rule| rule.pin)
+            .filter(|pin| *pin != thread.pinned());
+
+        if !added_tags.is_empty() || pinned.is_some() {
+            self.apply(guild_id, &thread, None, false, added_tags, pinned).await?;
+        }
+        if policy.max_open_threads.is_some() {
+            let threads = self.active_threads(guild_id).await?;
+            self.enforce(guild_id, policy, &threads, false).await?;
+        }
+        Ok(())
+    }
+
+    /// Handles a `$ThreadPolicySweep` for a guild, archiving the inactive threads and the threads beyond the open
+    /// thread limit of each of its policies
+    pub async fn sweep(&self, guild_id: GuildId) -> Result<(), crate::Error> {
+        let Some(config) = self.dispatch.tenant_state.thread_policies(Id::Guild(guild_id)) else {
+            return Ok(());
+        };
+        if !config.policies.iter().any(|p| p.archive_after_days.is_some() || p.max_open_threads.is_some()) {
+            return Ok(());
+        }
+
+        let threads = self.active_threads(guild_id).await?;
+        for policy in config.policies.iter() {
+            if let Err(e) = self.enforce(guild_id, policy, &threads, true).await {
+                log::warn!("Failed to sweep threads of channel {} in guild {guild_id}: {e}", policy.channel_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Archives the open threads of a policy's channel which are inactive (if `inactive`) or beyond its open thread
+    /// limit, least recently active first
+    async fn enforce(&self, guild_id: GuildId, policy: &ThreadPolicy, threads: &[Thread], inactive: bool) -> Result<(), crate::Error> {
+        let mut open: Vec<(Option<DateTime<Utc>>, &Thread)> = threads.iter()
+            .filter(|t| t.parent_id == Some(policy.channel_id) && !t.pinned() && !t.archived())
+            .map(|t| (t.last_active(), t))
+            .collect();
+        open.sort_by_key(|(last_active, _)| *last_active);
+
+        let cutoff = policy.archive_after_days
+            .filter(|_| inactive)
+            .map(|days| Utc::now() - chrono::Duration::days(days.into()));
+        let mut excess = policy.max_open_threads
+            .map(|max| open.len().saturating_sub(max as usize))
+            .unwrap_or_default();
+
+        for (last_active, thread) in open {
+            let reason = if cutoff.is_some_and(|cutoff| last_active.is_some_and(|l| l <= cutoff)) {
+                ThreadArchiveReason::Inactive
+            } else if excess > 0 {
+                ThreadArchiveReason::MaxOpenThreads
+            } else {
+                break;
+            };
+            excess = excess.saturating_sub(1);
+            self.apply(guild_id, thread, Some(reason), policy.lock_on_archive, Vec::new(), None).await?;
+        }
+        Ok(())
+    }
+
+    /// Edits a thread as a policy requires, dispatching `ThreadPolicyApplied`
+    async fn apply(
+        &self,
+        guild_id: GuildId,
+        thread: &Thread,
+        archived: Option<ThreadArchiveReason>,
+        lock: bool,
+        added_tags: Vec<String>,
+        pinned: Option<bool>,
+    ) -> Result<(), crate::Error> {
+        let mut body = Map::new();
+        if !added_tags.is_empty() {
+            let tags = thread.applied_tags.iter().chain(added_tags.iter()).cloned().collect::<Vec<_>>();
+            body.insert("applied_tags".to_string(), tags.into());
+        }
+        if let Some(pin) = pinned {
+            let flags = if pin { thread.flags | PINNED_FLAG } else { thread.flags & !PINNED_FLAG };
+            body.insert("flags".to_string(), flags.into());
+        }
+        let locked = archived.is_some() && lock;
+        if archived.is_some() {
+            body.insert("archived".to_string(), true.into());
+            if locked {
+                body.insert("locked".to_string(), true.into());
+            }
+        }
+
+        self.dispatch.worker_state.reqwest.patch(format!("{}/api/v10/channels/{}", CONFIG.proxy, thread.id))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+            .header("X-Audit-Log-Reason", AUDIT_REASON)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let applied = ThreadPolicyApplied {
+            thread_id: thread.id,
+            channel_id: thread.parent_id.unwrap_or(thread.id),
+            name: thread.name.clone(),
+            archived,
+            locked,
+            added_tags,
+            pinned,
+        };
+        self.dispatch.dispatch_event_complex(Id::Guild(guild_id), THREAD_POLICY_APPLIED_EVENT, None, applied).await?;
+        Ok(())
+    }
+
+    /// Returns the active (unarchived) threads of a guild
+    async fn active_threads(&self, guild_id: GuildId) -> Result<Vec<Thread>, crate::Error> {
+        let res: Value = self.dispatch.worker_state.reqwest.get(format!("{}/api/v10/guilds/{guild_id}/threads/active", CONFIG.proxy))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let threads = res.get("threads").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        Ok(threads.into_iter().filter_map(|t| serde_json::from_value(t).ok()).collect())
+    }
+}
@@ -0,0 +1,178 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use khronos_runtime::rt::mlua::prelude::*;
+
+/// How often the executor of a worker thread records that it got control back, see `turn`
+const TURN_TICK: Duration = Duration::from_millis(2);
+
+thread_local! {
+    /// Number of times the executor of the worker thread got control back
+    static TURNS: Cell<u64> = const { Cell::new(0) };
+    static TICKER_STARTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns the current turn of the executor of the worker thread, starting the ticker counting turns if needed
+///
+/// A Luau thread running without yielding blocks the executor, so the turn only changes once Luau code has
+/// yielded back to it. Coroutines resumed from Luau (or threads resumed back to back by the scheduler) run
+/// within the same turn
+fn turn() -> u64 {
+    if !TICKER_STARTED.replace(true) {
+        tokio::task::spawn_local(async {
+            let mut interval = tokio::time::interval(TURN_TICK);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                TURNS.set(TURNS.get() + 1);
+            }
+        });
+    }
+    TURNS.get()
+}
+
+#[derive(Default)]
+struct SliceState {
+    /// Turn of the executor the current run started in
+    turn: Option<u64>,
+    /// When the current slice started
+    slice_started: Option<Instant>,
+    /// Execution time used before the current slice, by the current run and the preempted runs it continues
+    run_used: Duration,
+    /// Whether a thread was preempted in the current turn
+    preempting: bool,
+    /// Execution time used by runs which were preempted and are waiting to be resumed, with the generation of
+    /// the preemption
+    preempted: HashMap<usize, (Duration, u64)>,
+    /// Generation of the next preemption
+    next_generation: u64,
+}
+
+/// Counters of a time slicer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeSliceStats {
+    /// Number of slices executed
+    pub slices: u64,
+    /// Number of times a thread was preempted at the end of its slice
+    pub preemptions: u64,
+}
+
+/// Interrupt-based execution time limiting and time slicing for a VM
+///
+/// Execution time is charged per turn of the executor of the worker thread: from a thread being resumed by
+/// the scheduler until the executor gets control back. Switching between coroutines (or a `pcall` catching
+/// the limit being exceeded) does not reset the budget, and once it is exceeded every interrupt errors until
+/// the executor gets control back. A thread which runs for longer than a slice is yielded back to the
+/// scheduler and requeued with `task.defer`, letting other threads (and other tenants on the same worker
+/// thread) run before it continues. Time spent preempted does not count towards the execution time limit,
+/// but the time used before being preempted does.
+///
+/// The slicer replaces the interrupt of the khronos runtime (a VM has one interrupt), so is only installed
+/// if slicing is enabled. The slice then takes the place of the give time of the runtime
+pub struct TimeSlicer {
+    slice: Duration,
+    execution_time_limit: Cell<Duration>,
+    state: RefCell<SliceState>,
+    slices: Cell<u64>,
+    preemptions: Cell<u64>,
+}
+
+impl TimeSlicer {
+    pub fn new(slice: Duration, execution_time_limit: Duration) -> Rc<Self> {
+        Rc::new(Self {
+            slice,
+            execution_time_limit: Cell::new(execution_time_limit),
+            state: RefCell::default(),
+            slices: Cell::new(0),
+            preemptions: Cell::new(0),
+        })
+    }
+
+    /// Sets the execution time limit, applies to the next interrupt
+    pub fn set_execution_time_limit(&self, limit: Duration) {
+        self.execution_time_limit.set(limit);
+    }
+
+    /// Returns the slice/preemption counters of the VM
+    pub fn stats(&self) -> TimeSliceStats {
+        TimeSliceStats {
+            slices: self.slices.get(),
+            preemptions: self.preemptions.get(),
+        }
+    }
+
+    /// Installs the slicer as the interrupt of the VM
+    ///
+    /// `task_defer` is the `task.defer` function of the VM used to requeue preempted threads, if unset, threads are never preempted
+    pub fn install(self: &Rc<Self>, lua: &Lua, task_defer: Option<LuaFunction>) {
+        let this = self.clone();
+        lua.set_interrupt(move |lua| this.interrupt(lua, task_defer.as_ref()));
+    }
+
+    fn interrupt(self: &Rc<Self>, lua: &Lua, task_defer: Option<&LuaFunction>) -> LuaResult<LuaVmState> {
+        let now = Instant::now();
+        let turn = turn();
+        let thread = lua.current_thread();
+        let key = thread.to_pointer() as usize;
+
+        let mut state = self.state.borrow_mut();
+        if state.turn != Some(turn) {
+            // The executor got control back since the last interrupt, so this is a new run
+            state.turn = Some(turn);
+            state.run_used = Duration::ZERO;
+            state.slice_started = Some(now);
+            state.preempting = false;
+            self.slices.set(self.slices.get() + 1);
+        }
+
+        // A thread which is running is not waiting to be resumed (it was resumed, or its yield failed)
+        if let Some((used, _)) = state.preempted.remove(&key) {
+            state.run_used += used;
+        }
+
+        let ran = state.slice_started.map(|started| now.duration_since(started)).unwrap_or_default();
+        if state.run_used + ran > self.execution_time_limit.get() {
+            return Err(LuaError::RuntimeError("Template exceeded its execution time limit without yielding".to_string()));
+        }
+
+        let Some(task_defer) = task_defer else {
+            return Ok(LuaVmState::Continue);
+        };
+
+        if ran < self.slice || state.preempting {
+            return Ok(LuaVmState::Continue);
+        }
+
+        let generation = state.next_generation;
+        state.next_generation += 1;
+        state.preempted.insert(key, (state.run_used + ran, generation));
+        state.preempting = true;
+        drop(state);
+        self.preemptions.set(self.preemptions.get() + 1);
+
+        // Requeue the thread once it has yielded. The thread may not have yielded if it was not yieldable
+        // (e.g. inside a metamethod) in which case it will have cleared its preempted entry on the next
+        // interrupt
+        let this = self.clone();
+        let task_defer = task_defer.clone();
+        tokio::task::spawn_local(async move {
+            if this.state.borrow().preempted.get(&key).is_none_or(|(_, g)| *g != generation) {
+                return;
+            }
+
+            if thread.status() != LuaThreadStatus::Resumable {
+                // Thread was killed while preempted
+                this.state.borrow_mut().preempted.remove(&key);
+                return;
+            }
+
+            if let Err(e) = task_defer.call::<LuaValue>(thread) {
+                log::error!("Failed to requeue preempted thread: {e}");
+            }
+        });
+
+        Ok(LuaVmState::Yield)
+    }
+}
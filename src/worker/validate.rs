@@ -0,0 +1,402 @@
+use khronos_runtime::rt::mlua::prelude::*;
+use regex::{Regex, RegexBuilder};
+
+/// Name of the VM global the `@antiraid/validate` module is exposed as
+pub const VALIDATE_GLOBAL: &str = "__antiraid_validate";
+
+/// Maximum nesting depth of schemas and validated values
+const MAX_DEPTH: usize = 64;
+/// Maximum number of errors reported by a single validation
+const MAX_ERRORS: usize = 100;
+/// Maximum compiled size of a string pattern
+const MAX_PATTERN_SIZE: usize = 64 * 1024;
+
+/// A literal value a schema can be restricted to
+#[derive(Debug, PartialEq)]
+enum Literal {
+    Boolean(bool),
+    Number(f64),
+    String(Vec<u8>),
+}
+
+impl Literal {
+    fn from_lua(value: &LuaValue) -> LuaResult<Self> {
+        match value {
+            LuaValue::Boolean(b) => Ok(Self::Boolean(*b)),
+            LuaValue::Integer(i) => Ok(Self::Number(*i as f64)),
+            LuaValue::Number(n) => Ok(Self::Number(*n)),
+            LuaValue::String(s) => Ok(Self::String(s.as_bytes().to_vec())),
+            v => Err(LuaError::external(format!("values can only contain booleans, numbers and strings, got {}", v.type_name()))),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Kind {
+    Any,
+    Nil,
+    Boolean,
+    Number { min: Option<f64>, max: Option<f64>, integer: bool },
+    String { min: Option<usize>, max: Option<usize>, pattern: Option<Regex> },
+    /// A sequential table with elements matching `items`
+    Array { items: Box<Schema>, min: Option<usize>, max: Option<usize> },
+    /// A table with known string keys
+    Table { fields: Vec<(String, Schema)>, additional: bool },
+    /// A table with arbitrary keys and values matching the given schemas
+    Map { keys: Option<Box<Schema>>, values: Box<Schema> },
+    /// A value matching at least one of the given schemas
+    OneOf(Vec<Schema>),
+}
+
+/// A compiled schema
+#[derive(Debug)]
+struct Schema {
+    kind: Kind,
+    optional: bool,
+    values: Option<Vec<Literal>>,
+}
+
+/// A validation error at a path in the validated value
+struct ValidationError {
+    path: String,
+    message: String,
+}
+
+impl Schema {
+    /// Compiles a schema from its Luau representation
+    ///
+    /// A schema is either a table (`{ type = "string", pattern = "^[a-z]+$" }`) or a type name shorthand (`"string"`),
+    /// a trailing `?` marks the value as optional
+    fn compile(value: &LuaValue, depth: usize) -> LuaResult<Self> {
+        if depth > MAX_DEPTH {
+            return Err(LuaError::external("schema is nested too deeply"));
+        }
+
+        let tab = match value {
+            LuaValue::String(s) => {
+                let s = s.to_str()?.to_string();
+                let (name, optional) = match s.strip_suffix('?') {
+                    Some(name) => (name.to_string(), true),
+                    None => (s, false),
+                };
+                let kind = match name.as_str() {
+                    "any" => Kind::Any,
+                    "nil" => Kind::Nil,
+                    "boolean" => Kind::Boolean,
+                    "number" => Kind::Number { min: None, max: None, integer: false },
+                    "integer" => Kind::Number { min: None, max: None, integer: true },
+                    "string" => Kind::String { min: None, max: None, pattern: None },
+                    t => return Err(LuaError::external(format!("unknown or incomplete schema type shorthand: {t}"))),
+                };
+                return Ok(Self { kind, optional, values: None });
+            }
+            LuaValue::Table(tab) => tab,
+            v => return Err(LuaError::external(format!("schema must be a table or string, got {}", v.type_name()))),
+        };
+
+        let typ: String = tab.get("type")?;
+        let optional = tab.get::<Option<bool>>("optional")?.unwrap_or(false);
+        let values = match tab.get::<Option<Vec<LuaValue>>>("values")? {
+            Some(values) => Some(values.iter().map(Literal::from_lua).collect::<LuaResult<Vec<_>>>()?),
+            None => None,
+        };
+
+        let kind = match typ.as_str() {
+            "any" => Kind::Any,
+            "nil" => Kind::Nil,
+            "boolean" => Kind::Boolean,
+            "number" | "integer" => Kind::Number {
+                min: tab.get("min")?,
+                max: tab.get("max")?,
+                integer: typ == "integer" || tab.get::<Option<bool>>("integer")?.unwrap_or(false),
+            },
+            "string" => {
+                let pattern = match tab.get::<Option<String>>("pattern")? {
+                    Some(pattern) => Some(
+                        RegexBuilder::new(&pattern)
+                            .size_limit(MAX_PATTERN_SIZE)
+                            .build()
+                            .map_err(|e| LuaError::external(format!("invalid pattern: {e}")))?,
+                    ),
+                    None => None,
+                };
+                Kind::String { min: tab.get("min")?, max: tab.get("max")?, pattern }
+            }
+            "array" => Kind::Array {
+                items: Box::new(Self::compile(&tab.get("items")?, depth + 1)?),
+                min: tab.get("min")?,
+                max: tab.get("max")?,
+            },
+            "table" => {
+                let mut fields = Vec::new();
+                if let Some(f) = tab.get::<Option<LuaTable>>("fields")? {
+                    for pair in f.pairs::<String, LuaValue>() {
+                        let (name, schema) = pair?;
+                        let schema = Self::compile(&schema, depth + 1)
+                            .map_err(|e| LuaError::external(format!("field {name}: {e}")))?;
+                        fields.push((name, schema));
+                    }
+                }
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+                Kind::Table { fields, additional: tab.get::<Option<bool>>("additional")?.unwrap_or(true) }
+            }
+            "map" => Kind::Map {
+                keys: match tab.get::<LuaValue>("keys")? {
+                    LuaValue::Nil => None,
+                    keys => Some(Box::new(Self::compile(&keys, depth + 1)?)),
+                },
+                values: Box::new(Self::compile(&tab.get("values")?, depth + 1)?),
+            },
+            "oneof" => {
+                let options: Vec<LuaValue> = tab.get("options")?;
+                if options.is_empty() {
+                    return Err(LuaError::external("oneof schemas must have at least one option"));
+                }
+                Kind::OneOf(options.iter().map(|o| Self::compile(o, depth + 1)).collect::<LuaResult<Vec<_>>>()?)
+            }
+            t => return Err(LuaError::external(format!("unknown schema type: {t}"))),
+        };
+
+        Ok(Self { kind, optional, values })
+    }
+
+    /// Validates `value` against the schema, pushing errors to `errors`
+    fn check(&self, value: &LuaValue, path: &mut String, depth: usize, errors: &mut Vec<ValidationError>) -> LuaResult<()> {
+        if errors.len() >= MAX_ERRORS {
+            return Ok(());
+        }
+
+        macro_rules! fail {
+            ($($arg:tt)*) => {{
+                errors.push(ValidationError { path: path.clone(), message: format!($($arg)*) });
+                return Ok(());
+            }};
+        }
+
+        if depth > MAX_DEPTH {
+            fail!("value is nested too deeply");
+        }
+
+        if value.is_nil() {
+            if self.optional || matches!(self.kind, Kind::Nil | Kind::Any) {
+                return Ok(());
+            }
+            fail!("required value is missing");
+        }
+
+        if let Some(values) = &self.values {
+            let lit = Literal::from_lua(value).ok();
+            if !lit.is_some_and(|lit| values.contains(&lit)) {
+                fail!("value is not one of the allowed values");
+            }
+        }
+
+        match &self.kind {
+            Kind::Any => {}
+            Kind::Nil => fail!("expected nil, got {}", value.type_name()),
+            Kind::Boolean => {
+                if !value.is_boolean() {
+                    fail!("expected boolean, got {}", value.type_name());
+                }
+            }
+            Kind::Number { min, max, integer } => {
+                let n = match value {
+                    LuaValue::Integer(i) => *i as f64,
+                    LuaValue::Number(n) => *n,
+                    v => fail!("expected number, got {}", v.type_name()),
+                };
+                if *integer && n.fract() != 0.0 {
+                    fail!("expected an integer, got {n}");
+                }
+                if min.is_some_and(|min| n < min) || max.is_some_and(|max| n > max) {
+                    fail!("{n} is out of range [{}, {}]", fmt_bound(*min), fmt_bound(*max));
+                }
+            }
+            Kind::String { min, max, pattern } => {
+                let LuaValue::String(s) = value else {
+                    fail!("expected string, got {}", value.type_name());
+                };
+                let len = s.as_bytes().len();
+                if min.is_some_and(|min| len < min) || max.is_some_and(|max| len > max) {
+                    fail!("string length {len} is out of range [{}, {}]", fmt_bound(*min), fmt_bound(*max));
+                }
+                if let Some(pattern) = pattern {
+                    let Ok(s) = s.to_str() else {
+                        fail!("string is not valid UTF-8");
+                    };
+                    if !pattern.is_match(&s) {
+                        fail!("string does not match pattern {}", pattern.as_str());
+                    }
+                }
+            }
+            Kind::Array { items, min, max } => {
+                let LuaValue::Table(tab) = value else {
+                    fail!("expected array, got {}", value.type_name());
+                };
+                let len = tab.raw_len();
+                if min.is_some_and(|min| len < min) || max.is_some_and(|max| len > max) {
+                    fail!("array length {len} is out of range [{}, {}]", fmt_bound(*min), fmt_bound(*max));
+                }
+                for pair in tab.pairs::<LuaValue, LuaValue>() {
+                    let (k, _) = pair?;
+                    if !matches!(k, LuaValue::Integer(i) if i >= 1 && i as usize <= len)
+                        && !matches!(k, LuaValue::Number(n) if n >= 1.0 && n.fract() == 0.0 && n as usize <= len)
+                    {
+                        fail!("expected array, got table with non-sequential keys");
+                    }
+                }
+                for i in 1..=len {
+                    let v: LuaValue = tab.raw_get(i)?;
+                    let len_before = path.len();
+                    path.push_str(&format!("[{i}]"));
+                    items.check(&v, path, depth + 1, errors)?;
+                    path.truncate(len_before);
+                }
+            }
+            Kind::Table { fields, additional } => {
+                let LuaValue::Table(tab) = value else {
+                    fail!("expected table, got {}", value.type_name());
+                };
+                for (name, schema) in fields {
+                    let v: LuaValue = tab.raw_get(name.as_str())?;
+                    let len_before = path.len();
+                    push_key(path, name);
+                    schema.check(&v, path, depth + 1, errors)?;
+                    path.truncate(len_before);
+                }
+                if !additional {
+                    for pair in tab.pairs::<LuaValue, LuaValue>() {
+                        let (k, _) = pair?;
+                        let known = match &k {
+                            LuaValue::String(s) => s.to_str().is_ok_and(|s| fields.iter().any(|(name, _)| name.as_str() == &*s)),
+                            _ => false,
+                        };
+                        if !known {
+                            errors.push(ValidationError { path: path.clone(), message: format!("unexpected key {}", fmt_key(&k)) });
+                            if errors.len() >= MAX_ERRORS {
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+            }
+            Kind::Map { keys, values } => {
+                let LuaValue::Table(tab) = value else {
+                    fail!("expected table, got {}", value.type_name());
+                };
+                for pair in tab.pairs::<LuaValue, LuaValue>() {
+                    let (k, v) = pair?;
+                    let len_before = path.len();
+                    path.push_str(&format!("[{}]", fmt_key(&k)));
+                    if let Some(keys) = keys {
+                        keys.check(&k, path, depth + 1, errors)?;
+                    }
+                    values.check(&v, path, depth + 1, errors)?;
+                    path.truncate(len_before);
+                }
+            }
+            Kind::OneOf(options) => {
+                for option in options {
+                    let mut option_errors = Vec::new();
+                    option.check(value, path, depth + 1, &mut option_errors)?;
+                    if option_errors.is_empty() {
+                        return Ok(());
+                    }
+                }
+                fail!("value does not match any of the {} options", options.len());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates `value`, returning the errors found
+    fn validate(&self, value: &LuaValue) -> LuaResult<Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let mut path = "$".to_string();
+        self.check(value, &mut path, 0, &mut errors)?;
+        Ok(errors)
+    }
+}
+
+fn fmt_bound<T: std::fmt::Display>(bound: Option<T>) -> String {
+    bound.map(|b| b.to_string()).unwrap_or_else(|| "-".to_string())
+}
+
+fn fmt_key(key: &LuaValue) -> String {
+    match key {
+        LuaValue::String(s) => format!("{:?}", s.to_string_lossy()),
+        LuaValue::Integer(i) => i.to_string(),
+        LuaValue::Number(n) => n.to_string(),
+        v => format!("<{}>", v.type_name()),
+    }
+}
+
+fn push_key(path: &mut String, name: &str) {
+    let is_ident = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_ident {
+        path.push('.');
+        path.push_str(name);
+    } else {
+        path.push_str(&format!("[{name:?}]"));
+    }
+}
+
+fn errors_to_lua(lua: &Lua, errors: Vec<ValidationError>) -> LuaResult<LuaTable> {
+    let tab = lua.create_table_with_capacity(errors.len(), 0)?;
+    for e in errors {
+        let err = lua.create_table_with_capacity(0, 2)?;
+        err.set("path", e.path)?;
+        err.set("message", e.message)?;
+        err.set_readonly(true);
+        tab.raw_push(err)?;
+    }
+    tab.set_readonly(true);
+    Ok(tab)
+}
+
+/// A compiled schema exposed to Luau
+struct CompiledSchema(Schema);
+
+impl CompiledSchema {
+    fn check(&self, lua: &Lua, value: LuaValue) -> LuaResult<(bool, Option<LuaTable>)> {
+        let errors = self.0.validate(&value)?;
+        if errors.is_empty() {
+            return Ok((true, None));
+        }
+        Ok((false, Some(errors_to_lua(lua, errors)?)))
+    }
+
+    fn assert(&self, value: LuaValue) -> LuaResult<LuaValue> {
+        let errors = self.0.validate(&value)?;
+        if errors.is_empty() {
+            return Ok(value);
+        }
+        let msg = errors.iter().map(|e| format!("{}: {}", e.path, e.message)).collect::<Vec<_>>().join("\n");
+        Err(LuaError::external(format!("Validation failed:\n{msg}")))
+    }
+}
+
+impl LuaUserData for CompiledSchema {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("check", |lua, this, value: LuaValue| this.check(lua, value));
+        methods.add_method("assert", |_, this, value: LuaValue| this.assert(value));
+    }
+}
+
+/// Creates the `@antiraid/validate` module of a VM
+pub fn create_module(lua: &Lua) -> LuaResult<LuaTable> {
+    let module = lua.create_table()?;
+
+    module.raw_set("compile", lua.create_function(|_, schema: LuaValue| {
+        Ok(CompiledSchema(Schema::compile(&schema, 0)?))
+    })?)?;
+
+    module.raw_set("check", lua.create_function(|lua, (schema, value): (LuaValue, LuaValue)| {
+        CompiledSchema(Schema::compile(&schema, 0)?).check(lua, value)
+    })?)?;
+
+    module.set_readonly(true);
+    Ok(module)
+}
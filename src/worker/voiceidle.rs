@@ -0,0 +1,203 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use dapi::{ChannelId, GuildId, UserId};
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::geese::voiceidle::{VoiceIdleConfig, VoiceIdleKind};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Event dispatched to a guild when a member crosses one of its voice idle thresholds
+pub const VOICE_IDLE_THRESHOLD_EVENT: &str = "VoiceIdleThreshold";
+
+/// How often sessions are checked for crossed thresholds
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum number of members tracked in voice, further members are not tracked until others leave
+const MAX_TRACKED_SESSIONS: usize = 100_000;
+
+/// Data of `VoiceIdleThreshold`
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceIdleThresholdCrossed {
+    pub user_id: UserId,
+    pub channel_id: ChannelId,
+    pub kind: VoiceIdleKind,
+    /// The threshold crossed
+    pub threshold_minutes: u32,
+    /// When the member became idle (or joined the channel, if camping)
+    pub since: DateTime<Utc>,
+    /// Whether the member is self-muted and self-deafened
+    pub self_mute: bool,
+    pub self_deaf: bool,
+}
+
+impl IntoLua for VoiceIdleThresholdCrossed {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// A member in a voice channel of a guild
+struct Session {
+    channel_id: ChannelId,
+    joined_at: DateTime<Utc>,
+    self_mute: bool,
+    self_deaf: bool,
+    /// When the member became idle, if idle
+    idle_since: Option<DateTime<Utc>>,
+    /// Thresholds already crossed in the current idle spell and stay in the channel
+    crossed: Vec<(VoiceIdleKind, u32)>,
+}
+
+impl Session {
+    fn since(&self, kind: VoiceIdleKind) -> Option<DateTime<Utc>> {
+        match kind {
+            VoiceIdleKind::Idle => self.idle_since,
+            VoiceIdleKind::Camping => Some(self.joined_at),
+        }
+    }
+}
+
+/// Detects members idling (self-muted or deafened) or camping in the voice channels of guilds, dispatching
+/// `VoiceIdleThreshold` as they cross the thresholds of the guild so templates can e.g. move them to the AFK channel
+///
+/// Voice states are tracked from VOICE_STATE_UPDATE (and the voice states of GUILD_CREATE, for members already in
+/// voice) in memory only and forgotten once members leave or the guild stops detecting idling. Sessions are swept
+/// every `SWEEP_INTERVAL`, so thresholds are crossed up to that late. Bots are not tracked
+#[derive(Clone, Default)]
+pub struct VoiceIdleDetector {
+    sessions: Rc<RefCell<HashMap<(GuildId, UserId), Session>>>,
+}
+
+impl VoiceIdleDetector {
+    /// Tracks the voice state of a VOICE_STATE_UPDATE payload
+    pub fn observe(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(user_id) = payload.get("user_id").and_then(|v| v.as_str()).and_then(|v| v.parse::<UserId>().ok()) else {
+            return;
+        };
+        let config = dispatch.tenant_state.voice_idle(Id::Guild(guild_id));
+        self.update(config.as_ref(), guild_id, user_id, payload, Utc::now());
+    }
+
+    /// Tracks the members already in voice given a GUILD_CREATE payload, as of now
+    pub fn seed(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.voice_idle(Id::Guild(guild_id)) else {
+            return;
+        };
+        let now = Utc::now();
+        for state in payload.get("voice_states").and_then(|v| v.as_array()).into_iter().flatten() {
+            if let Some(user_id) = state.get("user_id").and_then(|v| v.as_str()).and_then(|v| v.parse::<UserId>().ok()) {
+                self.update(Some(&config), guild_id, user_id, state, now);
+            }
+        }
+    }
+
+    fn update(&self, config: Option<&VoiceIdleConfig>, guild_id: GuildId, user_id: UserId, state: &Value, now: DateTime<Utc>) {
+        let mut sessions = self.sessions.borrow_mut();
+        let channel_id = state.get("channel_id").and_then(|v| v.as_str()).and_then(|v| v.parse::<ChannelId>().ok());
+        let bot = state.get("member").and_then(|m| m.get("user")).and_then(|u| u.get("bot")).and_then(|v| v.as_bool()).unwrap_or(false);
+        let (Some(config), Some(channel_id)) = (config, channel_id) else {
+            // Left voice (or the guild no longer detects idling)
+            sessions.remove(&(guild_id, user_id));
+            return;
+        };
+        if bot || config.ignored_channels.contains(&channel_id) {
+            sessions.remove(&(guild_id, user_id));
+            return;
+        }
+        if sessions.len() >= MAX_TRACKED_SESSIONS && !sessions.contains_key(&(guild_id, user_id)) {
+            return;
+        }
+
+        let self_mute = state.get("self_mute").and_then(|v| v.as_bool()).unwrap_or(false);
+        let self_deaf = state.get("self_deaf").and_then(|v| v.as_bool()).unwrap_or(false);
+        let idle = self_deaf || (self_mute && !config.deafened_only);
+
+        let session = sessions.entry((guild_id, user_id)).or_insert_with(|| Session {
+            channel_id,
+            joined_at: now,
+            self_mute,
+            self_deaf,
+            idle_since: None,
+            crossed: Vec::new(),
+        });
+        if session.channel_id != channel_id {
+            session.channel_id = channel_id;
+            session.joined_at = now;
+            session.crossed.retain(|(kind, _)| *kind != VoiceIdleKind::Camping);
+        }
+        session.self_mute = self_mute;
+        session.self_deaf = self_deaf;
+        match (idle, session.idle_since) {
+            (true, None) => session.idle_since = Some(now),
+            (false, Some(_)) => {
+                session.idle_since = None;
+                session.crossed.retain(|(kind, _)| *kind != VoiceIdleKind::Idle);
+            }
+            _ => {}
+        }
+    }
+
+    /// Spawns the background task sweeping sessions for crossed thresholds
+    pub fn spawn_sweeper(&self, dispatch: &WorkerDispatch) {
+        let (this, dispatch) = (self.clone(), dispatch.clone());
+        tokio::task::spawn_local(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                for (guild_id, crossed) in this.sweep(&dispatch) {
+                    let user_id = crossed.user_id;
+                    if let Err(e) = dispatch.dispatch_event_complex(Id::Guild(guild_id), VOICE_IDLE_THRESHOLD_EVENT, None, crossed).await {
+                        log::error!("Failed to dispatch voice idle threshold of {user_id} in guild {guild_id}: {e}");
+                    }
+                }
+            }
+        });
+    }
+
+    /// Returns the thresholds newly crossed by members, forgetting the sessions of guilds no longer detecting idling
+    fn sweep(&self, dispatch: &WorkerDispatch) -> Vec<(GuildId, VoiceIdleThresholdCrossed)> {
+        let now = Utc::now();
+        let mut configs: HashMap<GuildId, Option<VoiceIdleConfig>> = HashMap::new();
+        let mut crossed = Vec::new();
+        self.sessions.borrow_mut().retain(|(guild_id, user_id), session| {
+            let config = configs.entry(*guild_id).or_insert_with(|| dispatch.tenant_state.voice_idle(Id::Guild(*guild_id)));
+            let Some(config) = config else {
+                return false;
+            };
+            if config.ignored_channels.contains(&session.channel_id) {
+                return false;
+            }
+
+            for threshold in config.thresholds.iter() {
+                let key = (threshold.kind, threshold.minutes);
+                let Some(since) = session.since(threshold.kind) else {
+                    continue;
+                };
+                if session.crossed.contains(&key) || now - since < chrono::Duration::minutes(threshold.minutes.into()) {
+                    continue;
+                }
+                session.crossed.push(key);
+                crossed.push((*guild_id, VoiceIdleThresholdCrossed {
+                    user_id: *user_id,
+                    channel_id: session.channel_id,
+                    kind: threshold.kind,
+                    threshold_minutes: threshold.minutes,
+                    since,
+                    self_mute: session.self_mute,
+                    self_deaf: session.self_deaf,
+                }));
+            }
+            true
+        });
+        crossed
+    }
+}
@@ -0,0 +1,307 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use khronos_ext::mlua_scheduler_ext::LuaSchedulerAsyncUserData;
+use khronos_runtime::primitives::blob::Blob;
+use khronos_runtime::rt::mlua::prelude::*;
+use sha2::{Digest, Sha256};
+use wasmtime::{Engine, ExternType, Instance, Module, Store, StoreLimits, StoreLimitsBuilder, Trap, Val, ValType};
+
+use crate::config::WasmConfig;
+use crate::geese::ratelimit::Ratelimiter;
+use crate::worker::limits::{MAX_WASM_COMPILE_TIME, MAX_WASM_IO_BYTES, MAX_WASM_MODULE_BYTES, MAX_WASM_MODULES};
+use crate::worker::workervmmanager::Id;
+
+/// Name of the VM global the `@antiraid/wasm` module is exposed as
+pub const WASM_GLOBAL: &str = "__antiraid_wasm";
+
+/// Engine shared by all VMs of the worker, with fuel metering so calls into modules are bounded
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true);
+    // Compiles run on the blocking pool, one thread each
+    config.parallel_compilation(false);
+    Engine::new(&config).expect("Failed to create WASM engine")
+});
+
+/// Compiles of each tenant, shared by all VMs of the worker so recreating a VM doesn't reset it. Cached modules
+/// don't count
+static COMPILE_RATELIMIT: LazyLock<Ratelimiter<Id>> = LazyLock::new(|| Ratelimiter {
+    global: vec![
+        Ratelimiter::limit(2, Duration::from_secs(1)),
+        Ratelimiter::limit(20, Duration::from_secs(60)),
+    ],
+    per_bucket: indexmap::indexmap!(),
+    clock: Default::default(),
+});
+
+/// Compiled modules of a VM by the SHA-256 of their bytes
+///
+/// Compiled code lives outside the Luau heap, so the Luau memory limit of the VM is lowered by the size of the cached
+/// modules to keep the VM as a whole within the memory limit of its tenant
+struct ModuleCache {
+    modules: HashMap<[u8; 32], Module>,
+    /// Bytes of compiled code of the cached modules
+    bytes: usize,
+    /// Memory limit of the tenant
+    memory_limit: usize,
+}
+
+impl ModuleCache {
+    /// Applies the memory limit of the tenant minus the cached modules to the Luau heap
+    fn apply_memory_limit(&self, lua: &Lua) -> LuaResult<()> {
+        lua.set_memory_limit(self.memory_limit.saturating_sub(self.bytes))?;
+        Ok(())
+    }
+
+    /// Evicts a module to make room for another
+    fn evict_one(&mut self) -> bool {
+        let Some(evicted) = self.modules.keys().next().copied() else {
+            return false;
+        };
+        if let Some(module) = self.modules.remove(&evicted) {
+            self.bytes -= module_size(&module);
+        }
+        true
+    }
+}
+
+/// Module cache of a VM, kept as app data so `set_memory_limit` can find it
+struct WasmModules(Rc<RefCell<ModuleCache>>);
+
+/// Bytes of compiled code of a module
+fn module_size(module: &Module) -> usize {
+    let range = module.image_range();
+    range.end as usize - range.start as usize
+}
+
+/// Sets the memory limit of a VM, minus the memory used by its compiled WASM modules
+///
+/// Must be used instead of setting the memory limit of the runtime directly, which would count the modules for free
+pub fn set_memory_limit(lua: &Lua, memory_limit: usize) -> LuaResult<()> {
+    let Some(modules) = lua.app_data_ref::<WasmModules>() else {
+        lua.set_memory_limit(memory_limit)?;
+        return Ok(());
+    };
+    let mut cache = modules.0.borrow_mut();
+    cache.memory_limit = memory_limit;
+    cache.apply_memory_limit(lua)
+}
+
+fn wasm_error(e: wasmtime::Error) -> LuaError {
+    if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+        return LuaError::external("WASM call ran out of fuel");
+    }
+    LuaError::external(format!("WASM error: {e:#}"))
+}
+
+/// Converts a string, buffer or blob passed to the module to bytes, enforcing a size cap
+fn input_bytes(lua: &Lua, value: LuaValue, what: &str, max: usize) -> LuaResult<Vec<u8>> {
+    let bytes = match value {
+        LuaValue::String(s) => s.as_bytes().to_vec(),
+        LuaValue::Buffer(b) => b.to_vec(),
+        // Modules stored as key-value blobs
+        v @ LuaValue::UserData(_) => Blob::from_lua(v, lua)?.0.to_vec(),
+        v => return Err(LuaError::external(format!("{what} must be a string, buffer or blob, got {}", v.type_name()))),
+    };
+
+    if bytes.len() > max {
+        return Err(LuaError::external(format!("{what} exceeds the maximum size of {max} bytes")));
+    }
+
+    Ok(bytes)
+}
+
+/// Converts a Luau number to a WASM value of the given type
+fn to_val(ty: &ValType, arg: LuaValue) -> LuaResult<Val> {
+    let n = match arg {
+        LuaValue::Number(n) => n,
+        LuaValue::Integer(i) => i as f64,
+        v => return Err(LuaError::external(format!("WASM arguments must be numbers, got {}", v.type_name()))),
+    };
+    if matches!(ty, ValType::I32 | ValType::I64) && n.fract() != 0.0 {
+        return Err(LuaError::external(format!("WASM argument {n} must be an integer")));
+    }
+
+    Ok(match ty {
+        ValType::I32 => Val::I32(n as i32),
+        ValType::I64 => Val::I64(n as i64),
+        ValType::F32 => Val::F32((n as f32).to_bits()),
+        ValType::F64 => Val::F64(n.to_bits()),
+        t => return Err(LuaError::external(format!("WASM functions taking {t:?} are not supported"))),
+    })
+}
+
+/// Returns the zero value of a WASM type, for results to be written into
+fn zero_val(ty: &ValType) -> LuaResult<Val> {
+    Ok(match ty {
+        ValType::I32 => Val::I32(0),
+        ValType::I64 => Val::I64(0),
+        ValType::F32 => Val::F32(0),
+        ValType::F64 => Val::F64(0),
+        t => return Err(LuaError::external(format!("WASM functions returning {t:?} are not supported"))),
+    })
+}
+
+/// Converts a WASM value to a Luau number (64 bit integers lose precision past 2^53)
+fn from_val(val: Val) -> LuaResult<LuaValue> {
+    Ok(LuaValue::Number(match val {
+        Val::I32(i) => i as f64,
+        Val::I64(i) => i as f64,
+        Val::F32(f) => f32::from_bits(f) as f64,
+        Val::F64(f) => f64::from_bits(f),
+        _ => return Err(LuaError::external("WASM functions returning references are not supported")),
+    }))
+}
+
+/// A compiled WASM module
+///
+/// Every call runs in a fresh instance of the module with its own fuel and memory, so no state is kept between calls
+struct WasmModule {
+    module: Module,
+    fuel_per_call: u64,
+    max_memory_bytes: usize,
+}
+
+impl WasmModule {
+    fn instantiate(&self) -> LuaResult<(Store<StoreLimits>, Instance)> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory_bytes)
+            .instances(1)
+            .memories(1)
+            .tables(1)
+            .build();
+        let mut store = Store::new(&ENGINE, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.fuel_per_call).map_err(wasm_error)?;
+
+        // Modules can't import anything (checked on compile), so there is nothing to link
+        let instance = Instance::new(&mut store, &self.module, &[]).map_err(wasm_error)?;
+        Ok((store, instance))
+    }
+}
+
+impl LuaUserData for WasmModule {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("exports", |_, this, ()| {
+            Ok(this.module.exports()
+                .filter(|e| matches!(e.ty(), ExternType::Func(_)))
+                .map(|e| e.name().to_string())
+                .collect::<Vec<_>>())
+        });
+
+        methods.add_method("call", |_, this, (name, args): (String, LuaMultiValue)| {
+            let (mut store, instance) = this.instantiate()?;
+            let func = instance.get_func(&mut store, &name)
+                .ok_or_else(|| LuaError::external(format!("WASM module exports no function {name}")))?;
+
+            let ty = func.ty(&store);
+            if ty.params().len() != args.len() {
+                return Err(LuaError::external(format!("WASM function {name} takes {} arguments, got {}", ty.params().len(), args.len())));
+            }
+            let params = ty.params().zip(args).map(|(ty, arg)| to_val(&ty, arg)).collect::<LuaResult<Vec<_>>>()?;
+            let mut results = ty.results().map(|ty| zero_val(&ty)).collect::<LuaResult<Vec<_>>>()?;
+
+            func.call(&mut store, &params, &mut results).map_err(wasm_error)?;
+            results.into_iter().map(from_val).collect::<LuaResult<LuaMultiValue>>()
+        });
+
+        methods.add_method("invoke", |lua, this, (name, input): (String, LuaValue)| {
+            let input = input_bytes(lua, input, "input", MAX_WASM_IO_BYTES)?;
+
+            let (mut store, instance) = this.instantiate()?;
+            let memory = instance.get_memory(&mut store, "memory")
+                .ok_or_else(|| LuaError::external("WASM module exports no memory"))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc").map_err(wasm_error)?;
+            let func = instance.get_typed_func::<(i32, i32), i64>(&mut store, &name).map_err(wasm_error)?;
+
+            // Input size is capped well below i32::MAX
+            let len = input.len() as i32;
+            let ptr = alloc.call(&mut store, len).map_err(wasm_error)?;
+            memory.write(&mut store, ptr as u32 as usize, &input)
+                .map_err(|_| LuaError::external("WASM alloc returned a pointer out of bounds"))?;
+
+            let packed = func.call(&mut store, (ptr, len)).map_err(wasm_error)? as u64;
+            let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+            if out_len > MAX_WASM_IO_BYTES {
+                return Err(LuaError::external(format!("WASM output exceeds the maximum size of {MAX_WASM_IO_BYTES} bytes")));
+            }
+
+            let mut output = vec![0; out_len];
+            memory.read(&store, out_ptr, &mut output)
+                .map_err(|_| LuaError::external(format!("WASM function {name} returned output out of bounds")))?;
+            lua.create_buffer(output)
+        });
+    }
+}
+
+/// The `@antiraid/wasm` module of a VM
+pub struct Wasm {
+    id: Id,
+    cache: Rc<RefCell<ModuleCache>>,
+    fuel_per_call: u64,
+    max_memory_bytes: usize,
+}
+
+impl Wasm {
+    /// Compiles a module on the blocking pool so the VM thread (and every other VM on it) isn't stalled
+    ///
+    /// A compile running past `MAX_WASM_COMPILE_TIME` can't be interrupted, so it is abandoned (finishing in the
+    /// background) and the call errors. The rate limit bounds how many of them a tenant can start
+    async fn compile(&self, lua: &Lua, hash: [u8; 32], bytes: Vec<u8>) -> LuaResult<Module> {
+        COMPILE_RATELIMIT.check("compile", self.id)
+            .map_err(|e| LuaError::external(e.to_error_string()))?;
+
+        let task = tokio::task::spawn_blocking(move || Module::from_binary(&ENGINE, &bytes));
+        let module = match tokio::time::timeout(MAX_WASM_COMPILE_TIME, task).await {
+            Ok(res) => res.map_err(LuaError::external)?.map_err(wasm_error)?,
+            Err(_) => return Err(LuaError::external(format!("WASM module took longer than {MAX_WASM_COMPILE_TIME:?} to compile"))),
+        };
+        if module.imports().next().is_some() {
+            return Err(LuaError::external("WASM modules can't import anything (no host functions or WASI are provided)"));
+        }
+
+        // Make room for the module within the memory limit of the tenant
+        let size = module_size(&module);
+        let mut cache = self.cache.borrow_mut();
+        while (cache.modules.len() >= MAX_WASM_MODULES || lua.used_memory() + cache.bytes + size > cache.memory_limit)
+            && cache.evict_one()
+        {}
+        if lua.used_memory() + size > cache.memory_limit {
+            cache.apply_memory_limit(lua)?;
+            return Err(LuaError::external(format!("WASM module needs {size} bytes once compiled, exceeding the memory limit")));
+        }
+
+        cache.bytes += size;
+        cache.modules.insert(hash, module.clone());
+        cache.apply_memory_limit(lua)?;
+        Ok(module)
+    }
+}
+
+impl LuaUserData for Wasm {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_scheduler_async_method("compile", async |lua, this, bytes: LuaValue| {
+            let bytes = input_bytes(&lua, bytes, "module", MAX_WASM_MODULE_BYTES)?;
+            let hash: [u8; 32] = Sha256::digest(&bytes).into();
+
+            let cached = this.cache.borrow().modules.get(&hash).cloned();
+            let module = match cached {
+                Some(module) => module,
+                None => this.compile(&lua, hash, bytes).await?,
+            };
+
+            Ok(WasmModule { module, fuel_per_call: this.fuel_per_call, max_memory_bytes: this.max_memory_bytes })
+        });
+    }
+}
+
+/// Creates the `@antiraid/wasm` module of a VM, whose memory limit is then managed through `set_memory_limit`
+pub fn create_module(lua: &Lua, config: &WasmConfig, id: Id, memory_limit: usize) -> LuaResult<Wasm> {
+    let cache = Rc::new(RefCell::new(ModuleCache { modules: HashMap::new(), bytes: 0, memory_limit }));
+    lua.set_app_data(WasmModules(cache.clone()));
+    Ok(Wasm { id, cache, fuel_per_call: config.fuel_per_call, max_memory_bytes: config.max_memory_bytes })
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use dapi::GuildId;
+use serde_json::{Value, json};
+
+use crate::CONFIG;
+use crate::geese::watchlist::{Severity, WatchlistDigestReq, WatchlistEntry};
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Internal event dispatched to a guild by the master when its watchlist digest is due
+pub const WATCHLIST_DIGEST_EVENT: &str = "$WatchlistDigest";
+
+/// Max length of a Discord message
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// Posts the watchlist digest of a guild to its watchlist channel
+///
+/// The digest is built from the watchlist cached by the worker, so only needs a round trip to the master if the
+/// watchlist isn't cached
+pub struct WatchlistDigest<'a> {
+    dispatch: &'a WorkerDispatch,
+}
+
+impl<'a> WatchlistDigest<'a> {
+    pub fn new(dispatch: &'a WorkerDispatch) -> Self {
+        Self { dispatch }
+    }
+
+    /// Handles a `$WatchlistDigest` for a guild
+    pub async fn post(&self, guild_id: GuildId, payload: &Value) -> Result<(), crate::Error> {
+        let req: WatchlistDigestReq = serde_json::from_value(payload.clone())?;
+        let id = Id::Guild(guild_id);
+        let state = self.dispatch.tenant_state.get_cached_tenant_state_for(id)?;
+        let Some(channel_id) = state.settings.watchlist_channel_id else {
+            return Ok(());
+        };
+        let watchlist = self.dispatch.tenant_state.watchlist(id).await?;
+        if watchlist.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries = watchlist.values().cloned().collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.severity.cmp(&a.severity).then(b.updated_at.cmp(&a.updated_at)));
+
+        self.dispatch.worker_state.reqwest.post(format!("{}/api/v10/channels/{channel_id}/messages", CONFIG.proxy))
+            .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+            .json(&json!({ "content": digest_message(&entries, req.since), "allowed_mentions": { "parse": [] } }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Renders the digest of a watchlist (sorted by severity), entries added or updated since the previous digest first
+fn digest_message(entries: &[WatchlistEntry], since: Option<DateTime<Utc>>) -> String {
+    let is_new = |e: &WatchlistEntry| since.is_none_or(|since| e.updated_at > since);
+    let (new, rest): (Vec<_>, Vec<_>) = entries.iter().partition(|e| is_new(e));
+
+    let mut out = format!("**Watchlist digest** ({} users)\n", entries.len());
+    if !new.is_empty() {
+        out.push_str("\n__New or updated__\n");
+        for entry in new {
+            out.push_str(&digest_line(entry));
+        }
+    }
+    if !rest.is_empty() {
+        out.push_str("\n__Still watched__\n");
+        for entry in rest {
+            out.push_str(&digest_line(entry));
+        }
+    }
+
+    if out.chars().count() > MAX_MESSAGE_LENGTH {
+        let suffix = "\n...";
+        out = out.chars().take(MAX_MESSAGE_LENGTH - suffix.len()).collect();
+        out.push_str(suffix);
+    }
+    out
+}
+
+fn digest_line(entry: &WatchlistEntry) -> String {
+    let severity = match entry.severity {
+        Severity::High => "🔴",
+        Severity::Medium => "🟠",
+        Severity::Low => "🟡",
+    };
+    if entry.note.is_empty() {
+        format!("{severity} <@{}>\n", entry.user_id)
+    } else {
+        format!("{severity} <@{}>: {}\n", entry.user_id, entry.note)
+    }
+}
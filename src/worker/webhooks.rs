@@ -0,0 +1,260 @@
+use std::{collections::HashMap, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}, sync::Arc, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use hmac::{Hmac, KeyInit, Mac};
+use parking_lot::Mutex;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+use sha2::Sha256;
+
+use crate::worker::workervmmanager::Id;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Maximum number of delivery attempts for a single webhook payload
+const MAX_ATTEMPTS: u32 = 3;
+/// Base delay between retries, doubled for every failed attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+/// Number of consecutive failed deliveries after which the circuit for a tenant and url opens
+const CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+/// How long an open circuit stays open before a single trial delivery is allowed through
+const CIRCUIT_OPEN_DURATION: Duration = Duration::from_secs(5 * 60);
+/// Maximum number of failing tenant and url pairs tracked, all are forgotten once exceeded
+const MAX_TRACKED_CIRCUITS: usize = 10_000;
+/// Maximum size of a webhook body
+pub const MAX_WEBHOOK_BODY_SIZE: usize = 64 * 1024; // 64kb
+
+/// Returns whether an address is publicly routable, webhooks may only connect to these
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ipv4(ip);
+            }
+
+            let segments = ip.segments();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || (segments[0] & 0xfe00) == 0xfc00 // unique local (fc00::/7)
+                || (segments[0] & 0xffc0) == 0xfe80 // link local (fe80::/10)
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8) // documentation (2001:db8::/32)
+                || (segments[0] == 0x0064 && segments[1] == 0xff9b)) // NAT64 (64:ff9b::/96), may translate to an internal address
+        }
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || octets[0] == 0 // "this network" (0.0.0.0/8)
+        || (octets[0] == 100 && (octets[1] & 0xc0) == 64) // shared address space (100.64.0.0/10)
+        || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0) // IETF protocol assignments (192.0.0.0/24)
+        || (octets[0] == 198 && (octets[1] & 0xfe) == 18) // benchmarking (198.18.0.0/15)
+        || octets[0] >= 240) // reserved (240.0.0.0/4)
+}
+
+/// DNS resolver only returning public addresses, so a webhook domain resolving to an internal address
+/// can't be used to reach internal services
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect::<Vec<SocketAddr>>();
+
+            if addrs.is_empty() {
+                return Err(format!("Webhook host {} does not resolve to a public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Circuit breaker state for the webhook url of a single tenant
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Delivers signed execution result webhooks to external systems
+///
+/// Deliveries are fire-and-forget from the VMs point of view: they are retried in the background
+/// and urls which keep failing are circuit-broken for a while to avoid wasting worker resources. Circuits are per tenant
+/// so one tenant failing deliveries to a receiver does not pause them for the other tenants using it
+pub struct ExecWebhooks {
+    /// Client used for deliveries, which does not follow redirects and only connects to public addresses
+    reqwest: reqwest::Client,
+    circuits: Mutex<HashMap<(Id, String), Circuit>>,
+}
+
+impl Default for ExecWebhooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExecWebhooks {
+    pub fn new() -> Self {
+        Self { reqwest: Self::client(), circuits: Mutex::new(HashMap::new()) }
+    }
+
+    /// Creates the client used for deliveries
+    ///
+    /// Urls are validated before delivery, but a redirect or the domain resolving to an internal address
+    /// would bypass that, so redirects are not followed and only public addresses are connected to
+    fn client() -> reqwest::Client {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .connect_timeout(Duration::from_secs(10))
+            .build()
+            .expect("Could not initialize webhook reqwest client")
+    }
+
+    /// Validates a webhook url, only public https urls are allowed
+    pub fn validate_url(url: &str) -> Result<Url, crate::Error> {
+        if !url.is_ascii() {
+            return Err("Webhook url must be ascii-only".into());
+        }
+
+        let parsed_url = Url::parse(url)?;
+        if parsed_url.scheme() != "https" {
+            return Err("Webhook url must use HTTPS".into());
+        }
+
+        // Only allow domain names (no ip literals) to avoid trivially hitting internal services
+        let Some(domain) = parsed_url.domain() else {
+            return Err("Webhook url must have a domain name host".into());
+        };
+
+        if domain == "localhost" || domain.ends_with(".localhost") || domain.ends_with(".internal") {
+            return Err("Webhook url cannot point to an internal host".into());
+        }
+
+        if parsed_url.port().is_some() {
+            return Err("Webhook url cannot have a port".into());
+        }
+
+        Ok(parsed_url)
+    }
+
+    /// Returns the hex encoded signature for a body given the timestamp and secret
+    ///
+    /// Receivers should compute `HMAC-SHA256(secret, "{timestamp}.{body}")` and compare it to the `X-AntiRaid-Signature` header
+    pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Returns true if the circuit for the tenant and url is currently open (deliveries should be skipped)
+    fn is_open(&self, id: Id, url: &str) -> bool {
+        let mut circuits = self.circuits.lock();
+        let Some(circuit) = circuits.get_mut(&(id, url.to_string())) else {
+            return false;
+        };
+
+        match circuit.open_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // Half-open, allow a trial delivery through. A failure will reopen the circuit immediately
+                circuit.open_until = None;
+                circuit.consecutive_failures = CIRCUIT_FAILURE_THRESHOLD - 1;
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn record_result(&self, id: Id, url: &str, success: bool) {
+        let mut circuits = self.circuits.lock();
+        let key = (id, url.to_string());
+        if success {
+            circuits.remove(&key);
+            return;
+        }
+
+        if circuits.len() >= MAX_TRACKED_CIRCUITS && !circuits.contains_key(&key) {
+            circuits.clear();
+        }
+        let circuit = circuits.entry(key).or_default();
+        circuit.consecutive_failures += 1;
+        if circuit.consecutive_failures >= CIRCUIT_FAILURE_THRESHOLD {
+            circuit.open_until = Some(Instant::now() + CIRCUIT_OPEN_DURATION);
+        }
+    }
+
+    /// Queues a delivery of `body` to the given url in the background
+    ///
+    /// Returns an error if the url is invalid or the circuit for the tenant and url is open
+    pub fn deliver(self: &Arc<Self>, id: Id, url: &str, secret: String, body: Vec<u8>) -> Result<(), crate::Error> {
+        let parsed_url = Self::validate_url(url)?;
+
+        if body.len() > MAX_WEBHOOK_BODY_SIZE {
+            return Err(format!("Webhook body exceeds maximum size of {MAX_WEBHOOK_BODY_SIZE} bytes").into());
+        }
+
+        if self.is_open(id, parsed_url.as_str()) {
+            return Err("Webhook url is failing too often, deliveries are temporarily paused".into());
+        }
+
+        let this = self.clone();
+        tokio::task::spawn_local(async move {
+            let key = parsed_url.to_string();
+            for attempt in 0..MAX_ATTEMPTS {
+                if attempt > 0 {
+                    tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+                }
+
+                match this.send_once(parsed_url.clone(), &secret, &body).await {
+                    Ok(()) => {
+                        this.record_result(id, &key, true);
+                        return;
+                    }
+                    Err(e) => {
+                        log::warn!("Webhook delivery for {id:?} failed (attempt {}/{MAX_ATTEMPTS}): {e}", attempt + 1);
+                    }
+                }
+            }
+
+            this.record_result(id, &key, false);
+        });
+
+        Ok(())
+    }
+
+    async fn send_once(&self, url: Url, secret: &str, body: &[u8]) -> Result<(), crate::Error> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let signature = Self::sign(secret, timestamp, body);
+
+        let resp = self.reqwest.post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-AntiRaid-Timestamp", timestamp.to_string())
+            .header("X-AntiRaid-Signature", format!("sha256={signature}"))
+            .timeout(Duration::from_secs(10))
+            .body(body.to_vec())
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            return Err(format!("Webhook returned non-success status code {}", resp.status()).into());
+        }
+
+        Ok(())
+    }
+}
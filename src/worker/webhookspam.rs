@@ -0,0 +1,200 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+use chrono::{DateTime, Utc};
+use dapi::GuildId;
+use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
+use khronos_runtime::rt::mlua::prelude::*;
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::CONFIG;
+use crate::geese::webhookspam::WebhookSpamConfig;
+use crate::worker::workerdispatch::WorkerDispatch;
+use crate::worker::workervmmanager::Id;
+
+/// Event dispatched to a guild when one of its webhooks is deemed compromised
+pub const WEBHOOK_SPAM_EVENT: &str = "WebhookSpamDetected";
+
+/// Maximum number of webhooks whose messages (or revocations) are tracked, all are forgotten once exceeded
+const MAX_TRACKED_WEBHOOKS: usize = 10_000;
+
+/// Message flag of messages crossposted from followed announcement channels
+const CROSSPOSTED_FLAG: u64 = 1 << 1;
+
+/// Audit log reason of revocations
+const REVOKE_REASON: &str = "Webhook spam";
+
+/// Why a webhook was deemed compromised
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookSpamReason {
+    /// The webhook sent more than `max_messages` messages within the window
+    Velocity,
+    /// The webhook sent more than `max_duplicates` messages with the same content within the window
+    Duplicates,
+    /// The webhook mentioned everyone with `block_mass_mentions` set
+    MassMention,
+}
+
+/// A message sent through a webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookMessage {
+    pub message_id: String,
+    pub channel_id: String,
+    pub content: String,
+    pub at: DateTime<Utc>,
+}
+
+/// Data of `WebhookSpamDetected`
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookSpam {
+    pub webhook_id: String,
+    /// The name the webhook sent its messages with
+    pub name: String,
+    pub reason: WebhookSpamReason,
+    /// The messages of the webhook within the window
+    pub messages: Vec<WebhookMessage>,
+    /// Whether the webhook was deleted
+    pub revoked: bool,
+    /// Errors responding to the spam
+    pub errors: Vec<String>,
+}
+
+impl IntoLua for WebhookSpam {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        lua.to_value_with(&self, LUA_SERIALIZE_OPTIONS)
+    }
+}
+
+/// Detects compromised webhooks of a guild from the velocity and content of their messages
+///
+/// Messages of interaction responses and crossposts are not counted. Once a webhook is deemed compromised, it is
+/// deleted (if configured) and reported to the log channel of the guild by its worker before `WebhookSpamDetected`
+/// is dispatched with the messages of the webhook, so templates can delete them
+#[derive(Clone, Default)]
+pub struct WebhookSpamDetector {
+    messages: Rc<RefCell<HashMap<(GuildId, String), VecDeque<WebhookMessage>>>>,
+    /// Webhooks already deemed compromised, so messages sent before their deletion took effect are not reported again
+    flagged: Rc<RefCell<HashSet<(GuildId, String)>>>,
+}
+
+impl WebhookSpamDetector {
+    /// Counts a MESSAGE_CREATE payload if sent through a webhook of a guild with webhook spam detection, responding
+    /// in the background if the webhook is deemed compromised
+    pub fn observe(&self, dispatch: &WorkerDispatch, guild_id: GuildId, payload: &Value) {
+        let Some(config) = dispatch.tenant_state.webhook_spam(Id::Guild(guild_id)) else {
+            return;
+        };
+        let Some(webhook_id) = payload.get("webhook_id").and_then(|v| v.as_str()) else {
+            return;
+        };
+        let interaction = payload.get("application_id").is_some_and(|a| !a.is_null());
+        let crossposted = payload.get("flags").and_then(|v| v.as_u64()).unwrap_or_default() & CROSSPOSTED_FLAG != 0;
+        if interaction || crossposted || config.exempt_webhooks.iter().any(|w| w == webhook_id) {
+            return;
+        }
+        let key = (guild_id, webhook_id.to_string());
+        if self.flagged.borrow().contains(&key) {
+            return;
+        }
+
+        let now = Utc::now();
+        let message = WebhookMessage {
+            message_id: payload.get("id").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            channel_id: payload.get("channel_id").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            content: payload.get("content").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            at: now,
+        };
+        let mass_mention = payload.get("mention_everyone").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        let (reason, messages) = {
+            let mut tracked = self.messages.borrow_mut();
+            if tracked.len() >= MAX_TRACKED_WEBHOOKS && !tracked.contains_key(&key) {
+                tracked.clear();
+            }
+            let messages = tracked.entry(key.clone()).or_default();
+            messages.push_back(message);
+            while messages.front().is_some_and(|m| now - m.at > config.window()) {
+                messages.pop_front();
+            }
+
+            let reason = if config.block_mass_mentions && mass_mention {
+                WebhookSpamReason::MassMention
+            } else if messages.len() > config.max_messages as usize {
+                WebhookSpamReason::Velocity
+            } else if let Some(max) = config.max_duplicates
+                && let Some(last) = messages.back()
+                && !last.content.is_empty()
+                && messages.iter().filter(|m| m.content == last.content).count() > max as usize {
+                WebhookSpamReason::Duplicates
+            } else {
+                return;
+            };
+            (reason, tracked.remove(&key).map(Vec::from).unwrap_or_default())
+        };
+
+        {
+            let mut flagged = self.flagged.borrow_mut();
+            if flagged.len() >= MAX_TRACKED_WEBHOOKS {
+                flagged.clear();
+            }
+            flagged.insert(key);
+        }
+
+        let name = payload.get("author").and_then(|a| a.get("username")).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+        let spam = WebhookSpam { webhook_id: webhook_id.to_string(), name, reason, messages, revoked: false, errors: Vec::new() };
+        let dispatch = dispatch.clone();
+        tokio::task::spawn_local(async move {
+            let webhook_id = spam.webhook_id.clone();
+            if let Err(e) = Self::respond(&dispatch, guild_id, &config, spam).await {
+                log::error!("Failed to respond to spam of webhook {webhook_id} in guild {guild_id}: {e}");
+            }
+        });
+    }
+
+    async fn respond(dispatch: &WorkerDispatch, guild_id: GuildId, config: &WebhookSpamConfig, mut spam: WebhookSpam) -> Result<(), crate::Error> {
+        if config.revoke {
+            let res = dispatch.worker_state.reqwest.delete(format!("{}/api/v10/webhooks/{}", CONFIG.proxy, spam.webhook_id))
+                .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+                .header("X-Audit-Log-Reason", REVOKE_REASON)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            match res {
+                Ok(_) => spam.revoked = true,
+                Err(e) => spam.errors.push(format!("Failed to delete webhook: {e}")),
+            }
+        }
+        if let Some(channel_id) = config.log_channel_id
+            && let Err(e) = log(dispatch, channel_id, &spam).await {
+            spam.errors.push(format!("Failed to post to the log channel: {e}"));
+        }
+
+        dispatch.dispatch_event_complex(Id::Guild(guild_id), WEBHOOK_SPAM_EVENT, None, spam).await?;
+        Ok(())
+    }
+}
+
+/// Reports a compromised webhook to the log channel of the guild
+async fn log(dispatch: &WorkerDispatch, channel_id: dapi::ChannelId, spam: &WebhookSpam) -> Result<(), crate::Error> {
+    let reason = match spam.reason {
+        WebhookSpamReason::Velocity => "sent too many messages",
+        WebhookSpamReason::Duplicates => "sent too many duplicate messages",
+        WebhookSpamReason::MassMention => "mentioned everyone",
+    };
+    let channels = spam.messages.iter().map(|m| m.channel_id.as_str()).collect::<HashSet<_>>().into_iter()
+        .map(|c| format!("<#{c}>"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let action = if spam.revoked { "deleted" } else { "not deleted" };
+    let content = format!("**Webhook spam**: webhook {} ({}) {reason} in {channels}, {action}", spam.name, spam.webhook_id);
+    dispatch.worker_state.reqwest.post(format!("{}/api/v10/channels/{channel_id}/messages", CONFIG.proxy))
+        .header("Authorization", format!("Bot {}", CONFIG.nirn_token))
+        .json(&json!({ "content": content, "allowed_mentions": { "parse": [] } }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
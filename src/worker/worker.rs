@@ -1,3 +1,8 @@
+use crate::geese::activity::ActivityTracker;
+use crate::geese::emojiusage::EmojiUsageTracker;
+use crate::geese::entitlements::EntitlementCache;
+use crate::geese::pluginusage::PluginUsageTracker;
+use crate::geese::usage::UsageTracker;
 use crate::worker::workerstate::WorkerState;
 use crate::worker::workertenantstate::WorkerTenantState;
 
@@ -21,8 +26,14 @@ pub struct WorkerFastPath {
 
 impl Worker {
     pub async fn new(state: WorkerState) -> Result<Self, crate::Error> {        
+        Self::start_usage_flusher(&state);
+        Self::start_plugin_usage_flusher(&state);
+        Self::start_emoji_usage_flusher(&state);
+        Self::start_activity_flusher(&state);
+
         let vm_manager = WorkerVmManager::new();
-        let wts = WorkerTenantState::new(state.mesophyll_client.clone(), vm_manager.clone()).await?;
+        let wts = WorkerTenantState::new(state.mesophyll_client.clone(), vm_manager.clone(), state.entitlements.clone(), state.load.clone()).await?;
+        Self::start_entitlement_refresher(&state, &vm_manager, &wts);
         let dispatch = WorkerDispatch::new(vm_manager.clone(), state, wts.clone());
 
         Ok(Self {
@@ -31,4 +42,122 @@ impl Worker {
             wts
         })
     }
-}
\ No newline at end of file
+
+    /// Periodically flushes accumulated template usage to the master in the background
+    fn start_usage_flusher(state: &WorkerState) {
+        let usage = state.usage.clone();
+        let mesophyll_client = state.mesophyll_client.clone();
+        tokio::task::spawn_local(async move {
+            let mut interval = tokio::time::interval(UsageTracker::FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let records = usage.take();
+                if records.is_empty() {
+                    continue;
+                }
+
+                if let Err(e) = mesophyll_client.record_usage(&records).await {
+                    log::error!("Failed to flush template usage: {e}");
+                    usage.requeue(records);
+                }
+            }
+        });
+    }
+
+    /// Flushes accumulated plugin usage to the master hourly in the background
+    ///
+    /// Unlike template usage, plugin usage is only analytics so failed flushes are dropped rather than re-queued
+    fn start_plugin_usage_flusher(state: &WorkerState) {
+        let plugin_usage = state.plugin_usage.clone();
+        let mesophyll_client = state.mesophyll_client.clone();
+        tokio::task::spawn_local(async move {
+            let mut interval = tokio::time::interval(PluginUsageTracker::FLUSH_INTERVAL);
+            interval.tick().await; // The first tick completes immediately
+            loop {
+                interval.tick().await;
+                let records = plugin_usage.take();
+                if records.is_empty() {
+                    continue;
+                }
+
+                if let Err(e) = mesophyll_client.record_plugin_usage(&records).await {
+                    log::error!("Failed to flush plugin usage: {e}");
+                }
+            }
+        });
+    }
+
+    /// Periodically flushes accumulated emoji and sticker usage to the master in the background
+    ///
+    /// Emoji usage is only analytics so failed flushes are dropped rather than re-queued
+    fn start_emoji_usage_flusher(state: &WorkerState) {
+        let emoji_usage = state.emoji_usage.clone();
+        let mesophyll_client = state.mesophyll_client.clone();
+        tokio::task::spawn_local(async move {
+            let mut interval = tokio::time::interval(EmojiUsageTracker::FLUSH_INTERVAL);
+            interval.tick().await; // The first tick completes immediately
+            loop {
+                interval.tick().await;
+                let records = emoji_usage.take();
+                if records.is_empty() {
+                    continue;
+                }
+
+                if let Err(e) = mesophyll_client.record_emoji_usage(&records).await {
+                    log::error!("Failed to flush emoji usage: {e}");
+                }
+            }
+        });
+    }
+
+    /// Periodically flushes accumulated message activity to the master in the background
+    ///
+    /// Activity is only analytics so failed flushes are dropped rather than re-queued
+    fn start_activity_flusher(state: &WorkerState) {
+        let activity = state.activity.clone();
+        let mesophyll_client = state.mesophyll_client.clone();
+        tokio::task::spawn_local(async move {
+            let mut interval = tokio::time::interval(ActivityTracker::FLUSH_INTERVAL);
+            interval.tick().await; // The first tick completes immediately
+            loop {
+                interval.tick().await;
+                let records = activity.take();
+                if records.is_empty() {
+                    continue;
+                }
+
+                if let Err(e) = mesophyll_client.record_activity(&records).await {
+                    log::error!("Failed to flush activity: {e}");
+                }
+            }
+        });
+    }
+
+    /// Periodically reloads all entitlements in the background, catching up on any missed entitlement events
+    fn start_entitlement_refresher(state: &WorkerState, vm_manager: &WorkerVmManager, wts: &WorkerTenantState) {
+        if crate::CONFIG.premium.is_none() {
+            return;
+        }
+
+        let entitlements = state.entitlements.clone();
+        let reqwest = state.reqwest.clone();
+        let (vm_manager, wts) = (vm_manager.clone(), wts.clone());
+        tokio::task::spawn_local(async move {
+            let mut interval = tokio::time::interval(EntitlementCache::REFRESH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = entitlements.load(&reqwest).await {
+                    log::error!("Failed to load entitlements: {e}");
+                    continue;
+                }
+
+                // Tiers may have been gained or lost, update the limits of running VMs
+                for id in vm_manager.keys() {
+                    if let Err(e) = wts.reload_limits_for(id) {
+                        log::error!("Failed to reload limits for ID {id:?}: {e}");
+                    }
+                }
+            }
+        });
+    }
+}
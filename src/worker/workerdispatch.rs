@@ -3,11 +3,36 @@ use std::borrow::Cow;
 use dapi::UserId;
 use khronos_runtime::primitives::LUA_SERIALIZE_OPTIONS;
 use khronos_runtime::rt::mlua::prelude::*;
-use khronos_runtime::{utils::khronos_value::KhronosValue};
+use khronos_runtime::{utils::khronos_value::{CKhronosValue, KhronosValue}};
+use opentelemetry::{Context, KeyValue, trace::{FutureExt, SpanKind, Status, TraceContextExt}};
 use rand::distr::{Alphanumeric, SampleString};
 use serde::{Deserialize, Serialize};
-use crate::geese::state::{StateDbFlags, StateOp};
-use crate::{geese::tenantstate::DEFAULT_EVENTS, worker::{workerstate::WorkerState, workertenantstate::WorkerTenantState}};
+use crate::geese::state::{StateDbFlags, StateExecResult, StateOp};
+use crate::geese::telemetry;
+use crate::geese::watchlist::WatchlistEntry;
+use crate::worker::actor::EventActor;
+use crate::worker::eventsink::SinkResult;
+use crate::worker::eventtypes::create_typed;
+use crate::worker::altdetect::{ALT_EXPLAIN_EVENT, AltDetector};
+use crate::worker::invites::InviteTracker;
+use crate::worker::namepolicy::NamePolicyEnforcer;
+use crate::worker::nukeprotection::NukeProtection;
+use crate::worker::stickyroles::StickyRoles;
+use crate::worker::webhookspam::WebhookSpamDetector;
+use crate::worker::autopublish::AutoPublisher;
+use crate::worker::emojiusage::EmojiUsage;
+use crate::worker::activity::Activity;
+use crate::worker::auditcorrelation::AuditCorrelator;
+use crate::worker::voiceidle::VoiceIdleDetector;
+use crate::worker::appeals::{APPEAL_REQUEST_EVENT, Appeals};
+use crate::worker::modmail::Modmail;
+use crate::worker::onboarding::Onboarding;
+use crate::worker::permsnapshots::{PERMISSION_SNAPSHOT_EVENT, PermissionSnapshots};
+use crate::worker::schedules::{SCHEDULED_MESSAGE_EVENT, ScheduledMessages};
+use crate::worker::threadpolicies::{THREAD_POLICY_SWEEP_EVENT, ThreadPolicies};
+use crate::worker::watchlist::{WATCHLIST_DIGEST_EVENT, WatchlistDigest};
+use crate::worker::replay::{MAX_RECORDINGS, RECORD_FLAG, REPLAY_SCOPE, Recording, ReplayState};
+use crate::worker::{workerstate::WorkerState, workertenantstate::WorkerTenantState};
 
 use super::workervmmanager::{Id, WorkerVmManager};
 use khronos_runtime::rt::mlua;
@@ -22,18 +47,41 @@ pub struct WorkerDispatch {
     pub tenant_state: WorkerTenantState,
     /// The state all VMs in the WorkerVmManager share
     pub worker_state: WorkerState,
+    /// Onboarding of newly joined guilds
+    pub onboarding: Onboarding,
+    /// Alt detection of members joining guilds
+    pub alt_detector: AltDetector,
+    /// Attribution of members joining guilds to invites
+    pub invite_tracker: InviteTracker,
+    /// Enforcement of the name policies of guilds
+    pub name_policy: NamePolicyEnforcer,
+    /// Restoring the roles of members rejoining guilds
+    pub sticky_roles: StickyRoles,
+    /// Detection of users rapidly taking destructive actions in guilds
+    pub nuke_protection: NukeProtection,
+    /// Detection of compromised webhooks in guilds
+    pub webhook_spam: WebhookSpamDetector,
+    /// Publishing of messages sent in announcement channels of guilds
+    pub auto_publisher: AutoPublisher,
+    /// Attaching the audit log entries of gateway events of guilds to them
+    pub audit_correlator: AuditCorrelator,
+    /// Detection of members idling or camping in the voice channels of guilds
+    pub voice_idle: VoiceIdleDetector,
 }
 
 impl WorkerDispatch {
-    const ERR_SCOPE: &str = "#err";
+    pub const ERR_SCOPE: &str = "#err";
 
     /// Creates a new WorkerDispatch with the given WorkerVmManager
     pub fn new(vm_manager: WorkerVmManager, worker_state: WorkerState, tenant_state: WorkerTenantState) -> Self {
-        let dispatch = Self { vm_manager, worker_state, tenant_state };
+        let dispatch = Self { vm_manager, worker_state, tenant_state, onboarding: Onboarding::default(), alt_detector: AltDetector::default(), invite_tracker: InviteTracker::default(), name_policy: NamePolicyEnforcer::default(), sticky_roles: StickyRoles::default(), nuke_protection: NukeProtection::default(), webhook_spam: WebhookSpamDetector::default(), auto_publisher: AutoPublisher::default(), audit_correlator: AuditCorrelator::default(), voice_idle: VoiceIdleDetector::default() };
 
         // Dispatch startup events for all tenants in the background upon creation of the WorkerDispatch
         dispatch.dispatch_startup_events();
 
+        // Check members in voice against the idle thresholds of their guilds in the background
+        dispatch.voice_idle.spawn_sweeper(&dispatch);
+
         dispatch
     }
 
@@ -57,15 +105,180 @@ impl WorkerDispatch {
 
     /// Dispatches an event to the appropriate VM based on the tenant ID
     pub async fn dispatch_event(&self, id: Id, event: SimpleEvent) -> LuaResult<KhronosValue> {
+        let event = match event.data {
+            SimpleEventData::Replay(recording) => return self.replay(id, *recording).await,
+            data => SimpleEvent { data, ..event },
+        };
+
+        let record = if self.worker_state.feature_flags.is_enabled(RECORD_FLAG, id) {
+            Some(event.clone())
+        } else {
+            None
+        };
+
+        let parent = telemetry::extract(event.traceparent.as_deref());
         let (name, author, data) = (event.name, event.author, event.data);
-        self.dispatch_event_complex(id, &name, author, data).await
+        let bot_id = self.worker_state.stratum.current_user().id;
+        let (mut data, mut actor) = data.resolve_actor(&name, author, bot_id)?;
+        if actor.is_none() && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref mut payload) = data {
+            actor = self.audit_correlator.correlate(self, guild_id, &name, payload).await;
+        }
+        if name == "GUILD_CREATE" && self.tenant_state.archived(id) {
+            // Rehydrate the guild before anything runs against its (otherwise missing) data, restored templates
+            // are picked up through the usual template invalidation
+            match self.worker_state.mesophyll_client.restore_archive(id).await {
+                Ok(Some(ts)) => {
+                    if let Err(e) = self.tenant_state.reload_for_tenant(id, &ts) {
+                        log::error!("Failed to reload tenant state of restored tenant {id:?}: {e}");
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to restore archive of tenant {id:?}: {e}"),
+            }
+        }
+        if name == "GUILD_CREATE" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            self.onboarding.start(self, guild_id, payload);
+            self.voice_idle.seed(self, guild_id, payload);
+        }
+        if name == "VOICE_STATE_UPDATE" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            self.voice_idle.observe(self, guild_id, payload);
+        }
+        if name == "GUILD_DELETE" && let SimpleEventData::Json(ref payload) = data
+            && !payload.get("unavailable").and_then(|v| v.as_bool()).unwrap_or(false) {
+            // The bot was removed from the guild (and not an outage), schedule deletion of its data
+            let mesophyll_client = self.worker_state.mesophyll_client.clone();
+            tokio::task::spawn_local(async move {
+                if let Err(e) = mesophyll_client.tenant_removed(id).await {
+                    log::error!("Failed to report removal of tenant {id:?}: {e}");
+                }
+            });
+        }
+        if name == "GUILD_MEMBER_ADD" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref mut payload) = data {
+            let invite = self.invite_tracker.track(self, guild_id, payload).await;
+            self.alt_detector.start(self, guild_id, payload, invite.and_then(|i| i.code));
+            self.name_policy.start(self, guild_id, payload, true);
+            self.sticky_roles.restore(self, guild_id, payload);
+            self.sticky_roles.observe(self, guild_id, payload);
+        }
+        if name == "GUILD_MEMBER_UPDATE" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            self.name_policy.start(self, guild_id, payload, false);
+            self.sticky_roles.observe(self, guild_id, payload);
+        }
+        if name == "GUILD_MEMBER_REMOVE" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            self.sticky_roles.save(self, guild_id, payload);
+        }
+        if name == "GUILD_AUDIT_LOG_ENTRY_CREATE" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            self.nuke_protection.observe(self, guild_id, payload);
+            self.audit_correlator.observe(self, guild_id, payload);
+        }
+        if name == "MESSAGE_CREATE" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            self.webhook_spam.observe(self, guild_id, payload);
+            self.auto_publisher.observe(self, guild_id, payload);
+            EmojiUsage::observe_message(self, guild_id, payload);
+            Activity::observe_message(self, guild_id, payload);
+        }
+        if name == "MESSAGE_REACTION_ADD" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            EmojiUsage::observe_reaction(self, guild_id, payload);
+        }
+        if name == "THREAD_CREATE" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            ThreadPolicies::start(self, guild_id, payload);
+        }
+        if name == "MESSAGE_CREATE" && let SimpleEventData::Json(ref payload) = data && !Appeals::start_dm(self, id, payload) {
+            Modmail::start(self, id, payload);
+        }
+        if name == "INTERACTION_CREATE" && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data
+            && Appeals::start_interaction(self, guild_id, payload) {
+            return Ok(KhronosValue::Null(()));
+        }
+        if name == APPEAL_REQUEST_EVENT && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            return Appeals::new(self).request(guild_id, payload).await.map_err(LuaError::external);
+        }
+        if name == ALT_EXPLAIN_EVENT && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            return self.alt_detector.explain(self, guild_id, payload).map_err(LuaError::external);
+        }
+        if name == WATCHLIST_DIGEST_EVENT && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            WatchlistDigest::new(self).post(guild_id, payload).await.map_err(LuaError::external)?;
+            return Ok(KhronosValue::Null(()));
+        }
+        if name == PERMISSION_SNAPSHOT_EVENT && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            return PermissionSnapshots::new(self).take(guild_id, payload).await.map_err(LuaError::external);
+        }
+        if name == SCHEDULED_MESSAGE_EVENT && let Id::Guild(guild_id) = id && let SimpleEventData::Json(ref payload) = data {
+            ScheduledMessages::new(self).post(guild_id, payload).await.map_err(LuaError::external)?;
+            return Ok(KhronosValue::Null(()));
+        }
+        if name == THREAD_POLICY_SWEEP_EVENT && let Id::Guild(guild_id) = id {
+            ThreadPolicies::new(self).sweep(guild_id).await.map_err(LuaError::external)?;
+            return Ok(KhronosValue::Null(()));
+        }
+        if name.starts_with("ENTITLEMENT_") && let SimpleEventData::Json(ref payload) = data
+            && let Some(entitled) = self.worker_state.entitlements.handle_event(&name, payload)
+            && let Err(e) = self.tenant_state.reload_limits_for(entitled) {
+            log::error!("Failed to reload limits for ID {entitled:?}: {e}");
+        }
+
+        let Some(sink) = self.worker_state.event_sink.as_ref().filter(|s| s.selects(&name)) else {
+            return self.dispatch_event_cached(id, &name, author, actor, parent, data, record).await;
+        };
+
+        let sink_data = data.to_json();
+        let dispatched_at = chrono::Utc::now();
+        let res = self.dispatch_event_cached(id, &name, author, actor, parent, data, record).await;
+        let result = sink.includes_results().then(|| match &res {
+            Ok(value) => SinkResult::Ok { value: serde_json::to_value(CKhronosValue(value.clone())).unwrap_or_default() },
+            Err(e) => SinkResult::Error { error: e.to_string() },
+        });
+        sink.publish(id, &name, author, dispatched_at, sink_data, result);
+        res
+    }
+
+    /// Dispatches an event, serving it from the result cache if it targets a template whose results are cached
+    #[allow(clippy::too_many_arguments)]
+    async fn dispatch_event_cached(&self, id: Id, name: &str, author: Option<UserId>, actor: Option<EventActor>, parent: Context, data: SimpleEventData, record: Option<SimpleEvent>) -> LuaResult<KhronosValue> {
+        let result_cache = &self.vm_manager.result_cache;
+        let key = match data {
+            // Recorded executions must run to record their inputs
+            SimpleEventData::Json(ref payload) if record.is_none() => result_cache.key(&self.tenant_state, id, name, author, payload),
+            _ => None,
+        };
+        let Some(key) = key else {
+            let res = self.dispatch_event_with_actor(id, name, author, actor, parent, data, record).await;
+            if name == "$TemplateEnv" {
+                // Environment variables of a template may have changed
+                result_cache.invalidate(id);
+            }
+            return res;
+        };
+
+        if let Some(value) = result_cache.get(&key) {
+            self.worker_state.load.record_result_cache_hit();
+            return Ok(value);
+        }
+        self.worker_state.load.record_result_cache_miss();
+
+        let res = self.dispatch_event_with_actor(id, name, author, actor, parent, data, record).await;
+        if let Ok(ref value) = res {
+            result_cache.insert(key, value.clone());
+        }
+        res
     }
 
     pub async fn dispatch_event_complex<Data: IntoLua>(&self, id: Id, name: &str, author: Option<UserId>, data: Data) -> LuaResult<KhronosValue> {
-        let tenant_state = self.tenant_state.get_cached_tenant_state_for(id)
-            .map_err(|e| mlua::Error::external(format!("Failed to get tenant state for ID {id:?}: {e}")))?;
+        self.dispatch_event_with_actor(id, name, author, None, Context::current(), data, None).await
+    }
 
-        if !tenant_state.events.contains_key(name) && !DEFAULT_EVENTS.contains(&name) {
+    /// Dispatches an event to the VM of a tenant
+    ///
+    /// If `record` is set, the inputs of the execution are recorded and saved along with the given (unresolved) event
+    #[allow(clippy::too_many_arguments)]
+    async fn dispatch_event_with_actor<Data: IntoLua>(&self, id: Id, name: &str, author: Option<UserId>, actor: Option<EventActor>, parent: Context, data: Data, record: Option<SimpleEvent>) -> LuaResult<KhronosValue> {
+        if !self.worker_state.feature_flags.allows(&format!("event:{name}"), id) {
+            // Event gated off by a feature flag for this tenant, skip
+            return Ok(KhronosValue::Null(()));
+        }
+
+        let registry = self.tenant_state.registry_for(id);
+        if !registry.handles(name) {
             // Event not registered for this tenant, skip
             return Ok(KhronosValue::Null(()));
         }
@@ -77,7 +290,73 @@ impl WorkerDispatch {
             return Err(mlua::Error::external("Lua VM to dispatch to is broken"));
         }
 
-        match vm_data.runtime.call_in_scheduler::<_, KhronosValue>(vm_data.dispatch_func, Event { name, author, data }).await {
+        let cx = telemetry::child(&parent, "lua.execute", SpanKind::Internal, vec![
+            KeyValue::new("tenant", id.tenant_id()),
+            KeyValue::new("event", name.to_string()),
+            KeyValue::new("registry.age_ms", registry.age_ms() as i64),
+        ]);
+        *vm_data.trace_cx.borrow_mut() = cx.clone();
+        let slices_before = vm_data.time_slicer.as_ref().map(|t| t.stats()).unwrap_or_default();
+
+        // Syscalls made for the event are tagged with the recorded execution, so those made concurrently by other
+        // executions are not recorded. Executions overlapping one being recorded (the dispatches of a tenant normally
+        // run one at a time) are not recorded themselves
+        let replay = vm_data.replay.clone();
+        let record = record.filter(|_| !replay.is_recording());
+        let execution = record.as_ref().map(|_| replay.start_recording());
+        let recorded_at = chrono::Utc::now();
+
+        let watchlist = match actor.as_ref().and_then(|a| a.user_id) {
+            Some(user_id) => self.tenant_state.watchlist_entry(id, user_id).await,
+            None => None,
+        };
+        let fut = vm_data.runtime.call_in_scheduler::<_, KhronosValue>(vm_data.dispatch_func, Event { name, author, actor, watchlist, execution, data })
+            .with_context(cx.clone());
+
+        let running = vm_data.abort.execution();
+        let res = match tokio::time::timeout(registry.limits.return_wait(), fut).await {
+            Ok(res) => res,
+            Err(_) => {
+                // Don't leave the execution's queries running on the master
+                vm_data.abort.abort_execution();
+                Err(mlua::Error::external(format!("Timed out waiting for event {name} to return")))
+            }
+        };
+        drop(running);
+        if vm_data.abort.running() == 0 {
+            vm_data.scratch.clear();
+        }
+
+        // Approximate if other events were executing on the VM concurrently
+        let slices_after = vm_data.time_slicer.as_ref().map(|t| t.stats()).unwrap_or_default();
+        cx.span().set_attributes([
+            KeyValue::new("lua.slices", (slices_after.slices - slices_before.slices) as i64),
+            KeyValue::new("lua.preemptions", (slices_after.preemptions - slices_before.preemptions) as i64),
+        ]);
+
+        if let Err(ref e) = res {
+            cx.span().set_status(Status::error(e.to_string()));
+        }
+        cx.span().end();
+
+        if let Some(event) = record {
+            let (syscalls, clock, entropy) = replay.finish_recording();
+            let recording = Recording {
+                id,
+                event,
+                recorded_at,
+                syscalls,
+                clock,
+                entropy,
+                error: res.as_ref().err().map(|e| e.to_string()),
+            };
+
+            if let Err(e) = self.save_recording(recording).await {
+                log::error!("Failed to save recording for ID {id:?}: {e}");
+            }
+        }
+
+        match res {
             Ok(result) => Ok(result),
             Err(e) => {
                 let err_str = e.to_string();
@@ -89,6 +368,94 @@ impl WorkerDispatch {
         }
     }
 
+    /// Replays a recorded execution in a fresh VM
+    ///
+    /// Syscalls and clock readings are fed back from the recording. Returns the result of the replayed
+    /// execution or an error if the replay diverged from the recording
+    async fn replay(&self, id: Id, recording: Recording) -> LuaResult<KhronosValue> {
+        let tenant_state = self.tenant_state.get_cached_tenant_state_for(id)
+            .map_err(|e| mlua::Error::external(format!("Failed to get tenant state for ID {id:?}: {e}")))?;
+
+        // Only read-only syscalls are allowed while the VM is being set up
+        let replay = ReplayState::sealed();
+        let vm_data = self.vm_manager.create_replay_vm(id, &self.worker_state, &self.tenant_state, replay.clone())
+            .map_err(|e| mlua::Error::external(format!("Failed to create replay VM for ID {id:?}: {e}")))?;
+
+        let event = recording.event.clone();
+        let (name, author, data) = (event.name, event.author, event.data);
+        let bot_id = self.worker_state.stratum.current_user().id;
+        let res = match data.resolve_actor(&name, author, bot_id) {
+            Ok((data, actor)) => {
+                let watchlist = match actor.as_ref().and_then(|a| a.user_id) {
+                    Some(user_id) => self.tenant_state.watchlist_entry(id, user_id).await,
+                    None => None,
+                };
+                let execution = Some(replay.start_replay(&recording));
+                let fut = vm_data.runtime.call_in_scheduler::<_, KhronosValue>(vm_data.dispatch_func.clone(), Event { name: &name, author, actor, watchlist, execution, data });
+                match tokio::time::timeout(tenant_state.limits.return_wait(), fut).await {
+                    Ok(res) => res,
+                    Err(_) => Err(mlua::Error::external(format!("Timed out waiting for replay of event {name} to return"))),
+                }
+            }
+            Err(e) => Err(e),
+        };
+
+        vm_data.abort.abort();
+        if let Err(e) = vm_data.runtime.mark_broken(true) {
+            log::error!("Failed to tear down replay VM for ID {id:?}: {e}");
+        }
+
+        let remaining = replay.remaining_syscalls();
+        if remaining > 0 {
+            return Err(mlua::Error::external(format!("Replay diverged: {remaining} recorded syscalls were never made")));
+        }
+
+        match (res, recording.error) {
+            (Ok(_), Some(error)) => Err(mlua::Error::external(format!("Replay diverged: execution succeeded but originally failed with: {error}"))),
+            (Err(e), None) => Err(mlua::Error::external(format!("Replay diverged: execution failed but originally succeeded: {e}"))),
+            (res, _) => res,
+        }
+    }
+
+    /// Saves a recording to key-value API, keyed by the time it was recorded at
+    ///
+    /// Only the most recent `MAX_RECORDINGS` recordings of a tenant are kept, older ones are evicted (recordings past
+    /// `MAX_RECORDING_AGE` are also deleted by the data lifecycle of the master)
+    async fn save_recording(&self, recording: Recording) -> Result<(), crate::Error> {
+        let id = recording.id;
+        let key = format!("{}-{}", recording.recorded_at.timestamp_millis(), Alphanumeric.sample_string(&mut rand::rng(), 16));
+        let value = KhronosValue::Text(recording.event.name.to_string().into());
+        let blob = rmp_serde::to_vec(&recording)
+            .map_err(|e| format!("Failed to encode recording: {e}"))?;
+        let ops = vec![
+            StateOp::KvSet { key, scope: REPLAY_SCOPE.into(), value, blob: Some(blob.into()) },
+            StateOp::KvFind { query: "%%".to_string(), scope: REPLAY_SCOPE.into() },
+        ];
+        let res = self.worker_state.mesophyll_client.exec_state_op(id, ops, StateDbFlags::WORKER_INITIATED).await?;
+        if let Some(ref ts) = res.new_tenant_state {
+            self.tenant_state.reload_for_tenant(id, ts)?;
+        }
+
+        let mut recordings = res.results.into_iter()
+            .filter_map(|r| match r {
+                StateExecResult::Kv { l } => Some((Recording::recorded_at_millis(&l.key), l.key)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if recordings.len() <= MAX_RECORDINGS {
+            return Ok(());
+        }
+
+        recordings.sort();
+        let excess = recordings.len() - MAX_RECORDINGS;
+        let ops = recordings.into_iter()
+            .take(excess)
+            .map(|(_, key)| StateOp::KvDelete { key, scope: REPLAY_SCOPE.into() })
+            .collect::<Vec<_>>();
+        self.worker_state.mesophyll_client.exec_state_op(id, ops, StateDbFlags::WORKER_INITIATED).await?;
+        Ok(())
+    }
+
     /// Saves a error directly to key-value API
     async fn save_error(&self, id: Id, error: String) -> Result<(), crate::Error> {
         let key = Alphanumeric.sample_string(&mut rand::rng(), 64);
@@ -104,6 +471,11 @@ impl WorkerDispatch {
 pub struct Event<'a, Data: IntoLua> {
     name: &'a str,
     author: Option<UserId>,
+    actor: Option<EventActor>,
+    /// The watchlist entry of the actor, if they are watchlisted
+    watchlist: Option<WatchlistEntry>,
+    /// The recorded (or replayed) execution the event is run as, if any
+    execution: Option<u64>,
     data: Data,
 }
 
@@ -115,10 +487,20 @@ impl<'a, Data: IntoLua> IntoLua for Event<'a, Data> {
             Some(author) => tab.set("author", author.to_string())?,
             None => {},
         }
-        tab.set(
-            "data",
-            self.data
-        )?;
+        if let Some(actor) = self.actor {
+            tab.set("actor", actor)?;
+        }
+        if let Some(watchlist) = self.watchlist {
+            tab.set("watchlist", watchlist)?;
+        }
+        if let Some(execution) = self.execution {
+            tab.set("execution", execution)?;
+        }
+        let data = self.data.into_lua(lua)?;
+        if let Some(typed) = create_typed(lua, self.name, &data)? {
+            tab.set("typed", typed)?;
+        }
+        tab.set("data", data)?;
         tab.set_readonly(true);
         Ok(LuaValue::Table(tab))
     }
@@ -142,7 +524,41 @@ impl IntoLua for OnStartupData {
 enum SimpleEventData {
     KhronosValue(KhronosValue),
     JsonString(String),
-    FeedTicketRequest(Vec<String>)
+    FeedTicketRequest(Vec<String>),
+    /// Already parsed json data (created by the worker when resolving the actor of a json event)
+    Json(serde_json::Value),
+    /// A recorded execution to replay
+    Replay(Box<Recording>),
+}
+
+impl SimpleEventData {
+    /// Resolves the actor of the event, parsing json payloads in the process
+    fn resolve_actor(self, name: &str, author: Option<UserId>, bot_id: UserId) -> LuaResult<(Self, Option<EventActor>)> {
+        if let Some(author) = author {
+            return Ok((self, Some(EventActor::from_author(author, bot_id))));
+        }
+
+        match self {
+            Self::JsonString(value) => {
+                let value: serde_json::Value = serde_json::from_str(&value)
+                    .map_err(|e| LuaError::external(e))?;
+                let actor = EventActor::from_payload(name, &value, bot_id);
+                Ok((Self::Json(value), actor))
+            }
+            _ => Ok((self, None))
+        }
+    }
+
+    /// Returns the data as json, for the event sink
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::KhronosValue(value) => serde_json::to_value(CKhronosValue(value.clone())).unwrap_or_default(),
+            Self::JsonString(value) => serde_json::from_str(value).unwrap_or_default(),
+            Self::Json(value) => value.clone(),
+            Self::FeedTicketRequest(topics) => serde_json::json!({ "topics": topics }),
+            Self::Replay(_) => serde_json::Value::Null,
+        }
+    }
 }
 
 impl IntoLua for SimpleEventData {
@@ -156,18 +572,24 @@ impl IntoLua for SimpleEventData {
                     .map_err(|e| LuaError::external(e))?;
                 lua.to_value_with(&value, LUA_SERIALIZE_OPTIONS)
             },
+            Self::Json(ref value) => {
+                lua.to_value_with(value, LUA_SERIALIZE_OPTIONS)
+            },
             Self::FeedTicketRequest(topics) => {
                 let tab = lua.create_table_with_capacity(0, 2)?;
                 tab.set("topics", topics)?;
                 tab.set_readonly(true);
                 Ok(LuaValue::Table(tab))
             }
+            Self::Replay(_) => {
+                Err(LuaError::external("Replay events cannot be passed to templates"))
+            }
         }
     }
 }
 
 /// An `SimpleEvent` is a/an thread-safe object that can be used to create a Event
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SimpleEvent {
     /// The name of the event
     name: Cow<'static, str>,
@@ -175,21 +597,42 @@ pub struct SimpleEvent {
     author: Option<UserId>,
     /// The inner data of the object
     data: SimpleEventData,
+    /// The W3C trace context of the event, if traced
+    ///
+    /// This is sent out-of-band over mesophyll (and so is not serialized with the event)
+    #[serde(skip)]
+    traceparent: Option<String>,
 }
 
 impl SimpleEvent {
     /// Create a new Event given a khronos value
     pub fn new_khronos_value(name: String, author: Option<UserId>, data: KhronosValue) -> Self {
-        Self { name: name.into(), author, data: SimpleEventData::KhronosValue(data) }
+        Self { name: name.into(), author, data: SimpleEventData::KhronosValue(data), traceparent: None }
     }
 
     /// Create a new Event given a raw json string
     pub fn new_json_string(name: String, author: Option<UserId>, data: String) -> Self {
-        Self { name: name.into(), author, data: SimpleEventData::JsonString(data) }
+        Self { name: name.into(), author, data: SimpleEventData::JsonString(data), traceparent: None }
     }
 
     /// Create a new Event for a feed ticket request
     pub fn new_feed_ticket_request(author: Option<UserId>, topics: Vec<String>) -> Self {
-        Self { name: "FeedTicketRequest".into(), author, data: SimpleEventData::FeedTicketRequest(topics) }
+        Self { name: "FeedTicketRequest".into(), author, data: SimpleEventData::FeedTicketRequest(topics), traceparent: None }
     }
-}
\ No newline at end of file
+
+    /// Create a new Event replaying a recorded execution
+    pub fn new_replay(recording: Recording) -> Self {
+        Self { name: recording.event.name.clone(), author: None, data: SimpleEventData::Replay(Box::new(recording)), traceparent: None }
+    }
+
+    /// Sets the W3C trace context the event was dispatched under
+    pub fn with_traceparent(mut self, traceparent: Option<String>) -> Self {
+        self.traceparent = traceparent;
+        self
+    }
+
+    /// Returns the W3C trace context the event was dispatched under, if any
+    pub fn traceparent(&self) -> Option<&str> {
+        self.traceparent.as_deref()
+    }
+}
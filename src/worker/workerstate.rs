@@ -1,5 +1,6 @@
 use std::sync::Arc;
-use crate::{geese::stratum::Stratum, mesophyll::client::MesophyllClient};
+use crate::CONFIG;
+use crate::{geese::{entitlements::EntitlementCache, featureflags::FeatureFlagCache, stratum::Stratum, pluginusage::PluginUsageTracker, emojiusage::EmojiUsageTracker, activity::ActivityTracker, usage::UsageTracker}, mesophyll::client::MesophyllClient, worker::{eventsink::EventSink, imggen::ImgGen, load::LoadTracker, webhooks::ExecWebhooks}};
 
 
 #[derive(Clone)]
@@ -9,6 +10,17 @@ pub struct WorkerState {
     pub stratum: Stratum,
     pub worker_print: bool,
     pub reqwest: reqwest::Client,
+    pub webhooks: Arc<ExecWebhooks>,
+    pub imggen: Arc<ImgGen>,
+    pub feature_flags: Arc<FeatureFlagCache>,
+    pub usage: Arc<UsageTracker>,
+    pub entitlements: Arc<EntitlementCache>,
+    pub plugin_usage: Arc<PluginUsageTracker>,
+    pub emoji_usage: Arc<EmojiUsageTracker>,
+    pub activity: Arc<ActivityTracker>,
+    pub load: Arc<LoadTracker>,
+    /// Publishes dispatched events to Kafka or NATS, if configured
+    pub event_sink: Option<Arc<EventSink>>,
 }
 
 impl WorkerState {
@@ -20,8 +32,18 @@ impl WorkerState {
         worker_print: bool
     ) -> Self {
         Self {
+            feature_flags: mesophyll_client.feature_flags.clone(),
+            usage: Arc::new(UsageTracker::default()),
+            entitlements: Arc::new(EntitlementCache::default()),
+            plugin_usage: Arc::new(PluginUsageTracker::default()),
+            emoji_usage: Arc::new(EmojiUsageTracker::default()),
+            activity: Arc::new(ActivityTracker::default()),
+            load: Arc::new(LoadTracker::default()),
+            event_sink: CONFIG.event_sink.as_ref().map(|c| Arc::new(EventSink::new(c, mesophyll_client.worker_id, reqwest.clone()))),
             mesophyll_client,
             stratum,
+            webhooks: Arc::new(ExecWebhooks::new()),
+            imggen: Arc::new(ImgGen::new()),
             reqwest,
             worker_print
         }
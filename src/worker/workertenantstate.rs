@@ -1,22 +1,43 @@
-use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc, sync::Arc};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc, sync::Arc, time::{Duration, Instant}};
 
-use crate::{geese::tenantstate::{ModFlags, TenantState}, mesophyll::client::MesophyllClient, worker::workervmmanager::{Id, WorkerVmManager}};
+use dapi::UserId;
+
+use crate::{geese::{entitlements::EntitlementCache, namepolicy::NamePolicy, nukeprotection::NukeProtectionConfig, webhookspam::WebhookSpamConfig, autopublish::AutoPublishConfig, threadpolicies::ThreadPoliciesConfig, emojiusage::EmojiUsageConfig, activity::ActivityConfig, auditcorrelation::AuditCorrelationConfig, voiceidle::VoiceIdleConfig, resultcache::ResultCacheConfig, settingssync::{self, SyncOutcome, TenantStateSync}, stickyroles::StickyRolesConfig, state::{StateDbFlags, StateExecResult, StateOp}, tenantstate::{ModFlags, TenantLimits, TenantState}, watchlist::WatchlistEntry}, mesophyll::client::MesophyllClient, worker::{altdetect::AltSensitivity, load::LoadTracker, templateregistry::{RegistryEntry, TemplateRegistry}, workervmmanager::{Id, WorkerVmManager}}};
+
+/// How long a cached watchlist is used before being reloaded, bounding how long changes made outside of the worker
+/// (such as by the master) take to apply
+const WATCHLIST_TTL: Duration = Duration::from_secs(60);
+
+/// A watchlist cached by the worker
+type CachedWatchlist = Rc<HashMap<UserId, WatchlistEntry>>;
 
 #[derive(Clone)]
 pub struct WorkerTenantState {
+    mesophyll_client: Arc<MesophyllClient>,
     vm_manager: WorkerVmManager,
+    entitlements: Arc<EntitlementCache>,
     tenant_state_cache: Rc<RefCell<HashMap<Id, TenantState>>>, // Maps tenant IDs to their states
+    /// Versions of the tenant states last synced by the master, unset for tenant states loaded otherwise
+    synced_versions: Rc<RefCell<HashMap<Id, u64>>>,
+    registry: TemplateRegistry,
+    /// Watchlists of guilds, loaded when first needed as they aren't part of the tenant state
+    watchlists: Rc<RefCell<HashMap<Id, (Instant, CachedWatchlist)>>>,
 }
 
 impl WorkerTenantState {
-    pub async fn new(mesophyll_client: Arc<MesophyllClient>, vm_manager: WorkerVmManager) -> Result<Self, crate::Error> {
+    pub async fn new(mesophyll_client: Arc<MesophyllClient>, vm_manager: WorkerVmManager, entitlements: Arc<EntitlementCache>, load: Arc<LoadTracker>) -> Result<Self, crate::Error> {
         // Initialize the tenant state cache with the current tenant states from the database
         //
         // The tenant state cache acts as a routing table
         let t_states = mesophyll_client.list_tenant_states().await?;
         Ok(Self {
+            mesophyll_client,
             vm_manager,
-            tenant_state_cache: Rc::new(RefCell::new(t_states))
+            entitlements,
+            tenant_state_cache: Rc::new(RefCell::new(t_states)),
+            synced_versions: Rc::default(),
+            registry: TemplateRegistry::new(load),
+            watchlists: Rc::default(),
         })
     }
 
@@ -24,28 +45,217 @@ impl WorkerTenantState {
     /// 
     /// Returns if the worker was reloaded (true) or not (false)
     pub fn reload_for_tenant(&self, id: Id, tenant_state: &TenantState) -> Result<bool, crate::Error> {
-        let reload_vm = tenant_state.modflags.contains(ModFlags::BANNED);
-        {
+        // Not synced by the master, so the master's next diff can't be applied on top of it
+        self.synced_versions.borrow_mut().remove(&id);
+        self.load(id, tenant_state)
+    }
+
+    /// Applies a tenant state sync from the master, diffs only apply on top of the version they were made against
+    pub fn apply_sync(&self, id: Id, sync: TenantStateSync) -> Result<SyncOutcome, crate::Error> {
+        let current = self.synced_versions.borrow().get(&id).copied();
+        let (version, tenant_state) = match sync {
+            TenantStateSync::Full { version, .. } if current.is_some_and(|c| c >= version) => return Ok(SyncOutcome::Stale),
+            TenantStateSync::Full { version, tenant_state } => (version, tenant_state),
+            TenantStateSync::Diff { base_version, version, changes } => {
+                if current != Some(base_version) {
+                    return Ok(SyncOutcome::Resync);
+                }
+                let Some(base) = self.tenant_state(id) else {
+                    return Ok(SyncOutcome::Resync);
+                };
+                (version, settingssync::apply(&base, changes)?)
+            }
+        };
+
+        let reloaded = self.load(id, &tenant_state)?;
+        self.synced_versions.borrow_mut().insert(id, version);
+        Ok(SyncOutcome::Applied { reloaded })
+    }
+
+    fn load(&self, id: Id, tenant_state: &TenantState) -> Result<bool, crate::Error> {
+        let (old_limits, old_disabled_plugins) = {
             let mut cache = self.tenant_state_cache.borrow_mut();
-            cache.insert(id, tenant_state.clone());
-        }
+            cache.insert(id, tenant_state.clone()).map(|ts| (ts.limits, ts.settings.disabled_plugins)).unwrap_or_default()
+        };
+        self.registry.invalidate(id);
+        let (old_limits, new_limits) = (self.effective_limits(id, old_limits), self.effective_limits(id, tenant_state.limits));
+
+        // Plugins are set up when the VM is created, so the VM is recreated for changes to them to apply. So is the
+        // execution time limit if it is enforced by the runtime rather than the time slicer
+        let reload_vm = tenant_state.modflags.contains(ModFlags::BANNED)
+            || old_disabled_plugins != tenant_state.settings.disabled_plugins
+            || (crate::CONFIG.template_time_slice_ms.is_none() && old_limits.execution_time() != new_limits.execution_time());
 
         // Drop any bad tenants here 
         if reload_vm {
             self.vm_manager.remove_vm_for(id)?; 
+        } else if old_limits != new_limits {
+            self.vm_manager.apply_limits(id, &new_limits)?;
         }
 
         Ok(reload_vm)
     }
 
-    /// Gets the tenant state for a specific tenant
+    /// Reapplies the limits of a tenant to its VM after its premium tiers changed
+    pub fn reload_limits_for(&self, id: Id) -> Result<(), crate::Error> {
+        self.registry.invalidate(id);
+        if crate::CONFIG.template_time_slice_ms.is_none() {
+            // The execution time limit is enforced by the runtime, so the VM is recreated for it to apply
+            return self.vm_manager.remove_vm_for(id);
+        }
+        let limits = self.tenant_state_cache.borrow().get(&id).map(|ts| ts.limits).unwrap_or_default();
+        self.vm_manager.apply_limits(id, &self.effective_limits(id, limits))
+    }
+
+    /// Returns the tenant state of a tenant as last loaded, if loaded
+    pub fn tenant_state(&self, id: Id) -> Option<TenantState> {
+        self.tenant_state_cache.borrow().get(&id).cloned()
+    }
+
+    /// Gets the tenant state for a specific tenant, with its premium tiers and the defaults of its tenant type applied to its limits
     pub fn get_cached_tenant_state_for(&self, id: Id) -> Result<TenantState, crate::Error> {
         let cache = self.tenant_state_cache.borrow();
-        match cache.get(&id) {
-            Some(state) => Ok(state.clone()),
-            None => Ok(TenantState::default())
+        let mut state = cache.get(&id).cloned().unwrap_or_default();
+        state.limits = self.effective_limits(id, state.limits);
+        Ok(state)
+    }
+
+    /// Returns the template registry entry of a tenant, used on the dispatch hot path instead of the full tenant state
+    pub fn registry_for(&self, id: Id) -> Rc<RegistryEntry> {
+        self.registry.get_or_build(id, || {
+            let cache = self.tenant_state_cache.borrow();
+            let (events, limits) = cache.get(&id)
+                .map(|ts| (ts.events.keys().cloned().collect::<HashSet<_>>(), ts.limits))
+                .unwrap_or_default();
+            (events, self.effective_limits(id, limits))
+        })
+    }
+
+    /// Returns the watchlist of a tenant, loading it from the master unless cached within `WATCHLIST_TTL`
+    pub async fn watchlist(&self, id: Id) -> Result<CachedWatchlist, crate::Error> {
+        if !matches!(id, Id::Guild(_)) {
+            return Ok(Rc::default());
+        }
+        if let Some((loaded_at, watchlist)) = self.watchlists.borrow().get(&id)
+            && loaded_at.elapsed() < WATCHLIST_TTL
+        {
+            return Ok(watchlist.clone());
+        }
+
+        let res = self.mesophyll_client.exec_state_op(id, vec![StateOp::WatchlistList {}], StateDbFlags::WORKER_INITIATED).await?;
+        let watchlist: CachedWatchlist = Rc::new(res.results.into_iter()
+            .filter_map(|r| match r {
+                StateExecResult::Watchlist { e } => Some((e.user_id, e)),
+                _ => None,
+            })
+            .collect());
+        self.watchlists.borrow_mut().insert(id, (Instant::now(), watchlist.clone()));
+        Ok(watchlist)
+    }
+
+    /// Returns the watchlist entry of a user in a tenant, if they are watchlisted
+    ///
+    /// Failures to load the watchlist are logged and treated as the user not being watchlisted
+    pub async fn watchlist_entry(&self, id: Id, user_id: UserId) -> Option<WatchlistEntry> {
+        match self.watchlist(id).await {
+            Ok(watchlist) => watchlist.get(&user_id).cloned(),
+            Err(e) => {
+                log::warn!("Failed to load watchlist of {id:?}: {e}");
+                None
+            }
         }
     }
+
+    /// Drops the cached watchlist of a tenant after it changed
+    pub fn invalidate_watchlist(&self, id: Id) {
+        self.watchlists.borrow_mut().remove(&id);
+    }
+
+    /// Returns the alt detection sensitivity of a tenant, if alt detection is enabled
+    pub fn alt_sensitivity(&self, id: Id) -> Option<AltSensitivity> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.alt_sensitivity
+    }
+
+    /// Returns whether the invites members join a tenant through are tracked
+    pub fn invite_tracking(&self, id: Id) -> bool {
+        self.tenant_state_cache.borrow().get(&id).is_some_and(|ts| ts.settings.invite_tracking)
+    }
+
+    /// Returns the name policy of a tenant, if names are checked
+    pub fn name_policy(&self, id: Id) -> Option<NamePolicy> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.name_policy.clone()
+    }
+
+    /// Returns the sticky roles config of a tenant, if roles are restored on rejoin
+    pub fn sticky_roles(&self, id: Id) -> Option<StickyRolesConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.sticky_roles.clone()
+    }
+
+    /// Returns whether the permissions of a tenant are snapshotted
+    pub fn permission_snapshots(&self, id: Id) -> bool {
+        self.tenant_state_cache.borrow().get(&id).is_some_and(|ts| ts.settings.permission_snapshots)
+    }
+
+    /// Returns the nuke protection config of a tenant, if destructive actions are counted
+    pub fn nuke_protection(&self, id: Id) -> Option<NukeProtectionConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.nuke_protection.clone()
+    }
+
+    /// Returns the webhook spam config of a tenant, if webhook messages are counted
+    pub fn webhook_spam(&self, id: Id) -> Option<WebhookSpamConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.webhook_spam.clone()
+    }
+
+    /// Returns which messages of announcement channels are published for a tenant, if any
+    pub fn auto_publish(&self, id: Id) -> Option<AutoPublishConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.auto_publish.clone()
+    }
+
+    /// Returns the thread policies of a tenant, if any
+    pub fn thread_policies(&self, id: Id) -> Option<ThreadPoliciesConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.thread_policies.clone()
+    }
+
+    /// Returns how emoji usage of a tenant is tracked, if it is
+    pub fn emoji_usage(&self, id: Id) -> Option<EmojiUsageConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.emoji_usage.clone()
+    }
+
+    /// Returns how message activity of a tenant is tracked, if it is
+    pub fn activity(&self, id: Id) -> Option<ActivityConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.activity.clone()
+    }
+
+    /// Returns which gateway events of a tenant are correlated with the audit log, if any
+    pub fn audit_correlation(&self, id: Id) -> Option<AuditCorrelationConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.audit_correlation.clone()
+    }
+
+    /// Returns how members idling in voice channels of a tenant are detected, if they are
+    pub fn voice_idle(&self, id: Id) -> Option<VoiceIdleConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.voice_idle.clone()
+    }
+
+    /// Returns the result caching config of a tenant
+    pub fn result_cache(&self, id: Id) -> Option<ResultCacheConfig> {
+        self.tenant_state_cache.borrow().get(&id)?.settings.result_cache.clone()
+    }
+
+    /// Returns if the data of a tenant has been moved to cold storage
+    pub fn archived(&self, id: Id) -> bool {
+        self.tenant_state_cache.borrow().get(&id).is_some_and(|ts| ts.archived_at.is_some())
+    }
+
+    /// Drops the registry entry and cached watchlist of a tenant whose VM was dropped
+    pub fn forget(&self, id: Id) {
+        self.registry.invalidate(id);
+        self.invalidate_watchlist(id);
+    }
+
+    /// Returns the limits a tenant's VM runs with given its own limit overrides
+    fn effective_limits(&self, id: Id, limits: TenantLimits) -> TenantLimits {
+        self.entitlements.limits_for(id, limits).for_tenant(id)
+    }
     /// Returns the set of tenant IDs that have startup events enabled
     pub fn get_startup_event_tenants(&self) -> HashSet<Id> {
         let mut startup_events = HashSet::new();  
@@ -2,10 +2,21 @@ use khronos_runtime::utils::khronos_value::KhronosValue;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::Sender as OneShotSender;
 use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
 
 
-use crate::geese::tenantstate::TenantState;
-use crate::worker::limits::MAX_VM_THREAD_STACK_SIZE;
+use crate::geese::pluginusage::PluginUsageTracker;
+use crate::geese::emojiusage::EmojiUsageTracker;
+use crate::geese::activity::ActivityTracker;
+use crate::geese::telemetry;
+use crate::geese::templatecache::Invalidate;
+use crate::geese::settingssync::{SyncOutcome, TenantStateSync};
+use crate::geese::usage::UsageTracker;
+use crate::worker::dispatchgate::DispatchGate;
+use crate::worker::limits::{MAX_CONCURRENT_DISPATCHES, MAX_VM_THREAD_STACK_SIZE};
+use crate::worker::load::LoadTracker;
 use crate::worker::workerdispatch::SimpleEvent;
 use crate::worker::workerstate::WorkerState;
 use super::{worker::Worker, workervmmanager::Id};
@@ -19,15 +30,21 @@ enum WorkerThreadMessage {
         id: Id,
         tx: OneShotSender<Result<(), crate::Error>>,
     },
-    /// Requests the worker to update the tenant state to the new tenant state
+    /// Requests the worker to sync the tenant state of a tenant, in full or as a diff
     UpdateTenantState {
         id: Id,
-        ts: TenantState,
-        tx: OneShotSender<Result<bool, crate::Error>>,
+        sync: TenantStateSync,
+        tx: OneShotSender<Result<SyncOutcome, crate::Error>>,
+    },
+    /// Tells the VMs affected by an invalidation to reload their templates
+    InvalidateTemplates {
+        invalidate: Invalidate,
     },
     DispatchEvent {
         id: Id,
         event: SimpleEvent,
+        /// When the event was queued, used to trace time spent waiting for the VM thread
+        queued_at: SystemTime,
         tx: Option<OneShotSender<Result<KhronosValue, crate::Error>>>,
     },
 }
@@ -40,22 +57,34 @@ pub struct WorkerThread {
     tx: UnboundedSender<WorkerThreadMessage>,
     /// The id of the worker thread, used for routing
     id: usize,
+    /// Load of the worker thread, sampled by the master for autoscaling
+    load: Arc<LoadTracker>,
+    /// Usage queues of the worker thread, flushed when draining
+    usage: Arc<UsageTracker>,
+    plugin_usage: Arc<PluginUsageTracker>,
+    emoji_usage: Arc<EmojiUsageTracker>,
+    activity: Arc<ActivityTracker>,
+    /// Set once the worker is draining, new dispatches are rejected
+    draining: Arc<AtomicBool>,
 }
 
 impl WorkerThread {
     /// Creates a new WorkerThread with the given cache data and worker state
     pub fn new(state: WorkerState, id: usize) -> Result<Self, crate::Error> {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let load = state.load.clone();
+        let (usage, plugin_usage, emoji_usage, activity) = (state.usage.clone(), state.plugin_usage.clone(), state.emoji_usage.clone(), state.activity.clone());
         
-        Self::create_thread(id, state, rx)?;
+        Self::create_thread(id, state, rx, load.clone())?;
         
-        let worker_thread = Self { tx, id };
+        let worker_thread = Self { tx, id, load, usage, plugin_usage, emoji_usage, activity, draining: Arc::default() };
 
        Ok(worker_thread)
     }
 
     /// `id` is the worker thread ID, used for routing. `state` is the state to create the worker with. `rx` is the channel receiver for receiving messages from the worker thread.
-    fn create_thread(id: usize, state: WorkerState, mut rx: UnboundedReceiver<WorkerThreadMessage>) -> Result<(), crate::Error> {
+    /// `load` is updated with the load of the thread
+    fn create_thread(id: usize, state: WorkerState, mut rx: UnboundedReceiver<WorkerThreadMessage>, load: Arc<LoadTracker>) -> Result<(), crate::Error> {
         std::thread::Builder::new()
             .name(format!("lua-vm-threadpool-{id}"))
             .stack_size(MAX_VM_THREAD_STACK_SIZE)
@@ -68,6 +97,7 @@ impl WorkerThread {
 
                     rt.block_on(async move {
                         let worker = Worker::new(state).await.expect("Failed to setup worker");
+                        let gate = DispatchGate::new(MAX_CONCURRENT_DISPATCHES);
 
                         // Listen to messages and handle them
                         while let Some(msg) = rx.recv().await {
@@ -77,33 +107,82 @@ impl WorkerThread {
                                     let _ = tx.send(Ok(()));
                                     return; // Exitting the loop will stop the thread automatically
                                 }
-                                WorkerThreadMessage::DispatchEvent { id, event, tx } => {
-                                    let res = worker.dispatch.dispatch_event(id, event).await;
-                                    if let Some(tx) = tx {
-                                        let _ = tx.send(res.map_err(|e| e.to_string().into()));
-                                    }
+                                WorkerThreadMessage::DispatchEvent { id, event, queued_at, tx } => {
+                                    let parent = telemetry::extract(event.traceparent());
+                                    telemetry::record_since(&parent, "worker.queue", queued_at, vec![]);
+                                    let inflight = load.start_dispatch(queued_at);
+
+                                    // Dispatch in the background so a preempted (or yielding) template does not block other tenants on this thread,
+                                    // the gate holds off changes to the VM of the tenant while it runs and bounds the dispatches running at once
+                                    let (wd, gate) = (worker.dispatch.clone(), gate.clone());
+                                    tokio::task::spawn_local(async move {
+                                        let _guard = gate.dispatch(id).await;
+                                        let res = wd.dispatch_event(id, event).await;
+                                        drop(inflight);
+                                        if let Some(tx) = tx {
+                                            let _ = tx.send(res.map_err(|e| e.to_string().into()));
+                                        }
+                                    });
                                 }
                                 WorkerThreadMessage::DropTenant { id, tx } => {
-                                    let res = worker.vm_manager.remove_vm_for(id);
-                                    let _ = tx.send(res.map_err(|e| e.to_string().into()));
+                                    // Wait for the dispatches of the tenant to finish before dropping its VM
+                                    let (wd, gate, load) = (worker.dispatch.clone(), gate.clone(), load.clone());
+                                    tokio::task::spawn_local(async move {
+                                        let _guard = gate.tenant(id).await;
+                                        wd.tenant_state.forget(id);
+                                        let res = wd.vm_manager.remove_vm_for(id);
+                                        load.set_vms(wd.vm_manager.len());
+                                        let _ = tx.send(res.map_err(|e| e.to_string().into()));
+                                    });
                                 }
-                                WorkerThreadMessage::UpdateTenantState {id, ts, tx } => {
-                                    let res = worker.wts.reload_for_tenant(id, &ts);
+                                WorkerThreadMessage::UpdateTenantState { id, sync, tx } => {
+                                    // Applying a sync may reload the VM of the tenant, so wait for its dispatches to finish first
+                                    let (wd, gate, load) = (worker.dispatch.clone(), gate.clone(), load.clone());
+                                    tokio::task::spawn_local(async move {
+                                        let res = {
+                                            let _guard = gate.tenant(id).await;
+                                            let res = wd.tenant_state.apply_sync(id, sync);
+                                            load.set_vms(wd.vm_manager.len());
+                                            res
+                                        };
 
-                                    // If ok, push a event with new tenant state
-                                    if let Ok(reloaded) = res && !reloaded {
-                                        let wd = worker.dispatch.clone();
-                                        tokio::task::spawn_local(async move {
+                                        let ts = match res {
+                                            Ok(SyncOutcome::Applied { reloaded: false }) => wd.tenant_state.tenant_state(id),
+                                            _ => None,
+                                        };
+                                        let _ = tx.send(res.map_err(|e| e.to_string().into()));
+
+                                        // If applied, push a event with new tenant state
+                                        if let Some(ts) = ts {
+                                            let _guard = gate.dispatch(id).await;
                                             if let Err(e) = wd.dispatch_event_complex(id, "$UpdateTenantState", None, ts).await {
                                                 log::error!("failed to dispatch ts update: {e:?}");
                                             }
+                                        }
+                                    });
+                                }
+                                WorkerThreadMessage::InvalidateTemplates { invalidate } => {
+                                    // Tenants without a VM load their templates fresh when their VM is created
+                                    let ids = match invalidate {
+                                        Invalidate::Tenant(id) => worker.vm_manager.keys().into_iter().filter(|vm_id| *vm_id == id).collect::<Vec<_>>(),
+                                        Invalidate::All => worker.vm_manager.keys(),
+                                    };
+
+                                    for id in ids {
+                                        let (wd, gate) = (worker.dispatch.clone(), gate.clone());
+                                        tokio::task::spawn_local(async move {
+                                            let _guard = gate.dispatch(id).await;
+                                            if let Err(e) = wd.dispatch_event_complex(id, "$InvalidateTemplates", None, ()).await {
+                                                log::error!("failed to dispatch template invalidation: {e:?}");
+                                            }
+                                            // Drop results cached while the old templates were still loaded
+                                            wd.vm_manager.result_cache.invalidate(id);
                                         });
                                     }
-
-                                    let _ = tx.send(res.map_err(|e| e.to_string().into()));
                                 }
-
                             }
+
+                            load.set_vms(worker.vm_manager.len());
                         }
                     });
                 }));
@@ -124,6 +203,41 @@ impl WorkerThread {
         self.id
     }
 
+    /// Returns the load tracker of the worker thread
+    pub fn load(&self) -> &LoadTracker {
+        &self.load
+    }
+
+    /// Returns the template usage queue of the worker thread
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+
+    /// Returns the plugin usage queue of the worker thread
+    pub fn plugin_usage(&self) -> &PluginUsageTracker {
+        &self.plugin_usage
+    }
+
+    /// Returns the emoji usage queue of the worker thread
+    pub fn emoji_usage(&self) -> &EmojiUsageTracker {
+        &self.emoji_usage
+    }
+
+    /// Returns the activity queue of the worker thread
+    pub fn activity(&self) -> &ActivityTracker {
+        &self.activity
+    }
+
+    /// Stops the worker thread accepting new dispatches, already queued or running dispatches are unaffected
+    pub fn stop_accepting(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether the worker thread is draining
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
     pub async fn kill(&self) -> Result<(), crate::Error> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         self.tx.send(WorkerThreadMessage::Kill { tx: tx })
@@ -132,14 +246,20 @@ impl WorkerThread {
     }
 
     pub async fn dispatch_event(&self, id: Id, event: SimpleEvent) -> Result<KhronosValue, crate::Error> {
+        if self.is_draining() {
+            return Err("Worker is shutting down".into());
+        }
         let (tx, rx) = tokio::sync::oneshot::channel();
-        self.tx.send(WorkerThreadMessage::DispatchEvent { id, event, tx: Some(tx) })
+        self.tx.send(WorkerThreadMessage::DispatchEvent { id, event, queued_at: SystemTime::now(), tx: Some(tx) })
             .map_err(|e| format!("Failed to send message to worker thread: {e}"))?;
         Ok(rx.await.map_err(|e| format!("Failed to receive response from worker thread: {e}"))??)
     }
 
     pub fn dispatch_event_nowait(&self, id: Id, event: SimpleEvent) -> Result<(), crate::Error> {
-        self.tx.send(WorkerThreadMessage::DispatchEvent { id, event, tx: None })
+        if self.is_draining() {
+            return Err("Worker is shutting down".into());
+        }
+        self.tx.send(WorkerThreadMessage::DispatchEvent { id, event, queued_at: SystemTime::now(), tx: None })
             .map_err(|e| format!("Failed to send message to worker thread: {e}"))?;
         Ok(())
     }
@@ -153,9 +273,16 @@ impl WorkerThread {
         Ok(rx.await.map_err(|e| format!("Failed to receive response from worker thread: {e}"))??)
     }
 
-    pub async fn update_tenant_state(&self, id: Id, ts: TenantState) -> Result<bool, crate::Error> {
+    /// Tells the VMs affected by a template invalidation to reload their templates
+    pub fn invalidate_templates(&self, invalidate: Invalidate) -> Result<(), crate::Error> {
+        self.tx.send(WorkerThreadMessage::InvalidateTemplates { invalidate })
+            .map_err(|e| format!("Failed to send message to worker thread: {e}"))?;
+        Ok(())
+    }
+
+    pub async fn update_tenant_state(&self, id: Id, sync: TenantStateSync) -> Result<SyncOutcome, crate::Error> {
         let (tx, rx) = tokio::sync::oneshot::channel();
-        self.tx.send(WorkerThreadMessage::UpdateTenantState { id, ts, tx })
+        self.tx.send(WorkerThreadMessage::UpdateTenantState { id, sync, tx })
             .map_err(|e| format!("Failed to send message to worker thread: {e}"))?;
         Ok(rx.await.map_err(|e| format!("Failed to receive response from worker thread: {e}"))??)
     }
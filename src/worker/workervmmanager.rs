@@ -5,25 +5,51 @@ use khronos_runtime::core::typesext::Vfs;
 use khronos_runtime::rt::{KhronosRuntime, RuntimeCreateOpts};
 use serde::{Deserialize, Serialize};
 use dapi::{GuildId, UserId};
-use stratum_common::worker_id_for_tenant;
 use std::cell::RefCell;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashMap, rc::Rc};
 use khronos_runtime::rt::mlua::prelude::*;
+use opentelemetry::Context;
 
+use crate::geese::entitlements::EntitlementCache;
+use crate::geese::featureflags::FeatureFlagCache;
+use crate::geese::tenantstate::TenantLimits;
+use crate::geese::usage::UsageTracker;
 use crate::mesophyll::client::MesophyllClient;
 use crate::worker::builtins::BUILTINS;
+use crate::worker::fuzzy::{self, FUZZY_GLOBAL};
+use crate::worker::tableutils::{self, TABLEUTILS_GLOBAL};
+use crate::worker::markdown::{self, MARKDOWN_GLOBAL};
+use crate::worker::execlock::{EXECLOCK_GLOBAL, ExecLocks};
+use crate::worker::eventtypes::{self, EVENTSCHEMA_GLOBAL};
+use crate::worker::scratch::{self, SCRATCH_GLOBAL, ScratchSpace};
+use crate::worker::serdeext::{self, SERDE_GLOBAL};
+use crate::worker::int64::{self, INT64_GLOBAL};
 use crate::worker::limits::TEMPLATE_GIVE_TIME;
+use crate::worker::plugins::{self, REGISTERED_PLUGINS_GLOBAL, set_plugin};
+use crate::worker::crypto::{self, CRYPTO_GLOBAL};
+use crate::worker::random::{self, RANDOM_GLOBAL};
+use crate::worker::replay::ReplayState;
+use crate::worker::resultcache::ResultCache;
+use crate::worker::snowflake::{self, SNOWFLAKE_GLOBAL};
+use crate::worker::stringutils::{self, STRINGUTILS_GLOBAL};
 use crate::worker::syscall::SyscallHandler;
+use crate::worker::abort::AbortSignal;
+use crate::worker::timeslice::TimeSlicer;
+use crate::worker::validate::{self, VALIDATE_GLOBAL};
+use crate::worker::rules::{self, RULES_GLOBAL};
+use crate::worker::fixtures::{self, FIXTURES_GLOBAL};
+#[cfg(feature = "wasm")]
+use crate::worker::wasm::{self, WASM_GLOBAL};
 use crate::worker::workertenantstate::WorkerTenantState;
 
 use super::limits::Ratelimits;
 
 use super::workerstate::WorkerState;
-use super::limits::{MAX_TEMPLATE_MEMORY_USAGE, MAX_TEMPLATES_EXECUTION_TIME};
 
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
-/// Represents the ID of a tenant, which can currently only be a GuildId
+/// Represents the ID of a tenant, either a guild or a user (for user-installed apps and DMs)
 #[serde(tag = "type", content = "id")]
 pub enum Id {
     Guild(GuildId),
@@ -73,15 +99,6 @@ impl Id {
             Id::User(user_id) => DiscordProviderContext::User(user_id),
         }
     }
-
-    /// Returns a the worker ID given tenant ID
-    pub fn worker_id(&self, num_workers: usize) -> usize {
-        match self {
-            // This is safe as AntiRaid workers does not currently support 32 bit platforms
-            Id::Guild(guild_id) =>  worker_id_for_tenant(guild_id.get(), num_workers),
-            Id::User(user_id) => worker_id_for_tenant(user_id.get(), num_workers),
-        }
-    }
 }
 
 impl FromLua for Id {
@@ -121,6 +138,19 @@ impl IntoLua for Id {
 pub struct VmState {
     pub runtime: KhronosRuntime,
     pub dispatch_func: LuaFunction,
+    /// The trace context of the most recently started dispatch, used to parent spans created by syscalls
+    ///
+    /// This is best-effort: if multiple events are executing on the VM concurrently, syscall spans are
+    /// attached to whichever dispatch started last
+    pub trace_cx: Rc<RefCell<Context>>,
+    /// Enforces the execution time limit and time slicing of the VM, if time slicing is enabled
+    pub time_slicer: Option<Rc<TimeSlicer>>,
+    /// Record/replay state of the VM
+    pub replay: Rc<ReplayState>,
+    /// Aborts the in-flight syscalls of the VM when its executions time out or it is killed
+    pub abort: Rc<AbortSignal>,
+    /// Scratch files of the VM, closed once no execution is running
+    pub scratch: Rc<ScratchSpace>,
 }
 
 /// Feed sender
@@ -141,6 +171,48 @@ impl LuaUserData for FeedTx {
     }
 }
 
+/// Read-only view of the feature flags for a tenant
+struct FeatureFlagReader(Id, Arc<FeatureFlagCache>);
+impl LuaUserData for FeatureFlagReader {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("enabled", |_, this, name: String| {
+            Ok(this.1.is_enabled(&name, this.0))
+        });
+        methods.add_method("list", |_, this, _: ()| {
+            let mut flags = this.1.get().enabled_for(this.0).into_iter().collect::<Vec<_>>();
+            flags.sort();
+            Ok(flags)
+        });
+    }
+}
+
+/// Read-only view of the premium tiers and entitlements of a tenant
+struct PremiumReader(Id, Arc<EntitlementCache>);
+impl LuaUserData for PremiumReader {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("tiers", |_, this, _: ()| {
+            Ok(this.1.tiers_for(this.0).into_iter().collect::<Vec<_>>())
+        });
+        methods.add_method("allows", |_, this, feature: String| {
+            Ok(this.1.allows(&feature, this.0))
+        });
+        methods.add_method("entitlements", |lua, this, _: ()| {
+            lua.to_value(&this.1.active_for(this.0))
+        });
+    }
+}
+
+/// Records per-template execution usage (used by the builtin script manager)
+struct UsageRecorder(Id, Arc<UsageTracker>);
+impl LuaUserData for UsageRecorder {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("record", |lua, this, (template, wall_time_ms, ok): (String, u64, bool)| {
+            this.1.record_execution(this.0, &template, wall_time_ms, ok, lua.used_memory());
+            Ok(())
+        });
+    }
+}
+
 struct BaseTenantData<'a> {
     bot: Arc<User>,
     id: Id,
@@ -148,6 +220,9 @@ struct BaseTenantData<'a> {
     base_vfs: &'a HashMap<String, Vfs>,
     support_server: &'a str,
     feed_tx: FeedTx,
+    feature_flags: FeatureFlagReader,
+    premium: PremiumReader,
+    usage: UsageRecorder,
     website: &'a str
 }
 
@@ -167,6 +242,9 @@ impl<'a> IntoLua for BaseTenantData<'a> {
         table.set("support_server", self.support_server)?;
         table.set("website", self.website)?;
         table.set("feed_tx", self.feed_tx)?;
+        table.set("feature_flags", self.feature_flags)?;
+        table.set("premium", self.premium)?;
+        table.set("usage", self.usage)?;
         table.set_readonly(true);
         Ok(LuaValue::Table(table))
     }
@@ -182,6 +260,8 @@ impl<'a> IntoLua for BaseTenantData<'a> {
 pub struct WorkerVmManager {
     /// The VMs managed by this WorkerVmManager, keyed by their tenant ID
     vms: Rc<RefCell<HashMap<Id, VmState>>>,
+    /// Cached results of the templates of the VMs
+    pub result_cache: ResultCache,
 }
 
 impl WorkerVmManager {
@@ -189,6 +269,7 @@ impl WorkerVmManager {
     pub fn new() -> Self {
         Self {
             vms: RefCell::default().into(),
+            result_cache: ResultCache::default(),
         }
     }
 
@@ -199,35 +280,74 @@ impl WorkerVmManager {
             return Ok(vm.clone());
         }
 
-        let vm = self.create_vm(id, worker_state.clone(), wts.clone())?;
+        let vm = self.create_vm(id, worker_state.clone(), wts.clone(), None)?;
         vms.insert(id, vm.clone());
 
         Ok(vm)
     }
 
+    /// Creates a throwaway VM for replaying a recording, the VM is not managed by this WorkerVmManager
+    /// and must be marked as broken by the caller once done
+    pub fn create_replay_vm(&self, id: Id, worker_state: &WorkerState, wts: &WorkerTenantState, replay: Rc<ReplayState>) -> LuaResult<VmState> {
+        self.create_vm(id, worker_state.clone(), wts.clone(), Some(replay))
+    }
+
     /// Creates a new VmData
-    fn create_vm(&self, id: Id, worker_state: WorkerState, wts: WorkerTenantState) -> LuaResult<VmState> {
+    fn create_vm(&self, id: Id, worker_state: WorkerState, wts: WorkerTenantState, replay: Option<Rc<ReplayState>>) -> LuaResult<VmState> {
+        let tenant_state = wts.get_cached_tenant_state_for(id)
+            .map_err(|e| LuaError::external(format!("Failed to get tenant state for ID {id:?}: {e}")))?;
+
         // If it doesn't exist, create a new VM
-        let runtime = self.configure_runtime(&worker_state)
+        let (runtime, time_slicer) = self.configure_runtime(&worker_state, &tenant_state.limits)
             .map_err(|e| LuaError::external(e))?;
 
         let func: LuaFunction = runtime
         .eval_script("./builtins.templateloop")?;
 
-        let tenant_state = wts.get_cached_tenant_state_for(id)
-            .map_err(|e| LuaError::external(format!("Failed to get tenant state for ID {id:?}: {e}")))?;
-
-
-
-        // Setup cleanup code
-        let weak_vms = Rc::downgrade(&self.vms);
-        runtime.set_on_broken(Box::new(move || { 
-            if let Some(vms_rc) = weak_vms.upgrade() {
-                if let Ok(mut vms) = vms_rc.try_borrow_mut() {
-                    vms.remove(&id);
+        // Setup cleanup code (replay VMs are not managed)
+        let abort = AbortSignal::new();
+        if replay.is_none() {
+            // Templates are loaded fresh into the new VM
+            self.result_cache.invalidate(id);
+            let weak_vms = Rc::downgrade(&self.vms);
+            let abort = abort.clone();
+            runtime.set_on_broken(Box::new(move || { 
+                abort.abort();
+                if let Some(vms_rc) = weak_vms.upgrade() {
+                    if let Ok(mut vms) = vms_rc.try_borrow_mut() {
+                        vms.remove(&id);
+                    }
                 }
+            }));
+        }
+
+        let replay = replay.unwrap_or_else(ReplayState::live);
+        let scratch = ScratchSpace::new();
+        let gtab = runtime.global_table().clone();
+        runtime.with_lua(|lua| {
+            replay.install_clock(lua, &gtab)?;
+            let disabled = &tenant_state.settings.disabled_plugins;
+            set_plugin(&gtab, disabled, "random", RANDOM_GLOBAL, || random::create_module(lua, replay.clone()))?;
+            set_plugin(&gtab, disabled, "crypto", CRYPTO_GLOBAL, || crypto::create_module(lua))?;
+            set_plugin(&gtab, disabled, "validate", VALIDATE_GLOBAL, || validate::create_module(lua))?;
+            set_plugin(&gtab, disabled, "rules", RULES_GLOBAL, || rules::create_module(lua))?;
+            set_plugin(&gtab, disabled, "fixtures", FIXTURES_GLOBAL, || fixtures::create_module(lua, id, worker_state.stratum.current_user().id))?;
+            set_plugin(&gtab, disabled, "snowflake", SNOWFLAKE_GLOBAL, || snowflake::create_module(lua))?;
+            set_plugin(&gtab, disabled, "int64", INT64_GLOBAL, || int64::create_module(lua))?;
+            set_plugin(&gtab, disabled, "stringutils", STRINGUTILS_GLOBAL, || stringutils::create_module(lua))?;
+            set_plugin(&gtab, disabled, "fuzzy", FUZZY_GLOBAL, || fuzzy::create_module(lua))?;
+            set_plugin(&gtab, disabled, "tableutils", TABLEUTILS_GLOBAL, || tableutils::create_module(lua))?;
+            set_plugin(&gtab, disabled, "markdown", MARKDOWN_GLOBAL, || markdown::create_module(lua))?;
+            set_plugin(&gtab, disabled, "serde", SERDE_GLOBAL, || serdeext::create_module(lua))?;
+            set_plugin(&gtab, disabled, "scratch", SCRATCH_GLOBAL, || scratch::create_module(lua, scratch.clone()))?;
+            #[cfg(feature = "wasm")]
+            if let Some(config) = crate::CONFIG.wasm.as_ref() {
+                set_plugin(&gtab, disabled, "wasm", WASM_GLOBAL, || wasm::create_module(lua, config, id, tenant_state.limits.memory_limit()))?;
             }
-        }));
+            gtab.set(REGISTERED_PLUGINS_GLOBAL, plugins::create_module(lua, id, disabled)?)?;
+            gtab.set(EVENTSCHEMA_GLOBAL, eventtypes::create_module(lua)?)?;
+            gtab.set(EXECLOCK_GLOBAL, ExecLocks::new())
+        })?;
 
         // Setup vm dispatch function w/ base data
         let btd = BaseTenantData { 
@@ -238,13 +358,21 @@ impl WorkerVmManager {
             support_server: &crate::CONFIG.support_server_invite,
             website: &crate::CONFIG.frontend,
             feed_tx: FeedTx(id, worker_state.mesophyll_client.clone()),
+            feature_flags: FeatureFlagReader(id, worker_state.feature_flags.clone()),
+            premium: PremiumReader(id, worker_state.entitlements.clone()),
+            usage: UsageRecorder(id, worker_state.usage.clone()),
         };
 
+        let trace_cx = Rc::new(RefCell::new(Context::new()));
         let syscall_h = SyscallHandler::new(
             worker_state,
             wts,
             Ratelimits::new().into(),
-            id
+            id,
+            trace_cx.clone(),
+            replay.clone(),
+            abort.clone(),
+            self.result_cache.clone(),
         );
 
         let dispatch_func = func.call::<LuaFunction>((syscall_h, tenant_state, btd))?;
@@ -252,6 +380,11 @@ impl WorkerVmManager {
         Ok(VmState {
             runtime,
             dispatch_func,
+            trace_cx,
+            time_slicer,
+            replay,
+            abort,
+            scratch,
         })
     }
 
@@ -268,6 +401,22 @@ impl WorkerVmManager {
         Ok(())
     }
     
+    /// Applies new limits to the VM for the given tenant ID (if it is running)
+    pub fn apply_limits(&self, id: Id, limits: &TenantLimits) -> Result<(), crate::Error> {
+        let vm = self.vms.borrow().get(&id).cloned();
+        if let Some(vm) = vm {
+            vm.runtime.set_memory_limit(limits.memory_limit())?;
+            // Compiled WASM modules count against the memory limit too
+            #[cfg(feature = "wasm")]
+            vm.runtime.with_lua(|lua| wasm::set_memory_limit(lua, limits.memory_limit()))?;
+            if let Some(ref time_slicer) = vm.time_slicer {
+                time_slicer.set_execution_time_limit(limits.execution_time());
+            }
+        }
+
+        Ok(())
+    }
+
     /// Returns the number of VMs managed by this WorkerVmManager
     #[allow(dead_code)]
     pub fn len(&self) -> usize {
@@ -281,17 +430,16 @@ impl WorkerVmManager {
     }
 
     /// Returns a list of all tenant IDs for which VMs are managed by this WorkerVmManager
-    #[allow(dead_code)]
     pub fn keys(&self) -> Vec<Id> {
         self.vms.borrow().keys().cloned().collect()
     }
 
     /// Configures a new khronos runtime
-    fn configure_runtime(&self, worker_state: &WorkerState) -> LuaResult<KhronosRuntime> {
+    fn configure_runtime(&self, worker_state: &WorkerState, limits: &TenantLimits) -> LuaResult<(KhronosRuntime, Option<Rc<TimeSlicer>>)> {
         let rt = KhronosRuntime::new(
             RuntimeCreateOpts {
                 disable_task_lib: false,
-                time_limit: Some(MAX_TEMPLATES_EXECUTION_TIME),
+                time_limit: Some(limits.execution_time()),
                 give_time: TEMPLATE_GIVE_TIME,
                 wasm_max_fuel_per_slice: None,
                 wasm_max_memory_bytes: None,
@@ -303,7 +451,25 @@ impl WorkerVmManager {
             "antiraid"
         )?;
 
-        rt.set_memory_limit(MAX_TEMPLATE_MEMORY_USAGE)?;
+        rt.set_memory_limit(limits.memory_limit())?;
+
+        // The time slicer replaces the interrupt of the runtime, so is only installed if slicing is enabled
+        let time_slicer = match crate::CONFIG.template_time_slice_ms {
+            Some(slice_ms) => {
+                let time_slicer = TimeSlicer::new(Duration::from_millis(slice_ms), limits.execution_time());
+                let gtab = rt.global_table().clone();
+                rt.with_lua(|lua| {
+                    let task_defer = match gtab.get::<Option<LuaTable>>("task")? {
+                        Some(task) => task.get::<Option<LuaFunction>>("defer")?,
+                        None => None,
+                    };
+                    time_slicer.install(lua, task_defer);
+                    Ok(())
+                })?;
+                Some(time_slicer)
+            }
+            None => None,
+        };
 
         if worker_state.worker_print {
             let gtab = rt.global_table().clone();
@@ -318,6 +484,6 @@ impl WorkerVmManager {
             })?;
         }
 
-        Ok(rt)
+        Ok((rt, time_slicer))
     }
 }